@@ -14,7 +14,7 @@ use llama_agent::{
     types::{
         AgentAPI, AgentConfig, FinishReason, GenerationRequest, MCPServerConfig, Message,
         MessageRole, ModelConfig, ModelSource, QueueConfig, RepetitionConfig, RetryConfig,
-        SessionConfig, StoppingConfig,
+        SessionConfig, StoppingConfig, ValidationSeverity,
     },
     AgentServer,
 };
@@ -41,11 +41,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             use_hf_params: true, // Use HuggingFace generation_config.json
             retry_config: RetryConfig::default(),
             debug: true,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(30),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![MCPServerConfig {
             name: "filesystem".to_string(),
@@ -56,6 +74,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 ".".to_string(), // Current directory
             ],
             timeout_secs: None,
+            strict_protocol_version: false,
         }],
         session_config: SessionConfig::default(),
     };
@@ -75,6 +94,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             ".".to_string(), // Current directory
         ],
         timeout_secs: None,
+        strict_protocol_version: false,
     }];
 
     info!("Created session: {}", session.id);
@@ -94,6 +114,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     };
     agent.add_message(&session.id, message).await?;
 
@@ -103,6 +124,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_tokens: Some(100),
         repetition_detection: Some(RepetitionConfig::default()),
         eos_detection: true,
+        stop_token_ids: Vec::new(),
+        max_duration: None,
     };
 
     let request = GenerationRequest::new(session.id)
@@ -14,6 +14,7 @@ use llama_agent::{
     types::{
         AgentAPI, AgentConfig, FinishReason, GenerationRequest, MCPServerConfig, Message,
         MessageRole, ModelConfig, ModelSource, QueueConfig, RetryConfig, SessionConfig,
+        StreamChunking, ValidationSeverity,
     },
     AgentServer,
 };
@@ -70,6 +71,15 @@ async fn demonstrate_invalid_model_config() -> Result<(), Box<dyn std::error::Er
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -93,6 +103,15 @@ async fn demonstrate_invalid_model_config() -> Result<(), Box<dyn std::error::Er
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -116,6 +135,15 @@ async fn demonstrate_invalid_model_config() -> Result<(), Box<dyn std::error::Er
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -145,6 +173,15 @@ async fn demonstrate_mcp_server_failures() -> Result<(), Box<dyn std::error::Err
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![
@@ -157,6 +194,7 @@ async fn demonstrate_mcp_server_failures() -> Result<(), Box<dyn std::error::Err
                     "@modelcontextprotocol/server-filesystem".to_string(),
                 ],
                 timeout_secs: Some(30),
+                strict_protocol_version: false,
             },
             // Invalid server command
             MCPServerConfig {
@@ -164,6 +202,7 @@ async fn demonstrate_mcp_server_failures() -> Result<(), Box<dyn std::error::Err
                 command: "nonexistent-command".to_string(),
                 args: vec!["arg1".to_string()],
                 timeout_secs: Some(10),
+                strict_protocol_version: false,
             },
         ],
         session_config: SessionConfig::default(),
@@ -215,11 +254,29 @@ async fn demonstrate_generation_errors() -> Result<(), Box<dyn std::error::Error
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 10,
             request_timeout: Duration::from_secs(5), // Very short timeout
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![],
         session_config: SessionConfig::default(),
@@ -238,6 +295,7 @@ async fn demonstrate_generation_errors() -> Result<(), Box<dyn std::error::Error
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now(),
+                attachments: Vec::new(),
             });
 
             let request = GenerationRequest {
@@ -245,8 +303,17 @@ async fn demonstrate_generation_errors() -> Result<(), Box<dyn std::error::Error
                 max_tokens: Some(10000), // Very large token limit
                 temperature: Some(2.0),  // Extreme temperature
                 top_p: Some(1.0),
+                seed: None,
                 stop_tokens: vec![],
+                stop_token_ids: vec![],
+                n: None,
+                append_to_session: None,
+                chunking: StreamChunking::Token,
+                block_on_full: false,
+                model: None,
+                max_duration: None,
                 stopping_config: None,
+                debug_tokens: false,
             };
 
             match agent.generate(request).await {
@@ -7,7 +7,8 @@
 use llama_agent::{
     types::{
         AgentAPI, AgentConfig, GenerationRequest, MCPServerConfig, Message, MessageRole,
-        ModelConfig, ModelSource, QueueConfig, RetryConfig, SessionConfig, SessionId,
+        ModelConfig, ModelSource, QueueConfig, RetryConfig, SessionConfig, SessionEvictionPolicy,
+        SessionId, SessionUsage, StreamChunking, ToolPolicy, ValidationSeverity,
     },
     AgentServer,
 };
@@ -172,6 +173,15 @@ async fn test_configuration_validation() -> Result<(), Box<dyn std::error::Error
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -195,6 +205,15 @@ async fn test_configuration_validation() -> Result<(), Box<dyn std::error::Error
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -217,6 +236,15 @@ async fn test_configuration_validation() -> Result<(), Box<dyn std::error::Error
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -240,6 +268,15 @@ async fn test_configuration_validation() -> Result<(), Box<dyn std::error::Error
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -268,6 +305,15 @@ async fn test_agent_initialization() -> Result<(), Box<dyn std::error::Error>> {
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
@@ -295,6 +341,15 @@ async fn test_agent_initialization() -> Result<(), Box<dyn std::error::Error>> {
             max_queue_size: 0, // Invalid
             request_timeout: Duration::from_secs(30),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![],
         session_config: SessionConfig::default(),
@@ -350,6 +405,7 @@ async fn test_session_management() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     };
 
     if message.content != "Test message" {
@@ -396,6 +452,7 @@ async fn test_error_handling() -> Result<(), Box<dyn std::error::Error>> {
         command: "test".to_string(),
         args: vec![],
         timeout_secs: None,
+        strict_protocol_version: false,
     };
 
     match invalid_mcp_config.validate() {
@@ -429,6 +486,7 @@ async fn test_mcp_integration() -> Result<(), Box<dyn std::error::Error>> {
             "@modelcontextprotocol/server-filesystem".to_string(),
         ],
         timeout_secs: Some(30),
+        strict_protocol_version: false,
     };
 
     match valid_mcp_config.validate() {
@@ -442,6 +500,7 @@ async fn test_mcp_integration() -> Result<(), Box<dyn std::error::Error>> {
         command: "test".to_string(),
         args: vec![],
         timeout_secs: None,
+        strict_protocol_version: false,
     };
 
     match invalid_mcp_config.validate() {
@@ -457,6 +516,7 @@ async fn test_mcp_integration() -> Result<(), Box<dyn std::error::Error>> {
         description: "Test tool".to_string(),
         parameters: serde_json::json!({"type": "object"}),
         server_name: "test_server".to_string(),
+        original_name: None,
     };
 
     if tool_def.name != "test_tool" {
@@ -494,12 +554,15 @@ async fn test_streaming_patterns() -> Result<(), Box<dyn std::error::Error>> {
     info!("Testing streaming patterns");
 
     // Test 1: Stream chunk structure
-    use llama_agent::types::StreamChunk;
+    use llama_agent::types::{StreamChunk, StreamEvent};
 
     let chunk = StreamChunk {
         text: "Hello".to_string(),
         is_complete: false,
         token_count: 1,
+        response: None,
+        event: StreamEvent::TextChunk,
+        token_ids: Vec::new(),
     };
 
     if chunk.text != "Hello" {
@@ -522,6 +585,8 @@ async fn test_streaming_patterns() -> Result<(), Box<dyn std::error::Error>> {
         available_prompts: vec![],
         created_at: SystemTime::now(),
         updated_at: SystemTime::now(),
+        tool_policy: ToolPolicy::AllowAll,
+        usage: SessionUsage::default(),
     };
 
     let generation_request = GenerationRequest {
@@ -529,8 +594,17 @@ async fn test_streaming_patterns() -> Result<(), Box<dyn std::error::Error>> {
         max_tokens: Some(100),
         temperature: Some(0.7),
         top_p: Some(0.9),
+        seed: None,
         stop_tokens: vec!["</s>".to_string()],
+        stop_token_ids: vec![],
+        n: None,
+        append_to_session: None,
+        chunking: StreamChunking::Token,
+        block_on_full: false,
+        model: None,
+        max_duration: None,
         stopping_config: None,
+        debug_tokens: false,
     };
 
     if generation_request.max_tokens != Some(100) {
@@ -556,16 +630,39 @@ async fn test_performance_configurations() -> Result<(), Box<dyn std::error::Err
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 1000, // Large queue
             request_timeout: Duration::from_secs(180),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![],
         session_config: SessionConfig {
             max_sessions: 10000, // High session limit
             session_timeout: Duration::from_secs(1800),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         },
     };
 
@@ -585,16 +682,39 @@ async fn test_performance_configurations() -> Result<(), Box<dyn std::error::Err
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(30), // Tight timeout
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![], // No MCP for minimal latency
         session_config: SessionConfig {
             max_sessions: 1000,
             session_timeout: Duration::from_secs(600),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         },
     };
 
@@ -614,16 +734,39 @@ async fn test_performance_configurations() -> Result<(), Box<dyn std::error::Err
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 50, // Small queue
             request_timeout: Duration::from_secs(60),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![],
         session_config: SessionConfig {
             max_sessions: 100, // Low session count
             session_timeout: Duration::from_secs(300),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         },
     };
 
@@ -12,6 +12,7 @@ use llama_agent::{
     types::{
         AgentAPI, AgentConfig, FinishReason, GenerationRequest, MCPServerConfig, Message,
         MessageRole, ModelConfig, ModelSource, QueueConfig, RetryConfig, SessionConfig,
+        StreamChunking, ValidationSeverity,
     },
     AgentServer,
 };
@@ -36,11 +37,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(45),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![
             // Filesystem server for file operations
@@ -52,6 +71,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "@modelcontextprotocol/server-filesystem".to_string(),
                 ],
                 timeout_secs: Some(30),
+                strict_protocol_version: false,
             },
             // Web search server (if available)
             MCPServerConfig {
@@ -62,6 +82,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "@modelcontextprotocol/server-brave-search".to_string(),
                 ],
                 timeout_secs: Some(60),
+                strict_protocol_version: false,
             },
             // Memory server for persistent data
             MCPServerConfig {
@@ -72,6 +93,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "@modelcontextprotocol/server-memory".to_string(),
                 ],
                 timeout_secs: Some(30),
+                strict_protocol_version: false,
             },
         ],
         session_config: SessionConfig::default(),
@@ -100,11 +122,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     use_hf_params: true,
                     retry_config: RetryConfig::default(),
                     debug: false,
+                    local_search_depth: 0,
+                    allow_http: false,
+                    metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+                    refresh_metadata: false,
+                    cache_dir: None,
+                    force_load: false,
+                    memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+                    chat_template: None,
+                    extra_stop_sequences: Vec::new(),
                 },
                 queue_config: QueueConfig {
                     max_queue_size: 100,
                     request_timeout: Duration::from_secs(45),
                     worker_threads: 1,
+                    max_sequences_per_request: 4,
+                    max_token_conversion_failures: 8,
+                    context_pool_size: None,
+                    use_template_stop_tokens: true,
+                    enable_request_batching: false,
+                    max_batched_requests: 4,
+                    stop_token_collisions: ValidationSeverity::Error,
+                    max_stop_tokens: 20,
+                    max_stop_token_length: 100,
                 },
                 mcp_servers: vec![MCPServerConfig {
                     name: "filesystem".to_string(),
@@ -114,6 +154,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         "@modelcontextprotocol/server-filesystem".to_string(),
                     ],
                     timeout_secs: Some(30),
+                    strict_protocol_version: false,
                 }],
                 session_config: SessionConfig::default(),
             };
@@ -135,6 +176,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "@modelcontextprotocol/server-filesystem".to_string(),
         ],
         timeout_secs: Some(30),
+        strict_protocol_version: false,
     }];
 
     // Discover available tools from all configured MCP servers
@@ -168,6 +210,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     };
     agent.add_message(&session.id, message1).await?;
 
@@ -176,8 +219,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_tokens: Some(300),
         temperature: Some(0.3),
         top_p: Some(0.9),
+        seed: None,
         stop_tokens: vec![],
+        stop_token_ids: vec![],
+        n: None,
+        append_to_session: None,
+        chunking: StreamChunking::Token,
+        block_on_full: false,
+        model: None,
+        max_duration: None,
         stopping_config: None,
+        debug_tokens: false,
     };
 
     match agent.generate(request1).await {
@@ -187,16 +239,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "Tokens: {}, Finish: {:?}",
                 response.tokens_generated, response.finish_reason
             );
-
-            // Add response to session for context
-            let response_message = Message {
-                role: MessageRole::Assistant,
-                content: response.generated_text,
-                tool_call_id: None,
-                tool_name: None,
-                timestamp: SystemTime::now(),
-            };
-            agent.add_message(&session.id, response_message).await?;
+            // generate() already stored the response in the session
         }
         Err(e) => {
             warn!("Example 1 failed: {}", e);
@@ -215,6 +258,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     });
 
     let request2 = GenerationRequest {
@@ -222,8 +266,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_tokens: Some(400),
         temperature: Some(0.3),
         top_p: Some(0.9),
+        seed: None,
         stop_tokens: vec![],
+        stop_token_ids: vec![],
+        n: None,
+        append_to_session: None,
+        chunking: StreamChunking::Token,
+        block_on_full: false,
+        model: None,
+        max_duration: None,
         stopping_config: None,
+        debug_tokens: false,
     };
 
     match agent.generate(request2).await {
@@ -240,6 +293,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now(),
+                attachments: Vec::new(),
             });
         }
         Err(e) => {
@@ -259,6 +313,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     });
 
     let request3 = GenerationRequest {
@@ -266,8 +321,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_tokens: Some(200),
         temperature: Some(0.3),
         top_p: Some(0.9),
+        seed: None,
         stop_tokens: vec![],
+        stop_token_ids: vec![],
+        n: None,
+        append_to_session: None,
+        chunking: StreamChunking::Token,
+        block_on_full: false,
+        model: None,
+        max_duration: None,
         stopping_config: None,
+        debug_tokens: false,
     };
 
     match agent.generate(request3).await {
@@ -356,6 +420,7 @@ async fn demonstrate_custom_mcp_server() -> Result<(), Box<dyn std::error::Error
         command: "python3".to_string(),
         args: vec!["custom_mcp_server.py".to_string()],
         timeout_secs: Some(30),
+        strict_protocol_version: false,
     };
 
     println!("Custom MCP server would provide specialized tools for your domain");
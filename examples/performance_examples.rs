@@ -13,7 +13,8 @@
 use llama_agent::{
     types::{
         AgentAPI, AgentConfig, GenerationRequest, Message, MessageRole, ModelConfig, ModelSource,
-        QueueConfig, RetryConfig, SessionConfig,
+        QueueConfig, RetryConfig, SessionConfig, SessionEvictionPolicy, StreamChunking, ToolPolicy,
+        ValidationSeverity,
     },
     AgentServer,
 };
@@ -73,16 +74,39 @@ async fn demonstrate_configuration_optimization() -> Result<(), Box<dyn std::err
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 1000,                      // Large queue
             request_timeout: Duration::from_secs(180), // Generous timeout
             worker_threads: 1,                         // Single worker for memory efficiency
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![], // Minimal MCP servers
         session_config: SessionConfig {
             max_sessions: 10000,                        // High session limit
             session_timeout: Duration::from_secs(1800), // 30 minutes
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         },
     };
 
@@ -100,16 +124,39 @@ async fn demonstrate_configuration_optimization() -> Result<(), Box<dyn std::err
             use_hf_params: false, // Skip network calls
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 100,                      // Smaller queue
             request_timeout: Duration::from_secs(30), // Tight timeout
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![], // No MCP for minimal latency
         session_config: SessionConfig {
             max_sessions: 1000,
             session_timeout: Duration::from_secs(600), // 10 minutes
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         },
     };
 
@@ -127,16 +174,39 @@ async fn demonstrate_configuration_optimization() -> Result<(), Box<dyn std::err
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 50, // Small queue
             request_timeout: Duration::from_secs(60),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![],
         session_config: SessionConfig {
             max_sessions: 100,                         // Low session count
             session_timeout: Duration::from_secs(300), // 5 minutes
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         },
     };
 
@@ -450,6 +520,7 @@ async fn benchmark_real_performance(
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now(),
+                attachments: Vec::new(),
             });
 
             let request = GenerationRequest {
@@ -457,8 +528,17 @@ async fn benchmark_real_performance(
                 max_tokens: Some(100),
                 temperature: Some(0.7),
                 top_p: Some(0.9),
+                seed: None,
                 stop_tokens: vec![],
+                stop_token_ids: vec![],
+                n: None,
+                append_to_session: None,
+                chunking: StreamChunking::Token,
+                block_on_full: false,
+                model: None,
+                max_duration: None,
                 stopping_config: None,
+                debug_tokens: false,
             };
 
             let response = agent.generate(request).await?;
@@ -8,6 +8,7 @@ use llama_agent::{
     types::{
         AgentAPI, AgentConfig, GenerationRequest, Message, MessageRole, ModelConfig, ModelSource,
         QueueConfig, RepetitionConfig, RetryConfig, SessionConfig, StoppingConfig,
+        ValidationSeverity,
     },
     AgentServer,
 };
@@ -33,11 +34,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(60), // Longer timeout for streaming
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![], // No MCP servers for this example
         session_config: SessionConfig::default(),
@@ -57,6 +76,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     };
     agent.add_message(&session.id, message).await?;
 
@@ -70,6 +90,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             max_tokens: Some(500),
             repetition_detection: Some(RepetitionConfig::default()),
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         });
 
     println!("\nStarting streaming generation...");
@@ -136,6 +158,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     };
     agent.add_message(&batch_session.id, batch_message).await?;
 
@@ -11,6 +11,7 @@ use llama_agent::{
     types::{
         AgentAPI, AgentConfig, FinishReason, GenerationRequest, MCPServerConfig, Message,
         MessageRole, ModelConfig, ModelSource, QueueConfig, RetryConfig, SessionConfig,
+        StreamChunking, ValidationSeverity,
     },
     AgentServer,
 };
@@ -34,11 +35,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(30),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![MCPServerConfig {
             name: "filesystem".to_string(),
@@ -48,6 +67,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "@modelcontextprotocol/server-filesystem".to_string(),
             ],
             timeout_secs: None,
+            strict_protocol_version: false,
         }],
         session_config: SessionConfig::default(),
     };
@@ -64,6 +84,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             "@modelcontextprotocol/server-filesystem".to_string(),
         ],
         timeout_secs: None,
+        strict_protocol_version: false,
     }];
 
     // Discover available tools
@@ -80,6 +101,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     };
     agent.add_message(&session.id, message).await?;
 
@@ -89,8 +111,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         max_tokens: Some(200),
         temperature: Some(0.7),
         top_p: Some(0.9),
+        seed: None,
         stop_tokens: vec![],
+        stop_token_ids: vec![],
+        n: None,
+        append_to_session: None,
+        chunking: StreamChunking::Token,
+        block_on_full: false,
+        model: None,
+        max_duration: None,
         stopping_config: None,
+        debug_tokens: false,
     };
 
     println!("\nGenerating initial response...");
@@ -126,6 +157,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         tool_call_id: None,
                         tool_name: None,
                         timestamp: SystemTime::now(),
+                        attachments: Vec::new(),
                     });
 
                     // Execute each tool call
@@ -154,6 +186,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     tool_call_id: Some(tool_call.id),
                                     tool_name: Some(tool_call.name.clone()),
                                     timestamp: SystemTime::now(),
+                                    attachments: Vec::new(),
                                 });
                             }
                             Err(e) => {
@@ -166,6 +199,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     tool_call_id: Some(tool_call.id),
                                     tool_name: Some(tool_call.name.clone()),
                                     timestamp: SystemTime::now(),
+                                    attachments: Vec::new(),
                                 });
                             }
                         }
@@ -177,8 +211,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         max_tokens: Some(200),
                         temperature: Some(0.7),
                         top_p: Some(0.9),
+                        seed: None,
                         stop_tokens: vec![],
+                        stop_token_ids: vec![],
+                        n: None,
+                        append_to_session: None,
+                        chunking: StreamChunking::Token,
+                        block_on_full: false,
+                        model: None,
+                        max_duration: None,
                         stopping_config: None,
+                        debug_tokens: false,
                     };
 
                     println!("\nGenerating final response with tool results...");
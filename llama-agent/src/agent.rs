@@ -1,33 +1,65 @@
-use crate::chat_template::ChatTemplateEngine;
+use crate::audit::{AuditEvent, AuditLog};
+use crate::chat_template::{model_family, output_control_tokens};
+use crate::checkpoint::{ModelIdentity, RestoredSession, SessionCheckpoint};
+use crate::clock::{Clock, SystemClock};
 use crate::dependency_analysis::{DependencyAnalyzer, ParallelExecutionDecision};
-use crate::mcp::MCPClient;
+use crate::mcp::{ambiguous_name_error, MCPClient, ProgressHandler};
 use crate::model::ModelManager;
-use crate::queue::RequestQueue;
+use crate::postprocess::PostProcessing;
+use crate::queue::{QueueStats, RequestQueue, RequestStream, RequestTicket};
+use crate::rate_limit::{AdmissionPermit, RateLimiter};
+use crate::redaction::redact;
 use crate::session::SessionManager;
+use crate::sink::{GenerationSink, SinkGeneration};
 use crate::types::{
-    AgentAPI, AgentConfig, AgentError, GenerationRequest, GenerationResponse, HealthStatus,
-    Message, Session, SessionId, StreamChunk, ToolCall, ToolResult,
+    AgentAPI, AgentConfig, AgentError, ConfigError, ErrorContext, GenerationRequest,
+    GenerationResponse, HealthStatus, LoadMode, Message, MessageAttachment, ModelConfig,
+    ModelMetadata, ModelStatus, PromptMessage, QueueError, RenderedPrompt, Session, SessionConfig,
+    SessionError, SessionFilter, SessionId, SessionSummary, SessionUsage, ShutdownPhase,
+    ShutdownReport, StreamChunk, StreamEvent, ToolCall, ToolCallId, ToolPolicy, ToolProgress,
+    ToolResult, DEFAULT_MODEL_NAME, MAX_TOKENS_LIMIT,
 };
+use crate::validation::generation_request::{StopTokenConfig, StopTokenValidator};
+use crate::validation::Validator;
 use async_trait::async_trait;
 use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::{Instant, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, error, info, warn};
 
+/// Most generation passes one request may run, to prevent infinite tool call loops
+const MAX_TOOL_ITERATIONS: usize = 5;
+
+/// Time [`AgentServer::shutdown`] allows for every phase together
+pub const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(30);
+
 pub struct AgentServer {
     model_manager: Arc<ModelManager>,
     request_queue: Arc<RequestQueue>,
+    /// Models served besides the default one, by name
+    models: Arc<HashMap<String, ModelRoute>>,
     session_manager: Arc<SessionManager>,
     mcp_client: Arc<MCPClient>,
-    chat_template: Arc<ChatTemplateEngine>,
     dependency_analyzer: Arc<DependencyAnalyzer>,
+    rate_limiter: RateLimiter,
+    post_processing: Arc<PostProcessing>,
     config: AgentConfig,
     start_time: Instant,
     shutdown_token: tokio_util::sync::CancellationToken,
 }
 
+/// A model and the queue whose workers generate with it
+#[derive(Clone)]
+struct ModelRoute {
+    model_manager: Arc<ModelManager>,
+    request_queue: Arc<RequestQueue>,
+}
+
 impl std::fmt::Debug for AgentServer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AgentServer")
@@ -43,256 +75,1400 @@ impl AgentServer {
         request_queue: Arc<RequestQueue>,
         session_manager: Arc<SessionManager>,
         mcp_client: Arc<MCPClient>,
-        chat_template: Arc<ChatTemplateEngine>,
         dependency_analyzer: Arc<DependencyAnalyzer>,
         config: AgentConfig,
     ) -> Self {
+        crate::redaction::set_policy(config.redaction.clone());
+        // Limits use the session clock, so tests can drive both with one MockClock
+        let rate_limiter = RateLimiter::new(config.limits.clone(), session_manager.clock());
+        // Validation rejects invalid patterns; an agent built without it skips them all
+        let post_processing = PostProcessing::new(&config.post_processors).unwrap_or_else(|e| {
+            warn!("Ignoring post_processors: {}", e);
+            PostProcessing::default()
+        });
         Self {
             model_manager,
             request_queue,
+            models: Arc::new(HashMap::new()),
             session_manager,
             mcp_client,
-            chat_template,
             dependency_analyzer,
+            rate_limiter,
+            post_processing: Arc::new(post_processing),
             config,
             start_time: Instant::now(),
             shutdown_token: tokio_util::sync::CancellationToken::new(),
         }
     }
 
-    pub fn mcp_client(&self) -> &MCPClient {
-        &self.mcp_client
+    /// Another handle to this agent's shared state, for tasks that outlive a request
+    fn handle(&self) -> Self {
+        Self {
+            model_manager: self.model_manager.clone(),
+            request_queue: self.request_queue.clone(),
+            models: self.models.clone(),
+            session_manager: self.session_manager.clone(),
+            mcp_client: self.mcp_client.clone(),
+            dependency_analyzer: self.dependency_analyzer.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            post_processing: self.post_processing.clone(),
+            config: self.config.clone(),
+            start_time: self.start_time,
+            shutdown_token: self.shutdown_token.clone(),
+        }
     }
 
-    pub async fn shutdown(self) -> Result<(), AgentError> {
-        info!("Initiating AgentServer shutdown");
-        let shutdown_start = Instant::now();
+    /// Serve `model_manager` as `name` alongside the default model, generating on
+    /// `request_queue`; see [`AgentConfig::models`]
+    pub fn with_model(
+        mut self,
+        name: impl Into<String>,
+        model_manager: Arc<ModelManager>,
+        request_queue: Arc<RequestQueue>,
+    ) -> Self {
+        Arc::make_mut(&mut self.models).insert(
+            name.into(),
+            ModelRoute {
+                model_manager,
+                request_queue,
+            },
+        );
+        self
+    }
 
-        // Signal shutdown to all components
-        self.shutdown_token.cancel();
+    /// Control tokens of the chat template of the model serving `model`, removed by
+    /// post-processing
+    fn control_tokens(&self, model: Option<&str>) -> &'static [&'static str] {
+        let family = self
+            .route(model)
+            .ok()
+            .and_then(|route| model_family(&route.model_manager.get_config()));
+        output_control_tokens(family)
+    }
 
-        // Graceful shutdown with timeouts
-        let shutdown_timeout = std::time::Duration::from_secs(30);
+    /// Apply the configured post-processors to a finished response
+    fn post_process_response(&self, response: &mut GenerationResponse, control_tokens: &[&str]) {
+        if self.post_processing.is_empty() {
+            return;
+        }
+        response.generated_text = self
+            .post_processing
+            .apply(&response.generated_text, control_tokens);
+        for candidate in &mut response.candidates {
+            candidate.text = self.post_processing.apply(&candidate.text, control_tokens);
+        }
+    }
 
-        // Shutdown MCP client first with timeout
-        info!("Shutting down MCP client...");
-        let mcp_shutdown = async { self.mcp_client.shutdown_all().await };
+    /// Names of every served model, the default one first
+    pub fn model_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.models.keys().cloned().collect();
+        names.sort();
+        names.insert(0, DEFAULT_MODEL_NAME.to_string());
+        names
+    }
 
-        match tokio::time::timeout(shutdown_timeout, mcp_shutdown).await {
-            Ok(result) => result?,
-            Err(_) => {
-                warn!("MCP client shutdown timed out after {:?}", shutdown_timeout);
-            }
+    /// The model serving requests for `name`; `None` selects the default model
+    fn route(&self, name: Option<&str>) -> Result<ModelRoute, AgentError> {
+        match name {
+            None | Some(DEFAULT_MODEL_NAME) => Ok(self.default_route()),
+            Some(name) => self
+                .models
+                .get(name)
+                .cloned()
+                .ok_or_else(|| AgentError::UnknownModel {
+                    name: name.to_string(),
+                    available: self.model_names(),
+                }),
         }
+    }
 
-        // Wait for queue to finish current requests with timeout
-        info!("Waiting for request queue to drain...");
-        let queue_stats = self.request_queue.get_stats();
-        if queue_stats.current_queue_size > 0 {
-            warn!(
-                "Shutting down with {} requests still in queue",
-                queue_stats.current_queue_size
-            );
-
-            // Give some time for current requests to complete
-            let drain_timeout = std::time::Duration::from_secs(10);
-            tokio::time::sleep(drain_timeout).await;
+    fn default_route(&self) -> ModelRoute {
+        ModelRoute {
+            model_manager: self.model_manager.clone(),
+            request_queue: self.request_queue.clone(),
         }
+    }
+
+    /// The default model followed by the named ones
+    fn all_routes(&self) -> Vec<(String, ModelRoute)> {
+        let mut routes = vec![(DEFAULT_MODEL_NAME.to_string(), self.default_route())];
+        routes.extend(
+            self.models
+                .iter()
+                .map(|(name, route)| (name.clone(), route.clone())),
+        );
+        routes
+    }
+
+    pub fn mcp_client(&self) -> &MCPClient {
+        &self.mcp_client
+    }
 
-        // Force shutdown of remaining components
-        info!("Forcing shutdown of remaining components...");
+    /// The audit log configured with `AgentConfig::audit_log`, e.g. to set a redactor
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.session_manager.audit_log()
+    }
 
-        let shutdown_duration = shutdown_start.elapsed();
-        info!(
-            "AgentServer shutdown completed in {:?} (Final stats: {} completed, {} failed)",
-            shutdown_duration, queue_stats.completed_requests, queue_stats.failed_requests
+    /// Request counts and timing averages of the request queue
+    pub fn queue_stats(&self) -> QueueStats {
+        self.request_queue.get_stats()
+    }
+
+    /// Stop sequences `request` generates with: its own, then those of its model and the
+    /// model's chat template
+    pub fn stop_sequences(&self, request: &GenerationRequest) -> Result<Vec<String>, AgentError> {
+        let route = self.route(request.model.as_deref())?;
+        Ok(route.request_queue.stop_sequences(&request.stop_tokens))
+    }
+
+    /// Check a generation request against the configured limits before it is queued
+    fn admit(&self, session_id: &SessionId) -> Result<AdmissionPermit, AgentError> {
+        self.rate_limiter.admit(session_id).map_err(|e| {
+            self.request_queue.record_rate_limited();
+            warn!("Rejected request for session {}: {}", session_id, e);
+            e
+        })
+    }
+
+    /// Delete a session and shut down the MCP servers started for it
+    pub async fn delete_session(&self, session_id: &SessionId) -> Result<bool, AgentError> {
+        let deleted = self.session_manager.delete_session(session_id).await?;
+        self.mcp_client.remove_session_servers(session_id).await?;
+        Ok(deleted)
+    }
+
+    /// Append a conversation in the OpenAI chat messages format to a session.
+    ///
+    /// Assistant `tool_calls` are written into the message text and carried to the tool
+    /// results answering them. Returns the number of messages added; nothing is added if any
+    /// message is invalid. See [`Session::to_openai_messages`] for the reverse.
+    pub async fn import_openai_messages(
+        &self,
+        session_id: &SessionId,
+        messages: serde_json::Value,
+    ) -> Result<usize, AgentError> {
+        let messages =
+            crate::openai_format::messages_from_openai(&messages, self.session_manager.now())?;
+        let count = messages.len();
+        self.session_manager
+            .add_messages(session_id, messages)
+            .await?;
+        debug!(
+            "Imported {} OpenAI messages into session {}",
+            count, session_id
         );
+        Ok(count)
+    }
+
+    /// Change which tools a session may execute; takes effect on the next tool call
+    pub async fn set_tool_policy(
+        &self,
+        session_id: &SessionId,
+        policy: ToolPolicy,
+    ) -> Result<(), AgentError> {
+        self.session_manager
+            .set_tool_policy(session_id, policy)
+            .await?;
+        Ok(())
+    }
 
+    /// Mark a session as used now without changing it, keeping it from expiring; see
+    /// [`SessionManager::touch_session`]
+    pub async fn touch_session(&self, session_id: &SessionId) -> Result<(), AgentError> {
+        self.session_manager.touch_session(session_id).await?;
         Ok(())
     }
 
-    /// Validate tool call arguments against the tool's parameter schema
-    fn validate_tool_arguments(
+    /// Save a session to `path`, so [`AgentServer::restore_session`] can bring it back
+    /// after a restart; see [`crate::checkpoint`] for what is saved
+    pub async fn checkpoint_session(
         &self,
-        tool_call: &ToolCall,
-        tool_def: &crate::types::ToolDefinition,
-    ) -> Result<(), String> {
-        // If no parameters schema is defined, skip validation
-        if tool_def.parameters.is_null() {
-            debug!("No parameter schema defined for tool '{}'", tool_call.name);
-            return Ok(());
-        }
+        session_id: &SessionId,
+        path: &Path,
+    ) -> Result<(), AgentError> {
+        let session = self
+            .session_manager
+            .get_session(session_id)
+            .await?
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+        SessionCheckpoint::new(self.model_identity().await, session)
+            .write(path)
+            .await?;
+        info!("Checkpointed session {} to {}", session_id, path.display());
+        Ok(())
+    }
 
-        // Basic validation - could be enhanced with JSON Schema validation
-        if tool_call.arguments.is_null() && !tool_def.parameters.is_null() {
-            return Err("Tool requires arguments but none provided".to_string());
+    /// Bring back a session saved by [`AgentServer::checkpoint_session`], with its id,
+    /// messages and usage. Its session-scoped MCP servers start on its next request.
+    ///
+    /// A checkpoint taken with another model is restored all the same, with a warning
+    /// and `model_matches` false.
+    pub async fn restore_session(&self, path: &Path) -> Result<RestoredSession, AgentError> {
+        self.ensure_accepting()?;
+        let checkpoint = SessionCheckpoint::read(path).await?;
+        let model_matches = checkpoint.model.matches(&self.model_identity().await);
+        if !model_matches {
+            warn!(
+                "Session checkpoint {} was taken with another model ({:?}); restoring its messages only",
+                path.display(),
+                checkpoint.model.source
+            );
         }
 
-        // Additional validation could be added here:
-        // - JSON Schema validation against tool_def.parameters
-        // - Type checking for required fields
-        // - Range validation for numeric parameters
+        let (session, evicted) = self
+            .session_manager
+            .restore_session(checkpoint.session)
+            .await?;
+        if let Some(evicted) = evicted {
+            self.remove_evicted_session_servers(&evicted).await;
+        }
+        Ok(RestoredSession {
+            session,
+            model_matches,
+        })
+    }
 
-        debug!(
-            "Tool arguments validation passed for '{}' (basic validation only)",
-            tool_call.name
-        );
-        Ok(())
+    /// Identity of the default model, recorded in session checkpoints
+    async fn model_identity(&self) -> ModelIdentity {
+        let metadata = self.model_manager.get_metadata().await;
+        ModelIdentity::new(&self.model_manager.get_config(), metadata.as_ref())
     }
 
-    /// Determine if tool calls should be executed in parallel using sophisticated dependency analysis
-    fn should_execute_in_parallel(&self, tool_calls: &[ToolCall]) -> bool {
-        debug!(
-            "Analyzing {} tool calls for parallel execution using dependency analysis",
-            tool_calls.len()
-        );
+    async fn remove_evicted_session_servers(&self, evicted: &SessionId) {
+        if let Err(e) = self.mcp_client.remove_session_servers(evicted).await {
+            warn!(
+                "Failed to shut down MCP servers for evicted session {}: {}",
+                evicted, e
+            );
+        }
+    }
 
-        match self
-            .dependency_analyzer
-            .analyze_parallel_execution(tool_calls)
-        {
-            ParallelExecutionDecision::Parallel => {
-                debug!("Dependency analysis result: PARALLEL execution approved");
-                true
-            }
-            ParallelExecutionDecision::Sequential(reason) => {
-                debug!(
-                    "Dependency analysis result: SEQUENTIAL execution required - {}",
-                    reason
+    /// Remove expired sessions along with their session-scoped MCP servers
+    pub async fn cleanup_expired_sessions(&self) -> Result<usize, AgentError> {
+        let expired = self.session_manager.remove_expired_sessions().await?;
+        for session_id in &expired {
+            if let Err(e) = self.mcp_client.remove_session_servers(session_id).await {
+                warn!(
+                    "Failed to shut down MCP servers for expired session {}: {}",
+                    session_id, e
                 );
-                false
             }
         }
+        Ok(expired.len())
     }
 
-    /// Execute multiple tool calls in parallel
-    async fn execute_tools_parallel(
-        &self,
-        tool_calls: Vec<ToolCall>,
-        session: &Session,
-    ) -> Vec<ToolResult> {
-        use futures::future::join_all;
+    /// Load a different model and swap it in without restarting the agent.
+    ///
+    /// Sessions and MCP servers are preserved. Queued requests keep running on
+    /// the current model while the new one loads; the swap waits for in-flight
+    /// requests to finish. If the new model fails to load, the current model
+    /// stays in place and the error is returned.
+    pub async fn reload_model(&self, new_config: ModelConfig) -> Result<(), AgentError> {
+        info!("Reloading model for AgentServer");
+        self.model_manager.reload_model(new_config).await?;
+        Ok(())
+    }
 
-        let futures = tool_calls.into_iter().map(|tool_call| {
-            let session = session.clone();
-            async move {
-                debug!(
-                    "Starting parallel execution of tool: {} (id: {})",
-                    tool_call.name, tool_call.id
-                );
-                debug!("Parallel tool call arguments: {}", tool_call.arguments);
+    /// How the loaded model was resolved: file, path, size, cache hit and timings
+    pub async fn model_metadata(&self) -> Option<ModelMetadata> {
+        self.model_manager.get_metadata().await
+    }
 
-                match self.execute_tool(tool_call.clone(), &session).await {
-                    Ok(result) => {
-                        if let Some(error) = &result.error {
-                            debug!(
-                                "Parallel tool call '{}' completed with error: {}",
-                                tool_call.name, error
-                            );
-                        } else {
-                            debug!(
-                                "Parallel tool call '{}' completed successfully",
-                                tool_call.name
-                            );
-                            debug!(
-                                "Parallel tool call '{}' result: {}",
-                                tool_call.name, result.result
-                            );
-                        }
-                        result
-                    }
-                    Err(e) => {
-                        error!("Parallel tool call '{}' failed: {}", tool_call.name, e);
-                        debug!(
-                            "Parallel tool call '{}' unexpected error details: {}",
-                            tool_call.name, e
-                        );
-                        ToolResult {
-                            call_id: tool_call.id,
-                            result: serde_json::Value::Null,
-                            error: Some(format!("Parallel execution error: {}", e)),
-                        }
-                    }
-                }
-            }
-        });
+    /// Compute embeddings for `texts` with the loaded generation model.
+    ///
+    /// Vectors are mean-pooled over each text's tokens; set `normalize` to scale
+    /// them to unit L2 norm. Returns `AgentError::EmbeddingsNotSupported` when the
+    /// model cannot produce usable embeddings.
+    pub async fn embed(
+        &self,
+        texts: &[String],
+        normalize: bool,
+    ) -> Result<Vec<Vec<f32>>, AgentError> {
+        debug!("Embedding {} texts (normalize: {})", texts.len(), normalize);
+        self.request_queue
+            .submit_embedding_request(texts, normalize)
+            .await
+            .map_err(embedding_error)
+    }
 
-        let results = join_all(futures).await;
-        debug!(
-            "Parallel tool execution completed with {} results",
-            results.len()
-        );
-        results
+    /// Stream a generation to `sink` instead of returning a stream.
+    ///
+    /// Runs exactly like [`AgentAPI::generate_stream`], which feeds the sink on a
+    /// task of its own; errors before the request is queued are returned here rather
+    /// than passed to the sink. Cancelling the returned [`SinkGeneration`] has the
+    /// effect of dropping the stream.
+    pub async fn generate_with_sink(
+        &self,
+        request: GenerationRequest,
+        sink: Arc<dyn GenerationSink>,
+    ) -> Result<SinkGeneration, AgentError> {
+        let stream = self.generate_stream(request).await?;
+        Ok(SinkGeneration::spawn(stream, sink))
     }
 
-    async fn process_tool_calls(
+    /// Render a session's prompt with the chat template and count its tokens,
+    /// without generating. Tool instructions of the session's available tools are
+    /// included, as they would be for a generation request.
+    pub async fn render_prompt(
         &self,
-        text: &str,
-        session: &Session,
-    ) -> Result<Vec<ToolResult>, AgentError> {
-        debug!("Processing tool calls from generated text");
-        debug!("Generated text to analyze: {}", text);
+        session_id: &SessionId,
+    ) -> Result<RenderedPrompt, AgentError> {
+        let session = self
+            .session_manager
+            .get_session(session_id)
+            .await?
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+        Ok(self.request_queue.render_prompt(&session).await?)
+    }
 
-        // Extract tool calls from the generated text
-        let tool_calls = match self.chat_template.extract_tool_calls(text) {
-            Ok(calls) => {
-                debug!(
-                    "Successfully extracted {} tool calls from text",
-                    calls.len()
-                );
-                debug!("Tool call extraction result:");
-                for (i, call) in calls.iter().enumerate() {
-                    debug!(
-                        "  Tool call {}: name='{}', id='{}', arguments={}",
-                        i + 1,
-                        call.name,
-                        call.id,
-                        call.arguments
-                    );
-                }
-                calls
-            }
-            Err(e) => {
-                error!("Failed to extract tool calls from text: {}", e);
-                debug!("Text that failed tool call extraction: {}", text);
-                return Ok(Vec::new()); // Return empty results rather than failing
-            }
-        };
+    /// [`AgentAPI::generate`] under the id of `ticket`, without error context
+    async fn generate_as(
+        &self,
+        ticket: &RequestTicket,
+        request: GenerationRequest,
+    ) -> Result<GenerationResponse, AgentError> {
+        debug!(
+            "Processing generation request for session: {}",
+            request.session_id
+        );
+        self.ensure_accepting()?;
+        let route = self.route(request.model.as_deref())?;
 
-        if tool_calls.is_empty() {
-            debug!("No tool calls found in generated text");
-            debug!("Text analyzed: {}", text);
-            return Ok(Vec::new());
-        }
+        // Keeps the session from being evicted, expired or deleted while generating
+        let _in_flight = self.session_manager.begin_request(request.session_id);
 
-        debug!("Found {} tool calls to process", tool_calls.len());
-        for (i, tool_call) in tool_calls.iter().enumerate() {
-            debug!(
-                "Tool call {}: name='{}', id='{}', arguments={}",
-                i + 1,
-                tool_call.name,
-                tool_call.id,
-                tool_call.arguments
-            );
-        }
-        let mut results = Vec::new();
-        let mut successful_calls = 0;
-        let mut failed_calls = 0;
+        // Get session from session manager
+        let session = self
+            .session_manager
+            .get_session(&request.session_id)
+            .await?
+            .ok_or_else(|| {
+                AgentError::Session(crate::types::SessionError::NotFound(
+                    request.session_id.to_string(),
+                ))
+            })?;
 
-        // Check if we should execute tools in parallel or sequentially
-        let parallel_execution =
-            tool_calls.len() > 1 && self.should_execute_in_parallel(&tool_calls);
+        let request = self
+            .with_model_defaults(request, &route.model_manager)
+            .await;
 
-        if parallel_execution {
-            debug!("Executing {} tool calls in parallel", tool_calls.len());
-            results = self.execute_tools_parallel(tool_calls, session).await;
+        // Security: Validate input before processing
+        self.validate_generation_request_with_session(&request, &session)?;
+        self.validate_stop_tokens(&request, &session, &route.model_manager)?;
+        let _permit = self.admit(&request.session_id)?;
 
-            // Count results for logging
-            for result in &results {
-                if result.error.is_some() {
-                    failed_calls += 1;
-                } else {
-                    successful_calls += 1;
+        self.session_manager
+            .audit(AuditEvent::generation_started(&request, false));
+        let result = self.generate_with_tools(ticket, &request, session).await;
+        let audited = match &result {
+            Ok(response) => {
+                let usage = record_usage(&self.session_manager, request.session_id, response).await;
+                AuditEvent::generation_completed(request.session_id, response, usage)
+            }
+            Err(e) => AuditEvent::GenerationFailed {
+                session_id: request.session_id,
+                error: e.to_string(),
+            },
+        };
+        self.session_manager.audit(audited);
+        result
+    }
+
+    /// [`AgentAPI::generate_stream`] under the id of `ticket`; errors the stream
+    /// yields carry the request's context
+    async fn generate_stream_as(
+        &self,
+        ticket: &RequestTicket,
+        request: GenerationRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AgentError>> + Send>>, AgentError>
+    {
+        debug!(
+            "Processing streaming generation request for session: {}",
+            request.session_id
+        );
+        self.ensure_accepting()?;
+        let route = self.route(request.model.as_deref())?;
+
+        // Held by the returned stream, like the admission permit below
+        let in_flight = self.session_manager.begin_request(request.session_id);
+
+        // Get session from session manager
+        let session = self
+            .session_manager
+            .get_session(&request.session_id)
+            .await?
+            .ok_or_else(|| {
+                AgentError::Session(crate::types::SessionError::NotFound(
+                    request.session_id.to_string(),
+                ))
+            })?;
+
+        let request = self
+            .with_model_defaults(request, &route.model_manager)
+            .await;
+
+        // Security: Validate input before processing
+        self.validate_generation_request_with_session(&request, &session)?;
+        self.validate_stop_tokens(&request, &session, &route.model_manager)?;
+        // Held by the returned stream, so the request stays in flight until it is dropped
+        let permit = self.admit(&request.session_id)?;
+        self.session_manager
+            .audit(AuditEvent::generation_started(&request, true));
+
+        // Render session to prompt
+        let prompt = self.render_session_prompt(&session, &route.model_manager)?;
+        debug!("Session rendered to prompt: {} characters", prompt.len());
+
+        let append_response = appends_response(&request, &self.config.session_config);
+
+        // Submit to request queue for streaming; a full queue fails the call itself
+        let session = Arc::new(session);
+        let request_stream = route
+            .request_queue
+            .submit_streaming_request_as(ticket, request.clone(), Arc::clone(&session))
+            .await
+            .map_err(AgentError::from)?;
+        let session_manager = self.session_manager.clone();
+        let session_id = request.session_id;
+
+        // Dropping the stream cancels the request unless the response is to be stored anyway
+        let (sender, receiver) = mpsc::channel(100);
+        let forwarder = StreamForwarder::new(
+            sender,
+            append_response && self.config.session_config.append_on_stream_drop,
+        );
+        tokio::spawn(self.handle().stream_with_tools(
+            ticket.clone(),
+            request,
+            session,
+            request_stream,
+            forwarder,
+            append_response,
+        ));
+
+        let ticket = ticket.clone();
+        let stream = ReceiverStream::new(receiver).then(move |result| {
+            let _permit = &permit;
+            let _in_flight = &in_flight;
+            let session_manager = session_manager.clone();
+            let context = ticket.error_context(session_id);
+            async move {
+                record_stream_result(&session_manager, session_id, &result).await;
+                result.map_err(|e| e.with_context(context))
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+
+    /// Generate, running tool calls and generating again until the model answers
+    /// without one
+    async fn generate_with_tools(
+        &self,
+        ticket: &RequestTicket,
+        request: &GenerationRequest,
+        session: Session,
+    ) -> Result<GenerationResponse, AgentError> {
+        // Shared with queued requests as a snapshot; modified copy-on-write between iterations
+        let mut working_session = Arc::new(session);
+        let mut accumulated_response = String::new();
+        let mut total_tokens = 0u32;
+        let mut prompt_tokens = 0u32;
+        let mut prompt_time = std::time::Duration::ZERO;
+        let mut decode_time = std::time::Duration::ZERO;
+        let mut generation_time = std::time::Duration::ZERO;
+        let mut time_to_first_token = None;
+        let mut candidates = Vec::new();
+        let mut generated_tokens = Vec::new();
+        let mut finish_reason =
+            crate::types::FinishReason::Stopped("End of sequence token detected".to_string());
+        // Response text not yet stored in the session by a tool-call turn
+        let mut final_text = None;
+        let mut iterations = 0;
+        let control_tokens = self.control_tokens(request.model.as_deref());
+
+        loop {
+            iterations += 1;
+            if iterations > MAX_TOOL_ITERATIONS {
+                warn!(
+                    "Maximum tool call iterations ({}) reached for session: {}",
+                    MAX_TOOL_ITERATIONS, working_session.id
+                );
+                break;
+            }
+
+            debug!(
+                "Tool call iteration {} for session: {}",
+                iterations, working_session.id
+            );
+            debug!(
+                "Current session has {} messages",
+                working_session.messages.len()
+            );
+            for (i, msg) in working_session.messages.iter().enumerate() {
+                debug!(
+                    "Message {}: {:?} - {}",
+                    i + 1,
+                    msg.role,
+                    if msg.content.len() > 100 && crate::redaction::policy().is_full() {
+                        format!("{}...", &msg.content[..100])
+                    } else {
+                        redact(&msg.content).to_string()
+                    }
+                );
+            }
+
+            // Create generation request with current session state
+            let current_request = GenerationRequest {
+                session_id: working_session.id,
+                max_tokens: request.max_tokens,
+                temperature: request.temperature,
+                top_p: request.top_p,
+                seed: request.seed,
+                stop_tokens: request.stop_tokens.clone(),
+                stop_token_ids: request.stop_token_ids.clone(),
+                n: request.n,
+                append_to_session: request.append_to_session,
+                chunking: request.chunking,
+                block_on_full: request.block_on_full,
+                model: request.model.clone(),
+                max_duration: request.max_duration,
+                stopping_config: request.stopping_config.clone(),
+                debug_tokens: request.debug_tokens,
+            };
+
+            // Submit to the queue of the requested model
+            let route = self.route(request.model.as_deref())?;
+            let response = route
+                .request_queue
+                .submit_request_as(ticket, current_request, Arc::clone(&working_session))
+                .await?;
+
+            accumulated_response.push_str(&response.generated_text);
+            total_tokens += response.tokens_generated;
+            prompt_tokens += response.prompt_tokens;
+            prompt_time += response.prompt_time;
+            decode_time += response.decode_time;
+            // Timings cover model time only, not tool calls between iterations
+            if time_to_first_token.is_none() {
+                time_to_first_token = response
+                    .time_to_first_token
+                    .map(|first| generation_time + first);
+            }
+            generation_time += response.generation_time;
+            // Tool calls are followed on the first candidate; the others are
+            // returned from the final iteration only
+            candidates = response.candidates;
+            generated_tokens.extend(response.generated_tokens);
+            finish_reason = response.finish_reason.clone();
+
+            debug!(
+                "Generation iteration {} completed: {} tokens, finish_reason: {:?}",
+                iterations, response.tokens_generated, response.finish_reason
+            );
+            debug!(
+                "Generated text in iteration {}: '{}'",
+                iterations,
+                if response.generated_text.len() > 200 && crate::redaction::policy().is_full() {
+                    format!(
+                        "{}...(truncated, total {} chars)",
+                        &response.generated_text[..200],
+                        response.generated_text.len()
+                    )
+                } else {
+                    redact(&response.generated_text).to_string()
+                }
+            );
+
+            // Check if response contains tool calls
+            match &response.finish_reason {
+                crate::types::FinishReason::Stopped(reason) if reason == "Tool call detected" => {
+                    debug!(
+                        "Tool call detected in iteration {}, processing tool calls...",
+                        iterations
+                    );
+                    debug!(
+                        "Generated text for tool call processing: {}",
+                        redact(&response.generated_text)
+                    );
+
+                    // Pick up tool policy changes made while generating
+                    if let Some(stored) = self
+                        .session_manager
+                        .get_session(&working_session.id)
+                        .await?
+                    {
+                        Arc::make_mut(&mut working_session).tool_policy = stored.tool_policy;
+                    }
+
+                    // Process tool calls
+                    debug!("Beginning tool call processing workflow...");
+                    let tool_results = self
+                        .process_tool_calls(
+                            &response.generated_text,
+                            &working_session,
+                            &route.model_manager,
+                            None,
+                        )
+                        .await?;
+                    debug!(
+                        "Tool call processing completed with {} results",
+                        tool_results.len()
+                    );
+
+                    if tool_results.is_empty() {
+                        debug!("No tool results returned, ending tool call workflow");
+                        final_text = Some(response.generated_text);
+                        break;
+                    }
+
+                    // The assistant's response (with tool calls) followed by one Tool
+                    // message per result, stored as a single batch
+                    debug!(
+                        "Assistant message content: {}",
+                        redact(&response.generated_text)
+                    );
+                    let now = self.session_manager.now();
+                    let mut turn = Vec::with_capacity(tool_results.len() + 1);
+                    turn.push(assistant_message(response.generated_text.clone(), now));
+                    for (i, tool_result) in tool_results.iter().enumerate() {
+                        let message = tool_result_message(tool_result, now);
+                        debug!(
+                            "Adding tool message {}/{} for call_id: {} ({} characters, {} attachments)",
+                            i + 1,
+                            tool_results.len(),
+                            tool_result.call_id,
+                            message.content.len(),
+                            message.attachments.len()
+                        );
+                        turn.push(message);
+                    }
+
+                    debug!(
+                        "Session message count before adding tool turn: {}",
+                        working_session.messages.len()
+                    );
+                    record_turn(
+                        &self.session_manager,
+                        Arc::make_mut(&mut working_session),
+                        turn,
+                    )
+                    .await?;
+
+                    debug!(
+                        "Tool call processing completed with {} results, continuing generation",
+                        tool_results.len()
+                    );
+                    debug!(
+                        "Final session message count after tool workflow: {}",
+                        working_session.messages.len()
+                    );
+                    debug!("Continuing to next iteration {} to generate response incorporating tool results", iterations + 1);
+
+                    // Continue the loop to generate response incorporating tool results
+                    continue;
+                }
+                crate::types::FinishReason::Stopped(reason) => {
+                    // No more tool calls, we're done
+                    debug!(
+                        "Generation completed without tool calls after {} iterations (reason: {})",
+                        iterations, reason
+                    );
+                    debug!("Final generated text: {}", redact(&response.generated_text));
+                    debug!(
+                        "Final accumulated response length: {} characters",
+                        accumulated_response.len()
+                    );
+                    final_text = Some(response.generated_text);
+                    break;
+                }
+            }
+        }
+
+        if let Some(text) = final_text {
+            if appends_response(request, &self.config.session_config) {
+                let text = self.post_processing.apply(&text, control_tokens);
+                record_turn(
+                    &self.session_manager,
+                    Arc::make_mut(&mut working_session),
+                    vec![assistant_message(text, self.session_manager.now())],
+                )
+                .await?;
+            }
+        }
+
+        let mut final_response = GenerationResponse {
+            generated_text: accumulated_response,
+            tokens_generated: total_tokens,
+            generation_time,
+            finish_reason,
+            prompt_tokens,
+            prompt_time,
+            decode_time,
+            time_to_first_token,
+            candidates,
+            generated_tokens,
+        };
+        self.post_process_response(&mut final_response, control_tokens);
+
+        debug!(
+            "Complete generation workflow finished: {} total tokens across {} iterations",
+            total_tokens, iterations
+        );
+
+        Ok(final_response)
+    }
+
+    /// Forward a streamed generation, running tool calls and streaming again until the
+    /// model answers without one, as [`Self::generate_with_tools`] does.
+    ///
+    /// Tool calls are reported as [`StreamEvent`]s between the passes. The final chunk
+    /// sums up all passes; the final answer is stored in the session when
+    /// `append_response` is set, tool-call turns always are.
+    async fn stream_with_tools(
+        self,
+        ticket: RequestTicket,
+        request: GenerationRequest,
+        mut working_session: Arc<Session>,
+        mut request_stream: RequestStream,
+        mut forwarder: StreamForwarder,
+        append_response: bool,
+    ) {
+        // `working_session` is shared with queued requests as a snapshot; it is
+        // modified copy-on-write between passes
+        let mut total: Option<GenerationResponse> = None;
+        // Text of the latest pass, which is the answer once the loop ends
+        let mut text = String::new();
+        let mut iterations = 1;
+        let control_tokens = self.control_tokens(request.model.as_deref());
+        let model_manager = match self.route(request.model.as_deref()) {
+            Ok(route) => route.model_manager,
+            Err(e) => {
+                forwarder.send(Err(e)).await;
+                return;
+            }
+        };
+
+        loop {
+            let token_offset = total.as_ref().map_or(0, |t| t.tokens_generated);
+            let pass = PassOutput {
+                post_processing: &self.post_processing,
+                control_tokens,
+                token_offset,
+            };
+            let Some(response) = forward_pass(&mut request_stream, &mut forwarder, pass).await
+            else {
+                return;
+            };
+            let tool_call_detected = matches!(
+                &response.finish_reason,
+                crate::types::FinishReason::Stopped(reason) if reason == "Tool call detected"
+            );
+            text.clone_from(&response.generated_text);
+            let total_so_far = accumulate_response(total.take(), response);
+            let token_count = total_so_far.tokens_generated;
+            total = Some(total_so_far);
+
+            if !tool_call_detected {
+                break;
+            }
+            if iterations >= MAX_TOOL_ITERATIONS {
+                warn!(
+                    "Maximum tool call iterations ({}) reached for session: {}",
+                    MAX_TOOL_ITERATIONS, working_session.id
+                );
+                break;
+            }
+
+            // Pick up tool policy changes made while generating
+            let stored = match self.session_manager.get_session(&working_session.id).await {
+                Ok(stored) => stored,
+                Err(e) => {
+                    forwarder.send(Err(e.into())).await;
+                    return;
+                }
+            };
+            if let Some(stored) = stored {
+                Arc::make_mut(&mut working_session).tool_policy = stored.tool_policy;
+            }
+
+            // Tool calls stop with the agent, or when the consumer drops the stream
+            // and nothing is left to store
+            let tool_cancel = self.shutdown_token.child_token();
+            let events = ToolEvents {
+                sender: &forwarder.sender,
+                token_count,
+                cancel: &tool_cancel,
+            };
+            let run_tools =
+                self.process_tool_calls(&text, &working_session, &model_manager, Some(&events));
+            let tool_results = tokio::select! {
+                results = run_tools => results,
+                never = forwarder.cancel_when_dropped(&tool_cancel) => match never {},
+            };
+            if tool_cancel.is_cancelled() {
+                debug!("Tool calls cancelled, not resuming generation");
+                return;
+            }
+            let tool_results = match tool_results {
+                Ok(results) => results,
+                Err(e) => {
+                    forwarder.send(Err(e)).await;
+                    return;
+                }
+            };
+            if tool_results.is_empty() {
+                break;
+            }
+
+            // The assistant's response (with tool calls) followed by one Tool message per
+            // result, stored as a single batch
+            let now = self.session_manager.now();
+            let mut turn = Vec::with_capacity(tool_results.len() + 1);
+            turn.push(assistant_message(std::mem::take(&mut text), now));
+            turn.extend(
+                tool_results
+                    .iter()
+                    .map(|tool_result| tool_result_message(tool_result, now)),
+            );
+            if let Err(e) = record_turn(
+                &self.session_manager,
+                Arc::make_mut(&mut working_session),
+                turn,
+            )
+            .await
+            {
+                forwarder.send(Err(e)).await;
+                return;
+            }
+
+            if !forwarder
+                .send(Ok(StreamChunk::for_event(
+                    StreamEvent::GenerationResumed,
+                    token_count,
+                )))
+                .await
+            {
+                return;
+            }
+            iterations += 1;
+            debug!(
+                "Streaming pass {} for session {} after {} tool results",
+                iterations,
+                working_session.id,
+                tool_results.len()
+            );
+            let submitted = match self.route(request.model.as_deref()) {
+                Ok(route) => {
+                    route
+                        .request_queue
+                        .submit_streaming_request_as(
+                            &ticket,
+                            request.clone(),
+                            Arc::clone(&working_session),
+                        )
+                        .await
+                }
+                Err(e) => {
+                    forwarder.send(Err(e)).await;
+                    return;
+                }
+            };
+            request_stream = match submitted {
+                Ok(stream) => stream,
+                Err(e) => {
+                    forwarder.send(Err(e.into())).await;
+                    return;
+                }
+            };
+        }
+
+        let Some(mut total) = total else {
+            return;
+        };
+        self.post_process_response(&mut total, control_tokens);
+        if append_response {
+            let text = self.post_processing.apply(&text, control_tokens);
+            let answer = vec![assistant_message(text, self.session_manager.now())];
+            if let Err(e) = record_turn(
+                &self.session_manager,
+                Arc::make_mut(&mut working_session),
+                answer,
+            )
+            .await
+            {
+                warn!(
+                    "Failed to store streamed response in session {}: {}",
+                    working_session.id, e
+                );
+            }
+        }
+        forwarder
+            .send(Ok(StreamChunk {
+                text: String::new(),
+                is_complete: true,
+                token_count: total.tokens_generated,
+                response: Some(total),
+                event: StreamEvent::TextChunk,
+                token_ids: Vec::new(),
+            }))
+            .await;
+    }
+
+    /// [`AgentAPI::execute_tool`] without error context, recorded in the audit log
+    async fn execute_tool_audited(
+        &self,
+        tool_call: ToolCall,
+        session: &Session,
+        on_progress: Option<ProgressHandler>,
+        cancel: &tokio_util::sync::CancellationToken,
+    ) -> Result<ToolResult, AgentError> {
+        self.session_manager
+            .audit(AuditEvent::tool_call(session.id, &tool_call));
+        let call_id = tool_call.id;
+        let result = self.run_tool(tool_call, session, on_progress, cancel).await;
+        let audited = match &result {
+            Ok(tool_result) => AuditEvent::tool_result(session.id, tool_result),
+            Err(e) => AuditEvent::ToolResult {
+                session_id: session.id,
+                call_id,
+                result: serde_json::Value::Null,
+                error: Some(e.to_string()),
+            },
+        };
+        self.session_manager.audit(audited);
+        result
+    }
+
+    /// Run a tool call without recording it in the audit log. The call is cancelled
+    /// with `cancel`, or by id through the MCP client.
+    async fn run_tool(
+        &self,
+        tool_call: ToolCall,
+        session: &Session,
+        on_progress: Option<ProgressHandler>,
+        cancel: &tokio_util::sync::CancellationToken,
+    ) -> Result<ToolResult, AgentError> {
+        debug!(
+            "Executing tool call: {} (id: {}) in session: {}",
+            tool_call.name, tool_call.id, session.id
+        );
+        debug!("Tool call arguments: {}", redact(&tool_call.arguments));
+
+        // Validate tool call name is not empty
+        if tool_call.name.trim().is_empty() {
+            let error_msg = "Tool name cannot be empty";
+            error!("{}", error_msg);
+            return Ok(ToolResult {
+                call_id: tool_call.id,
+                result: serde_json::Value::Null,
+                error: Some(error_msg.to_string()),
+            });
+        }
+
+        check_tool_policy(session, &tool_call)?;
+
+        // Find the tool definition
+        let tool_def = match session
+            .available_tools
+            .iter()
+            .find(|t| t.name == tool_call.name)
+        {
+            Some(tool) => tool,
+            None => {
+                // A name several servers share is only available qualified by server
+                let qualified: Vec<String> = session
+                    .available_tools
+                    .iter()
+                    .filter(|t| t.original_name.as_deref() == Some(tool_call.name.as_str()))
+                    .map(|t| t.name.clone())
+                    .collect();
+                let error_msg = if qualified.is_empty() {
+                    format!(
+                        "Tool '{}' not found in available tools. Available tools: {}",
+                        tool_call.name,
+                        session
+                            .available_tools
+                            .iter()
+                            .map(|t| t.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                } else {
+                    ambiguous_name_error("Tool", &tool_call.name, &qualified)
+                };
+                error!("{}", error_msg);
+                return Ok(ToolResult {
+                    call_id: tool_call.id,
+                    result: serde_json::Value::Null,
+                    error: Some(error_msg),
+                });
+            }
+        };
+
+        debug!(
+            "Found tool definition for '{}' on server '{}'",
+            tool_call.name, tool_def.server_name
+        );
+
+        // Validate tool arguments structure if parameters schema is available
+        if let Err(validation_error) = self.validate_tool_arguments(&tool_call, tool_def) {
+            warn!(
+                "Tool call arguments validation failed for '{}': {}",
+                tool_call.name, validation_error
+            );
+            // Continue execution despite validation failure but log the issue
+        }
+
+        // Execute the tool call through MCP client with error handling
+        debug!(
+            "Calling MCP server '{}' for tool '{}'",
+            tool_def.server_name, tool_call.name
+        );
+        match self
+            .mcp_client
+            .call_routed_tool(
+                &tool_call,
+                &tool_def.server_name,
+                tool_def.server_tool_name(),
+                on_progress,
+                cancel,
+            )
+            .await
+        {
+            Ok(result_value) => {
+                debug!("Tool call '{}' completed successfully", tool_call.name);
+                debug!("Tool call result: {}", redact(&result_value));
+                let result = limit_tool_result(
+                    &tool_call,
+                    result_value,
+                    &self.config.parallel_execution_config,
+                )
+                .await;
+                Ok(ToolResult {
+                    call_id: tool_call.id,
+                    result,
+                    error: None,
+                })
+            }
+            Err(crate::types::MCPError::Cancelled) => Ok(ToolResult::cancelled(tool_call.id)),
+            Err(mcp_error) => {
+                let error_msg = format!("Tool execution failed: {}", mcp_error);
+                error!("Tool call '{}' failed: {}", tool_call.name, error_msg);
+                debug!(
+                    "Failed tool call arguments were: {}",
+                    redact(&tool_call.arguments)
+                );
+
+                // Return ToolResult with error instead of propagating the error
+                // This allows the workflow to continue with partial failures
+                Ok(ToolResult {
+                    call_id: tool_call.id,
+                    result: serde_json::Value::Null,
+                    error: Some(error_msg),
+                })
+            }
+        }
+    }
+
+    /// Shut down within [`DEFAULT_SHUTDOWN_DEADLINE`]; see [`Self::shutdown_with_deadline`]
+    pub async fn shutdown(&self) -> Result<ShutdownReport, AgentError> {
+        self.shutdown_with_deadline(DEFAULT_SHUTDOWN_DEADLINE).await
+    }
+
+    /// Shut down in order, finishing by `deadline`:
+    ///
+    /// 1. Stop accepting work: `generate`, `generate_stream` and `create_session` fail
+    ///    with `AgentError::ShuttingDown`.
+    /// 2. Let queued and running requests finish for up to two thirds of the deadline,
+    ///    then cancel the rest.
+    /// 3. Shut down every MCP server within the remaining time.
+    /// 4. Unload the model.
+    /// 5. Flush the audit log.
+    ///
+    /// Every phase runs even if an earlier one fails; the first MCP error is returned
+    /// once all are done.
+    pub async fn shutdown_with_deadline(
+        &self,
+        deadline: Duration,
+    ) -> Result<ShutdownReport, AgentError> {
+        info!("Initiating AgentServer shutdown (deadline {:?})", deadline);
+        let shutdown_start = tokio::time::Instant::now();
+        let deadline_at = shutdown_start + deadline;
+        let mut report = ShutdownReport::default();
+        let mut phase_start = Instant::now();
+        let mut end_phase = |report: &mut ShutdownReport, phase: ShutdownPhase| {
+            let elapsed = phase_start.elapsed();
+            info!("Shutdown phase {:?} finished in {:?}", phase, elapsed);
+            report.phases.push((phase, elapsed));
+            phase_start = Instant::now();
+        };
+
+        self.shutdown_token.cancel();
+        end_phase(&mut report, ShutdownPhase::StopAccepting);
+
+        let routes = self.all_routes();
+        let queue_size: usize = routes
+            .iter()
+            .map(|(_, route)| route.request_queue.get_queue_size())
+            .sum();
+        if queue_size > 0 {
+            info!("Waiting for {} requests to finish...", queue_size);
+        }
+        for (_, route) in &routes {
+            if !route
+                .request_queue
+                .drain(shutdown_start + deadline * 2 / 3)
+                .await
+            {
+                report.cancelled_requests += route.request_queue.cancel_all();
+            }
+        }
+        end_phase(&mut report, ShutdownPhase::DrainQueue);
+
+        let mcp_timeout = deadline_at.saturating_duration_since(tokio::time::Instant::now());
+        let mcp_result =
+            match tokio::time::timeout(mcp_timeout, self.mcp_client.shutdown_all()).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!("MCP client shutdown timed out after {:?}", mcp_timeout);
+                    report.mcp_timed_out = true;
+                    Ok(())
+                }
+            };
+        if let Err(e) = &mcp_result {
+            warn!("Error shutting down MCP servers: {}", e);
+        }
+        end_phase(&mut report, ShutdownPhase::StopMcpServers);
+
+        for (_, route) in &routes {
+            route.model_manager.unload_model().await;
+        }
+        end_phase(&mut report, ShutdownPhase::UnloadModel);
+
+        if let Some(audit_log) = self.audit_log() {
+            audit_log.flush().await;
+        }
+        end_phase(&mut report, ShutdownPhase::FlushAuditLog);
+
+        let queue_stats = self.request_queue.get_stats();
+        info!(
+            "AgentServer shutdown completed in {:?} (Final stats: {} completed, {} failed, {} cancelled)",
+            shutdown_start.elapsed(),
+            queue_stats.completed_requests,
+            queue_stats.failed_requests,
+            queue_stats.cancelled_requests
+        );
+
+        mcp_result?;
+        Ok(report)
+    }
+
+    /// Fail with `AgentError::ShuttingDown` once shutdown has begun
+    fn ensure_accepting(&self) -> Result<(), AgentError> {
+        if self.shutdown_token.is_cancelled() {
+            return Err(AgentError::ShuttingDown);
+        }
+        Ok(())
+    }
+
+    /// Validate tool call arguments against the tool's parameter schema
+    fn validate_tool_arguments(
+        &self,
+        tool_call: &ToolCall,
+        tool_def: &crate::types::ToolDefinition,
+    ) -> Result<(), String> {
+        // If no parameters schema is defined, skip validation
+        if tool_def.parameters.is_null() {
+            debug!("No parameter schema defined for tool '{}'", tool_call.name);
+            return Ok(());
+        }
+
+        // Basic validation - could be enhanced with JSON Schema validation
+        if tool_call.arguments.is_null() && !tool_def.parameters.is_null() {
+            return Err("Tool requires arguments but none provided".to_string());
+        }
+
+        // Additional validation could be added here:
+        // - JSON Schema validation against tool_def.parameters
+        // - Type checking for required fields
+        // - Range validation for numeric parameters
+
+        debug!(
+            "Tool arguments validation passed for '{}' (basic validation only)",
+            tool_call.name
+        );
+        Ok(())
+    }
+
+    /// Determine if tool calls should be executed in parallel using sophisticated dependency analysis
+    fn should_execute_in_parallel(&self, tool_calls: &[ToolCall]) -> bool {
+        debug!(
+            "Analyzing {} tool calls for parallel execution using dependency analysis",
+            tool_calls.len()
+        );
+
+        match self
+            .dependency_analyzer
+            .analyze_parallel_execution(tool_calls)
+        {
+            ParallelExecutionDecision::Parallel => {
+                debug!("Dependency analysis result: PARALLEL execution approved");
+                true
+            }
+            ParallelExecutionDecision::Sequential(reason) => {
+                debug!(
+                    "Dependency analysis result: SEQUENTIAL execution required - {}",
+                    reason
+                );
+                false
+            }
+        }
+    }
+
+    /// Execute multiple tool calls in parallel
+    async fn execute_tools_parallel(
+        &self,
+        tool_calls: Vec<ToolCall>,
+        session: &Session,
+        events: Option<&ToolEvents<'_>>,
+    ) -> Vec<ToolResult> {
+        use futures::future::join_all;
+
+        let futures = tool_calls.into_iter().map(|tool_call| {
+            let session = session.clone();
+            async move {
+                debug!(
+                    "Starting parallel execution of tool: {} (id: {})",
+                    tool_call.name, tool_call.id
+                );
+                debug!(
+                    "Parallel tool call arguments: {}",
+                    redact(&tool_call.arguments)
+                );
+
+                match self
+                    .execute_tool_reporting(tool_call.clone(), &session, events)
+                    .await
+                {
+                    Err(AgentError::ToolDenied { tool, policy }) => {
+                        denied_tool_result(tool_call.id, &tool, &policy)
+                    }
+                    Ok(result) => {
+                        if let Some(error) = &result.error {
+                            debug!(
+                                "Parallel tool call '{}' completed with error: {}",
+                                tool_call.name, error
+                            );
+                        } else {
+                            debug!(
+                                "Parallel tool call '{}' completed successfully",
+                                tool_call.name
+                            );
+                            debug!(
+                                "Parallel tool call '{}' result: {}",
+                                tool_call.name, result.result
+                            );
+                        }
+                        result
+                    }
+                    Err(e) => {
+                        error!("Parallel tool call '{}' failed: {}", tool_call.name, e);
+                        debug!(
+                            "Parallel tool call '{}' unexpected error details: {}",
+                            tool_call.name, e
+                        );
+                        ToolResult {
+                            call_id: tool_call.id,
+                            result: serde_json::Value::Null,
+                            error: Some(format!("Parallel execution error: {}", e)),
+                        }
+                    }
+                }
+            }
+        });
+
+        let results = join_all(futures).await;
+        debug!(
+            "Parallel tool execution completed with {} results",
+            results.len()
+        );
+        results
+    }
+
+    /// Run the tool calls in generated text, extracted with the chat template of the model
+    /// that generated it, reporting each to `events` if given
+    async fn process_tool_calls(
+        &self,
+        text: &str,
+        session: &Session,
+        model_manager: &ModelManager,
+        events: Option<&ToolEvents<'_>>,
+    ) -> Result<Vec<ToolResult>, AgentError> {
+        debug!("Processing tool calls from generated text");
+        debug!("Generated text to analyze: {}", redact(text));
+
+        // Extract tool calls from the generated text
+        let tool_calls = match model_manager.chat_template().extract_tool_calls(text) {
+            Ok(calls) => {
+                debug!(
+                    "Successfully extracted {} tool calls from text",
+                    calls.len()
+                );
+                debug!("Tool call extraction result:");
+                for (i, call) in calls.iter().enumerate() {
+                    debug!(
+                        "  Tool call {}: name='{}', id='{}', arguments={}",
+                        i + 1,
+                        call.name,
+                        call.id,
+                        redact(&call.arguments)
+                    );
+                }
+                calls
+            }
+            Err(e) => {
+                error!("Failed to extract tool calls from text: {}", e);
+                debug!("Text that failed tool call extraction: {}", redact(text));
+                return Ok(Vec::new()); // Return empty results rather than failing
+            }
+        };
+
+        if tool_calls.is_empty() {
+            debug!("No tool calls found in generated text");
+            debug!("Text analyzed: {}", redact(text));
+            return Ok(Vec::new());
+        }
+
+        debug!("Found {} tool calls to process", tool_calls.len());
+        for (i, tool_call) in tool_calls.iter().enumerate() {
+            debug!(
+                "Tool call {}: name='{}', id='{}', arguments={}",
+                i + 1,
+                tool_call.name,
+                tool_call.id,
+                redact(&tool_call.arguments)
+            );
+        }
+        let mut results = Vec::new();
+        let mut successful_calls = 0;
+        let mut failed_calls = 0;
+
+        // Check if we should execute tools in parallel or sequentially
+        let parallel_execution =
+            tool_calls.len() > 1 && self.should_execute_in_parallel(&tool_calls);
+
+        if parallel_execution {
+            debug!("Executing {} tool calls in parallel", tool_calls.len());
+            results = self
+                .execute_tools_parallel(tool_calls, session, events)
+                .await;
+
+            // Count results for logging
+            for result in &results {
+                if result.error.is_some() {
+                    failed_calls += 1;
+                } else {
+                    successful_calls += 1;
                 }
             }
         } else {
@@ -307,14 +1483,21 @@ impl AgentServer {
                     tool_call.name,
                     tool_call.id
                 );
-                debug!("Tool call arguments: {}", tool_call.arguments);
+                debug!("Tool call arguments: {}", redact(&tool_call.arguments));
 
                 // Execute tool call - errors are handled within execute_tool and returned as ToolResult
                 debug!(
                     "Executing tool call '{}' with id '{}'...",
                     tool_call.name, tool_call.id
                 );
-                match self.execute_tool(tool_call.clone(), session).await {
+                match self
+                    .execute_tool_reporting(tool_call.clone(), session, events)
+                    .await
+                {
+                    Err(AgentError::ToolDenied { tool, policy }) => {
+                        failed_calls += 1;
+                        results.push(denied_tool_result(tool_call.id, &tool, &policy));
+                    }
                     Ok(result) => {
                         if let Some(error) = &result.error {
                             failed_calls += 1;
@@ -324,14 +1507,18 @@ impl AgentServer {
                             );
                             debug!(
                                 "Tool call '{}' error result: call_id={}, error={}",
-                                tool_call.name, result.call_id, error
+                                tool_call.name,
+                                result.call_id,
+                                redact(error)
                             );
                         } else {
                             successful_calls += 1;
                             debug!("Tool call '{}' completed successfully", tool_call.name);
                             debug!(
                                 "Tool call '{}' success result: call_id={}, result={}",
-                                tool_call.name, result.call_id, result.result
+                                tool_call.name,
+                                result.call_id,
+                                redact(&result.result)
                             );
                         }
                         results.push(result);
@@ -366,19 +1553,91 @@ impl AgentServer {
         Ok(results)
     }
 
-    async fn render_session_prompt(&self, session: &Session) -> Result<String, AgentError> {
-        self.model_manager
-            .with_model(|model| {
-                self.chat_template.render_session_with_config(
+    /// Execute a tool call, reporting its start, progress and completion to `events`
+    /// if given
+    async fn execute_tool_reporting(
+        &self,
+        tool_call: ToolCall,
+        session: &Session,
+        events: Option<&ToolEvents<'_>>,
+    ) -> Result<ToolResult, AgentError> {
+        let Some(events) = events else {
+            return self
+                .execute_tool_audited(
+                    tool_call,
                     session,
-                    model,
-                    Some(&self.config.model),
+                    None,
+                    &tokio_util::sync::CancellationToken::new(),
                 )
+                .await;
+        };
+
+        let name = tool_call.name.clone();
+        events
+            .send(StreamEvent::ToolCallStarted {
+                name: name.clone(),
+                arguments: tool_call.arguments.clone(),
             })
-            .await?
+            .await;
+        let started = Instant::now();
+        let on_progress = events.progress_handler(name.clone());
+        let result = self
+            .execute_tool_audited(tool_call, session, Some(on_progress), events.cancel)
+            .await;
+        let is_error = !matches!(&result, Ok(tool_result) if tool_result.error.is_none());
+        events
+            .send(StreamEvent::ToolCallCompleted {
+                name,
+                duration: started.elapsed(),
+                is_error,
+            })
+            .await;
+        result
+    }
+
+    /// Fill unset request fields from the loaded model's `generation_config.json`.
+    ///
+    /// In lazy mode nothing is filled until the first request has loaded the model.
+    async fn with_model_defaults(
+        &self,
+        request: GenerationRequest,
+        model_manager: &ModelManager,
+    ) -> GenerationRequest {
+        match model_manager.hf_generation_defaults().await {
+            Some(defaults) => request.with_model_defaults(&defaults),
+            None => request,
+        }
+    }
+
+    fn render_session_prompt(
+        &self,
+        session: &Session,
+        model_manager: &ModelManager,
+    ) -> Result<String, AgentError> {
+        model_manager
+            .chat_template()
+            .render_session_for_config(session, Some(&model_manager.get_config()))
             .map_err(AgentError::Template)
     }
 
+    /// Check the request's stop tokens against the chat template of the model serving it
+    fn validate_stop_tokens(
+        &self,
+        request: &GenerationRequest,
+        session: &Session,
+        model_manager: &ModelManager,
+    ) -> Result<(), AgentError> {
+        let model_config = model_manager.get_config();
+        let chat_template = model_manager.chat_template();
+        StopTokenValidator::with_config(StopTokenConfig {
+            control_sequences: chat_template.control_sequences(Some(&model_config)),
+            assistant_header: chat_template.assistant_header(Some(&model_config)),
+            collision_severity: self.config.queue_config.stop_token_collisions,
+        })
+        .validate(session, request)
+        .map_err(|e| AgentError::Queue(crate::types::QueueError::WorkerError(e.to_string())))
+    }
+
     /// Comprehensive security validation for generation requests
     fn validate_generation_request_with_session(
         &self,
@@ -430,16 +1689,29 @@ impl AgentServer {
 
         // Security: Validate generation parameters with bounds
         if let Some(max_tokens) = request.max_tokens {
-            if max_tokens == 0 {
+            if max_tokens > MAX_TOKENS_LIMIT {
+                return Err(AgentError::Queue(crate::types::QueueError::WorkerError(
+                    format!(
+                        "max_tokens exceeds security limit of 32K (requested: {})",
+                        max_tokens
+                    ),
+                )));
+            }
+        }
+
+        // A time budget must end before the request times out
+        if let Some(max_duration) = request.effective_max_duration() {
+            if max_duration.is_zero() {
                 return Err(AgentError::Queue(crate::types::QueueError::WorkerError(
-                    "max_tokens must be greater than 0".to_string(),
+                    "max_duration must be greater than 0".to_string(),
                 )));
             }
-            if max_tokens > 32_768 {
+            let request_timeout = self.config.queue_config.request_timeout;
+            if max_duration > request_timeout {
                 return Err(AgentError::Queue(crate::types::QueueError::WorkerError(
                     format!(
-                        "max_tokens exceeds security limit of 32K (requested: {})",
-                        max_tokens
+                        "max_duration of {:?} exceeds the request timeout of {:?}",
+                        max_duration, request_timeout
                     ),
                 )));
             }
@@ -474,23 +1746,37 @@ impl AgentServer {
         }
 
         // Security: Validate stop tokens
-        if request.stop_tokens.len() > 20 {
+        let queue_config = &self.config.queue_config;
+        if request.stop_tokens.len() > queue_config.max_stop_tokens {
             return Err(AgentError::Queue(crate::types::QueueError::WorkerError(
                 format!(
-                    "Too many stop tokens: {} (max 20 allowed)",
-                    request.stop_tokens.len()
+                    "Too many stop tokens: {} (max {} allowed)",
+                    request.stop_tokens.len(),
+                    queue_config.max_stop_tokens
                 ),
             )));
         }
 
         for (i, stop_token) in request.stop_tokens.iter().enumerate() {
-            if stop_token.len() > 100 {
+            if stop_token.len() > queue_config.max_stop_token_length {
                 return Err(AgentError::Queue(crate::types::QueueError::WorkerError(
-                    format!("Stop token {} exceeds maximum length of 100 chars", i),
+                    format!(
+                        "Stop token {} exceeds maximum length of {} chars",
+                        i, queue_config.max_stop_token_length
+                    ),
                 )));
             }
         }
 
+        if request.stop_token_ids.len() > 20 {
+            return Err(AgentError::Queue(crate::types::QueueError::WorkerError(
+                format!(
+                    "Too many stop token ids: {} (max 20 allowed)",
+                    request.stop_token_ids.len()
+                ),
+            )));
+        }
+
         Ok(())
     }
 
@@ -554,295 +1840,635 @@ impl AgentServer {
     }
 }
 
-#[async_trait]
-impl AgentAPI for AgentServer {
-    async fn initialize(config: AgentConfig) -> Result<Self, AgentError> {
-        info!("Initializing AgentServer with config: {:?}", config);
+/// Surface unsupported-embedding failures as their own error instead of a queue error
+fn embedding_error(error: QueueError) -> AgentError {
+    match error {
+        QueueError::EmbeddingsNotSupported(reason) => AgentError::EmbeddingsNotSupported(reason),
+        other => other.into(),
+    }
+}
 
-        // Validate configuration
-        config.validate()?;
+/// The session manager for `config`, recording to its audit log if one is configured
+pub(crate) fn session_manager_for(
+    config: &AgentConfig,
+    clock: Arc<dyn Clock>,
+) -> Result<SessionManager, AgentError> {
+    let mut session_config = config.session_config.clone();
+    if session_config.system_prompt.is_none() {
+        session_config.system_prompt = config.default_system_prompt.clone();
+    }
+    let session_manager = SessionManager::new(session_config).with_clock(clock);
+    let Some(audit_config) = &config.audit_log else {
+        return Ok(session_manager);
+    };
+    let audit_log = AuditLog::open(audit_config).map_err(|e| {
+        ConfigError::Invalid(format!(
+            "Cannot open audit log {}: {}",
+            audit_config.path.display(),
+            e
+        ))
+    })?;
+    info!(
+        "Recording session events to {}",
+        audit_config.path.display()
+    );
+    if !config.redaction.is_full() {
+        let policy = config.redaction.clone();
+        audit_log.set_redactor(move |text| policy.apply(text));
+    }
+    Ok(session_manager.with_audit_log(audit_log))
+}
 
-        // Initialize model manager
-        let model_manager = ModelManager::new(config.model.clone())?;
-        model_manager.load_model().await?;
-        info!("Model manager initialized and model loaded");
-        let model_manager = Arc::new(model_manager);
+/// Add a completed response to its session's token usage, returning the new totals
+/// unless the session is gone
+async fn record_usage(
+    session_manager: &SessionManager,
+    session_id: SessionId,
+    response: &GenerationResponse,
+) -> Option<SessionUsage> {
+    session_manager
+        .record_usage(
+            &session_id,
+            response.prompt_tokens,
+            response.tokens_generated,
+        )
+        .await
+        .ok()
+}
 
-        // Initialize request queue
-        let request_queue = Arc::new(RequestQueue::new(
-            model_manager.clone(),
-            config.queue_config.clone(),
-        ));
-        info!("Request queue initialized");
+/// Record the end of a streamed generation: its final chunk or an error
+async fn record_stream_result(
+    session_manager: &SessionManager,
+    session_id: SessionId,
+    result: &Result<StreamChunk, AgentError>,
+) {
+    match result {
+        Ok(chunk) => {
+            if let Some(response) = &chunk.response {
+                let usage = record_usage(session_manager, session_id, response).await;
+                session_manager.audit(AuditEvent::generation_completed(
+                    session_id, response, usage,
+                ));
+            }
+        }
+        Err(e) => session_manager.audit(AuditEvent::GenerationFailed {
+            session_id,
+            error: e.to_string(),
+        }),
+    }
+}
 
-        // Initialize session manager
-        let session_manager = Arc::new(SessionManager::new(config.session_config.clone()));
-        info!("Session manager initialized");
+/// Store a tool-call turn in the session and mirror it into the working copy.
+///
+/// The turn is appended as one batch. If another writer changed the session since the working
+/// copy was loaded, the turn goes after their messages and the working copy is reloaded.
+async fn record_turn(
+    session_manager: &SessionManager,
+    working_session: &mut Session,
+    turn: Vec<Message>,
+) -> Result<(), AgentError> {
+    let expected = working_session.messages.len();
+    match session_manager
+        .compare_and_append(&working_session.id, expected, turn.clone())
+        .await
+    {
+        Ok(()) => {
+            working_session.messages.extend(turn);
+            working_session.updated_at = session_manager.now().max(working_session.updated_at);
+        }
+        Err(SessionError::ConcurrentModification {
+            expected, actual, ..
+        }) => {
+            warn!(
+                "Session {} changed during tool execution ({} messages expected, {} found); appending turn after the new messages",
+                working_session.id, expected, actual
+            );
+            session_manager
+                .add_messages(&working_session.id, turn)
+                .await?;
+            *working_session = session_manager
+                .get_session(&working_session.id)
+                .await?
+                .ok_or_else(|| SessionError::NotFound(working_session.id.to_string()))?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
 
-        // Initialize MCP client
-        let mcp_client = Arc::new(MCPClient::new());
+/// Whether a request's response is stored in its session; prefill-only requests
+/// have no response to store
+fn appends_response(request: &GenerationRequest, config: &SessionConfig) -> bool {
+    !request.is_prefill_only() && request.append_to_session.unwrap_or(config.append_responses)
+}
 
-        // Add configured MCP servers
-        for server_config in &config.mcp_servers {
-            mcp_client.add_server(server_config.clone()).await?;
+/// Assistant message holding generated text
+fn assistant_message(content: String, timestamp: SystemTime) -> Message {
+    Message {
+        role: crate::types::MessageRole::Assistant,
+        content,
+        tool_call_id: None,
+        tool_name: None,
+        timestamp,
+        attachments: Vec::new(),
+    }
+}
+
+/// The consumer end of a streamed generation
+struct StreamForwarder {
+    sender: mpsc::Sender<Result<StreamChunk, AgentError>>,
+    /// Keep generating after the consumer drops the stream, so the response can still be stored
+    continue_on_drop: bool,
+    consumer_open: bool,
+}
+
+impl StreamForwarder {
+    fn new(sender: mpsc::Sender<Result<StreamChunk, AgentError>>, continue_on_drop: bool) -> Self {
+        Self {
+            sender,
+            continue_on_drop,
+            consumer_open: true,
         }
-        info!("MCP client initialized");
+    }
 
-        // Initialize chat template engine
-        let chat_template = Arc::new(ChatTemplateEngine::new());
-        info!("Chat template engine initialized");
+    /// Cancel `cancel` once the consumer drops the stream, unless generation goes on
+    /// without it; never returns
+    async fn cancel_when_dropped(
+        &self,
+        cancel: &tokio_util::sync::CancellationToken,
+    ) -> std::convert::Infallible {
+        if self.consumer_open && !self.continue_on_drop {
+            self.sender.closed().await;
+            cancel.cancel();
+        }
+        std::future::pending().await
+    }
 
-        // Initialize dependency analyzer with configured settings
-        let dependency_analyzer = Arc::new(DependencyAnalyzer::new(
-            config.parallel_execution_config.clone(),
-        ));
-        info!("Dependency analyzer initialized with configuration");
+    /// Send an item to the consumer unless it has gone; returns whether generation
+    /// should go on
+    async fn send(&mut self, item: Result<StreamChunk, AgentError>) -> bool {
+        if self.consumer_open && self.sender.send(item).await.is_err() {
+            self.consumer_open = false;
+        }
+        self.consumer_open || self.continue_on_drop
+    }
+}
 
-        let agent_server = Self::new(
-            model_manager,
-            request_queue,
-            session_manager,
-            mcp_client,
-            chat_template,
-            dependency_analyzer,
-            config,
-        );
+/// Forward the chunks of one generation pass, returning the pass's summary from its
+/// final chunk.
+///
+/// `token_offset` is added to the token counts of forwarded chunks, so they count
+/// from the start of the whole generation. Returns `None` if the pass failed, which
+/// is forwarded, or ended early, or the consumer dropped the stream and generation
+/// should stop.
+async fn forward_pass(
+    request_stream: &mut RequestStream,
+    forwarder: &mut StreamForwarder,
+    pass: PassOutput<'_>,
+) -> Option<GenerationResponse> {
+    while let Some(item) = request_stream.recv().await {
+        match item {
+            Ok(chunk) if chunk.is_complete => return chunk.response,
+            Ok(mut chunk) => {
+                chunk.token_count += pass.token_offset;
+                if !chunk.text.is_empty() {
+                    chunk.text = pass
+                        .post_processing
+                        .apply_to_chunk(&chunk.text, pass.control_tokens);
+                    // Nothing is left of a chunk that was only a control token,
+                    // unless its token ids are being recorded
+                    if chunk.text.is_empty() && chunk.token_ids.is_empty() {
+                        continue;
+                    }
+                }
+                if !forwarder.send(Ok(chunk)).await {
+                    debug!("Stream dropped, not storing response");
+                    return None;
+                }
+            }
+            Err(e) => {
+                forwarder.send(Err(e.into())).await;
+                return None;
+            }
+        }
+    }
+    None
+}
 
-        info!("AgentServer initialization completed");
-        Ok(agent_server)
+/// How the chunks of one streamed pass are adjusted before they are forwarded
+struct PassOutput<'a> {
+    post_processing: &'a PostProcessing,
+    control_tokens: &'a [&'a str],
+    /// Tokens generated by earlier passes, added to each chunk's count
+    token_offset: u32,
+}
+
+/// Where tool calls run during a streamed generation are reported
+struct ToolEvents<'a> {
+    sender: &'a mpsc::Sender<Result<StreamChunk, AgentError>>,
+    /// Tokens generated so far, for the event chunks
+    token_count: u32,
+    /// Cancels the tool calls of the generation
+    cancel: &'a tokio_util::sync::CancellationToken,
+}
+
+impl ToolEvents<'_> {
+    async fn send(&self, event: StreamEvent) {
+        // A consumer that went away notices nothing; the tool loop carries on regardless
+        let _ = self
+            .sender
+            .send(Ok(StreamChunk::for_event(event, self.token_count)))
+            .await;
     }
 
-    async fn generate(&self, request: GenerationRequest) -> Result<GenerationResponse, AgentError> {
-        debug!(
-            "Processing generation request for session: {}",
-            request.session_id
-        );
+    /// Report the progress of tool `name`. Progress is skipped rather than waited
+    /// for while the consumer is behind.
+    fn progress_handler(&self, name: String) -> ProgressHandler {
+        let sender = self.sender.clone();
+        let token_count = self.token_count;
+        Arc::new(move |progress: ToolProgress| {
+            let event = StreamEvent::ToolCallProgress {
+                name: name.clone(),
+                progress,
+            };
+            let _ = sender.try_send(Ok(StreamChunk::for_event(event, token_count)));
+        })
+    }
+}
 
-        // Get session from session manager
-        let session = self
-            .session_manager
-            .get_session(&request.session_id)
-            .await?
-            .ok_or_else(|| {
-                AgentError::Session(crate::types::SessionError::NotFound(
-                    request.session_id.to_string(),
-                ))
-            })?;
+/// Add a generation pass to the summary of the passes before it
+fn accumulate_response(
+    total: Option<GenerationResponse>,
+    pass: GenerationResponse,
+) -> GenerationResponse {
+    let Some(mut total) = total else {
+        return pass;
+    };
+    // Timings cover model time only, not tool calls between passes
+    if total.time_to_first_token.is_none() {
+        total.time_to_first_token = pass
+            .time_to_first_token
+            .map(|first| total.generation_time + first);
+    }
+    total.generated_text.push_str(&pass.generated_text);
+    total.tokens_generated += pass.tokens_generated;
+    total.prompt_tokens += pass.prompt_tokens;
+    total.prompt_time += pass.prompt_time;
+    total.decode_time += pass.decode_time;
+    total.generation_time += pass.generation_time;
+    total.finish_reason = pass.finish_reason;
+    total.candidates = pass.candidates;
+    total.generated_tokens.extend(pass.generated_tokens);
+    total
+}
 
-        // Security: Validate input before processing
-        self.validate_generation_request_with_session(&request, &session)?;
+/// Reject a tool call the session's tool policy does not permit
+fn check_tool_policy(session: &Session, tool_call: &ToolCall) -> Result<(), AgentError> {
+    if session.tool_policy.allows(&tool_call.name) {
+        return Ok(());
+    }
+    warn!(
+        "Tool '{}' denied in session {} by policy {}",
+        tool_call.name, session.id, session.tool_policy
+    );
+    Err(AgentError::ToolDenied {
+        tool: tool_call.name.clone(),
+        policy: session.tool_policy.clone(),
+    })
+}
 
-        let mut working_session = session;
-        let mut accumulated_response = String::new();
-        let mut total_tokens = 0u32;
-        let mut iterations = 0;
-        const MAX_TOOL_ITERATIONS: usize = 5; // Prevent infinite tool call loops
+/// Error result recorded for a denied tool call so the model can pick another approach
+fn denied_tool_result(call_id: ToolCallId, tool: &str, policy: &ToolPolicy) -> ToolResult {
+    ToolResult {
+        call_id,
+        result: serde_json::Value::Null,
+        error: Some(format!(
+            "Tool '{}' is not permitted in this session (policy: {}). Do not call it again.",
+            tool, policy
+        )),
+    }
+}
 
-        loop {
-            iterations += 1;
-            if iterations > MAX_TOOL_ITERATIONS {
-                warn!(
-                    "Maximum tool call iterations ({}) reached for session: {}",
-                    MAX_TOOL_ITERATIONS, working_session.id
-                );
-                break;
-            }
+/// Text of a successful tool result as the model sees it: the text of its MCP content
+/// blocks, or the JSON itself when it has none
+fn tool_result_text(result: &serde_json::Value, attachments: &[MessageAttachment]) -> String {
+    if attachments.is_empty() {
+        serde_json::to_string(result).unwrap_or_else(|_| "Invalid tool result".to_string())
+    } else {
+        attachments
+            .iter()
+            .filter_map(MessageAttachment::text)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
 
-            debug!(
-                "Tool call iteration {} for session: {}",
-                iterations, working_session.id
-            );
-            debug!(
-                "Current session has {} messages",
-                working_session.messages.len()
-            );
-            for (i, msg) in working_session.messages.iter().enumerate() {
-                debug!(
-                    "Message {}: {:?} - {}",
-                    i + 1,
-                    msg.role,
-                    if msg.content.len() > 100 {
-                        format!("{}...", &msg.content[..100])
-                    } else {
-                        msg.content.clone()
-                    }
-                );
-            }
+/// Cap a tool result at `max_tool_result_bytes` of text before it reaches the session.
+///
+/// With `tool_result_spill_dir` set, the full text is first saved there and its path is
+/// included in the truncation marker.
+async fn limit_tool_result(
+    tool_call: &ToolCall,
+    result: serde_json::Value,
+    config: &crate::types::ParallelExecutionConfig,
+) -> serde_json::Value {
+    let attachments = MessageAttachment::from_tool_result(&result);
+    let text = tool_result_text(&result, &attachments);
+    if text.len() <= config.max_tool_result_bytes {
+        return result;
+    }
 
-            // Create generation request with current session state
-            let current_request = GenerationRequest {
-                session_id: working_session.id,
-                max_tokens: request.max_tokens,
-                temperature: request.temperature,
-                top_p: request.top_p,
-                stop_tokens: request.stop_tokens.clone(),
-                stopping_config: request.stopping_config.clone(),
-            };
+    let mut spill_path = None;
+    if let Some(dir) = &config.tool_result_spill_dir {
+        let path = dir.join(format!("tool-result-{}.txt", tool_call.id));
+        let written = async {
+            tokio::fs::create_dir_all(dir).await?;
+            tokio::fs::write(&path, &text).await
+        };
+        match written.await {
+            Ok(()) => spill_path = Some(path),
+            Err(e) => warn!(
+                "Failed to save full result of tool '{}': {}",
+                tool_call.name, e
+            ),
+        }
+    }
 
-            // Submit to request queue
-            let response = self
-                .request_queue
-                .submit_request(current_request, &working_session)
-                .await?;
+    warn!(
+        "Tool '{}' returned {} bytes, truncating to {}",
+        tool_call.name,
+        text.len(),
+        config.max_tool_result_bytes
+    );
+    truncate_tool_result(
+        &text,
+        &attachments,
+        config.max_tool_result_bytes,
+        spill_path.as_deref(),
+    )
+}
 
-            accumulated_response.push_str(&response.generated_text);
-            total_tokens += response.tokens_generated;
+/// Replace an oversized tool result with its first `max_bytes` of text and a
+/// `[truncated N bytes]` marker.
+///
+/// Images are kept as content blocks; the original size is recorded under `truncated`.
+fn truncate_tool_result(
+    text: &str,
+    attachments: &[MessageAttachment],
+    max_bytes: usize,
+    spill_path: Option<&std::path::Path>,
+) -> serde_json::Value {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
 
+    let removed = text.len() - end;
+    let marker = match spill_path {
+        Some(path) => format!(
+            "[truncated {} bytes; full result saved to {}]",
+            removed,
+            path.display()
+        ),
+        None => format!("[truncated {} bytes]", removed),
+    };
+
+    let mut content = vec![serde_json::json!({
+        "type": "text",
+        "text": format!("{}\n{}", &text[..end], marker),
+    })];
+    for attachment in attachments {
+        if let MessageAttachment::Image { data, mime_type } = attachment {
+            content.push(serde_json::json!({
+                "type": "image",
+                "data": data,
+                "mimeType": mime_type,
+            }));
+        }
+    }
+
+    serde_json::json!({
+        "content": content,
+        "truncated": {
+            "original_bytes": text.len(),
+            "full_result_path": spill_path,
+        },
+    })
+}
+
+/// Build the Tool message for a tool result.
+///
+/// MCP content blocks are kept as attachments, one per block; the message text joins their
+/// text so the model sees it, with placeholders for images and resources added at render time.
+fn tool_result_message(tool_result: &ToolResult, timestamp: SystemTime) -> Message {
+    let (content, attachments) = match &tool_result.error {
+        Some(error) => {
+            debug!("Tool result {}: ERROR - {}", tool_result.call_id, error);
+            (format!("Error: {}", error), Vec::new())
+        }
+        None => {
+            let attachments = MessageAttachment::from_tool_result(&tool_result.result);
+            let content = tool_result_text(&tool_result.result, &attachments);
             debug!(
-                "Generation iteration {} completed: {} tokens, finish_reason: {:?}",
-                iterations, response.tokens_generated, response.finish_reason
-            );
-            debug!(
-                "Generated text in iteration {}: '{}'",
-                iterations,
-                if response.generated_text.len() > 200 {
-                    format!(
-                        "{}...(truncated, total {} chars)",
-                        &response.generated_text[..200],
-                        response.generated_text.len()
-                    )
-                } else {
-                    response.generated_text.clone()
-                }
+                "Tool result {}: SUCCESS - {}",
+                tool_result.call_id,
+                redact(&content)
             );
+            (content, attachments)
+        }
+    };
 
-            // Check if response contains tool calls
-            match &response.finish_reason {
-                crate::types::FinishReason::Stopped(reason) if reason == "Tool call detected" => {
-                    debug!(
-                        "Tool call detected in iteration {}, processing tool calls...",
-                        iterations
-                    );
-                    debug!(
-                        "Generated text for tool call processing: {}",
-                        response.generated_text
-                    );
+    Message {
+        role: crate::types::MessageRole::Tool,
+        content,
+        tool_call_id: Some(tool_result.call_id),
+        tool_name: None,
+        timestamp,
+        attachments,
+    }
+}
+
+/// Resolve an MCP prompt and append its messages to a session.
+///
+/// Image and resource content is kept as message attachments.
+async fn apply_prompt_to_session(
+    mcp_client: &MCPClient,
+    session_manager: &SessionManager,
+    session_id: &SessionId,
+    prompt_name: &str,
+    arguments: Option<serde_json::Value>,
+) -> Result<(), AgentError> {
+    if session_manager.get_session(session_id).await?.is_none() {
+        return Err(SessionError::NotFound(session_id.to_string()).into());
+    }
+
+    let prompt = mcp_client.execute_prompt(prompt_name, arguments).await?;
+    let now = session_manager.now();
+    let messages: Vec<Message> = prompt
+        .messages
+        .iter()
+        .map(|message| message.to_message(now))
+        .collect();
+
+    let count = messages.len();
+    session_manager.add_messages(session_id, messages).await?;
+
+    info!(
+        "Applied prompt '{}' to session {} ({} messages)",
+        prompt_name, session_id, count
+    );
+    Ok(())
+}
 
-                    // Process tool calls
-                    debug!("Beginning tool call processing workflow...");
-                    let tool_results = self
-                        .process_tool_calls(&response.generated_text, &working_session)
-                        .await?;
-                    debug!(
-                        "Tool call processing completed with {} results",
-                        tool_results.len()
-                    );
+/// Create the manager for `model_config`, loading it now in eager mode, and the
+/// request queue generating with it
+async fn start_model(
+    config: &AgentConfig,
+    model_config: &ModelConfig,
+    cancel: &tokio_util::sync::CancellationToken,
+) -> Result<(Arc<ModelManager>, Arc<RequestQueue>), AgentError> {
+    let model_manager = ModelManager::new(model_config.clone())?
+        .with_load_mode(config.load_mode)
+        .with_context_pool_size(config.queue_config.effective_context_pool_size())
+        .with_cancellation(cancel.clone());
+    match config.load_mode {
+        LoadMode::Eager => {
+            model_manager.load_model().await?;
+            info!("Model manager initialized and model loaded");
+        }
+        LoadMode::Lazy => info!("Model manager initialized; model loads on first request"),
+    }
+    let model_manager = Arc::new(model_manager);
+
+    let request_queue = Arc::new(RequestQueue::new(
+        model_manager.clone(),
+        config.queue_config.clone(),
+    ));
+    info!("Request queue initialized");
+    Ok((model_manager, request_queue))
+}
 
-                    if tool_results.is_empty() {
-                        debug!("No tool results returned, ending tool call workflow");
-                        break;
-                    }
+/// Warn when the loaded models together need more memory than was available
+/// before loading them
+async fn warn_if_models_exceed_memory(managers: &[Arc<ModelManager>], available: Option<u64>) {
+    let Some(available) = available else {
+        return;
+    };
+    let mut model_bytes = 0;
+    for manager in managers {
+        if let Some(metadata) = manager.get_metadata().await {
+            model_bytes += metadata.size_bytes;
+        }
+    }
+    let required = llama_loader::memory::estimate_required_memory(model_bytes);
+    if required > available {
+        warn!(
+            "{} models need about {} of memory but {} was available; expect swapping or load failures",
+            managers.len(),
+            llama_loader::memory::format_bytes(required),
+            llama_loader::memory::format_bytes(available)
+        );
+    }
+}
 
-                    // Add the assistant's response (with tool calls) to the session
-                    debug!("Adding assistant message with tool calls to session");
-                    debug!("Assistant message content: {}", response.generated_text);
-                    debug!(
-                        "Session message count before adding assistant message: {}",
-                        working_session.messages.len()
-                    );
-                    working_session.messages.push(crate::types::Message {
-                        role: crate::types::MessageRole::Assistant,
-                        content: response.generated_text.clone(),
-                        tool_call_id: None,
-                        tool_name: None,
-                        timestamp: std::time::SystemTime::now(),
-                    });
-                    debug!(
-                        "Session message count after adding assistant message: {}",
-                        working_session.messages.len()
-                    );
+impl AgentServer {
+    /// Initialize like [`AgentAPI::initialize`], taking session and message timestamps
+    /// from `clock`
+    pub async fn initialize_with_clock(
+        config: AgentConfig,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, AgentError> {
+        Self::initialize_inner(config, clock, tokio_util::sync::CancellationToken::new()).await
+    }
 
-                    // Add tool results as Tool messages to the session
-                    debug!(
-                        "Adding {} tool results as messages to session",
-                        tool_results.len()
-                    );
-                    debug!(
-                        "Session message count before adding tool results: {}",
-                        working_session.messages.len()
-                    );
+    /// Initialize like [`AgentAPI::initialize`], abandoning model downloads when
+    /// `cancel` is cancelled. Partial downloads are removed and initialization fails
+    /// with [`ModelError::Cancelled`](crate::types::ModelError::Cancelled).
+    pub async fn initialize_cancellable(
+        config: AgentConfig,
+        cancel: tokio_util::sync::CancellationToken,
+    ) -> Result<Self, AgentError> {
+        Self::initialize_inner(config, Arc::new(SystemClock), cancel).await
+    }
 
-                    for (i, tool_result) in tool_results.iter().enumerate() {
-                        let tool_content = if let Some(error) = &tool_result.error {
-                            debug!("Tool result {}: ERROR - {}", i + 1, error);
-                            format!("Error: {}", error)
-                        } else {
-                            let content = serde_json::to_string(&tool_result.result)
-                                .unwrap_or_else(|_| "Invalid tool result".to_string());
-                            debug!("Tool result {}: SUCCESS - {}", i + 1, content);
-                            content
-                        };
+    async fn initialize_inner(
+        config: AgentConfig,
+        clock: Arc<dyn Clock>,
+        cancel: tokio_util::sync::CancellationToken,
+    ) -> Result<Self, AgentError> {
+        info!("Initializing AgentServer with config: {:?}", config);
+        let start_time = Instant::now();
 
-                        debug!(
-                            "Adding tool message {}/{} for call_id: {}",
-                            i + 1,
-                            tool_results.len(),
-                            tool_result.call_id
-                        );
-                        debug!(
-                            "Tool message content length: {} characters",
-                            tool_content.len()
-                        );
-                        working_session.messages.push(crate::types::Message {
-                            role: crate::types::MessageRole::Tool,
-                            content: tool_content,
-                            tool_call_id: Some(tool_result.call_id),
-                            tool_name: None,
-                            timestamp: std::time::SystemTime::now(),
-                        });
-                        debug!(
-                            "Session message count after adding tool result {}: {}",
-                            i + 1,
-                            working_session.messages.len()
-                        );
-                    }
+        // Report every configuration problem before downloading or loading anything
+        config.preflight()?;
 
-                    working_session.updated_at = std::time::SystemTime::now();
+        // Measured before loading, since every model loaded takes from it
+        let available_memory = llama_loader::memory::available_memory_bytes();
 
-                    debug!(
-                        "Tool call processing completed with {} results, continuing generation",
-                        tool_results.len()
-                    );
-                    debug!(
-                        "Final session message count after tool workflow: {}",
-                        working_session.messages.len()
-                    );
-                    debug!("Continuing to next iteration {} to generate response incorporating tool results", iterations + 1);
+        // Initialize model managers, each with a request queue of its own
+        let (model_manager, request_queue) = start_model(&config, &config.model, &cancel).await?;
+        let mut named_models = Vec::new();
+        for named in &config.models {
+            let (manager, queue) = start_model(&config, &named.model, &cancel).await?;
+            info!("Model '{}' initialized", named.name);
+            named_models.push((named.name.clone(), manager, queue));
+        }
+        if config.load_mode == LoadMode::Eager && !named_models.is_empty() {
+            let mut managers = vec![model_manager.clone()];
+            managers.extend(named_models.iter().map(|(_, manager, _)| manager.clone()));
+            warn_if_models_exceed_memory(&managers, available_memory).await;
+        }
 
-                    // Continue the loop to generate response incorporating tool results
-                    continue;
-                }
-                crate::types::FinishReason::Stopped(reason) => {
-                    // No more tool calls, we're done
-                    debug!(
-                        "Generation completed without tool calls after {} iterations (reason: {})",
-                        iterations, reason
-                    );
-                    debug!("Final generated text: {}", response.generated_text);
-                    debug!(
-                        "Final accumulated response length: {} characters",
-                        accumulated_response.len()
-                    );
-                    break;
-                }
-            }
+        // Initialize session manager
+        let session_manager = Arc::new(session_manager_for(&config, clock)?);
+        info!("Session manager initialized");
+
+        // Initialize MCP client
+        let mcp_client = Arc::new(MCPClient::new().with_clock(session_manager.clock()));
+
+        // Add configured MCP servers
+        for server_config in &config.mcp_servers {
+            mcp_client.add_server(server_config.clone()).await?;
         }
+        info!("MCP client initialized");
 
-        let final_response = GenerationResponse {
-            generated_text: accumulated_response,
-            tokens_generated: total_tokens,
-            generation_time: std::time::Duration::from_millis(0), // This would need proper timing
-            finish_reason: crate::types::FinishReason::Stopped(
-                "End of sequence token detected".to_string(),
-            ), // Or original finish reason
-        };
+        // Initialize dependency analyzer with configured settings
+        let dependency_analyzer = Arc::new(DependencyAnalyzer::new(
+            config.parallel_execution_config.clone(),
+        ));
+        info!("Dependency analyzer initialized with configuration");
 
-        debug!(
-            "Complete generation workflow finished: {} total tokens across {} iterations",
-            total_tokens, iterations
+        let mut agent_server = Self::new(
+            model_manager,
+            request_queue,
+            session_manager,
+            mcp_client,
+            dependency_analyzer,
+            config,
         );
+        for (name, manager, queue) in named_models {
+            agent_server = agent_server.with_model(name, manager, queue);
+        }
+        // Uptime covers model loading, not just the time since construction
+        agent_server.start_time = start_time;
 
-        Ok(final_response)
+        info!("AgentServer initialization completed");
+        Ok(agent_server)
+    }
+}
+
+#[async_trait]
+impl AgentAPI for AgentServer {
+    async fn initialize(config: AgentConfig) -> Result<Self, AgentError> {
+        Self::initialize_with_clock(config, Arc::new(SystemClock)).await
+    }
+
+    async fn generate(&self, request: GenerationRequest) -> Result<GenerationResponse, AgentError> {
+        let ticket = RequestTicket::new();
+        let session_id = request.session_id;
+        self.generate_as(&ticket, request)
+            .await
+            .map_err(|e| e.with_context(ticket.error_context(session_id)))
     }
 
     async fn generate_stream(
@@ -850,54 +2476,19 @@ impl AgentAPI for AgentServer {
         request: GenerationRequest,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AgentError>> + Send>>, AgentError>
     {
-        debug!(
-            "Processing streaming generation request for session: {}",
-            request.session_id
-        );
-
-        // Get session from session manager
-        let session = self
-            .session_manager
-            .get_session(&request.session_id)
-            .await?
-            .ok_or_else(|| {
-                AgentError::Session(crate::types::SessionError::NotFound(
-                    request.session_id.to_string(),
-                ))
-            })?;
-
-        // Security: Validate input before processing
-        self.validate_generation_request_with_session(&request, &session)?;
-
-        // Render session to prompt
-        let prompt = self.render_session_prompt(&session).await?;
-        debug!("Session rendered to prompt: {} characters", prompt.len());
-
-        // Create streaming request
-        let streaming_request = GenerationRequest {
-            session_id: request.session_id,
-            max_tokens: request.max_tokens,
-            temperature: request.temperature,
-            top_p: request.top_p,
-            stop_tokens: request.stop_tokens,
-            stopping_config: request.stopping_config,
-        };
-
-        // Submit to request queue for streaming
-        let receiver = self
-            .request_queue
-            .submit_streaming_request(streaming_request, &session)
+        let ticket = RequestTicket::new();
+        let session_id = request.session_id;
+        self.generate_stream_as(&ticket, request)
             .await
-            .map_err(AgentError::Queue)?;
-
-        // Convert the receiver to a stream and map QueueError to AgentError
-        let stream = ReceiverStream::new(receiver).map(|result| result.map_err(AgentError::Queue));
-
-        Ok(Box::pin(stream))
+            .map_err(|e| e.with_context(ticket.error_context(session_id)))
     }
 
     async fn create_session(&self) -> Result<Session, AgentError> {
-        let session = self.session_manager.create_session().await?;
+        self.ensure_accepting()?;
+        let (session, evicted) = self.session_manager.create_session_evicting().await?;
+        if let Some(evicted) = evicted {
+            self.remove_evicted_session_servers(&evicted).await;
+        }
         debug!("Created new session: {}", session.id);
         Ok(session)
     }
@@ -911,6 +2502,21 @@ impl AgentAPI for AgentServer {
         Ok(session)
     }
 
+    async fn list_sessions(
+        &self,
+        filter: SessionFilter,
+    ) -> Result<Vec<SessionSummary>, AgentError> {
+        Ok(self.session_manager.query_sessions(&filter).await)
+    }
+
+    async fn get_usage(&self, session_id: &SessionId) -> Result<SessionUsage, AgentError> {
+        self.session_manager
+            .get_session(session_id)
+            .await?
+            .map(|session| session.usage)
+            .ok_or_else(|| AgentError::Session(SessionError::NotFound(session_id.to_string())))
+    }
+
     async fn add_message(
         &self,
         session_id: &SessionId,
@@ -922,143 +2528,122 @@ impl AgentAPI for AgentServer {
             .map_err(AgentError::Session)
     }
 
+    async fn set_system_prompt(
+        &self,
+        session_id: &SessionId,
+        text: &str,
+    ) -> Result<(), AgentError> {
+        crate::types::validate_system_prompt(text).map_err(SessionError::InvalidState)?;
+        self.session_manager
+            .set_system_prompt(session_id, text)
+            .await
+            .map_err(AgentError::Session)
+    }
+
     async fn discover_tools(&self, session: &mut Session) -> Result<(), AgentError> {
         debug!("Discovering tools for session: {}", session.id);
 
-        let tools = self.mcp_client.discover_tools().await?;
-        session.available_tools = tools;
-        session.updated_at = SystemTime::now();
-
+        // Servers listed on the session are started on demand and only visible to it
+        self.mcp_client
+            .ensure_session_servers(&session.id, &session.mcp_servers)
+            .await?;
+        let tools = self.mcp_client.discover_session_tools(&session.id).await?;
         info!(
             "Discovered {} tools for session {}",
-            session.available_tools.len(),
+            tools.len(),
             session.id
         );
 
-        // Update the session in the session manager so the tools are persisted
+        // Only the tools are stored, so messages added since `session` was read are kept
         self.session_manager
-            .update_session(session.clone())
+            .set_available_tools(&session.id, tools.clone())
             .await
             .map_err(AgentError::Session)?;
+        session.available_tools = tools;
+        session.updated_at = self.session_manager.now().max(session.updated_at);
 
         Ok(())
     }
 
-    async fn execute_tool(
+    async fn apply_prompt(
         &self,
-        tool_call: ToolCall,
-        session: &Session,
-    ) -> Result<ToolResult, AgentError> {
-        debug!(
-            "Executing tool call: {} (id: {}) in session: {}",
-            tool_call.name, tool_call.id, session.id
-        );
-        debug!("Tool call arguments: {}", tool_call.arguments);
-
-        // Validate tool call name is not empty
-        if tool_call.name.trim().is_empty() {
-            let error_msg = "Tool name cannot be empty";
-            error!("{}", error_msg);
-            return Ok(ToolResult {
-                call_id: tool_call.id,
-                result: serde_json::Value::Null,
-                error: Some(error_msg.to_string()),
-            });
-        }
-
-        // Find the tool definition
-        let tool_def = match session
-            .available_tools
-            .iter()
-            .find(|t| t.name == tool_call.name)
-        {
-            Some(tool) => tool,
-            None => {
-                let error_msg = format!(
-                    "Tool '{}' not found in available tools. Available tools: {}",
-                    tool_call.name,
-                    session
-                        .available_tools
-                        .iter()
-                        .map(|t| t.name.as_str())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                );
-                error!("{}", error_msg);
-                return Ok(ToolResult {
-                    call_id: tool_call.id,
-                    result: serde_json::Value::Null,
-                    error: Some(error_msg),
-                });
-            }
-        };
-
-        debug!(
-            "Found tool definition for '{}' on server '{}'",
-            tool_call.name, tool_def.server_name
-        );
-
-        // Validate tool arguments structure if parameters schema is available
-        if let Err(validation_error) = self.validate_tool_arguments(&tool_call, tool_def) {
-            warn!(
-                "Tool call arguments validation failed for '{}': {}",
-                tool_call.name, validation_error
-            );
-            // Continue execution despite validation failure but log the issue
-        }
-
-        // Execute the tool call through MCP client with error handling
-        debug!(
-            "Calling MCP server '{}' for tool '{}'",
-            tool_def.server_name, tool_call.name
-        );
-        match self
-            .mcp_client
-            .call_tool(
-                &tool_def.server_name,
-                &tool_call.name,
-                tool_call.arguments.clone(),
-            )
-            .await
-        {
-            Ok(result_value) => {
-                debug!("Tool call '{}' completed successfully", tool_call.name);
-                debug!("Tool call result: {}", result_value);
-                Ok(ToolResult {
-                    call_id: tool_call.id,
-                    result: result_value,
-                    error: None,
-                })
-            }
-            Err(mcp_error) => {
-                let error_msg = format!("Tool execution failed: {}", mcp_error);
-                error!("Tool call '{}' failed: {}", tool_call.name, error_msg);
-                debug!("Failed tool call arguments were: {}", tool_call.arguments);
+        session_id: &SessionId,
+        prompt_name: &str,
+        arguments: Option<serde_json::Value>,
+    ) -> Result<(), AgentError> {
+        apply_prompt_to_session(
+            &self.mcp_client,
+            &self.session_manager,
+            session_id,
+            prompt_name,
+            arguments,
+        )
+        .await
+    }
 
-                // Return ToolResult with error instead of propagating the error
-                // This allows the workflow to continue with partial failures
-                Ok(ToolResult {
-                    call_id: tool_call.id,
-                    result: serde_json::Value::Null,
-                    error: Some(error_msg),
-                })
+    async fn execute_tool(
+        &self,
+        tool_call: ToolCall,
+        session: &Session,
+    ) -> Result<ToolResult, AgentError> {
+        let context = ErrorContext {
+            session_id: Some(session.id),
+            ..ErrorContext::default()
+        };
+        async {
+            // The stored session's policy applies, whatever the caller's copy says; a
+            // session the agent does not know gets the default policy
+            let policy = match self.session_manager.get_session(&session.id).await? {
+                Some(stored) => stored.tool_policy,
+                None => self.config.session_config.default_tool_policy.clone(),
+            };
+            let mut session = std::borrow::Cow::Borrowed(session);
+            if session.tool_policy != policy {
+                session.to_mut().tool_policy = policy;
             }
+            self.execute_tool_audited(
+                tool_call,
+                &session,
+                None,
+                &tokio_util::sync::CancellationToken::new(),
+            )
+            .await
         }
+        .await
+        .map_err(|e: AgentError| e.with_context(context))
     }
 
     async fn health(&self) -> Result<HealthStatus, AgentError> {
         debug!("Performing health check");
 
         let model_loaded = self.model_manager.is_loaded().await;
+        let loading = self.model_manager.is_loading();
         let queue_stats = self.request_queue.get_stats();
         let sessions_count = self.session_manager.get_session_count().await;
         let mcp_health = self.mcp_client.health_check_all().await;
 
+        let mut models = HashMap::new();
+        for (name, route) in self.all_routes() {
+            let queue_stats = route.request_queue.get_stats();
+            let status = ModelStatus {
+                loaded: route.model_manager.is_loaded().await,
+                loading: route.model_manager.is_loading(),
+                reloading: route.model_manager.is_reloading(),
+                queue_size: route.request_queue.get_queue_size(),
+                model: route.model_manager.get_model_info().await,
+                worker_panics: queue_stats.worker_panics,
+                last_worker_panic: queue_stats.last_worker_panic,
+            };
+            models.insert(name, status);
+        }
+
         let all_servers_healthy = mcp_health
             .values()
             .all(|status| matches!(status, crate::mcp::HealthStatus::Healthy));
         let status = if model_loaded && all_servers_healthy {
             "healthy".to_string()
+        } else if loading {
+            "loading".to_string()
         } else {
             "unhealthy".to_string()
         };
@@ -1069,6 +2654,12 @@ impl AgentAPI for AgentServer {
             queue_size: queue_stats.current_queue_size,
             active_sessions: sessions_count,
             uptime: self.start_time.elapsed(),
+            model: self.model_manager.get_model_info().await,
+            reloading: self.model_manager.is_reloading(),
+            loading,
+            mcp_servers: self.mcp_client.server_infos().await,
+            evicted_sessions: self.session_manager.evicted_count(),
+            models,
         };
 
         debug!("Health check completed: {:?}", health_status);
@@ -1079,9 +2670,17 @@ impl AgentAPI for AgentServer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mcp::{HealthStatus as McpHealthStatus, MCPServer};
+    use crate::redaction::RedactionPolicy;
     use crate::types::{
-        ModelConfig, ModelSource, ParallelExecutionConfig, QueueConfig, RetryConfig, SessionConfig,
+        FinishReason, GetPromptResult, LimitsConfig, LoadMode, MCPError, MCPServerConfig,
+        MessageRole, ModelConfig, ModelSource, ParallelExecutionConfig, PromptContent,
+        PromptDefinition, PromptResource, PromptRole, QueueConfig, RetryConfig, SessionConfig,
+        ToolDefinition,
     };
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use tokio_util::sync::CancellationToken;
 
     fn create_test_config() -> AgentConfig {
         use tempfile::TempDir;
@@ -1097,11 +2696,27 @@ mod tests {
                 use_hf_params: false,
                 retry_config: RetryConfig::default(),
                 debug: false,
+                local_search_depth: 0,
+                allow_http: false,
+                metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+                refresh_metadata: false,
+                cache_dir: None,
+                force_load: false,
+                memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+                chat_template: None,
+                extra_stop_sequences: Vec::new(),
             },
             queue_config: QueueConfig::default(),
             mcp_servers: Vec::new(),
             session_config: SessionConfig::default(),
             parallel_execution_config: ParallelExecutionConfig::default(),
+            load_mode: LoadMode::Eager,
+            limits: LimitsConfig::default(),
+            audit_log: None,
+            default_system_prompt: None,
+            models: Vec::new(),
+            redaction: RedactionPolicy::LogFull,
+            post_processors: Vec::new(),
         }
     }
 
@@ -1136,6 +2751,81 @@ mod tests {
         assert!(debug_str.contains("session_config"));
     }
 
+    #[tokio::test]
+    async fn test_initialize_reports_config_problems_before_loading() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = AgentConfig {
+            model: ModelConfig {
+                source: ModelSource::HuggingFace {
+                    repo: "unsloth/Qwen3-0.6B-GGUF".to_string(),
+                    filename: None,
+                },
+                cache_dir: Some(cache_dir.path().to_path_buf()),
+                ..ModelConfig::default()
+            },
+            queue_config: QueueConfig {
+                worker_threads: 0,
+                ..QueueConfig::default()
+            },
+            session_config: SessionConfig {
+                session_timeout: Duration::ZERO,
+                ..SessionConfig::default()
+            },
+            mcp_servers: vec![MCPServerConfig {
+                name: "files".to_string(),
+                command: "no-such-mcp-server-command".to_string(),
+                args: Vec::new(),
+                timeout_secs: None,
+                strict_protocol_version: false,
+            }],
+            ..AgentConfig::default()
+        };
+
+        // Fails before the model is downloaded, with all three problems
+        let error = AgentServer::initialize(config).await.unwrap_err();
+        let AgentError::InvalidConfig(problems) = &error else {
+            panic!("expected every configuration problem, got {}", error);
+        };
+        assert_eq!(problems.len(), 3);
+        let message = error.to_string();
+        assert!(message.starts_with("Configuration has 3 problems:\n- "));
+        for expected in [
+            "Worker threads must be greater than 0",
+            "Session timeout must be greater than 0 seconds",
+            "Command 'no-such-mcp-server-command' of MCP server 'files' was not found",
+        ] {
+            assert!(
+                message.contains(expected),
+                "{} not in {}",
+                expected,
+                message
+            );
+        }
+        assert!(std::fs::read_dir(cache_dir.path())
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_default_system_prompt_applies_to_new_sessions() {
+        let mut config = AgentConfig {
+            default_system_prompt: Some("Agent prompt".to_string()),
+            ..AgentConfig::default()
+        };
+        let session_manager = session_manager_for(&config, Arc::new(SystemClock)).unwrap();
+        let session = session_manager.create_session().await.unwrap();
+        assert_eq!(session.messages.len(), 1);
+        assert_eq!(session.messages[0].role, MessageRole::System);
+        assert_eq!(session.messages[0].content, "Agent prompt");
+
+        // The session-level prompt wins over the agent default
+        config.session_config.system_prompt = Some("Session prompt".to_string());
+        let session_manager = session_manager_for(&config, Arc::new(SystemClock)).unwrap();
+        let session = session_manager.create_session().await.unwrap();
+        assert_eq!(session.messages[0].content, "Session prompt");
+    }
+
     #[test]
     fn test_config_validation() {
         let mut config = create_test_config();
@@ -1165,6 +2855,15 @@ mod tests {
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         };
 
         let valid_config = AgentConfig {
@@ -1173,6 +2872,13 @@ mod tests {
             mcp_servers: Vec::new(),
             session_config: SessionConfig::default(),
             parallel_execution_config: ParallelExecutionConfig::default(),
+            load_mode: LoadMode::Eager,
+            limits: LimitsConfig::default(),
+            audit_log: None,
+            default_system_prompt: None,
+            models: Vec::new(),
+            redaction: RedactionPolicy::LogFull,
+            post_processors: Vec::new(),
         };
 
         // This should pass all validation except for the model file not existing
@@ -1186,4 +2892,848 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_embedding_error_mapping() {
+        let error = embedding_error(QueueError::EmbeddingsNotSupported("no pooling".to_string()));
+        assert!(
+            matches!(error, AgentError::EmbeddingsNotSupported(ref reason) if reason == "no pooling")
+        );
+        assert!(error.to_string().contains("💡"));
+
+        let error = embedding_error(QueueError::Timeout);
+        assert!(matches!(error, AgentError::Queue(QueueError::Timeout)));
+    }
+
+    /// MCP server that serves a single prompt with fixed messages
+    struct PromptServer {
+        messages: Vec<PromptMessage>,
+    }
+
+    #[async_trait]
+    impl MCPServer for PromptServer {
+        async fn initialize(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+            Ok(Vec::new())
+        }
+
+        async fn call_tool(
+            &mut self,
+            tool_name: &str,
+            _args: serde_json::Value,
+        ) -> Result<serde_json::Value, MCPError> {
+            Err(MCPError::ToolCallFailed(tool_name.to_string()))
+        }
+
+        async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+            Ok(vec![PromptDefinition {
+                name: "review".to_string(),
+                title: None,
+                description: None,
+                arguments: None,
+                server_name: "prompts".to_string(),
+                original_name: None,
+            }])
+        }
+
+        async fn get_prompt(
+            &mut self,
+            _prompt_name: &str,
+            _arguments: Option<serde_json::Value>,
+        ) -> Result<GetPromptResult, MCPError> {
+            Ok(GetPromptResult {
+                description: None,
+                messages: self.messages.clone(),
+            })
+        }
+
+        async fn health(&self) -> Result<McpHealthStatus, MCPError> {
+            Ok(McpHealthStatus::Healthy)
+        }
+
+        async fn shutdown(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            "prompts"
+        }
+    }
+
+    fn text_message(role: PromptRole, text: &str) -> PromptMessage {
+        PromptMessage {
+            role,
+            content: PromptContent::Text {
+                text: text.to_string(),
+            },
+        }
+    }
+
+    async fn prompt_fixture(messages: Vec<PromptMessage>) -> (MCPClient, SessionManager) {
+        let mcp_client = MCPClient::new();
+        mcp_client
+            .add_server_instance(Box::new(PromptServer { messages }))
+            .await
+            .unwrap();
+        (mcp_client, SessionManager::new(SessionConfig::default()))
+    }
+
+    #[tokio::test]
+    async fn test_apply_prompt_appends_messages() {
+        let (mcp_client, session_manager) = prompt_fixture(vec![
+            text_message(PromptRole::User, "Review this code"),
+            text_message(PromptRole::Assistant, "Sure, paste it"),
+            PromptMessage {
+                role: PromptRole::User,
+                content: PromptContent::Resource {
+                    resource: PromptResource {
+                        uri: "file:///main.rs".to_string(),
+                        name: "main.rs".to_string(),
+                        title: None,
+                        mime_type: "text/x-rust".to_string(),
+                        text: Some("fn main() {}".to_string()),
+                    },
+                },
+            },
+        ])
+        .await;
+        let session = session_manager.create_session().await.unwrap();
+
+        apply_prompt_to_session(
+            &mcp_client,
+            &session_manager,
+            &session.id,
+            "review",
+            Some(serde_json::json!({"language": "rust"})),
+        )
+        .await
+        .unwrap();
+
+        let session = session_manager
+            .get_session(&session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.messages.len(), 3);
+        assert_eq!(session.messages[0].role, MessageRole::User);
+        assert_eq!(session.messages[0].content, "Review this code");
+        assert_eq!(session.messages[1].role, MessageRole::Assistant);
+        assert_eq!(session.messages[2].content, "fn main() {}");
+    }
+
+    #[tokio::test]
+    async fn test_apply_prompt_keeps_image_content() {
+        let (mcp_client, session_manager) = prompt_fixture(vec![
+            text_message(PromptRole::User, "Describe this"),
+            PromptMessage {
+                role: PromptRole::User,
+                content: PromptContent::Image {
+                    data: "aGVsbG8=".to_string(),
+                    mime_type: "image/png".to_string(),
+                },
+            },
+        ])
+        .await;
+        let session = session_manager.create_session().await.unwrap();
+
+        apply_prompt_to_session(&mcp_client, &session_manager, &session.id, "review", None)
+            .await
+            .unwrap();
+
+        let session = session_manager
+            .get_session(&session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.messages.len(), 2);
+        assert_eq!(
+            session.messages[1].attachments,
+            vec![MessageAttachment::Image {
+                data: "aGVsbG8=".to_string(),
+                mime_type: "image/png".to_string(),
+            }]
+        );
+        assert_eq!(
+            session.messages[1].rendered_content(),
+            "[image image/png 5B]"
+        );
+    }
+
+    fn chat_message(role: MessageRole, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            tool_call_id: None,
+            tool_name: None,
+            timestamp: SystemTime::now(),
+            attachments: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_turn_appends_batch() {
+        let session_manager = SessionManager::new(SessionConfig::default());
+        let mut working = session_manager.create_session().await.unwrap();
+
+        let turn = vec![
+            chat_message(MessageRole::Assistant, "calling tool"),
+            chat_message(MessageRole::Tool, "tool output"),
+        ];
+        record_turn(&session_manager, &mut working, turn)
+            .await
+            .unwrap();
+
+        let stored = session_manager
+            .get_session(&working.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.messages.len(), 2);
+        assert_eq!(working.messages.len(), 2);
+        assert_eq!(working.messages[1].content, "tool output");
+    }
+
+    #[tokio::test]
+    async fn test_record_turn_after_concurrent_write() {
+        let session_manager = SessionManager::new(SessionConfig::default());
+        let mut working = session_manager.create_session().await.unwrap();
+
+        // Another writer appends while the tool call is running
+        session_manager
+            .add_message(&working.id, chat_message(MessageRole::User, "interjection"))
+            .await
+            .unwrap();
+
+        let turn = vec![
+            chat_message(MessageRole::Assistant, "calling tool"),
+            chat_message(MessageRole::Tool, "tool output"),
+        ];
+        record_turn(&session_manager, &mut working, turn)
+            .await
+            .unwrap();
+
+        let contents: Vec<&str> = working
+            .messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            vec!["interjection", "calling tool", "tool output"]
+        );
+    }
+
+    fn session_with_policy(tool_policy: ToolPolicy) -> Session {
+        Session {
+            id: SessionId::new(),
+            messages: Vec::new(),
+            mcp_servers: Vec::new(),
+            available_tools: Vec::new(),
+            available_prompts: Vec::new(),
+            created_at: SystemTime::now(),
+            updated_at: SystemTime::now(),
+            tool_policy,
+            usage: SessionUsage::default(),
+        }
+    }
+
+    fn call(name: &str) -> ToolCall {
+        ToolCall {
+            id: ToolCallId::new(),
+            name: name.to_string(),
+            arguments: serde_json::json!({}),
+        }
+    }
+
+    #[test]
+    fn test_check_tool_policy_allow_all() {
+        let session = session_with_policy(ToolPolicy::AllowAll);
+        assert!(check_tool_policy(&session, &call("write_file")).is_ok());
+    }
+
+    #[test]
+    fn test_check_tool_policy_allow_list() {
+        let policy = ToolPolicy::AllowList(vec!["read_file".to_string()]);
+        let session = session_with_policy(policy.clone());
+
+        assert!(check_tool_policy(&session, &call("read_file")).is_ok());
+        match check_tool_policy(&session, &call("write_file")) {
+            Err(AgentError::ToolDenied {
+                tool,
+                policy: denied_by,
+            }) => {
+                assert_eq!(tool, "write_file");
+                assert_eq!(denied_by, policy);
+            }
+            other => panic!("expected ToolDenied, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_tool_policy_deny_list() {
+        let session = session_with_policy(ToolPolicy::DenyList(vec!["shell".to_string()]));
+
+        assert!(check_tool_policy(&session, &call("read_file")).is_ok());
+        let error = check_tool_policy(&session, &call("shell")).unwrap_err();
+        assert!(matches!(error, AgentError::ToolDenied { .. }));
+        assert!(error
+            .to_string()
+            .starts_with("Tool 'shell' denied by session tool policy (deny-list [shell])"));
+    }
+
+    #[test]
+    fn test_denied_tool_result_is_visible_to_model() {
+        let tool_call = call("shell");
+        let policy = ToolPolicy::DenyList(vec!["shell".to_string()]);
+        let result = denied_tool_result(tool_call.id, &tool_call.name, &policy);
+
+        assert_eq!(result.call_id, tool_call.id);
+        assert_eq!(result.result, serde_json::Value::Null);
+
+        let message = tool_result_message(&result, SystemTime::now());
+        assert_eq!(message.role, MessageRole::Tool);
+        assert_eq!(
+            message.content,
+            "Error: Tool 'shell' is not permitted in this session (policy: deny-list [shell]). Do not call it again."
+        );
+    }
+
+    #[test]
+    fn test_tool_result_message_keeps_each_content_block() {
+        let tool_result = ToolResult {
+            call_id: crate::types::ToolCallId::new(),
+            result: serde_json::json!({
+                "content": [
+                    {"type": "text", "text": "First block"},
+                    {"type": "image", "data": "aGVsbG8=", "mimeType": "image/png"},
+                    {"type": "resource", "resource": {
+                        "uri": "file:///notes.md",
+                        "mimeType": "text/markdown",
+                        "text": "Second block"
+                    }}
+                ],
+                "isError": false
+            }),
+            error: None,
+        };
+
+        let message = tool_result_message(&tool_result, SystemTime::now());
+        assert_eq!(message.role, MessageRole::Tool);
+        assert_eq!(message.attachments.len(), 3);
+        assert_eq!(message.content, "First block\nSecond block");
+        assert!(matches!(
+            &message.attachments[2],
+            MessageAttachment::ResourceLink { uri, .. } if uri == "file:///notes.md"
+        ));
+        assert_eq!(
+            message.rendered_content(),
+            "First block\nSecond block\n[image image/png 5B]\n[resource file:///notes.md text/markdown]"
+        );
+
+        // Results without content blocks keep the JSON text
+        let plain = ToolResult {
+            result: serde_json::json!({"files": ["a.txt"]}),
+            ..tool_result
+        };
+        let message = tool_result_message(&plain, SystemTime::now());
+        assert!(message.attachments.is_empty());
+        assert_eq!(message.content, r#"{"files":["a.txt"]}"#);
+    }
+
+    fn oversized_result() -> serde_json::Value {
+        serde_json::json!({
+            "content": [
+                {"type": "text", "text": "é".repeat(40)},
+                {"type": "image", "data": "aGVsbG8=", "mimeType": "image/png"}
+            ]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_limit_tool_result_truncates_with_marker() {
+        let config = crate::types::ParallelExecutionConfig {
+            max_tool_result_bytes: 25,
+            ..Default::default()
+        };
+
+        // Results within the limit are untouched
+        let small = serde_json::json!({"content": [{"type": "text", "text": "ok"}]});
+        assert_eq!(
+            limit_tool_result(&call("big"), small.clone(), &config).await,
+            small
+        );
+
+        let result = limit_tool_result(&call("big"), oversized_result(), &config).await;
+        assert_eq!(result["truncated"]["original_bytes"], 80);
+        assert!(result["truncated"]["full_result_path"].is_null());
+
+        let message = tool_result_message(
+            &ToolResult {
+                call_id: crate::types::ToolCallId::new(),
+                result,
+                error: None,
+            },
+            SystemTime::now(),
+        );
+        // Cut at a character boundary, with the marker after the kept text
+        assert_eq!(
+            message.content,
+            format!("{}\n[truncated 56 bytes]", "é".repeat(12))
+        );
+        assert!(matches!(
+            &message.attachments[1],
+            MessageAttachment::Image { mime_type, .. } if mime_type == "image/png"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_limit_tool_result_spills_full_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = crate::types::ParallelExecutionConfig {
+            max_tool_result_bytes: 10,
+            tool_result_spill_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let tool_call = call("big");
+
+        let result = limit_tool_result(&tool_call, oversized_result(), &config).await;
+        let path = dir.path().join(format!("tool-result-{}.txt", tool_call.id));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "é".repeat(40));
+        assert_eq!(
+            result["truncated"]["full_result_path"],
+            path.display().to_string()
+        );
+        assert!(result["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .ends_with(&format!(
+                "[truncated 70 bytes; full result saved to {}]",
+                path.display()
+            )));
+    }
+
+    fn chunk(text: &str) -> Result<StreamChunk, QueueError> {
+        Ok(StreamChunk {
+            text: text.to_string(),
+            is_complete: false,
+            token_count: 1,
+            response: None,
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
+        })
+    }
+
+    fn final_chunk(text: &str) -> Result<StreamChunk, QueueError> {
+        Ok(StreamChunk {
+            text: String::new(),
+            is_complete: true,
+            token_count: 2,
+            response: Some(GenerationResponse {
+                generated_text: text.to_string(),
+                tokens_generated: 2,
+                generation_time: Duration::from_millis(10),
+                finish_reason: FinishReason::Stopped("End of sequence token detected".to_string()),
+                prompt_tokens: 3,
+                prompt_time: Duration::from_millis(4),
+                decode_time: Duration::from_millis(5),
+                time_to_first_token: Some(Duration::from_millis(6)),
+                candidates: Vec::new(),
+                generated_tokens: Vec::new(),
+            }),
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
+        })
+    }
+
+    /// Forward one pass of `chunks`, reading `read` of them before dropping the
+    /// consumer; returns the pass summary, the token counts the consumer saw and
+    /// whether the request was cancelled
+    async fn forward(
+        chunks: Vec<Result<StreamChunk, QueueError>>,
+        read: usize,
+        continue_on_drop: bool,
+    ) -> (Option<GenerationResponse>, Vec<u32>, bool) {
+        let (queue_sender, queue_receiver) = mpsc::channel(chunks.len());
+        for chunk in chunks {
+            queue_sender.send(chunk).await.unwrap();
+        }
+        drop(queue_sender);
+        let cancellation_token = CancellationToken::new();
+        let mut request_stream = RequestStream::new(queue_receiver, cancellation_token.clone());
+
+        let (sender, mut receiver) = mpsc::channel(1);
+        let mut forwarder = StreamForwarder::new(sender, continue_on_drop);
+        let consumer = async move {
+            let mut token_counts = Vec::new();
+            for _ in 0..read {
+                if let Ok(chunk) = receiver.recv().await.unwrap() {
+                    token_counts.push(chunk.token_count);
+                }
+            }
+            token_counts
+        };
+        let post_processing = PostProcessing::default();
+        let pass = PassOutput {
+            post_processing: &post_processing,
+            control_tokens: &[],
+            token_offset: 10,
+        };
+        let (response, token_counts) = tokio::join!(
+            forward_pass(&mut request_stream, &mut forwarder, pass),
+            consumer
+        );
+        drop(request_stream);
+        (response, token_counts, cancellation_token.is_cancelled())
+    }
+
+    #[tokio::test]
+    async fn test_forward_pass_returns_summary() {
+        let chunks = || vec![chunk("Hel"), chunk("lo"), final_chunk("Hello")];
+
+        let (response, token_counts, cancelled) = forward(chunks(), 2, false).await;
+        assert_eq!(response.unwrap().generated_text, "Hello");
+        // Counts continue from the earlier passes
+        assert_eq!(token_counts, [11, 11]);
+        assert!(!cancelled);
+
+        // A consumer dropping the stream early still lets the pass finish
+        let (response, _, cancelled) = forward(chunks(), 1, true).await;
+        assert!(response.is_some());
+        assert!(!cancelled);
+
+        // Otherwise dropping the stream cancels the request
+        let (response, _, cancelled) = forward(chunks(), 1, false).await;
+        assert!(response.is_none());
+        assert!(cancelled);
+    }
+
+    #[tokio::test]
+    async fn test_forward_pass_stops_on_error() {
+        let chunks = vec![
+            chunk("Hel"),
+            Err(QueueError::WorkerError("decode failed".to_string())),
+            final_chunk("Hello"),
+        ];
+        let (response, token_counts, _) = forward(chunks, 2, true).await;
+        assert!(response.is_none());
+        // The error reaches the consumer
+        assert_eq!(token_counts, [11]);
+    }
+
+    #[test]
+    fn test_accumulate_response_sums_passes() {
+        let pass = || final_chunk("ab").unwrap().response.unwrap();
+        let first = accumulate_response(None, pass());
+        assert_eq!(first.generated_text, "ab");
+
+        let total = accumulate_response(Some(first), pass());
+        assert_eq!(total.generated_text, "abab");
+        assert_eq!(total.tokens_generated, 4);
+        assert_eq!(total.prompt_tokens, 6);
+        assert_eq!(total.generation_time, Duration::from_millis(20));
+        // The first token came in the first pass
+        assert_eq!(total.time_to_first_token, Some(Duration::from_millis(6)));
+    }
+
+    #[test]
+    fn test_appends_response_per_request_override() {
+        let request = || GenerationRequest::new(SessionId::new());
+        let mut config = SessionConfig::default();
+        assert!(appends_response(&request(), &config));
+        assert!(!appends_response(
+            &request().with_append_to_session(false),
+            &config
+        ));
+
+        config.append_responses = false;
+        assert!(!appends_response(&request(), &config));
+        assert!(appends_response(
+            &request().with_append_to_session(true),
+            &config
+        ));
+        assert!(!appends_response(
+            &request().with_append_to_session(true).with_max_tokens(0),
+            &config
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_apply_prompt_unknown_session() {
+        let (mcp_client, session_manager) =
+            prompt_fixture(vec![text_message(PromptRole::User, "Hi")]).await;
+
+        let result = apply_prompt_to_session(
+            &mcp_client,
+            &session_manager,
+            &SessionId::new(),
+            "review",
+            None,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(AgentError::Session(SessionError::NotFound(_)))
+        ));
+    }
+
+    /// MCP server with a `lookup` tool that reports progress and answers, an `explode`
+    /// tool that fails and a `wait` tool that runs until it is cancelled
+    #[derive(Default)]
+    struct ToolServer {
+        wait_cancelled: Arc<AtomicBool>,
+    }
+
+    /// Sets its flag when dropped
+    struct SetOnDrop(Arc<AtomicBool>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl MCPServer for ToolServer {
+        async fn initialize(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+            Ok(["lookup", "explode", "wait"]
+                .into_iter()
+                .map(|name| ToolDefinition {
+                    name: name.to_string(),
+                    description: String::new(),
+                    parameters: serde_json::Value::Null,
+                    server_name: "tools".to_string(),
+                    original_name: None,
+                })
+                .collect())
+        }
+
+        async fn call_tool(
+            &mut self,
+            tool_name: &str,
+            args: serde_json::Value,
+        ) -> Result<serde_json::Value, MCPError> {
+            self.call_tool_with_progress(tool_name, args, Arc::new(|_: ToolProgress| {}))
+                .await
+        }
+
+        async fn call_tool_with_progress(
+            &mut self,
+            tool_name: &str,
+            _args: serde_json::Value,
+            on_progress: ProgressHandler,
+        ) -> Result<serde_json::Value, MCPError> {
+            match tool_name {
+                "lookup" => {
+                    on_progress(ToolProgress {
+                        progress: 1.0,
+                        total: None,
+                        message: Some("searching".to_string()),
+                    });
+                    Ok(serde_json::json!({"answer": 42}))
+                }
+                "wait" => {
+                    let _cancelled = SetOnDrop(Arc::clone(&self.wait_cancelled));
+                    std::future::pending().await
+                }
+                _ => Err(MCPError::ToolCallFailed(tool_name.to_string())),
+            }
+        }
+
+        async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+            Ok(Vec::new())
+        }
+
+        async fn get_prompt(
+            &mut self,
+            prompt_name: &str,
+            _arguments: Option<serde_json::Value>,
+        ) -> Result<GetPromptResult, MCPError> {
+            Err(MCPError::Protocol(format!("No prompt {}", prompt_name)))
+        }
+
+        async fn health(&self) -> Result<McpHealthStatus, MCPError> {
+            Ok(McpHealthStatus::Healthy)
+        }
+
+        async fn shutdown(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            "tools"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_stream_reports_tool_calls() {
+        use crate::test_support::{agent_with_fake_model, FakeModel};
+
+        let model = FakeModel::new()
+            .with_reply([r#"{"function_name": "lookup", "arguments": {"key": "a"}}"#])
+            .with_reply([r#"{"function_name": "explode", "arguments": {}}"#])
+            .with_reply(["The", " answer", " is", " 42"]);
+        let agent = agent_with_fake_model(create_test_config(), model).unwrap();
+        agent
+            .mcp_client()
+            .add_server_instance(Box::new(ToolServer::default()))
+            .await
+            .unwrap();
+        let mut session = agent.create_session().await.unwrap();
+        agent.discover_tools(&mut session).await.unwrap();
+        agent
+            .add_message(
+                &session.id,
+                Message {
+                    role: MessageRole::User,
+                    content: "What is the answer?".to_string(),
+                    tool_call_id: None,
+                    tool_name: None,
+                    timestamp: SystemTime::now(),
+                    attachments: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut stream = agent
+            .generate_stream(GenerationRequest::new(session.id))
+            .await
+            .unwrap();
+        let mut events = Vec::new();
+        let mut answer = String::new();
+        let mut last = None;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.unwrap();
+            if chunk.is_complete {
+                last = chunk.response;
+                break;
+            }
+            match chunk.event {
+                StreamEvent::TextChunk => answer.push_str(&chunk.text),
+                StreamEvent::ToolCallCompleted { name, is_error, .. } => {
+                    events.push(format!("completed {} error={}", name, is_error))
+                }
+                StreamEvent::ToolCallStarted { name, arguments } => {
+                    events.push(format!("started {} {}", name, arguments))
+                }
+                StreamEvent::ToolCallProgress { name, progress } => events.push(format!(
+                    "progress {} {}",
+                    name,
+                    progress.message.unwrap_or_default()
+                )),
+                StreamEvent::GenerationResumed => events.push("resumed".to_string()),
+            }
+        }
+
+        assert_eq!(
+            events,
+            [
+                r#"started lookup {"key":"a"}"#,
+                "progress lookup searching",
+                "completed lookup error=false",
+                "resumed",
+                "started explode {}",
+                "completed explode error=true",
+                "resumed",
+            ]
+        );
+        assert!(answer.ends_with("The answer is 42"));
+        let last = last.unwrap();
+        assert_eq!(last.tokens_generated, 6);
+        assert_eq!(
+            last.finish_reason,
+            FinishReason::Stopped("End of sequence token detected".to_string())
+        );
+
+        // Each call and its result are stored as a turn, then the answer
+        let stored = agent.get_session(&session.id).await.unwrap().unwrap();
+        let roles: Vec<MessageRole> = stored.messages.iter().map(|m| m.role.clone()).collect();
+        assert_eq!(
+            roles,
+            [
+                MessageRole::User,
+                MessageRole::Assistant,
+                MessageRole::Tool,
+                MessageRole::Assistant,
+                MessageRole::Tool,
+                MessageRole::Assistant,
+            ]
+        );
+        assert_eq!(stored.messages[5].content, "The answer is 42");
+    }
+
+    #[tokio::test]
+    async fn test_dropping_stream_cancels_tool_calls() {
+        use crate::test_support::{agent_with_fake_model, FakeModel};
+
+        let model = FakeModel::new().with_reply([r#"{"function_name": "wait", "arguments": {}}"#]);
+        let mut config = create_test_config();
+        config.session_config.append_on_stream_drop = false;
+        let agent = agent_with_fake_model(config, model).unwrap();
+        let server = ToolServer::default();
+        let wait_cancelled = Arc::clone(&server.wait_cancelled);
+        agent
+            .mcp_client()
+            .add_server_instance(Box::new(server))
+            .await
+            .unwrap();
+        let mut session = agent.create_session().await.unwrap();
+        agent.discover_tools(&mut session).await.unwrap();
+        agent
+            .add_message(
+                &session.id,
+                Message {
+                    role: MessageRole::User,
+                    content: "Wait for it".to_string(),
+                    tool_call_id: None,
+                    tool_name: None,
+                    timestamp: SystemTime::now(),
+                    attachments: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut stream = agent
+            .generate_stream(GenerationRequest::new(session.id))
+            .await
+            .unwrap();
+        while let Some(chunk) = stream.next().await {
+            if matches!(chunk.unwrap().event, StreamEvent::ToolCallStarted { .. }) {
+                break;
+            }
+        }
+        drop(stream);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !wait_cancelled.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the tool call is cancelled with its generation");
+    }
 }
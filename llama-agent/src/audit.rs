@@ -0,0 +1,442 @@
+//! Append-only JSONL log of session events
+//!
+//! With `AgentConfig::audit_log` set, session creation and deletion, every message
+//! stored in a session, generation requests and tool calls are written as one JSON
+//! object per line, each with an RFC 3339 `timestamp` and an `event` name. Events
+//! are handed to a dedicated writer thread over a channel, so recording never waits
+//! for the disk. The file is rotated by size to `<path>.1`, `<path>.2`, ...
+//!
+//! A redactor set with [`AuditLog::set_redactor`] rewrites message content, tool
+//! arguments and tool results before they are written. `AgentServer` sets one from
+//! `AgentConfig::redaction` unless that is `LogFull`.
+
+use crate::types::{
+    AuditLogConfig, GenerationRequest, GenerationResponse, Message, SessionId, SessionUsage,
+    ToolCall, ToolCallId, ToolResult,
+};
+use serde::{Serialize, Serializer};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+pub use crate::redaction::Redactor;
+
+/// Something that happened to a session
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    SessionCreated {
+        session_id: SessionId,
+    },
+    MessageAdded {
+        session_id: SessionId,
+        role: &'static str,
+        content: String,
+        tool_call_id: Option<ToolCallId>,
+    },
+    GenerationStarted {
+        session_id: SessionId,
+        stream: bool,
+        max_tokens: Option<u32>,
+        temperature: Option<f32>,
+        top_p: Option<f32>,
+        n: Option<u32>,
+    },
+    GenerationCompleted {
+        session_id: SessionId,
+        finish_reason: String,
+        prompt_tokens: u32,
+        tokens_generated: u32,
+        generation_time_ms: u64,
+        /// Cumulative usage of the session including this request; `None` if the
+        /// session was deleted while generating
+        usage: Option<SessionUsage>,
+    },
+    GenerationFailed {
+        session_id: SessionId,
+        error: String,
+    },
+    ToolCall {
+        session_id: SessionId,
+        call_id: ToolCallId,
+        tool_name: String,
+        arguments: serde_json::Value,
+    },
+    ToolResult {
+        session_id: SessionId,
+        call_id: ToolCallId,
+        result: serde_json::Value,
+        error: Option<String>,
+    },
+    SessionDeleted {
+        session_id: SessionId,
+    },
+    SessionExpired {
+        session_id: SessionId,
+    },
+    SessionEvicted {
+        session_id: SessionId,
+    },
+}
+
+impl AuditEvent {
+    pub fn message_added(session_id: SessionId, message: &Message) -> Self {
+        Self::MessageAdded {
+            session_id,
+            role: message.role.as_str(),
+            content: message.content.clone(),
+            tool_call_id: message.tool_call_id,
+        }
+    }
+
+    pub fn generation_started(request: &GenerationRequest, stream: bool) -> Self {
+        Self::GenerationStarted {
+            session_id: request.session_id,
+            stream,
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            top_p: request.top_p,
+            n: request.n,
+        }
+    }
+
+    pub fn generation_completed(
+        session_id: SessionId,
+        response: &GenerationResponse,
+        usage: Option<SessionUsage>,
+    ) -> Self {
+        let crate::types::FinishReason::Stopped(finish_reason) = &response.finish_reason;
+        Self::GenerationCompleted {
+            session_id,
+            finish_reason: finish_reason.clone(),
+            prompt_tokens: response.prompt_tokens,
+            tokens_generated: response.tokens_generated,
+            generation_time_ms: response.generation_time.as_millis() as u64,
+            usage,
+        }
+    }
+
+    pub fn tool_call(session_id: SessionId, tool_call: &ToolCall) -> Self {
+        Self::ToolCall {
+            session_id,
+            call_id: tool_call.id,
+            tool_name: tool_call.name.clone(),
+            arguments: tool_call.arguments.clone(),
+        }
+    }
+
+    pub fn tool_result(session_id: SessionId, result: &ToolResult) -> Self {
+        Self::ToolResult {
+            session_id,
+            call_id: result.call_id,
+            result: result.result.clone(),
+            error: result.error.clone(),
+        }
+    }
+
+    /// Pass the text an event carries through `redact`
+    fn redact(&mut self, redact: &dyn Fn(&str) -> String) {
+        match self {
+            Self::MessageAdded { content, .. } => *content = redact(content),
+            Self::ToolCall { arguments, .. } => redact_json(arguments, redact),
+            Self::ToolResult { result, error, .. } => {
+                redact_json(result, redact);
+                if let Some(error) = error {
+                    *error = redact(error);
+                }
+            }
+            Self::GenerationFailed { error, .. } => *error = redact(error),
+            _ => {}
+        }
+    }
+}
+
+fn redact_json(value: &mut serde_json::Value, redact: &dyn Fn(&str) -> String) {
+    match value {
+        serde_json::Value::String(text) => *text = redact(text),
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redact_json(item, redact);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for field in fields.values_mut() {
+                redact_json(field, redact);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    #[serde(serialize_with = "serialize_timestamp")]
+    timestamp: SystemTime,
+    #[serde(flatten)]
+    event: AuditEvent,
+}
+
+fn serialize_timestamp<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+}
+
+enum Command {
+    Record(AuditRecord),
+    /// Acknowledged once every earlier record is written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Handle to an audit log file. Clones share the writer, which stops once every
+/// handle is dropped.
+#[derive(Clone)]
+pub struct AuditLog {
+    sender: mpsc::UnboundedSender<Command>,
+    redactor: Arc<RwLock<Option<Redactor>>>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
+
+impl AuditLog {
+    /// Open or create the log file and start its writer thread
+    pub fn open(config: &AuditLogConfig) -> std::io::Result<Self> {
+        let mut file = RotatingFile::open(config)?;
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let redactor: Arc<RwLock<Option<Redactor>>> = Arc::new(RwLock::new(None));
+
+        let writer_redactor = redactor.clone();
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                while let Some(command) = receiver.blocking_recv() {
+                    match command {
+                        Command::Record(mut record) => {
+                            let redactor = writer_redactor
+                                .read()
+                                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                                .clone();
+                            if let Some(redact) = redactor {
+                                record.event.redact(redact.as_ref());
+                            }
+                            if let Err(e) = file.write_record(&record) {
+                                warn!("Failed to write audit log {}: {}", file.path.display(), e);
+                            }
+                        }
+                        Command::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Self { sender, redactor })
+    }
+
+    /// Rewrite message content, tool arguments and tool results with `redactor`
+    /// before they are written, including events already waiting to be written
+    pub fn set_redactor(&self, redactor: impl Fn(&str) -> String + Send + Sync + 'static) {
+        *self
+            .redactor
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(redactor));
+    }
+
+    /// Queue an event for writing; never blocks
+    pub fn record(&self, timestamp: SystemTime, event: AuditEvent) {
+        let _ = self
+            .sender
+            .send(Command::Record(AuditRecord { timestamp, event }));
+    }
+
+    /// Wait until every event recorded so far is written
+    pub async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        if self.sender.send(Command::Flush(done)).is_ok() {
+            let _ = written.await;
+        }
+    }
+}
+
+/// The log file, moved aside once it would grow past `max_bytes`
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(config: &AuditLogConfig) -> std::io::Result<Self> {
+        if let Some(parent) = config.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: config.path.clone(),
+            max_bytes: config.max_bytes,
+            max_files: config.max_files,
+            file,
+            size,
+        })
+    }
+
+    fn write_record(&mut self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        // A line longer than max_bytes still gets a file of its own
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `<path>.1` to `<path>.2` and so on, dropping the oldest, then move the
+    /// current file to `<path>.1` and start a new one
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let oldest = self.rotated(self.max_files);
+            if oldest.exists() {
+                std::fs::remove_file(&oldest)?;
+            }
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn read_events(path: &std::path::Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_events_are_appended_as_json_lines() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("logs").join("audit.jsonl");
+        let log = AuditLog::open(&AuditLogConfig::new(&path)).unwrap();
+        let session_id = SessionId::new();
+
+        log.record(
+            SystemTime::UNIX_EPOCH,
+            AuditEvent::SessionCreated { session_id },
+        );
+        log.record(
+            SystemTime::UNIX_EPOCH,
+            AuditEvent::SessionDeleted { session_id },
+        );
+        log.flush().await;
+
+        let events = read_events(&path);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "session_created");
+        assert_eq!(events[0]["timestamp"], "1970-01-01T00:00:00.000Z");
+        assert_eq!(events[0]["session_id"], session_id.to_string());
+        assert_eq!(events[1]["event"], "session_deleted");
+    }
+
+    #[tokio::test]
+    async fn test_redactor_scrubs_content() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = AuditLog::open(&AuditLogConfig::new(&path)).unwrap();
+        log.set_redactor(|text| text.replace("hunter2", "[redacted]"));
+
+        let session_id = SessionId::new();
+        log.record(
+            SystemTime::now(),
+            AuditEvent::MessageAdded {
+                session_id,
+                role: "user",
+                content: "my password is hunter2".to_string(),
+                tool_call_id: None,
+            },
+        );
+        log.record(
+            SystemTime::now(),
+            AuditEvent::ToolCall {
+                session_id,
+                call_id: ToolCallId::new(),
+                tool_name: "login".to_string(),
+                arguments: serde_json::json!({"user": "me", "secrets": ["hunter2"]}),
+            },
+        );
+        log.flush().await;
+
+        let events = read_events(&path);
+        assert_eq!(events[0]["content"], "my password is [redacted]");
+        assert_eq!(events[1]["arguments"]["secrets"][0], "[redacted]");
+        assert_eq!(events[1]["arguments"]["user"], "me");
+    }
+
+    #[tokio::test]
+    async fn test_file_is_rotated_by_size() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let config = AuditLogConfig::new(&path).with_rotation(250, 2);
+        let log = AuditLog::open(&config).unwrap();
+
+        for _ in 0..10 {
+            log.record(
+                SystemTime::now(),
+                AuditEvent::SessionCreated {
+                    session_id: SessionId::new(),
+                },
+            );
+        }
+        log.flush().await;
+
+        // Two events fit in 250 bytes; only the two newest rotated files are kept
+        let rotated = |index: usize| dir.path().join(format!("audit.jsonl.{}", index));
+        assert_eq!(read_events(&path).len(), 2);
+        assert_eq!(read_events(&rotated(1)).len(), 2);
+        assert_eq!(read_events(&rotated(2)).len(), 2);
+        assert!(!rotated(3).exists());
+        assert!(std::fs::metadata(&path).unwrap().len() <= 250);
+    }
+}
@@ -0,0 +1,334 @@
+//! The model operations the generation loops run on
+//!
+//! Workers generate through [`ModelBackend`] rather than calling llama.cpp directly.
+//! [`LlamaCppBackend`] is the real implementation; with the `fake-backend` feature,
+//! `test_support::FakeModel` replays scripted tokens so the generation loops can be
+//! tested without a GGUF file. Requests a worker batches together generate through
+//! [`BatchBackend`] instead.
+
+use crate::chat_template::end_of_turn_token_ids;
+use crate::context_pool::ContextLease;
+use crate::model::ModelManager;
+use crate::stopper::{Stopper, StopperFactory};
+use crate::types::{
+    FinishReason, GenerationRequest, ModelConfig, ModelError, QueueError, StoppingConfig,
+};
+use llama_cpp_2::{
+    context::LlamaContext,
+    llama_batch::LlamaBatch,
+    model::{AddBos, LlamaModel, Special},
+    sampling::LlamaSampler,
+    token::LlamaToken,
+};
+
+/// A model and its decoding state for one generation request
+pub trait ModelBackend {
+    /// Tokenize a prompt, starting with the beginning-of-sequence token
+    fn tokenize(&self, text: &str) -> Result<Vec<u32>, QueueError>;
+
+    /// Decode `tokens` at positions starting from `start_pos`
+    fn decode(&mut self, tokens: &[u32], start_pos: usize) -> Result<(), QueueError>;
+
+    /// Sample the token following the last decoded one
+    fn sample_next_token(&mut self) -> u32;
+
+    /// Text of a token
+    fn token_to_str(&self, token: u32) -> Result<String, String>;
+
+    /// Whether a token ends generation
+    fn is_eog(&self, token: u32) -> bool;
+
+    /// Stoppers for `config`, set up with this model's end tokens
+    fn stoppers(&self, config: &StoppingConfig) -> Vec<Box<dyn Stopper>>;
+
+    /// Ask a stopper whether generation should end after the last decoded token
+    fn should_stop(&self, stopper: &mut dyn Stopper) -> Option<FinishReason>;
+
+    /// Token ids of the end-of-turn markers of the configured model family
+    fn end_of_turn_token_ids(&self, config: &ModelConfig) -> Vec<u32>;
+}
+
+/// Creates a backend per request, for workers that do not generate with the loaded model
+pub trait BackendFactory: Send + Sync {
+    fn create_backend(&self) -> Box<dyn ModelBackend + Send>;
+
+    /// Tokenize a prompt without starting a request
+    fn tokenize(&self, text: &str) -> Result<Vec<u32>, QueueError> {
+        self.create_backend().tokenize(text)
+    }
+
+    /// A backend generating `sequences` batched requests together, or `None` to run
+    /// them one after another on backends from [`BackendFactory::create_backend`]
+    fn create_batch_backend(&self, _sequences: usize) -> Option<Box<dyn BatchBackend + Send>> {
+        None
+    }
+}
+
+/// A model and its decoding state for several requests generated side by side.
+///
+/// Each request is a sequence, numbered from 0, with its own positions and sampler.
+pub trait BatchBackend {
+    /// Decode tokens of several sequences in one batch. Each input is a sequence, its
+    /// tokens and the position of the first token.
+    fn decode(&mut self, inputs: &[(usize, &[u32], usize)]) -> Result<(), QueueError>;
+
+    /// Decode a prompt shared by sequences `0..sequences`, from position 0
+    fn decode_shared(&mut self, tokens: &[u32], sequences: usize) -> Result<(), QueueError> {
+        let inputs: Vec<(usize, &[u32], usize)> =
+            (0..sequences).map(|seq| (seq, tokens, 0)).collect();
+        self.decode(&inputs)
+    }
+
+    /// Sample the token following the last decoded token of `seq`
+    fn sample_next_token(&mut self, seq: usize) -> u32;
+
+    /// Text of a token
+    fn token_to_str(&self, token: u32) -> Result<String, String>;
+
+    /// Whether a token ends generation
+    fn is_eog(&self, token: u32) -> bool;
+
+    /// Token ids of the end-of-turn markers of the configured model family
+    fn end_of_turn_token_ids(&self, config: &ModelConfig) -> Vec<u32>;
+}
+
+/// [`ModelBackend`] over a llama.cpp model and a fresh context
+pub struct LlamaCppBackend<'a> {
+    model: &'a LlamaModel,
+    ctx: LlamaContext<'a>,
+    batch: LlamaBatch,
+    sampler: LlamaSampler,
+}
+
+impl<'a> LlamaCppBackend<'a> {
+    /// Create a context for `model` with the manager's settings, in the slot of `lease`,
+    /// sampling with the temperature and top_p of `request`
+    pub fn new(
+        model_manager: &ModelManager,
+        model: &'a LlamaModel,
+        request: &GenerationRequest,
+        lease: &ContextLease,
+    ) -> Result<Self, ModelError> {
+        let ctx = model_manager.create_context(model, lease)?;
+        Ok(Self {
+            model,
+            ctx,
+            batch: LlamaBatch::new(model_manager.get_batch_size(), 1),
+            sampler: request_sampler(request, 0),
+        })
+    }
+}
+
+/// Sampler for completion `completion` of `request`: greedy when it sets neither
+/// temperature nor top_p or sets a temperature of 0, otherwise top_p then temperature
+/// then a seeded draw
+pub(crate) fn request_sampler(request: &GenerationRequest, completion: u32) -> LlamaSampler {
+    if (request.temperature.is_none() && request.top_p.is_none())
+        || request
+            .temperature
+            .is_some_and(|temperature| temperature <= 0.0)
+    {
+        return LlamaSampler::greedy();
+    }
+
+    let mut samplers = Vec::new();
+    if let Some(top_p) = request.top_p {
+        samplers.push(LlamaSampler::top_p(top_p, 1));
+    }
+    samplers.push(LlamaSampler::temp(request.temperature.unwrap_or(1.0)));
+    // Seeded, so the same request samples the same completions
+    let seed = request.seed.unwrap_or(DEFAULT_SEED);
+    samplers.push(LlamaSampler::dist(seed.wrapping_add(completion)));
+    LlamaSampler::chain_simple(samplers)
+}
+
+/// Seed of requests that do not set one
+const DEFAULT_SEED: u32 = 1234;
+
+impl ModelBackend for LlamaCppBackend<'_> {
+    fn tokenize(&self, text: &str) -> Result<Vec<u32>, QueueError> {
+        tokenize(self.model, text)
+    }
+
+    fn decode(&mut self, tokens: &[u32], start_pos: usize) -> Result<(), QueueError> {
+        self.batch.clear();
+        for (i, token) in tokens.iter().enumerate() {
+            // Logits are only needed for the last token
+            let is_last = i == tokens.len() - 1;
+            self.batch
+                .add(
+                    LlamaToken(*token as i32),
+                    (start_pos + i) as i32,
+                    &[0],
+                    is_last,
+                )
+                .map_err(|e| QueueError::WorkerError(format!("Batch token add failed: {}", e)))?;
+        }
+        self.ctx
+            .decode(&mut self.batch)
+            .map_err(|e| QueueError::WorkerError(format!("Batch decode failed: {}", e)))
+    }
+
+    fn sample_next_token(&mut self) -> u32 {
+        self.sampler.sample(&self.ctx, self.batch.n_tokens() - 1).0 as u32
+    }
+
+    fn token_to_str(&self, token: u32) -> Result<String, String> {
+        self.model
+            .token_to_str(LlamaToken(token as i32), Special::Tokenize)
+            .map_err(|e| e.to_string())
+    }
+
+    fn is_eog(&self, token: u32) -> bool {
+        self.model.is_eog_token(LlamaToken(token as i32))
+    }
+
+    fn stoppers(&self, config: &StoppingConfig) -> Vec<Box<dyn Stopper>> {
+        StopperFactory::from_config(config, self.model)
+    }
+
+    fn should_stop(&self, stopper: &mut dyn Stopper) -> Option<FinishReason> {
+        stopper.should_stop(&self.ctx, &self.batch)
+    }
+
+    fn end_of_turn_token_ids(&self, config: &ModelConfig) -> Vec<u32> {
+        end_of_turn_token_ids(self.model, config)
+    }
+}
+
+/// Tokenize a prompt for `model`, starting with the beginning-of-sequence token
+pub(crate) fn tokenize(model: &LlamaModel, text: &str) -> Result<Vec<u32>, QueueError> {
+    model
+        .str_to_token(text, AddBos::Always)
+        .map(|tokens| tokens.into_iter().map(|token| token.0 as u32).collect())
+        .map_err(|e| QueueError::WorkerError(format!("Tokenization failed: {}", e)))
+}
+
+/// [`BatchBackend`] over a llama.cpp model and a fresh multi-sequence context
+pub struct LlamaCppBatchBackend<'a> {
+    model: &'a LlamaModel,
+    ctx: LlamaContext<'a>,
+    batch: LlamaBatch,
+    samplers: Vec<LlamaSampler>,
+    /// Batch index of the logits of each sequence's last decoded token
+    logits_index: Vec<i32>,
+}
+
+impl<'a> LlamaCppBatchBackend<'a> {
+    /// Create a context decoding one sequence per request for `model`, in the slot of
+    /// `lease`; each sequence samples with its request's temperature and top_p
+    pub fn new(
+        model_manager: &ModelManager,
+        model: &'a LlamaModel,
+        requests: &[&GenerationRequest],
+        lease: &ContextLease,
+    ) -> Result<Self, ModelError> {
+        let samplers = requests
+            .iter()
+            .map(|request| request_sampler(request, 0))
+            .collect();
+        Self::with_samplers(model_manager, model, samplers, lease)
+    }
+
+    /// Create a context decoding `n` completions of `request` for `model`, in the slot
+    /// of `lease`; completion `i` samples with the request's seed plus `i`
+    pub fn for_completions(
+        model_manager: &ModelManager,
+        model: &'a LlamaModel,
+        request: &GenerationRequest,
+        n: u32,
+        lease: &ContextLease,
+    ) -> Result<Self, ModelError> {
+        let samplers = (0..n)
+            .map(|completion| request_sampler(request, completion))
+            .collect();
+        Self::with_samplers(model_manager, model, samplers, lease)
+    }
+
+    fn with_samplers(
+        model_manager: &ModelManager,
+        model: &'a LlamaModel,
+        samplers: Vec<LlamaSampler>,
+        lease: &ContextLease,
+    ) -> Result<Self, ModelError> {
+        let sequences = samplers.len();
+        let ctx = model_manager.create_multi_sequence_context(model, sequences as u32, lease)?;
+        Ok(Self {
+            model,
+            ctx,
+            batch: LlamaBatch::new(
+                model_manager.get_batch_size().max(sequences),
+                sequences as i32,
+            ),
+            samplers,
+            logits_index: vec![0; sequences],
+        })
+    }
+}
+
+impl BatchBackend for LlamaCppBatchBackend<'_> {
+    fn decode(&mut self, inputs: &[(usize, &[u32], usize)]) -> Result<(), QueueError> {
+        self.batch.clear();
+        for &(seq, tokens, start_pos) in inputs {
+            for (i, token) in tokens.iter().enumerate() {
+                // Logits are only needed for each sequence's last token
+                let is_last = i == tokens.len() - 1;
+                self.batch
+                    .add(
+                        LlamaToken(*token as i32),
+                        (start_pos + i) as i32,
+                        &[seq as i32],
+                        is_last,
+                    )
+                    .map_err(|e| {
+                        QueueError::WorkerError(format!("Batch token add failed: {}", e))
+                    })?;
+                if is_last {
+                    self.logits_index[seq] = self.batch.n_tokens() - 1;
+                }
+            }
+        }
+        self.ctx
+            .decode(&mut self.batch)
+            .map_err(|e| QueueError::WorkerError(format!("Batch decode failed: {}", e)))
+    }
+
+    fn decode_shared(&mut self, tokens: &[u32], sequences: usize) -> Result<(), QueueError> {
+        // Each token is decoded once, for all sequences
+        let seq_ids: Vec<i32> = (0..sequences as i32).collect();
+        self.batch.clear();
+        for (i, token) in tokens.iter().enumerate() {
+            let is_last = i == tokens.len() - 1;
+            self.batch
+                .add(LlamaToken(*token as i32), i as i32, &seq_ids, is_last)
+                .map_err(|e| QueueError::WorkerError(format!("Batch token add failed: {}", e)))?;
+        }
+        let last = self.batch.n_tokens() - 1;
+        for index in self.logits_index.iter_mut().take(sequences) {
+            *index = last;
+        }
+        self.ctx
+            .decode(&mut self.batch)
+            .map_err(|e| QueueError::WorkerError(format!("Batch decode failed: {}", e)))
+    }
+
+    fn sample_next_token(&mut self, seq: usize) -> u32 {
+        self.samplers[seq]
+            .sample(&self.ctx, self.logits_index[seq])
+            .0 as u32
+    }
+
+    fn token_to_str(&self, token: u32) -> Result<String, String> {
+        self.model
+            .token_to_str(LlamaToken(token as i32), Special::Tokenize)
+            .map_err(|e| e.to_string())
+    }
+
+    fn is_eog(&self, token: u32) -> bool {
+        self.model.is_eog_token(LlamaToken(token as i32))
+    }
+
+    fn end_of_turn_token_ids(&self, config: &ModelConfig) -> Vec<u32> {
+        end_of_turn_token_ids(self.model, config)
+    }
+}
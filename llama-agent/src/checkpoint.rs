@@ -0,0 +1,214 @@
+//! Session checkpoints, so a long session survives a process restart
+//!
+//! A checkpoint is a JSON file with a small header, the format name and version plus
+//! the identity of the model that served the session, followed by the session itself.
+//! Contexts are created for each request rather than kept per session, so there is
+//! no KV cache state to save alongside: a restored session is prefilled again on its
+//! next request. The model identity lets a restore tell whether the checkpoint was
+//! taken with the model now loaded.
+
+use crate::types::{ModelConfig, ModelMetadata, ModelSource, Session, SessionError};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Format name in the checkpoint header
+pub const CHECKPOINT_FORMAT: &str = "llama-agent-session-checkpoint";
+
+/// Checkpoint format version written, and the newest one read
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// The model a session was served by
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelIdentity {
+    /// Configured source of the model
+    pub source: ModelSource,
+    /// Size of the model file; `None` when the model was not loaded
+    pub size_bytes: Option<u64>,
+    /// HuggingFace revision of the model file, when known
+    pub revision: Option<String>,
+}
+
+impl ModelIdentity {
+    /// Identity of the model configured by `config`, with file details once it is loaded
+    pub fn new(config: &ModelConfig, metadata: Option<&ModelMetadata>) -> Self {
+        Self {
+            source: config.source.clone(),
+            size_bytes: metadata.map(|metadata| metadata.size_bytes),
+            revision: metadata.and_then(|metadata| metadata.revision.clone()),
+        }
+    }
+
+    /// Whether both identify the same model. File details only known to one side,
+    /// e.g. before the model loads, are not compared.
+    pub fn matches(&self, other: &ModelIdentity) -> bool {
+        fn same<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+        }
+        self.source == other.source
+            && same(&self.size_bytes, &other.size_bytes)
+            && same(&self.revision, &other.revision)
+    }
+}
+
+/// Contents of a checkpoint file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionCheckpoint {
+    pub format: String,
+    pub version: u32,
+    pub model: ModelIdentity,
+    pub session: Session,
+}
+
+impl SessionCheckpoint {
+    pub fn new(model: ModelIdentity, session: Session) -> Self {
+        Self {
+            format: CHECKPOINT_FORMAT.to_string(),
+            version: CHECKPOINT_VERSION,
+            model,
+            session,
+        }
+    }
+
+    /// Write the checkpoint to `path`, replacing it only once the new file is complete
+    pub async fn write(&self, path: &Path) -> Result<(), SessionError> {
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| checkpoint_error(path, format!("cannot serialize the session: {}", e)))?;
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        tokio::fs::write(&partial, json)
+            .await
+            .map_err(|e| checkpoint_error(path, e))?;
+        tokio::fs::rename(&partial, path)
+            .await
+            .map_err(|e| checkpoint_error(path, e))
+    }
+
+    /// Read a checkpoint, checking its header
+    pub async fn read(path: &Path) -> Result<Self, SessionError> {
+        let json = tokio::fs::read(path)
+            .await
+            .map_err(|e| checkpoint_error(path, e))?;
+
+        // The header is checked first, so a newer format is reported as such rather than
+        // as a session that fails to parse
+        #[derive(Deserialize)]
+        struct Header {
+            format: String,
+            version: u32,
+        }
+        let header: Header = serde_json::from_slice(&json)
+            .map_err(|e| checkpoint_error(path, format!("not a session checkpoint: {}", e)))?;
+        if header.format != CHECKPOINT_FORMAT {
+            return Err(checkpoint_error(
+                path,
+                format!("not a session checkpoint (format '{}')", header.format),
+            ));
+        }
+        if header.version > CHECKPOINT_VERSION {
+            return Err(checkpoint_error(
+                path,
+                format!(
+                    "written by a newer version (format version {}, this version reads up to {})",
+                    header.version, CHECKPOINT_VERSION
+                ),
+            ));
+        }
+
+        serde_json::from_slice(&json)
+            .map_err(|e| checkpoint_error(path, format!("invalid checkpoint: {}", e)))
+    }
+}
+
+/// A session put back from a checkpoint
+#[derive(Debug, Clone)]
+pub struct RestoredSession {
+    pub session: Session,
+    /// Whether the checkpoint was taken with the model serving the agent now
+    pub model_matches: bool,
+}
+
+fn checkpoint_error(path: &Path, reason: impl std::fmt::Display) -> SessionError {
+    SessionError::Checkpoint(format!("{}: {}", path.display(), reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{SessionId, SessionUsage, ToolPolicy};
+    use std::time::SystemTime;
+
+    fn session() -> Session {
+        Session {
+            id: SessionId::new(),
+            messages: Vec::new(),
+            mcp_servers: Vec::new(),
+            available_tools: Vec::new(),
+            available_prompts: Vec::new(),
+            created_at: SystemTime::UNIX_EPOCH,
+            updated_at: SystemTime::UNIX_EPOCH,
+            tool_policy: ToolPolicy::default(),
+            usage: SessionUsage::default(),
+        }
+    }
+
+    fn identity(repo: &str, size_bytes: Option<u64>) -> ModelIdentity {
+        ModelIdentity {
+            source: ModelSource::HuggingFace {
+                repo: repo.to_string(),
+                filename: None,
+            },
+            size_bytes,
+            revision: None,
+        }
+    }
+
+    #[test]
+    fn test_model_identity_matches() {
+        let loaded = identity("org/model", Some(1024));
+        assert!(loaded.matches(&identity("org/model", None)));
+        assert!(loaded.matches(&identity("org/model", Some(1024))));
+        assert!(!loaded.matches(&identity("org/model", Some(2048))));
+        assert!(!loaded.matches(&identity("org/other", Some(1024))));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+        let session = session();
+        SessionCheckpoint::new(identity("org/model", Some(1024)), session.clone())
+            .write(&path)
+            .await
+            .unwrap();
+
+        let checkpoint = SessionCheckpoint::read(&path).await.unwrap();
+        assert_eq!(checkpoint.version, CHECKPOINT_VERSION);
+        assert_eq!(checkpoint.model, identity("org/model", Some(1024)));
+        assert_eq!(checkpoint.session.id, session.id);
+        assert!(!dir.path().join("session.json.partial").exists());
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_header_is_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.json");
+
+        let mut checkpoint = SessionCheckpoint::new(identity("org/model", None), session());
+        checkpoint.version = CHECKPOINT_VERSION + 1;
+        checkpoint.write(&path).await.unwrap();
+        let error = SessionCheckpoint::read(&path).await.unwrap_err();
+        assert!(error.to_string().contains("newer version"), "{}", error);
+
+        std::fs::write(&path, r#"{"format": "something-else", "version": 1}"#).unwrap();
+        let error = SessionCheckpoint::read(&path).await.unwrap_err();
+        assert!(error.to_string().contains("something-else"), "{}", error);
+
+        let error = SessionCheckpoint::read(&dir.path().join("missing.json"))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SessionError::Checkpoint(_)));
+    }
+}
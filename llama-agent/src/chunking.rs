@@ -0,0 +1,277 @@
+//! Regrouping streamed token text into words or sentences
+//!
+//! Streaming requests send one chunk per token by default. With
+//! [`StreamChunking::Word`] or [`StreamChunking::Sentence`], [`StreamChunker`] holds
+//! token text back until a boundary and releases everything up to the last one.
+//!
+//! Sentences end at a newline, at `.`, `!` or `?` followed by whitespace (closing
+//! quotes and brackets may come in between), and after the full-width terminators
+//! `。`, `！` and `？` with any closing quotes. A period does not end a sentence after
+//! a common abbreviation such as "Dr", a single-letter initial or a list number at
+//! the start of a line. Decimals like "3.14" never split, since no whitespace follows
+//! the point.
+
+use crate::types::StreamChunking;
+
+/// Words whose trailing period does not end a sentence, compared in lowercase
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "e.g", "i.e", "cf", "fig", "approx",
+];
+
+/// Characters that may follow a sentence terminator before the whitespace
+const CLOSING_PUNCTUATION: &[char] = &['"', '\'', ')', ']', '}', '”', '’', '」', '』', '）'];
+
+/// Buffers streamed text and releases it at word or sentence boundaries
+#[derive(Debug, Clone)]
+pub struct StreamChunker {
+    mode: StreamChunking,
+    buffer: String,
+}
+
+impl StreamChunker {
+    pub fn new(mode: StreamChunking) -> Self {
+        Self {
+            mode,
+            buffer: String::new(),
+        }
+    }
+
+    /// Add the text of a token, returning the text ready to be sent, if any.
+    ///
+    /// In `Token` mode the text is returned as is, even when empty.
+    pub fn push(&mut self, text: &str) -> Option<String> {
+        if self.mode == StreamChunking::Token {
+            return Some(text.to_string());
+        }
+
+        self.buffer.push_str(text);
+        let end = last_boundary(&self.buffer, self.mode);
+        if end == 0 {
+            return None;
+        }
+        let rest = self.buffer.split_off(end);
+        Some(std::mem::replace(&mut self.buffer, rest))
+    }
+
+    /// Text held back since the last boundary, to send when generation ends
+    pub fn finish(&mut self) -> Option<String> {
+        if self.buffer.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.buffer))
+        }
+    }
+}
+
+/// Byte offset just past the last complete word or sentence in `text`, or 0 if none
+pub fn last_boundary(text: &str, mode: StreamChunking) -> usize {
+    match mode {
+        StreamChunking::Token => text.len(),
+        StreamChunking::Word => text
+            .char_indices()
+            .rfind(|&(_, c)| c.is_whitespace() || is_cjk(c))
+            .map_or(0, |(i, c)| i + c.len_utf8()),
+        StreamChunking::Sentence => last_sentence_boundary(text),
+    }
+}
+
+fn last_sentence_boundary(text: &str) -> usize {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut boundary = 0;
+
+    for (k, &(i, c)) in chars.iter().enumerate() {
+        if c == '\n' {
+            boundary = i + c.len_utf8();
+            continue;
+        }
+        if CLOSING_PUNCTUATION.contains(&c) {
+            continue;
+        }
+
+        // The character before any closing punctuation that precedes this one
+        let Some(&(before_at, before)) = chars[..k]
+            .iter()
+            .rev()
+            .find(|(_, p)| !CLOSING_PUNCTUATION.contains(p))
+        else {
+            continue;
+        };
+        let ends_sentence = match before {
+            '。' | '！' | '？' => true,
+            '!' | '?' => c.is_whitespace(),
+            '.' => c.is_whitespace() && !is_abbreviation(&text[..before_at]),
+            _ => false,
+        };
+        if ends_sentence {
+            // Whitespace stays with the sentence it ends
+            boundary = if c.is_whitespace() {
+                i + c.len_utf8()
+            } else {
+                i
+            };
+        }
+    }
+
+    boundary
+}
+
+/// Whether the word before a period makes it part of the sentence rather than its end
+fn is_abbreviation(before_period: &str) -> bool {
+    let line = before_period.rsplit('\n').next().unwrap_or_default();
+    let word = line
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(|c: char| !c.is_alphanumeric());
+    if word.is_empty() {
+        return false;
+    }
+
+    // Initials, as in "J. R. R. Tolkien"
+    let mut letters = word.chars();
+    if letters.next().is_some_and(char::is_alphabetic) && letters.next().is_none() {
+        return true;
+    }
+
+    // List numbers, as in "1. First step"
+    if word.chars().all(|c| c.is_ascii_digit()) {
+        return line.trim_start() == word;
+    }
+
+    ABBREVIATIONS.contains(&word.to_lowercase().as_str())
+}
+
+/// Characters of scripts written without spaces between words
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{303F}' // CJK punctuation
+        | '\u{3040}'..='\u{30FF}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4DBF}' // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}' // CJK Unified Ideographs
+        | '\u{F900}'..='\u{FAFF}' // CJK Compatibility Ideographs
+        | '\u{FF00}'..='\u{FFEF}' // Full-width forms
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `pieces` through a chunker, returning the chunks sent including the final flush
+    fn chunks(mode: StreamChunking, pieces: &[&str]) -> Vec<String> {
+        let mut chunker = StreamChunker::new(mode);
+        let mut out: Vec<String> = pieces.iter().filter_map(|p| chunker.push(p)).collect();
+        out.extend(chunker.finish());
+        assert_eq!(out.concat(), pieces.concat(), "no text may be lost");
+        out
+    }
+
+    fn sentences(text: &str) -> Vec<String> {
+        // One character per push, the worst case for boundary detection
+        let pieces: Vec<String> = text.chars().map(String::from).collect();
+        let pieces: Vec<&str> = pieces.iter().map(String::as_str).collect();
+        chunks(StreamChunking::Sentence, &pieces)
+    }
+
+    #[test]
+    fn test_token_mode_passes_text_through() {
+        assert_eq!(
+            chunks(StreamChunking::Token, &["Hel", "lo", "", " world"]),
+            ["Hel", "lo", "", " world"]
+        );
+    }
+
+    #[test]
+    fn test_word_chunks() {
+        assert_eq!(
+            chunks(StreamChunking::Word, &["Hel", "lo", ",", " wor", "ld", "!"]),
+            ["Hello, ", "world!"]
+        );
+        // Text after the last boundary in a token waits for the next one
+        assert_eq!(
+            chunks(StreamChunking::Word, &["one two", " three"]),
+            ["one ", "two ", "three"]
+        );
+        assert_eq!(
+            chunks(StreamChunking::Word, &["line\n", "\nnext"]),
+            ["line\n", "\n", "next"]
+        );
+    }
+
+    #[test]
+    fn test_word_chunks_without_spaces() {
+        assert_eq!(
+            chunks(StreamChunking::Word, &["你好", "世界"]),
+            ["你好", "世界"]
+        );
+        // Latin text next to CJK text waits for its own boundary
+        assert_eq!(
+            chunks(StreamChunking::Word, &["日本", "GPU", "です"]),
+            ["日本", "GPUです"]
+        );
+    }
+
+    #[test]
+    fn test_sentence_chunks() {
+        assert_eq!(
+            sentences("Hello there. How are you? Fine!"),
+            ["Hello there. ", "How are you? ", "Fine!"]
+        );
+        assert_eq!(
+            sentences("First line\nSecond line"),
+            ["First line\n", "Second line"]
+        );
+        assert_eq!(sentences("What?! Really."), ["What?! ", "Really."]);
+    }
+
+    #[test]
+    fn test_sentence_chunks_keep_numbers_and_abbreviations() {
+        assert_eq!(
+            sentences("Pi is 3.14 or so. Dr. Smith agrees, e.g. in his book."),
+            ["Pi is 3.14 or so. ", "Dr. Smith agrees, e.g. in his book."]
+        );
+        assert_eq!(
+            sentences("Written by J. R. R. Tolkien. The end."),
+            ["Written by J. R. R. Tolkien. ", "The end."]
+        );
+        assert_eq!(
+            sentences("Steps:\n1. Open it.\n2. Close it."),
+            ["Steps:\n", "1. Open it.\n", "2. Close it."]
+        );
+        // A number at the end of a sentence still ends it
+        assert_eq!(
+            sentences("The answer is 42. Next question."),
+            ["The answer is 42. ", "Next question."]
+        );
+    }
+
+    #[test]
+    fn test_sentence_chunks_with_closing_quotes() {
+        assert_eq!(
+            sentences("He said \"Stop.\" Then he left."),
+            ["He said \"Stop.\" ", "Then he left."]
+        );
+        assert_eq!(sentences("(See above.) Next."), ["(See above.) ", "Next."]);
+    }
+
+    #[test]
+    fn test_sentence_chunks_without_spaces() {
+        assert_eq!(
+            sentences("今日は晴れです。明日は雨？「はい。」そうです"),
+            ["今日は晴れです。", "明日は雨？", "「はい。」", "そうです"]
+        );
+    }
+
+    #[test]
+    fn test_period_waits_for_following_character() {
+        let mut chunker = StreamChunker::new(StreamChunking::Sentence);
+        assert_eq!(chunker.push("It costs 3."), None);
+        assert_eq!(
+            chunker.push("50 now. "),
+            Some("It costs 3.50 now. ".to_string())
+        );
+        assert_eq!(chunker.push("Done."), None);
+        assert_eq!(chunker.finish(), Some("Done.".to_string()));
+        assert_eq!(chunker.finish(), None);
+    }
+}
@@ -0,0 +1,23 @@
+//! Source of wall-clock time for sessions and messages
+//!
+//! Timestamps are taken from a [`Clock`] instead of calling `SystemTime::now()`
+//! directly, so time-dependent behaviour such as session expiry can be tested
+//! with [`MockClock`](crate::test_support::MockClock).
+
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+/// Provides the current time
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// [`Clock`] reading the system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
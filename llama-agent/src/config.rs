@@ -0,0 +1,597 @@
+//! Loading [`AgentConfig`] from configuration files and environment variables.
+//!
+//! Configuration is layered: the file is parsed first (TOML, YAML or JSON,
+//! detected by extension), then any environment variable starting with
+//! `LLAMA_AGENT__` is applied on top. Nested keys are separated by a double
+//! underscore, so `LLAMA_AGENT__QUEUE__WORKER_THREADS=4` sets
+//! `queue_config.worker_threads`. The `_config` suffix of the top-level
+//! sections may be omitted, and key matching is case-insensitive.
+//!
+//! Override values are parsed as JSON when possible (numbers, booleans,
+//! arrays) and treated as plain strings otherwise. Duration fields accept
+//! humantime strings such as `"30s"` or `"5m"`, or a plain number of seconds.
+
+use crate::types::{AgentConfig, AgentError, ConfigError};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::path::Path;
+use tracing::debug;
+
+/// Prefix for environment variables that override configuration values
+pub const ENV_PREFIX: &str = "LLAMA_AGENT";
+
+/// Separator between nested keys in environment variable names
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Short names accepted for the top-level configuration sections
+const SECTION_ALIASES: &[(&str, &str)] = &[
+    ("queue", "queue_config"),
+    ("session", "session_config"),
+    ("parallel_execution", "parallel_execution_config"),
+];
+
+/// Supported configuration file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Detect the configuration format from a file extension
+    pub fn from_path(path: &Path) -> Result<Self, ConfigError> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+
+        match extension.as_deref() {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => Err(ConfigError::UnsupportedFormat(format!(
+                "{} (expected .toml, .yaml, .yml or .json)",
+                path.display()
+            ))),
+        }
+    }
+
+    fn parse(&self, contents: &str) -> Result<Value, ConfigError> {
+        match self {
+            ConfigFormat::Toml => {
+                toml::from_str(contents).map_err(|e| ConfigError::Parse(e.to_string()))
+            }
+            ConfigFormat::Yaml => serde_yaml::from_str(contents)
+                .map_err(|e| ConfigError::Parse(e.to_string()))
+                .and_then(yaml_to_json),
+            ConfigFormat::Json => {
+                serde_json::from_str(contents).map_err(|e| ConfigError::Parse(e.to_string()))
+            }
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Load configuration from a file, applying `LLAMA_AGENT__*` environment overrides.
+    ///
+    /// The result is not validated; [`AgentConfig::validate`] runs during
+    /// `AgentServer::initialize`, after callers had a chance to layer their own values on top.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, AgentError> {
+        Self::from_file_with_env(path, std::env::vars())
+    }
+
+    /// Load configuration from a file, applying overrides from the given variables
+    pub fn from_file_with_env<I>(path: impl AsRef<Path>, vars: I) -> Result<Self, AgentError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path)?;
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(format!("{}: {}", path.display(), e)))?;
+
+        debug!("Loading {:?} configuration from {}", format, path.display());
+        Self::from_str_with_env(&contents, format, vars)
+    }
+
+    /// Parse configuration from a string, applying overrides from the given variables
+    pub fn from_str_with_env<I>(
+        contents: &str,
+        format: ConfigFormat,
+        vars: I,
+    ) -> Result<Self, AgentError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut value = format.parse(contents)?;
+        if value.is_null() {
+            // An empty YAML document parses to null; treat it as an empty table
+            value = Value::Object(Map::new());
+        }
+
+        apply_env_overrides(&mut value, vars)?;
+        Ok(from_value(value)?)
+    }
+
+    /// Build configuration from defaults plus `LLAMA_AGENT__*` environment overrides
+    pub fn from_env() -> Result<Self, AgentError> {
+        let mut value = Value::Object(Map::new());
+        apply_env_overrides(&mut value, std::env::vars())?;
+        Ok(from_value(value)?)
+    }
+}
+
+/// Convert YAML into a JSON tree, mapping `!Variant` tags to single-key tables
+/// so that enums written by serde_yaml deserialize like their TOML/JSON form.
+fn yaml_to_json(value: serde_yaml::Value) -> Result<Value, ConfigError> {
+    use serde_yaml::Value as Yaml;
+
+    Ok(match value {
+        Yaml::Null => Value::Null,
+        Yaml::Bool(b) => Value::Bool(b),
+        Yaml::Number(n) => {
+            serde_json::to_value(&n).map_err(|e| ConfigError::Parse(e.to_string()))?
+        }
+        Yaml::String(s) => Value::String(s),
+        Yaml::Sequence(items) => Value::Array(
+            items
+                .into_iter()
+                .map(yaml_to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        Yaml::Mapping(mapping) => {
+            let mut map = Map::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    Yaml::String(s) => s,
+                    Yaml::Number(n) => n.to_string(),
+                    Yaml::Bool(b) => b.to_string(),
+                    other => {
+                        return Err(ConfigError::Parse(format!(
+                            "unsupported YAML mapping key: {:?}",
+                            other
+                        )))
+                    }
+                };
+                map.insert(key, yaml_to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        Yaml::Tagged(tagged) => {
+            let mut map = Map::new();
+            let tag = tagged.tag.to_string();
+            map.insert(
+                tag.trim_start_matches('!').to_string(),
+                yaml_to_json(tagged.value)?,
+            );
+            Value::Object(map)
+        }
+    })
+}
+
+fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, ConfigError> {
+    serde_json::from_value(value).map_err(|e| ConfigError::Parse(e.to_string()))
+}
+
+/// Apply every `LLAMA_AGENT__*` variable from `vars` onto a configuration tree
+pub fn apply_env_overrides<I>(value: &mut Value, vars: I) -> Result<(), ConfigError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let prefix = format!("{}{}", ENV_PREFIX, ENV_SEPARATOR);
+
+    let mut overrides: Vec<(String, Vec<String>, String)> = vars
+        .into_iter()
+        .filter_map(|(key, raw)| {
+            let path = key
+                .strip_prefix(&prefix)?
+                .split(ENV_SEPARATOR)
+                .map(|segment| segment.to_lowercase())
+                .collect();
+            Some((key, path, raw))
+        })
+        .collect();
+
+    // Apply in a stable order so that overlapping overrides are deterministic
+    overrides.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (key, path, raw) in overrides {
+        if path.iter().any(|segment| segment.is_empty()) {
+            return Err(ConfigError::EnvOverride(format!(
+                "{} contains an empty key segment",
+                key
+            )));
+        }
+
+        debug!("Applying configuration override from {}", key);
+        set_path(value, &path, parse_env_value(&raw))
+            .map_err(|reason| ConfigError::EnvOverride(format!("{}: {}", key, reason)))?;
+    }
+
+    Ok(())
+}
+
+fn parse_env_value(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+fn set_path(root: &mut Value, path: &[String], new_value: Value) -> Result<(), String> {
+    let mut current = root;
+
+    for (depth, segment) in path.iter().enumerate() {
+        let is_last = depth == path.len() - 1;
+
+        if current.is_null() {
+            *current = Value::Object(Map::new());
+        }
+
+        current = match current {
+            Value::Object(map) => {
+                let key = resolve_key(map, segment, depth == 0);
+                if is_last {
+                    map.insert(key, new_value);
+                    return Ok(());
+                }
+                map.entry(key).or_insert(Value::Null)
+            }
+            Value::Array(items) => {
+                let index: usize = segment
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a valid array index", segment))?;
+                if index == items.len() {
+                    items.push(Value::Null);
+                } else if index > items.len() {
+                    return Err(format!(
+                        "array index {} is out of bounds (length {})",
+                        index,
+                        items.len()
+                    ));
+                }
+                if is_last {
+                    items[index] = new_value;
+                    return Ok(());
+                }
+                &mut items[index]
+            }
+            _ => return Err(format!("cannot set '{}' inside a non-table value", segment)),
+        };
+    }
+
+    Ok(())
+}
+
+/// Find the existing key matching `segment`, ignoring case and section aliases
+fn resolve_key(map: &Map<String, Value>, segment: &str, top_level: bool) -> String {
+    let canonical = if top_level {
+        SECTION_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == segment)
+            .map(|(_, name)| *name)
+            .unwrap_or(segment)
+    } else {
+        segment
+    };
+
+    map.keys()
+        .find(|key| key.eq_ignore_ascii_case(canonical))
+        .cloned()
+        .unwrap_or_else(|| canonical.to_string())
+}
+
+/// Serde adapter for `Duration` fields using humantime strings ("30s", "5m").
+///
+/// Deserialization also accepts a plain number of seconds and the
+/// `{ secs, nanos }` form produced by serde's default `Duration` encoding.
+pub mod duration {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DurationRepr {
+        Text(String),
+        Seconds(u64),
+        Struct { secs: u64, nanos: u32 },
+    }
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match DurationRepr::deserialize(deserializer)? {
+            DurationRepr::Text(text) => {
+                humantime::parse_duration(text.trim()).map_err(serde::de::Error::custom)
+            }
+            DurationRepr::Seconds(secs) => Ok(Duration::from_secs(secs)),
+            DurationRepr::Struct { secs, nanos } => Ok(Duration::new(secs, nanos)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{LoadMode, ModelSource, QueueConfig};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn no_env() -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    const FULL_TOML: &str = r#"
+[model]
+batch_size = 256
+use_hf_params = false
+debug = true
+
+[model.source.HuggingFace]
+repo = "unsloth/Qwen3-0.6B-GGUF"
+filename = "Qwen3-0.6B-BF16.gguf"
+
+[model.retry_config]
+max_retries = 5
+initial_delay_ms = 500
+backoff_multiplier = 1.5
+max_delay_ms = 10000
+
+[queue_config]
+max_queue_size = 42
+request_timeout = "45s"
+worker_threads = 2
+
+[session_config]
+max_sessions = 12
+session_timeout = "5m"
+
+[[mcp_servers]]
+name = "filesystem"
+command = "npx"
+args = ["-y", "@modelcontextprotocol/server-filesystem", "/tmp"]
+timeout_secs = 30
+
+[parallel_execution_config]
+max_parallel_tools = 2
+"#;
+
+    fn write_file(dir: &TempDir, name: &str, contents: &str) -> std::path::PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_format_detection() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("agent.toml")).unwrap(),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("agent.YML")).unwrap(),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("agent.json")).unwrap(),
+            ConfigFormat::Json
+        );
+        assert!(ConfigFormat::from_path(Path::new("agent.ini")).is_err());
+        assert!(ConfigFormat::from_path(Path::new("agent")).is_err());
+    }
+
+    #[test]
+    fn test_load_full_toml_file() {
+        let dir = TempDir::new().unwrap();
+        let path = write_file(&dir, "agent.toml", FULL_TOML);
+
+        let config = AgentConfig::from_file_with_env(&path, no_env()).unwrap();
+
+        match &config.model.source {
+            ModelSource::HuggingFace { repo, filename } => {
+                assert_eq!(repo, "unsloth/Qwen3-0.6B-GGUF");
+                assert_eq!(filename.as_deref(), Some("Qwen3-0.6B-BF16.gguf"));
+            }
+            _ => panic!("Expected HuggingFace source"),
+        }
+        assert_eq!(config.model.batch_size, 256);
+        assert!(!config.model.use_hf_params);
+        assert!(config.model.debug);
+        assert_eq!(config.model.retry_config.max_retries, 5);
+        assert_eq!(config.queue_config.max_queue_size, 42);
+        assert_eq!(config.queue_config.request_timeout, Duration::from_secs(45));
+        assert_eq!(config.queue_config.worker_threads, 2);
+        assert_eq!(config.session_config.max_sessions, 12);
+        assert_eq!(
+            config.session_config.session_timeout,
+            Duration::from_secs(300)
+        );
+        assert_eq!(config.mcp_servers.len(), 1);
+        assert_eq!(config.mcp_servers[0].name, "filesystem");
+        assert_eq!(config.mcp_servers[0].timeout_secs, Some(30));
+        assert_eq!(config.parallel_execution_config.max_parallel_tools, 2);
+        // Unspecified fields fall back to defaults
+        assert!(config.parallel_execution_config.conflict_detection);
+    }
+
+    #[test]
+    fn test_round_trip_toml_and_yaml() {
+        let dir = TempDir::new().unwrap();
+        let original =
+            AgentConfig::from_file_with_env(write_file(&dir, "agent.toml", FULL_TOML), no_env())
+                .unwrap();
+
+        let toml_text = toml::to_string(&original).unwrap();
+        let yaml_text = serde_yaml::to_string(&original).unwrap();
+        assert!(toml_text.contains("request_timeout = \"45s\""));
+
+        for path in [
+            write_file(&dir, "round_trip.toml", &toml_text),
+            write_file(&dir, "round_trip.yaml", &yaml_text),
+        ] {
+            let loaded = AgentConfig::from_file_with_env(&path, no_env()).unwrap();
+            assert_eq!(loaded.model.source, original.model.source);
+            assert_eq!(loaded.model.batch_size, original.model.batch_size);
+            assert_eq!(
+                loaded.queue_config.request_timeout,
+                original.queue_config.request_timeout
+            );
+            assert_eq!(
+                loaded.session_config.session_timeout,
+                original.session_config.session_timeout
+            );
+            assert_eq!(loaded.mcp_servers[0].args, original.mcp_servers[0].args);
+        }
+    }
+
+    #[test]
+    fn test_partial_yaml_uses_defaults() {
+        let yaml = "queue_config:\n  worker_threads: 3\n";
+        let config = AgentConfig::from_str_with_env(yaml, ConfigFormat::Yaml, no_env()).unwrap();
+
+        assert_eq!(config.queue_config.worker_threads, 3);
+        assert_eq!(
+            config.queue_config.max_queue_size,
+            QueueConfig::default().max_queue_size
+        );
+        assert!(config.mcp_servers.is_empty());
+        assert_eq!(config.load_mode, LoadMode::Eager);
+    }
+
+    #[test]
+    fn test_load_mode() {
+        let config =
+            AgentConfig::from_str_with_env("load_mode: lazy\n", ConfigFormat::Yaml, no_env())
+                .unwrap();
+        assert_eq!(config.load_mode, LoadMode::Lazy);
+
+        let vars = env(&[("LLAMA_AGENT__LOAD_MODE", "eager")]);
+        let config =
+            AgentConfig::from_str_with_env("load_mode: lazy\n", ConfigFormat::Yaml, vars).unwrap();
+        assert_eq!(config.load_mode, LoadMode::Eager);
+    }
+
+    #[test]
+    fn test_limits_section() {
+        let toml = "[limits]\nsession_requests_per_minute = 30\nsession_max_in_flight = 2\n";
+        let vars = env(&[("LLAMA_AGENT__LIMITS__GLOBAL_REQUESTS_PER_MINUTE", "600")]);
+        let config = AgentConfig::from_str_with_env(toml, ConfigFormat::Toml, vars).unwrap();
+
+        assert_eq!(config.limits.session_requests_per_minute, Some(30));
+        assert_eq!(config.limits.session_max_in_flight, Some(2));
+        assert_eq!(config.limits.global_requests_per_minute, Some(600));
+        assert_eq!(config.limits.session_burst, None);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let vars = env(&[
+            ("LLAMA_AGENT__QUEUE__WORKER_THREADS", "4"),
+            ("LLAMA_AGENT__SESSION_CONFIG__SESSION_TIMEOUT", "2h"),
+            (
+                "LLAMA_AGENT__MODEL__SOURCE__HUGGINGFACE__REPO",
+                "org/other-model",
+            ),
+            ("LLAMA_AGENT__MCP_SERVERS__0__TIMEOUT_SECS", "90"),
+            ("LLAMA_AGENT__MODEL__DEBUG", "false"),
+            ("UNRELATED_VARIABLE", "ignored"),
+        ]);
+
+        let config = AgentConfig::from_str_with_env(FULL_TOML, ConfigFormat::Toml, vars).unwrap();
+
+        assert_eq!(config.queue_config.worker_threads, 4);
+        assert_eq!(
+            config.session_config.session_timeout,
+            Duration::from_secs(7200)
+        );
+        match &config.model.source {
+            ModelSource::HuggingFace { repo, filename } => {
+                assert_eq!(repo, "org/other-model");
+                // Sibling keys from the file are preserved
+                assert_eq!(filename.as_deref(), Some("Qwen3-0.6B-BF16.gguf"));
+            }
+            _ => panic!("Expected HuggingFace source"),
+        }
+        assert_eq!(config.mcp_servers[0].timeout_secs, Some(90));
+        assert!(!config.model.debug);
+    }
+
+    #[test]
+    fn test_env_overrides_without_file_values() {
+        let vars = env(&[
+            ("LLAMA_AGENT__MODEL__SOURCE__LOCAL__FOLDER", "/models"),
+            ("LLAMA_AGENT__QUEUE__REQUEST_TIMEOUT", "90"),
+        ]);
+
+        let config = AgentConfig::from_str_with_env("", ConfigFormat::Toml, vars).unwrap();
+
+        match &config.model.source {
+            ModelSource::Local { folder, filename } => {
+                assert_eq!(folder, Path::new("/models"));
+                assert!(filename.is_none());
+            }
+            _ => panic!("Expected Local source"),
+        }
+        assert_eq!(config.queue_config.request_timeout, Duration::from_secs(90));
+    }
+
+    #[test]
+    fn test_invalid_env_overrides() {
+        let empty_segment = env(&[("LLAMA_AGENT__QUEUE____WORKER_THREADS", "4")]);
+        let result = AgentConfig::from_str_with_env("", ConfigFormat::Toml, empty_segment);
+        assert!(matches!(
+            result,
+            Err(AgentError::Config(ConfigError::EnvOverride(_)))
+        ));
+
+        let bad_index = env(&[("LLAMA_AGENT__MCP_SERVERS__5__NAME", "x")]);
+        let result = AgentConfig::from_str_with_env(FULL_TOML, ConfigFormat::Toml, bad_index);
+        assert!(matches!(
+            result,
+            Err(AgentError::Config(ConfigError::EnvOverride(_)))
+        ));
+
+        let bad_type = env(&[("LLAMA_AGENT__QUEUE__WORKER_THREADS", "many")]);
+        let result = AgentConfig::from_str_with_env("", ConfigFormat::Toml, bad_type);
+        assert!(matches!(
+            result,
+            Err(AgentError::Config(ConfigError::Parse(_)))
+        ));
+    }
+
+    #[test]
+    fn test_duration_formats() {
+        let yaml = "queue_config:\n  request_timeout: 15\nsession_config:\n  session_timeout:\n    secs: 20\n    nanos: 0\n";
+        let config = AgentConfig::from_str_with_env(yaml, ConfigFormat::Yaml, no_env()).unwrap();
+        assert_eq!(config.queue_config.request_timeout, Duration::from_secs(15));
+        assert_eq!(
+            config.session_config.session_timeout,
+            Duration::from_secs(20)
+        );
+
+        let invalid = "[queue_config]\nrequest_timeout = \"soon\"\n";
+        assert!(AgentConfig::from_str_with_env(invalid, ConfigFormat::Toml, no_env()).is_err());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let result = AgentConfig::from_file_with_env("/nonexistent/agent.toml", no_env());
+        assert!(matches!(
+            result,
+            Err(AgentError::Config(ConfigError::Io(_)))
+        ));
+    }
+}
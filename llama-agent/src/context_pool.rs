@@ -0,0 +1,123 @@
+//! Slots bounding how many llama.cpp contexts exist at once
+//!
+//! One loaded model may be shared by any number of contexts decoding in parallel, but
+//! a context holds the KV cache and decode state of a single request and must never
+//! be used by two at a time. Every generation or embedding request takes a
+//! [`ContextLease`] from the [`ContextPool`] before it creates its context and keeps it
+//! until the context is dropped, so at most `size` contexts exist. Contexts borrow the
+//! model, so each lease creates its own rather than reusing a stored one.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A fixed number of context slots handed out to in-flight requests
+#[derive(Debug)]
+pub struct ContextPool {
+    size: usize,
+    semaphore: Arc<Semaphore>,
+    free_slots: Arc<Mutex<Vec<usize>>>,
+}
+
+impl ContextPool {
+    /// A pool of `size` slots, at least one
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            size,
+            semaphore: Arc::new(Semaphore::new(size)),
+            // Lowest slot first
+            free_slots: Arc::new(Mutex::new((0..size).rev().collect())),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Slots currently leased
+    pub fn in_use(&self) -> usize {
+        self.size - self.semaphore.available_permits()
+    }
+
+    /// Wait for a free slot
+    pub async fn acquire(&self) -> ContextLease {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("context pool semaphore is never closed");
+        let slot = lock(&self.free_slots)
+            .pop()
+            .expect("a permit always leaves a free slot");
+        ContextLease {
+            slot,
+            free_slots: self.free_slots.clone(),
+            _permit: permit,
+        }
+    }
+}
+
+fn lock(slots: &Mutex<Vec<usize>>) -> MutexGuard<'_, Vec<usize>> {
+    slots
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A leased context slot; returns to the pool when dropped
+#[derive(Debug)]
+pub struct ContextLease {
+    slot: usize,
+    free_slots: Arc<Mutex<Vec<usize>>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ContextLease {
+    /// Index of the slot, below the pool size
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+}
+
+impl Drop for ContextLease {
+    fn drop(&mut self) {
+        // Returned before the permit, so the next holder finds the slot free
+        lock(&self.free_slots).push(self.slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_leases_are_bounded_by_pool_size() {
+        let pool = ContextPool::new(2);
+        let first = pool.acquire().await;
+        let second = pool.acquire().await;
+        assert_ne!(first.slot(), second.slot());
+        assert_eq!(pool.in_use(), 2);
+
+        // A third request waits until a lease is returned
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), pool.acquire())
+                .await
+                .is_err()
+        );
+
+        let freed = first.slot();
+        drop(first);
+        let third = pool.acquire().await;
+        assert_eq!(third.slot(), freed);
+        assert_eq!(pool.in_use(), 2);
+
+        drop((second, third));
+        assert_eq!(pool.in_use(), 0);
+    }
+
+    #[test]
+    fn test_pool_has_at_least_one_slot() {
+        assert_eq!(ContextPool::new(0).size(), 1);
+    }
+}
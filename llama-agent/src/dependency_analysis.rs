@@ -1,12 +1,55 @@
 use crate::types::{
     AccessType, ConflictType, ParallelExecutionConfig, ParameterReference, ReferenceType,
-    ResourceAccess, ResourceType, ToolCall, ToolConflict,
+    ResourceAccess, ResourceType, ToolCall, ToolConflict, ToolDefinition,
 };
 use regex::Regex;
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
+use thiserror::Error;
 use tracing::debug;
 
+/// Plan the execution order of a batch of tool calls.
+///
+/// See [`DependencyAnalyzer::plan_execution`].
+pub fn plan_execution(
+    calls: &[ToolCall],
+    tools: &[ToolDefinition],
+    config: &ParallelExecutionConfig,
+) -> Result<ExecutionPlan, PlanError> {
+    DependencyAnalyzer::new(config.clone()).plan_execution(calls, tools)
+}
+
+/// Tool calls grouped into stages that run one after another; the calls within a
+/// stage are safe to run concurrently
+#[derive(Debug, Clone)]
+pub struct ExecutionPlan {
+    pub stages: Vec<Vec<ToolCall>>,
+}
+
+impl ExecutionPlan {
+    /// Number of tool calls across all stages
+    pub fn call_count(&self) -> usize {
+        self.stages.iter().map(Vec::len).sum()
+    }
+
+    /// Tool names per stage, mainly for logging and tests
+    pub fn stage_names(&self) -> Vec<Vec<&str>> {
+        self.stages
+            .iter()
+            .map(|stage| stage.iter().map(|call| call.name.as_str()).collect())
+            .collect()
+    }
+}
+
+/// Error returned when tool calls cannot be ordered
+#[derive(Debug, Error, PartialEq)]
+pub enum PlanError {
+    /// The calls depend on each other's output in a loop; `tools` lists the cycle in
+    /// dependency order, starting and ending with the same tool
+    #[error("Tool calls depend on each other in a cycle: {}", tools.join(" -> "))]
+    DependencyCycle { tools: Vec<String> },
+}
+
 /// Analyzes tool dependencies and conflicts for parallel execution decisions
 pub struct DependencyAnalyzer {
     config: ParallelExecutionConfig,
@@ -92,6 +135,118 @@ impl DependencyAnalyzer {
         ParallelExecutionDecision::Parallel
     }
 
+    /// Group tool calls into stages that respect their dependencies.
+    ///
+    /// A call runs after every call whose output it references (see
+    /// [`ParameterReference`]). Calls that conflict on a resource, are listed in
+    /// `never_parallel` or `tool_conflicts` keep their original order in separate
+    /// stages. Everything else runs in the earliest possible stage, in call order.
+    /// `tools` supplies descriptions used to infer resource access for tools whose
+    /// names say nothing about it.
+    pub fn plan_execution(
+        &self,
+        calls: &[ToolCall],
+        tools: &[ToolDefinition],
+    ) -> Result<ExecutionPlan, PlanError> {
+        let resources: Vec<Vec<ResourceAccess>> = calls
+            .iter()
+            .map(|call| {
+                let mut access = self.infer_resource_access(&call.name, &call.arguments);
+                if access.is_empty() {
+                    if let Some(tool) = tools.iter().find(|tool| tool.name == call.name) {
+                        self.infer_from_tool_name(&tool.description, &mut access);
+                    }
+                }
+                access
+            })
+            .collect();
+
+        // before[j] lists the calls that must finish before call j starts
+        let mut before: Vec<HashSet<usize>> = vec![HashSet::new(); calls.len()];
+        for (j, call) in calls.iter().enumerate() {
+            for reference in self
+                .extract_parameter_references(&call.arguments)
+                .unwrap_or_default()
+            {
+                for (i, other) in calls.iter().enumerate() {
+                    if i != j && other.name == reference.referenced_tool {
+                        before[j].insert(i);
+                    }
+                }
+            }
+
+            for i in 0..j {
+                if self.calls_conflict(&calls[i], &calls[j], &resources[i], &resources[j]) {
+                    before[j].insert(i);
+                }
+            }
+        }
+
+        let mut stages = Vec::new();
+        let mut done = vec![false; calls.len()];
+        let mut remaining = calls.len();
+        while remaining > 0 {
+            let ready: Vec<usize> = (0..calls.len())
+                .filter(|&j| !done[j] && before[j].iter().all(|&i| done[i]))
+                .collect();
+            if ready.is_empty() {
+                return Err(PlanError::DependencyCycle {
+                    tools: dependency_cycle(calls, &before, &done),
+                });
+            }
+
+            for &j in &ready {
+                done[j] = true;
+            }
+            remaining -= ready.len();
+            stages.push(ready.into_iter().map(|j| calls[j].clone()).collect());
+        }
+
+        debug!(
+            "Planned {} tool calls in {} stages",
+            calls.len(),
+            stages.len()
+        );
+        Ok(ExecutionPlan { stages })
+    }
+
+    /// Whether two calls must not run concurrently
+    fn calls_conflict(
+        &self,
+        first: &ToolCall,
+        second: &ToolCall,
+        first_resources: &[ResourceAccess],
+        second_resources: &[ResourceAccess],
+    ) -> bool {
+        let is_pair = |tool1: &str, tool2: &str| {
+            (first.name == tool1 && second.name == tool2)
+                || (first.name == tool2 && second.name == tool1)
+        };
+        if self
+            .config
+            .tool_conflicts
+            .iter()
+            .any(|conflict| is_pair(&conflict.tool1, &conflict.tool2))
+            || self
+                .config
+                .never_parallel
+                .iter()
+                .any(|(tool1, tool2)| is_pair(tool1, tool2))
+        {
+            return true;
+        }
+
+        first_resources.iter().any(|a| {
+            second_resources.iter().any(|b| {
+                resource_key(&a.resource) == resource_key(&b.resource)
+                    && (a.exclusive
+                        || b.exclusive
+                        || is_write(&a.access_type)
+                        || is_write(&b.access_type))
+            })
+        })
+    }
+
     /// Analyzes parameter dependencies between tool calls
     fn analyze_parameter_dependencies(&self, tool_calls: &[ToolCall]) -> Option<String> {
         for (i, tool_call) in tool_calls.iter().enumerate() {
@@ -184,18 +339,8 @@ impl DependencyAnalyzer {
             let resources = self.infer_resource_access(&tool_call.name, &tool_call.arguments);
 
             for resource in resources {
-                let resource_key = match &resource.resource {
-                    ResourceType::File(path) => format!("file:{}", path),
-                    ResourceType::FileSystem(path) => format!("fs:{}", path),
-                    ResourceType::Network(url) => format!("net:{}", url),
-                    ResourceType::Database(db) => format!("db:{}", db),
-                    ResourceType::Memory => "mem:shared".to_string(),
-                    ResourceType::System => "sys:shared".to_string(),
-                    ResourceType::Other(name) => format!("other:{}", name),
-                };
-
                 resource_usage
-                    .entry(resource_key.clone())
+                    .entry(resource_key(&resource.resource))
                     .or_default()
                     .push((tool_call.name.clone(), resource.access_type.clone()));
             }
@@ -204,12 +349,7 @@ impl DependencyAnalyzer {
         // Check for conflicts
         for (resource, accesses) in resource_usage {
             if accesses.len() > 1 {
-                let has_write = accesses.iter().any(|(_, access)| {
-                    matches!(
-                        access,
-                        AccessType::Write | AccessType::ReadWrite | AccessType::Delete
-                    )
-                });
+                let has_write = accesses.iter().any(|(_, access)| is_write(access));
 
                 if has_write {
                     let tool_names: Vec<String> =
@@ -348,6 +488,58 @@ impl DependencyAnalyzer {
     }
 }
 
+/// Key identifying a resource, so accesses to the same resource can be compared
+fn resource_key(resource: &ResourceType) -> String {
+    match resource {
+        ResourceType::File(path) => format!("file:{}", path),
+        ResourceType::FileSystem(path) => format!("fs:{}", path),
+        ResourceType::Network(url) => format!("net:{}", url),
+        ResourceType::Database(db) => format!("db:{}", db),
+        ResourceType::Memory => "mem:shared".to_string(),
+        ResourceType::System => "sys:shared".to_string(),
+        ResourceType::Other(name) => format!("other:{}", name),
+    }
+}
+
+/// Whether an access modifies the resource
+fn is_write(access: &AccessType) -> bool {
+    matches!(
+        access,
+        AccessType::Write | AccessType::ReadWrite | AccessType::Delete
+    )
+}
+
+/// Tool names along one dependency cycle among the calls not yet planned, first
+/// tool repeated at the end
+fn dependency_cycle(calls: &[ToolCall], before: &[HashSet<usize>], done: &[bool]) -> Vec<String> {
+    // Every unplanned call waits on another unplanned call, so walking back through
+    // those prerequisites must eventually revisit a call
+    let mut path = Vec::new();
+    let mut seen = HashMap::new();
+    let mut current = (0..calls.len()).find(|&j| !done[j]).unwrap_or_default();
+    while !seen.contains_key(&current) {
+        seen.insert(current, path.len());
+        path.push(current);
+        current = before[current]
+            .iter()
+            .copied()
+            .filter(|&i| !done[i])
+            .min()
+            .unwrap_or(current);
+    }
+
+    // The walk went backwards through prerequisites; report the cycle forwards
+    let mut cycle: Vec<String> = path[seen[&current]..]
+        .iter()
+        .rev()
+        .map(|&j| calls[j].name.clone())
+        .collect();
+    if let Some(first) = cycle.first().cloned() {
+        cycle.push(first);
+    }
+    cycle
+}
+
 /// Decision about parallel execution
 #[derive(Debug, Clone)]
 pub enum ParallelExecutionDecision {
@@ -1,11 +1,25 @@
 pub mod agent;
+pub mod audit;
+pub mod backend;
 pub mod chat_template;
+pub mod checkpoint;
+pub mod chunking;
+pub mod clock;
+pub mod config;
+pub mod context_pool;
 pub mod dependency_analysis;
 pub mod mcp;
 pub mod model;
+pub mod openai_format;
+pub mod postprocess;
+pub mod preflight;
 pub mod queue;
+pub mod rate_limit;
+pub mod redaction;
 pub mod session;
+pub mod sink;
 pub mod stopper;
+pub mod test_support;
 pub mod types;
 pub mod validation;
 
@@ -15,11 +29,32 @@ pub use types::*;
 // Re-export main agent functionality
 pub use agent::AgentServer;
 
+// Re-export session checkpoints
+pub use checkpoint::{ModelIdentity, RestoredSession, SessionCheckpoint};
+
+// Re-export the clock abstraction
+pub use clock::{Clock, SystemClock};
+
+// Re-export configuration loading
+pub use config::ConfigFormat;
+
 // Re-export MCP functionality
-pub use mcp::{HealthStatus as MCPHealthStatus, MCPClient, MCPServer, RetryConfig};
+pub use mcp::{
+    HealthStatus as MCPHealthStatus, ListChanges as MCPListChanges, MCPClient, MCPServer,
+    ProcessServerFactory, ProgressHandler, RetryConfig, ServerFactory, ToolCaller,
+};
+
+// Re-export log redaction
+pub use redaction::RedactionPolicy;
+
+// Re-export response post-processing
+pub use postprocess::{PostProcessing, PostProcessor};
+
+// Re-export push-based generation consumers
+pub use sink::{ChannelSink, GenerationSink, SinkGeneration};
 
 // Re-export validation functionality
 pub use validation::{ValidationError, Validator};
 
 // Re-export stopper functionality
-pub use stopper::{EosStopper, MaxTokensStopper, RepetitionStopper, Stopper};
+pub use stopper::{EosStopper, MaxTokensStopper, RepetitionStopper, Stopper, StopperFactory};
@@ -1,1991 +0,0 @@
-use crate::types::{
-    GetPromptResult, MCPError, MCPServerConfig, PromptArgument, PromptContent, PromptDefinition,
-    PromptMessage, PromptResource, PromptRole, ToolCall, ToolDefinition, ToolResult,
-};
-use async_trait::async_trait;
-use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::process::Stdio;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{ChildStdin, ChildStdout};
-use tokio::sync::{Mutex, RwLock};
-use tokio::time::timeout;
-use tracing::{debug, error, info, warn};
-
-// Type alias to reduce complexity
-type ServerMap = Arc<RwLock<HashMap<String, Arc<Mutex<Box<dyn MCPServer>>>>>>;
-
-#[derive(Debug, Clone)]
-pub enum HealthStatus {
-    Healthy,
-    Unhealthy(String),
-    Unknown,
-}
-
-#[async_trait]
-pub trait MCPServer: Send + Sync {
-    async fn initialize(&mut self) -> Result<(), MCPError>;
-    async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError>;
-    async fn call_tool(&mut self, tool_name: &str, args: Value) -> Result<Value, MCPError>;
-    async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError>;
-    async fn get_prompt(
-        &mut self,
-        prompt_name: &str,
-        arguments: Option<Value>,
-    ) -> Result<GetPromptResult, MCPError>;
-    async fn health(&self) -> Result<HealthStatus, MCPError>;
-    async fn shutdown(&mut self) -> Result<(), MCPError>;
-    async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError>;
-    async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError>;
-    fn name(&self) -> &str;
-}
-
-struct MCPServerImpl {
-    config: MCPServerConfig,
-    process: Option<tokio::process::Child>,
-    stdin: Option<ChildStdin>,
-    stdout: Option<BufReader<ChildStdout>>,
-    request_id_counter: u64,
-    last_health_check: Option<SystemTime>,
-    initialized: bool,
-}
-
-impl MCPServerImpl {
-    pub fn new(config: MCPServerConfig) -> Self {
-        Self {
-            config,
-            process: None,
-            stdin: None,
-            stdout: None,
-            request_id_counter: 0,
-            last_health_check: None,
-            initialized: false,
-        }
-    }
-
-    async fn spawn_process(&mut self) -> Result<tokio::process::Child, MCPError> {
-        debug!(
-            "Spawning MCP server process: {} {:?}",
-            self.config.command, self.config.args
-        );
-
-        let mut cmd = tokio::process::Command::new(&self.config.command);
-        cmd.args(&self.config.args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        let process = cmd.spawn().map_err(|e| {
-            MCPError::Connection(format!(
-                "Failed to spawn MCP server '{}': {}",
-                self.config.name, e
-            ))
-        })?;
-
-        info!(
-            "Spawned MCP server '{}' with PID: {:?}",
-            self.config.name,
-            process.id()
-        );
-        Ok(process)
-    }
-
-    fn next_request_id(&mut self) -> u64 {
-        self.request_id_counter += 1;
-        self.request_id_counter
-    }
-
-    async fn send_request(&mut self, method: &str, params: Value) -> Result<Value, MCPError> {
-        let request_id = self.next_request_id();
-
-        let stdin = self
-            .stdin
-            .as_mut()
-            .ok_or_else(|| MCPError::Connection("No stdin available".to_string()))?;
-        let request = json!({
-            "jsonrpc": "2.0",
-            "id": request_id,
-            "method": method,
-            "params": params
-        });
-
-        let request_str = serde_json::to_string(&request)
-            .map_err(|e| MCPError::Protocol(format!("Failed to serialize request: {}", e)))?;
-
-        debug!("Sending MCP request: {}", request_str);
-
-        stdin
-            .write_all(request_str.as_bytes())
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to write request: {}", e)))?;
-        stdin
-            .write_all(b"\n")
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to write newline: {}", e)))?;
-        stdin
-            .flush()
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to flush: {}", e)))?;
-
-        self.read_response().await
-    }
-
-    async fn read_response(&mut self) -> Result<Value, MCPError> {
-        let stdout = self
-            .stdout
-            .as_mut()
-            .ok_or_else(|| MCPError::Connection("No stdout available".to_string()))?;
-
-        let mut line = String::new();
-        stdout
-            .read_line(&mut line)
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to read response: {}", e)))?;
-
-        debug!("Received MCP response: {}", line.trim());
-
-        let response: Value = serde_json::from_str(&line)
-            .map_err(|e| MCPError::Protocol(format!("Failed to parse response: {}", e)))?;
-
-        if let Some(error) = response.get("error") {
-            return Err(MCPError::Protocol(format!("MCP server error: {}", error)));
-        }
-
-        response
-            .get("result")
-            .cloned()
-            .ok_or_else(|| MCPError::Protocol("Missing result in response".to_string()))
-    }
-
-    async fn send_initialized_notification(&mut self) -> Result<(), MCPError> {
-        let stdin = self
-            .stdin
-            .as_mut()
-            .ok_or_else(|| MCPError::Connection("No stdin available".to_string()))?;
-
-        let notification = json!({
-            "jsonrpc": "2.0",
-            "method": "notifications/initialized",
-            "params": {}
-        });
-
-        let notification_str = serde_json::to_string(&notification)
-            .map_err(|e| MCPError::Protocol(format!("Failed to serialize notification: {}", e)))?;
-
-        debug!("Sending MCP notification: {}", notification_str);
-
-        stdin
-            .write_all(notification_str.as_bytes())
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to write notification: {}", e)))?;
-        stdin
-            .write_all(b"\n")
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to write newline: {}", e)))?;
-        stdin
-            .flush()
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to flush: {}", e)))?;
-
-        Ok(())
-    }
-
-    async fn send_notification(&mut self, method: &str, params: Value) -> Result<(), MCPError> {
-        let stdin = self
-            .stdin
-            .as_mut()
-            .ok_or_else(|| MCPError::Connection("No stdin available".to_string()))?;
-
-        let notification = json!({
-            "jsonrpc": "2.0",
-            "method": method,
-            "params": params
-        });
-
-        let notification_str = serde_json::to_string(&notification)
-            .map_err(|e| MCPError::Protocol(format!("Failed to serialize notification: {}", e)))?;
-
-        debug!("Sending MCP notification: {}", notification_str);
-
-        stdin
-            .write_all(notification_str.as_bytes())
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to write notification: {}", e)))?;
-        stdin
-            .write_all(b"\n")
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to write newline: {}", e)))?;
-        stdin
-            .flush()
-            .await
-            .map_err(|e| MCPError::Connection(format!("Failed to flush: {}", e)))?;
-
-        Ok(())
-    }
-
-    async fn send_tools_list_changed(&mut self) -> Result<(), MCPError> {
-        debug!("Sending tools list changed notification");
-        self.send_notification("notifications/tools/list_changed", json!({}))
-            .await
-    }
-
-    async fn send_prompts_list_changed(&mut self) -> Result<(), MCPError> {
-        debug!("Sending prompts list changed notification");
-        self.send_notification("notifications/prompts/list_changed", json!({}))
-            .await
-    }
-}
-
-#[async_trait]
-impl MCPServer for MCPServerImpl {
-    async fn initialize(&mut self) -> Result<(), MCPError> {
-        debug!("Initializing MCP server: {}", self.config.name);
-
-        let mut process = self.spawn_process().await?;
-
-        // Get stdio handles from the process
-        let stdin = process
-            .stdin
-            .take()
-            .ok_or_else(|| MCPError::Connection("Failed to get stdin from process".to_string()))?;
-        let stdout = process
-            .stdout
-            .take()
-            .ok_or_else(|| MCPError::Connection("Failed to get stdout from process".to_string()))?;
-
-        self.stdin = Some(stdin);
-        self.stdout = Some(BufReader::new(stdout));
-        self.process = Some(process);
-
-        // Send initialization request
-        let init_params = json!({
-            "protocolVersion": "2024-11-05",
-            "capabilities": {
-                "tools": {
-                    "listChanged": true
-                },
-                "prompts": {
-                    "listChanged": true
-                }
-            },
-            "clientInfo": {
-                "name": "llama-agent",
-                "version": "0.1.0"
-            }
-        });
-
-        let _init_result = self.send_request("initialize", init_params).await?;
-
-        // Send initialized notification
-        self.send_initialized_notification().await?;
-
-        self.initialized = true;
-        self.last_health_check = Some(SystemTime::now());
-
-        info!("Successfully initialized MCP server: {}", self.config.name);
-        Ok(())
-    }
-
-    async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
-        if !self.initialized {
-            return Err(MCPError::Connection(format!(
-                "Server '{}' not initialized",
-                self.config.name
-            )));
-        }
-
-        debug!("Listing tools for MCP server: {}", self.config.name);
-
-        // Send tools/list request to the server
-        let response = self.send_request("tools/list", json!({})).await?;
-
-        // Parse the response to extract tool definitions
-        let tools_array = response
-            .get("tools")
-            .and_then(|t| t.as_array())
-            .ok_or_else(|| {
-                MCPError::Protocol(
-                    "Invalid tools/list response format: missing tools array".to_string(),
-                )
-            })?;
-
-        let mut tool_definitions = Vec::new();
-        for tool_data in tools_array {
-            // Parse the tool name
-            let name = tool_data
-                .get("name")
-                .and_then(|n| n.as_str())
-                .ok_or_else(|| MCPError::Protocol("Tool missing name field".to_string()))?
-                .to_string();
-
-            // Parse the tool description
-            let description = tool_data
-                .get("description")
-                .and_then(|d| d.as_str())
-                .unwrap_or("")
-                .to_string();
-
-            // Parse the input schema (optional)
-            let parameters = tool_data
-                .get("inputSchema")
-                .cloned()
-                .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
-
-            tool_definitions.push(ToolDefinition {
-                name,
-                description,
-                parameters,
-                server_name: self.config.name.clone(),
-            });
-        }
-
-        debug!(
-            "Found {} tools for server '{}'",
-            tool_definitions.len(),
-            self.config.name
-        );
-        Ok(tool_definitions)
-    }
-
-    async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
-        if !self.initialized {
-            return Err(MCPError::Connection(format!(
-                "Server '{}' not initialized",
-                self.config.name
-            )));
-        }
-
-        debug!("Listing prompts for MCP server: {}", self.config.name);
-
-        // Send prompts/list request to the server
-        let response = self.send_request("prompts/list", json!({})).await?;
-
-        // Parse the response to extract prompt definitions
-        let prompts_array = response
-            .get("prompts")
-            .and_then(|p| p.as_array())
-            .ok_or_else(|| {
-                MCPError::Protocol("Invalid prompts/list response format".to_string())
-            })?;
-
-        let mut prompt_definitions = Vec::new();
-        for prompt_data in prompts_array {
-            let name = prompt_data
-                .get("name")
-                .and_then(|n| n.as_str())
-                .ok_or_else(|| MCPError::Protocol("Prompt missing name field".to_string()))?
-                .to_string();
-
-            let description = prompt_data
-                .get("description")
-                .and_then(|d| d.as_str())
-                .map(|s| s.to_string());
-
-            let mut arguments = Vec::new();
-            if let Some(args_array) = prompt_data.get("arguments").and_then(|a| a.as_array()) {
-                for arg_data in args_array {
-                    let arg_name = arg_data
-                        .get("name")
-                        .and_then(|n| n.as_str())
-                        .ok_or_else(|| {
-                            MCPError::Protocol("Prompt argument missing name".to_string())
-                        })?
-                        .to_string();
-
-                    let arg_description = arg_data
-                        .get("description")
-                        .and_then(|d| d.as_str())
-                        .map(|s| s.to_string());
-
-                    let required = arg_data
-                        .get("required")
-                        .and_then(|r| r.as_bool())
-                        .unwrap_or(false);
-
-                    arguments.push(PromptArgument {
-                        name: arg_name,
-                        description: arg_description,
-                        required: Some(required),
-                    });
-                }
-            }
-
-            prompt_definitions.push(PromptDefinition {
-                name,
-                title: None,
-                description,
-                arguments: Some(arguments),
-                server_name: self.config.name.clone(),
-            });
-        }
-
-        debug!(
-            "Found {} prompts for server '{}'",
-            prompt_definitions.len(),
-            self.config.name
-        );
-        Ok(prompt_definitions)
-    }
-
-    async fn get_prompt(
-        &mut self,
-        prompt_name: &str,
-        arguments: Option<Value>,
-    ) -> Result<GetPromptResult, MCPError> {
-        if !self.initialized {
-            return Err(MCPError::Connection(format!(
-                "Server '{}' not initialized",
-                self.config.name
-            )));
-        }
-
-        debug!(
-            "Getting prompt '{}' from server '{}' with arguments: {:?}",
-            prompt_name, self.config.name, arguments
-        );
-
-        // Build the prompts/get request parameters
-        let mut params = json!({
-            "name": prompt_name
-        });
-
-        if let Some(args) = arguments {
-            params["arguments"] = args;
-        }
-
-        // Send prompts/get request to the server
-        let response = self.send_request("prompts/get", params).await?;
-
-        // Parse the description from the response
-        let description = response
-            .get("description")
-            .and_then(|d| d.as_str())
-            .map(|s| s.to_string());
-
-        // Parse the messages array from the response
-        let messages_array = response
-            .get("messages")
-            .and_then(|m| m.as_array())
-            .ok_or_else(|| {
-                MCPError::Protocol(
-                    "Invalid prompts/get response format: missing messages".to_string(),
-                )
-            })?;
-
-        let mut messages = Vec::new();
-        for message_data in messages_array {
-            // Parse the role
-            let role_str = message_data
-                .get("role")
-                .and_then(|r| r.as_str())
-                .ok_or_else(|| MCPError::Protocol("Message missing role field".to_string()))?;
-
-            let role = match role_str {
-                "user" => PromptRole::User,
-                "assistant" => PromptRole::Assistant,
-                _ => {
-                    return Err(MCPError::Protocol(format!(
-                        "Unknown prompt role: {} (only 'user' and 'assistant' are supported)",
-                        role_str
-                    )))
-                }
-            };
-
-            // Parse the content
-            let content_data = message_data
-                .get("content")
-                .ok_or_else(|| MCPError::Protocol("Message missing content field".to_string()))?;
-
-            let content = if let Some(content_type) =
-                content_data.get("type").and_then(|t| t.as_str())
-            {
-                match content_type {
-                    "text" => {
-                        let text = content_data
-                            .get("text")
-                            .and_then(|t| t.as_str())
-                            .ok_or_else(|| {
-                                MCPError::Protocol("Text content missing text field".to_string())
-                            })?
-                            .to_string();
-                        PromptContent::Text { text }
-                    }
-                    "image" => {
-                        let data = content_data
-                            .get("data")
-                            .and_then(|d| d.as_str())
-                            .ok_or_else(|| {
-                                MCPError::Protocol("Image content missing data field".to_string())
-                            })?
-                            .to_string();
-                        let mime_type = content_data
-                            .get("mimeType")
-                            .and_then(|m| m.as_str())
-                            .ok_or_else(|| {
-                                MCPError::Protocol(
-                                    "Image content missing mimeType field".to_string(),
-                                )
-                            })?
-                            .to_string();
-                        PromptContent::Image { data, mime_type }
-                    }
-                    "resource" => {
-                        let resource_data = content_data.get("resource").ok_or_else(|| {
-                            MCPError::Protocol(
-                                "Resource content missing resource field".to_string(),
-                            )
-                        })?;
-
-                        let uri = resource_data
-                            .get("uri")
-                            .and_then(|u| u.as_str())
-                            .ok_or_else(|| {
-                                MCPError::Protocol("Resource missing uri field".to_string())
-                            })?
-                            .to_string();
-
-                        let text = resource_data
-                            .get("text")
-                            .and_then(|t| t.as_str())
-                            .map(|s| s.to_string());
-
-                        let mime_type = resource_data
-                            .get("mimeType")
-                            .and_then(|m| m.as_str())
-                            .map(|s| s.to_string());
-
-                        PromptContent::Resource {
-                            resource: PromptResource {
-                                uri: uri.clone(),
-                                name: uri.split('/').next_back().unwrap_or(&uri).to_string(),
-                                title: None,
-                                mime_type: mime_type.unwrap_or_default(),
-                                text,
-                            },
-                        }
-                    }
-                    _ => {
-                        return Err(MCPError::Protocol(format!(
-                            "Unknown content type: {}",
-                            content_type
-                        )))
-                    }
-                }
-            } else {
-                // Handle legacy format where content might be just a string
-                let text = content_data
-                    .as_str()
-                    .ok_or_else(|| {
-                        MCPError::Protocol(
-                            "Content must be either object with type or string".to_string(),
-                        )
-                    })?
-                    .to_string();
-                PromptContent::Text { text }
-            };
-
-            messages.push(PromptMessage { role, content });
-        }
-
-        let result = GetPromptResult {
-            description,
-            messages,
-        };
-
-        debug!(
-            "Retrieved prompt '{}' from server '{}' successfully with {} messages",
-            prompt_name,
-            self.config.name,
-            result.messages.len()
-        );
-
-        Ok(result)
-    }
-
-    async fn call_tool(&mut self, tool_name: &str, args: Value) -> Result<Value, MCPError> {
-        if !self.initialized {
-            return Err(MCPError::Connection(format!(
-                "Server '{}' not initialized",
-                self.config.name
-            )));
-        }
-
-        debug!(
-            "Calling tool '{}' on server '{}'",
-            tool_name, self.config.name
-        );
-
-        // Send actual MCP tool call request
-        let params = json!({
-            "name": tool_name,
-            "arguments": args
-        });
-
-        let result = self.send_request("tools/call", params).await?;
-
-        debug!(
-            "Tool '{}' on server '{}' completed successfully",
-            tool_name, self.config.name
-        );
-
-        Ok(result)
-    }
-
-    async fn health(&self) -> Result<HealthStatus, MCPError> {
-        if !self.initialized {
-            return Ok(HealthStatus::Unhealthy("Not initialized".to_string()));
-        }
-
-        // Check if process is still running
-        if let Some(process) = self.process.as_ref() {
-            // For tokio::process::Child, we need to use a different approach
-            // Check if process is still alive by trying to get its id
-            match process.id() {
-                Some(_pid) => {
-                    debug!("Process is still running for server: {}", self.config.name);
-                    Ok(HealthStatus::Healthy)
-                }
-                None => Ok(HealthStatus::Unhealthy("Process has exited".to_string())),
-            }
-        } else {
-            Ok(HealthStatus::Unhealthy("Process not found".to_string()))
-        }
-    }
-
-    async fn shutdown(&mut self) -> Result<(), MCPError> {
-        info!("Shutting down MCP server: {}", self.config.name);
-
-        // Close stdin/stdout first
-        self.stdin.take();
-        self.stdout.take();
-
-        // Terminate process
-        if let Some(mut process) = self.process.take() {
-            match process.kill().await {
-                Ok(_) => {
-                    // Wait for process to exit
-                    match timeout(Duration::from_secs(5), process.wait()).await {
-                        Ok(Ok(status)) => {
-                            info!(
-                                "MCP server '{}' shut down with status: {:?}",
-                                self.config.name, status
-                            );
-                        }
-                        Ok(Err(e)) => {
-                            warn!(
-                                "Error waiting for MCP server '{}' shutdown: {}",
-                                self.config.name, e
-                            );
-                        }
-                        Err(_) => {
-                            warn!(
-                                "Timeout waiting for MCP server '{}' shutdown",
-                                self.config.name
-                            );
-                        }
-                    }
-                }
-                Err(e) => {
-                    warn!(
-                        "Failed to kill MCP server '{}' process: {}",
-                        self.config.name, e
-                    );
-                }
-            }
-        }
-
-        self.initialized = false;
-        Ok(())
-    }
-
-    async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
-        if !self.initialized {
-            return Err(MCPError::Connection(format!(
-                "Server '{}' not initialized",
-                self.config.name
-            )));
-        }
-
-        debug!(
-            "Notifying tools list changed for server: {}",
-            self.config.name
-        );
-        self.send_tools_list_changed().await
-    }
-
-    async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
-        if !self.initialized {
-            return Err(MCPError::Connection(format!(
-                "Server '{}' not initialized",
-                self.config.name
-            )));
-        }
-
-        debug!(
-            "Notifying prompts list changed for server: {}",
-            self.config.name
-        );
-        self.send_prompts_list_changed().await
-    }
-
-    fn name(&self) -> &str {
-        &self.config.name
-    }
-}
-
-pub struct MCPClient {
-    servers: ServerMap,
-    retry_config: RetryConfig,
-    tool_to_server_cache: Arc<RwLock<HashMap<String, String>>>,
-    previous_tools_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
-    prompt_to_server_cache: Arc<RwLock<HashMap<String, String>>>,
-    previous_prompts_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
-}
-
-#[derive(Debug, Clone)]
-pub struct RetryConfig {
-    pub max_retries: u32,
-    pub initial_delay: Duration,
-    pub max_delay: Duration,
-    pub backoff_multiplier: f64,
-}
-
-impl Default for RetryConfig {
-    fn default() -> Self {
-        Self {
-            max_retries: 3,
-            initial_delay: Duration::from_millis(100),
-            max_delay: Duration::from_secs(5),
-            backoff_multiplier: 2.0,
-        }
-    }
-}
-
-impl MCPClient {
-    pub fn new() -> Self {
-        Self {
-            servers: Arc::new(RwLock::new(HashMap::new())),
-            retry_config: RetryConfig::default(),
-            tool_to_server_cache: Arc::new(RwLock::new(HashMap::new())),
-            previous_tools_cache: Arc::new(RwLock::new(HashMap::new())),
-            prompt_to_server_cache: Arc::new(RwLock::new(HashMap::new())),
-            previous_prompts_cache: Arc::new(RwLock::new(HashMap::new())),
-        }
-    }
-
-    pub fn with_retry_config(retry_config: RetryConfig) -> Self {
-        Self {
-            servers: Arc::new(RwLock::new(HashMap::new())),
-            retry_config,
-            tool_to_server_cache: Arc::new(RwLock::new(HashMap::new())),
-            previous_tools_cache: Arc::new(RwLock::new(HashMap::new())),
-            prompt_to_server_cache: Arc::new(RwLock::new(HashMap::new())),
-            previous_prompts_cache: Arc::new(RwLock::new(HashMap::new())),
-        }
-    }
-
-    pub async fn initialize(configs: Vec<MCPServerConfig>) -> Result<Self, MCPError> {
-        let client = Self::new();
-
-        for config in configs {
-            config.validate()?;
-            client.add_server(config).await?;
-        }
-
-        Ok(client)
-    }
-
-    pub async fn add_server(&self, config: MCPServerConfig) -> Result<(), MCPError> {
-        let server_name = config.name.clone();
-        let mut server: Box<dyn MCPServer> = Box::new(MCPServerImpl::new(config));
-
-        info!("Adding MCP server: {}", server_name);
-
-        // Initialize server with retries
-        let mut delay = self.retry_config.initial_delay;
-        let mut last_error = None;
-
-        for attempt in 0..=self.retry_config.max_retries {
-            match server.initialize().await {
-                Ok(_) => break,
-                Err(e) => {
-                    last_error = Some(e);
-
-                    if attempt < self.retry_config.max_retries {
-                        debug!(
-                            "Retry attempt {} failed, waiting {:?} before next attempt",
-                            attempt + 1,
-                            delay
-                        );
-                        tokio::time::sleep(delay).await;
-
-                        // Exponential backoff
-                        delay = std::cmp::min(
-                            self.retry_config.max_delay,
-                            Duration::from_millis(
-                                (delay.as_millis() as f64 * self.retry_config.backoff_multiplier)
-                                    as u64,
-                            ),
-                        );
-                    }
-                }
-            }
-        }
-
-        if let Some(e) = last_error {
-            error!(
-                "Failed to initialize MCP server '{}' after retries: {}",
-                server_name, e
-            );
-            return Err(e);
-        }
-
-        // Store the initialized server
-        let mut servers = self.servers.write().await;
-        servers.insert(server_name.clone(), Arc::new(Mutex::new(server)));
-
-        info!("Successfully added MCP server: {}", server_name);
-        Ok(())
-    }
-
-    pub async fn remove_server(&self, server_name: &str) -> Result<(), MCPError> {
-        info!("Removing MCP server: {}", server_name);
-
-        let mut servers = self.servers.write().await;
-        if let Some(server_arc) = servers.remove(server_name) {
-            let mut server = server_arc.lock().await;
-            server.shutdown().await?;
-
-            // Clear cache entries for this server
-            let mut cache = self.tool_to_server_cache.write().await;
-            cache.retain(|_tool, server| server != server_name);
-            drop(cache);
-
-            // Clear previous tools cache for this server
-            let mut previous_cache = self.previous_tools_cache.write().await;
-            previous_cache.remove(server_name);
-            drop(previous_cache);
-
-            // Clear prompt cache entries for this server
-            let mut prompt_cache = self.prompt_to_server_cache.write().await;
-            prompt_cache.retain(|_prompt, server| server != server_name);
-            drop(prompt_cache);
-
-            // Clear previous prompts cache for this server
-            let mut previous_prompts_cache = self.previous_prompts_cache.write().await;
-            previous_prompts_cache.remove(server_name);
-            drop(previous_prompts_cache);
-
-            info!("Successfully removed MCP server: {}", server_name);
-        } else {
-            warn!(
-                "Attempted to remove non-existent MCP server: {}",
-                server_name
-            );
-        }
-
-        Ok(())
-    }
-
-    pub async fn discover_tools(&self) -> Result<Vec<ToolDefinition>, MCPError> {
-        debug!("Discovering tools from all MCP servers");
-
-        let servers = self.servers.read().await;
-        let mut all_tools = Vec::new();
-        let mut errors = Vec::new();
-        let mut cache_updates = HashMap::new();
-        let mut current_tools_by_server = HashMap::new();
-
-        for (server_name, server_arc) in servers.iter() {
-            let mut server = server_arc.lock().await;
-
-            match server.list_tools().await {
-                Ok(mut tools) => {
-                    debug!("Found {} tools from server '{}'", tools.len(), server_name);
-
-                    // Track current tools for this server
-                    let tool_names: Vec<String> = tools.iter().map(|t| t.name.clone()).collect();
-                    current_tools_by_server.insert(server_name.clone(), tool_names);
-
-                    // Update cache mapping for each tool
-                    for tool in &tools {
-                        cache_updates.insert(tool.name.clone(), server_name.clone());
-                    }
-
-                    all_tools.append(&mut tools);
-                }
-                Err(e) => {
-                    warn!(
-                        "Failed to discover tools from server '{}': {}",
-                        server_name, e
-                    );
-                    errors.push(format!("Server '{}': {}", server_name, e));
-                }
-            }
-        }
-        drop(servers);
-
-        // Check for changes and send notifications
-        let mut previous_tools_cache = self.previous_tools_cache.write().await;
-        let mut servers_with_changes = Vec::new();
-
-        for (server_name, current_tools) in &current_tools_by_server {
-            let previous_tools = previous_tools_cache.get(server_name);
-
-            let tools_changed = match previous_tools {
-                Some(prev) => prev != current_tools,
-                None => !current_tools.is_empty(), // First time seeing this server with tools
-            };
-
-            if tools_changed {
-                debug!(
-                    "Tools changed for server '{}': {:?} -> {:?}",
-                    server_name, previous_tools, current_tools
-                );
-                servers_with_changes.push(server_name.clone());
-            }
-        }
-
-        // Update the previous tools cache
-        previous_tools_cache.clear();
-        previous_tools_cache.extend(current_tools_by_server);
-        drop(previous_tools_cache);
-
-        // Send notifications for servers with changes
-        if !servers_with_changes.is_empty() {
-            let servers = self.servers.read().await;
-            for server_name in servers_with_changes {
-                if let Some(server_arc) = servers.get(&server_name) {
-                    let mut server = server_arc.lock().await;
-                    if let Err(e) = server.notify_tools_list_changed().await {
-                        warn!(
-                            "Failed to send tools list changed notification for server '{}': {}",
-                            server_name, e
-                        );
-                    } else {
-                        info!(
-                            "Sent tools list changed notification for server '{}'",
-                            server_name
-                        );
-                    }
-                }
-            }
-        }
-
-        // Update the tool-to-server cache
-        let mut cache = self.tool_to_server_cache.write().await;
-        cache.clear();
-        cache.extend(cache_updates);
-        drop(cache);
-
-        if all_tools.is_empty() && !errors.is_empty() {
-            return Err(MCPError::Connection(format!(
-                "Failed to discover tools from any server. Errors: {}",
-                errors.join("; ")
-            )));
-        }
-
-        if !errors.is_empty() {
-            warn!(
-                "Some servers failed during tool discovery: {}",
-                errors.join("; ")
-            );
-        }
-
-        let servers = self.servers.read().await;
-        info!(
-            "Discovered {} tools from {} servers",
-            all_tools.len(),
-            servers.len()
-        );
-        Ok(all_tools)
-    }
-
-    pub async fn discover_prompts(&self) -> Result<Vec<PromptDefinition>, MCPError> {
-        debug!("Discovering prompts from all MCP servers");
-
-        let servers = self.servers.read().await;
-        let mut all_prompts = Vec::new();
-        let mut errors = Vec::new();
-        let mut cache_updates = HashMap::new();
-        let mut current_prompts_by_server = HashMap::new();
-
-        for (server_name, server_arc) in servers.iter() {
-            let mut server = server_arc.lock().await;
-
-            match server.list_prompts().await {
-                Ok(mut prompts) => {
-                    debug!(
-                        "Found {} prompts from server '{}'",
-                        prompts.len(),
-                        server_name
-                    );
-
-                    // Track current prompts for this server
-                    let prompt_names: Vec<String> =
-                        prompts.iter().map(|p| p.name.clone()).collect();
-                    current_prompts_by_server.insert(server_name.clone(), prompt_names);
-
-                    // Update cache mapping for each prompt
-                    for prompt in &prompts {
-                        cache_updates.insert(prompt.name.clone(), server_name.clone());
-                    }
-
-                    all_prompts.append(&mut prompts);
-                }
-                Err(e) => {
-                    warn!(
-                        "Failed to discover prompts from server '{}': {}",
-                        server_name, e
-                    );
-                    errors.push(format!("Server '{}': {}", server_name, e));
-                }
-            }
-        }
-        drop(servers);
-
-        // Check for changes and send notifications
-        let mut previous_prompts_cache = self.previous_prompts_cache.write().await;
-        let mut servers_with_changes = Vec::new();
-
-        for (server_name, current_prompts) in &current_prompts_by_server {
-            let previous_prompts = previous_prompts_cache.get(server_name as &str);
-
-            let prompts_changed = match previous_prompts {
-                Some(prev) => prev != current_prompts,
-                None => !current_prompts.is_empty(), // First time seeing this server with prompts
-            };
-
-            if prompts_changed {
-                debug!(
-                    "Prompts changed for server '{}': {:?} -> {:?}",
-                    server_name, previous_prompts, current_prompts
-                );
-                servers_with_changes.push(server_name.clone());
-            }
-        }
-
-        // Update the previous prompts cache
-        previous_prompts_cache.clear();
-        previous_prompts_cache.extend(current_prompts_by_server);
-        drop(previous_prompts_cache);
-
-        // Send notifications for servers with changes
-        if !servers_with_changes.is_empty() {
-            let servers = self.servers.read().await;
-            for server_name in servers_with_changes {
-                if let Some(server_arc) = servers.get(&server_name) {
-                    let mut server = server_arc.lock().await;
-                    if let Err(e) = server.notify_prompts_list_changed().await {
-                        warn!(
-                            "Failed to send prompts list changed notification for server '{}': {}",
-                            server_name, e
-                        );
-                    } else {
-                        info!(
-                            "Sent prompts list changed notification for server '{}'",
-                            server_name
-                        );
-                    }
-                }
-            }
-        }
-
-        // Update the prompt-to-server cache
-        let mut cache = self.prompt_to_server_cache.write().await;
-        cache.clear();
-        cache.extend(cache_updates);
-        drop(cache);
-
-        if all_prompts.is_empty() && !errors.is_empty() {
-            return Err(MCPError::Connection(format!(
-                "Failed to discover prompts from any server. Errors: {}",
-                errors.join("; ")
-            )));
-        }
-
-        if !errors.is_empty() {
-            warn!(
-                "Some servers failed during prompt discovery: {}",
-                errors.join("; ")
-            );
-        }
-
-        let servers = self.servers.read().await;
-        info!(
-            "Discovered {} prompts from {} servers",
-            all_prompts.len(),
-            servers.len()
-        );
-        Ok(all_prompts)
-    }
-
-    pub async fn get_prompt(
-        &self,
-        server_name: &str,
-        prompt_name: &str,
-        arguments: Option<Value>,
-    ) -> Result<GetPromptResult, MCPError> {
-        debug!(
-            "Getting prompt '{}' from server '{}' with arguments: {:?}",
-            prompt_name, server_name, arguments
-        );
-
-        let servers = self.servers.read().await;
-        let server_arc = servers
-            .get(server_name)
-            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?;
-
-        let mut server = server_arc.lock().await;
-
-        // Execute the prompt get
-        let result = server.get_prompt(prompt_name, arguments).await?;
-
-        info!(
-            "Successfully got prompt '{}' from server '{}'",
-            prompt_name, server_name
-        );
-        Ok(result)
-    }
-
-    pub async fn execute_prompt(
-        &self,
-        prompt_name: &str,
-        arguments: Option<Value>,
-    ) -> Result<GetPromptResult, MCPError> {
-        debug!(
-            "Executing prompt '{}' with arguments: {:?}",
-            prompt_name, arguments
-        );
-
-        // Check cache first for the server that has this prompt
-        let cache = self.prompt_to_server_cache.read().await;
-        let server_name = cache.get(prompt_name).cloned();
-        drop(cache);
-
-        let server_name = match server_name {
-            Some(name) => {
-                debug!(
-                    "Found prompt '{}' in cache for server '{}'",
-                    prompt_name, name
-                );
-                name
-            }
-            None => {
-                // Cache miss - need to rediscover prompts
-                warn!(
-                    "Prompt '{}' not found in cache, refreshing prompt discovery",
-                    prompt_name
-                );
-                self.discover_prompts().await?;
-
-                // Try cache again
-                let cache = self.prompt_to_server_cache.read().await;
-                let server_name = cache.get(prompt_name).cloned();
-                drop(cache);
-
-                server_name.ok_or_else(|| {
-                    MCPError::Protocol(format!(
-                        "Prompt '{}' not found in any connected server after refresh",
-                        prompt_name
-                    ))
-                })?
-            }
-        };
-
-        // Execute the prompt get
-        self.get_prompt(&server_name, prompt_name, arguments).await
-    }
-
-    pub async fn list_servers(&self) -> Vec<String> {
-        let servers = self.servers.read().await;
-        servers.keys().cloned().collect()
-    }
-
-    pub async fn server_count(&self) -> usize {
-        let servers = self.servers.read().await;
-        servers.len()
-    }
-
-    pub async fn call_tool(
-        &self,
-        server_name: &str,
-        tool_name: &str,
-        args: Value,
-    ) -> Result<Value, MCPError> {
-        debug!("Calling tool '{}' on server '{}'", tool_name, server_name);
-
-        let servers = self.servers.read().await;
-        let server_arc = servers
-            .get(server_name)
-            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?;
-
-        let mut server = server_arc.lock().await;
-
-        // Execute the tool call
-        let result = server.call_tool(tool_name, args).await?;
-
-        info!(
-            "Successfully called tool '{}' on server '{}'",
-            tool_name, server_name
-        );
-        Ok(result)
-    }
-
-    pub async fn execute_tool_call(&self, tool_call: &ToolCall) -> Result<ToolResult, MCPError> {
-        debug!(
-            "Executing tool call: {} (ID: {})",
-            tool_call.name, tool_call.id
-        );
-
-        // Check cache first for the server that has this tool
-        let cache = self.tool_to_server_cache.read().await;
-        let server_name = cache.get(&tool_call.name).cloned();
-        drop(cache);
-
-        let server_name = match server_name {
-            Some(name) => {
-                debug!(
-                    "Found tool '{}' in cache for server '{}'",
-                    tool_call.name, name
-                );
-                name
-            }
-            None => {
-                // Cache miss - need to rediscover tools
-                warn!(
-                    "Tool '{}' not found in cache, refreshing tool discovery",
-                    tool_call.name
-                );
-                self.discover_tools().await?;
-
-                // Try cache again
-                let cache = self.tool_to_server_cache.read().await;
-                let server_name = cache.get(&tool_call.name).cloned();
-                drop(cache);
-
-                server_name.ok_or_else(|| {
-                    MCPError::ToolCallFailed(format!(
-                        "Tool '{}' not found in any connected server after refresh",
-                        tool_call.name
-                    ))
-                })?
-            }
-        };
-
-        // Execute the tool call
-        match self
-            .call_tool(&server_name, &tool_call.name, tool_call.arguments.clone())
-            .await
-        {
-            Ok(result) => Ok(ToolResult {
-                call_id: tool_call.id,
-                result,
-                error: None,
-            }),
-            Err(e) => Ok(ToolResult {
-                call_id: tool_call.id,
-                result: Value::Null,
-                error: Some(format!("Tool execution failed: {}", e)),
-            }),
-        }
-    }
-
-    pub async fn server_health(&self, server_name: &str) -> Result<HealthStatus, MCPError> {
-        debug!("Checking health for server: {}", server_name);
-
-        let servers = self.servers.read().await;
-        let server_arc = servers
-            .get(server_name)
-            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?;
-
-        let server = server_arc.lock().await;
-        server.health().await
-    }
-
-    pub async fn health_check_all(&self) -> HashMap<String, HealthStatus> {
-        debug!("Performing health check on all servers");
-
-        let servers = self.servers.read().await;
-        let mut health_results = HashMap::new();
-
-        for (server_name, server_arc) in servers.iter() {
-            let server = server_arc.lock().await;
-
-            match server.health().await {
-                Ok(status) => {
-                    health_results.insert(server_name.clone(), status);
-                }
-                Err(e) => {
-                    warn!("Health check failed for server '{}': {}", server_name, e);
-                    health_results.insert(
-                        server_name.clone(),
-                        HealthStatus::Unhealthy(format!("Health check error: {}", e)),
-                    );
-                }
-            }
-        }
-
-        info!(
-            "Health check completed for {} servers",
-            health_results.len()
-        );
-        health_results
-    }
-
-    pub async fn restart_server(&self, server_name: &str) -> Result<(), MCPError> {
-        info!("Restarting MCP server: {}", server_name);
-
-        let servers = self.servers.read().await;
-        let server_arc = servers
-            .get(server_name)
-            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?
-            .clone();
-
-        drop(servers); // Release read lock
-
-        let mut server = server_arc.lock().await;
-
-        // Shutdown existing server
-        if let Err(e) = server.shutdown().await {
-            warn!("Error during server shutdown for '{}': {}", server_name, e);
-        }
-
-        // Re-initialize server with retries
-        let mut delay = self.retry_config.initial_delay;
-        let mut last_error = None;
-
-        for attempt in 0..=self.retry_config.max_retries {
-            match server.initialize().await {
-                Ok(_) => break,
-                Err(e) => {
-                    last_error = Some(e);
-
-                    if attempt < self.retry_config.max_retries {
-                        debug!(
-                            "Retry attempt {} failed, waiting {:?} before next attempt",
-                            attempt + 1,
-                            delay
-                        );
-                        tokio::time::sleep(delay).await;
-
-                        // Exponential backoff
-                        delay = std::cmp::min(
-                            self.retry_config.max_delay,
-                            Duration::from_millis(
-                                (delay.as_millis() as f64 * self.retry_config.backoff_multiplier)
-                                    as u64,
-                            ),
-                        );
-                    }
-                }
-            }
-        }
-
-        if let Some(e) = last_error {
-            error!(
-                "Failed to restart MCP server '{}' after retries: {}",
-                server_name, e
-            );
-            return Err(e);
-        }
-
-        info!("Successfully restarted MCP server: {}", server_name);
-        Ok(())
-    }
-
-    pub async fn shutdown_all(&self) -> Result<(), MCPError> {
-        info!("Shutting down all MCP servers");
-
-        let mut servers = self.servers.write().await;
-        let mut errors = Vec::new();
-
-        for (server_name, server_arc) in servers.drain() {
-            let mut server = server_arc.lock().await;
-
-            if let Err(e) = server.shutdown().await {
-                errors.push(format!("Server '{}': {}", server_name, e));
-            } else {
-                info!("Successfully shut down server: {}", server_name);
-            }
-        }
-
-        if !errors.is_empty() {
-            return Err(MCPError::Connection(format!(
-                "Errors during shutdown: {}",
-                errors.join("; ")
-            )));
-        }
-
-        info!("All MCP servers shut down successfully");
-        Ok(())
-    }
-}
-
-impl Default for MCPClient {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::types::{PromptArgument, ToolCallId};
-    use serde_json::json;
-    use tokio::time::Duration;
-
-    // Mock server implementation for testing
-    struct MockMCPServer {
-        name: String,
-        tools: Vec<ToolDefinition>,
-        prompts: Vec<PromptDefinition>,
-        should_fail: bool,
-        fail_on_health: bool,
-    }
-
-    impl MockMCPServer {
-        fn new(name: &str, tools: Vec<ToolDefinition>) -> Self {
-            Self {
-                name: name.to_string(),
-                tools,
-                prompts: Vec::new(),
-                should_fail: false,
-                fail_on_health: false,
-            }
-        }
-
-        fn with_prompts(mut self, prompts: Vec<PromptDefinition>) -> Self {
-            self.prompts = prompts;
-            self
-        }
-
-        fn with_failure(mut self, should_fail: bool) -> Self {
-            self.should_fail = should_fail;
-            self
-        }
-
-        #[allow(dead_code)]
-        fn with_health_failure(mut self, fail_on_health: bool) -> Self {
-            self.fail_on_health = fail_on_health;
-            self
-        }
-    }
-
-    #[async_trait]
-    impl MCPServer for MockMCPServer {
-        async fn initialize(&mut self) -> Result<(), MCPError> {
-            if self.should_fail {
-                return Err(MCPError::Connection(
-                    "Mock initialization failure".to_string(),
-                ));
-            }
-            Ok(())
-        }
-
-        async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
-            if self.should_fail {
-                return Err(MCPError::Protocol("Mock tool listing failure".to_string()));
-            }
-            Ok(self.tools.clone())
-        }
-
-        async fn call_tool(&mut self, tool_name: &str, args: Value) -> Result<Value, MCPError> {
-            if self.should_fail {
-                return Err(MCPError::ToolCallFailed(format!(
-                    "Mock tool call failure for {}",
-                    tool_name
-                )));
-            }
-
-            // Simulate successful tool execution
-            if self.tools.iter().any(|tool| tool.name == tool_name) {
-                Ok(json!({
-                    "result": format!("Mock result for {} with args: {}", tool_name, args),
-                    "success": true
-                }))
-            } else {
-                Err(MCPError::ToolCallFailed(format!(
-                    "Tool '{}' not found",
-                    tool_name
-                )))
-            }
-        }
-
-        async fn health(&self) -> Result<HealthStatus, MCPError> {
-            if self.fail_on_health {
-                Err(MCPError::Connection("Health check failed".to_string()))
-            } else if self.should_fail {
-                Ok(HealthStatus::Unhealthy("Mock server unhealthy".to_string()))
-            } else {
-                Ok(HealthStatus::Healthy)
-            }
-        }
-
-        async fn shutdown(&mut self) -> Result<(), MCPError> {
-            Ok(())
-        }
-
-        async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
-            if self.should_fail {
-                return Err(MCPError::Protocol("Mock notification failure".to_string()));
-            }
-            Ok(())
-        }
-
-        async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
-            if self.should_fail {
-                return Err(MCPError::Protocol(
-                    "Mock prompt listing failure".to_string(),
-                ));
-            }
-            Ok(self.prompts.clone())
-        }
-
-        async fn get_prompt(
-            &mut self,
-            prompt_name: &str,
-            arguments: Option<Value>,
-        ) -> Result<GetPromptResult, MCPError> {
-            if self.should_fail {
-                return Err(MCPError::Protocol(format!(
-                    "Mock prompt get failure for {}",
-                    prompt_name
-                )));
-            }
-
-            // Simulate successful prompt execution
-            if self.prompts.iter().any(|prompt| prompt.name == prompt_name) {
-                Ok(GetPromptResult {
-                    description: Some(format!(
-                        "Mock prompt result for {} with args: {:?}",
-                        prompt_name, arguments
-                    )),
-                    messages: Vec::new(),
-                })
-            } else {
-                Err(MCPError::Protocol(format!(
-                    "Prompt '{}' not found",
-                    prompt_name
-                )))
-            }
-        }
-
-        async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
-            if self.should_fail {
-                return Err(MCPError::Protocol(
-                    "Mock prompt notification failure".to_string(),
-                ));
-            }
-            Ok(())
-        }
-
-        fn name(&self) -> &str {
-            &self.name
-        }
-    }
-
-    #[tokio::test]
-    async fn test_mcp_client_initialization() {
-        let client = MCPClient::new();
-        assert_eq!(client.server_count().await, 0);
-        assert!(client.list_servers().await.is_empty());
-    }
-
-    #[tokio::test]
-    async fn test_retry_config() {
-        let config = RetryConfig::default();
-        assert_eq!(config.max_retries, 3);
-        assert_eq!(config.initial_delay, Duration::from_millis(100));
-        assert_eq!(config.backoff_multiplier, 2.0);
-
-        let custom_config = RetryConfig {
-            max_retries: 5,
-            initial_delay: Duration::from_millis(50),
-            max_delay: Duration::from_secs(10),
-            backoff_multiplier: 1.5,
-        };
-
-        let client = MCPClient::with_retry_config(custom_config.clone());
-        assert_eq!(client.retry_config.max_retries, 5);
-    }
-
-    #[tokio::test]
-    async fn test_health_status() {
-        let healthy = HealthStatus::Healthy;
-        let unhealthy = HealthStatus::Unhealthy("Test error".to_string());
-        let unknown = HealthStatus::Unknown;
-
-        match healthy {
-            HealthStatus::Healthy => {}
-            _ => panic!("Expected Healthy status"),
-        }
-
-        match unhealthy {
-            HealthStatus::Unhealthy(msg) => assert_eq!(msg, "Test error"),
-            _ => panic!("Expected Unhealthy status"),
-        }
-
-        match unknown {
-            HealthStatus::Unknown => {}
-            _ => panic!("Expected Unknown status"),
-        }
-    }
-
-    #[tokio::test]
-    async fn test_tool_definition_creation() {
-        let tool = ToolDefinition {
-            name: "test_tool".to_string(),
-            description: "A test tool".to_string(),
-            parameters: json!({"type": "object"}),
-            server_name: "test_server".to_string(),
-        };
-
-        assert_eq!(tool.name, "test_tool");
-        assert_eq!(tool.description, "A test tool");
-        assert_eq!(tool.server_name, "test_server");
-    }
-
-    #[tokio::test]
-    async fn test_mock_server_functionality() {
-        let tools = vec![ToolDefinition {
-            name: "list_files".to_string(),
-            description: "List files in directory".to_string(),
-            parameters: json!({"type": "object"}),
-            server_name: "test_server".to_string(),
-        }];
-
-        let mut server = MockMCPServer::new("test_server", tools);
-
-        // Test initialization
-        assert!(server.initialize().await.is_ok());
-
-        // Test tool listing
-        let listed_tools = server.list_tools().await.unwrap();
-        assert_eq!(listed_tools.len(), 1);
-        assert_eq!(listed_tools[0].name, "list_files");
-
-        // Test tool calling
-        let result = server
-            .call_tool("list_files", json!({"path": "/tmp"}))
-            .await
-            .unwrap();
-        assert!(result.get("success").unwrap().as_bool().unwrap());
-
-        // Test health check
-        let health = server.health().await.unwrap();
-        match health {
-            HealthStatus::Healthy => {}
-            _ => panic!("Expected healthy status"),
-        }
-
-        // Test shutdown
-        assert!(server.shutdown().await.is_ok());
-    }
-
-    #[tokio::test]
-    async fn test_mock_server_failures() {
-        let tools = vec![ToolDefinition {
-            name: "failing_tool".to_string(),
-            description: "A tool that fails".to_string(),
-            parameters: json!({"type": "object"}),
-            server_name: "failing_server".to_string(),
-        }];
-
-        let mut failing_server = MockMCPServer::new("failing_server", tools).with_failure(true);
-
-        // Test initialization failure
-        assert!(failing_server.initialize().await.is_err());
-
-        // Reset failure for other tests
-        failing_server.should_fail = false;
-        assert!(failing_server.initialize().await.is_ok());
-
-        // Test tool listing failure
-        failing_server.should_fail = true;
-        assert!(failing_server.list_tools().await.is_err());
-
-        // Test tool call failure
-        assert!(failing_server
-            .call_tool("failing_tool", json!({}))
-            .await
-            .is_err());
-
-        // Test health check failure
-        let health = failing_server.health().await.unwrap();
-        match health {
-            HealthStatus::Unhealthy(_) => {}
-            _ => panic!("Expected unhealthy status"),
-        }
-    }
-
-    #[tokio::test]
-    async fn test_tool_call_and_result() {
-        let call_id = ToolCallId::new();
-        let tool_call = ToolCall {
-            id: call_id,
-            name: "test_tool".to_string(),
-            arguments: json!({"param": "value"}),
-        };
-
-        let result = ToolResult {
-            call_id: tool_call.id,
-            result: json!({"status": "success"}),
-            error: None,
-        };
-
-        assert_eq!(result.call_id, call_id);
-        assert!(result.error.is_none());
-
-        let error_result = ToolResult {
-            call_id: tool_call.id,
-            result: Value::Null,
-            error: Some("Tool execution failed".to_string()),
-        };
-
-        assert_eq!(error_result.call_id, call_id);
-        assert!(error_result.error.is_some());
-    }
-
-    #[tokio::test]
-    async fn test_server_config_validation() {
-        let valid_config = MCPServerConfig {
-            name: "filesystem".to_string(),
-            command: "npx".to_string(),
-            args: vec![
-                "-y".to_string(),
-                "@modelcontextprotocol/server-filesystem".to_string(),
-            ],
-            timeout_secs: None,
-        };
-
-        assert!(valid_config.validate().is_ok());
-
-        let invalid_config = MCPServerConfig {
-            name: "".to_string(),
-            command: "npx".to_string(),
-            args: vec![],
-            timeout_secs: None,
-        };
-
-        assert!(invalid_config.validate().is_err());
-    }
-
-    #[tokio::test]
-    async fn test_mcp_client_default() {
-        let client1 = MCPClient::default();
-        let client2 = MCPClient::new();
-
-        assert_eq!(client1.server_count().await, client2.server_count().await);
-    }
-
-    #[tokio::test]
-    async fn test_concurrent_operations() {
-        let client = Arc::new(MCPClient::new());
-        let mut handles = vec![];
-
-        // Simulate concurrent server operations
-        for i in 0..5 {
-            let client_clone = client.clone();
-            let handle = tokio::spawn(async move {
-                let _server_name = format!("concurrent_server_{}", i);
-                let servers = client_clone.list_servers().await;
-                servers.len()
-            });
-            handles.push(handle);
-        }
-
-        // Wait for all operations to complete
-        for handle in handles {
-            let result = handle.await.unwrap();
-            assert_eq!(result, 0); // No servers initially
-        }
-    }
-
-    #[tokio::test]
-    async fn test_error_propagation() {
-        // Test that errors are properly wrapped and propagated
-        let connection_error = MCPError::Connection("Test connection error".to_string());
-        let protocol_error = MCPError::Protocol("Test protocol error".to_string());
-        let server_not_found = MCPError::ServerNotFound("test_server".to_string());
-        let tool_call_failed = MCPError::ToolCallFailed("Test tool call failure".to_string());
-
-        assert!(matches!(connection_error, MCPError::Connection(_)));
-        assert!(matches!(protocol_error, MCPError::Protocol(_)));
-        assert!(matches!(server_not_found, MCPError::ServerNotFound(_)));
-        assert!(matches!(tool_call_failed, MCPError::ToolCallFailed(_)));
-    }
-
-    #[tokio::test]
-    async fn test_tools_list_changed_notification() {
-        let tools = vec![ToolDefinition {
-            name: "test_tool".to_string(),
-            description: "A test tool".to_string(),
-            parameters: json!({"type": "object"}),
-            server_name: "test_server".to_string(),
-        }];
-
-        let mut server = MockMCPServer::new("test_server", tools);
-
-        // Test successful notification
-        assert!(server.notify_tools_list_changed().await.is_ok());
-
-        // Test failed notification
-        server.should_fail = true;
-        assert!(server.notify_tools_list_changed().await.is_err());
-    }
-
-    #[tokio::test]
-    async fn test_tools_list_change_detection() {
-        let client = MCPClient::new();
-
-        // First discovery - should detect changes (empty -> some tools)
-        let _initial_tools = vec![ToolDefinition {
-            name: "tool1".to_string(),
-            description: "Tool 1".to_string(),
-            parameters: json!({"type": "object"}),
-            server_name: "server1".to_string(),
-        }];
-
-        // Simulate adding tools to the cache
-        {
-            let mut cache = client.previous_tools_cache.write().await;
-            cache.insert("server1".to_string(), vec!["tool1".to_string()]);
-        }
-
-        // Second discovery with same tools - should not detect changes
-        {
-            let cache = client.previous_tools_cache.read().await;
-            let previous = cache.get("server1");
-            let current = vec!["tool1".to_string()];
-            assert_eq!(previous, Some(&current));
-        }
-
-        // Third discovery with different tools - should detect changes
-        let new_tools = vec!["tool1".to_string(), "tool2".to_string()];
-        {
-            let cache = client.previous_tools_cache.read().await;
-            let previous = cache.get("server1");
-            assert_ne!(previous, Some(&new_tools));
-        }
-    }
-
-    #[tokio::test]
-    async fn test_mcp_client_with_previous_tools_cache() {
-        let client = MCPClient::new();
-
-        // Test that previous tools cache is properly initialized
-        {
-            let cache = client.previous_tools_cache.read().await;
-            assert!(cache.is_empty());
-        }
-
-        // Test manual cache updates
-        {
-            let mut cache = client.previous_tools_cache.write().await;
-            cache.insert(
-                "test_server".to_string(),
-                vec!["tool1".to_string(), "tool2".to_string()],
-            );
-        }
-
-        {
-            let cache = client.previous_tools_cache.read().await;
-            assert_eq!(cache.len(), 1);
-            assert_eq!(
-                cache.get("test_server"),
-                Some(&vec!["tool1".to_string(), "tool2".to_string()])
-            );
-        }
-    }
-
-    #[tokio::test]
-    async fn test_prompt_definition_creation() {
-        let prompt = PromptDefinition {
-            name: "test_prompt".to_string(),
-            title: None,
-            description: Some("A test prompt".to_string()),
-            arguments: Some(vec![PromptArgument {
-                name: "user_input".to_string(),
-                description: Some("User input for the prompt".to_string()),
-                required: Some(true),
-            }]),
-            server_name: "test_server".to_string(),
-        };
-
-        assert_eq!(prompt.name, "test_prompt");
-        assert_eq!(prompt.description, Some("A test prompt".to_string()));
-        assert_eq!(prompt.server_name, "test_server");
-        assert_eq!(prompt.arguments.as_ref().unwrap().len(), 1);
-        assert_eq!(prompt.arguments.as_ref().unwrap()[0].name, "user_input");
-        assert_eq!(prompt.arguments.as_ref().unwrap()[0].required, Some(true));
-    }
-
-    #[tokio::test]
-    async fn test_mock_server_prompt_functionality() {
-        let prompts = vec![PromptDefinition {
-            name: "code_review".to_string(),
-            title: None,
-            description: Some("Review code for best practices".to_string()),
-            arguments: Some(vec![PromptArgument {
-                name: "code".to_string(),
-                description: Some("The code to review".to_string()),
-                required: Some(true),
-            }]),
-            server_name: "test_server".to_string(),
-        }];
-
-        let mut server = MockMCPServer::new("test_server", Vec::new()).with_prompts(prompts);
-
-        // Test initialization
-        assert!(server.initialize().await.is_ok());
-
-        // Test prompt listing
-        let listed_prompts = server.list_prompts().await.unwrap();
-        assert_eq!(listed_prompts.len(), 1);
-        assert_eq!(listed_prompts[0].name, "code_review");
-
-        // Test prompt getting
-        let result = server
-            .get_prompt("code_review", Some(json!({"code": "def hello(): pass"})))
-            .await
-            .unwrap();
-        assert!(result.description.is_some());
-        assert!(result.description.unwrap().contains("code_review"));
-
-        // Test prompt notification
-        assert!(server.notify_prompts_list_changed().await.is_ok());
-
-        // Test health check
-        let health = server.health().await.unwrap();
-        match health {
-            HealthStatus::Healthy => {}
-            _ => panic!("Expected healthy status"),
-        }
-
-        // Test shutdown
-        assert!(server.shutdown().await.is_ok());
-    }
-
-    #[tokio::test]
-    async fn test_prompt_id() {
-        use crate::types::PromptId;
-
-        let prompt_id = PromptId::new();
-        let prompt_id_str = prompt_id.to_string();
-
-        // Test that we can parse back the string representation
-        let parsed_prompt_id: PromptId = prompt_id_str.parse().unwrap();
-        assert_eq!(prompt_id, parsed_prompt_id);
-
-        // Test serialization
-        let serialized = serde_json::to_string(&prompt_id).unwrap();
-        let deserialized: PromptId = serde_json::from_str(&serialized).unwrap();
-        assert_eq!(prompt_id, deserialized);
-
-        // Test Display trait
-        assert!(!format!("{}", prompt_id).is_empty());
-    }
-}
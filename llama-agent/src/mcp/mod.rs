@@ -0,0 +1,3931 @@
+use crate::clock::{Clock, SystemClock};
+use crate::redaction::redact;
+use crate::types::{
+    GetPromptResult, MCPError, MCPServerConfig, MCPServerInfo, PromptArgument, PromptContent,
+    PromptDefinition, PromptMessage, PromptResource, PromptRole, SessionId, ToolCall, ToolCallId,
+    ToolDefinition, ToolResult,
+};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+mod stdio;
+
+use stdio::StdioTransport;
+pub use stdio::{ListChanges, ProgressHandler};
+
+// Type alias to reduce complexity
+type ServerMap = Arc<RwLock<HashMap<String, Arc<Mutex<Box<dyn MCPServer>>>>>>;
+
+/// Prefix of the names under which session-scoped servers are registered
+const SESSION_SERVER_PREFIX: &str = "session:";
+
+/// Name under which a server listed on a session is registered with the client
+pub fn session_server_name(session_id: &SessionId, server_name: &str) -> String {
+    format!("{}{}:{}", SESSION_SERVER_PREFIX, session_id, server_name)
+}
+
+fn is_session_server(server_name: &str) -> bool {
+    server_name.starts_with(SESSION_SERVER_PREFIX)
+}
+
+/// Name of a server as configured, without the scope of a session server
+fn configured_server_name(server_name: &str) -> &str {
+    server_name
+        .strip_prefix(SESSION_SERVER_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+        .map_or(server_name, |(_, name)| name)
+}
+
+/// Where a tool or prompt name leads
+#[derive(Debug, Clone)]
+enum Route {
+    /// Served by `server_name`, which knows it as `name`
+    Server { server_name: String, name: String },
+    /// Offered by several servers; one of these qualified names must be used instead
+    Ambiguous(Vec<String>),
+}
+
+/// Error text for a tool or prompt called by a name several servers share
+pub(crate) fn ambiguous_name_error(kind: &str, name: &str, qualified: &[String]) -> String {
+    format!(
+        "{} '{}' is offered by several MCP servers; use one of: {}",
+        kind,
+        name,
+        qualified.join(", ")
+    )
+}
+
+/// A tool or prompt as offered by one server
+pub(crate) trait ServerItem {
+    fn name(&self) -> &str;
+    fn server_name(&self) -> &str;
+    /// The name the server itself uses
+    fn server_item_name(&self) -> &str;
+    /// Rename to `qualified`, keeping the server's own name for calls
+    fn qualify(&mut self, qualified: String);
+    /// Undo [`ServerItem::qualify`]
+    fn unqualify(&mut self);
+}
+
+impl ServerItem for ToolDefinition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    fn server_item_name(&self) -> &str {
+        self.server_tool_name()
+    }
+
+    fn qualify(&mut self, qualified: String) {
+        self.original_name = Some(std::mem::replace(&mut self.name, qualified));
+    }
+
+    fn unqualify(&mut self) {
+        if let Some(original) = self.original_name.take() {
+            self.name = original;
+        }
+    }
+}
+
+impl ServerItem for PromptDefinition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    fn server_item_name(&self) -> &str {
+        self.server_prompt_name()
+    }
+
+    fn qualify(&mut self, qualified: String) {
+        self.original_name = Some(std::mem::replace(&mut self.name, qualified));
+    }
+
+    fn unqualify(&mut self) {
+        if let Some(original) = self.original_name.take() {
+            self.name = original;
+        }
+    }
+}
+
+/// Sort by server, then name, so prompts rendered from the list do not depend on the
+/// order servers were iterated in
+pub(crate) fn sort_by_server<T: ServerItem>(items: &mut [T]) {
+    items.sort_by(|a, b| {
+        a.server_name()
+            .cmp(b.server_name())
+            .then_with(|| a.name().cmp(b.name()))
+    });
+}
+
+/// Rename every item whose name more than one server offers to `server.name`, with the
+/// server part given by `label`, so each stays addressable.
+///
+/// Returns the colliding names with the qualified names replacing them, sorted by server.
+fn qualify_collisions<T: ServerItem>(
+    items: &mut [T],
+    kind: &str,
+    label: impl Fn(&str) -> &str,
+) -> HashMap<String, Vec<String>> {
+    let mut servers_by_name: HashMap<&str, Vec<&str>> = HashMap::new();
+    for item in items.iter() {
+        servers_by_name
+            .entry(item.name())
+            .or_default()
+            .push(item.server_name());
+    }
+
+    let mut collisions = HashMap::new();
+    for (name, mut servers) in servers_by_name {
+        servers.sort_unstable();
+        servers.dedup();
+        if servers.len() < 2 {
+            continue;
+        }
+        warn!(
+            "{} '{}' is offered by MCP servers {}; it is only available under qualified names",
+            kind,
+            name,
+            servers.join(", ")
+        );
+        let qualified = servers
+            .iter()
+            .map(|server| format!("{}.{}", label(server), name))
+            .collect();
+        collisions.insert(name.to_string(), qualified);
+    }
+
+    for item in items.iter_mut() {
+        if collisions.contains_key(item.name()) {
+            let qualified = format!("{}.{}", label(item.server_name()), item.name());
+            item.qualify(qualified);
+        }
+    }
+    collisions
+}
+
+/// Routes for discovered items: their names, plus an [`Route::Ambiguous`] entry per collision
+fn routes<T: ServerItem>(
+    items: &[T],
+    collisions: HashMap<String, Vec<String>>,
+) -> HashMap<String, Route> {
+    let mut routes: HashMap<String, Route> = collisions
+        .into_iter()
+        .map(|(name, qualified)| (name, Route::Ambiguous(qualified)))
+        .collect();
+    for item in items {
+        routes.insert(
+            item.name().to_string(),
+            Route::Server {
+                server_name: item.server_name().to_string(),
+                name: item.server_item_name().to_string(),
+            },
+        );
+    }
+    routes
+}
+
+/// Routes of discovered tools or prompts, with when they were discovered.
+///
+/// Each discovery replaces every route, so the cache only ever holds what the
+/// servers offered last time.
+#[derive(Debug, Default)]
+struct RouteCache {
+    routes: HashMap<String, Route>,
+    refreshed_at: Option<SystemTime>,
+}
+
+impl RouteCache {
+    fn replace(&mut self, routes: HashMap<String, Route>, now: SystemTime) {
+        self.routes = routes;
+        self.refreshed_at = Some(now);
+    }
+
+    /// Whether the routes are older than `ttl`, or were never discovered
+    fn is_stale(&self, now: SystemTime, ttl: Duration) -> bool {
+        self.refreshed_at
+            .is_none_or(|at| now.duration_since(at).map_or(true, |age| age >= ttl))
+    }
+
+    /// Have the next lookup discover again, whatever the age of the routes
+    fn invalidate(&mut self) {
+        self.refreshed_at = None;
+    }
+
+    fn get(&self, name: &str) -> Option<Route> {
+        self.routes.get(name).cloned()
+    }
+
+    /// Drop the routes to `server_name`. Collisions may be gone with the server, so
+    /// ambiguous names are dropped too
+    fn remove_server(&mut self, server_name: &str) {
+        self.routes.retain(|_, route| {
+            matches!(route, Route::Server { server_name: server, .. } if server != server_name)
+        });
+    }
+}
+
+/// JSON-RPC error code servers answer a call to a tool they do not have with
+const INVALID_PARAMS: i64 = -32602;
+
+/// Whether a tool call failed because the server is gone or no longer has the tool,
+/// so the route that led there may be stale
+fn is_missing_tool_error(error: &MCPError) -> bool {
+    matches!(
+        error,
+        MCPError::ServerNotFound(_)
+            | MCPError::Rpc {
+                code: INVALID_PARAMS,
+                ..
+            }
+    )
+}
+
+/// Protocol version this client requests in `initialize`
+const CLIENT_PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Protocol revisions whose tool and prompt methods this client can speak
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
+/// Read the server's reply to `initialize`.
+///
+/// `protocolVersion` is required; a missing `serverInfo` leaves name and version empty.
+/// Capabilities are the names of the keys the server declared, sorted.
+fn parse_initialize_result(result: &Value) -> Result<MCPServerInfo, MCPError> {
+    let protocol_version = result
+        .get("protocolVersion")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| {
+            MCPError::Protocol("Invalid initialize response: missing protocolVersion".to_string())
+        })?
+        .to_string();
+
+    let server_info = result.get("serverInfo");
+    let info_field = |field: &str| {
+        server_info
+            .and_then(|info| info.get(field))
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+
+    let mut capabilities: Vec<String> = match result.get("capabilities") {
+        Some(Value::Object(caps)) => caps.keys().cloned().collect(),
+        Some(Value::Null) | None => Vec::new(),
+        Some(other) => {
+            return Err(MCPError::Protocol(format!(
+                "Invalid initialize response: capabilities must be an object, got {}",
+                other
+            )))
+        }
+    };
+    capabilities.sort();
+
+    Ok(MCPServerInfo {
+        name: info_field("name"),
+        version: info_field("version"),
+        protocol_version,
+        capabilities,
+    })
+}
+
+/// Check the protocol version a server replied with, failing in strict mode
+/// and logging a warning otherwise
+fn check_protocol_version(
+    server_name: &str,
+    info: &MCPServerInfo,
+    strict: bool,
+) -> Result<(), MCPError> {
+    if SUPPORTED_PROTOCOL_VERSIONS.contains(&info.protocol_version.as_str()) {
+        return Ok(());
+    }
+
+    let message = format!(
+        "MCP server '{}' replied with unsupported protocol version '{}' (supported: {})",
+        server_name,
+        info.protocol_version,
+        SUPPORTED_PROTOCOL_VERSIONS.join(", ")
+    );
+    if strict {
+        return Err(MCPError::Protocol(message));
+    }
+    warn!("{}; continuing anyway", message);
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub enum HealthStatus {
+    Healthy,
+    Unhealthy(String),
+    Unknown,
+}
+
+#[async_trait]
+pub trait MCPServer: Send + Sync {
+    async fn initialize(&mut self) -> Result<(), MCPError>;
+    async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError>;
+    async fn call_tool(&mut self, tool_name: &str, args: Value) -> Result<Value, MCPError>;
+
+    /// Call a tool, passing the progress it reports to `on_progress`. Servers that
+    /// cannot report progress just call the tool.
+    async fn call_tool_with_progress(
+        &mut self,
+        tool_name: &str,
+        args: Value,
+        _on_progress: ProgressHandler,
+    ) -> Result<Value, MCPError> {
+        self.call_tool(tool_name, args).await
+    }
+
+    async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError>;
+    async fn get_prompt(
+        &mut self,
+        prompt_name: &str,
+        arguments: Option<Value>,
+    ) -> Result<GetPromptResult, MCPError>;
+    async fn health(&self) -> Result<HealthStatus, MCPError>;
+    async fn shutdown(&mut self) -> Result<(), MCPError>;
+    async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError>;
+    async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError>;
+    fn name(&self) -> &str;
+
+    /// What the server reported about itself during `initialize`, if known
+    fn server_info(&self) -> Option<MCPServerInfo> {
+        None
+    }
+
+    /// Lists the server announced as changed since they were last listed, which
+    /// calls for discovering its tools or prompts again
+    fn list_changes(&self) -> ListChanges {
+        ListChanges::default()
+    }
+
+    /// Calls this server's tools without holding the server, so a long call does not
+    /// keep other calls and requests to it waiting; `None` to call tools through
+    /// [`MCPServer::call_tool_with_progress`]
+    fn tool_caller(&self) -> Option<ToolCaller> {
+        None
+    }
+}
+
+/// Calls the tools of a server over the connection it shares with the server
+#[derive(Clone)]
+pub struct ToolCaller {
+    server_name: String,
+    transport: Arc<StdioTransport>,
+}
+
+impl ToolCaller {
+    /// Call a tool, passing the progress it reports to `on_progress` if given
+    pub async fn call(
+        &self,
+        tool_name: &str,
+        args: Value,
+        on_progress: Option<ProgressHandler>,
+    ) -> Result<Value, MCPError> {
+        debug!(
+            "Calling tool '{}' on server '{}'",
+            tool_name, self.server_name
+        );
+
+        // Send actual MCP tool call request
+        let params = json!({
+            "name": tool_name,
+            "arguments": args
+        });
+
+        let result = match on_progress {
+            Some(on_progress) => {
+                self.transport
+                    .request_with_progress("tools/call", params, on_progress)
+                    .await?
+            }
+            None => self.transport.request("tools/call", params).await?,
+        };
+
+        debug!(
+            "Tool '{}' on server '{}' completed successfully",
+            tool_name, self.server_name
+        );
+
+        Ok(result)
+    }
+}
+
+struct MCPServerImpl {
+    config: MCPServerConfig,
+    process: Option<tokio::process::Child>,
+    transport: Option<Arc<StdioTransport>>,
+    last_health_check: Option<SystemTime>,
+    initialized: bool,
+    server_info: Option<MCPServerInfo>,
+}
+
+impl MCPServerImpl {
+    pub fn new(config: MCPServerConfig) -> Self {
+        Self {
+            config,
+            process: None,
+            transport: None,
+            last_health_check: None,
+            initialized: false,
+            server_info: None,
+        }
+    }
+
+    /// Whether the server declared the prompts capability; assumed when unknown
+    fn supports_prompts(&self) -> bool {
+        self.server_info
+            .as_ref()
+            .is_none_or(|info| info.supports("prompts"))
+    }
+
+    async fn spawn_process(&mut self) -> Result<tokio::process::Child, MCPError> {
+        debug!(
+            "Spawning MCP server process: {} {:?}",
+            self.config.command, self.config.args
+        );
+
+        let mut cmd = tokio::process::Command::new(&self.config.command);
+        cmd.args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let process = cmd.spawn().map_err(|e| {
+            MCPError::Connection(format!(
+                "Failed to spawn MCP server '{}': {}",
+                self.config.name, e
+            ))
+        })?;
+
+        info!(
+            "Spawned MCP server '{}' with PID: {:?}",
+            self.config.name,
+            process.id()
+        );
+        Ok(process)
+    }
+
+    fn transport(&self) -> Result<&Arc<StdioTransport>, MCPError> {
+        self.transport
+            .as_ref()
+            .ok_or_else(|| MCPError::Connection("No stdio connection available".to_string()))
+    }
+
+    async fn send_request(&self, method: &str, params: Value) -> Result<Value, MCPError> {
+        self.transport()?.request(method, params).await
+    }
+
+    async fn send_initialized_notification(&self) -> Result<(), MCPError> {
+        self.send_notification("notifications/initialized", json!({}))
+            .await
+    }
+
+    async fn send_notification(&self, method: &str, params: Value) -> Result<(), MCPError> {
+        self.transport()?.notify(method, params).await
+    }
+
+    async fn send_tool_call(
+        &self,
+        tool_name: &str,
+        args: Value,
+        on_progress: Option<ProgressHandler>,
+    ) -> Result<Value, MCPError> {
+        if !self.initialized {
+            return Err(MCPError::Connection(format!(
+                "Server '{}' not initialized",
+                self.config.name
+            )));
+        }
+
+        ToolCaller {
+            server_name: self.config.name.clone(),
+            transport: Arc::clone(self.transport()?),
+        }
+        .call(tool_name, args, on_progress)
+        .await
+    }
+
+    async fn send_tools_list_changed(&mut self) -> Result<(), MCPError> {
+        debug!("Sending tools list changed notification");
+        self.send_notification("notifications/tools/list_changed", json!({}))
+            .await
+    }
+
+    async fn send_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+        debug!("Sending prompts list changed notification");
+        self.send_notification("notifications/prompts/list_changed", json!({}))
+            .await
+    }
+}
+
+#[async_trait]
+impl MCPServer for MCPServerImpl {
+    async fn initialize(&mut self) -> Result<(), MCPError> {
+        debug!("Initializing MCP server: {}", self.config.name);
+
+        let mut process = self.spawn_process().await?;
+
+        // Get stdio handles from the process
+        let stdin = process
+            .stdin
+            .take()
+            .ok_or_else(|| MCPError::Connection("Failed to get stdin from process".to_string()))?;
+        let stdout = process
+            .stdout
+            .take()
+            .ok_or_else(|| MCPError::Connection("Failed to get stdout from process".to_string()))?;
+
+        self.transport = Some(Arc::new(StdioTransport::new(
+            &self.config.name,
+            stdout,
+            stdin,
+        )));
+        self.process = Some(process);
+
+        // Send initialization request
+        let init_params = json!({
+            "protocolVersion": CLIENT_PROTOCOL_VERSION,
+            "capabilities": {
+                "tools": {
+                    "listChanged": true
+                },
+                "prompts": {
+                    "listChanged": true
+                }
+            },
+            "clientInfo": {
+                "name": "llama-agent",
+                "version": "0.1.0"
+            }
+        });
+
+        let init_result = self.send_request("initialize", init_params).await?;
+        let server_info = parse_initialize_result(&init_result).and_then(|info| {
+            check_protocol_version(
+                &self.config.name,
+                &info,
+                self.config.strict_protocol_version,
+            )?;
+            Ok(info)
+        });
+        let server_info = match server_info {
+            Ok(info) => info,
+            Err(e) => {
+                self.shutdown().await?;
+                return Err(e);
+            }
+        };
+        info!(
+            "MCP server '{}' is {} {} (protocol {}, capabilities: {:?})",
+            self.config.name,
+            server_info.name,
+            server_info.version,
+            server_info.protocol_version,
+            server_info.capabilities
+        );
+        self.server_info = Some(server_info);
+
+        // Send initialized notification
+        self.send_initialized_notification().await?;
+
+        self.initialized = true;
+        self.last_health_check = Some(SystemTime::now());
+
+        info!("Successfully initialized MCP server: {}", self.config.name);
+        Ok(())
+    }
+
+    async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+        if !self.initialized {
+            return Err(MCPError::Connection(format!(
+                "Server '{}' not initialized",
+                self.config.name
+            )));
+        }
+
+        debug!("Listing tools for MCP server: {}", self.config.name);
+
+        // Changes announced from here on are not covered by this listing
+        self.transport()?.clear_list_changes(ListChanges {
+            tools: true,
+            prompts: false,
+        });
+        // Send tools/list request to the server
+        let response = self.send_request("tools/list", json!({})).await?;
+
+        // Parse the response to extract tool definitions
+        let tools_array = response
+            .get("tools")
+            .and_then(|t| t.as_array())
+            .ok_or_else(|| {
+                MCPError::Protocol(
+                    "Invalid tools/list response format: missing tools array".to_string(),
+                )
+            })?;
+
+        let mut tool_definitions = Vec::new();
+        for tool_data in tools_array {
+            // Parse the tool name
+            let name = tool_data
+                .get("name")
+                .and_then(|n| n.as_str())
+                .ok_or_else(|| MCPError::Protocol("Tool missing name field".to_string()))?
+                .to_string();
+
+            // Parse the tool description
+            let description = tool_data
+                .get("description")
+                .and_then(|d| d.as_str())
+                .unwrap_or("")
+                .to_string();
+
+            // Parse the input schema (optional)
+            let parameters = tool_data
+                .get("inputSchema")
+                .cloned()
+                .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
+
+            tool_definitions.push(ToolDefinition {
+                name,
+                description,
+                parameters,
+                server_name: self.config.name.clone(),
+                original_name: None,
+            });
+        }
+
+        debug!(
+            "Found {} tools for server '{}'",
+            tool_definitions.len(),
+            self.config.name
+        );
+        Ok(tool_definitions)
+    }
+
+    async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+        if !self.initialized {
+            return Err(MCPError::Connection(format!(
+                "Server '{}' not initialized",
+                self.config.name
+            )));
+        }
+
+        if !self.supports_prompts() {
+            debug!(
+                "MCP server '{}' does not support prompts, skipping prompts/list",
+                self.config.name
+            );
+            return Ok(Vec::new());
+        }
+
+        debug!("Listing prompts for MCP server: {}", self.config.name);
+
+        // Changes announced from here on are not covered by this listing
+        self.transport()?.clear_list_changes(ListChanges {
+            tools: false,
+            prompts: true,
+        });
+        // Send prompts/list request to the server
+        let response = self.send_request("prompts/list", json!({})).await?;
+
+        // Parse the response to extract prompt definitions
+        let prompts_array = response
+            .get("prompts")
+            .and_then(|p| p.as_array())
+            .ok_or_else(|| {
+                MCPError::Protocol("Invalid prompts/list response format".to_string())
+            })?;
+
+        let mut prompt_definitions = Vec::new();
+        for prompt_data in prompts_array {
+            let name = prompt_data
+                .get("name")
+                .and_then(|n| n.as_str())
+                .ok_or_else(|| MCPError::Protocol("Prompt missing name field".to_string()))?
+                .to_string();
+
+            let description = prompt_data
+                .get("description")
+                .and_then(|d| d.as_str())
+                .map(|s| s.to_string());
+
+            let mut arguments = Vec::new();
+            if let Some(args_array) = prompt_data.get("arguments").and_then(|a| a.as_array()) {
+                for arg_data in args_array {
+                    let arg_name = arg_data
+                        .get("name")
+                        .and_then(|n| n.as_str())
+                        .ok_or_else(|| {
+                            MCPError::Protocol("Prompt argument missing name".to_string())
+                        })?
+                        .to_string();
+
+                    let arg_description = arg_data
+                        .get("description")
+                        .and_then(|d| d.as_str())
+                        .map(|s| s.to_string());
+
+                    let required = arg_data
+                        .get("required")
+                        .and_then(|r| r.as_bool())
+                        .unwrap_or(false);
+
+                    arguments.push(PromptArgument {
+                        name: arg_name,
+                        description: arg_description,
+                        required: Some(required),
+                    });
+                }
+            }
+
+            prompt_definitions.push(PromptDefinition {
+                name,
+                title: None,
+                description,
+                arguments: Some(arguments),
+                server_name: self.config.name.clone(),
+                original_name: None,
+            });
+        }
+
+        debug!(
+            "Found {} prompts for server '{}'",
+            prompt_definitions.len(),
+            self.config.name
+        );
+        Ok(prompt_definitions)
+    }
+
+    async fn get_prompt(
+        &mut self,
+        prompt_name: &str,
+        arguments: Option<Value>,
+    ) -> Result<GetPromptResult, MCPError> {
+        if !self.initialized {
+            return Err(MCPError::Connection(format!(
+                "Server '{}' not initialized",
+                self.config.name
+            )));
+        }
+
+        if !self.supports_prompts() {
+            return Err(MCPError::Protocol(format!(
+                "Server '{}' does not support prompts",
+                self.config.name
+            )));
+        }
+
+        debug!(
+            "Getting prompt '{}' from server '{}' with arguments: {}",
+            prompt_name,
+            self.config.name,
+            redact(arguments.as_ref().unwrap_or(&Value::Null))
+        );
+
+        // Build the prompts/get request parameters
+        let mut params = json!({
+            "name": prompt_name
+        });
+
+        if let Some(args) = arguments {
+            params["arguments"] = args;
+        }
+
+        // Send prompts/get request to the server
+        let response = self.send_request("prompts/get", params).await?;
+
+        // Parse the description from the response
+        let description = response
+            .get("description")
+            .and_then(|d| d.as_str())
+            .map(|s| s.to_string());
+
+        // Parse the messages array from the response
+        let messages_array = response
+            .get("messages")
+            .and_then(|m| m.as_array())
+            .ok_or_else(|| {
+                MCPError::Protocol(
+                    "Invalid prompts/get response format: missing messages".to_string(),
+                )
+            })?;
+
+        let mut messages = Vec::new();
+        for message_data in messages_array {
+            // Parse the role
+            let role_str = message_data
+                .get("role")
+                .and_then(|r| r.as_str())
+                .ok_or_else(|| MCPError::Protocol("Message missing role field".to_string()))?;
+
+            let role = match role_str {
+                "user" => PromptRole::User,
+                "assistant" => PromptRole::Assistant,
+                _ => {
+                    return Err(MCPError::Protocol(format!(
+                        "Unknown prompt role: {} (only 'user' and 'assistant' are supported)",
+                        role_str
+                    )))
+                }
+            };
+
+            // Parse the content
+            let content_data = message_data
+                .get("content")
+                .ok_or_else(|| MCPError::Protocol("Message missing content field".to_string()))?;
+
+            let content = if let Some(content_type) =
+                content_data.get("type").and_then(|t| t.as_str())
+            {
+                match content_type {
+                    "text" => {
+                        let text = content_data
+                            .get("text")
+                            .and_then(|t| t.as_str())
+                            .ok_or_else(|| {
+                                MCPError::Protocol("Text content missing text field".to_string())
+                            })?
+                            .to_string();
+                        PromptContent::Text { text }
+                    }
+                    "image" => {
+                        let data = content_data
+                            .get("data")
+                            .and_then(|d| d.as_str())
+                            .ok_or_else(|| {
+                                MCPError::Protocol("Image content missing data field".to_string())
+                            })?
+                            .to_string();
+                        let mime_type = content_data
+                            .get("mimeType")
+                            .and_then(|m| m.as_str())
+                            .ok_or_else(|| {
+                                MCPError::Protocol(
+                                    "Image content missing mimeType field".to_string(),
+                                )
+                            })?
+                            .to_string();
+                        PromptContent::Image { data, mime_type }
+                    }
+                    "resource" => {
+                        let resource_data = content_data.get("resource").ok_or_else(|| {
+                            MCPError::Protocol(
+                                "Resource content missing resource field".to_string(),
+                            )
+                        })?;
+
+                        let uri = resource_data
+                            .get("uri")
+                            .and_then(|u| u.as_str())
+                            .ok_or_else(|| {
+                                MCPError::Protocol("Resource missing uri field".to_string())
+                            })?
+                            .to_string();
+
+                        let text = resource_data
+                            .get("text")
+                            .and_then(|t| t.as_str())
+                            .map(|s| s.to_string());
+
+                        let mime_type = resource_data
+                            .get("mimeType")
+                            .and_then(|m| m.as_str())
+                            .map(|s| s.to_string());
+
+                        PromptContent::Resource {
+                            resource: PromptResource {
+                                uri: uri.clone(),
+                                name: uri.split('/').next_back().unwrap_or(&uri).to_string(),
+                                title: None,
+                                mime_type: mime_type.unwrap_or_default(),
+                                text,
+                            },
+                        }
+                    }
+                    _ => {
+                        return Err(MCPError::Protocol(format!(
+                            "Unknown content type: {}",
+                            content_type
+                        )))
+                    }
+                }
+            } else {
+                // Handle legacy format where content might be just a string
+                let text = content_data
+                    .as_str()
+                    .ok_or_else(|| {
+                        MCPError::Protocol(
+                            "Content must be either object with type or string".to_string(),
+                        )
+                    })?
+                    .to_string();
+                PromptContent::Text { text }
+            };
+
+            messages.push(PromptMessage { role, content });
+        }
+
+        let result = GetPromptResult {
+            description,
+            messages,
+        };
+
+        debug!(
+            "Retrieved prompt '{}' from server '{}' successfully with {} messages",
+            prompt_name,
+            self.config.name,
+            result.messages.len()
+        );
+
+        Ok(result)
+    }
+
+    async fn call_tool(&mut self, tool_name: &str, args: Value) -> Result<Value, MCPError> {
+        self.send_tool_call(tool_name, args, None).await
+    }
+
+    async fn call_tool_with_progress(
+        &mut self,
+        tool_name: &str,
+        args: Value,
+        on_progress: ProgressHandler,
+    ) -> Result<Value, MCPError> {
+        self.send_tool_call(tool_name, args, Some(on_progress))
+            .await
+    }
+
+    async fn health(&self) -> Result<HealthStatus, MCPError> {
+        if !self.initialized {
+            return Ok(HealthStatus::Unhealthy("Not initialized".to_string()));
+        }
+        if self
+            .transport
+            .as_deref()
+            .is_some_and(StdioTransport::is_closed)
+        {
+            return Ok(HealthStatus::Unhealthy("Closed its output".to_string()));
+        }
+
+        // Check if process is still running
+        if let Some(process) = self.process.as_ref() {
+            // For tokio::process::Child, we need to use a different approach
+            // Check if process is still alive by trying to get its id
+            match process.id() {
+                Some(_pid) => {
+                    debug!("Process is still running for server: {}", self.config.name);
+                    Ok(HealthStatus::Healthy)
+                }
+                None => Ok(HealthStatus::Unhealthy("Process has exited".to_string())),
+            }
+        } else {
+            Ok(HealthStatus::Unhealthy("Process not found".to_string()))
+        }
+    }
+
+    async fn shutdown(&mut self) -> Result<(), MCPError> {
+        info!("Shutting down MCP server: {}", self.config.name);
+
+        // Close stdin/stdout first
+        self.transport.take();
+
+        // Terminate process
+        if let Some(mut process) = self.process.take() {
+            match process.kill().await {
+                Ok(_) => {
+                    // Wait for process to exit
+                    match timeout(Duration::from_secs(5), process.wait()).await {
+                        Ok(Ok(status)) => {
+                            info!(
+                                "MCP server '{}' shut down with status: {:?}",
+                                self.config.name, status
+                            );
+                        }
+                        Ok(Err(e)) => {
+                            warn!(
+                                "Error waiting for MCP server '{}' shutdown: {}",
+                                self.config.name, e
+                            );
+                        }
+                        Err(_) => {
+                            warn!(
+                                "Timeout waiting for MCP server '{}' shutdown",
+                                self.config.name
+                            );
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to kill MCP server '{}' process: {}",
+                        self.config.name, e
+                    );
+                }
+            }
+        }
+
+        self.initialized = false;
+        self.server_info = None;
+        Ok(())
+    }
+
+    async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+        if !self.initialized {
+            return Err(MCPError::Connection(format!(
+                "Server '{}' not initialized",
+                self.config.name
+            )));
+        }
+
+        debug!(
+            "Notifying tools list changed for server: {}",
+            self.config.name
+        );
+        self.send_tools_list_changed().await
+    }
+
+    async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+        if !self.initialized {
+            return Err(MCPError::Connection(format!(
+                "Server '{}' not initialized",
+                self.config.name
+            )));
+        }
+
+        if !self.supports_prompts() {
+            debug!(
+                "MCP server '{}' does not support prompts, not notifying",
+                self.config.name
+            );
+            return Ok(());
+        }
+
+        debug!(
+            "Notifying prompts list changed for server: {}",
+            self.config.name
+        );
+        self.send_prompts_list_changed().await
+    }
+
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn server_info(&self) -> Option<MCPServerInfo> {
+        self.server_info.clone()
+    }
+
+    fn list_changes(&self) -> ListChanges {
+        self.transport
+            .as_deref()
+            .map_or_else(ListChanges::default, StdioTransport::list_changes)
+    }
+
+    fn tool_caller(&self) -> Option<ToolCaller> {
+        let transport = self.transport.as_ref().filter(|_| self.initialized)?;
+        Some(ToolCaller {
+            server_name: self.config.name.clone(),
+            transport: Arc::clone(transport),
+        })
+    }
+}
+
+/// How long one initialization attempt may take before it counts as failed
+pub const DEFAULT_INIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long discovered tools and prompts are routed to before they are discovered again
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Builds the server for a config; [`MCPClient`] uses it when adding and restarting servers
+pub trait ServerFactory: Send + Sync {
+    fn create(&self, config: &MCPServerConfig) -> Box<dyn MCPServer>;
+}
+
+/// Spawns each server as a child process speaking MCP over stdio
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessServerFactory;
+
+impl ServerFactory for ProcessServerFactory {
+    fn create(&self, config: &MCPServerConfig) -> Box<dyn MCPServer> {
+        Box::new(MCPServerImpl::new(config.clone()))
+    }
+}
+
+pub struct MCPClient {
+    servers: ServerMap,
+    server_configs: Arc<RwLock<HashMap<String, MCPServerConfig>>>,
+    server_factory: Arc<dyn ServerFactory>,
+    retry_config: RetryConfig,
+    init_timeout: Duration,
+    cache_ttl: Duration,
+    clock: Arc<dyn Clock>,
+    tool_to_server_cache: Arc<RwLock<RouteCache>>,
+    previous_tools_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    prompt_to_server_cache: Arc<RwLock<RouteCache>>,
+    previous_prompts_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    /// Cancellation of the tool calls in flight, by call id
+    tool_calls: Arc<StdMutex<HashMap<ToolCallId, CancellationToken>>>,
+}
+
+/// Keeps a tool call cancellable by id while it runs
+struct TrackedCall {
+    tool_calls: Arc<StdMutex<HashMap<ToolCallId, CancellationToken>>>,
+    call_id: ToolCallId,
+}
+
+impl Drop for TrackedCall {
+    fn drop(&mut self) {
+        self.tool_calls.lock().unwrap().remove(&self.call_id);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub backoff_multiplier: f64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            backoff_multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Wait before the given retry (1 for the first), growing by `backoff_multiplier`
+    /// from `initial_delay` and capped at `max_delay`
+    pub fn delay_before_retry(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.initial_delay.as_secs_f64() * self.backoff_multiplier.powi(exponent);
+        Duration::try_from_secs_f64(secs)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+impl MCPClient {
+    pub fn new() -> Self {
+        Self::with_retry_config(RetryConfig::default())
+    }
+
+    pub fn with_retry_config(retry_config: RetryConfig) -> Self {
+        Self {
+            servers: Arc::new(RwLock::new(HashMap::new())),
+            server_configs: Arc::new(RwLock::new(HashMap::new())),
+            server_factory: Arc::new(ProcessServerFactory),
+            retry_config,
+            init_timeout: DEFAULT_INIT_TIMEOUT,
+            cache_ttl: DEFAULT_CACHE_TTL,
+            clock: Arc::new(SystemClock),
+            tool_to_server_cache: Arc::new(RwLock::new(RouteCache::default())),
+            previous_tools_cache: Arc::new(RwLock::new(HashMap::new())),
+            prompt_to_server_cache: Arc::new(RwLock::new(RouteCache::default())),
+            previous_prompts_cache: Arc::new(RwLock::new(HashMap::new())),
+            tool_calls: Arc::new(StdMutex::new(HashMap::new())),
+        }
+    }
+
+    /// Build servers added by config with `factory` instead of spawning processes
+    pub fn with_server_factory(mut self, factory: Arc<dyn ServerFactory>) -> Self {
+        self.server_factory = factory;
+        self
+    }
+
+    /// Limit how long each initialization attempt may take
+    pub fn with_init_timeout(mut self, init_timeout: Duration) -> Self {
+        self.init_timeout = init_timeout;
+        self
+    }
+
+    /// Discover tools and prompts again before routing a call once the last
+    /// discovery is older than `cache_ttl`
+    pub fn with_cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    /// Use `clock` to age discovered tools and prompts
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub async fn initialize(configs: Vec<MCPServerConfig>) -> Result<Self, MCPError> {
+        let client = Self::new();
+
+        for config in configs {
+            client.add_server(config).await?;
+        }
+
+        Ok(client)
+    }
+
+    /// Build a server for `config` with the client's factory, initialize and register it.
+    ///
+    /// An invalid config fails right away without building a server.
+    pub async fn add_server(&self, config: MCPServerConfig) -> Result<(), MCPError> {
+        config.validate()?;
+
+        let server = self.server_factory.create(&config);
+        let server_name = server.name().to_string();
+        self.add_server_instance(server).await?;
+
+        let mut server_configs = self.server_configs.write().await;
+        server_configs.insert(server_name, config);
+        Ok(())
+    }
+
+    /// Initialize and register a custom `MCPServer` implementation
+    pub async fn add_server_instance(
+        &self,
+        mut server: Box<dyn MCPServer>,
+    ) -> Result<(), MCPError> {
+        let server_name = server.name().to_string();
+
+        info!("Adding MCP server: {}", server_name);
+
+        if let Err(e) = self.initialize_with_retry(server.as_mut()).await {
+            error!(
+                "Failed to initialize MCP server '{}' after retries: {}",
+                server_name, e
+            );
+            return Err(e);
+        }
+
+        // Store the initialized server
+        let mut servers = self.servers.write().await;
+        servers.insert(server_name.clone(), Arc::new(Mutex::new(server)));
+
+        info!("Successfully added MCP server: {}", server_name);
+        Ok(())
+    }
+
+    pub async fn remove_server(&self, server_name: &str) -> Result<(), MCPError> {
+        info!("Removing MCP server: {}", server_name);
+
+        let mut servers = self.servers.write().await;
+        if let Some(server_arc) = servers.remove(server_name) {
+            let mut server = server_arc.lock().await;
+            server.shutdown().await?;
+
+            // Clear cache entries for this server
+            let mut cache = self.tool_to_server_cache.write().await;
+            cache.remove_server(server_name);
+            drop(cache);
+
+            // Clear previous tools cache for this server
+            let mut previous_cache = self.previous_tools_cache.write().await;
+            previous_cache.remove(server_name);
+            drop(previous_cache);
+
+            // Clear prompt cache entries for this server
+            let mut prompt_cache = self.prompt_to_server_cache.write().await;
+            prompt_cache.remove_server(server_name);
+            drop(prompt_cache);
+
+            // Clear previous prompts cache for this server
+            let mut previous_prompts_cache = self.previous_prompts_cache.write().await;
+            previous_prompts_cache.remove(server_name);
+            drop(previous_prompts_cache);
+
+            let mut server_configs = self.server_configs.write().await;
+            server_configs.remove(server_name);
+            drop(server_configs);
+
+            info!("Successfully removed MCP server: {}", server_name);
+        } else {
+            warn!(
+                "Attempted to remove non-existent MCP server: {}",
+                server_name
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Start the servers listed on a session that are not running yet
+    pub async fn ensure_session_servers(
+        &self,
+        session_id: &SessionId,
+        configs: &[MCPServerConfig],
+    ) -> Result<(), MCPError> {
+        for config in configs {
+            let scoped_name = session_server_name(session_id, &config.name);
+
+            {
+                let servers = self.servers.read().await;
+                if servers.contains_key(&scoped_name) {
+                    continue;
+                }
+                if servers.contains_key(&config.name) {
+                    warn!(
+                        "Session {} server '{}' has the same name as a global server; its tools take precedence in this session",
+                        session_id, config.name
+                    );
+                }
+            }
+
+            self.add_server(MCPServerConfig {
+                name: scoped_name,
+                ..config.clone()
+            })
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Shut down and remove every server started for a session
+    pub async fn remove_session_servers(&self, session_id: &SessionId) -> Result<usize, MCPError> {
+        let prefix = session_server_name(session_id, "");
+        let server_names: Vec<String> = self
+            .servers
+            .read()
+            .await
+            .keys()
+            .filter(|name| name.starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        for server_name in &server_names {
+            self.remove_server(server_name).await?;
+        }
+
+        if !server_names.is_empty() {
+            info!(
+                "Removed {} MCP servers for session {}",
+                server_names.len(),
+                session_id
+            );
+        }
+        Ok(server_names.len())
+    }
+
+    /// Discover the tools visible to a session: those of global servers plus the session's own.
+    ///
+    /// Other sessions' tools are excluded. When a session tool and a global tool share a name,
+    /// the session tool wins. Tools sharing a name otherwise are qualified as `server.tool`,
+    /// using the configured server name for session servers. Session tools are not routed by
+    /// name through [`Self::execute_tool_call`]; they are called on the server the returned
+    /// definition names.
+    pub async fn discover_session_tools(
+        &self,
+        session_id: &SessionId,
+    ) -> Result<Vec<ToolDefinition>, MCPError> {
+        let prefix = session_server_name(session_id, "");
+        let (session_tools, errors) = self.list_tools(|server| server.starts_with(&prefix)).await;
+        if session_tools.is_empty() && !errors.is_empty() {
+            return Err(MCPError::Connection(format!(
+                "Failed to discover tools from the session's servers. Errors: {}",
+                errors.join("; ")
+            )));
+        }
+
+        let session_names: HashMap<String, String> = session_tools
+            .iter()
+            .map(|tool| (tool.name.clone(), tool.server_name.clone()))
+            .collect();
+        let mut tools: Vec<ToolDefinition> = self
+            .discover_tools()
+            .await?
+            .into_iter()
+            .map(|mut tool| {
+                // Collisions are settled again among the tools this session sees
+                tool.unqualify();
+                tool
+            })
+            .filter(|tool| match session_names.get(&tool.name) {
+                Some(session_server) => {
+                    warn!(
+                        "Tool '{}' from session server '{}' overrides global server '{}'",
+                        tool.name, session_server, tool.server_name
+                    );
+                    false
+                }
+                None => true,
+            })
+            .collect();
+        tools.extend(session_tools);
+
+        qualify_collisions(&mut tools, "Tool", configured_server_name);
+
+        sort_by_server(&mut tools);
+        Ok(tools)
+    }
+
+    /// Discover the tools of the global servers and route calls to them by name.
+    ///
+    /// Servers started for a session are left out; see [`Self::discover_session_tools`].
+    pub async fn discover_tools(&self) -> Result<Vec<ToolDefinition>, MCPError> {
+        debug!("Discovering tools from all MCP servers");
+
+        let (mut all_tools, errors) = self.list_tools(|server| !is_session_server(server)).await;
+
+        // Update the tool-to-server cache, qualifying names several servers share
+        let collisions = qualify_collisions(&mut all_tools, "Tool", |server| server);
+        let mut cache = self.tool_to_server_cache.write().await;
+        cache.replace(routes(&all_tools, collisions), self.clock.now());
+        drop(cache);
+        sort_by_server(&mut all_tools);
+
+        if all_tools.is_empty() && !errors.is_empty() {
+            return Err(MCPError::Connection(format!(
+                "Failed to discover tools from any server. Errors: {}",
+                errors.join("; ")
+            )));
+        }
+
+        if !errors.is_empty() {
+            warn!(
+                "Some servers failed during tool discovery: {}",
+                errors.join("; ")
+            );
+        }
+
+        info!("Discovered {} tools from MCP servers", all_tools.len());
+        Ok(all_tools)
+    }
+
+    /// List the tools of the servers whose names `include` accepts, telling servers whose
+    /// tools changed since they were last listed. Failures are returned per server.
+    async fn list_tools(
+        &self,
+        include: impl Fn(&str) -> bool,
+    ) -> (Vec<ToolDefinition>, Vec<String>) {
+        let servers = self.servers.read().await;
+        let mut all_tools = Vec::new();
+        let mut errors = Vec::new();
+        let mut current_tools_by_server = HashMap::new();
+
+        for (server_name, server_arc) in servers.iter() {
+            if !include(server_name) {
+                continue;
+            }
+            let mut server = server_arc.lock().await;
+
+            match server.list_tools().await {
+                Ok(mut tools) => {
+                    debug!("Found {} tools from server '{}'", tools.len(), server_name);
+
+                    // Track current tools for this server
+                    let tool_names: Vec<String> = tools.iter().map(|t| t.name.clone()).collect();
+                    current_tools_by_server.insert(server_name.clone(), tool_names);
+
+                    all_tools.append(&mut tools);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to discover tools from server '{}': {}",
+                        server_name, e
+                    );
+                    errors.push(format!("Server '{}': {}", server_name, e));
+                }
+            }
+        }
+        drop(servers);
+
+        // Check for changes and send notifications
+        let mut previous_tools_cache = self.previous_tools_cache.write().await;
+        let mut servers_with_changes = Vec::new();
+
+        for (server_name, current_tools) in &current_tools_by_server {
+            let previous_tools = previous_tools_cache.get(server_name);
+
+            let tools_changed = match previous_tools {
+                Some(prev) => prev != current_tools,
+                None => !current_tools.is_empty(), // First time seeing this server with tools
+            };
+
+            if tools_changed {
+                debug!(
+                    "Tools changed for server '{}': {:?} -> {:?}",
+                    server_name, previous_tools, current_tools
+                );
+                servers_with_changes.push(server_name.clone());
+            }
+        }
+
+        // Update the previous tools cache for the listed servers
+        previous_tools_cache.retain(|server_name, _| !include(server_name));
+        previous_tools_cache.extend(current_tools_by_server);
+        drop(previous_tools_cache);
+
+        // Send notifications for servers with changes
+        if !servers_with_changes.is_empty() {
+            let servers = self.servers.read().await;
+            for server_name in servers_with_changes {
+                if let Some(server_arc) = servers.get(&server_name) {
+                    let mut server = server_arc.lock().await;
+                    if let Err(e) = server.notify_tools_list_changed().await {
+                        warn!(
+                            "Failed to send tools list changed notification for server '{}': {}",
+                            server_name, e
+                        );
+                    } else {
+                        info!(
+                            "Sent tools list changed notification for server '{}'",
+                            server_name
+                        );
+                    }
+                }
+            }
+        }
+
+        (all_tools, errors)
+    }
+
+    /// Discover the prompts of the global servers and route requests to them by name.
+    ///
+    /// Servers started for a session are left out, so their prompts are not reachable from
+    /// other sessions.
+    pub async fn discover_prompts(&self) -> Result<Vec<PromptDefinition>, MCPError> {
+        debug!("Discovering prompts from all MCP servers");
+
+        let servers = self.servers.read().await;
+        let mut all_prompts = Vec::new();
+        let mut errors = Vec::new();
+        let mut current_prompts_by_server = HashMap::new();
+
+        for (server_name, server_arc) in servers.iter() {
+            if is_session_server(server_name) {
+                continue;
+            }
+            let mut server = server_arc.lock().await;
+
+            match server.list_prompts().await {
+                Ok(mut prompts) => {
+                    debug!(
+                        "Found {} prompts from server '{}'",
+                        prompts.len(),
+                        server_name
+                    );
+
+                    // Track current prompts for this server
+                    let prompt_names: Vec<String> =
+                        prompts.iter().map(|p| p.name.clone()).collect();
+                    current_prompts_by_server.insert(server_name.clone(), prompt_names);
+
+                    all_prompts.append(&mut prompts);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to discover prompts from server '{}': {}",
+                        server_name, e
+                    );
+                    errors.push(format!("Server '{}': {}", server_name, e));
+                }
+            }
+        }
+        drop(servers);
+
+        // Check for changes and send notifications
+        let mut previous_prompts_cache = self.previous_prompts_cache.write().await;
+        let mut servers_with_changes = Vec::new();
+
+        for (server_name, current_prompts) in &current_prompts_by_server {
+            let previous_prompts = previous_prompts_cache.get(server_name as &str);
+
+            let prompts_changed = match previous_prompts {
+                Some(prev) => prev != current_prompts,
+                None => !current_prompts.is_empty(), // First time seeing this server with prompts
+            };
+
+            if prompts_changed {
+                debug!(
+                    "Prompts changed for server '{}': {:?} -> {:?}",
+                    server_name, previous_prompts, current_prompts
+                );
+                servers_with_changes.push(server_name.clone());
+            }
+        }
+
+        // Update the previous prompts cache
+        previous_prompts_cache.clear();
+        previous_prompts_cache.extend(current_prompts_by_server);
+        drop(previous_prompts_cache);
+
+        // Send notifications for servers with changes
+        if !servers_with_changes.is_empty() {
+            let servers = self.servers.read().await;
+            for server_name in servers_with_changes {
+                if let Some(server_arc) = servers.get(&server_name) {
+                    let mut server = server_arc.lock().await;
+                    if let Err(e) = server.notify_prompts_list_changed().await {
+                        warn!(
+                            "Failed to send prompts list changed notification for server '{}': {}",
+                            server_name, e
+                        );
+                    } else {
+                        info!(
+                            "Sent prompts list changed notification for server '{}'",
+                            server_name
+                        );
+                    }
+                }
+            }
+        }
+
+        // Update the prompt-to-server cache, qualifying names several servers share
+        let collisions = qualify_collisions(&mut all_prompts, "Prompt", |server| server);
+        let mut cache = self.prompt_to_server_cache.write().await;
+        cache.replace(routes(&all_prompts, collisions), self.clock.now());
+        drop(cache);
+        sort_by_server(&mut all_prompts);
+
+        if all_prompts.is_empty() && !errors.is_empty() {
+            return Err(MCPError::Connection(format!(
+                "Failed to discover prompts from any server. Errors: {}",
+                errors.join("; ")
+            )));
+        }
+
+        if !errors.is_empty() {
+            warn!(
+                "Some servers failed during prompt discovery: {}",
+                errors.join("; ")
+            );
+        }
+
+        let servers = self.servers.read().await;
+        info!(
+            "Discovered {} prompts from {} servers",
+            all_prompts.len(),
+            servers.len()
+        );
+        Ok(all_prompts)
+    }
+
+    pub async fn get_prompt(
+        &self,
+        server_name: &str,
+        prompt_name: &str,
+        arguments: Option<Value>,
+    ) -> Result<GetPromptResult, MCPError> {
+        debug!(
+            "Getting prompt '{}' from server '{}' with arguments: {}",
+            prompt_name,
+            server_name,
+            redact(arguments.as_ref().unwrap_or(&Value::Null))
+        );
+
+        let servers = self.servers.read().await;
+        let server_arc = servers
+            .get(server_name)
+            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?;
+
+        let mut server = server_arc.lock().await;
+
+        // Execute the prompt get
+        let result = server.get_prompt(prompt_name, arguments).await?;
+
+        info!(
+            "Successfully got prompt '{}' from server '{}'",
+            prompt_name, server_name
+        );
+        Ok(result)
+    }
+
+    pub async fn execute_prompt(
+        &self,
+        prompt_name: &str,
+        arguments: Option<Value>,
+    ) -> Result<GetPromptResult, MCPError> {
+        debug!(
+            "Executing prompt '{}' with arguments: {}",
+            prompt_name,
+            redact(arguments.as_ref().unwrap_or(&Value::Null))
+        );
+
+        let route = self.prompt_route(prompt_name).await?;
+        let (server_name, server_prompt_name) = match route {
+            Route::Server { server_name, name } => (server_name, name),
+            Route::Ambiguous(qualified) => {
+                return Err(MCPError::Protocol(ambiguous_name_error(
+                    "Prompt",
+                    prompt_name,
+                    &qualified,
+                )));
+            }
+        };
+
+        // Execute the prompt get
+        self.get_prompt(&server_name, &server_prompt_name, arguments)
+            .await
+    }
+
+    pub async fn list_servers(&self) -> Vec<String> {
+        let servers = self.servers.read().await;
+        servers.keys().cloned().collect()
+    }
+
+    /// Handshake details of every server that reported them, keyed by server name
+    pub async fn server_infos(&self) -> HashMap<String, MCPServerInfo> {
+        let servers = self.servers.read().await;
+        let mut infos = HashMap::new();
+        for (server_name, server_arc) in servers.iter() {
+            if let Some(info) = server_arc.lock().await.server_info() {
+                infos.insert(server_name.clone(), info);
+            }
+        }
+        infos
+    }
+
+    /// Servers that announced tool or prompt list changes since their lists were
+    /// last discovered, keyed by server name
+    pub async fn list_changes(&self) -> HashMap<String, ListChanges> {
+        let servers = self.servers.read().await;
+        let mut changes = HashMap::new();
+        for (server_name, server_arc) in servers.iter() {
+            let server_changes = server_arc.lock().await.list_changes();
+            if server_changes.any() {
+                changes.insert(server_name.clone(), server_changes);
+            }
+        }
+        changes
+    }
+
+    pub async fn server_count(&self) -> usize {
+        let servers = self.servers.read().await;
+        servers.len()
+    }
+
+    pub async fn call_tool(
+        &self,
+        server_name: &str,
+        tool_name: &str,
+        args: Value,
+    ) -> Result<Value, MCPError> {
+        self.call_tool_reporting(server_name, tool_name, args, None)
+            .await
+    }
+
+    /// Call a tool as the call `call_id`, passing the progress it reports to
+    /// `on_progress`. The call fails with [`MCPError::Cancelled`] once `cancel` fires
+    /// or [`Self::cancel_tool_call`] is given its id, and the server is told to stop.
+    pub async fn call_tool_tracked(
+        &self,
+        call_id: ToolCallId,
+        server_name: &str,
+        tool_name: &str,
+        args: Value,
+        on_progress: Option<ProgressHandler>,
+        cancel: &CancellationToken,
+    ) -> Result<Value, MCPError> {
+        let cancel = cancel.child_token();
+        self.tool_calls
+            .lock()
+            .unwrap()
+            .insert(call_id, cancel.clone());
+        let _tracked = TrackedCall {
+            tool_calls: Arc::clone(&self.tool_calls),
+            call_id,
+        };
+
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => {
+                // Dropping the call sends the server a cancellation notification
+                info!(
+                    "Cancelled tool call {} to '{}' on server '{}'",
+                    call_id, tool_name, server_name
+                );
+                Err(MCPError::Cancelled)
+            }
+            result = self.call_tool_reporting(server_name, tool_name, args, on_progress) => result,
+        }
+    }
+
+    /// Cancel a tool call started with [`Self::call_tool_tracked`] or
+    /// [`Self::execute_tool_call`]; returns whether it was still running
+    pub fn cancel_tool_call(&self, call_id: &ToolCallId) -> bool {
+        match self.tool_calls.lock().unwrap().get(call_id) {
+            Some(cancel) => {
+                cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn call_tool_reporting(
+        &self,
+        server_name: &str,
+        tool_name: &str,
+        args: Value,
+        on_progress: Option<ProgressHandler>,
+    ) -> Result<Value, MCPError> {
+        debug!("Calling tool '{}' on server '{}'", tool_name, server_name);
+
+        let servers = self.servers.read().await;
+        let server_arc = servers
+            .get(server_name)
+            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?
+            .clone();
+
+        drop(servers); // Release read lock
+
+        let mut server = server_arc.lock().await;
+        let caller = server.tool_caller();
+
+        // Execute the tool call, without holding servers that share their connection
+        let result = match (caller, on_progress) {
+            (Some(caller), on_progress) => {
+                drop(server);
+                caller.call(tool_name, args, on_progress).await?
+            }
+            (None, Some(on_progress)) => {
+                server
+                    .call_tool_with_progress(tool_name, args, on_progress)
+                    .await?
+            }
+            (None, None) => server.call_tool(tool_name, args).await?,
+        };
+
+        info!(
+            "Successfully called tool '{}' on server '{}'",
+            tool_name, server_name
+        );
+        Ok(result)
+    }
+
+    pub async fn execute_tool_call(&self, tool_call: &ToolCall) -> Result<ToolResult, MCPError> {
+        debug!(
+            "Executing tool call: {} (ID: {})",
+            tool_call.name, tool_call.id
+        );
+
+        let (server_name, server_tool_name) = self.server_route(&tool_call.name).await?;
+        let result = self
+            .call_routed_tool(
+                tool_call,
+                &server_name,
+                &server_tool_name,
+                None,
+                &CancellationToken::new(),
+            )
+            .await;
+
+        match result {
+            Ok(result) => Ok(ToolResult {
+                call_id: tool_call.id,
+                result,
+                error: None,
+            }),
+            Err(MCPError::Cancelled) => Ok(ToolResult::cancelled(tool_call.id)),
+            Err(e) => Ok(ToolResult {
+                call_id: tool_call.id,
+                result: Value::Null,
+                error: Some(format!("Tool execution failed: {}", e)),
+            }),
+        }
+    }
+
+    /// Run `tool_call` as a tracked call to `server_tool_name` on `server_name`, the
+    /// route its name was given.
+    ///
+    /// When the server is gone or rejects the tool as unknown, tools are discovered
+    /// again and the call is retried once if the tool moved to another server.
+    /// Otherwise the first failure is returned.
+    pub async fn call_routed_tool(
+        &self,
+        tool_call: &ToolCall,
+        server_name: &str,
+        server_tool_name: &str,
+        on_progress: Option<ProgressHandler>,
+        cancel: &CancellationToken,
+    ) -> Result<Value, MCPError> {
+        let result = self
+            .call_tool_tracked(
+                tool_call.id,
+                server_name,
+                server_tool_name,
+                tool_call.arguments.clone(),
+                on_progress.clone(),
+                cancel,
+            )
+            .await;
+        // Session servers are never routed to by name, so there is nothing to rediscover
+        if !matches!(&result, Err(e) if is_missing_tool_error(e)) || is_session_server(server_name)
+        {
+            return result;
+        }
+
+        warn!(
+            "Tool '{}' is gone from server '{}', refreshing tool discovery",
+            tool_call.name, server_name
+        );
+        self.tool_to_server_cache.write().await.invalidate();
+        match self.server_route(&tool_call.name).await {
+            Ok((moved_to, moved_tool_name)) if moved_to != server_name => {
+                info!(
+                    "Tool '{}' moved from server '{}' to '{}', retrying",
+                    tool_call.name, server_name, moved_to
+                );
+                self.call_tool_tracked(
+                    tool_call.id,
+                    &moved_to,
+                    &moved_tool_name,
+                    tool_call.arguments.clone(),
+                    on_progress,
+                    cancel,
+                )
+                .await
+            }
+            Ok(_) => result,
+            Err(e) => {
+                debug!(
+                    "Tool '{}' was not rerouted after refresh: {}",
+                    tool_call.name, e
+                );
+                result
+            }
+        }
+    }
+
+    /// The server and server-side name a tool is routed to; a name several servers
+    /// share is an error listing the qualified names to use instead
+    async fn server_route(&self, tool_name: &str) -> Result<(String, String), MCPError> {
+        match self.tool_route(tool_name).await? {
+            Route::Server { server_name, name } => Ok((server_name, name)),
+            Route::Ambiguous(qualified) => Err(MCPError::ToolCallFailed(ambiguous_name_error(
+                "Tool", tool_name, &qualified,
+            ))),
+        }
+    }
+
+    /// Where a tool is served, discovering tools again first when the cached routes
+    /// are older than the TTL, a server announced a change, or the tool is unknown
+    async fn tool_route(&self, tool_name: &str) -> Result<Route, MCPError> {
+        let cached = {
+            let cache = self.tool_to_server_cache.read().await;
+            if cache.is_stale(self.clock.now(), self.cache_ttl) {
+                None
+            } else {
+                cache.get(tool_name)
+            }
+        };
+        if let Some(route) = cached.filter(|_| !self.lists_changed(|changes| changes.tools)) {
+            debug!("Found tool '{}' in cache: {:?}", tool_name, route);
+            return Ok(route);
+        }
+
+        debug!("Refreshing tool discovery to route tool '{}'", tool_name);
+        self.discover_tools().await?;
+        self.tool_to_server_cache
+            .read()
+            .await
+            .get(tool_name)
+            .ok_or_else(|| {
+                MCPError::ToolCallFailed(format!(
+                    "Tool '{}' not found in any connected server after refresh",
+                    tool_name
+                ))
+            })
+    }
+
+    /// Where a prompt is served, discovering prompts again first when the cached
+    /// routes are older than the TTL, a server announced a change, or the prompt is
+    /// unknown
+    async fn prompt_route(&self, prompt_name: &str) -> Result<Route, MCPError> {
+        let cached = {
+            let cache = self.prompt_to_server_cache.read().await;
+            if cache.is_stale(self.clock.now(), self.cache_ttl) {
+                None
+            } else {
+                cache.get(prompt_name)
+            }
+        };
+        if let Some(route) = cached.filter(|_| !self.lists_changed(|changes| changes.prompts)) {
+            debug!("Found prompt '{}' in cache: {:?}", prompt_name, route);
+            return Ok(route);
+        }
+
+        debug!(
+            "Refreshing prompt discovery to route prompt '{}'",
+            prompt_name
+        );
+        self.discover_prompts().await?;
+        self.prompt_to_server_cache
+            .read()
+            .await
+            .get(prompt_name)
+            .ok_or_else(|| {
+                MCPError::Protocol(format!(
+                    "Prompt '{}' not found in any connected server after refresh",
+                    prompt_name
+                ))
+            })
+    }
+
+    /// Whether any server announced a change `kind` picks out. Servers busy with a
+    /// request are skipped; their announcements are seen on a later call.
+    fn lists_changed(&self, kind: impl Fn(ListChanges) -> bool) -> bool {
+        let Ok(servers) = self.servers.try_read() else {
+            return false;
+        };
+        servers.values().any(|server| {
+            server
+                .try_lock()
+                .is_ok_and(|server| kind(server.list_changes()))
+        })
+    }
+
+    /// Discover the tools and prompts of every server again, replacing the cached
+    /// routes whatever their age
+    pub async fn refresh(&self) -> Result<(), MCPError> {
+        self.discover_tools().await?;
+        self.discover_prompts().await?;
+        Ok(())
+    }
+
+    pub async fn server_health(&self, server_name: &str) -> Result<HealthStatus, MCPError> {
+        debug!("Checking health for server: {}", server_name);
+
+        let servers = self.servers.read().await;
+        let server_arc = servers
+            .get(server_name)
+            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?;
+
+        let server = server_arc.lock().await;
+        server.health().await
+    }
+
+    pub async fn health_check_all(&self) -> HashMap<String, HealthStatus> {
+        debug!("Performing health check on all servers");
+
+        let servers = self.servers.read().await;
+        let mut health_results = HashMap::new();
+
+        for (server_name, server_arc) in servers.iter() {
+            let server = server_arc.lock().await;
+
+            match server.health().await {
+                Ok(status) => {
+                    health_results.insert(server_name.clone(), status);
+                }
+                Err(e) => {
+                    warn!("Health check failed for server '{}': {}", server_name, e);
+                    health_results.insert(
+                        server_name.clone(),
+                        HealthStatus::Unhealthy(format!("Health check error: {}", e)),
+                    );
+                }
+            }
+        }
+
+        info!(
+            "Health check completed for {} servers",
+            health_results.len()
+        );
+        health_results
+    }
+
+    pub async fn restart_server(&self, server_name: &str) -> Result<(), MCPError> {
+        info!("Restarting MCP server: {}", server_name);
+
+        let servers = self.servers.read().await;
+        let server_arc = servers
+            .get(server_name)
+            .ok_or_else(|| MCPError::ServerNotFound(server_name.to_string()))?
+            .clone();
+
+        drop(servers); // Release read lock
+
+        let mut server = server_arc.lock().await;
+
+        // Shutdown existing server
+        if let Err(e) = server.shutdown().await {
+            warn!("Error during server shutdown for '{}': {}", server_name, e);
+        }
+
+        // Servers added by config start over as a fresh instance from the factory;
+        // custom instances are re-initialized in place
+        let config = self.server_configs.read().await.get(server_name).cloned();
+        if let Some(config) = config {
+            *server = self.server_factory.create(&config);
+        }
+
+        if let Err(e) = self.initialize_with_retry(server.as_mut()).await {
+            error!(
+                "Failed to restart MCP server '{}' after retries: {}",
+                server_name, e
+            );
+            return Err(e);
+        }
+
+        info!("Successfully restarted MCP server: {}", server_name);
+        Ok(())
+    }
+
+    pub async fn shutdown_all(&self) -> Result<(), MCPError> {
+        info!("Shutting down all MCP servers");
+
+        let mut servers = self.servers.write().await;
+        let mut errors = Vec::new();
+
+        self.server_configs.write().await.clear();
+
+        for (server_name, server_arc) in servers.drain() {
+            let mut server = server_arc.lock().await;
+
+            if let Err(e) = server.shutdown().await {
+                errors.push(format!("Server '{}': {}", server_name, e));
+            } else {
+                info!("Successfully shut down server: {}", server_name);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(MCPError::Connection(format!(
+                "Errors during shutdown: {}",
+                errors.join("; ")
+            )));
+        }
+
+        info!("All MCP servers shut down successfully");
+        Ok(())
+    }
+
+    /// Run `server.initialize()` until it succeeds, retrying connection failures and
+    /// timed out attempts with backoff. Protocol errors are permanent and returned at once.
+    async fn initialize_with_retry(&self, server: &mut dyn MCPServer) -> Result<(), MCPError> {
+        let mut retry = 0;
+        loop {
+            let error = match timeout(self.init_timeout, server.initialize()).await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) => e,
+                Err(_) => {
+                    // Stop whatever the hung attempt left running before trying again
+                    if let Err(e) = server.shutdown().await {
+                        debug!("Error shutting down timed out MCP server: {}", e);
+                    }
+                    MCPError::Connection(format!(
+                        "MCP server '{}' did not finish initializing within {:?}",
+                        server.name(),
+                        self.init_timeout
+                    ))
+                }
+            };
+
+            if !matches!(error, MCPError::Connection(_)) || retry >= self.retry_config.max_retries {
+                return Err(error);
+            }
+
+            retry += 1;
+            let delay = self.retry_config.delay_before_retry(retry);
+            debug!(
+                "Retry attempt {} failed, waiting {:?} before next attempt: {}",
+                retry, delay, error
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+impl Default for MCPClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PromptArgument, ToolProgress};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex as StdMutex;
+    use std::time::Instant;
+    use tokio::time::Duration;
+
+    // Mock server implementation for testing
+    struct MockMCPServer {
+        name: String,
+        tools: Vec<ToolDefinition>,
+        prompts: Vec<PromptDefinition>,
+        should_fail: bool,
+        fail_on_health: bool,
+    }
+
+    impl MockMCPServer {
+        fn new(name: &str, tools: Vec<ToolDefinition>) -> Self {
+            Self {
+                name: name.to_string(),
+                tools,
+                prompts: Vec::new(),
+                should_fail: false,
+                fail_on_health: false,
+            }
+        }
+
+        fn with_prompts(mut self, prompts: Vec<PromptDefinition>) -> Self {
+            self.prompts = prompts;
+            self
+        }
+
+        fn with_failure(mut self, should_fail: bool) -> Self {
+            self.should_fail = should_fail;
+            self
+        }
+
+        #[allow(dead_code)]
+        fn with_health_failure(mut self, fail_on_health: bool) -> Self {
+            self.fail_on_health = fail_on_health;
+            self
+        }
+    }
+
+    #[async_trait]
+    impl MCPServer for MockMCPServer {
+        async fn initialize(&mut self) -> Result<(), MCPError> {
+            if self.should_fail {
+                return Err(MCPError::Connection(
+                    "Mock initialization failure".to_string(),
+                ));
+            }
+            Ok(())
+        }
+
+        async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+            if self.should_fail {
+                return Err(MCPError::Protocol("Mock tool listing failure".to_string()));
+            }
+            Ok(self.tools.clone())
+        }
+
+        async fn call_tool(&mut self, tool_name: &str, args: Value) -> Result<Value, MCPError> {
+            if self.should_fail {
+                return Err(MCPError::ToolCallFailed(format!(
+                    "Mock tool call failure for {}",
+                    tool_name
+                )));
+            }
+
+            // Simulate successful tool execution
+            if self.tools.iter().any(|tool| tool.name == tool_name) {
+                Ok(json!({
+                    "result": format!("Mock result for {} with args: {}", tool_name, args),
+                    "success": true
+                }))
+            } else {
+                Err(MCPError::ToolCallFailed(format!(
+                    "Tool '{}' not found",
+                    tool_name
+                )))
+            }
+        }
+
+        async fn health(&self) -> Result<HealthStatus, MCPError> {
+            if self.fail_on_health {
+                Err(MCPError::Connection("Health check failed".to_string()))
+            } else if self.should_fail {
+                Ok(HealthStatus::Unhealthy("Mock server unhealthy".to_string()))
+            } else {
+                Ok(HealthStatus::Healthy)
+            }
+        }
+
+        async fn shutdown(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+            if self.should_fail {
+                return Err(MCPError::Protocol("Mock notification failure".to_string()));
+            }
+            Ok(())
+        }
+
+        async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+            if self.should_fail {
+                return Err(MCPError::Protocol(
+                    "Mock prompt listing failure".to_string(),
+                ));
+            }
+            Ok(self.prompts.clone())
+        }
+
+        async fn get_prompt(
+            &mut self,
+            prompt_name: &str,
+            arguments: Option<Value>,
+        ) -> Result<GetPromptResult, MCPError> {
+            if self.should_fail {
+                return Err(MCPError::Protocol(format!(
+                    "Mock prompt get failure for {}",
+                    prompt_name
+                )));
+            }
+
+            // Simulate successful prompt execution
+            if self.prompts.iter().any(|prompt| prompt.name == prompt_name) {
+                Ok(GetPromptResult {
+                    description: Some(format!(
+                        "Mock prompt result for {} with args: {:?}",
+                        prompt_name, arguments
+                    )),
+                    messages: Vec::new(),
+                })
+            } else {
+                Err(MCPError::Protocol(format!(
+                    "Prompt '{}' not found",
+                    prompt_name
+                )))
+            }
+        }
+
+        async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+            if self.should_fail {
+                return Err(MCPError::Protocol(
+                    "Mock prompt notification failure".to_string(),
+                ));
+            }
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mcp_client_initialization() {
+        let client = MCPClient::new();
+        assert_eq!(client.server_count().await, 0);
+        assert!(client.list_servers().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retry_config() {
+        let config = RetryConfig::default();
+        assert_eq!(config.max_retries, 3);
+        assert_eq!(config.initial_delay, Duration::from_millis(100));
+        assert_eq!(config.backoff_multiplier, 2.0);
+
+        let custom_config = RetryConfig {
+            max_retries: 5,
+            initial_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(10),
+            backoff_multiplier: 1.5,
+        };
+
+        let client = MCPClient::with_retry_config(custom_config.clone());
+        assert_eq!(client.retry_config.max_retries, 5);
+    }
+
+    #[tokio::test]
+    async fn test_health_status() {
+        let healthy = HealthStatus::Healthy;
+        let unhealthy = HealthStatus::Unhealthy("Test error".to_string());
+        let unknown = HealthStatus::Unknown;
+
+        match healthy {
+            HealthStatus::Healthy => {}
+            _ => panic!("Expected Healthy status"),
+        }
+
+        match unhealthy {
+            HealthStatus::Unhealthy(msg) => assert_eq!(msg, "Test error"),
+            _ => panic!("Expected Unhealthy status"),
+        }
+
+        match unknown {
+            HealthStatus::Unknown => {}
+            _ => panic!("Expected Unknown status"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_definition_creation() {
+        let tool = ToolDefinition {
+            name: "test_tool".to_string(),
+            description: "A test tool".to_string(),
+            parameters: json!({"type": "object"}),
+            server_name: "test_server".to_string(),
+            original_name: None,
+        };
+
+        assert_eq!(tool.name, "test_tool");
+        assert_eq!(tool.description, "A test tool");
+        assert_eq!(tool.server_name, "test_server");
+    }
+
+    #[tokio::test]
+    async fn test_mock_server_functionality() {
+        let tools = vec![ToolDefinition {
+            name: "list_files".to_string(),
+            description: "List files in directory".to_string(),
+            parameters: json!({"type": "object"}),
+            server_name: "test_server".to_string(),
+            original_name: None,
+        }];
+
+        let mut server = MockMCPServer::new("test_server", tools);
+
+        // Test initialization
+        assert!(server.initialize().await.is_ok());
+
+        // Test tool listing
+        let listed_tools = server.list_tools().await.unwrap();
+        assert_eq!(listed_tools.len(), 1);
+        assert_eq!(listed_tools[0].name, "list_files");
+
+        // Test tool calling
+        let result = server
+            .call_tool("list_files", json!({"path": "/tmp"}))
+            .await
+            .unwrap();
+        assert!(result.get("success").unwrap().as_bool().unwrap());
+
+        // Test health check
+        let health = server.health().await.unwrap();
+        match health {
+            HealthStatus::Healthy => {}
+            _ => panic!("Expected healthy status"),
+        }
+
+        // Test shutdown
+        assert!(server.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_mock_server_failures() {
+        let tools = vec![ToolDefinition {
+            name: "failing_tool".to_string(),
+            description: "A tool that fails".to_string(),
+            parameters: json!({"type": "object"}),
+            server_name: "failing_server".to_string(),
+            original_name: None,
+        }];
+
+        let mut failing_server = MockMCPServer::new("failing_server", tools).with_failure(true);
+
+        // Test initialization failure
+        assert!(failing_server.initialize().await.is_err());
+
+        // Reset failure for other tests
+        failing_server.should_fail = false;
+        assert!(failing_server.initialize().await.is_ok());
+
+        // Test tool listing failure
+        failing_server.should_fail = true;
+        assert!(failing_server.list_tools().await.is_err());
+
+        // Test tool call failure
+        assert!(failing_server
+            .call_tool("failing_tool", json!({}))
+            .await
+            .is_err());
+
+        // Test health check failure
+        let health = failing_server.health().await.unwrap();
+        match health {
+            HealthStatus::Unhealthy(_) => {}
+            _ => panic!("Expected unhealthy status"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_and_result() {
+        let call_id = ToolCallId::new();
+        let tool_call = ToolCall {
+            id: call_id,
+            name: "test_tool".to_string(),
+            arguments: json!({"param": "value"}),
+        };
+
+        let result = ToolResult {
+            call_id: tool_call.id,
+            result: json!({"status": "success"}),
+            error: None,
+        };
+
+        assert_eq!(result.call_id, call_id);
+        assert!(result.error.is_none());
+
+        let error_result = ToolResult {
+            call_id: tool_call.id,
+            result: Value::Null,
+            error: Some("Tool execution failed".to_string()),
+        };
+
+        assert_eq!(error_result.call_id, call_id);
+        assert!(error_result.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_server_config_validation() {
+        let valid_config = MCPServerConfig {
+            name: "filesystem".to_string(),
+            command: "npx".to_string(),
+            args: vec![
+                "-y".to_string(),
+                "@modelcontextprotocol/server-filesystem".to_string(),
+            ],
+            timeout_secs: None,
+            strict_protocol_version: false,
+        };
+
+        assert!(valid_config.validate().is_ok());
+
+        let invalid_config = MCPServerConfig {
+            name: "".to_string(),
+            command: "npx".to_string(),
+            args: vec![],
+            timeout_secs: None,
+            strict_protocol_version: false,
+        };
+
+        assert!(invalid_config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mcp_client_default() {
+        let client1 = MCPClient::default();
+        let client2 = MCPClient::new();
+
+        assert_eq!(client1.server_count().await, client2.server_count().await);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_operations() {
+        let client = Arc::new(MCPClient::new());
+        let mut handles = vec![];
+
+        // Simulate concurrent server operations
+        for i in 0..5 {
+            let client_clone = client.clone();
+            let handle = tokio::spawn(async move {
+                let _server_name = format!("concurrent_server_{}", i);
+                let servers = client_clone.list_servers().await;
+                servers.len()
+            });
+            handles.push(handle);
+        }
+
+        // Wait for all operations to complete
+        for handle in handles {
+            let result = handle.await.unwrap();
+            assert_eq!(result, 0); // No servers initially
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_propagation() {
+        // Test that errors are properly wrapped and propagated
+        let connection_error = MCPError::Connection("Test connection error".to_string());
+        let protocol_error = MCPError::Protocol("Test protocol error".to_string());
+        let server_not_found = MCPError::ServerNotFound("test_server".to_string());
+        let tool_call_failed = MCPError::ToolCallFailed("Test tool call failure".to_string());
+
+        assert!(matches!(connection_error, MCPError::Connection(_)));
+        assert!(matches!(protocol_error, MCPError::Protocol(_)));
+        assert!(matches!(server_not_found, MCPError::ServerNotFound(_)));
+        assert!(matches!(tool_call_failed, MCPError::ToolCallFailed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_tools_list_changed_notification() {
+        let tools = vec![ToolDefinition {
+            name: "test_tool".to_string(),
+            description: "A test tool".to_string(),
+            parameters: json!({"type": "object"}),
+            server_name: "test_server".to_string(),
+            original_name: None,
+        }];
+
+        let mut server = MockMCPServer::new("test_server", tools);
+
+        // Test successful notification
+        assert!(server.notify_tools_list_changed().await.is_ok());
+
+        // Test failed notification
+        server.should_fail = true;
+        assert!(server.notify_tools_list_changed().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tools_list_change_detection() {
+        let client = MCPClient::new();
+
+        // First discovery - should detect changes (empty -> some tools)
+        let _initial_tools = vec![ToolDefinition {
+            name: "tool1".to_string(),
+            description: "Tool 1".to_string(),
+            parameters: json!({"type": "object"}),
+            server_name: "server1".to_string(),
+            original_name: None,
+        }];
+
+        // Simulate adding tools to the cache
+        {
+            let mut cache = client.previous_tools_cache.write().await;
+            cache.insert("server1".to_string(), vec!["tool1".to_string()]);
+        }
+
+        // Second discovery with same tools - should not detect changes
+        {
+            let cache = client.previous_tools_cache.read().await;
+            let previous = cache.get("server1");
+            let current = vec!["tool1".to_string()];
+            assert_eq!(previous, Some(&current));
+        }
+
+        // Third discovery with different tools - should detect changes
+        let new_tools = vec!["tool1".to_string(), "tool2".to_string()];
+        {
+            let cache = client.previous_tools_cache.read().await;
+            let previous = cache.get("server1");
+            assert_ne!(previous, Some(&new_tools));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mcp_client_with_previous_tools_cache() {
+        let client = MCPClient::new();
+
+        // Test that previous tools cache is properly initialized
+        {
+            let cache = client.previous_tools_cache.read().await;
+            assert!(cache.is_empty());
+        }
+
+        // Test manual cache updates
+        {
+            let mut cache = client.previous_tools_cache.write().await;
+            cache.insert(
+                "test_server".to_string(),
+                vec!["tool1".to_string(), "tool2".to_string()],
+            );
+        }
+
+        {
+            let cache = client.previous_tools_cache.read().await;
+            assert_eq!(cache.len(), 1);
+            assert_eq!(
+                cache.get("test_server"),
+                Some(&vec!["tool1".to_string(), "tool2".to_string()])
+            );
+        }
+    }
+
+    fn tool_on(name: &str, server_name: &str) -> ToolDefinition {
+        ToolDefinition {
+            name: name.to_string(),
+            description: format!("{} on {}", name, server_name),
+            parameters: json!({"type": "object"}),
+            server_name: server_name.to_string(),
+            original_name: None,
+        }
+    }
+
+    async fn add_mock(client: &MCPClient, server_name: &str, tool_names: &[&str]) {
+        let tools = tool_names
+            .iter()
+            .map(|name| tool_on(name, server_name))
+            .collect();
+        client
+            .add_server_instance(Box::new(MockMCPServer::new(server_name, tools)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_tools_are_isolated() {
+        let client = MCPClient::new();
+        let session_a = SessionId::new();
+        let session_b = SessionId::new();
+        let server_a = session_server_name(&session_a, "tools");
+        let server_b = session_server_name(&session_b, "tools");
+
+        add_mock(&client, "global", &["search", "list_files"]).await;
+        add_mock(&client, &server_a, &["search", "only_a"]).await;
+        add_mock(&client, &server_b, &["only_b"]).await;
+
+        let tools_a = client.discover_session_tools(&session_a).await.unwrap();
+        let names_a: Vec<&str> = tools_a.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names_a, vec!["list_files", "only_a", "search"]);
+
+        // The session's own server wins the name collision with the global one
+        let search = tools_a.iter().find(|t| t.name == "search").unwrap();
+        assert_eq!(search.server_name, server_a);
+
+        let tools_b = client.discover_session_tools(&session_b).await.unwrap();
+        let names_b: Vec<&str> = tools_b.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names_b, vec!["list_files", "search", "only_b"]);
+        let search = tools_b.iter().find(|t| t.name == "search").unwrap();
+        assert_eq!(search.server_name, "global");
+
+        // Session tools stay out of the global tools and their routes
+        let global = client.discover_tools().await.unwrap();
+        let names: Vec<&str> = global.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["list_files", "search"]);
+        assert!(client
+            .execute_tool_call(&tool_call("only_a"))
+            .await
+            .is_err());
+        let result = client
+            .execute_tool_call(&tool_call("search"))
+            .await
+            .unwrap();
+        assert_eq!(result.error, None);
+
+        // Another session's server with the same tool names does not qualify them
+        add_mock(
+            &client,
+            &session_server_name(&session_b, "more"),
+            &["only_a"],
+        )
+        .await;
+        let tools_a = client.discover_session_tools(&session_a).await.unwrap();
+        assert!(tools_a.iter().all(|t| t.original_name.is_none()));
+    }
+
+    fn tool_call(name: &str) -> ToolCall {
+        ToolCall {
+            id: ToolCallId::new(),
+            name: name.to_string(),
+            arguments: json!({}),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_colliding_tools_are_qualified() {
+        let client = MCPClient::new();
+        add_mock(&client, "beta", &["search", "fetch"]).await;
+        add_mock(&client, "alpha", &["search", "list_files"]).await;
+
+        let mut tools = client.discover_tools().await.unwrap();
+        tools.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["alpha.search", "beta.search", "fetch", "list_files"]
+        );
+
+        let search = &tools[0];
+        assert_eq!(search.server_name, "alpha");
+        assert_eq!(search.original_name.as_deref(), Some("search"));
+        assert_eq!(search.server_tool_name(), "search");
+        assert_eq!(tools[2].original_name, None);
+    }
+
+    #[tokio::test]
+    async fn test_colliding_tool_call_routing() {
+        let client = MCPClient::new();
+        add_mock(&client, "alpha", &["search"]).await;
+        add_mock(&client, "beta", &["search", "fetch"]).await;
+        client.discover_tools().await.unwrap();
+
+        // A qualified name reaches its server under the server's own name
+        let result = client
+            .execute_tool_call(&tool_call("beta.search"))
+            .await
+            .unwrap();
+        assert_eq!(result.error, None);
+        assert!(result.result["result"]
+            .as_str()
+            .unwrap()
+            .starts_with("Mock result for search"));
+
+        // An unqualified name is refused with the alternatives
+        match client.execute_tool_call(&tool_call("search")).await {
+            Err(MCPError::ToolCallFailed(msg)) => assert_eq!(
+                msg,
+                "Tool 'search' is offered by several MCP servers; use one of: alpha.search, beta.search"
+            ),
+            other => panic!("Expected ToolCallFailed, got {:?}", other),
+        }
+
+        // Names only one server offers are unaffected
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert_eq!(result.error, None);
+
+        // Without the collision the plain name routes again
+        client.remove_server("alpha").await.unwrap();
+        let result = client
+            .execute_tool_call(&tool_call("search"))
+            .await
+            .unwrap();
+        assert_eq!(result.error, None);
+        assert!(client
+            .execute_tool_call(&tool_call("beta.search"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_session_tool_collisions_are_qualified() {
+        let client = MCPClient::new();
+        let session_id = SessionId::new();
+        add_mock(&client, "alpha", &["search", "status"]).await;
+        add_mock(&client, "beta", &["search"]).await;
+        add_mock(
+            &client,
+            &session_server_name(&session_id, "one"),
+            &["status", "x"],
+        )
+        .await;
+        add_mock(&client, &session_server_name(&session_id, "two"), &["x"]).await;
+
+        let tools = client.discover_session_tools(&session_id).await.unwrap();
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_str()).collect();
+        // Global servers sharing a name are qualified, session servers by their
+        // configured name, and a session tool still overrides a global one
+        assert_eq!(
+            names,
+            vec!["alpha.search", "beta.search", "one.x", "status", "two.x"]
+        );
+        let status = tools.iter().find(|t| t.name == "status").unwrap();
+        assert_eq!(status.server_name, session_server_name(&session_id, "one"));
+        assert_eq!(status.original_name, None);
+    }
+
+    #[tokio::test]
+    async fn test_session_prompts_stay_out_of_global_prompts() {
+        let prompt = |name: &str, server_name: &str| PromptDefinition {
+            name: name.to_string(),
+            title: None,
+            description: None,
+            arguments: None,
+            server_name: server_name.to_string(),
+            original_name: None,
+        };
+        let client = MCPClient::new();
+        let session_server = session_server_name(&SessionId::new(), "prompts");
+        for (server_name, prompt_name) in [("global", "summarize"), (&session_server, "secret")] {
+            client
+                .add_server_instance(Box::new(
+                    MockMCPServer::new(server_name, Vec::new())
+                        .with_prompts(vec![prompt(prompt_name, server_name)]),
+                ))
+                .await
+                .unwrap();
+        }
+
+        let prompts = client.discover_prompts().await.unwrap();
+        let names: Vec<&str> = prompts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["summarize"]);
+        assert!(client.execute_prompt("secret", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_colliding_prompts_are_qualified() {
+        let prompt = |server_name: &str| PromptDefinition {
+            name: "summarize".to_string(),
+            title: None,
+            description: None,
+            arguments: None,
+            server_name: server_name.to_string(),
+            original_name: None,
+        };
+        let client = MCPClient::new();
+        for server_name in ["alpha", "beta"] {
+            client
+                .add_server_instance(Box::new(
+                    MockMCPServer::new(server_name, Vec::new())
+                        .with_prompts(vec![prompt(server_name)]),
+                ))
+                .await
+                .unwrap();
+        }
+
+        let mut prompts = client.discover_prompts().await.unwrap();
+        prompts.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = prompts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["alpha.summarize", "beta.summarize"]);
+        assert_eq!(prompts[1].server_prompt_name(), "summarize");
+
+        let result = client
+            .execute_prompt("alpha.summarize", None)
+            .await
+            .unwrap();
+        assert!(result
+            .description
+            .unwrap()
+            .starts_with("Mock prompt result for summarize"));
+
+        match client.execute_prompt("summarize", None).await {
+            Err(MCPError::Protocol(msg)) => {
+                assert!(
+                    msg.ends_with("use one of: alpha.summarize, beta.summarize"),
+                    "{}",
+                    msg
+                )
+            }
+            other => panic!("Expected Protocol error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_configured_server_name() {
+        let session_id = SessionId::new();
+        assert_eq!(
+            configured_server_name(&session_server_name(&session_id, "files")),
+            "files"
+        );
+        assert_eq!(configured_server_name("files"), "files");
+    }
+
+    #[tokio::test]
+    async fn test_remove_session_servers() {
+        let client = MCPClient::new();
+        let session_a = SessionId::new();
+        let session_b = SessionId::new();
+
+        add_mock(&client, "global", &["search"]).await;
+        add_mock(&client, &session_server_name(&session_a, "one"), &["a1"]).await;
+        add_mock(&client, &session_server_name(&session_a, "two"), &["a2"]).await;
+        add_mock(&client, &session_server_name(&session_b, "one"), &["b1"]).await;
+
+        assert_eq!(client.remove_session_servers(&session_a).await.unwrap(), 2);
+        assert_eq!(client.server_count().await, 2);
+        assert_eq!(client.remove_session_servers(&session_a).await.unwrap(), 0);
+
+        let names: Vec<String> = client
+            .discover_session_tools(&session_b)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["search", "b1"]);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_session_servers_skips_running_servers() {
+        let client = MCPClient::new();
+        let session_id = SessionId::new();
+        add_mock(&client, &session_server_name(&session_id, "tools"), &["t"]).await;
+
+        let config = MCPServerConfig {
+            name: "tools".to_string(),
+            command: "does-not-exist".to_string(),
+            args: vec![],
+            timeout_secs: None,
+            strict_protocol_version: false,
+        };
+
+        // Already registered under the scoped name, so no process is spawned
+        client
+            .ensure_session_servers(&session_id, &[config])
+            .await
+            .unwrap();
+        assert_eq!(client.server_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_definition_creation() {
+        let prompt = PromptDefinition {
+            name: "test_prompt".to_string(),
+            title: None,
+            description: Some("A test prompt".to_string()),
+            arguments: Some(vec![PromptArgument {
+                name: "user_input".to_string(),
+                description: Some("User input for the prompt".to_string()),
+                required: Some(true),
+            }]),
+            server_name: "test_server".to_string(),
+            original_name: None,
+        };
+
+        assert_eq!(prompt.name, "test_prompt");
+        assert_eq!(prompt.description, Some("A test prompt".to_string()));
+        assert_eq!(prompt.server_name, "test_server");
+        assert_eq!(prompt.arguments.as_ref().unwrap().len(), 1);
+        assert_eq!(prompt.arguments.as_ref().unwrap()[0].name, "user_input");
+        assert_eq!(prompt.arguments.as_ref().unwrap()[0].required, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_mock_server_prompt_functionality() {
+        let prompts = vec![PromptDefinition {
+            name: "code_review".to_string(),
+            title: None,
+            description: Some("Review code for best practices".to_string()),
+            arguments: Some(vec![PromptArgument {
+                name: "code".to_string(),
+                description: Some("The code to review".to_string()),
+                required: Some(true),
+            }]),
+            server_name: "test_server".to_string(),
+            original_name: None,
+        }];
+
+        let mut server = MockMCPServer::new("test_server", Vec::new()).with_prompts(prompts);
+
+        // Test initialization
+        assert!(server.initialize().await.is_ok());
+
+        // Test prompt listing
+        let listed_prompts = server.list_prompts().await.unwrap();
+        assert_eq!(listed_prompts.len(), 1);
+        assert_eq!(listed_prompts[0].name, "code_review");
+
+        // Test prompt getting
+        let result = server
+            .get_prompt("code_review", Some(json!({"code": "def hello(): pass"})))
+            .await
+            .unwrap();
+        assert!(result.description.is_some());
+        assert!(result.description.unwrap().contains("code_review"));
+
+        // Test prompt notification
+        assert!(server.notify_prompts_list_changed().await.is_ok());
+
+        // Test health check
+        let health = server.health().await.unwrap();
+        match health {
+            HealthStatus::Healthy => {}
+            _ => panic!("Expected healthy status"),
+        }
+
+        // Test shutdown
+        assert!(server.shutdown().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_prompt_id() {
+        use crate::types::PromptId;
+
+        let prompt_id = PromptId::new();
+        let prompt_id_str = prompt_id.to_string();
+
+        // Test that we can parse back the string representation
+        let parsed_prompt_id: PromptId = prompt_id_str.parse().unwrap();
+        assert_eq!(prompt_id, parsed_prompt_id);
+
+        // Test serialization
+        let serialized = serde_json::to_string(&prompt_id).unwrap();
+        let deserialized: PromptId = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(prompt_id, deserialized);
+
+        // Test Display trait
+        assert!(!format!("{}", prompt_id).is_empty());
+    }
+
+    fn tools_only_config(strict: bool) -> MCPServerConfig {
+        MCPServerConfig {
+            name: "tools-only".to_string(),
+            command: "unused".to_string(),
+            args: vec![],
+            timeout_secs: None,
+            strict_protocol_version: strict,
+        }
+    }
+
+    #[test]
+    fn test_parse_initialize_result() {
+        let result = json!({
+            "protocolVersion": "2024-11-05",
+            "capabilities": {
+                "tools": {"listChanged": true},
+                "prompts": {},
+                "logging": {}
+            },
+            "serverInfo": {"name": "example-server", "version": "1.2.0"}
+        });
+
+        let info = parse_initialize_result(&result).unwrap();
+        assert_eq!(info.name, "example-server");
+        assert_eq!(info.version, "1.2.0");
+        assert_eq!(info.protocol_version, "2024-11-05");
+        assert_eq!(info.capabilities, ["logging", "prompts", "tools"]);
+        assert!(info.supports("prompts"));
+        assert!(check_protocol_version("example", &info, true).is_ok());
+    }
+
+    #[test]
+    fn test_parse_initialize_result_tools_only_and_invalid() {
+        let info = parse_initialize_result(&json!({
+            "protocolVersion": "2025-03-26",
+            "capabilities": {"tools": {}}
+        }))
+        .unwrap();
+        assert_eq!(info.name, "");
+        assert!(info.supports("tools"));
+        assert!(!info.supports("prompts"));
+
+        assert!(parse_initialize_result(&json!({"capabilities": {}})).is_err());
+        assert!(parse_initialize_result(&json!({
+            "protocolVersion": "2024-11-05",
+            "capabilities": ["tools"]
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn test_unsupported_protocol_version() {
+        let info = parse_initialize_result(&json!({
+            "protocolVersion": "1999-01-01",
+            "capabilities": {"tools": {}}
+        }))
+        .unwrap();
+
+        assert!(check_protocol_version("old", &info, false).is_ok());
+        match check_protocol_version("old", &info, true) {
+            Err(MCPError::Protocol(msg)) => assert!(msg.contains("1999-01-01")),
+            other => panic!("Expected protocol error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompts_skipped_without_capability() {
+        let mut server = MCPServerImpl::new(tools_only_config(false));
+        server.initialized = true;
+        server.server_info = Some(MCPServerInfo {
+            name: "tools-only".to_string(),
+            version: "0.1.0".to_string(),
+            protocol_version: CLIENT_PROTOCOL_VERSION.to_string(),
+            capabilities: vec!["tools".to_string()],
+        });
+
+        // No process is attached, so any request sent to it would fail
+        assert!(server.list_prompts().await.unwrap().is_empty());
+        assert!(server.notify_prompts_list_changed().await.is_ok());
+        assert!(matches!(
+            server.get_prompt("anything", None).await,
+            Err(MCPError::Protocol(_))
+        ));
+        assert_eq!(server.server_info().unwrap().capabilities, ["tools"]);
+
+        server.shutdown().await.unwrap();
+        assert!(server.server_info().is_none());
+    }
+
+    /// How servers from a [`ChaosFactory`] behave when initialized
+    #[derive(Clone, Copy)]
+    enum Chaos {
+        /// Fail with a connection error this many times in total, then succeed
+        FailTimes(usize),
+        FailAlways,
+        FailProtocol,
+        Hang,
+    }
+
+    /// Builds servers that misbehave on purpose, recording every initialize attempt
+    struct ChaosFactory {
+        chaos: Chaos,
+        created: AtomicUsize,
+        attempts: Arc<StdMutex<Vec<Instant>>>,
+    }
+
+    impl ChaosFactory {
+        fn new(chaos: Chaos) -> Arc<Self> {
+            Arc::new(Self {
+                chaos,
+                created: AtomicUsize::new(0),
+                attempts: Arc::new(StdMutex::new(Vec::new())),
+            })
+        }
+
+        fn created(&self) -> usize {
+            self.created.load(Ordering::SeqCst)
+        }
+
+        fn attempts(&self) -> Vec<Instant> {
+            self.attempts.lock().unwrap().clone()
+        }
+    }
+
+    impl ServerFactory for ChaosFactory {
+        fn create(&self, config: &MCPServerConfig) -> Box<dyn MCPServer> {
+            self.created.fetch_add(1, Ordering::SeqCst);
+            Box::new(ChaosServer {
+                name: config.name.clone(),
+                chaos: self.chaos,
+                attempts: Arc::clone(&self.attempts),
+            })
+        }
+    }
+
+    struct ChaosServer {
+        name: String,
+        chaos: Chaos,
+        attempts: Arc<StdMutex<Vec<Instant>>>,
+    }
+
+    #[async_trait]
+    impl MCPServer for ChaosServer {
+        async fn initialize(&mut self) -> Result<(), MCPError> {
+            let attempt = {
+                let mut attempts = self.attempts.lock().unwrap();
+                attempts.push(Instant::now());
+                attempts.len()
+            };
+            match self.chaos {
+                Chaos::FailTimes(n) if attempt > n => Ok(()),
+                Chaos::FailTimes(_) | Chaos::FailAlways => {
+                    Err(MCPError::Connection("connection refused".to_string()))
+                }
+                Chaos::FailProtocol => Err(MCPError::Protocol("unsupported".to_string())),
+                Chaos::Hang => std::future::pending().await,
+            }
+        }
+
+        async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+            Ok(vec![])
+        }
+
+        async fn call_tool(&mut self, _tool_name: &str, _args: Value) -> Result<Value, MCPError> {
+            Ok(Value::Null)
+        }
+
+        async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+            Ok(vec![])
+        }
+
+        async fn get_prompt(
+            &mut self,
+            prompt_name: &str,
+            _arguments: Option<Value>,
+        ) -> Result<GetPromptResult, MCPError> {
+            Err(MCPError::Protocol(format!("No prompt {}", prompt_name)))
+        }
+
+        async fn health(&self) -> Result<HealthStatus, MCPError> {
+            Ok(HealthStatus::Healthy)
+        }
+
+        async fn shutdown(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+    }
+
+    fn chaos_config(name: &str) -> MCPServerConfig {
+        MCPServerConfig {
+            name: name.to_string(),
+            command: "chaos".to_string(),
+            args: vec![],
+            timeout_secs: None,
+            strict_protocol_version: false,
+        }
+    }
+
+    fn chaos_client(factory: &Arc<ChaosFactory>, max_retries: u32) -> MCPClient {
+        MCPClient::with_retry_config(RetryConfig {
+            max_retries,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(30),
+            backoff_multiplier: 2.0,
+        })
+        .with_server_factory(factory.clone())
+    }
+
+    #[test]
+    fn test_delay_before_retry_progression() {
+        let retry = RetryConfig {
+            max_retries: 10,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            backoff_multiplier: 2.0,
+        };
+        let delays: Vec<u128> = (1..=6)
+            .map(|n| retry.delay_before_retry(n).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+
+        // Huge exponents and multipliers stay at the cap instead of overflowing
+        assert_eq!(
+            retry.delay_before_retry(u32::MAX),
+            Duration::from_millis(1000)
+        );
+        let steep = RetryConfig {
+            backoff_multiplier: f64::MAX,
+            ..retry.clone()
+        };
+        assert_eq!(steep.delay_before_retry(3), Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn test_add_server_retries_until_success() {
+        let factory = ChaosFactory::new(Chaos::FailTimes(2));
+        let client = chaos_client(&factory, 3);
+
+        client.add_server(chaos_config("flaky")).await.unwrap();
+
+        assert_eq!(factory.created(), 1);
+        assert_eq!(factory.attempts().len(), 3);
+        assert_eq!(client.list_servers().await, ["flaky"]);
+    }
+
+    #[tokio::test]
+    async fn test_add_server_gives_up_after_max_retries() {
+        let factory = ChaosFactory::new(Chaos::FailAlways);
+        let client = chaos_client(&factory, 3);
+
+        let result = client.add_server(chaos_config("down")).await;
+
+        assert!(matches!(result, Err(MCPError::Connection(_))));
+        let attempts = factory.attempts();
+        assert_eq!(attempts.len(), 4);
+        assert!(client.list_servers().await.is_empty());
+
+        // Waits of 10, 20 and then 30 ms, since 40 ms is over max_delay
+        let gaps: Vec<Duration> = attempts.windows(2).map(|w| w[1] - w[0]).collect();
+        for (gap, expected) in gaps.iter().zip([10, 20, 30]) {
+            assert!(
+                *gap >= Duration::from_millis(expected),
+                "gaps {:?} shorter than the backoff",
+                gaps
+            );
+        }
+        assert!(attempts[3] - attempts[0] < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_add_server_does_not_retry_permanent_errors() {
+        // An invalid config fails before any server is built
+        let factory = ChaosFactory::new(Chaos::FailAlways);
+        let client = chaos_client(&factory, 3);
+        let result = client.add_server(chaos_config("")).await;
+        assert!(matches!(result, Err(MCPError::Protocol(_))));
+        assert_eq!(factory.created(), 0);
+
+        // A protocol error from the server itself is returned after one attempt
+        let factory = ChaosFactory::new(Chaos::FailProtocol);
+        let client = chaos_client(&factory, 3);
+        let result = client.add_server(chaos_config("strict")).await;
+        assert!(matches!(result, Err(MCPError::Protocol(_))));
+        assert_eq!(factory.attempts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_server_hang_is_bounded_by_init_timeout() {
+        let factory = ChaosFactory::new(Chaos::Hang);
+        let client = chaos_client(&factory, 1).with_init_timeout(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let result = client.add_server(chaos_config("stuck")).await;
+
+        match result {
+            Err(MCPError::Connection(msg)) => assert!(msg.contains("did not finish initializing")),
+            other => panic!("Expected connection error, got {:?}", other),
+        }
+        assert_eq!(factory.attempts().len(), 2);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_restart_server_builds_fresh_instance() {
+        let factory = ChaosFactory::new(Chaos::FailTimes(1));
+        let client = chaos_client(&factory, 2);
+
+        client.add_server(chaos_config("flaky")).await.unwrap();
+        assert_eq!(factory.created(), 1);
+        assert_eq!(factory.attempts().len(), 2);
+
+        client.restart_server("flaky").await.unwrap();
+        assert_eq!(factory.created(), 2);
+        assert_eq!(factory.attempts().len(), 3);
+
+        // Once removed, the config is forgotten along with the server
+        client.remove_server("flaky").await.unwrap();
+        assert!(matches!(
+            client.restart_server("flaky").await,
+            Err(MCPError::ServerNotFound(_))
+        ));
+    }
+
+    /// Tools of a [`ChangingServer`], changed by the test while the client holds it
+    #[derive(Clone, Default)]
+    struct ChangingTools {
+        tools: Arc<StdMutex<Vec<String>>>,
+        calls: Arc<StdMutex<Vec<String>>>,
+        listings: Arc<AtomicUsize>,
+        changed: Arc<std::sync::atomic::AtomicBool>,
+        /// Fails every call, even to tools the server has
+        fails_with: Arc<StdMutex<Option<fn(&str) -> MCPError>>>,
+    }
+
+    impl ChangingTools {
+        fn new(tools: &[&str]) -> Self {
+            let state = Self::default();
+            state.set(tools);
+            state
+        }
+
+        fn set(&self, tools: &[&str]) {
+            *self.tools.lock().unwrap() = tools.iter().map(|t| t.to_string()).collect();
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn listings(&self) -> usize {
+            self.listings.load(Ordering::SeqCst)
+        }
+
+        fn fail_with(&self, error: fn(&str) -> MCPError) {
+            *self.fails_with.lock().unwrap() = Some(error);
+        }
+    }
+
+    /// A server whose tools change between discoveries, refusing calls to tools it
+    /// no longer has as a real server would
+    struct ChangingServer {
+        name: String,
+        state: ChangingTools,
+    }
+
+    #[async_trait]
+    impl MCPServer for ChangingServer {
+        async fn initialize(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+            self.state.listings.fetch_add(1, Ordering::SeqCst);
+            self.state.changed.store(false, Ordering::SeqCst);
+            let tools = self.state.tools.lock().unwrap().clone();
+            Ok(tools
+                .into_iter()
+                .map(|name| ToolDefinition {
+                    name,
+                    description: String::new(),
+                    parameters: json!({}),
+                    server_name: self.name.clone(),
+                    original_name: None,
+                })
+                .collect())
+        }
+
+        async fn call_tool(&mut self, tool_name: &str, _args: Value) -> Result<Value, MCPError> {
+            self.state.calls.lock().unwrap().push(tool_name.to_string());
+            if let Some(error) = *self.state.fails_with.lock().unwrap() {
+                return Err(error(tool_name));
+            }
+            if self
+                .state
+                .tools
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|t| t == tool_name)
+            {
+                Ok(json!({ "server": self.name }))
+            } else {
+                Err(MCPError::Rpc {
+                    code: INVALID_PARAMS,
+                    message: format!("Unknown tool: {}", tool_name),
+                })
+            }
+        }
+
+        async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+            Ok(vec![])
+        }
+
+        async fn get_prompt(
+            &mut self,
+            prompt_name: &str,
+            _arguments: Option<Value>,
+        ) -> Result<GetPromptResult, MCPError> {
+            Err(MCPError::Protocol(format!("No prompt {}", prompt_name)))
+        }
+
+        async fn health(&self) -> Result<HealthStatus, MCPError> {
+            Ok(HealthStatus::Healthy)
+        }
+
+        async fn shutdown(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn list_changes(&self) -> ListChanges {
+            ListChanges {
+                tools: self.state.changed.load(Ordering::SeqCst),
+                prompts: false,
+            }
+        }
+    }
+
+    /// A client with servers `old` offering `fetch` and `new` offering nothing yet,
+    /// its tools discovered at the time of `clock`
+    async fn changing_client(
+        clock: &crate::test_support::MockClock,
+    ) -> (MCPClient, ChangingTools, ChangingTools) {
+        let client = MCPClient::new().with_clock(Arc::new(clock.clone()));
+        let old = ChangingTools::new(&["fetch"]);
+        let new = ChangingTools::new(&[]);
+        for (name, state) in [("old", &old), ("new", &new)] {
+            client
+                .add_server_instance(Box::new(ChangingServer {
+                    name: name.to_string(),
+                    state: state.clone(),
+                }))
+                .await
+                .unwrap();
+        }
+        client.discover_tools().await.unwrap();
+        (client, old, new)
+    }
+
+    /// Move `fetch` from server `old` to server `new`
+    fn move_fetch(old: &ChangingTools, new: &ChangingTools) {
+        old.set(&[]);
+        new.set(&["fetch"]);
+    }
+
+    fn served_by(result: &ToolResult) -> &str {
+        assert_eq!(result.error, None);
+        result.result["server"].as_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stale_route_is_refreshed_and_retried() {
+        let clock = crate::test_support::MockClock::default();
+        let (client, old, new) = changing_client(&clock).await;
+        move_fetch(&old, &new);
+
+        // Within the TTL the cached route leads to the old server, which refuses
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert_eq!(served_by(&result), "new");
+        assert_eq!(old.calls(), vec!["fetch"]);
+        assert_eq!(old.listings(), 2);
+
+        // A tool no server has any more fails with the server's answer after one refresh
+        new.set(&[]);
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert!(
+            result
+                .error
+                .as_deref()
+                .is_some_and(|e| e.contains("Unknown tool: fetch")),
+            "{:?}",
+            result
+        );
+        assert_eq!(new.calls(), vec!["fetch", "fetch"]);
+        assert_eq!(new.listings(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_call_is_not_retried_unless_the_tool_moved() {
+        let clock = crate::test_support::MockClock::default();
+        let (client, old, _new) = changing_client(&clock).await;
+
+        // Bad arguments share the unknown tool code; rediscovery finds the tool where it was
+        old.fail_with(|_| MCPError::Rpc {
+            code: INVALID_PARAMS,
+            message: "Invalid arguments".to_string(),
+        });
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert!(result.error.unwrap().contains("Invalid arguments"));
+        assert_eq!(old.calls(), vec!["fetch"]);
+        assert_eq!(old.listings(), 2);
+
+        // A failure that only mentions a missing tool leaves the routes alone
+        old.fail_with(|name| MCPError::ToolCallFailed(format!("Tool {} not found", name)));
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert!(result.error.is_some());
+        assert_eq!(old.calls(), vec!["fetch", "fetch"]);
+        assert_eq!(old.listings(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_routes_are_rediscovered_before_calls() {
+        let clock = crate::test_support::MockClock::default();
+        let (client, old, new) = changing_client(&clock).await;
+        move_fetch(&old, &new);
+
+        clock.advance(DEFAULT_CACHE_TTL);
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert_eq!(served_by(&result), "new");
+        assert!(old.calls().is_empty());
+        assert_eq!(old.listings(), 2);
+
+        // Fresh again, so the next call goes straight to the server
+        client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert_eq!(old.listings(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_list_changed_notification_triggers_rediscovery() {
+        let clock = crate::test_support::MockClock::default();
+        let (client, old, new) = changing_client(&clock).await;
+        move_fetch(&old, &new);
+        new.changed.store(true, Ordering::SeqCst);
+
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert_eq!(served_by(&result), "new");
+        assert!(old.calls().is_empty());
+        assert!(client.list_changes().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rediscovers_every_server() {
+        let clock = crate::test_support::MockClock::default();
+        let (client, old, new) = changing_client(&clock).await;
+        move_fetch(&old, &new);
+
+        client.refresh().await.unwrap();
+        assert_eq!((old.listings(), new.listings()), (2, 2));
+        let result = client.execute_tool_call(&tool_call("fetch")).await.unwrap();
+        assert_eq!(served_by(&result), "new");
+        assert!(old.calls().is_empty());
+    }
+
+    /// A shell script playing an MCP server that interleaves notifications and its own
+    /// requests with the responses; request ids follow the client's counter
+    #[cfg(unix)]
+    const SCRIPTED_SERVER: &str = r#"
+read initialize
+echo '{"jsonrpc":"2.0","method":"notifications/message","params":{"level":"info","data":"starting"}}'
+echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{"tools":{}}}}'
+read initialized
+read list_tools
+echo '{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}'
+echo '{"jsonrpc":"2.0","id":"server-1","method":"roots/list"}'
+echo '{"jsonrpc":"2.0","id":2,"result":{"tools":[{"name":"echo","description":"Echo"}]}}'
+read rejected
+echo "$rejected" | grep -q '"code":-32601' || exit 1
+read client_list_changed
+read call_tool
+echo '{"jsonrpc":"2.0","id":3,"result":{"content":[{"type":"text","text":"echoed"}]}}'
+cat > /dev/null
+"#;
+
+    /// A shell script answering two tool calls only once both arrived, the later first
+    #[cfg(unix)]
+    const OVERLAPPING_CALLS_SERVER: &str = r#"
+read initialize
+echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{"tools":{}}}}'
+read initialized
+read first_call
+read second_call
+echo '{"jsonrpc":"2.0","id":3,"result":{"content":[{"type":"text","text":"second"}]}}'
+echo '{"jsonrpc":"2.0","id":2,"result":{"content":[{"type":"text","text":"first"}]}}'
+cat > /dev/null
+"#;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_server_calls_overlap() {
+        let client = MCPClient::new();
+        client
+            .add_server(MCPServerConfig {
+                name: "overlapping".to_string(),
+                command: "sh".to_string(),
+                args: vec!["-c".to_string(), OVERLAPPING_CALLS_SERVER.to_string()],
+                timeout_secs: None,
+                strict_protocol_version: false,
+            })
+            .await
+            .unwrap();
+
+        // The second call is sent while the first waits, and the server stays free
+        let (first, second, health) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(
+                client.call_tool("overlapping", "echo", json!({})),
+                client.call_tool("overlapping", "echo", json!({})),
+                client.server_health("overlapping"),
+            )
+        })
+        .await
+        .expect("calls to one server overlap");
+        let mut texts = vec![
+            first.unwrap()["content"][0]["text"].clone(),
+            second.unwrap()["content"][0]["text"].clone(),
+        ];
+        texts.sort_by_key(|text| text.to_string());
+        assert_eq!(texts, vec![json!("first"), json!("second")]);
+        assert!(matches!(health.unwrap(), HealthStatus::Healthy));
+
+        client.shutdown_all().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_server_interleaving_notifications() {
+        let client = MCPClient::new();
+        client
+            .add_server(MCPServerConfig {
+                name: "scripted".to_string(),
+                command: "sh".to_string(),
+                args: vec!["-c".to_string(), SCRIPTED_SERVER.to_string()],
+                timeout_secs: None,
+                strict_protocol_version: false,
+            })
+            .await
+            .unwrap();
+
+        let tools = client.discover_tools().await.unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "echo");
+        // The change was announced while listing, so the list may already be stale
+        assert_eq!(
+            client.list_changes().await.get("scripted"),
+            Some(&ListChanges {
+                tools: true,
+                prompts: false
+            })
+        );
+
+        // The script only answers the tool call once its request was rejected
+        let result = client
+            .call_tool("scripted", "echo", json!({}))
+            .await
+            .unwrap();
+        assert_eq!(result["content"][0]["text"], "echoed");
+
+        client.shutdown_all().await.unwrap();
+    }
+
+    /// A server whose `count` tool reports progress before answering and whose
+    /// `hang` tool never answers
+    struct SlowServer;
+
+    #[async_trait]
+    impl MCPServer for SlowServer {
+        async fn initialize(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+            Ok(["count", "hang"]
+                .into_iter()
+                .map(|name| tool_on(name, "slow"))
+                .collect())
+        }
+
+        async fn call_tool(&mut self, tool_name: &str, args: Value) -> Result<Value, MCPError> {
+            self.call_tool_with_progress(tool_name, args, Arc::new(|_: ToolProgress| {}))
+                .await
+        }
+
+        async fn call_tool_with_progress(
+            &mut self,
+            tool_name: &str,
+            _args: Value,
+            on_progress: ProgressHandler,
+        ) -> Result<Value, MCPError> {
+            if tool_name == "hang" {
+                std::future::pending::<()>().await;
+            }
+            for progress in 1..=3 {
+                on_progress(ToolProgress {
+                    progress: progress as f64,
+                    total: Some(3.0),
+                    message: None,
+                });
+            }
+            Ok(json!({ "counted": 3 }))
+        }
+
+        async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+            Ok(vec![])
+        }
+
+        async fn get_prompt(
+            &mut self,
+            prompt_name: &str,
+            _arguments: Option<Value>,
+        ) -> Result<GetPromptResult, MCPError> {
+            Err(MCPError::Protocol(format!("No prompt {}", prompt_name)))
+        }
+
+        async fn health(&self) -> Result<HealthStatus, MCPError> {
+            Ok(HealthStatus::Healthy)
+        }
+
+        async fn shutdown(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            "slow"
+        }
+    }
+
+    async fn slow_client() -> Arc<MCPClient> {
+        let client = MCPClient::new();
+        client
+            .add_server_instance(Box::new(SlowServer))
+            .await
+            .unwrap();
+        client.discover_tools().await.unwrap();
+        Arc::new(client)
+    }
+
+    #[tokio::test]
+    async fn test_tracked_call_reports_progress() {
+        let client = slow_client().await;
+        let received = Arc::new(StdMutex::new(Vec::new()));
+        let on_progress: ProgressHandler = {
+            let received = Arc::clone(&received);
+            Arc::new(move |progress: ToolProgress| {
+                received.lock().unwrap().push(progress.progress);
+            })
+        };
+
+        let call_id = ToolCallId::new();
+        let result = client
+            .call_tool_tracked(
+                call_id,
+                "slow",
+                "count",
+                json!({}),
+                Some(on_progress),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result, json!({ "counted": 3 }));
+        assert_eq!(*received.lock().unwrap(), vec![1.0, 2.0, 3.0]);
+        // Finished calls cannot be cancelled
+        assert!(!client.cancel_tool_call(&call_id));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_tool_call_resolves_promptly() {
+        let client = slow_client().await;
+        let tool_call = ToolCall {
+            id: ToolCallId::new(),
+            name: "hang".to_string(),
+            arguments: json!({}),
+        };
+        let call = tokio::spawn({
+            let client = Arc::clone(&client);
+            let tool_call = tool_call.clone();
+            async move { client.execute_tool_call(&tool_call).await }
+        });
+
+        while !client.cancel_tool_call(&tool_call.id) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let result = tokio::time::timeout(Duration::from_secs(1), call)
+            .await
+            .expect("cancelled call resolves promptly")
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.call_id, tool_call.id);
+        assert!(result.is_cancelled(), "{:?}", result);
+
+        // The server is free again, and cancelling a caller's token cancels its call
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let error = client
+            .call_tool_tracked(ToolCallId::new(), "slow", "hang", json!({}), None, &cancel)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, MCPError::Cancelled), "{}", error);
+        let counted = client.call_tool("slow", "count", json!({})).await.unwrap();
+        assert_eq!(counted, json!({ "counted": 3 }));
+    }
+}
@@ -0,0 +1,759 @@
+//! JSON-RPC over the stdio of an MCP server process.
+//!
+//! A reader task parses every line the server writes. Responses are matched to the
+//! request awaiting them by id, so they may arrive in any order and several requests
+//! may be in flight at once. Notifications are handled as they come: list changes are
+//! recorded for re-discovery, log messages go to tracing and progress goes to the
+//! request that asked for it. Requests from the server are answered with a
+//! method-not-found error, except `ping`.
+//!
+//! A caller that stops waiting for a response tells the server with
+//! `notifications/cancelled`, so it can stop working on the request.
+
+use crate::redaction::redact;
+use crate::types::{MCPError, ToolProgress};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// JSON-RPC error code for a method the receiver does not implement
+const METHOD_NOT_FOUND: i64 = -32601;
+
+type ResponseSender = oneshot::Sender<Result<Value, MCPError>>;
+
+/// Receives the progress notifications of a request
+pub type ProgressHandler = Arc<dyn Fn(ToolProgress) + Send + Sync>;
+
+/// Lists a server announced as changed since they were last listed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListChanges {
+    pub tools: bool,
+    pub prompts: bool,
+}
+
+impl ListChanges {
+    pub fn any(&self) -> bool {
+        self.tools || self.prompts
+    }
+}
+
+/// State shared between a transport and its reader task
+struct Shared {
+    server_name: String,
+    writer: Mutex<Box<dyn AsyncWrite + Send + Unpin>>,
+    /// Requests awaiting a response by id; `None` once the server closed its output
+    pending: StdMutex<Option<HashMap<u64, ResponseSender>>>,
+    /// Progress handlers of pending requests, by the id used as their progress token
+    progress: StdMutex<HashMap<u64, ProgressHandler>>,
+    tools_changed: AtomicBool,
+    prompts_changed: AtomicBool,
+}
+
+/// A connection to an MCP server over its stdin and stdout
+pub(crate) struct StdioTransport {
+    shared: Arc<Shared>,
+    next_id: AtomicU64,
+    reader: JoinHandle<()>,
+}
+
+impl StdioTransport {
+    /// Start reading messages from `output`, writing to `input`
+    pub fn new<R, W>(server_name: &str, output: R, input: W) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let shared = Arc::new(Shared {
+            server_name: server_name.to_string(),
+            writer: Mutex::new(Box::new(input)),
+            pending: StdMutex::new(Some(HashMap::new())),
+            progress: StdMutex::new(HashMap::new()),
+            tools_changed: AtomicBool::new(false),
+            prompts_changed: AtomicBool::new(false),
+        });
+        let reader = tokio::spawn(read_messages(Arc::clone(&shared), output));
+        Self {
+            shared,
+            next_id: AtomicU64::new(1),
+            reader,
+        }
+    }
+
+    /// Send a request and wait for the response with its id
+    pub async fn request(&self, method: &str, params: Value) -> Result<Value, MCPError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.send_request(id, method, params, None).await
+    }
+
+    /// Send a request asking for progress notifications, which go to `on_progress`
+    /// until the response arrives
+    pub async fn request_with_progress(
+        &self,
+        method: &str,
+        mut params: Value,
+        on_progress: ProgressHandler,
+    ) -> Result<Value, MCPError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Some(params) = params.as_object_mut() {
+            let meta = params.entry("_meta").or_insert_with(|| json!({}));
+            if let Some(meta) = meta.as_object_mut() {
+                meta.insert("progressToken".to_string(), json!(id));
+            }
+        }
+        self.send_request(id, method, params, Some(on_progress))
+            .await
+    }
+
+    async fn send_request(
+        &self,
+        id: u64,
+        method: &str,
+        params: Value,
+        on_progress: Option<ProgressHandler>,
+    ) -> Result<Value, MCPError> {
+        let (sender, receiver) = oneshot::channel();
+        // The server must not be told to cancel initialization
+        let mut pending = self.shared.register(id, sender, method != "initialize")?;
+        if let Some(on_progress) = on_progress {
+            pending.track_progress(on_progress);
+        }
+
+        self.shared
+            .write_message(&json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": params
+            }))
+            .await?;
+
+        receiver
+            .await
+            .unwrap_or_else(|_| Err(self.shared.closed_error()))
+    }
+
+    /// Send a notification, which gets no response
+    pub async fn notify(&self, method: &str, params: Value) -> Result<(), MCPError> {
+        self.shared
+            .write_message(&json!({
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": params
+            }))
+            .await
+    }
+
+    /// Whether the server closed its output, so no more responses can arrive
+    pub fn is_closed(&self) -> bool {
+        self.shared.pending.lock().unwrap().is_none()
+    }
+
+    /// Lists the server announced as changed since they were last cleared
+    pub fn list_changes(&self) -> ListChanges {
+        ListChanges {
+            tools: self.shared.tools_changed.load(Ordering::Relaxed),
+            prompts: self.shared.prompts_changed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Forget announced changes to the lists about to be listed again
+    pub fn clear_list_changes(&self, cleared: ListChanges) {
+        if cleared.tools {
+            self.shared.tools_changed.store(false, Ordering::Relaxed);
+        }
+        if cleared.prompts {
+            self.shared.prompts_changed.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for StdioTransport {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Removes a request from the pending ones when its caller stops waiting, telling
+/// the server the request is cancelled if no response came yet
+struct PendingRequest {
+    shared: Arc<Shared>,
+    id: u64,
+    cancellable: bool,
+}
+
+impl PendingRequest {
+    fn track_progress(&mut self, on_progress: ProgressHandler) {
+        self.shared
+            .progress
+            .lock()
+            .unwrap()
+            .insert(self.id, on_progress);
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        self.shared.progress.lock().unwrap().remove(&self.id);
+        let unanswered = self
+            .shared
+            .pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .is_some_and(|pending| pending.remove(&self.id).is_some());
+        if !unanswered || !self.cancellable {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let shared = Arc::clone(&self.shared);
+        let id = self.id;
+        runtime.spawn(async move {
+            debug!(
+                "Cancelling request {} to MCP server '{}'",
+                id, shared.server_name
+            );
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/cancelled",
+                "params": { "requestId": id, "reason": "The client stopped waiting" }
+            });
+            if let Err(e) = shared.write_message(&notification).await {
+                debug!(
+                    "Failed to cancel request {} to MCP server '{}': {}",
+                    id, shared.server_name, e
+                );
+            }
+        });
+    }
+}
+
+impl Shared {
+    fn register(
+        self: &Arc<Self>,
+        id: u64,
+        sender: ResponseSender,
+        cancellable: bool,
+    ) -> Result<PendingRequest, MCPError> {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(pending) = pending.as_mut() else {
+            return Err(self.closed_error());
+        };
+        pending.insert(id, sender);
+        Ok(PendingRequest {
+            shared: Arc::clone(self),
+            id,
+            cancellable,
+        })
+    }
+
+    fn closed_error(&self) -> MCPError {
+        MCPError::Connection(format!(
+            "MCP server '{}' closed its output",
+            self.server_name
+        ))
+    }
+
+    /// Write one message as a line
+    async fn write_message(&self, message: &Value) -> Result<(), MCPError> {
+        let line = serde_json::to_string(message)
+            .map_err(|e| MCPError::Protocol(format!("Failed to serialize message: {}", e)))?;
+        debug!("Sending MCP message: {}", line);
+
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| MCPError::Connection(format!("Failed to write message: {}", e)))?;
+        writer
+            .write_all(b"\n")
+            .await
+            .map_err(|e| MCPError::Connection(format!("Failed to write newline: {}", e)))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| MCPError::Connection(format!("Failed to flush: {}", e)))
+    }
+
+    fn handle_line(self: &Arc<Self>, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        debug!("Received MCP message: {}", line);
+
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!(
+                    "Ignoring unparsable line from MCP server '{}': {}",
+                    self.server_name, e
+                );
+                return;
+            }
+        };
+
+        match (
+            message.get("method").and_then(Value::as_str),
+            message.get("id"),
+        ) {
+            (Some(method), Some(id)) => self.answer_request(id.clone(), method),
+            (Some(method), None) => self.handle_notification(method, message.get("params")),
+            (None, Some(_)) => self.resolve_response(&message),
+            (None, None) => warn!(
+                "Ignoring message without id or method from MCP server '{}'",
+                self.server_name
+            ),
+        }
+    }
+
+    /// Hand a response to the request waiting for it
+    fn resolve_response(&self, response: &Value) {
+        let id = response.get("id").and_then(Value::as_u64);
+        let sender = id.and_then(|id| {
+            self.pending
+                .lock()
+                .unwrap()
+                .as_mut()
+                .and_then(|pending| pending.remove(&id))
+        });
+        let Some(sender) = sender else {
+            warn!(
+                "Ignoring response to unknown request {} from MCP server '{}'",
+                response["id"], self.server_name
+            );
+            return;
+        };
+
+        let result = match response.get("error") {
+            Some(error) => Err(rpc_error(error)),
+            None => response
+                .get("result")
+                .cloned()
+                .ok_or_else(|| MCPError::Protocol("Missing result in response".to_string())),
+        };
+        // The caller may have stopped waiting
+        let _ = sender.send(result);
+    }
+
+    fn handle_notification(&self, method: &str, params: Option<&Value>) {
+        match method {
+            "notifications/tools/list_changed" => {
+                info!("MCP server '{}' changed its tools", self.server_name);
+                self.tools_changed.store(true, Ordering::Relaxed);
+            }
+            "notifications/prompts/list_changed" => {
+                info!("MCP server '{}' changed its prompts", self.server_name);
+                self.prompts_changed.store(true, Ordering::Relaxed);
+            }
+            "notifications/message" => self.log_server_message(params.unwrap_or(&Value::Null)),
+            "notifications/progress" => self.report_progress(params.unwrap_or(&Value::Null)),
+            _ => debug!(
+                "Ignoring notification '{}' from MCP server '{}'",
+                method, self.server_name
+            ),
+        }
+    }
+
+    /// Pass a `notifications/progress` to the request with its progress token
+    fn report_progress(&self, params: &Value) {
+        let token = params.get("progressToken").and_then(Value::as_u64);
+        let Some(progress) = params.get("progress").and_then(Value::as_f64) else {
+            warn!(
+                "Ignoring progress without a value from MCP server '{}'",
+                self.server_name
+            );
+            return;
+        };
+        let handler = token.and_then(|token| self.progress.lock().unwrap().get(&token).cloned());
+        let Some(handler) = handler else {
+            debug!(
+                "Ignoring progress of unknown request {} from MCP server '{}'",
+                params["progressToken"], self.server_name
+            );
+            return;
+        };
+        handler(ToolProgress {
+            progress,
+            total: params.get("total").and_then(Value::as_f64),
+            message: params
+                .get("message")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        });
+    }
+
+    /// Log a `notifications/message` at the level the server gave it
+    fn log_server_message(&self, params: &Value) {
+        let data = params.get("data").unwrap_or(&Value::Null);
+        let source = match params.get("logger").and_then(Value::as_str) {
+            Some(logger) => format!("MCP server '{}' ({})", self.server_name, logger),
+            None => format!("MCP server '{}'", self.server_name),
+        };
+        match params.get("level").and_then(Value::as_str) {
+            Some("debug") => debug!("{}: {}", source, redact(data)),
+            Some("warning") => warn!("{}: {}", source, redact(data)),
+            Some("error" | "critical" | "alert" | "emergency") => {
+                error!("{}: {}", source, redact(data))
+            }
+            _ => info!("{}: {}", source, redact(data)),
+        }
+    }
+
+    /// Answer a request from the server: `ping` succeeds, anything else is not supported.
+    /// The answer is written on its own task, so reading never waits for the writer.
+    fn answer_request(self: &Arc<Self>, id: Value, method: &str) {
+        let response = if method == "ping" {
+            json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+        } else {
+            debug!(
+                "Rejecting unsupported request '{}' from MCP server '{}'",
+                method, self.server_name
+            );
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": METHOD_NOT_FOUND,
+                    "message": format!("Method not supported by client: {}", method)
+                }
+            })
+        };
+        let shared = Arc::clone(self);
+        let method = method.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = shared.write_message(&response).await {
+                warn!(
+                    "Failed to answer request '{}' from MCP server '{}': {}",
+                    method, shared.server_name, e
+                );
+            }
+        });
+    }
+}
+
+/// Handle the server's messages until it closes its output, then fail the requests
+/// still waiting
+async fn read_messages<R: AsyncRead + Unpin>(shared: Arc<Shared>, output: R) {
+    let mut lines = BufReader::new(output).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => shared.handle_line(&line),
+            Ok(None) => break,
+            Err(e) => {
+                warn!(
+                    "Failed to read from MCP server '{}': {}",
+                    shared.server_name, e
+                );
+                break;
+            }
+        }
+    }
+    debug!("MCP server '{}' closed its output", shared.server_name);
+    // Dropping the senders wakes their requests with a connection error
+    shared.pending.lock().unwrap().take();
+}
+
+/// The error a JSON-RPC error object reports; one without a code and message is kept
+/// whole as a protocol error
+fn rpc_error(error: &Value) -> MCPError {
+    match (
+        error.get("code").and_then(Value::as_i64),
+        error.get("message").and_then(Value::as_str),
+    ) {
+        (Some(code), Some(message)) => MCPError::Rpc {
+            code,
+            message: message.to_string(),
+        },
+        _ => MCPError::Protocol(format!("MCP server error: {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream, Lines};
+
+    /// The server end of a transport: reads what the client sent, writes replies
+    struct ScriptedServer {
+        requests: Lines<BufReader<DuplexStream>>,
+        output: DuplexStream,
+    }
+
+    impl ScriptedServer {
+        async fn next_message(&mut self) -> Value {
+            let line = self.requests.next_line().await.unwrap().unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        async fn send(&mut self, message: Value) {
+            let line = format!("{}\n", message);
+            self.output.write_all(line.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn connect() -> (StdioTransport, ScriptedServer) {
+        let (client_output, server_output) = duplex(64 * 1024);
+        let (client_input, server_input) = duplex(64 * 1024);
+        let transport = StdioTransport::new("scripted", client_output, client_input);
+        let server = ScriptedServer {
+            requests: BufReader::new(server_input).lines(),
+            output: server_output,
+        };
+        (transport, server)
+    }
+
+    fn reply(id: &Value, result: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "result": result })
+    }
+
+    #[tokio::test]
+    async fn test_notifications_between_request_and_response() {
+        let (transport, mut server) = connect();
+        let script = tokio::spawn(async move {
+            let request = server.next_message().await;
+            assert_eq!(request["method"], "tools/list");
+            server
+                .send(json!({ "jsonrpc": "2.0", "method": "notifications/tools/list_changed" }))
+                .await;
+            server
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/message",
+                    "params": { "level": "warning", "logger": "fs", "data": "low disk space" }
+                }))
+                .await;
+            server
+                .send(reply(&request["id"], json!({ "tools": [] })))
+                .await;
+        });
+
+        // The notifications are not taken for the response
+        let result = transport.request("tools/list", json!({})).await.unwrap();
+        assert_eq!(result, json!({ "tools": [] }));
+        script.await.unwrap();
+
+        let tools_only = ListChanges {
+            tools: true,
+            prompts: false,
+        };
+        assert_eq!(transport.list_changes(), tools_only);
+        transport.clear_list_changes(tools_only);
+        assert!(!transport.list_changes().any());
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_responses_reach_their_requests() {
+        let (transport, mut server) = connect();
+        let script = tokio::spawn(async move {
+            let first = server.next_message().await;
+            let second = server.next_message().await;
+            // Answer the later request first
+            for request in [second, first] {
+                let name = request["params"]["name"].clone();
+                server
+                    .send(reply(&request["id"], json!({ "echo": name })))
+                    .await;
+            }
+        });
+
+        let (a, b) = tokio::join!(
+            transport.request("tools/call", json!({ "name": "a" })),
+            transport.request("tools/call", json!({ "name": "b" })),
+        );
+        assert_eq!(a.unwrap(), json!({ "echo": "a" }));
+        assert_eq!(b.unwrap(), json!({ "echo": "b" }));
+        script.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_error_responses_keep_their_code() {
+        let (transport, mut server) = connect();
+        let script = tokio::spawn(async move {
+            let request = server.next_message().await;
+            server
+                .send(json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "error": { "code": -32602, "message": "Unknown tool: fetch" }
+                }))
+                .await;
+        });
+
+        let error = transport
+            .request("tools/call", json!({ "name": "fetch" }))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, MCPError::Rpc { code: -32602, message } if message == "Unknown tool: fetch"),
+            "{:?}",
+            error
+        );
+        script.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_requests_are_answered() {
+        let (transport, mut server) = connect();
+        let script = tokio::spawn(async move {
+            let request = server.next_message().await;
+            server
+                .send(json!({ "jsonrpc": "2.0", "id": "s1", "method": "sampling/createMessage" }))
+                .await;
+            server
+                .send(json!({ "jsonrpc": "2.0", "id": 7, "method": "ping" }))
+                .await;
+
+            let rejected = server.next_message().await;
+            assert_eq!(rejected["id"], "s1");
+            assert_eq!(rejected["error"]["code"], METHOD_NOT_FOUND);
+            let pong = server.next_message().await;
+            assert_eq!(pong, json!({ "jsonrpc": "2.0", "id": 7, "result": {} }));
+
+            server.send(reply(&request["id"], json!({}))).await;
+        });
+
+        transport.request("prompts/list", json!({})).await.unwrap();
+        script.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_responses_arrive_while_an_answer_waits_to_be_written() {
+        let (transport, mut server) = connect();
+        let (sender, receiver) = oneshot::channel();
+        let _pending = transport.shared.register(1, sender, false).unwrap();
+
+        // Another request holds the writer, so the ping's answer has to wait
+        let writer = transport.shared.writer.lock().await;
+        server
+            .send(json!({ "jsonrpc": "2.0", "id": "s1", "method": "ping" }))
+            .await;
+        server.send(reply(&json!(1), json!({}))).await;
+        let response = tokio::time::timeout(std::time::Duration::from_secs(1), receiver)
+            .await
+            .expect("the reader is not blocked by the answer")
+            .unwrap();
+        assert_eq!(response.unwrap(), json!({}));
+
+        drop(writer);
+        let pong = server.next_message().await;
+        assert_eq!(pong, json!({ "jsonrpc": "2.0", "id": "s1", "result": {} }));
+    }
+
+    #[tokio::test]
+    async fn test_closed_output_fails_pending_requests() {
+        let (transport, mut server) = connect();
+        let script = tokio::spawn(async move {
+            let request = server.next_message().await;
+            // Garbage and a response to nothing are skipped, then the server exits
+            server.output.write_all(b"not json\n").await.unwrap();
+            server.send(reply(&json!(999), json!({}))).await;
+            assert_eq!(request["method"], "tools/list");
+        });
+
+        let error = transport
+            .request("tools/list", json!({}))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, MCPError::Connection(_)), "{}", error);
+        script.await.unwrap();
+        assert!(transport.is_closed());
+
+        let error = transport
+            .request("tools/list", json!({}))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("closed its output"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_progress_reaches_its_request() {
+        let (transport, mut server) = connect();
+        let script = tokio::spawn(async move {
+            let request = server.next_message().await;
+            let token = request["params"]["_meta"]["progressToken"].clone();
+            assert_eq!(token, request["id"]);
+            for (progress, message) in [(1, "indexing"), (2, "searching")] {
+                server
+                    .send(json!({
+                        "jsonrpc": "2.0",
+                        "method": "notifications/progress",
+                        "params": {
+                            "progressToken": token,
+                            "progress": progress,
+                            "total": 2,
+                            "message": message
+                        }
+                    }))
+                    .await;
+            }
+            server
+                .send(reply(&request["id"], json!({ "content": [] })))
+                .await;
+        });
+
+        let received = Arc::new(StdMutex::new(Vec::new()));
+        let on_progress: ProgressHandler = {
+            let received = Arc::clone(&received);
+            Arc::new(move |progress: ToolProgress| received.lock().unwrap().push(progress))
+        };
+        let result = transport
+            .request_with_progress("tools/call", json!({ "name": "search" }), on_progress)
+            .await
+            .unwrap();
+        assert_eq!(result, json!({ "content": [] }));
+        script.await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(
+            *received,
+            vec![
+                ToolProgress {
+                    progress: 1.0,
+                    total: Some(2.0),
+                    message: Some("indexing".to_string()),
+                },
+                ToolProgress {
+                    progress: 2.0,
+                    total: Some(2.0),
+                    message: Some("searching".to_string()),
+                },
+            ]
+        );
+        assert!(transport.shared.progress.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_requests_are_cancelled() {
+        let (transport, mut server) = connect();
+
+        let call = transport.request("tools/call", json!({ "name": "slow" }));
+        let abandoned = tokio::time::timeout(std::time::Duration::from_millis(20), call).await;
+        assert!(abandoned.is_err());
+
+        let request = server.next_message().await;
+        let cancelled = server.next_message().await;
+        assert_eq!(cancelled["method"], "notifications/cancelled");
+        assert_eq!(cancelled["params"]["requestId"], request["id"]);
+
+        // A late response is ignored
+        server.send(reply(&request["id"], json!({}))).await;
+        let script = tokio::spawn(async move {
+            let request = server.next_message().await;
+            server.send(reply(&request["id"], json!({}))).await;
+            server
+        });
+        transport.request("ping", json!({})).await.unwrap();
+        let mut server = script.await.unwrap();
+
+        // Answered requests are not cancelled
+        drop(transport);
+        assert!(server.requests.next_line().await.unwrap().is_none());
+    }
+}
@@ -1,69 +1,235 @@
-use crate::types::{ModelConfig, ModelError};
+use crate::chat_template::ChatTemplateEngine;
+use crate::context_pool::{ContextLease, ContextPool};
+use crate::types::{LoadMode, ModelConfig, ModelError, ModelInfo};
 use llama_cpp_2::{
-    context::{params::LlamaContextParams, LlamaContext},
+    context::{
+        params::{LlamaContextParams, LlamaPoolingType},
+        LlamaContext,
+    },
     llama_backend::LlamaBackend,
     model::LlamaModel,
-    send_logs_to_tracing, LogOptions,
 };
-use llama_loader::{ModelLoader, ModelMetadata};
-use std::sync::{Arc, OnceLock};
-use tokio::sync::RwLock;
-use tracing::{debug, info};
+use llama_loader::{HfGenerationDefaults, LoadedModel, ModelLoader, ModelMetadata};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Once, OnceLock};
+use std::time::SystemTime;
+use tokio::sync::{Mutex, RwLock, RwLockWriteGuard};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn, Level};
 // Need access to raw FFI bindings for llama_log_set
-use std::ffi::c_void;
+use std::ffi::{c_void, CStr};
 use std::os::raw::c_char;
 
 static GLOBAL_BACKEND: OnceLock<Arc<LlamaBackend>> = OnceLock::new();
 
-// Null log callback to suppress llama.cpp verbose output
-extern "C" fn null_log_callback(_level: i32, _text: *const c_char, _user_data: *mut c_void) {
-    // Do nothing - this suppresses all llama.cpp logging
+// ggml log levels passed to the llama.cpp log callback
+const GGML_LOG_LEVEL_DEBUG: i32 = 1;
+const GGML_LOG_LEVEL_INFO: i32 = 2;
+const GGML_LOG_LEVEL_WARN: i32 = 3;
+const GGML_LOG_LEVEL_ERROR: i32 = 4;
+const GGML_LOG_LEVEL_CONT: i32 = 5;
+
+static LLAMA_LOG_INSTALL: Once = Once::new();
+/// Whether llama.cpp debug and info output is forwarded. The callback is
+/// process-wide, so this follows the most recently created ModelManager.
+static LLAMA_VERBOSE_LOGS: AtomicBool = AtomicBool::new(false);
+/// Level of the last llama.cpp message, for continuation pieces
+static LLAMA_LAST_LOG_LEVEL: AtomicI32 = AtomicI32::new(GGML_LOG_LEVEL_INFO);
+
+/// Tracing level for a llama.cpp message, or `None` if it is dropped.
+///
+/// llama.cpp info output is model-loading chatter, so it maps to debug and is
+/// only forwarded when `verbose`, as is its debug output. Warnings and errors
+/// are always forwarded. Continuation pieces keep the level of the message
+/// they continue.
+fn llama_log_level(level: i32, previous: i32, verbose: bool) -> Option<Level> {
+    let level = if level == GGML_LOG_LEVEL_CONT {
+        previous
+    } else {
+        level
+    };
+    match level {
+        GGML_LOG_LEVEL_ERROR => Some(Level::ERROR),
+        GGML_LOG_LEVEL_WARN => Some(Level::WARN),
+        GGML_LOG_LEVEL_INFO if verbose => Some(Level::DEBUG),
+        GGML_LOG_LEVEL_DEBUG if verbose => Some(Level::TRACE),
+        _ => None,
+    }
+}
+
+/// Pass one llama.cpp message, without its trailing newline, to `emit` at its mapped level
+fn handle_llama_log(
+    level: i32,
+    text: &str,
+    verbose: bool,
+    last_level: &AtomicI32,
+    emit: impl FnOnce(Level, &str),
+) {
+    let previous = if level == GGML_LOG_LEVEL_CONT {
+        last_level.load(Ordering::Relaxed)
+    } else {
+        last_level.swap(level, Ordering::Relaxed)
+    };
+
+    let text = text.trim_end();
+    if text.is_empty() {
+        return;
+    }
+    if let Some(level) = llama_log_level(level, previous, verbose) {
+        emit(level, text);
+    }
+}
+
+fn emit_llama_log(level: Level, text: &str) {
+    match level {
+        Level::ERROR => error!(target: "llama_cpp", "{}", text),
+        Level::WARN => warn!(target: "llama_cpp", "{}", text),
+        Level::INFO => info!(target: "llama_cpp", "{}", text),
+        Level::DEBUG => debug!(target: "llama_cpp", "{}", text),
+        _ => trace!(target: "llama_cpp", "{}", text),
+    }
+}
+
+extern "C" fn llama_log_callback(level: i32, text: *const c_char, _user_data: *mut c_void) {
+    if text.is_null() {
+        return;
+    }
+    // llama.cpp passes a NUL-terminated string that is valid for the duration of the call
+    let text = unsafe { CStr::from_ptr(text) }.to_string_lossy();
+    handle_llama_log(
+        level,
+        &text,
+        LLAMA_VERBOSE_LOGS.load(Ordering::Relaxed),
+        &LLAMA_LAST_LOG_LEVEL,
+        emit_llama_log,
+    );
 }
 
-// Set up logging suppression using llama_log_set
-fn set_logging_suppression(suppress: bool) {
-    unsafe {
-        // Access the raw FFI binding
+/// Run `install` the first time `guard` is used; returns whether this call ran it
+fn install_once(guard: &Once, install: impl FnOnce()) -> bool {
+    let mut installed = false;
+    guard.call_once(|| {
+        install();
+        installed = true;
+    });
+    installed
+}
+
+/// Route llama.cpp and ggml output through tracing instead of stderr.
+///
+/// The callback is installed once per process; later calls only change
+/// whether debug and info output is forwarded.
+fn configure_llama_logging(verbose: bool) {
+    LLAMA_VERBOSE_LOGS.store(verbose, Ordering::Relaxed);
+    install_once(&LLAMA_LOG_INSTALL, || unsafe {
+        // Access the raw FFI binding; this also sets the ggml log callback
         extern "C" {
             fn llama_log_set(
                 log_callback: Option<extern "C" fn(i32, *const c_char, *mut c_void)>,
                 user_data: *mut c_void,
             );
         }
-
-        if suppress {
-            // Set null callback to suppress logging
-            llama_log_set(Some(null_log_callback), std::ptr::null_mut());
-        } else {
-            // Restore default logging (NULL callback means output to stderr)
-            llama_log_set(None, std::ptr::null_mut());
-        }
-    }
+        llama_log_set(Some(llama_log_callback), std::ptr::null_mut());
+    });
 }
 
 pub struct ModelManager {
     model: Arc<RwLock<Option<LlamaModel>>>,
     backend: Arc<LlamaBackend>,
-    config: ModelConfig,
+    config: std::sync::RwLock<ModelConfig>,
+    /// Template engine for the loaded model, with its tool call parser order
+    chat_template: std::sync::RwLock<Arc<ChatTemplateEngine>>,
     loader: RwLock<Option<ModelLoader>>,
     metadata: RwLock<Option<ModelMetadata>>,
+    hf_generation_defaults: RwLock<Option<HfGenerationDefaults>>,
+    loaded_at: RwLock<Option<SystemTime>>,
     memory_usage_bytes: Arc<std::sync::atomic::AtomicU64>,
+    reloading: AtomicBool,
+    load_mode: LoadMode,
+    load_gate: Mutex<()>,
+    loading: AtomicBool,
+    context_pool: ContextPool,
+    cancel: CancellationToken,
+}
+
+/// Marks an operation such as a reload as in progress; only one may run at a time.
+struct FlagGuard<'a> {
+    flag: &'a AtomicBool,
+}
+
+impl<'a> FlagGuard<'a> {
+    fn acquire(flag: &'a AtomicBool) -> Option<Self> {
+        flag.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| Self { flag })
+    }
+}
+
+impl Drop for FlagGuard<'_> {
+    fn drop(&mut self) {
+        self.flag.store(false, Ordering::Release);
+    }
+}
+
+/// Run `load` without blocking readers of `slot`, then swap the result in.
+///
+/// `gate` is held throughout, so a first load through [`load_once`] waits for
+/// this one. Taking the write lock waits for in-flight readers (requests running
+/// inside `with_model`) to finish, and new readers queue behind it, so every
+/// request sees either the old or the new value in full. On load failure the
+/// slot is left untouched. Returns the write guard, so state that goes with the
+/// value can be updated before readers see it, and the previous value, so it can
+/// be dropped outside the lock.
+async fn load_and_swap<'a, T, E, F>(
+    slot: &'a RwLock<Option<T>>,
+    gate: &Mutex<()>,
+    load: F,
+) -> Result<(RwLockWriteGuard<'a, Option<T>>, Option<T>), E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let _gate = gate.lock().await;
+    let new_value = load.await?;
+    let mut guard = slot.write().await;
+    let previous = guard.replace(new_value);
+    Ok((guard, previous))
+}
+
+/// Run `load` and store its result in `slot`, unless the slot is already filled.
+///
+/// `gate` makes this single-flight: callers arriving while a load runs wait for
+/// it and then find the slot filled instead of loading again. `loading` is set
+/// for the duration of the load. If the load fails the slot stays empty and the
+/// next caller tries again. Returns true if this call performed the load.
+async fn load_once<T, E, F>(
+    slot: &RwLock<Option<T>>,
+    gate: &Mutex<()>,
+    loading: &AtomicBool,
+    load: F,
+) -> Result<bool, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    if slot.read().await.is_some() {
+        return Ok(false);
+    }
+
+    let _gate = gate.lock().await;
+    if slot.read().await.is_some() {
+        return Ok(false);
+    }
+
+    let _loading = FlagGuard::acquire(loading);
+    let value = load.await?;
+    *slot.write().await = Some(value);
+    Ok(true)
 }
 
 impl ModelManager {
     pub fn new(config: ModelConfig) -> Result<Self, ModelError> {
-        // Configure llama.cpp logging based on debug setting
-        if config.debug {
-            // Enable debug logging - send llama.cpp logs to tracing
-            send_logs_to_tracing(LogOptions::default());
-            debug!("Enabled verbose llama.cpp logging via tracing");
-            set_logging_suppression(false);
-        } else {
-            // When debug is false, we rely on the tracing level configuration
-            // from main.rs (WARN level) to filter out verbose logs
-            debug!("llama.cpp logs will be filtered by tracing WARN level");
-            set_logging_suppression(true);
-        }
+        // Send llama.cpp output to tracing, with loading chatter only in debug mode
+        configure_llama_logging(config.debug);
 
         // Get existing backend or try to initialize new one
         let backend = if let Some(backend) = GLOBAL_BACKEND.get() {
@@ -100,28 +266,196 @@ impl ModelManager {
         let manager = Self {
             model: Arc::new(RwLock::new(None)),
             backend,
-            config,
+            chat_template: std::sync::RwLock::new(Arc::new(ChatTemplateEngine::for_model(&config))),
+            config: std::sync::RwLock::new(config),
             loader: RwLock::new(None),
             metadata: RwLock::new(None),
+            hf_generation_defaults: RwLock::new(None),
+            loaded_at: RwLock::new(None),
             memory_usage_bytes: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            reloading: AtomicBool::new(false),
+            load_mode: LoadMode::Eager,
+            load_gate: Mutex::new(()),
+            loading: AtomicBool::new(false),
+            context_pool: ContextPool::new(1),
+            cancel: CancellationToken::new(),
         };
         Ok(manager)
     }
 
+    /// Set whether the model is loaded up front or by the first request
+    pub fn with_load_mode(mut self, load_mode: LoadMode) -> Self {
+        self.load_mode = load_mode;
+        self
+    }
+
+    /// Set how many contexts may exist at once, typically one per queue worker.
+    ///
+    /// The CPU threads used for inference are split between the contexts.
+    pub fn with_context_pool_size(mut self, size: usize) -> Self {
+        self.context_pool = ContextPool::new(size);
+        self
+    }
+
+    /// Report `metadata` as that of a model loaded just now, without loading one
+    #[cfg(any(test, feature = "fake-backend"))]
+    pub(crate) fn with_metadata(self, metadata: ModelMetadata) -> Self {
+        Self {
+            metadata: RwLock::new(Some(metadata)),
+            loaded_at: RwLock::new(Some(SystemTime::now())),
+            ..self
+        }
+    }
+
+    /// Abandon model downloads in progress when `cancel` is cancelled; loading then
+    /// fails with [`ModelError::Cancelled`]
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn context_pool(&self) -> &ContextPool {
+        &self.context_pool
+    }
+
+    /// Wait for a context slot. Contexts are created with the lease and must be
+    /// dropped before it.
+    pub async fn acquire_context(&self) -> ContextLease {
+        self.context_pool.acquire().await
+    }
+
+    pub fn load_mode(&self) -> LoadMode {
+        self.load_mode
+    }
+
     /// Initialize the ModelLoader (must be called after construction)
     pub async fn initialize_loader(&self) -> Result<(), ModelError> {
-        let mut loader = ModelLoader::new(self.backend.clone())?;
+        let config = self.get_config();
+        let mut loader =
+            ModelLoader::with_cache_dir(self.backend.clone(), config.cache_dir.as_deref())?
+                .with_cancellation(self.cancel.clone())
+                .with_context_count(self.context_pool.size());
         loader.initialize().await?;
         *self.loader.write().await = Some(loader);
         Ok(())
     }
 
     pub async fn load_model(&self) -> Result<(), ModelError> {
-        info!("Loading model with configuration: {:?}", self.config);
+        let config = self.get_config();
+        info!("Loading model with configuration: {:?}", config);
 
         // Validate config before proceeding
-        self.config.validate()?;
+        config.validate()?;
+
+        let loaded_model = self.load_with_loader(&config).await?;
 
+        // Store model and metadata
+        {
+            let mut model_lock = self.model.write().await;
+            *model_lock = Some(loaded_model.model);
+        }
+        self.record_load(loaded_model.metadata, loaded_model.hf_generation_defaults)
+            .await;
+
+        Ok(())
+    }
+
+    /// Load a different model and swap it in without interrupting the manager.
+    ///
+    /// The new model is loaded while requests keep running on the current one;
+    /// the swap then waits for in-flight requests to finish. The config, metadata
+    /// and chat template change along with the model, before any request sees it.
+    /// Both models are briefly resident in memory. If loading fails, the current
+    /// model, config and metadata are left untouched.
+    pub async fn reload_model(&self, new_config: ModelConfig) -> Result<(), ModelError> {
+        new_config.validate()?;
+
+        let _reloading = FlagGuard::acquire(&self.reloading).ok_or_else(|| {
+            ModelError::LoadingFailed("A model reload is already in progress".to_string())
+        })?;
+        info!("Reloading model with configuration: {:?}", new_config);
+
+        let mut loaded = None;
+        let (swapped, previous) = load_and_swap(&self.model, &self.load_gate, async {
+            let loaded_model = self.load_with_loader(&new_config).await?;
+            loaded = Some((loaded_model.metadata, loaded_model.hf_generation_defaults));
+            Ok::<_, ModelError>(loaded_model.model)
+        })
+        .await?;
+
+        configure_llama_logging(new_config.debug);
+        *self
+            .chat_template
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) =
+            Arc::new(ChatTemplateEngine::for_model(&new_config));
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = new_config;
+        if let Some((metadata, hf_generation_defaults)) = loaded {
+            self.record_load(metadata, hf_generation_defaults).await;
+        }
+        drop(swapped);
+
+        // Free the old model only after the swap has released the lock
+        drop(previous);
+        info!("Model reload completed");
+
+        Ok(())
+    }
+
+    /// Whether a model reload is currently in progress
+    pub fn is_reloading(&self) -> bool {
+        self.reloading.load(Ordering::Acquire)
+    }
+
+    /// Make sure a model is loaded before serving a request.
+    ///
+    /// In lazy mode the first call loads the model; concurrent callers wait for
+    /// that load instead of starting their own. In eager mode a missing model
+    /// is an error.
+    pub async fn ensure_loaded(&self) -> Result<(), ModelError> {
+        if self.load_mode == LoadMode::Eager {
+            return if self.is_loaded().await {
+                Ok(())
+            } else {
+                Err(ModelError::NotLoaded)
+            };
+        }
+
+        let config = self.get_config();
+        let mut loaded = None;
+        load_once(&self.model, &self.load_gate, &self.loading, async {
+            info!("Loading model on first use: {:?}", config);
+            config.validate()?;
+            let loaded_model = self.load_with_loader(&config).await?;
+            loaded = Some((loaded_model.metadata, loaded_model.hf_generation_defaults));
+            Ok::<_, ModelError>(loaded_model.model)
+        })
+        .await?;
+
+        if let Some((metadata, hf_generation_defaults)) = loaded {
+            self.record_load(metadata, hf_generation_defaults).await;
+        }
+        Ok(())
+    }
+
+    /// Store what is known about a model that was just loaded
+    async fn record_load(
+        &self,
+        metadata: ModelMetadata,
+        hf_generation_defaults: Option<HfGenerationDefaults>,
+    ) {
+        *self.metadata.write().await = Some(metadata);
+        *self.hf_generation_defaults.write().await = hf_generation_defaults;
+        *self.loaded_at.write().await = Some(SystemTime::now());
+    }
+
+    /// Whether a lazily loaded model is loading for the first time
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Acquire)
+    }
+
+    /// Load a model through the ModelLoader, initializing it on first use
+    async fn load_with_loader(&self, config: &ModelConfig) -> Result<LoadedModel, ModelError> {
         // Ensure loader is initialized
         {
             let loader_guard = self.loader.read().await;
@@ -138,11 +472,7 @@ impl ModelManager {
         // Load model using ModelLoader
         let loaded_model = {
             let mut loader_guard = self.loader.write().await;
-            loader_guard
-                .as_mut()
-                .unwrap()
-                .load_model(&self.config)
-                .await?
+            loader_guard.as_mut().unwrap().load_model(config).await?
         };
 
         let memory_after = Self::get_process_memory_mb().unwrap_or(0);
@@ -162,14 +492,7 @@ impl ModelManager {
             loaded_model.metadata.cache_hit
         );
 
-        // Store model and metadata
-        {
-            let mut model_lock = self.model.write().await;
-            *model_lock = Some(loaded_model.model);
-        }
-        *self.metadata.write().await = Some(loaded_model.metadata);
-
-        Ok(())
+        Ok(loaded_model)
     }
 
     pub async fn is_loaded(&self) -> bool {
@@ -177,12 +500,39 @@ impl ModelManager {
         model_lock.is_some()
     }
 
+    /// Free the loaded model once requests using it have finished, returning
+    /// whether one was loaded
+    pub async fn unload_model(&self) -> bool {
+        let previous = self.model.write().await.take();
+        *self.loaded_at.write().await = None;
+        self.memory_usage_bytes
+            .store(0, std::sync::atomic::Ordering::Relaxed);
+        let was_loaded = previous.is_some();
+        drop(previous);
+        if was_loaded {
+            info!("Model unloaded");
+        }
+        was_loaded
+    }
+
     pub fn get_batch_size(&self) -> usize {
-        self.config.batch_size as usize
+        self.get_config().batch_size as usize
     }
 
-    pub fn get_config(&self) -> &ModelConfig {
-        &self.config
+    /// Get the configuration of the currently loaded model
+    pub fn get_config(&self) -> ModelConfig {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Get the chat template engine for the currently loaded model
+    pub fn chat_template(&self) -> Arc<ChatTemplateEngine> {
+        self.chat_template
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
     }
 
     pub async fn with_model<F, R>(&self, f: F) -> Result<R, ModelError>
@@ -192,32 +542,91 @@ impl ModelManager {
         let model_lock = self.model.read().await;
         match model_lock.as_ref() {
             Some(model) => Ok(f(model)),
-            None => Err(ModelError::LoadingFailed("Model not loaded".to_string())),
+            None => Err(ModelError::NotLoaded),
         }
     }
 
+    /// Like [`Self::with_model`], for blocking threads such as those of `spawn_blocking`.
+    ///
+    /// Panics if called from async code.
+    pub fn with_model_blocking<F, R>(&self, f: F) -> Result<R, ModelError>
+    where
+        F: FnOnce(&LlamaModel) -> R,
+    {
+        let model_lock = self.model.blocking_read();
+        match model_lock.as_ref() {
+            Some(model) => Ok(f(model)),
+            None => Err(ModelError::NotLoaded),
+        }
+    }
+
+    /// Create a context that can decode `n_seq` sequences side by side
+    pub fn create_multi_sequence_context<'a>(
+        &self,
+        model: &'a LlamaModel,
+        n_seq: u32,
+        _lease: &ContextLease,
+    ) -> Result<LlamaContext<'a>, ModelError> {
+        model
+            .new_context(&self.backend, self.context_params().with_n_seq_max(n_seq))
+            .map_err(move |e| ModelError::LoadingFailed(format!("Failed to create context: {}", e)))
+    }
+
+    /// Create a context for one request, taking the slot of `_lease`
     pub fn create_context<'a>(
         &self,
         model: &'a LlamaModel,
+        _lease: &ContextLease,
+    ) -> Result<LlamaContext<'a>, ModelError> {
+        model
+            .new_context(&self.backend, self.context_params())
+            .map_err(move |e| ModelError::LoadingFailed(format!("Failed to create context: {}", e)))
+    }
+
+    /// Create a context that outputs mean-pooled sequence embeddings instead of logits
+    pub fn create_embedding_context<'a>(
+        &self,
+        model: &'a LlamaModel,
+        _lease: &ContextLease,
     ) -> Result<LlamaContext<'a>, ModelError> {
-        // Ensure context size is at least as large as batch size, minimum 8192
-        let n_ctx = std::cmp::max(8192, self.config.batch_size);
-        let n_batch = self.config.batch_size;
-        let n_ubatch = self.config.batch_size;
+        let context_params = self
+            .context_params()
+            .with_embeddings(true)
+            .with_pooling_type(LlamaPoolingType::Mean);
+
+        model
+            .new_context(&self.backend, context_params)
+            .map_err(move |e| {
+                ModelError::LoadingFailed(format!("Failed to create embedding context: {}", e))
+            })
+    }
+
+    /// Tokens held by each context: the batch size, but at least 8192
+    pub fn context_size(&self) -> usize {
+        self.get_config().context_size() as usize
+    }
+
+    fn context_params(&self) -> LlamaContextParams {
+        let batch_size = self.get_batch_size() as u32;
+        let n_ctx = self.context_size() as u32;
+        let n_batch = batch_size;
+        let n_ubatch = batch_size;
+        // Contexts of the pool decode in parallel, so they share the CPU threads
+        let n_threads = (Self::get_optimal_thread_count() / self.context_pool.size() as u32).max(1);
 
         let context_params = LlamaContextParams::default()
             .with_n_ctx(Some(std::num::NonZero::new(n_ctx).unwrap()))
             .with_n_batch(n_batch)
-            .with_n_ubatch(n_ubatch);
+            .with_n_ubatch(n_ubatch)
+            .with_n_threads(n_threads as i32)
+            .with_n_threads_batch(n_threads as i32);
 
         debug!(
-            "Creating context with n_ctx={}, n_batch={}, n_ubatch={}",
-            n_ctx, n_batch, n_ubatch
+            "Creating context with n_ctx={}, n_batch={}, n_ubatch={}, n_threads={}",
+            n_ctx, n_batch, n_ubatch, n_threads
         );
 
-        model
-            .new_context(&self.backend, context_params)
-            .map_err(move |e| ModelError::LoadingFailed(format!("Failed to create context: {}", e)))
+        context_params
     }
 
     /// Get current process memory usage in MB
@@ -257,7 +666,6 @@ impl ModelManager {
     }
 
     /// Get optimal thread count for inference
-    #[allow(dead_code)]
     fn get_optimal_thread_count() -> u32 {
         let logical_cores = std::thread::available_parallelism()
             .map(|p| p.get() as u32)
@@ -291,6 +699,23 @@ impl ModelManager {
     pub async fn get_metadata(&self) -> Option<ModelMetadata> {
         self.metadata.read().await.clone()
     }
+
+    /// Sampling defaults published with the loaded model, if `use_hf_params` fetched any
+    pub async fn hf_generation_defaults(&self) -> Option<HfGenerationDefaults> {
+        self.hf_generation_defaults.read().await.clone()
+    }
+
+    /// Get a summary of the loaded model for health reporting
+    pub async fn get_model_info(&self) -> Option<ModelInfo> {
+        let metadata = self.metadata.read().await;
+        let loaded_at = self.loaded_at.read().await;
+        match (metadata.as_ref(), *loaded_at) {
+            (Some(metadata), Some(loaded_at)) => {
+                Some(ModelInfo::from_metadata(metadata, loaded_at))
+            }
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -308,6 +733,15 @@ mod tests {
             use_hf_params: false,
             retry_config: crate::types::RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         }
     }
 
@@ -318,6 +752,15 @@ mod tests {
             use_hf_params: true,
             retry_config: crate::types::RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         }
     }
 
@@ -532,7 +975,7 @@ mod tests {
 
         // The function exists and can be called - detailed testing would require
         // mocking the HuggingFace API which is complex
-        assert_eq!(manager.config.retry_config.max_retries, 3);
+        assert_eq!(manager.get_config().retry_config.max_retries, 3);
     }
 
     #[test]
@@ -572,4 +1015,304 @@ mod tests {
         assert_eq!(config.retry_config.backoff_multiplier, 1.5);
         assert_eq!(config.retry_config.max_delay_ms, 10000);
     }
+
+    #[tokio::test]
+    async fn test_load_and_swap_waits_for_readers() {
+        let slot = Arc::new(RwLock::new(Some("old-model")));
+
+        // Simulate an in-flight request holding the model
+        let reader = slot.clone().read_owned().await;
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+
+        let swap_slot = slot.clone();
+        let swap = tokio::spawn(async move {
+            let gate = Mutex::new(());
+            load_and_swap(&swap_slot, &gate, async {
+                let _ = started_tx.send(());
+                Ok::<_, ModelError>("new-model")
+            })
+            .await
+            .map(|(_, previous)| previous)
+        });
+
+        // Loading happens without the lock, but the swap must wait for the reader
+        started_rx.await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(*reader, Some("old-model"));
+        assert!(!swap.is_finished());
+
+        drop(reader);
+        let previous = swap.await.unwrap().unwrap();
+        assert_eq!(previous, Some("old-model"));
+        assert_eq!(*slot.read().await, Some("new-model"));
+    }
+
+    #[tokio::test]
+    async fn test_load_and_swap_failure_keeps_old_value() {
+        let slot = RwLock::new(Some("old-model"));
+        let gate = Mutex::new(());
+
+        let result = load_and_swap(&slot, &gate, async {
+            Err::<&str, _>(ModelError::LoadingFailed("dummy load failure".to_string()))
+        })
+        .await;
+
+        assert!(matches!(result, Err(ModelError::LoadingFailed(_))));
+        assert_eq!(*slot.read().await, Some("old-model"));
+    }
+
+    #[test]
+    fn test_reload_guard_is_exclusive() {
+        let flag = AtomicBool::new(false);
+
+        let guard = FlagGuard::acquire(&flag).expect("first reload should start");
+        assert!(flag.load(Ordering::Acquire));
+        assert!(FlagGuard::acquire(&flag).is_none());
+
+        drop(guard);
+        assert!(!flag.load(Ordering::Acquire));
+        assert!(FlagGuard::acquire(&flag).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_load_once_is_single_flight() {
+        let slot = Arc::new(RwLock::new(None));
+        let gate = Arc::new(Mutex::new(()));
+        let loading = Arc::new(AtomicBool::new(false));
+        let loads = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let (slot, gate, loading, loads) =
+                    (slot.clone(), gate.clone(), loading.clone(), loads.clone());
+                tokio::spawn(async move {
+                    load_once(&slot, &gate, &loading, async {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        // Slow loader, so the other callers arrive mid-load
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        Ok::<_, ModelError>("model")
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(loading.load(Ordering::Acquire));
+
+        let mut performed = 0;
+        for caller in callers {
+            if caller.await.unwrap().unwrap() {
+                performed += 1;
+            }
+        }
+        assert_eq!(performed, 1);
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(!loading.load(Ordering::Acquire));
+        assert_eq!(*slot.read().await, Some("model"));
+    }
+
+    #[tokio::test]
+    async fn test_load_once_failure_allows_retry() {
+        let slot = RwLock::new(None);
+        let gate = Mutex::new(());
+        let loading = AtomicBool::new(false);
+
+        let result = load_once(&slot, &gate, &loading, async {
+            Err::<&str, _>(ModelError::LoadingFailed("dummy load failure".to_string()))
+        })
+        .await;
+        assert!(matches!(result, Err(ModelError::LoadingFailed(_))));
+        assert!(!loading.load(Ordering::Acquire));
+        assert!(slot.read().await.is_none());
+
+        let result = load_once(&slot, &gate, &loading, async {
+            Ok::<_, ModelError>("model")
+        })
+        .await;
+        assert!(result.unwrap());
+        assert_eq!(*slot.read().await, Some("model"));
+    }
+
+    #[tokio::test]
+    async fn test_load_once_waits_for_load_and_swap() {
+        let slot = Arc::new(RwLock::new(None));
+        let gate = Arc::new(Mutex::new(()));
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+
+        let (swap_slot, swap_gate) = (slot.clone(), gate.clone());
+        let swap = tokio::spawn(async move {
+            load_and_swap(&swap_slot, &swap_gate, async {
+                let _ = started_tx.send(());
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                Ok::<_, ModelError>("reloaded")
+            })
+            .await
+            .map(|(_, previous)| previous)
+        });
+
+        // A first load arriving mid-reload finds the reloaded model instead of loading
+        started_rx.await.unwrap();
+        let loading = AtomicBool::new(false);
+        let performed = load_once(&slot, &gate, &loading, async {
+            Ok::<_, ModelError>("first-load")
+        })
+        .await
+        .unwrap();
+
+        assert!(!performed);
+        assert_eq!(swap.await.unwrap().unwrap(), None);
+        assert_eq!(*slot.read().await, Some("reloaded"));
+    }
+
+    #[tokio::test]
+    async fn test_ensure_loaded_in_eager_mode() {
+        let config = create_test_config_local(PathBuf::from("/tmp"), None);
+        let manager = match ModelManager::new(config) {
+            Ok(manager) => manager,
+            Err(ModelError::LoadingFailed(msg)) if msg.contains("Backend already initialized") => {
+                return;
+            }
+            Err(e) => panic!("Unexpected error: {:?}", e),
+        };
+
+        // Eager managers never load on demand
+        assert_eq!(manager.load_mode(), LoadMode::Eager);
+        let result = manager.ensure_loaded().await;
+        assert!(matches!(result, Err(ModelError::NotLoaded)));
+        assert!(!manager.is_loading());
+    }
+
+    #[tokio::test]
+    async fn test_reload_model_rejects_invalid_config() {
+        let config = create_test_config_local(PathBuf::from("/tmp"), None);
+        let manager = match ModelManager::new(config.clone()) {
+            Ok(manager) => manager,
+            Err(ModelError::LoadingFailed(msg)) if msg.contains("Backend already initialized") => {
+                return;
+            }
+            Err(e) => panic!("Unexpected error: {:?}", e),
+        };
+
+        let mut invalid = config;
+        invalid.batch_size = 0;
+        assert!(manager.reload_model(invalid).await.is_err());
+        assert!(!manager.is_reloading());
+        assert!(!manager.is_loaded().await);
+        assert_eq!(manager.get_batch_size(), 512);
+    }
+
+    #[test]
+    fn test_llama_log_level_mapping() {
+        let info = GGML_LOG_LEVEL_INFO;
+        assert_eq!(llama_log_level(info, info, true), Some(Level::DEBUG));
+        assert_eq!(llama_log_level(info, info, false), None);
+        assert_eq!(
+            llama_log_level(GGML_LOG_LEVEL_DEBUG, info, true),
+            Some(Level::TRACE)
+        );
+        assert_eq!(llama_log_level(GGML_LOG_LEVEL_DEBUG, info, false), None);
+
+        for verbose in [true, false] {
+            assert_eq!(
+                llama_log_level(GGML_LOG_LEVEL_WARN, info, verbose),
+                Some(Level::WARN)
+            );
+            assert_eq!(
+                llama_log_level(GGML_LOG_LEVEL_ERROR, info, verbose),
+                Some(Level::ERROR)
+            );
+            assert_eq!(llama_log_level(0, info, verbose), None);
+        }
+
+        // Continuations follow the message they belong to
+        assert_eq!(
+            llama_log_level(GGML_LOG_LEVEL_CONT, GGML_LOG_LEVEL_WARN, false),
+            Some(Level::WARN)
+        );
+        assert_eq!(llama_log_level(GGML_LOG_LEVEL_CONT, info, false), None);
+    }
+
+    #[test]
+    fn test_handle_llama_log_messages() {
+        let messages = [
+            (
+                GGML_LOG_LEVEL_INFO,
+                "llama_model_loader: loaded meta data\n",
+            ),
+            (GGML_LOG_LEVEL_CONT, "."),
+            (
+                GGML_LOG_LEVEL_WARN,
+                "load: special tokens cache size = 22\n",
+            ),
+            (GGML_LOG_LEVEL_CONT, "continued warning\n"),
+            (GGML_LOG_LEVEL_ERROR, "\n"),
+            (GGML_LOG_LEVEL_ERROR, "failed to allocate buffer\n"),
+        ];
+        let collect = |verbose: bool| {
+            let last_level = AtomicI32::new(GGML_LOG_LEVEL_INFO);
+            let mut emitted = Vec::new();
+            for (level, text) in messages {
+                handle_llama_log(level, text, verbose, &last_level, |level, text| {
+                    emitted.push((level, text.to_string()))
+                });
+            }
+            emitted
+        };
+
+        let quiet = collect(false);
+        assert_eq!(
+            quiet,
+            vec![
+                (
+                    Level::WARN,
+                    "load: special tokens cache size = 22".to_string()
+                ),
+                (Level::WARN, "continued warning".to_string()),
+                (Level::ERROR, "failed to allocate buffer".to_string()),
+            ]
+        );
+
+        let verbose = collect(true);
+        assert_eq!(verbose.len(), 5);
+        assert_eq!(
+            verbose[0],
+            (
+                Level::DEBUG,
+                "llama_model_loader: loaded meta data".to_string()
+            )
+        );
+        assert_eq!(verbose[1], (Level::DEBUG, ".".to_string()));
+    }
+
+    #[test]
+    fn test_install_once() {
+        let guard = Once::new();
+        let installs = std::sync::atomic::AtomicUsize::new(0);
+        let install = || {
+            installs.fetch_add(1, Ordering::SeqCst);
+        };
+
+        let installed: Vec<bool> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| install_once(&guard, install)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(installs.load(Ordering::SeqCst), 1);
+        assert_eq!(installed.iter().filter(|&&i| i).count(), 1);
+        assert!(!install_once(&guard, install));
+    }
+
+    #[test]
+    fn test_configure_llama_logging_installs_callback_once() {
+        configure_llama_logging(false);
+        assert!(LLAMA_LOG_INSTALL.is_completed());
+        assert!(!LLAMA_VERBOSE_LOGS.load(Ordering::Relaxed));
+
+        configure_llama_logging(true);
+        assert!(LLAMA_VERBOSE_LOGS.load(Ordering::Relaxed));
+        assert!(!install_once(&LLAMA_LOG_INSTALL, || unreachable!()));
+    }
 }
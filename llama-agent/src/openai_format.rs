@@ -0,0 +1,529 @@
+//! Conversion between sessions and the OpenAI chat messages format.
+//!
+//! Messages map to `{"role": ..., "content": ..., "tool_call_id": ...}` objects. Tool calls are
+//! not stored separately in a session: the assistant message holds the generated text and the
+//! Tool messages after it hold one result each. On export, the calls in that text become the
+//! assistant's `tool_calls` array, matched in order to the results that follow. On import,
+//! `tool_calls` are written into the assistant text so the model and the tool call parsers see
+//! them.
+
+use crate::chat_template::ChatTemplateEngine;
+use crate::types::{Message, MessageRole, OpenAIFormatError, Session, ToolCall, ToolCallId};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tracing::warn;
+
+impl Session {
+    /// The session's messages in the OpenAI chat format.
+    ///
+    /// Only `content` is exported; attachments are left out.
+    pub fn to_openai_messages(&self) -> Vec<Value> {
+        let engine = ChatTemplateEngine::new();
+        let mut exported = Vec::with_capacity(self.messages.len());
+        // Calls of the last assistant message, by the ID of the result answering them
+        let mut call_names: HashMap<ToolCallId, String> = HashMap::new();
+
+        for (index, message) in self.messages.iter().enumerate() {
+            let mut object = Map::new();
+            object.insert("role".to_string(), json!(message.role.as_str()));
+            object.insert("content".to_string(), json!(message.content));
+
+            match message.role {
+                MessageRole::Assistant => {
+                    let results = tool_results_after(&self.messages[index + 1..]);
+                    let calls = detected_tool_calls(&engine, message, &results);
+                    call_names = calls
+                        .iter()
+                        .map(|call| (call.id, call.name.clone()))
+                        .collect();
+                    if !calls.is_empty() {
+                        let tool_calls: Vec<Value> = calls.iter().map(openai_tool_call).collect();
+                        object.insert("tool_calls".to_string(), Value::Array(tool_calls));
+                    }
+                }
+                MessageRole::Tool => {
+                    if let Some(call_id) = message.tool_call_id {
+                        object.insert("tool_call_id".to_string(), json!(call_id.to_string()));
+                    }
+                    let name = message.tool_name.clone().or_else(|| {
+                        message
+                            .tool_call_id
+                            .and_then(|id| call_names.get(&id).cloned())
+                    });
+                    if let Some(name) = name {
+                        object.insert("name".to_string(), json!(name));
+                    }
+                }
+                MessageRole::System | MessageRole::User => {}
+            }
+
+            exported.push(Value::Object(object));
+        }
+
+        exported
+    }
+}
+
+/// IDs of the Tool messages directly following an assistant message
+fn tool_results_after(messages: &[Message]) -> Vec<Option<ToolCallId>> {
+    messages
+        .iter()
+        .take_while(|m| m.role == MessageRole::Tool)
+        .map(|m| m.tool_call_id)
+        .collect()
+}
+
+/// Tool calls in an assistant message that has tool results after it, carrying the IDs of
+/// those results in order
+fn detected_tool_calls(
+    engine: &ChatTemplateEngine,
+    message: &Message,
+    results: &[Option<ToolCallId>],
+) -> Vec<ToolCall> {
+    // Text of a final answer is not checked, so JSON in it is not mistaken for a call
+    if results.is_empty() {
+        return Vec::new();
+    }
+
+    let mut calls = engine
+        .extract_tool_calls(&message.content)
+        .unwrap_or_default();
+    for (call, result_id) in calls.iter_mut().zip(results) {
+        if let Some(id) = result_id {
+            call.id = *id;
+        }
+    }
+    calls
+}
+
+fn openai_tool_call(call: &ToolCall) -> Value {
+    json!({
+        "id": call.id.to_string(),
+        "type": "function",
+        "function": {
+            "name": call.name,
+            "arguments": call.arguments.to_string(),
+        },
+    })
+}
+
+/// Convert messages in the OpenAI chat format into session messages stamped with `timestamp`.
+///
+/// Unknown fields are ignored with a warning. Tool call IDs that are not ULIDs, such as
+/// `call_abc123`, get a new ID that is used consistently for the call and its result.
+pub fn messages_from_openai(
+    value: &Value,
+    timestamp: SystemTime,
+) -> Result<Vec<Message>, OpenAIFormatError> {
+    let Value::Array(items) = value else {
+        return Err(OpenAIFormatError::NotAnArray(json_type(value).to_string()));
+    };
+
+    let engine = ChatTemplateEngine::new();
+    let mut importer = Importer::default();
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| importer.message(&engine, index, item, timestamp))
+        .collect()
+}
+
+/// State shared across the messages of one import
+#[derive(Default)]
+struct Importer {
+    ids: HashMap<String, ToolCallId>,
+    call_names: HashMap<ToolCallId, String>,
+}
+
+impl Importer {
+    fn message(
+        &mut self,
+        engine: &ChatTemplateEngine,
+        index: usize,
+        item: &Value,
+        timestamp: SystemTime,
+    ) -> Result<Message, OpenAIFormatError> {
+        let invalid = |reason: &str| OpenAIFormatError::InvalidMessage {
+            index,
+            reason: reason.to_string(),
+        };
+
+        let Value::Object(object) = item else {
+            return Err(invalid(&format!(
+                "expected an object, found {}",
+                json_type(item)
+            )));
+        };
+
+        let role = match object.get("role") {
+            Some(Value::String(role)) => match role.as_str() {
+                "system" => MessageRole::System,
+                "user" => MessageRole::User,
+                "assistant" => MessageRole::Assistant,
+                "tool" => MessageRole::Tool,
+                _ => {
+                    return Err(OpenAIFormatError::InvalidRole {
+                        index,
+                        role: role.clone(),
+                    })
+                }
+            },
+            Some(_) => return Err(invalid("role must be a string")),
+            None => return Err(invalid("missing role")),
+        };
+
+        let known_fields: &[&str] = match role {
+            MessageRole::Assistant => &["role", "content", "tool_calls"],
+            MessageRole::Tool => &["role", "content", "tool_call_id", "name"],
+            MessageRole::System | MessageRole::User => &["role", "content"],
+        };
+        for key in object.keys() {
+            if !known_fields.contains(&key.as_str()) {
+                warn!(
+                    "Ignoring unsupported field '{}' on {} message {}",
+                    key,
+                    role.as_str(),
+                    index
+                );
+            }
+        }
+
+        let mut content = content_text(object.get("content"), index)?;
+        let mut tool_call_id = None;
+        let mut tool_name = None;
+
+        match role {
+            MessageRole::Assistant => {
+                let calls = match object.get("tool_calls") {
+                    None | Some(Value::Null) => Vec::new(),
+                    Some(Value::Array(calls)) => calls
+                        .iter()
+                        .map(|call| self.tool_call(call, index))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    Some(_) => return Err(invalid("tool_calls must be an array")),
+                };
+                self.call_names = calls
+                    .iter()
+                    .map(|call| (call.id, call.name.clone()))
+                    .collect();
+
+                // Text that already holds the calls, e.g. from an export, is kept as it is
+                let in_text = engine.extract_tool_calls(&content).unwrap_or_default();
+                if !calls.is_empty() && in_text.is_empty() {
+                    for call in &calls {
+                        if !content.is_empty() {
+                            content.push('\n');
+                        }
+                        content.push_str(
+                            &json!({"function_name": call.name, "arguments": call.arguments})
+                                .to_string(),
+                        );
+                    }
+                }
+            }
+            MessageRole::Tool => {
+                let id = match object.get("tool_call_id") {
+                    Some(Value::String(id)) => self.resolve_id(id),
+                    Some(_) => return Err(invalid("tool_call_id must be a string")),
+                    None => return Err(invalid("tool messages need a tool_call_id")),
+                };
+                tool_name = match object.get("name") {
+                    Some(Value::String(name)) => Some(name.clone()),
+                    _ => self.call_names.get(&id).cloned(),
+                };
+                tool_call_id = Some(id);
+            }
+            MessageRole::System | MessageRole::User => {}
+        }
+
+        Ok(Message {
+            role,
+            content,
+            tool_call_id,
+            tool_name,
+            timestamp,
+            attachments: Vec::new(),
+        })
+    }
+
+    /// One entry of an assistant's `tool_calls` array
+    fn tool_call(&mut self, call: &Value, index: usize) -> Result<ToolCall, OpenAIFormatError> {
+        let invalid = |reason: &str| OpenAIFormatError::InvalidMessage {
+            index,
+            reason: reason.to_string(),
+        };
+
+        let function = call
+            .get("function")
+            .ok_or_else(|| invalid("tool call without a function"))?;
+        let name = function
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("tool call function without a name"))?;
+        // OpenAI sends arguments as a JSON-encoded string
+        let arguments = match function.get("arguments") {
+            None | Some(Value::Null) => json!({}),
+            Some(Value::String(encoded)) => serde_json::from_str(encoded).map_err(|e| {
+                invalid(&format!(
+                    "arguments of tool call '{}' are not JSON: {}",
+                    name, e
+                ))
+            })?,
+            Some(arguments) => arguments.clone(),
+        };
+        let id = match call.get("id").and_then(Value::as_str) {
+            Some(id) => self.resolve_id(id),
+            None => ToolCallId::new(),
+        };
+
+        Ok(ToolCall {
+            id,
+            name: name.to_string(),
+            arguments,
+        })
+    }
+
+    /// The session ID for an external tool call ID, the same for every mention of it
+    fn resolve_id(&mut self, external: &str) -> ToolCallId {
+        *self
+            .ids
+            .entry(external.to_string())
+            .or_insert_with(|| external.parse().unwrap_or_default())
+    }
+}
+
+/// Text of a `content` field: a string, null, or an array of content parts
+fn content_text(content: Option<&Value>, index: usize) -> Result<String, OpenAIFormatError> {
+    match content {
+        None | Some(Value::Null) => Ok(String::new()),
+        Some(Value::String(text)) => Ok(text.clone()),
+        Some(Value::Array(parts)) => {
+            let mut texts = Vec::new();
+            for part in parts {
+                match part.get("text").and_then(Value::as_str) {
+                    Some(text) => texts.push(text),
+                    None => {
+                        let kind = part
+                            .get("type")
+                            .and_then(Value::as_str)
+                            .unwrap_or("unknown");
+                        warn!(
+                            "Ignoring non-text content part of type {} on message {}",
+                            kind, index
+                        );
+                    }
+                }
+            }
+            Ok(texts.join("\n"))
+        }
+        Some(other) => Err(OpenAIFormatError::InvalidMessage {
+            index,
+            reason: format!(
+                "content must be a string or an array of parts, found {}",
+                json_type(other)
+            ),
+        }),
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{SessionId, SessionUsage, ToolPolicy};
+
+    fn message(role: MessageRole, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            tool_call_id: None,
+            tool_name: None,
+            timestamp: SystemTime::now(),
+            attachments: Vec::new(),
+        }
+    }
+
+    fn tool_result(call_id: ToolCallId, content: &str) -> Message {
+        Message {
+            tool_call_id: Some(call_id),
+            ..message(MessageRole::Tool, content)
+        }
+    }
+
+    fn session(messages: Vec<Message>) -> Session {
+        Session {
+            id: SessionId::new(),
+            messages,
+            mcp_servers: Vec::new(),
+            available_tools: Vec::new(),
+            available_prompts: Vec::new(),
+            created_at: SystemTime::now(),
+            updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::default(),
+            usage: SessionUsage::default(),
+        }
+    }
+
+    /// A conversation as the agent stores it: calls in the assistant text, one result each
+    fn tool_conversation() -> (Session, ToolCallId, ToolCallId) {
+        let read_id = ToolCallId::new();
+        let list_id = ToolCallId::new();
+        let session = session(vec![
+            message(MessageRole::System, "You are a helpful assistant."),
+            message(MessageRole::User, "What is in /tmp?"),
+            message(
+                MessageRole::Assistant,
+                "{\"function_name\": \"list_directory\", \"arguments\": {\"path\": \"/tmp\"}}\n{\"function_name\": \"read_file\", \"arguments\": {\"path\": \"/tmp/a.txt\"}}",
+            ),
+            tool_result(list_id, "a.txt"),
+            tool_result(read_id, "hello"),
+            message(MessageRole::Assistant, "/tmp holds a.txt, which says hello."),
+        ]);
+        (session, list_id, read_id)
+    }
+
+    #[test]
+    fn test_export_maps_roles_and_tool_calls() {
+        let (session, list_id, read_id) = tool_conversation();
+        let exported = session.to_openai_messages();
+
+        assert_eq!(exported.len(), 6);
+        assert_eq!(
+            exported[0],
+            json!({"role": "system", "content": "You are a helpful assistant."})
+        );
+        assert_eq!(exported[1]["role"], "user");
+
+        let tool_calls = exported[2]["tool_calls"].as_array().unwrap();
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0]["id"], list_id.to_string());
+        assert_eq!(tool_calls[0]["type"], "function");
+        assert_eq!(tool_calls[0]["function"]["name"], "list_directory");
+        let arguments: Value =
+            serde_json::from_str(tool_calls[0]["function"]["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(arguments, json!({"path": "/tmp"}));
+        assert_eq!(tool_calls[1]["id"], read_id.to_string());
+
+        assert_eq!(
+            exported[4],
+            json!({
+                "role": "tool",
+                "content": "hello",
+                "tool_call_id": read_id.to_string(),
+                "name": "read_file",
+            })
+        );
+        assert!(exported[5].get("tool_calls").is_none());
+    }
+
+    #[test]
+    fn test_round_trip_with_tool_calls() {
+        let (original, _, _) = tool_conversation();
+        let exported = Value::Array(original.to_openai_messages());
+
+        let messages = messages_from_openai(&exported, SystemTime::now()).unwrap();
+        let imported = session(messages);
+
+        for (before, after) in original.messages.iter().zip(&imported.messages) {
+            assert_eq!(before.role, after.role);
+            assert_eq!(before.content, after.content);
+            assert_eq!(before.tool_call_id, after.tool_call_id);
+        }
+        assert_eq!(Value::Array(imported.to_openai_messages()), exported);
+    }
+
+    #[test]
+    fn test_import_openai_tool_calls() {
+        let conversation = json!([
+            {"role": "user", "content": "Read a.txt"},
+            {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_abc123",
+                    "type": "function",
+                    "function": {"name": "read_file", "arguments": "{\"path\":\"a.txt\"}"}
+                }]
+            },
+            {"role": "tool", "tool_call_id": "call_abc123", "content": "hello"},
+            {"role": "assistant", "content": [{"type": "text", "text": "It says hello."}]}
+        ]);
+
+        let messages = messages_from_openai(&conversation, SystemTime::now()).unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[3].content, "It says hello.");
+
+        // The call is written into the assistant text and its ID carried to the result
+        assert_eq!(messages[2].tool_name.as_deref(), Some("read_file"));
+        let call_id = messages[2].tool_call_id.unwrap();
+        let calls = ChatTemplateEngine::new()
+            .extract_tool_calls(&messages[1].content)
+            .unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].name, "read_file");
+        assert_eq!(calls[0].arguments, json!({"path": "a.txt"}));
+
+        let exported = session(messages).to_openai_messages();
+        assert_eq!(
+            exported[1]["tool_calls"][0]["id"],
+            exported[2]["tool_call_id"]
+        );
+        assert_eq!(exported[2]["tool_call_id"], call_id.to_string());
+    }
+
+    #[test]
+    fn test_import_ignores_unknown_fields() {
+        let conversation = json!([
+            {"role": "user", "content": "Hi", "name": "alice", "metadata": {"source": "web"}},
+            {"role": "assistant", "content": "Hello!", "refusal": null}
+        ]);
+
+        let messages = messages_from_openai(&conversation, SystemTime::now()).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "Hi");
+        assert_eq!(messages[1].role, MessageRole::Assistant);
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_input() {
+        let now = SystemTime::now();
+
+        let error = messages_from_openai(
+            &json!([{"role": "user", "content": "Hi"}, {"role": "developer", "content": "x"}]),
+            now,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            OpenAIFormatError::InvalidRole { index: 1, ref role } if role == "developer"
+        ));
+
+        assert!(matches!(
+            messages_from_openai(&json!({"role": "user"}), now),
+            Err(OpenAIFormatError::NotAnArray(_))
+        ));
+
+        for invalid in [
+            json!([{"content": "no role"}]),
+            json!([{"role": "user", "content": 42}]),
+            json!([{"role": "tool", "content": "no call id"}]),
+            json!([{"role": "assistant", "tool_calls": [{"function": {"name": "f", "arguments": "{"}}]}]),
+        ] {
+            assert!(matches!(
+                messages_from_openai(&invalid, now),
+                Err(OpenAIFormatError::InvalidMessage { index: 0, .. })
+            ));
+        }
+    }
+}
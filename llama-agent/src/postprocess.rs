@@ -0,0 +1,398 @@
+//! Clean-up of generated text before it is returned
+//!
+//! `AgentConfig::post_processors` lists the steps, applied in order to the final text
+//! of every generation and to the answer stored in the session. Tool calls the model
+//! made are left exactly as generated, so the steps only see the text around them.
+//! Streamed chunks only have control tokens removed, since the other steps need the
+//! whole text.
+
+use crate::types::ConfigError;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+/// One step of the post-processing pipeline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostProcessor {
+    /// Remove the control tokens of the model's chat template, such as `<|im_end|>`
+    StripControlTokens,
+    /// Remove leading and trailing whitespace
+    TrimWhitespace,
+    /// Replace runs of blank lines with a single blank line
+    CollapseRepeatedNewlines,
+    /// Replace matches of the regular expression `pattern` with `replacement`, which
+    /// may refer to capture groups as `$1` or `${name}`
+    Custom {
+        pattern: String,
+        replacement: String,
+    },
+}
+
+/// A compiled list of [`PostProcessor`]s
+#[derive(Debug, Clone, Default)]
+pub struct PostProcessing {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    StripControlTokens,
+    TrimWhitespace,
+    CollapseRepeatedNewlines,
+    Replace(Regex, String),
+}
+
+impl PostProcessing {
+    /// Compile `processors`, failing on an invalid `Custom` pattern
+    pub fn new(processors: &[PostProcessor]) -> Result<Self, ConfigError> {
+        let steps = processors
+            .iter()
+            .map(|processor| match processor {
+                PostProcessor::StripControlTokens => Ok(Step::StripControlTokens),
+                PostProcessor::TrimWhitespace => Ok(Step::TrimWhitespace),
+                PostProcessor::CollapseRepeatedNewlines => Ok(Step::CollapseRepeatedNewlines),
+                PostProcessor::Custom {
+                    pattern,
+                    replacement,
+                } => Regex::new(pattern)
+                    .map(|regex| Step::Replace(regex, replacement.clone()))
+                    .map_err(|e| {
+                        ConfigError::Invalid(format!(
+                            "post_processors: invalid pattern '{}': {}",
+                            pattern, e
+                        ))
+                    }),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { steps })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// `text` after every step, leaving tool calls untouched. `control_tokens` are the
+    /// tokens [`PostProcessor::StripControlTokens`] removes.
+    pub fn apply(&self, text: &str, control_tokens: &[&str]) -> String {
+        if self.steps.is_empty() {
+            return text.to_string();
+        }
+
+        // Free text and tool calls in turn; only free text is processed
+        let mut pieces = Vec::new();
+        let mut offset = 0;
+        for span in tool_call_spans(text) {
+            pieces.push((text[offset..span.start].to_string(), false));
+            pieces.push((text[span.clone()].to_string(), true));
+            offset = span.end;
+        }
+        pieces.push((text[offset..].to_string(), false));
+
+        let last = pieces.len() - 1;
+        for step in &self.steps {
+            for (index, (piece, is_tool_call)) in pieces.iter_mut().enumerate() {
+                if !*is_tool_call {
+                    *piece = step.apply(piece, control_tokens, index == 0, index == last);
+                }
+            }
+        }
+        pieces.into_iter().map(|(piece, _)| piece).collect()
+    }
+
+    /// Process a streamed chunk. Only control tokens are removed, and only when
+    /// [`PostProcessor::StripControlTokens`] is configured.
+    pub fn apply_to_chunk(&self, text: &str, control_tokens: &[&str]) -> String {
+        if self
+            .steps
+            .iter()
+            .any(|step| matches!(step, Step::StripControlTokens))
+        {
+            strip_tokens(text, control_tokens)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+impl Step {
+    /// Process a piece of free text; `at_start` and `at_end` say whether it begins or
+    /// ends the whole text
+    fn apply(&self, text: &str, control_tokens: &[&str], at_start: bool, at_end: bool) -> String {
+        match self {
+            Step::StripControlTokens => strip_tokens(text, control_tokens),
+            Step::TrimWhitespace => {
+                let text = if at_start { text.trim_start() } else { text };
+                let text = if at_end { text.trim_end() } else { text };
+                text.to_string()
+            }
+            Step::CollapseRepeatedNewlines => {
+                static BLANK_LINES: OnceLock<Regex> = OnceLock::new();
+                BLANK_LINES
+                    .get_or_init(|| Regex::new(r"\n(?:[ \t]*\r?\n){2,}").unwrap())
+                    .replace_all(text, "\n\n")
+                    .into_owned()
+            }
+            Step::Replace(regex, replacement) => {
+                regex.replace_all(text, replacement.as_str()).into_owned()
+            }
+        }
+    }
+}
+
+/// Remove every occurrence of `tokens`, including ones formed by removing others
+fn strip_tokens(text: &str, tokens: &[&str]) -> String {
+    let mut stripped = text.to_string();
+    while let Some(token) = tokens
+        .iter()
+        .find(|token| !token.is_empty() && stripped.contains(*token))
+    {
+        stripped = stripped.replace(token, "");
+    }
+    stripped
+}
+
+/// Byte ranges of the tool calls in `text`, in order and not overlapping: tagged
+/// calls such as `<tool_call>...</tool_call>`, pythonic calls after
+/// `<|python_tag|>`, and JSON objects in one of the tool call formats the chat
+/// template parsers recognize
+fn tool_call_spans(text: &str) -> Vec<Range<usize>> {
+    static TAGGED: OnceLock<Regex> = OnceLock::new();
+    let tagged = TAGGED.get_or_init(|| {
+        Regex::new(
+            r"(?s)<tool_call[^>]*>.*?</tool_call>|<function_call[^>]*>.*?</function_call>|<\|python_tag\|>[^\n]*",
+        )
+        .unwrap()
+    });
+
+    let mut spans: Vec<Range<usize>> = tagged.find_iter(text).map(|m| m.range()).collect();
+    let mut start = 0;
+    while let Some(found) = text[start..].find('{') {
+        let open = start + found;
+        if let Some(span) = spans.iter().find(|span| span.contains(&open)) {
+            start = span.end;
+            continue;
+        }
+        let end = balanced_json_end(&text[open..]).map(|len| open + len);
+        match end {
+            Some(end)
+                if is_tool_call_json(&text[open..end])
+                    && !spans.iter().any(|span| (open..end).contains(&span.start)) =>
+            {
+                spans.push(open..end);
+                start = end;
+            }
+            _ => start = open + 1,
+        }
+    }
+
+    spans.sort_by_key(|span| span.start);
+    spans
+}
+
+/// Length of the JSON object starting `text`, if its braces balance
+fn balanced_json_end(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, ch) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match ch {
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether `json` is an object naming a tool and its arguments
+fn is_tool_call_json(json: &str) -> bool {
+    let Ok(Value::Object(object)) = serde_json::from_str::<Value>(json) else {
+        return false;
+    };
+    let names = |key: &str| object.get(key).is_some_and(Value::is_string);
+    (names("function_name") && object.contains_key("arguments"))
+        || (names("tool") && object.contains_key("parameters"))
+        || (names("name") && (object.contains_key("args") || object.contains_key("arguments")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKENS: &[&str] = &["<|im_start|>", "<|im_end|>", "<|endoftext|>"];
+
+    fn run(processors: &[PostProcessor], text: &str) -> String {
+        PostProcessing::new(processors).unwrap().apply(text, TOKENS)
+    }
+
+    #[test]
+    fn test_strip_control_tokens() {
+        assert_eq!(
+            run(
+                &[PostProcessor::StripControlTokens],
+                "Paris.<|im_end|>\n<|endoftext|>"
+            ),
+            "Paris.\n"
+        );
+        // Removing one token can form another
+        assert_eq!(
+            run(
+                &[PostProcessor::StripControlTokens],
+                "<|im_<|im_end|>end|>done"
+            ),
+            "done"
+        );
+    }
+
+    #[test]
+    fn test_trim_whitespace() {
+        assert_eq!(
+            run(
+                &[PostProcessor::TrimWhitespace],
+                "\n  Paris is the capital.  \n"
+            ),
+            "Paris is the capital."
+        );
+    }
+
+    #[test]
+    fn test_collapse_repeated_newlines() {
+        assert_eq!(
+            run(
+                &[PostProcessor::CollapseRepeatedNewlines],
+                "One\n\n\n\nTwo\n \n\t\nThree\n\nFour"
+            ),
+            "One\n\nTwo\n\nThree\n\nFour"
+        );
+    }
+
+    #[test]
+    fn test_custom_replacement() {
+        let custom = PostProcessor::Custom {
+            pattern: r"(?i)answer:\s*(\w+)".to_string(),
+            replacement: "$1".to_string(),
+        };
+        assert_eq!(run(&[custom], "Answer:  Paris"), "Paris");
+
+        let invalid = PostProcessor::Custom {
+            pattern: "(".to_string(),
+            replacement: String::new(),
+        };
+        assert!(matches!(
+            PostProcessing::new(&[invalid]),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_processors_apply_in_order() {
+        assert_eq!(
+            run(
+                &[
+                    PostProcessor::StripControlTokens,
+                    PostProcessor::TrimWhitespace
+                ],
+                "  Paris <|im_end|>\n"
+            ),
+            "Paris"
+        );
+        // Trimming first leaves the whitespace the token was hiding
+        assert_eq!(
+            run(
+                &[
+                    PostProcessor::TrimWhitespace,
+                    PostProcessor::StripControlTokens
+                ],
+                "  Paris <|im_end|>\n"
+            ),
+            "Paris "
+        );
+    }
+
+    #[test]
+    fn test_tool_calls_are_preserved() {
+        let processors = [
+            PostProcessor::StripControlTokens,
+            PostProcessor::TrimWhitespace,
+            PostProcessor::CollapseRepeatedNewlines,
+            PostProcessor::Custom {
+                pattern: "  +".to_string(),
+                replacement: " ".to_string(),
+            },
+        ];
+        let json =
+            r#"{"function_name": "write",  "arguments": {"text": "a\n\n\n\nb  <|im_end|>"}}"#;
+        let tagged = "<tool_call>\n{\"name\": \"ls\",  \"arguments\": {}}\n</tool_call>";
+        let text = format!(
+            "\n Let me   check.\n\n\n{}\n\n\n{}<|im_end|>  ",
+            json, tagged
+        );
+        assert_eq!(
+            run(&processors, &text),
+            format!("Let me check.\n\n{}\n\n{}", json, tagged)
+        );
+
+        // Whitespace after a trailing tool call is still trimmed, and JSON that is not a
+        // tool call is processed like any other text
+        assert_eq!(
+            run(&processors, r#"{"name": "ls", "args": {}}  "#),
+            r#"{"name": "ls", "args": {}}"#
+        );
+        assert_eq!(
+            run(&processors, r#"{"city":  "Paris"}"#),
+            r#"{"city": "Paris"}"#
+        );
+    }
+
+    #[test]
+    fn test_chunks_only_lose_control_tokens() {
+        let pipeline = PostProcessing::new(&[
+            PostProcessor::TrimWhitespace,
+            PostProcessor::StripControlTokens,
+        ])
+        .unwrap();
+        assert_eq!(
+            pipeline.apply_to_chunk(" Paris<|im_end|>", TOKENS),
+            " Paris"
+        );
+
+        let trim_only = PostProcessing::new(&[PostProcessor::TrimWhitespace]).unwrap();
+        assert_eq!(
+            trim_only.apply_to_chunk(" Paris<|im_end|>", TOKENS),
+            " Paris<|im_end|>"
+        );
+    }
+
+    #[test]
+    fn test_config_format() {
+        let processors: Vec<PostProcessor> = serde_json::from_str(
+            r#"["strip_control_tokens", "trim_whitespace", {"custom": {"pattern": "x", "replacement": "y"}}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            processors,
+            vec![
+                PostProcessor::StripControlTokens,
+                PostProcessor::TrimWhitespace,
+                PostProcessor::Custom {
+                    pattern: "x".to_string(),
+                    replacement: "y".to_string()
+                },
+            ]
+        );
+    }
+}
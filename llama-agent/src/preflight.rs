@@ -0,0 +1,195 @@
+//! Checks run before initialization downloads or loads anything.
+//!
+//! Beyond [`AgentConfig::validate`], these look at the machine the config will run
+//! on: MCP server commands must exist and the model cache must be writable. Every
+//! problem is reported at once, so a bad MCP entry is not found only after a long
+//! model download.
+
+use crate::types::{config_result, AgentConfig, AgentError, MCPError, ModelConfig, ModelSource};
+use llama_loader::{CacheManager, ModelError};
+use std::path::{Path, PathBuf};
+
+impl AgentConfig {
+    /// Validate the configuration and check its MCP commands and model cache, returning
+    /// every problem found in one error
+    pub fn preflight(&self) -> Result<(), AgentError> {
+        let mut problems = self.problems();
+
+        for server_config in &self.mcp_servers {
+            // An empty command is already reported by validation
+            if !server_config.command.is_empty() && find_command(&server_config.command).is_none() {
+                problems.push(
+                    MCPError::Connection(format!(
+                        "Command '{}' of MCP server '{}' was not found",
+                        server_config.command, server_config.name
+                    ))
+                    .into(),
+                );
+            }
+        }
+
+        let mut checked_dirs = Vec::new();
+        for model in std::iter::once(&self.model).chain(self.models.iter().map(|m| &m.model)) {
+            if let Some(dir) = download_cache_dir(model) {
+                if !checked_dirs.contains(&dir) {
+                    if let Err(e) = check_cache_dir(&dir) {
+                        problems.push(e.into());
+                    }
+                    checked_dirs.push(dir);
+                }
+            }
+        }
+
+        config_result(problems)
+    }
+}
+
+/// The path a command runs from: itself when it names a path, else the first match
+/// on `PATH`
+fn find_command(command: &str) -> Option<PathBuf> {
+    let path = Path::new(command);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+
+    let search_path = std::env::var_os("PATH")?;
+    std::env::split_paths(&search_path).find_map(|dir| {
+        let candidate = dir.join(command);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        let executable = candidate.with_extension(std::env::consts::EXE_EXTENSION);
+        (cfg!(windows) && executable.is_file()).then_some(executable)
+    })
+}
+
+/// Cache directory a model is downloaded to, or `None` for a local model
+fn download_cache_dir(model: &ModelConfig) -> Option<PathBuf> {
+    match model.source {
+        ModelSource::Local { .. } => None,
+        // A directory that cannot be resolved fails loading with its own error
+        ModelSource::HuggingFace { .. } | ModelSource::Url { .. } => {
+            CacheManager::resolve_cache_dir(model.cache_dir.as_deref()).ok()
+        }
+    }
+}
+
+/// Check that downloads can be written to `dir`, or to the nearest existing folder
+/// above it when it is created on the first download
+fn check_cache_dir(dir: &Path) -> Result<(), ModelError> {
+    let Some(existing) = dir.ancestors().find(|path| path.exists()) else {
+        return Ok(());
+    };
+    if !existing.is_dir() {
+        return Err(ModelError::Cache(format!(
+            "{} is not a directory",
+            existing.display()
+        )));
+    }
+    let metadata = std::fs::metadata(existing)?;
+    if metadata.permissions().readonly() {
+        return Err(ModelError::Cache(format!(
+            "{} is not writable",
+            existing.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MCPServerConfig, QueueConfig, SessionConfig};
+
+    fn server(name: &str, command: &str) -> MCPServerConfig {
+        MCPServerConfig {
+            name: name.to_string(),
+            command: command.to_string(),
+            args: Vec::new(),
+            timeout_secs: None,
+            strict_protocol_version: false,
+        }
+    }
+
+    #[test]
+    fn test_preflight_reports_every_problem() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = AgentConfig {
+            model: ModelConfig {
+                batch_size: 0,
+                cache_dir: Some(cache_dir.path().to_path_buf()),
+                ..ModelConfig::default()
+            },
+            queue_config: QueueConfig {
+                max_queue_size: 0,
+                ..QueueConfig::default()
+            },
+            session_config: SessionConfig {
+                max_sessions: 0,
+                ..SessionConfig::default()
+            },
+            mcp_servers: vec![server("missing", "no-such-mcp-server-command")],
+            ..AgentConfig::default()
+        };
+
+        let error = config.preflight().unwrap_err();
+        let AgentError::InvalidConfig(problems) = &error else {
+            panic!("expected every problem, got {}", error);
+        };
+        assert_eq!(problems.len(), 4, "{}", error);
+        let message = error.to_string();
+        for expected in [
+            "Batch size must be greater than 0",
+            "Queue size must be greater than 0",
+            "Max sessions must be greater than 0",
+            "no-such-mcp-server-command",
+        ] {
+            assert!(
+                message.contains(expected),
+                "{} not in {}",
+                expected,
+                message
+            );
+        }
+
+        // Validation alone leaves out the missing command
+        let AgentError::InvalidConfig(problems) = config.validate().unwrap_err() else {
+            panic!("expected every validation problem");
+        };
+        assert_eq!(problems.len(), 3);
+    }
+
+    #[test]
+    fn test_preflight_checks_the_cache_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+
+        let with_cache_dir = |cache_dir: PathBuf| AgentConfig {
+            model: ModelConfig {
+                cache_dir: Some(cache_dir),
+                ..ModelConfig::default()
+            },
+            ..AgentConfig::default()
+        };
+
+        // Created on the first download
+        assert!(with_cache_dir(dir.path().join("models/new"))
+            .preflight()
+            .is_ok());
+        let error = with_cache_dir(file.join("models")).preflight().unwrap_err();
+        assert!(
+            matches!(error, AgentError::Model(ModelError::Cache(_))),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_find_command() {
+        assert!(find_command("no-such-mcp-server-command").is_none());
+        assert!(find_command("./no/such/server").is_none());
+        #[cfg(unix)]
+        assert!(find_command("sh").is_some());
+    }
+}
@@ -1,17 +1,29 @@
+use crate::backend::{
+    BackendFactory, BatchBackend, LlamaCppBackend, LlamaCppBatchBackend, ModelBackend,
+};
 use crate::chat_template::ChatTemplateEngine;
+use crate::chunking::StreamChunker;
+use crate::context_pool::ContextLease;
 use crate::model::ModelManager;
-use crate::stopper::{EosStopper, MaxTokensStopper, RepetitionStopper, Stopper};
+use crate::stopper::{repetition::RepetitionConfig, RepetitionStopper, Stopper};
 use crate::types::{
-    FinishReason, GenerationRequest, GenerationResponse, QueueConfig, QueueError, Session,
-    StreamChunk,
+    ErrorContext, FinishReason, GeneratedToken, GenerationCandidate, GenerationRequest,
+    GenerationResponse, ModelConfig, ModelError, QueueConfig, QueueError, RenderedPrompt, Session,
+    SessionId, StreamChunk, StreamEvent,
 };
+use crate::validation::generation_request::ParameterConfig;
+use futures::{FutureExt, Stream};
 use llama_cpp_2::{
     llama_batch::LlamaBatch,
-    model::{AddBos, LlamaModel, Special},
-    sampling::LlamaSampler,
+    model::{AddBos, LlamaModel},
+    EmbeddingsError,
 };
+use std::collections::{HashSet, VecDeque};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio::task::JoinHandle;
@@ -19,7 +31,7 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use ulid::Ulid;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct QueueMetrics {
     pub total_requests: AtomicU64,
     pub completed_requests: AtomicU64,
@@ -31,10 +43,34 @@ pub struct QueueMetrics {
     pub total_tokens_generated: AtomicU64,
     pub peak_queue_size: AtomicUsize,
     pub last_throughput_tokens_per_second: AtomicU64,
+    pub rate_limited_requests: AtomicU64,
+    pub timed_generations: AtomicU64,
+    pub total_prompt_time_us: AtomicU64,
+    pub total_decode_time_us: AtomicU64,
+    /// Time each worker spent processing requests, by worker id
+    pub worker_busy_time: std::sync::Mutex<Vec<Duration>>,
+    /// Requests whose worker panicked; each panicked worker is replaced
+    pub worker_panics: AtomicU64,
+    pub last_worker_panic: std::sync::Mutex<Option<String>>,
+    /// Batches of requests generated together, and the requests they held
+    pub generated_batches: AtomicU64,
+    pub batched_requests: AtomicU64,
+    pub started_at: Instant,
+}
+
+impl Default for QueueMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl QueueMetrics {
     pub fn new() -> Self {
+        Self::for_workers(0)
+    }
+
+    /// Metrics reporting the utilization of `worker_count` workers
+    pub fn for_workers(worker_count: usize) -> Self {
         Self {
             total_requests: AtomicU64::new(0),
             completed_requests: AtomicU64::new(0),
@@ -46,6 +82,16 @@ impl QueueMetrics {
             total_tokens_generated: AtomicU64::new(0),
             peak_queue_size: AtomicUsize::new(0),
             last_throughput_tokens_per_second: AtomicU64::new(0),
+            rate_limited_requests: AtomicU64::new(0),
+            timed_generations: AtomicU64::new(0),
+            total_prompt_time_us: AtomicU64::new(0),
+            total_decode_time_us: AtomicU64::new(0),
+            worker_busy_time: std::sync::Mutex::new(vec![Duration::ZERO; worker_count]),
+            worker_panics: AtomicU64::new(0),
+            last_worker_panic: std::sync::Mutex::new(None),
+            generated_batches: AtomicU64::new(0),
+            batched_requests: AtomicU64::new(0),
+            started_at: Instant::now(),
         }
     }
 
@@ -101,6 +147,49 @@ impl QueueMetrics {
         self.current_queue_size.fetch_sub(1, Ordering::Relaxed);
     }
 
+    /// Add the prompt and decode times of a finished generation
+    pub fn record_generation_timing(&self, prompt_time: Duration, decode_time: Duration) {
+        self.timed_generations.fetch_add(1, Ordering::Relaxed);
+        self.total_prompt_time_us
+            .fetch_add(prompt_time.as_micros() as u64, Ordering::Relaxed);
+        self.total_decode_time_us
+            .fetch_add(decode_time.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Add time a worker spent processing a request
+    pub fn record_worker_busy(&self, worker_id: usize, busy: Duration) {
+        let mut busy_time = self
+            .worker_busy_time
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if busy_time.len() <= worker_id {
+            busy_time.resize(worker_id + 1, Duration::ZERO);
+        }
+        busy_time[worker_id] += busy;
+    }
+
+    /// Count a worker panic, keeping its message. The requests it failed are counted
+    /// separately.
+    pub fn record_worker_panic(&self, message: &str) {
+        self.worker_panics.fetch_add(1, Ordering::Relaxed);
+        *self
+            .last_worker_panic
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(message.to_string());
+    }
+
+    /// Count a batch of `requests` requests generated together
+    pub fn record_batch(&self, requests: usize) {
+        self.generated_batches.fetch_add(1, Ordering::Relaxed);
+        self.batched_requests
+            .fetch_add(requests as u64, Ordering::Relaxed);
+    }
+
+    /// Count a request rejected by the rate limiter before it was queued
+    pub fn record_request_rate_limited(&self) {
+        self.rate_limited_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn get_stats(&self) -> QueueStats {
         QueueStats {
             total_requests: self.total_requests.load(Ordering::Relaxed),
@@ -123,6 +212,51 @@ impl QueueMetrics {
             current_throughput_tps: self
                 .last_throughput_tokens_per_second
                 .load(Ordering::Relaxed),
+            rate_limited_requests: self.rate_limited_requests.load(Ordering::Relaxed),
+            average_prompt_time: self.average_time(&self.total_prompt_time_us),
+            average_decode_time: self.average_time(&self.total_decode_time_us),
+            worker_utilization: self.worker_utilization(),
+            worker_panics: self.worker_panics.load(Ordering::Relaxed),
+            last_worker_panic: self
+                .last_worker_panic
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone(),
+            average_batch_size: {
+                let batches = self.generated_batches.load(Ordering::Relaxed);
+                if batches > 0 {
+                    self.batched_requests.load(Ordering::Relaxed) as f64 / batches as f64
+                } else {
+                    0.0
+                }
+            },
+        }
+    }
+
+    /// Share of the time since the metrics were created that each worker was busy
+    fn worker_utilization(&self) -> Vec<f64> {
+        let elapsed = self.started_at.elapsed().as_secs_f64();
+        self.worker_busy_time
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|busy| {
+                if elapsed > 0.0 {
+                    (busy.as_secs_f64() / elapsed).min(1.0)
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    /// Average of a microsecond total over the timed generations
+    fn average_time(&self, total_us: &AtomicU64) -> Duration {
+        let count = self.timed_generations.load(Ordering::Relaxed);
+        if count > 0 {
+            Duration::from_micros(total_us.load(Ordering::Relaxed) / count)
+        } else {
+            Duration::ZERO
         }
     }
 }
@@ -139,36 +273,501 @@ pub struct QueueStats {
     pub total_tokens_generated: u64,
     pub peak_queue_size: usize,
     pub current_throughput_tps: u64,
+    /// Requests rejected by the rate limiter; these never entered the queue
+    pub rate_limited_requests: u64,
+    /// Average time generations spent decoding their prompt
+    pub average_prompt_time: Duration,
+    /// Average time generations spent generating tokens after the prompt
+    pub average_decode_time: Duration,
+    /// Share of the time since the queue started that each worker spent processing
+    /// requests, from 0.0 to 1.0, by worker id. Workers that stay idle while others
+    /// are busy add no throughput.
+    pub worker_utilization: Vec<f64>,
+    /// Worker panics; one panic fails every request its batch held
+    pub worker_panics: u64,
+    /// Message of the most recent worker panic
+    pub last_worker_panic: Option<String>,
+    /// Average number of requests generated together by request batching, 0.0 until
+    /// a batch has run
+    pub average_batch_size: f64,
+}
+
+impl QueueStats {
+    /// Rough time until a new request would start: the average processing time of
+    /// every queued request, spread over `worker_count` workers.
+    ///
+    /// `None` until a request has completed, since there is nothing to average.
+    pub fn estimated_wait(&self, worker_count: usize) -> Option<Duration> {
+        if self.average_processing_time_ms == 0 {
+            return None;
+        }
+        let queued = self.current_queue_size as u64;
+        let workers = worker_count.max(1) as u64;
+        Some(Duration::from_millis(
+            self.average_processing_time_ms * queued / workers,
+        ))
+    }
+}
+
+/// Identifies a request to the queue, and reports back which worker ran it.
+///
+/// A ticket may be used for several submissions, such as the passes of a tool
+/// loop, which are then logged under the same id.
+#[derive(Debug, Clone)]
+pub struct RequestTicket {
+    id: String,
+    /// Worker that last picked the request up, `NO_WORKER` until one has
+    worker: Arc<AtomicUsize>,
+}
+
+const NO_WORKER: usize = usize::MAX;
+
+impl RequestTicket {
+    pub fn new() -> Self {
+        Self {
+            id: Ulid::new().to_string(),
+            worker: Arc::new(AtomicUsize::new(NO_WORKER)),
+        }
+    }
+
+    /// Id the queue logs the request under
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Worker that last picked the request up
+    pub fn worker_id(&self) -> Option<usize> {
+        Some(self.worker.load(Ordering::Relaxed)).filter(|&id| id != NO_WORKER)
+    }
+
+    /// Context for errors of the request, made for `session_id`
+    pub fn error_context(&self, session_id: SessionId) -> ErrorContext {
+        ErrorContext {
+            request_id: Some(self.id.clone()),
+            session_id: Some(session_id),
+            worker_id: self.worker_id(),
+        }
+    }
+
+    fn assign(&self, worker_id: usize) {
+        self.worker.store(worker_id, Ordering::Relaxed);
+    }
+}
+
+impl Default for RequestTicket {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug)]
 pub struct QueuedRequest {
-    pub id: String,
+    pub ticket: RequestTicket,
     pub request: GenerationRequest,
-    pub session: Session,
+    /// Snapshot of the session at submission, shared rather than copied per request
+    pub session: Arc<Session>,
     pub response_sender: oneshot::Sender<Result<GenerationResponse, QueueError>>,
     pub stream_sender: Option<mpsc::Sender<Result<StreamChunk, QueueError>>>,
     pub submitted_at: Instant,
     pub cancellation_token: CancellationToken,
 }
 
+impl QueuedRequest {
+    /// Whether the request can be generated in a batch with others: it is not
+    /// streamed and asks for a single completion
+    fn is_batchable(&self) -> bool {
+        self.stream_sender.is_none() && self.request.n.unwrap_or(1) <= 1
+    }
+}
+
+/// Chunks of a streaming request.
+///
+/// The final chunk carries the [`GenerationResponse`] summary. Dropping the stream
+/// before that chunk (or an error) arrives cancels the request, so the worker stops
+/// generating instead of filling a channel nobody reads.
+#[derive(Debug)]
+pub struct RequestStream {
+    receiver: mpsc::Receiver<Result<StreamChunk, QueueError>>,
+    cancellation_token: CancellationToken,
+    finished: bool,
+}
+
+impl RequestStream {
+    pub(crate) fn new(
+        receiver: mpsc::Receiver<Result<StreamChunk, QueueError>>,
+        cancellation_token: CancellationToken,
+    ) -> Self {
+        Self {
+            receiver,
+            cancellation_token,
+            finished: false,
+        }
+    }
+
+    /// Receive the next chunk, or `None` once the request has ended
+    pub async fn recv(&mut self) -> Option<Result<StreamChunk, QueueError>> {
+        let item = self.receiver.recv().await;
+        self.observe(&item);
+        item
+    }
+
+    /// Token that cancels the underlying request
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    fn observe(&mut self, item: &Option<Result<StreamChunk, QueueError>>) {
+        match item {
+            Some(Ok(chunk)) if !chunk.is_complete => {}
+            _ => self.finished = true,
+        }
+    }
+}
+
+impl Stream for RequestStream {
+    type Item = Result<StreamChunk, QueueError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.receiver.poll_recv(cx);
+        if let Poll::Ready(item) = &poll {
+            self.observe(item);
+        }
+        poll
+    }
+}
+
+impl Drop for RequestStream {
+    fn drop(&mut self) {
+        if !self.finished {
+            debug!("Stream dropped before completion, cancelling request");
+            self.cancellation_token.cancel();
+        }
+    }
+}
+
+/// Prompt size and timings of a streaming request, reported on its final chunk
+#[derive(Debug)]
+struct StreamStats {
+    prompt_tokens: u32,
+    prompt_time: Duration,
+    first_token_time: Option<Duration>,
+    tokens: TokenLog,
+}
+
+/// The generated tokens of a request with `debug_tokens` set; records nothing for
+/// other requests. Only tokens counted towards `max_tokens` are recorded, so the log
+/// holds at most `max_tokens` entries.
+#[derive(Debug, Default)]
+struct TokenLog {
+    enabled: bool,
+    tokens: Vec<GeneratedToken>,
+    /// Ids recorded since the last stream chunk
+    unsent_ids: Vec<u32>,
+}
+
+impl TokenLog {
+    fn for_request(request: &GenerationRequest) -> Self {
+        Self {
+            enabled: request.debug_tokens,
+            ..Self::default()
+        }
+    }
+
+    fn record(&mut self, id: u32, piece: &str) {
+        if self.enabled {
+            self.tokens.push(GeneratedToken {
+                id,
+                piece: piece.to_string(),
+                logprob: None,
+            });
+            self.unsent_ids.push(id);
+        }
+    }
+
+    /// Ids of the tokens recorded since the previous call, for the next stream chunk
+    fn take_unsent_ids(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.unsent_ids)
+    }
+}
+
+/// A generation request as a worker runs it on a [`ModelBackend`]
+struct GenerationJob<'a> {
+    worker_id: usize,
+    request_id: String,
+    request: &'a GenerationRequest,
+    session: &'a Session,
+    model_config: &'a ModelConfig,
+    batch_size: usize,
+    cancellation_token: &'a CancellationToken,
+    chat_template: &'a ChatTemplateEngine,
+    max_conversion_failures: u32,
+    metrics: &'a QueueMetrics,
+}
+
+impl GenerationJob<'_> {
+    /// Render the session, then tokenize and decode the prompt, returning its tokens
+    fn decode_prompt<B: ModelBackend + ?Sized>(
+        &self,
+        backend: &mut B,
+    ) -> Result<Vec<u32>, QueueError> {
+        let tokens = self.tokenize_prompt(|prompt| backend.tokenize(prompt))?;
+        backend.decode(&tokens, 0)?;
+        Ok(tokens)
+    }
+
+    /// Render the session and tokenize the prompt, which must fit in one batch
+    fn tokenize_prompt(
+        &self,
+        tokenize: impl FnOnce(&str) -> Result<Vec<u32>, QueueError>,
+    ) -> Result<Vec<u32>, QueueError> {
+        // Format the session messages into a prompt using ChatTemplateEngine
+        let prompt = self
+            .chat_template
+            .render_session_for_config(self.session, Some(self.model_config))
+            .map_err(|e| QueueError::WorkerError(format!("Template rendering failed: {}", e)))?;
+        debug!("Formatted prompt: {}", crate::redaction::redact(&prompt));
+
+        let tokens = tokenize(&prompt)?;
+        debug!("Tokenized prompt to {} tokens", tokens.len());
+
+        // Validate that prompt tokens don't exceed batch size
+        if tokens.len() > self.batch_size {
+            error!(
+                "Prompt token count ({}) exceeds configured batch size ({}). Consider reducing prompt length or increasing batch_size in config.",
+                tokens.len(),
+                self.batch_size
+            );
+            return Err(QueueError::WorkerError(format!(
+                "Prompt too long: {} tokens exceeds batch size limit of {}",
+                tokens.len(),
+                self.batch_size
+            )));
+        }
+
+        Ok(tokens)
+    }
+
+    /// The reason generation finished, as a tool call when it ended naturally with
+    /// tool calls in `generated_text`
+    fn final_finish_reason(
+        &self,
+        generated_text: &str,
+        finish_reason: FinishReason,
+    ) -> FinishReason {
+        let (worker_id, request_id) = (self.worker_id, &self.request_id);
+        match &finish_reason {
+            FinishReason::Stopped(reason)
+                if reason == "End of sequence token detected"
+                    || reason == "Stop token detected"
+                    || reason == "Maximum tokens reached" =>
+            {
+                match self.chat_template.extract_tool_calls(generated_text) {
+                    Ok(tool_calls) if !tool_calls.is_empty() => {
+                        debug!(
+                            "Worker {} detected {} tool calls in generated text for request {}",
+                            worker_id,
+                            tool_calls.len(),
+                            request_id
+                        );
+                        FinishReason::Stopped("Tool call detected".to_string())
+                    }
+                    Ok(_) => {
+                        debug!(
+                            "Worker {} no tool calls detected in generated text for request {}",
+                            worker_id, request_id
+                        );
+                        finish_reason
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Worker {} failed to extract tool calls for request {}: {}",
+                            worker_id, request_id, e
+                        );
+                        finish_reason
+                    }
+                }
+            }
+            _ => finish_reason,
+        }
+    }
+}
+
+/// Send a chunk from a worker, waiting while the consumer's buffer is full so a
+/// slow reader slows generation down instead of losing chunks.
+///
+/// Returns false once the stream has been dropped.
+fn send_chunk(
+    sender: &mpsc::Sender<Result<StreamChunk, QueueError>>,
+    mut item: Result<StreamChunk, QueueError>,
+) -> bool {
+    loop {
+        match sender.try_send(item) {
+            Ok(()) => return true,
+            Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            Err(mpsc::error::TrySendError::Full(returned)) => {
+                // Workers run generation synchronously, so this cannot await
+                item = returned;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+/// Run synchronous model work on the current worker task.
+///
+/// On a multi-threaded runtime, whatever its number of worker threads, the thread
+/// running the work is handed over to the blocking pool and a replacement takes
+/// over its share of the runtime, so timers, I/O and other workers keep running
+/// during a long generation. Workers are not `spawn_blocking` tasks because the
+/// work borrows the request, its context lease and the model under `with_model`'s
+/// read lock, none of which can be moved onto another thread. A current-thread
+/// runtime has no thread to hand over, so there the work runs inline and stalls
+/// the runtime until it returns; [`RequestQueue`] warns about this on startup.
+fn run_blocking<R>(work: impl FnOnce() -> R) -> R {
+    match tokio::runtime::Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Ok(tokio::runtime::RuntimeFlavor::MultiThread) => tokio::task::block_in_place(work),
+        _ => work(),
+    }
+}
+
+/// Record how a streaming request ended: cancelled when its stream was dropped
+/// early, completed otherwise
+fn record_streaming_outcome(
+    metrics: &QueueMetrics,
+    cancellation_token: &CancellationToken,
+    processing_time: Duration,
+) {
+    if cancellation_token.is_cancelled() {
+        metrics.record_request_cancelled();
+    } else {
+        // Note: For streaming, tokens are tracked within process_streaming_request_sync
+        metrics.record_request_completed(processing_time, 0);
+    }
+}
+
+/// Fail a request a worker took from the queue but is not going to run
+fn abandon_request(queued_request: Option<QueuedRequest>, metrics: &QueueMetrics) {
+    if let Some(queued_request) = queued_request {
+        let error =
+            QueueError::WorkerError("Worker stopped before running the request".to_string());
+        match queued_request.stream_sender {
+            Some(stream_sender) => {
+                let _ = stream_sender.try_send(Err(error));
+            }
+            None => {
+                let _ = queued_request.response_sender.send(Err(error));
+            }
+        }
+        metrics.record_request_failed();
+    }
+}
+
+/// Why a worker loop returned
+enum WorkerExit {
+    /// The queue was dropped; nothing is left to process
+    Closed,
+    /// A request panicked; the worker should be replaced
+    Panicked,
+}
+
+/// Most worker replacements within [`WORKER_RESPAWN_WINDOW`], so a panic that every
+/// request hits does not respawn workers in a hot loop
+const MAX_WORKER_RESPAWNS: usize = 5;
+const WORKER_RESPAWN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Replacement times of panicked workers, shared by all workers of a queue
+#[derive(Default)]
+struct RespawnLimiter {
+    respawns: std::sync::Mutex<VecDeque<Instant>>,
+}
+
+impl RespawnLimiter {
+    /// Whether another worker may be replaced at `now`, counting it if so
+    fn allow(&self, now: Instant) -> bool {
+        let mut respawns = self
+            .respawns
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        while respawns
+            .front()
+            .is_some_and(|&at| now.duration_since(at) >= WORKER_RESPAWN_WINDOW)
+        {
+            respawns.pop_front();
+        }
+        if respawns.len() >= MAX_WORKER_RESPAWNS {
+            return false;
+        }
+        respawns.push_back(now);
+        true
+    }
+}
+
+/// Text of a panic payload, as passed to `panic!`
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
 pub struct RequestQueue {
     sender: mpsc::Sender<QueuedRequest>,
     worker_handles: Vec<JoinHandle<()>>,
     config: QueueConfig,
     metrics: Arc<QueueMetrics>,
-    #[allow(dead_code)]
-    chat_template: Arc<ChatTemplateEngine>,
+    model_manager: Arc<ModelManager>,
+    backend_factory: Option<Arc<dyn BackendFactory>>,
+    /// Parent of every request's cancellation token; cancelled by [`Self::cancel_all`]
+    cancel_token: CancellationToken,
 }
 
 impl RequestQueue {
     pub fn new(model_manager: Arc<ModelManager>, config: QueueConfig) -> Self {
+        Self::start(model_manager, config, None)
+    }
+
+    /// Create a queue whose workers generate with backends from `backend_factory`
+    /// instead of the model, which is never loaded.
+    ///
+    /// `model_manager` still provides the model config and batch size. Embedding
+    /// requests need the real model and fail, as do requests for more than one
+    /// completion when the factory creates no batch backends.
+    pub fn with_backend_factory(
+        model_manager: Arc<ModelManager>,
+        config: QueueConfig,
+        backend_factory: Arc<dyn BackendFactory>,
+    ) -> Self {
+        Self::start(model_manager, config, Some(backend_factory))
+    }
+
+    fn start(
+        model_manager: Arc<ModelManager>,
+        config: QueueConfig,
+        backend_factory: Option<Arc<dyn BackendFactory>>,
+    ) -> Self {
+        let pool_size = model_manager.context_pool().size();
+        if config.worker_threads > pool_size {
+            warn!(
+                "{} workers share {} context slots; workers beyond the pool wait for a slot",
+                config.worker_threads, pool_size
+            );
+        }
+
+        if let Ok(tokio::runtime::RuntimeFlavor::CurrentThread) =
+            tokio::runtime::Handle::try_current().map(|handle| handle.runtime_flavor())
+        {
+            warn!(
+                "Running on a current-thread runtime; other tasks stall while a request generates"
+            );
+        }
+
         let (sender, receiver) = mpsc::channel(config.max_queue_size);
         let receiver = Arc::new(Mutex::new(receiver));
-        let metrics = Arc::new(QueueMetrics::new());
-        let chat_template = Arc::new(ChatTemplateEngine::new());
+        let metrics = Arc::new(QueueMetrics::for_workers(config.worker_threads));
 
         let mut worker_handles = Vec::new();
+        let respawns = Arc::new(RespawnLimiter::default());
 
         // Spawn worker threads
         for worker_id in 0..config.worker_threads {
@@ -176,18 +775,32 @@ impl RequestQueue {
             let model_manager = model_manager.clone();
             let config = config.clone();
             let metrics = metrics.clone();
-            let chat_template = chat_template.clone();
+            let backend_factory = backend_factory.clone();
+
+            let respawns = respawns.clone();
 
             let handle = tokio::spawn(async move {
-                Self::worker_loop(
+                // Replace the worker each time a request panics, unless workers keep
+                // crashing
+                while let WorkerExit::Panicked = Self::worker_loop(
                     worker_id,
-                    receiver,
-                    model_manager,
-                    config,
-                    metrics,
-                    chat_template,
+                    receiver.clone(),
+                    model_manager.clone(),
+                    backend_factory.clone(),
+                    config.clone(),
+                    metrics.clone(),
                 )
-                .await;
+                .await
+                {
+                    if !respawns.allow(Instant::now()) {
+                        error!(
+                            "Workers panicked more than {} times in {:?}; not replacing worker {}",
+                            MAX_WORKER_RESPAWNS, WORKER_RESPAWN_WINDOW, worker_id
+                        );
+                        break;
+                    }
+                    warn!("Replacing worker {} after a panic", worker_id);
+                }
             });
 
             worker_handles.push(handle);
@@ -203,41 +816,57 @@ impl RequestQueue {
             worker_handles,
             config,
             metrics,
-            chat_template,
+            model_manager,
+            backend_factory,
+            cancel_token: CancellationToken::new(),
         }
     }
 
+    /// Queue a request and wait for its response.
+    ///
+    /// The worker renders `session` as it was when submitted; later changes made
+    /// through another handle are not seen.
     pub async fn submit_request(
         &self,
         request: GenerationRequest,
-        session: &Session,
+        session: Arc<Session>,
+    ) -> Result<GenerationResponse, QueueError> {
+        self.submit_request_as(&RequestTicket::new(), request, session)
+            .await
+    }
+
+    /// [`Self::submit_request`] under the id of `ticket`, which learns the worker
+    /// that runs the request
+    pub async fn submit_request_as(
+        &self,
+        ticket: &RequestTicket,
+        request: GenerationRequest,
+        session: Arc<Session>,
     ) -> Result<GenerationResponse, QueueError> {
+        sequence_count(&request, self.config.max_sequences_per_request)?;
         let (response_sender, response_receiver) = oneshot::channel();
 
         let queued_request = QueuedRequest {
-            id: Ulid::new().to_string(),
+            ticket: ticket.clone(),
             request,
-            session: session.clone(),
+            session,
             response_sender,
             stream_sender: None,
             submitted_at: Instant::now(),
-            cancellation_token: CancellationToken::new(),
+            cancellation_token: self.cancel_token.child_token(),
         };
 
-        debug!("Submitting request to queue: {}", queued_request.id);
-
-        // Record request submission
-        self.metrics.record_request_submitted();
+        debug!(
+            "Submitting request to queue: {}",
+            queued_request.ticket.id()
+        );
 
-        // Try to send to queue
-        if self.sender.try_send(queued_request).is_err() {
-            warn!("Queue is full, rejecting request");
-            self.metrics.record_request_failed(); // Adjust queue size back down
-            return Err(QueueError::Full);
-        }
+        let block_on_full = queued_request.request.block_on_full;
+        let deadline = tokio::time::Instant::now() + self.config.request_timeout;
+        self.enqueue(queued_request, block_on_full).await?;
 
-        // Wait for response with timeout
-        match tokio::time::timeout(self.config.request_timeout, response_receiver).await {
+        // Wait for response with timeout; time spent waiting for a slot counts towards it
+        match tokio::time::timeout_at(deadline, response_receiver).await {
             Ok(Ok(response)) => response,
             Ok(Err(_)) => {
                 error!("Response channel closed unexpectedly");
@@ -245,6 +874,13 @@ impl RequestQueue {
                     "Response channel closed".to_string(),
                 ))
             }
+            Err(_) if self.model_manager.is_loading() => {
+                warn!(
+                    "Request timed out after {:?} while the model is still loading",
+                    self.config.request_timeout
+                );
+                Err(QueueError::ModelLoading)
+            }
             Err(_) => {
                 warn!("Request timed out after {:?}", self.config.request_timeout);
                 Err(QueueError::Timeout)
@@ -252,169 +888,611 @@ impl RequestQueue {
         }
     }
 
+    /// Queue a streaming request; `session` is a snapshot as for [`Self::submit_request`]
     pub async fn submit_streaming_request(
         &self,
         request: GenerationRequest,
-        session: &Session,
-    ) -> Result<mpsc::Receiver<Result<StreamChunk, QueueError>>, QueueError> {
+        session: Arc<Session>,
+    ) -> Result<RequestStream, QueueError> {
+        self.submit_streaming_request_as(&RequestTicket::new(), request, session)
+            .await
+    }
+
+    /// [`Self::submit_streaming_request`] under the id of `ticket`
+    pub async fn submit_streaming_request_as(
+        &self,
+        ticket: &RequestTicket,
+        request: GenerationRequest,
+        session: Arc<Session>,
+    ) -> Result<RequestStream, QueueError> {
+        if sequence_count(&request, self.config.max_sequences_per_request)? > 1 {
+            return Err(QueueError::WorkerError(
+                "Streaming supports a single completion; use n = 1 or a non-streaming request"
+                    .to_string(),
+            ));
+        }
         let (response_sender, _) = oneshot::channel();
         let (stream_sender, stream_receiver) = mpsc::channel(100);
+        let cancellation_token = self.cancel_token.child_token();
 
         let queued_request = QueuedRequest {
-            id: Ulid::new().to_string(),
+            ticket: ticket.clone(),
             request,
-            session: session.clone(),
+            session,
             response_sender,
             stream_sender: Some(stream_sender),
             submitted_at: Instant::now(),
-            cancellation_token: CancellationToken::new(),
+            cancellation_token: cancellation_token.clone(),
         };
 
         debug!(
             "Submitting streaming request to queue: {}",
-            queued_request.id
+            queued_request.ticket.id()
         );
 
-        // Record request submission
-        self.metrics.record_request_submitted();
-
-        // Try to send to queue
-        if self.sender.try_send(queued_request).is_err() {
-            warn!("Queue is full, rejecting streaming request");
-            self.metrics.record_request_failed(); // Adjust queue size back down
-            return Err(QueueError::Full);
-        }
-
-        Ok(stream_receiver)
-    }
+        let block_on_full = queued_request.request.block_on_full;
+        self.enqueue(queued_request, block_on_full).await?;
 
-    pub fn get_queue_size(&self) -> usize {
-        // Use metrics for more accurate queue size
-        self.metrics.current_queue_size.load(Ordering::Relaxed)
+        Ok(RequestStream::new(stream_receiver, cancellation_token))
     }
 
-    pub fn get_stats(&self) -> QueueStats {
-        self.metrics.get_stats()
-    }
+    /// Hand a request to the workers.
+    ///
+    /// A full queue is rejected with [`QueueError::Full`] unless `block_on_full` is
+    /// set, in which case this waits for a slot for up to the request timeout.
+    async fn enqueue(
+        &self,
+        queued_request: QueuedRequest,
+        block_on_full: bool,
+    ) -> Result<(), QueueError> {
+        // Record request submission
+        self.metrics.record_request_submitted();
 
-    async fn worker_loop(
+        let queued_request = match self.sender.try_send(queued_request) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(returned)) if block_on_full => returned,
+            Err(_) => {
+                self.metrics.record_request_failed(); // Adjust queue size back down
+                let stats = self.metrics.get_stats();
+                let estimated_wait = stats.estimated_wait(self.config.worker_threads);
+                warn!(
+                    "Queue is full, rejecting request (estimated wait {:?})",
+                    estimated_wait
+                );
+                return Err(QueueError::Full {
+                    current_depth: stats.current_queue_size,
+                    max_depth: self.config.max_queue_size,
+                    estimated_wait,
+                });
+            }
+        };
+
+        debug!(
+            "Queue is full, waiting for a slot for {}",
+            queued_request.ticket.id()
+        );
+        match tokio::time::timeout(
+            self.config.request_timeout,
+            self.sender.send(queued_request),
+        )
+        .await
+        {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => {
+                self.metrics.record_request_failed();
+                Err(QueueError::WorkerError("Queue is shut down".to_string()))
+            }
+            Err(_) => {
+                warn!(
+                    "No queue slot freed up within {:?}",
+                    self.config.request_timeout
+                );
+                self.metrics.record_request_timeout();
+                Err(QueueError::Timeout)
+            }
+        }
+    }
+
+    /// Compute one embedding per text using the loaded generation model.
+    ///
+    /// Embedding requests are short, so they run directly against the model
+    /// instead of going through the worker channel; they still count towards
+    /// queue metrics and honour the request timeout. When `normalize` is set,
+    /// each vector is scaled to unit L2 norm.
+    pub async fn submit_embedding_request(
+        &self,
+        texts: &[String],
+        normalize: bool,
+    ) -> Result<Vec<Vec<f32>>, QueueError> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        debug!("Submitting embedding request for {} texts", texts.len());
+        self.metrics.record_request_submitted();
+        let start_time = Instant::now();
+
+        // Lazy loading happens here, outside the request timeout
+        if let Err(e) = self.model_manager.ensure_loaded().await {
+            self.metrics.record_request_failed();
+            return Err(model_unavailable(e));
+        }
+
+        // Texts not yet embedded are skipped once the request times out
+        let cancel = CancellationToken::new();
+        let _cancel_on_return = cancel.clone().drop_guard();
+        let model_manager = self.model_manager.clone();
+        let texts = texts.to_vec();
+        let embed = async move {
+            let lease = model_manager.acquire_context().await;
+            // Decoding blocks, so it runs off the executor and the timeout can fire
+            tokio::task::spawn_blocking(move || {
+                model_manager.with_model_blocking(|model| {
+                    Self::process_embedding_request_sync(
+                        &texts,
+                        normalize,
+                        model,
+                        &model_manager,
+                        &lease,
+                        &cancel,
+                    )
+                })
+            })
+            .await
+        };
+
+        let result = match tokio::time::timeout(self.config.request_timeout, embed).await {
+            Ok(Ok(Ok(result))) => result,
+            Ok(Ok(Err(model_error))) => Err(QueueError::WorkerError(format!(
+                "Model error: {}",
+                model_error
+            ))),
+            Ok(Err(join_error)) => Err(QueueError::WorkerError(format!(
+                "Embedding task failed: {}",
+                join_error
+            ))),
+            Err(_) => {
+                warn!(
+                    "Embedding request timed out after {:?}",
+                    self.config.request_timeout
+                );
+                self.metrics.record_request_timeout();
+                return Err(QueueError::Timeout);
+            }
+        };
+
+        match &result {
+            Ok(_) => self
+                .metrics
+                .record_request_completed(start_time.elapsed(), 0),
+            Err(_) => self.metrics.record_request_failed(),
+        }
+        result
+    }
+
+    /// Render and tokenize `session` the way a worker would, without queueing a
+    /// request. Loads the model in lazy mode, since tokenizing needs it.
+    pub async fn render_prompt(&self, session: &Session) -> Result<RenderedPrompt, QueueError> {
+        let prompt = self
+            .model_manager
+            .chat_template()
+            .render_session_for_config(session, Some(&self.model_manager.get_config()))
+            .map_err(|e| QueueError::WorkerError(format!("Template rendering failed: {}", e)))?;
+
+        let prompt_tokens = match &self.backend_factory {
+            Some(factory) => factory.tokenize(&prompt)?.len(),
+            None => {
+                self.model_manager
+                    .ensure_loaded()
+                    .await
+                    .map_err(model_unavailable)?;
+                self.model_manager
+                    .with_model(|model| model.str_to_token(&prompt, AddBos::Always))
+                    .await
+                    .map_err(model_unavailable)?
+                    .map_err(|e| QueueError::WorkerError(format!("Tokenization failed: {}", e)))?
+                    .len()
+            }
+        };
+
+        Ok(RenderedPrompt {
+            prompt,
+            prompt_tokens,
+            context_size: self.model_manager.context_size(),
+            batch_size: self.model_manager.get_batch_size(),
+        })
+    }
+
+    pub fn get_queue_size(&self) -> usize {
+        // Use metrics for more accurate queue size
+        self.metrics.current_queue_size.load(Ordering::Relaxed)
+    }
+
+    pub fn get_stats(&self) -> QueueStats {
+        self.metrics.get_stats()
+    }
+
+    /// Stop sequences a request asking for `requested` generates with: those, then the
+    /// model's, merged as workers merge them
+    pub fn stop_sequences(&self, requested: &[String]) -> Vec<String> {
+        let model_stops = self.model_manager.chat_template().stop_sequences_for_model(
+            &self.model_manager.get_config(),
+            self.config.use_template_stop_tokens,
+        );
+        merge_stop_sequences(requested, model_stops, &self.config)
+    }
+
+    /// Wait until no request is queued or running, returning false if `deadline`
+    /// passes first
+    pub async fn drain(&self, deadline: tokio::time::Instant) -> bool {
+        while self.get_queue_size() > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        true
+    }
+
+    /// Cancel every queued and running request, returning how many there were.
+    ///
+    /// Workers drop queued requests and stop running ones before their next token.
+    /// Requests submitted afterwards are cancelled too.
+    pub fn cancel_all(&self) -> usize {
+        let outstanding = self.get_queue_size();
+        self.cancel_token.cancel();
+        if outstanding > 0 {
+            warn!("Cancelled {} outstanding requests", outstanding);
+        }
+        outstanding
+    }
+
+    /// Count a request the rate limiter turned away before submission
+    pub fn record_rate_limited(&self) {
+        self.metrics.record_request_rate_limited();
+    }
+
+    async fn worker_loop(
         worker_id: usize,
         receiver: Arc<Mutex<mpsc::Receiver<QueuedRequest>>>,
         model_manager: Arc<ModelManager>,
+        backend_factory: Option<Arc<dyn BackendFactory>>,
         config: QueueConfig,
         metrics: Arc<QueueMetrics>,
-        chat_template: Arc<ChatTemplateEngine>,
-    ) {
+    ) -> WorkerExit {
         info!("Worker {} started", worker_id);
+        // A request taken while gathering a batch that could not join it
+        let mut held_back: Option<QueuedRequest> = None;
 
         loop {
-            let queued_request = {
-                let mut receiver = receiver.lock().await;
-                match receiver.recv().await {
-                    Some(request) => request,
-                    None => {
-                        info!("Worker {} shutting down - channel closed", worker_id);
-                        break;
+            let mut queued_request = match held_back.take() {
+                Some(request) => request,
+                None => {
+                    let request = {
+                        let mut receiver = receiver.lock().await;
+                        match receiver.recv().await {
+                            Some(request) => request,
+                            None => {
+                                info!("Worker {} shutting down - channel closed", worker_id);
+                                return WorkerExit::Closed;
+                            }
+                        }
+                    };
+                    match Self::start_request(worker_id, request, &config, &metrics) {
+                        Some(request) => request,
+                        None => continue,
                     }
                 }
             };
 
-            let queue_time = queued_request.submitted_at.elapsed();
-            debug!(
-                "Worker {} processing request {} (queue time: {:?})",
-                worker_id, queued_request.id, queue_time
-            );
+            if config.enable_request_batching && queued_request.is_batchable() {
+                let mut batch = vec![queued_request];
+                // A worker waiting for requests holds the receiver, and the queue is
+                // then empty
+                if let Ok(mut receiver) = receiver.try_lock() {
+                    while batch.len() < config.max_batched_requests {
+                        let Ok(next) = receiver.try_recv() else {
+                            break;
+                        };
+                        match Self::start_request(worker_id, next, &config, &metrics) {
+                            Some(next) if next.is_batchable() => batch.push(next),
+                            Some(next) => {
+                                held_back = Some(next);
+                                break;
+                            }
+                            None => {}
+                        }
+                    }
+                }
 
-            // Check if request has already timed out
-            if queue_time > config.request_timeout {
-                warn!(
-                    "Worker {} dropping expired request {} (queued for {:?})",
-                    worker_id, queued_request.id, queue_time
-                );
-                let _ = queued_request
-                    .response_sender
-                    .send(Err(QueueError::Timeout));
-                metrics.record_request_timeout();
-                continue;
+                if batch.len() > 1 {
+                    let completed = Self::process_batched_requests(
+                        worker_id,
+                        batch,
+                        model_manager.clone(),
+                        backend_factory.clone(),
+                        metrics.clone(),
+                        model_manager.chat_template(),
+                        &config,
+                    )
+                    .await;
+                    if !completed {
+                        abandon_request(held_back, &metrics);
+                        return WorkerExit::Panicked;
+                    }
+                    continue;
+                }
+                metrics.record_batch(1);
+                queued_request = batch.pop().expect("batch holds the first request");
             }
 
-            // Check if request was cancelled
-            if queued_request.cancellation_token.is_cancelled() {
-                warn!(
-                    "Worker {} dropping cancelled request {} (queued for {:?})",
-                    worker_id, queued_request.id, queue_time
-                );
-                let _ = queued_request
-                    .response_sender
-                    .send(Err(QueueError::WorkerError(
-                        "Request cancelled".to_string(),
-                    )));
-                metrics.record_request_cancelled();
-                continue;
-            }
+            // Keep the caller's channels, so a panic can still be reported to it
+            let (response_sender, response_receiver) = oneshot::channel();
+            let caller = std::mem::replace(&mut queued_request.response_sender, response_sender);
+            let stream_sender = queued_request.stream_sender.clone();
+            let request_id = queued_request.ticket.id().to_string();
 
             // Process the request
-            Self::process_request(
+            let processed = AssertUnwindSafe(Self::process_request(
                 worker_id,
                 queued_request,
                 model_manager.clone(),
+                backend_factory.clone(),
                 metrics.clone(),
-                chat_template.clone(),
-            )
+                model_manager.chat_template(),
+                &config,
+            ))
+            .catch_unwind()
             .await;
+
+            match processed {
+                Ok(()) => {
+                    if let Ok(result) = response_receiver.await {
+                        let _ = caller.send(result);
+                    }
+                }
+                Err(panic) => {
+                    let message = panic_message(panic.as_ref());
+                    error!(
+                        "Worker {} panicked processing request {}: {}",
+                        worker_id, request_id, message
+                    );
+                    metrics.record_worker_panic(&message);
+                    metrics.record_request_failed();
+                    let error = QueueError::WorkerError(format!("Worker panicked: {}", message));
+                    match stream_sender {
+                        Some(stream_sender) => {
+                            let _ = stream_sender.send(Err(error)).await;
+                        }
+                        None => {
+                            let _ = caller.send(Err(error));
+                        }
+                    }
+                    abandon_request(held_back, &metrics);
+                    return WorkerExit::Panicked;
+                }
+            }
         }
     }
 
-    async fn process_request(
+    /// Assign a request taken from the queue to the worker, or answer it and return
+    /// `None` when it expired or was cancelled while queued
+    fn start_request(
         worker_id: usize,
         queued_request: QueuedRequest,
+        config: &QueueConfig,
+        metrics: &QueueMetrics,
+    ) -> Option<QueuedRequest> {
+        queued_request.ticket.assign(worker_id);
+        let queue_time = queued_request.submitted_at.elapsed();
+        debug!(
+            "Worker {} processing request {} (queue time: {:?})",
+            worker_id,
+            queued_request.ticket.id(),
+            queue_time
+        );
+
+        // Check if request has already timed out
+        if queue_time > config.request_timeout {
+            warn!(
+                "Worker {} dropping expired request {} (queued for {:?})",
+                worker_id,
+                queued_request.ticket.id(),
+                queue_time
+            );
+            let _ = queued_request
+                .response_sender
+                .send(Err(QueueError::Timeout));
+            metrics.record_request_timeout();
+            return None;
+        }
+
+        // Check if request was cancelled
+        if queued_request.cancellation_token.is_cancelled() {
+            warn!(
+                "Worker {} dropping cancelled request {} (queued for {:?})",
+                worker_id,
+                queued_request.ticket.id(),
+                queue_time
+            );
+            let _ = queued_request
+                .response_sender
+                .send(Err(QueueError::WorkerError(
+                    "Request cancelled".to_string(),
+                )));
+            metrics.record_request_cancelled();
+            return None;
+        }
+
+        Some(queued_request)
+    }
+
+    /// Generate requests taken together side by side. Returns false when they
+    /// panicked, after failing each of them.
+    async fn process_batched_requests(
+        worker_id: usize,
+        mut batch: Vec<QueuedRequest>,
+        model_manager: Arc<ModelManager>,
+        backend_factory: Option<Arc<dyn BackendFactory>>,
+        metrics: Arc<QueueMetrics>,
+        chat_template: Arc<ChatTemplateEngine>,
+        config: &QueueConfig,
+    ) -> bool {
+        // Keep the callers' channels, so a panic can still be reported to them
+        let mut callers = Vec::with_capacity(batch.len());
+        let mut responses = Vec::with_capacity(batch.len());
+        for queued_request in &mut batch {
+            let (response_sender, response_receiver) = oneshot::channel();
+            callers.push(std::mem::replace(
+                &mut queued_request.response_sender,
+                response_sender,
+            ));
+            responses.push(response_receiver);
+        }
+
+        let processed = AssertUnwindSafe(Self::process_request_group(
+            worker_id,
+            batch,
+            model_manager,
+            backend_factory,
+            metrics.clone(),
+            chat_template,
+            config,
+        ))
+        .catch_unwind()
+        .await;
+
+        match processed {
+            Ok(()) => {
+                for (caller, response) in callers.into_iter().zip(responses) {
+                    if let Ok(result) = response.await {
+                        let _ = caller.send(result);
+                    }
+                }
+                true
+            }
+            Err(panic) => {
+                let message = panic_message(panic.as_ref());
+                error!(
+                    "Worker {} panicked processing {} batched requests: {}",
+                    worker_id,
+                    callers.len(),
+                    message
+                );
+                metrics.record_worker_panic(&message);
+                for caller in callers {
+                    metrics.record_request_failed();
+                    let _ = caller.send(Err(QueueError::WorkerError(format!(
+                        "Worker panicked: {}",
+                        message
+                    ))));
+                }
+                false
+            }
+        }
+    }
+
+    async fn process_request(
+        worker_id: usize,
+        mut queued_request: QueuedRequest,
         model_manager: Arc<ModelManager>,
+        backend_factory: Option<Arc<dyn BackendFactory>>,
         metrics: Arc<QueueMetrics>,
         chat_template: Arc<ChatTemplateEngine>,
+        config: &QueueConfig,
     ) {
         let start_time = Instant::now();
 
-        // Check if model is loaded
-        if !model_manager.is_loaded().await {
-            let error = QueueError::WorkerError("Model not loaded".to_string());
-            if let Some(stream_sender) = queued_request.stream_sender {
-                let _ = stream_sender.send(Err(error)).await;
-            } else {
-                let _ = queued_request.response_sender.send(Err(error));
+        // Check if model is loaded, loading it now in lazy mode; with a backend
+        // factory the model is never loaded
+        if backend_factory.is_none() {
+            if let Err(e) = model_manager.ensure_loaded().await {
+                let error = model_unavailable(e);
+                if let Some(stream_sender) = queued_request.stream_sender {
+                    let _ = stream_sender.send(Err(error)).await;
+                } else {
+                    let _ = queued_request.response_sender.send(Err(error));
+                }
+                metrics.record_request_failed();
+                return;
             }
-            metrics.record_request_failed();
-            return;
         }
 
-        let request_id = queued_request.id.clone();
+        // Wait for a context slot; a context is never shared between requests
+        let lease = model_manager.acquire_context().await;
+        let busy_since = Instant::now();
+        debug!(
+            "Worker {} using context slot {} for request {}",
+            worker_id,
+            lease.slot(),
+            queued_request.ticket.id()
+        );
+
+        let request_id = queued_request.ticket.id().to_string();
+        let model_config = model_manager.get_config();
+        let model_stops =
+            chat_template.stop_sequences_for_model(&model_config, config.use_template_stop_tokens);
+        if !model_stops.is_empty() {
+            let request = &mut queued_request.request;
+            request.stop_tokens = merge_stop_sequences(&request.stop_tokens, model_stops, config);
+        }
+        let job = GenerationJob {
+            worker_id,
+            request_id: request_id.clone(),
+            request: &queued_request.request,
+            session: &queued_request.session,
+            model_config: &model_config,
+            batch_size: model_manager.get_batch_size(),
+            cancellation_token: &queued_request.cancellation_token,
+            chat_template: &chat_template,
+            max_conversion_failures: config.max_token_conversion_failures,
+            metrics: &metrics,
+        };
 
         // Process request with model access - use a closure to work within model lifetime
         if let Some(stream_sender) = queued_request.stream_sender {
             // Handle streaming request
-            let result = model_manager
-                .with_model(|model| {
-                    // Process the streaming request synchronously within the model lifetime
+            let result = match &backend_factory {
+                Some(factory) => Ok(run_blocking(|| {
                     Self::process_streaming_request_sync(
-                        worker_id,
-                        request_id.clone(),
-                        &queued_request.request,
-                        &queued_request.session,
-                        model,
-                        &model_manager,
+                        &job,
+                        &mut *factory.create_backend(),
                         stream_sender.clone(),
-                        &queued_request.cancellation_token,
-                        &chat_template,
                     )
-                })
-                .await;
+                })),
+                None => {
+                    model_manager
+                        .with_model(|model| {
+                            // Process the streaming request synchronously within the model lifetime
+                            run_blocking(|| {
+                                match LlamaCppBackend::new(
+                                    &model_manager,
+                                    model,
+                                    job.request,
+                                    &lease,
+                                ) {
+                                    Ok(mut backend) => Self::process_streaming_request_sync(
+                                        &job,
+                                        &mut backend,
+                                        stream_sender.clone(),
+                                    ),
+                                    Err(e) => {
+                                        let _ = stream_sender.try_send(Err(context_failed(e)));
+                                        Ok(())
+                                    }
+                                }
+                            })
+                        })
+                        .await
+                }
+            };
 
             match result {
-                Ok(_) => {
-                    // Streaming completed successfully
-                    let processing_time = start_time.elapsed();
-                    // Note: For streaming, tokens are tracked within process_streaming_request_sync
-                    metrics.record_request_completed(processing_time, 0);
-                }
+                Ok(_) => record_streaming_outcome(
+                    &metrics,
+                    &queued_request.cancellation_token,
+                    start_time.elapsed(),
+                ),
                 Err(model_error) => {
                     let queue_error =
                         QueueError::WorkerError(format!("Model error: {}", model_error));
@@ -424,21 +1502,64 @@ impl RequestQueue {
             }
         } else {
             // Handle batch request
-            let result = model_manager
-                .with_model(|model| {
-                    // Process the request synchronously within the model lifetime
-                    Self::process_batch_request_sync(
-                        worker_id,
-                        request_id.clone(),
-                        &queued_request.request,
-                        &queued_request.session,
-                        model,
-                        &model_manager,
-                        &queued_request.cancellation_token,
-                        &chat_template,
-                    )
-                })
-                .await;
+            let n = queued_request.request.n.unwrap_or(1);
+            let result = match &backend_factory {
+                Some(factory) if n > 1 => Ok(run_blocking(|| {
+                    let prompt = job.tokenize_prompt(|prompt| factory.tokenize(prompt))?;
+                    match factory.create_batch_backend(n as usize) {
+                        Some(mut backend) => Self::process_multi_sequence_request_sync(
+                            &job,
+                            &prompt,
+                            n as usize,
+                            &mut *backend,
+                        ),
+                        None => Err(QueueError::WorkerError(
+                            "Multiple completions need a backend generating sequences together; use n = 1"
+                                .to_string(),
+                        )),
+                    }
+                })),
+                Some(factory) => Ok(run_blocking(|| {
+                    Self::process_batch_request_sync(&job, &mut *factory.create_backend())
+                })),
+                None => {
+                    model_manager
+                        .with_model(|model| {
+                            // Process the request synchronously within the model lifetime
+                            run_blocking(|| match n {
+                                n if n > 1 => {
+                                    let prompt = job.tokenize_prompt(|prompt| {
+                                        crate::backend::tokenize(model, prompt)
+                                    })?;
+                                    LlamaCppBatchBackend::for_completions(
+                                        &model_manager,
+                                        model,
+                                        job.request,
+                                        n,
+                                        &lease,
+                                    )
+                                    .map_err(context_failed)
+                                    .and_then(|mut backend| {
+                                        Self::process_multi_sequence_request_sync(
+                                            &job,
+                                            &prompt,
+                                            n as usize,
+                                            &mut backend,
+                                        )
+                                    })
+                                }
+                                _ => {
+                                    LlamaCppBackend::new(&model_manager, model, job.request, &lease)
+                                        .map_err(context_failed)
+                                        .and_then(|mut backend| {
+                                            Self::process_batch_request_sync(&job, &mut backend)
+                                        })
+                                }
+                            })
+                        })
+                        .await
+                }
+            };
 
             match result {
                 Ok(inner_result) => {
@@ -450,6 +1571,10 @@ impl RequestQueue {
                                 processing_time,
                                 response.tokens_generated,
                             );
+                            metrics.record_generation_timing(
+                                response.prompt_time,
+                                response.decode_time,
+                            );
                             let _ = queued_request.response_sender.send(Ok(response));
                         }
                         Err(queue_error) => {
@@ -467,6 +1592,9 @@ impl RequestQueue {
             };
         }
 
+        drop(lease);
+        metrics.record_worker_busy(worker_id, busy_since.elapsed());
+
         let processing_time = start_time.elapsed();
         debug!(
             "Worker {} completed request {} in {:?}",
@@ -474,17 +1602,16 @@ impl RequestQueue {
         );
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn process_batch_request_sync(
-        worker_id: usize,
-        request_id: String,
-        request: &GenerationRequest,
-        session: &Session,
-        model: &LlamaModel,
-        model_manager: &ModelManager,
-        cancellation_token: &CancellationToken,
-        chat_template: &ChatTemplateEngine,
+    fn process_batch_request_sync<B: ModelBackend + ?Sized>(
+        job: &GenerationJob,
+        backend: &mut B,
     ) -> Result<GenerationResponse, QueueError> {
+        let GenerationJob {
+            worker_id,
+            ref request_id,
+            request,
+            ..
+        } = *job;
         let start_time = Instant::now();
 
         debug!(
@@ -492,122 +1619,60 @@ impl RequestQueue {
             worker_id, request_id
         );
 
-        // Format the session messages into a prompt using ChatTemplateEngine
-        let prompt = match chat_template.render_session_with_config(
-            session,
-            model,
-            Some(model_manager.get_config()),
-        ) {
-            Ok(prompt) => prompt,
-            Err(e) => {
-                error!("Failed to render session prompt: {}", e);
-                return Err(QueueError::WorkerError(format!(
-                    "Template rendering failed: {}",
-                    e
-                )));
-            }
-        };
-        debug!("Formatted prompt: {}", prompt);
-
-        // Create context for this inference
-        let mut ctx = match model_manager.create_context(model) {
-            Ok(context) => context,
-            Err(e) => {
-                error!("Failed to create context: {}", e);
-                return Err(QueueError::WorkerError(format!(
-                    "Context creation failed: {}",
-                    e
-                )));
-            }
-        };
-
-        // Tokenize the prompt
-        let tokens_list = match model.str_to_token(&prompt, AddBos::Always) {
-            Ok(tokens) => tokens,
-            Err(e) => {
-                error!("Failed to tokenize prompt: {}", e);
-                return Err(QueueError::WorkerError(format!(
-                    "Tokenization failed: {}",
-                    e
-                )));
-            }
-        };
-
-        debug!("Tokenized prompt to {} tokens", tokens_list.len());
-
-        // Create batch for initial prompt processing
-        let batch_size = model_manager.get_batch_size();
+        let tokens_list = job.decode_prompt(backend).inspect_err(|e| {
+            error!("Failed to process prompt: {}", e);
+        })?;
 
-        // Validate that prompt tokens don't exceed batch size
-        if tokens_list.len() > batch_size {
-            error!(
-                "Prompt token count ({}) exceeds configured batch size ({}). Consider reducing prompt length or increasing batch_size in config.",
-                tokens_list.len(),
-                batch_size
+        let prompt_time = start_time.elapsed();
+        if request.is_prefill_only() {
+            debug!(
+                "Worker {} decoded the prompt of prefill-only request {} in {:?}",
+                worker_id, request_id, prompt_time
             );
-            return Err(QueueError::WorkerError(format!(
-                "Prompt too long: {} tokens exceeds batch size limit of {}",
-                tokens_list.len(),
-                batch_size
-            )));
-        }
-
-        let mut batch = LlamaBatch::new(batch_size, 1);
-
-        // Add prompt tokens to batch
-        for (i, token) in tokens_list.iter().enumerate() {
-            let is_last = i == tokens_list.len() - 1;
-            if let Err(e) = batch.add(*token, i as i32, &[0], is_last) {
-                error!("Failed to add token to batch: {}", e);
-                return Err(QueueError::WorkerError(format!(
-                    "Batch token add failed: {}",
-                    e
-                )));
-            }
-        }
-
-        // Process the initial prompt batch
-        if let Err(e) = ctx.decode(&mut batch) {
-            error!("Failed to decode batch: {}", e);
-            return Err(QueueError::WorkerError(format!(
-                "Batch decode failed: {}",
-                e
-            )));
+            return Ok(GenerationResponse {
+                generated_text: String::new(),
+                tokens_generated: 0,
+                generation_time: start_time.elapsed(),
+                finish_reason: FinishReason::Stopped("Prefill only".to_string()),
+                prompt_tokens: tokens_list.len() as u32,
+                prompt_time,
+                decode_time: Duration::ZERO,
+                time_to_first_token: None,
+                candidates: Vec::new(),
+                generated_tokens: Vec::new(),
+            });
         }
-
         debug!("Initial prompt processed, starting generation");
 
         // Create fresh stoppers for this request
-        let mut stoppers: Vec<Box<dyn Stopper>> = vec![
-            Box::new(EosStopper::new(2)), // Common EOS token ID, actual detection via model.is_eog_token()
-            Box::new(MaxTokensStopper::new(
-                request.max_tokens.unwrap_or(4096) as usize
-            )),
-            Box::new(RepetitionStopper::new(
-                request
-                    .stopping_config
-                    .as_ref()
-                    .and_then(|c| c.repetition_detection.clone())
-                    .unwrap_or_default(),
-            )),
-        ];
+        let mut stoppers = request_stoppers(request, backend);
 
-        // Create sampler for token generation
-        let mut sampler = LlamaSampler::chain_simple([
-            LlamaSampler::dist(1234), // Use fixed seed for deterministic behavior
-            LlamaSampler::greedy(),
-        ]);
+        let stop_conditions =
+            StopConditions::for_request(request, &backend.end_of_turn_token_ids(job.model_config))
+                .with_deadline(start_time, request.effective_max_duration());
 
         let max_tokens = request.max_tokens.unwrap_or(512);
         let mut generated_text = String::new();
         let mut finish_reason = FinishReason::Stopped("Maximum tokens reached".to_string());
         let mut tokens_generated = 0u32;
+        let mut time_to_first_token = None;
         let mut n_cur = tokens_list.len();
+        let mut budget = GenerationBudget::new(max_tokens, job.max_conversion_failures);
+        let mut token_log = TokenLog::for_request(request);
 
         // Generation loop
         while tokens_generated < max_tokens {
+            if let Some(reason) = budget.next_iteration() {
+                finish_reason = FinishReason::Stopped(reason);
+                break;
+            }
+            if stop_conditions.time_up() {
+                finish_reason = FinishReason::Stopped("Time limit reached".to_string());
+                break;
+            }
+
             // Check for cancellation before each token
-            if cancellation_token.is_cancelled() {
+            if job.cancellation_token.is_cancelled() {
                 debug!(
                     "Worker {} batch request {} cancelled during token generation",
                     worker_id, request_id
@@ -617,20 +1682,36 @@ impl RequestQueue {
             }
 
             // Sample next token
-            let token = sampler.sample(&ctx, batch.n_tokens() - 1);
+            let token = backend.sample_next_token();
 
             // Check for end of sequence token
-            if model.is_eog_token(token) {
+            if backend.is_eog(token) {
                 finish_reason = FinishReason::Stopped("End of sequence token detected".to_string());
                 break;
             }
 
+            // Check stop token ids before converting the token to text
+            if stop_conditions.matches_token(token) {
+                finish_reason = FinishReason::Stopped("Stop token detected".to_string());
+                break;
+            }
+
             // Convert token to string with buffer reuse
-            let token_str = match model.token_to_str(token, Special::Tokenize) {
-                Ok(s) => s,
+            let token_str = match backend.token_to_str(token) {
+                Ok(s) => {
+                    budget.record_conversion_success();
+                    s
+                }
                 Err(e) => {
-                    warn!("Failed to convert token to string: {}", e);
-                    continue; // Skip this token but continue generation
+                    warn!("Failed to convert token {} to string: {}", token, e);
+                    // Skip this token, unless conversions keep failing
+                    match budget.record_conversion_failure(token as i32) {
+                        Some(reason) => {
+                            finish_reason = FinishReason::Stopped(reason);
+                            break;
+                        }
+                        None => continue,
+                    }
                 }
             };
 
@@ -640,20 +1721,23 @@ impl RequestQueue {
             }
             generated_text.push_str(&token_str);
             tokens_generated += 1;
+            token_log.record(token, &token_str);
+            time_to_first_token.get_or_insert_with(|| start_time.elapsed());
 
             // Feed token text to RepetitionStopper specifically
             for stopper in &mut stoppers {
-                // Special handling for RepetitionStopper to feed token text
+                // Special handling for RepetitionStopper to feed the token and its text
                 if let Some(repetition_stopper) =
                     stopper.as_any_mut().downcast_mut::<RepetitionStopper>()
                 {
+                    repetition_stopper.add_token(token);
                     repetition_stopper.add_token_text(token_str.clone());
                 }
             }
 
             // Check stoppers for early termination
             for stopper in &mut stoppers {
-                if let Some(FinishReason::Stopped(reason)) = stopper.should_stop(&ctx, &batch) {
+                if let Some(FinishReason::Stopped(reason)) = backend.should_stop(stopper.as_mut()) {
                     finish_reason = FinishReason::Stopped(reason);
                     break;
                 }
@@ -666,62 +1750,23 @@ impl RequestQueue {
             }
 
             // Check for stop tokens in the generated text
-            if Self::should_stop(&generated_text, &request.stop_tokens) {
+            if stop_conditions.matches_text(&generated_text, token_str.len()) {
                 finish_reason = FinishReason::Stopped("Stop token detected".to_string());
                 break;
             }
 
-            // Prepare next batch for continued generation
-            batch.clear();
-            if let Err(e) = batch.add(token, n_cur as i32, &[0], true) {
-                error!("Failed to add continuation token: {}", e);
-                break;
-            }
-
-            // Decode the new token
-            if let Err(e) = ctx.decode(&mut batch) {
-                error!("Failed to decode continuation batch: {}", e);
+            // Decode the new token for continued generation
+            if let Err(e) = backend.decode(&[token], n_cur) {
+                error!("Failed to decode continuation token: {}", e);
                 break;
             }
 
             n_cur += 1;
         }
+        let decode_time = start_time.elapsed().saturating_sub(prompt_time);
 
         // Check if the generated text contains tool calls
-        let final_finish_reason = match &finish_reason {
-            FinishReason::Stopped(reason)
-                if reason == "End of sequence token detected"
-                    || reason == "Stop token detected"
-                    || reason == "Maximum tokens reached" =>
-            {
-                match chat_template.extract_tool_calls(&generated_text) {
-                    Ok(tool_calls) if !tool_calls.is_empty() => {
-                        debug!(
-                            "Worker {} detected {} tool calls in generated text for request {}",
-                            worker_id,
-                            tool_calls.len(),
-                            request_id
-                        );
-                        FinishReason::Stopped("Tool call detected".to_string())
-                    }
-                    Ok(_) => {
-                        debug!(
-                            "Worker {} no tool calls detected in generated text for request {}",
-                            worker_id, request_id
-                        );
-                        finish_reason
-                    }
-                    Err(e) => {
-                        warn!(
-                            "Worker {} failed to extract tool calls for request {}: {}",
-                            worker_id, request_id, e
-                        );
-                        finish_reason
-                    }
-                }
-            }
-            _ => finish_reason,
-        };
+        let final_finish_reason = job.final_finish_reason(&generated_text, finish_reason);
 
         let generation_time = start_time.elapsed();
 
@@ -735,151 +1780,459 @@ impl RequestQueue {
             tokens_generated,
             generation_time,
             finish_reason: final_finish_reason,
+            prompt_tokens: tokens_list.len() as u32,
+            prompt_time,
+            decode_time,
+            time_to_first_token,
+            candidates: Vec::new(),
+            generated_tokens: token_log.tokens,
         })
     }
 
-    fn should_stop(generated_text: &str, stop_tokens: &[String]) -> bool {
-        for stop_token in stop_tokens {
-            if generated_text.contains(stop_token) {
-                return true;
+    /// Generate requests taken together, side by side in one context. Each gets its
+    /// own response, as if it had run alone.
+    async fn process_request_group(
+        worker_id: usize,
+        mut requests: Vec<QueuedRequest>,
+        model_manager: Arc<ModelManager>,
+        backend_factory: Option<Arc<dyn BackendFactory>>,
+        metrics: Arc<QueueMetrics>,
+        chat_template: Arc<ChatTemplateEngine>,
+        config: &QueueConfig,
+    ) {
+        let start_time = Instant::now();
+
+        if backend_factory.is_none() {
+            if let Err(e) = model_manager.ensure_loaded().await {
+                let error = model_unavailable(e);
+                for queued_request in requests {
+                    let _ = queued_request.response_sender.send(Err(error.clone()));
+                    metrics.record_request_failed();
+                }
+                return;
+            }
+        }
+
+        // The whole group generates in one context
+        let lease = model_manager.acquire_context().await;
+        let busy_since = Instant::now();
+        debug!(
+            "Worker {} using context slot {} for {} batched requests",
+            worker_id,
+            lease.slot(),
+            requests.len()
+        );
+
+        let model_config = model_manager.get_config();
+        let model_stops =
+            chat_template.stop_sequences_for_model(&model_config, config.use_template_stop_tokens);
+        if !model_stops.is_empty() {
+            for queued_request in &mut requests {
+                let request = &mut queued_request.request;
+                request.stop_tokens =
+                    merge_stop_sequences(&request.stop_tokens, model_stops.clone(), config);
             }
         }
-        false
+        let jobs: Vec<GenerationJob> = requests
+            .iter()
+            .map(|queued_request| GenerationJob {
+                worker_id,
+                request_id: queued_request.ticket.id().to_string(),
+                request: &queued_request.request,
+                session: &queued_request.session,
+                model_config: &model_config,
+                batch_size: model_manager.get_batch_size(),
+                cancellation_token: &queued_request.cancellation_token,
+                chat_template: &chat_template,
+                max_conversion_failures: config.max_token_conversion_failures,
+                metrics: &metrics,
+            })
+            .collect();
+
+        let results = match &backend_factory {
+            Some(factory) => run_blocking(|| {
+                Self::generate_request_groups_sync(
+                    &jobs,
+                    |prompt| factory.tokenize(prompt),
+                    |group, prompts| match factory.create_batch_backend(group.len()) {
+                        Some(mut backend) => {
+                            Self::generate_request_group_sync(group, prompts, &mut *backend)
+                        }
+                        // Without batch support, the requests run one after another
+                        None => group
+                            .iter()
+                            .map(|job| {
+                                Self::process_batch_request_sync(
+                                    job,
+                                    &mut *factory.create_backend(),
+                                )
+                            })
+                            .collect(),
+                    },
+                )
+            }),
+            None => model_manager
+                .with_model(|model| {
+                    run_blocking(|| {
+                        Self::generate_request_groups_sync(
+                            &jobs,
+                            |prompt| crate::backend::tokenize(model, prompt),
+                            |group, prompts| match LlamaCppBatchBackend::new(
+                                &model_manager,
+                                model,
+                                &group.iter().map(|job| job.request).collect::<Vec<_>>(),
+                                &lease,
+                            ) {
+                                Ok(mut backend) => {
+                                    Self::generate_request_group_sync(group, prompts, &mut backend)
+                                }
+                                Err(e) => {
+                                    let error = context_failed(e);
+                                    group.iter().map(|_| Err(error.clone())).collect()
+                                }
+                            },
+                        )
+                    })
+                })
+                .await
+                .unwrap_or_else(|model_error| {
+                    let error = QueueError::WorkerError(format!("Model error: {}", model_error));
+                    jobs.iter().map(|_| Err(error.clone())).collect()
+                }),
+        };
+        drop(jobs);
+
+        for (queued_request, result) in requests.into_iter().zip(results) {
+            match result {
+                Ok(response) => {
+                    metrics
+                        .record_request_completed(start_time.elapsed(), response.tokens_generated);
+                    metrics.record_generation_timing(response.prompt_time, response.decode_time);
+                    let _ = queued_request.response_sender.send(Ok(response));
+                }
+                Err(queue_error) => {
+                    metrics.record_request_failed();
+                    let _ = queued_request.response_sender.send(Err(queue_error));
+                }
+            }
+        }
+
+        drop(lease);
+        metrics.record_worker_busy(worker_id, busy_since.elapsed());
+        debug!(
+            "Worker {} completed a batch of requests in {:?}",
+            worker_id,
+            start_time.elapsed()
+        );
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn process_streaming_request_sync(
-        worker_id: usize,
-        request_id: String,
-        request: &GenerationRequest,
-        session: &Session,
-        model: &LlamaModel,
-        model_manager: &ModelManager,
-        stream_sender: mpsc::Sender<Result<StreamChunk, QueueError>>,
-        cancellation_token: &CancellationToken,
-        chat_template: &ChatTemplateEngine,
-    ) -> Result<(), QueueError> {
+    /// Tokenize the prompts of `jobs`, then generate them with `generate` in groups
+    /// whose prompts fit in one batch together
+    fn generate_request_groups_sync(
+        jobs: &[GenerationJob],
+        tokenize: impl Fn(&str) -> Result<Vec<u32>, QueueError>,
+        mut generate: impl FnMut(
+            &[&GenerationJob],
+            &[Vec<u32>],
+        ) -> Vec<Result<GenerationResponse, QueueError>>,
+    ) -> Vec<Result<GenerationResponse, QueueError>> {
+        let mut results: Vec<Option<Result<GenerationResponse, QueueError>>> =
+            jobs.iter().map(|_| None).collect();
+        let mut prompts = Vec::new();
+        for (index, job) in jobs.iter().enumerate() {
+            match job.tokenize_prompt(&tokenize) {
+                Ok(prompt) => prompts.push((index, prompt)),
+                Err(e) => results[index] = Some(Err(e)),
+            }
+        }
+
+        let batch_size = jobs.first().map_or(0, |job| job.batch_size);
+        for group in prompt_groups(prompts, batch_size) {
+            let (indices, prompts): (Vec<usize>, Vec<Vec<u32>>) = group.into_iter().unzip();
+            let group_jobs: Vec<&GenerationJob> =
+                indices.iter().map(|&index| &jobs[index]).collect();
+            for (index, result) in indices.into_iter().zip(generate(&group_jobs, &prompts)) {
+                results[index] = Some(result);
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| {
+                result.unwrap_or_else(|| {
+                    Err(QueueError::WorkerError(
+                        "Request was not generated".to_string(),
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// Generate `jobs` as the sequences of `backend`, one token of each unfinished
+    /// sequence per step, so no request waits for another to finish. Each prompt
+    /// has been tokenized and all of them fit in one batch.
+    fn generate_request_group_sync<B: BatchBackend + ?Sized>(
+        jobs: &[&GenerationJob],
+        prompts: &[Vec<u32>],
+        backend: &mut B,
+    ) -> Vec<Result<GenerationResponse, QueueError>> {
         let start_time = Instant::now();
+        if let Some(job) = jobs.first() {
+            job.metrics.record_batch(jobs.len());
+            debug!(
+                "Worker {} generating {} requests together",
+                job.worker_id,
+                jobs.len()
+            );
+        }
 
+        let inputs: Vec<(usize, &[u32], usize)> = prompts
+            .iter()
+            .enumerate()
+            .map(|(seq, prompt)| (seq, prompt.as_slice(), 0))
+            .collect();
+        if let Err(e) = backend.decode(&inputs) {
+            error!("Failed to process batched prompts: {}", e);
+            return jobs.iter().map(|_| Err(e.clone())).collect();
+        }
+        let prompt_time = start_time.elapsed();
+
+        let mut sequences: Vec<GroupedSequence> = jobs
+            .iter()
+            .zip(prompts)
+            .map(|(job, prompt)| {
+                let end_of_turn_ids = backend.end_of_turn_token_ids(job.model_config);
+                GroupedSequence::new(job, prompt.len(), start_time, &end_of_turn_ids)
+            })
+            .collect();
+
+        let mut active: Vec<usize> = (0..sequences.len())
+            .filter(|&seq| sequences[seq].finish.is_none())
+            .collect();
+        while !active.is_empty() {
+            let sampled: Vec<(usize, u32)> = active
+                .iter()
+                .filter_map(|&seq| {
+                    sequences[seq]
+                        .next_token(seq, &mut *backend)
+                        .map(|token| (seq, token))
+                })
+                .collect();
+            if sampled.is_empty() {
+                break;
+            }
+
+            let inputs: Vec<(usize, &[u32], usize)> = sampled
+                .iter()
+                .map(|(seq, token)| (*seq, std::slice::from_ref(token), sequences[*seq].n_cur))
+                .collect();
+            if let Err(e) = backend.decode(&inputs) {
+                error!("Failed to decode batched continuation tokens: {}", e);
+                break;
+            }
+            for &(seq, _) in &sampled {
+                sequences[seq].n_cur += 1;
+            }
+            active = sampled.into_iter().map(|(seq, _)| seq).collect();
+        }
+
+        sequences
+            .into_iter()
+            .map(|sequence| Ok(sequence.into_response(prompt_time)))
+            .collect()
+    }
+
+    /// Generate `n` completions of `job` as the sequences of `backend`, from one
+    /// decode of the prompt shared by all of them. The response is that of the first
+    /// completion, with every completion in `candidates`.
+    fn process_multi_sequence_request_sync<B: BatchBackend + ?Sized>(
+        job: &GenerationJob,
+        prompt: &[u32],
+        n: usize,
+        backend: &mut B,
+    ) -> Result<GenerationResponse, QueueError> {
+        let start_time = Instant::now();
         debug!(
-            "Worker {} starting streaming inference for request {}",
-            worker_id, request_id
+            "Worker {} starting {}-sequence inference for request {}",
+            job.worker_id, n, job.request_id
         );
 
-        // Format the session messages into a prompt using ChatTemplateEngine
-        let prompt = match chat_template.render_session_with_config(
-            session,
-            model,
-            Some(model_manager.get_config()),
-        ) {
-            Ok(prompt) => prompt,
-            Err(e) => {
-                error!("Failed to render session prompt for streaming: {}", e);
-                let _ = stream_sender.try_send(Err(QueueError::WorkerError(format!(
-                    "Template rendering failed: {}",
-                    e
-                ))));
-                return Ok(());
+        backend.decode_shared(prompt, n)?;
+        let prompt_time = start_time.elapsed();
+
+        let end_of_turn_ids = backend.end_of_turn_token_ids(job.model_config);
+        let mut sequences: Vec<GroupedSequence> = (0..n)
+            .map(|_| GroupedSequence::new(job, prompt.len(), start_time, &end_of_turn_ids))
+            .collect();
+
+        let mut active: Vec<usize> = (0..n)
+            .filter(|&seq| sequences[seq].finish.is_none())
+            .collect();
+        while !active.is_empty() {
+            let sampled: Vec<(usize, u32)> = active
+                .iter()
+                .filter_map(|&seq| {
+                    sequences[seq]
+                        .next_token(seq, &mut *backend)
+                        .map(|token| (seq, token))
+                })
+                .collect();
+            if sampled.is_empty() {
+                break;
             }
-        };
-        debug!("Formatted prompt for streaming: {}", prompt);
 
-        // Create context for this inference
-        let mut ctx = match model_manager.create_context(model) {
-            Ok(context) => context,
-            Err(e) => {
-                error!("Failed to create context for streaming: {}", e);
-                let _ = stream_sender.try_send(Err(QueueError::WorkerError(format!(
-                    "Context creation failed: {}",
-                    e
-                ))));
-                return Ok(());
+            let inputs: Vec<(usize, &[u32], usize)> = sampled
+                .iter()
+                .map(|(seq, token)| (*seq, std::slice::from_ref(token), sequences[*seq].n_cur))
+                .collect();
+            if let Err(e) = backend.decode(&inputs) {
+                error!("Failed to decode continuation batch: {}", e);
+                break;
             }
+            for &(seq, _) in &sampled {
+                sequences[seq].n_cur += 1;
+            }
+            active = sampled.into_iter().map(|(seq, _)| seq).collect();
+        }
+
+        let mut responses = sequences
+            .into_iter()
+            .map(|sequence| sequence.into_response(prompt_time));
+        let Some(mut response) = responses.next() else {
+            return Err(QueueError::WorkerError("n must be at least 1".to_string()));
         };
+        let first = GenerationCandidate {
+            text: response.generated_text.clone(),
+            tokens_generated: response.tokens_generated,
+            finish_reason: response.finish_reason.clone(),
+        };
+        response.candidates = std::iter::once(first)
+            .chain(responses.map(|other| GenerationCandidate {
+                text: other.generated_text,
+                tokens_generated: other.tokens_generated,
+                finish_reason: other.finish_reason,
+            }))
+            .collect();
+        // The last completion to finish ends the request
+        response.generation_time = start_time.elapsed();
+        response.decode_time = response.generation_time.saturating_sub(prompt_time);
+        Ok(response)
+    }
 
-        // Tokenize the prompt
-        let tokens_list = match model.str_to_token(&prompt, AddBos::Always) {
-            Ok(tokens) => tokens,
-            Err(e) => {
-                error!("Failed to tokenize prompt for streaming: {}", e);
-                let _ = stream_sender.try_send(Err(QueueError::WorkerError(format!(
-                    "Tokenization failed: {}",
-                    e
-                ))));
-                return Ok(());
+    fn process_embedding_request_sync(
+        texts: &[String],
+        normalize: bool,
+        model: &LlamaModel,
+        model_manager: &ModelManager,
+        lease: &ContextLease,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Vec<f32>>, QueueError> {
+        if model.n_embd() <= 0 {
+            return Err(QueueError::EmbeddingsNotSupported(
+                "model reports no embedding dimension".to_string(),
+            ));
+        }
+
+        let mut ctx = model_manager
+            .create_embedding_context(model, lease)
+            .map_err(|e| QueueError::EmbeddingsNotSupported(e.to_string()))?;
+        let batch_size = model_manager.get_batch_size();
+
+        collect_embeddings(texts, normalize, |text| {
+            if cancel.is_cancelled() {
+                return Err(QueueError::Timeout);
+            }
+            let tokens = model
+                .str_to_token(text, AddBos::Always)
+                .map_err(|e| QueueError::WorkerError(format!("Tokenization failed: {}", e)))?;
+            if tokens.is_empty() {
+                return Err(QueueError::WorkerError(
+                    "Text produced no tokens".to_string(),
+                ));
+            }
+            if tokens.len() > batch_size {
+                return Err(QueueError::WorkerError(format!(
+                    "Text has {} tokens, exceeding the batch size of {}",
+                    tokens.len(),
+                    batch_size
+                )));
             }
-        };
+
+            // Each text is embedded as an independent sequence
+            ctx.clear_kv_cache();
+            let mut batch = LlamaBatch::new(tokens.len(), 1);
+            batch
+                .add_sequence(&tokens, 0, false)
+                .map_err(|e| QueueError::WorkerError(format!("Failed to build batch: {}", e)))?;
+            ctx.decode(&mut batch)
+                .map_err(|e| QueueError::WorkerError(format!("Decode failed: {}", e)))?;
+
+            let embedding = ctx.embeddings_seq_ith(0).map_err(|e| match e {
+                EmbeddingsError::NotEnabled | EmbeddingsError::NonePoolType => {
+                    QueueError::EmbeddingsNotSupported(e.to_string())
+                }
+                other => {
+                    QueueError::WorkerError(format!("Failed to extract embeddings: {}", other))
+                }
+            })?;
+            Ok(embedding.to_vec())
+        })
+    }
+
+    fn process_streaming_request_sync<B: ModelBackend + ?Sized>(
+        job: &GenerationJob,
+        backend: &mut B,
+        stream_sender: mpsc::Sender<Result<StreamChunk, QueueError>>,
+    ) -> Result<(), QueueError> {
+        let GenerationJob {
+            worker_id,
+            ref request_id,
+            request,
+            ..
+        } = *job;
+        let start_time = Instant::now();
 
         debug!(
-            "Tokenized prompt to {} tokens for streaming",
-            tokens_list.len()
+            "Worker {} starting streaming inference for request {}",
+            worker_id, request_id
         );
 
-        // Create and process initial batch
-        let batch_size = model_manager.get_batch_size();
-
-        // Validate that prompt tokens don't exceed batch size
-        if tokens_list.len() > batch_size {
-            error!(
-                "Streaming prompt token count ({}) exceeds configured batch size ({}). Consider reducing prompt length or increasing batch_size in config.",
-                tokens_list.len(),
-                batch_size
-            );
-            let _ = stream_sender.try_send(Err(QueueError::WorkerError(format!(
-                "Prompt too long: {} tokens exceeds batch size limit of {}",
-                tokens_list.len(),
-                batch_size
-            ))));
-            return Ok(());
-        }
-
-        let mut batch = LlamaBatch::new(batch_size, 1);
-
-        // Add prompt tokens to batch
-        for (i, token) in tokens_list.iter().enumerate() {
-            let is_last = i == tokens_list.len() - 1;
-            if let Err(e) = batch.add(*token, i as i32, &[0], is_last) {
-                error!("Failed to add token to streaming batch: {}", e);
-                let _ = stream_sender.try_send(Err(QueueError::WorkerError(format!(
-                    "Batch token add failed: {}",
-                    e
-                ))));
+        let tokens_list = match job.decode_prompt(backend) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                error!("Failed to process prompt for streaming: {}", e);
+                let _ = stream_sender.try_send(Err(e));
                 return Ok(());
             }
-        }
+        };
 
-        // Process the initial prompt batch
-        if let Err(e) = ctx.decode(&mut batch) {
-            error!("Failed to decode streaming batch: {}", e);
-            let _ = stream_sender.try_send(Err(QueueError::WorkerError(format!(
-                "Batch decode failed: {}",
-                e
-            ))));
-            return Ok(());
+        let mut stats = StreamStats {
+            prompt_tokens: tokens_list.len() as u32,
+            prompt_time: start_time.elapsed(),
+            first_token_time: None,
+            tokens: TokenLog::for_request(request),
+        };
+        let mut chunker = StreamChunker::new(request.chunking);
+        if request.is_prefill_only() {
+            return Self::handle_streaming_completion(
+                job,
+                "",
+                0,
+                start_time,
+                stats,
+                &stream_sender,
+                &mut chunker,
+                "Prefill only",
+            );
         }
-
         debug!("Initial prompt processed for streaming, starting generation");
 
         // Create fresh stoppers for this request
-        let mut stoppers: Vec<Box<dyn Stopper>> = vec![
-            Box::new(EosStopper::new(2)), // Common EOS token ID, actual detection via model.is_eog_token()
-            Box::new(MaxTokensStopper::new(
-                request.max_tokens.unwrap_or(4096) as usize
-            )),
-            Box::new(RepetitionStopper::new(
-                request
-                    .stopping_config
-                    .as_ref()
-                    .and_then(|c| c.repetition_detection.clone())
-                    .unwrap_or_default(),
-            )),
-        ];
+        let mut stoppers = request_stoppers(request, backend);
 
-        // Create sampler for token generation
-        let mut sampler = LlamaSampler::chain_simple([
-            LlamaSampler::dist(1234), // Use fixed seed for deterministic behavior
-            LlamaSampler::greedy(),
-        ]);
+        let stop_conditions =
+            StopConditions::for_request(request, &backend.end_of_turn_token_ids(job.model_config))
+                .with_deadline(start_time, request.effective_max_duration());
 
         let max_tokens = request.max_tokens.unwrap_or(512);
         // Pre-allocate string capacity to reduce reallocations
@@ -888,13 +2241,37 @@ impl RequestQueue {
         let mut tokens_generated = 0u32;
         let mut n_cur = tokens_list.len();
 
-        // Pre-allocate token buffer for better memory management
-        let mut _token_buffer: Vec<u8> = Vec::with_capacity(64);
+        let mut budget = GenerationBudget::new(max_tokens, job.max_conversion_failures);
 
         // Generation loop - stream tokens one by one
         while tokens_generated < max_tokens {
+            if let Some(reason) = budget.next_iteration() {
+                return Self::handle_streaming_completion(
+                    job,
+                    &generated_text,
+                    tokens_generated,
+                    start_time,
+                    stats,
+                    &stream_sender,
+                    &mut chunker,
+                    &reason,
+                );
+            }
+            if stop_conditions.time_up() {
+                return Self::handle_streaming_completion(
+                    job,
+                    &generated_text,
+                    tokens_generated,
+                    start_time,
+                    stats,
+                    &stream_sender,
+                    &mut chunker,
+                    "Time limit reached",
+                );
+            }
+
             // Check for cancellation before each token
-            if cancellation_token.is_cancelled() {
+            if job.cancellation_token.is_cancelled() {
                 debug!(
                     "Worker {} streaming request {} cancelled during token generation",
                     worker_id, request_id
@@ -906,96 +2283,134 @@ impl RequestQueue {
             }
 
             // Sample next token
-            let token = sampler.sample(&ctx, batch.n_tokens() - 1);
+            let token = backend.sample_next_token();
 
             // Check for end of sequence token
-            if model.is_eog_token(token) {
+            if backend.is_eog(token) {
                 return Self::handle_streaming_completion(
-                    worker_id,
-                    request_id,
+                    job,
                     &generated_text,
                     tokens_generated,
                     start_time,
+                    stats,
                     &stream_sender,
-                    chat_template,
-                    "EndOfSequence",
+                    &mut chunker,
+                    "End of sequence token detected",
+                );
+            }
+
+            // Check stop token ids before converting the token to text
+            if stop_conditions.matches_token(token) {
+                return Self::handle_streaming_completion(
+                    job,
+                    &generated_text,
+                    tokens_generated,
+                    start_time,
+                    stats,
+                    &stream_sender,
+                    &mut chunker,
+                    "Stop token detected",
                 );
             }
 
             // Convert token to string
-            let token_text = match model.token_to_str(token, Special::Tokenize) {
-                Ok(s) => s,
+            let token_text = match backend.token_to_str(token) {
+                Ok(s) => {
+                    budget.record_conversion_success();
+                    s
+                }
                 Err(e) => {
-                    warn!("Failed to convert token to string in streaming: {}", e);
-                    continue; // Skip this token but continue generation
+                    warn!(
+                        "Failed to convert token {} to string in streaming: {}",
+                        token, e
+                    );
+                    // Skip this token, unless conversions keep failing
+                    match budget.record_conversion_failure(token as i32) {
+                        Some(reason) => {
+                            return Self::handle_streaming_completion(
+                                job,
+                                &generated_text,
+                                tokens_generated,
+                                start_time,
+                                stats,
+                                &stream_sender,
+                                &mut chunker,
+                                &reason,
+                            );
+                        }
+                        None => continue,
+                    }
                 }
             };
 
             generated_text.push_str(&token_text);
             tokens_generated += 1;
-
-            // Send the streaming chunk immediately
-            let chunk = StreamChunk {
-                text: token_text.clone(),
-                is_complete: false,
-                token_count: tokens_generated,
-            };
-
-            if stream_sender.try_send(Ok(chunk)).is_err() {
-                warn!("Stream receiver disconnected, stopping generation");
-                return Ok(());
+            stats.tokens.record(token, &token_text);
+            stats
+                .first_token_time
+                .get_or_insert_with(|| start_time.elapsed());
+
+            // Send the text as soon as it completes a chunk
+            if let Some(text) = chunker.push(&token_text) {
+                let chunk = StreamChunk {
+                    text,
+                    is_complete: false,
+                    token_count: tokens_generated,
+                    response: None,
+                    event: StreamEvent::TextChunk,
+                    token_ids: stats.tokens.take_unsent_ids(),
+                };
+
+                if !send_chunk(&stream_sender, Ok(chunk)) {
+                    warn!("Stream receiver disconnected, stopping generation");
+                    return Ok(());
+                }
             }
 
             // Feed token text to RepetitionStopper specifically
             for stopper in &mut stoppers {
-                // Special handling for RepetitionStopper to feed token text
+                // Special handling for RepetitionStopper to feed the token and its text
                 if let Some(repetition_stopper) =
                     stopper.as_any_mut().downcast_mut::<RepetitionStopper>()
                 {
+                    repetition_stopper.add_token(token);
                     repetition_stopper.add_token_text(token_text.clone());
                 }
             }
 
             // Check stoppers for early termination
             for stopper in &mut stoppers {
-                if let Some(FinishReason::Stopped(reason)) = stopper.should_stop(&ctx, &batch) {
+                if let Some(FinishReason::Stopped(reason)) = backend.should_stop(stopper.as_mut()) {
                     return Self::handle_streaming_completion(
-                        worker_id,
-                        request_id,
+                        job,
                         &generated_text,
                         tokens_generated,
                         start_time,
+                        stats,
                         &stream_sender,
-                        chat_template,
+                        &mut chunker,
                         &reason,
                     );
                 }
             }
 
             // Check for stop tokens in the accumulated generated text
-            if Self::should_stop(&generated_text, &request.stop_tokens) {
+            if stop_conditions.matches_text(&generated_text, token_text.len()) {
                 return Self::handle_streaming_completion(
-                    worker_id,
-                    request_id,
+                    job,
                     &generated_text,
                     tokens_generated,
                     start_time,
+                    stats,
                     &stream_sender,
-                    chat_template,
-                    "StopToken",
+                    &mut chunker,
+                    "Stop token detected",
                 );
             }
 
-            // Prepare next batch for continued generation
-            batch.clear();
-            if let Err(e) = batch.add(token, n_cur as i32, &[0], true) {
-                error!("Failed to add continuation token for streaming: {}", e);
-                break;
-            }
-
-            // Decode the new token
-            if let Err(e) = ctx.decode(&mut batch) {
-                error!("Failed to decode continuation batch for streaming: {}", e);
+            // Decode the new token for continued generation
+            if let Err(e) = backend.decode(&[token], n_cur) {
+                error!("Failed to decode continuation token for streaming: {}", e);
                 break;
             }
 
@@ -1004,29 +2419,52 @@ impl RequestQueue {
 
         // If we exit the loop due to max tokens, send final completion chunk
         Self::handle_streaming_completion(
-            worker_id,
-            request_id,
+            job,
             &generated_text,
             tokens_generated,
             start_time,
+            stats,
             &stream_sender,
-            chat_template,
-            "MaxTokens",
+            &mut chunker,
+            "Maximum tokens reached",
         )
     }
 
     /// Handle completion of streaming request with tool call detection
-    #[allow(clippy::too_many_arguments)]
     fn handle_streaming_completion(
-        worker_id: usize,
-        request_id: String,
+        job: &GenerationJob,
         generated_text: &str,
         tokens_generated: u32,
         start_time: Instant,
+        mut stats: StreamStats,
         stream_sender: &mpsc::Sender<Result<StreamChunk, QueueError>>,
-        chat_template: &ChatTemplateEngine,
+        chunker: &mut StreamChunker,
         base_reason: &str,
     ) -> Result<(), QueueError> {
+        let GenerationJob {
+            worker_id,
+            ref request_id,
+            chat_template,
+            metrics,
+            ..
+        } = *job;
+        let decode_time = start_time.elapsed().saturating_sub(stats.prompt_time);
+
+        // Text held back for a word or sentence boundary goes out before the final chunk
+        if let Some(text) = chunker.finish() {
+            let chunk = StreamChunk {
+                text,
+                is_complete: false,
+                token_count: tokens_generated,
+                response: None,
+                event: StreamEvent::TextChunk,
+                token_ids: stats.tokens.take_unsent_ids(),
+            };
+            if !send_chunk(stream_sender, Ok(chunk)) {
+                return Ok(());
+            }
+        }
+
         // Check if the generated text contains tool calls
         let has_tool_calls = match chat_template.extract_tool_calls(generated_text) {
             Ok(tool_calls) if !tool_calls.is_empty() => {
@@ -1054,95 +2492,528 @@ impl RequestQueue {
             }
         };
 
-        // Create final completion chunk - for streaming, we can't change the finish reason
-        // but we could potentially add metadata to indicate tool calls were detected
+        // Tool calls only replace the natural end reasons, as for batch requests
+        let finish_reason = match base_reason {
+            "End of sequence token detected" | "Stop token detected" | "Maximum tokens reached"
+                if has_tool_calls =>
+            {
+                "Tool call detected"
+            }
+            reason => reason,
+        };
+
+        let generation_time = start_time.elapsed();
         let final_chunk = StreamChunk {
             text: String::new(),
             is_complete: true,
             token_count: tokens_generated,
+            response: Some(GenerationResponse {
+                generated_text: generated_text.to_string(),
+                tokens_generated,
+                generation_time,
+                finish_reason: FinishReason::Stopped(finish_reason.to_string()),
+                prompt_tokens: stats.prompt_tokens,
+                prompt_time: stats.prompt_time,
+                decode_time,
+                time_to_first_token: stats.first_token_time,
+                candidates: Vec::new(),
+                generated_tokens: stats.tokens.tokens,
+            }),
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
         };
-        let _ = stream_sender.try_send(Ok(final_chunk));
+        metrics.record_generation_timing(stats.prompt_time, decode_time);
+        send_chunk(stream_sender, Ok(final_chunk));
+
+        debug!(
+            "Worker {} completed streaming inference for request {} in {:?} ({} tokens, reason: {})",
+            worker_id, request_id, generation_time, tokens_generated, finish_reason
+        );
+
+        Ok(())
+    }
+}
+
+impl RequestQueue {
+    /// Gracefully shutdown the queue, waiting for all workers to complete
+    pub async fn shutdown(mut self) {
+        info!("RequestQueue shutting down gracefully");
+        let shutdown_start = Instant::now();
+        let stats = self.get_stats();
+
+        info!(
+            "Shutdown initiated with {} requests in queue, {} total processed",
+            stats.current_queue_size, stats.total_requests
+        );
+
+        // Close the sender to signal workers to shutdown
+        // (sender will be dropped when this method ends)
+
+        // Wait for all worker handles to complete with individual timeouts
+        let worker_timeout = Duration::from_secs(15);
+        let mut successful_shutdowns = 0;
+        let total_workers = self.worker_handles.len();
+
+        for (i, handle) in self.worker_handles.drain(..).enumerate() {
+            match tokio::time::timeout(worker_timeout, handle).await {
+                Ok(Ok(())) => {
+                    debug!("Worker {} shutdown successfully", i);
+                    successful_shutdowns += 1;
+                }
+                Ok(Err(join_error)) => {
+                    warn!("Worker {} panicked during shutdown: {:?}", i, join_error);
+                }
+                Err(_) => {
+                    warn!("Worker {} shutdown timed out after {:?}", i, worker_timeout);
+                }
+            }
+        }
+
+        let shutdown_duration = shutdown_start.elapsed();
+
+        info!(
+            "RequestQueue shutdown complete in {:?}: {}/{} workers successful",
+            shutdown_duration, successful_shutdowns, total_workers
+        );
+    }
+
+    /// Shutdown with timeout and return statistics
+    pub async fn shutdown_with_timeout(self, timeout: Duration) -> QueueStats {
+        let stats_before = self.get_stats();
+        info!("Starting RequestQueue shutdown with {:?} timeout", timeout);
+
+        let shutdown_future = async {
+            self.shutdown().await;
+        };
+
+        match tokio::time::timeout(timeout, shutdown_future).await {
+            Ok(()) => {
+                info!("RequestQueue shutdown completed within timeout");
+            }
+            Err(_) => {
+                warn!(
+                    "RequestQueue shutdown timed out after {:?} (had {} requests in queue)",
+                    timeout, stats_before.current_queue_size
+                );
+            }
+        }
+
+        stats_before
+    }
+}
+
+/// Embed each text with `embed_one`, rejecting unusable vectors and optionally L2-normalizing
+fn collect_embeddings<F>(
+    texts: &[String],
+    normalize: bool,
+    mut embed_one: F,
+) -> Result<Vec<Vec<f32>>, QueueError>
+where
+    F: FnMut(&str) -> Result<Vec<f32>, QueueError>,
+{
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for text in texts {
+        let mut embedding = embed_one(text)?;
+        if embedding.is_empty() || embedding.iter().any(|value| !value.is_finite()) {
+            return Err(QueueError::EmbeddingsNotSupported(
+                "model produced an empty or non-finite embedding".to_string(),
+            ));
+        }
+        if normalize {
+            l2_normalize(&mut embedding);
+        }
+        embeddings.push(embedding);
+    }
+    Ok(embeddings)
+}
+
+/// Stoppers for a request: its stopping config with the effective token limit, and
+/// repetition detection on unless configured
+fn request_stoppers<B: ModelBackend + ?Sized>(
+    request: &GenerationRequest,
+    backend: &B,
+) -> Vec<Box<dyn Stopper>> {
+    let mut config = request.stopping_config.clone().unwrap_or_default();
+    config.max_tokens = Some(request.effective_max_tokens().unwrap_or(4096) as usize);
+    config
+        .repetition_detection
+        .get_or_insert_with(RepetitionConfig::default);
+    backend.stoppers(&config)
+}
+
+/// A request generating as one sequence of a [`BatchBackend`], with the stop checks
+/// of the single-request generation loop
+struct GroupedSequence<'a> {
+    job: &'a GenerationJob<'a>,
+    prompt_tokens: u32,
+    start_time: Instant,
+    stop_conditions: StopConditions,
+    repetition: RepetitionStopper,
+    budget: GenerationBudget,
+    max_tokens: u32,
+    generated_text: String,
+    tokens_generated: u32,
+    time_to_first_token: Option<Duration>,
+    token_log: TokenLog,
+    /// Position of the next token to decode
+    n_cur: usize,
+    /// Why the sequence finished, and when
+    finish: Option<(FinishReason, Instant)>,
+}
+
+impl<'a> GroupedSequence<'a> {
+    fn new(
+        job: &'a GenerationJob<'a>,
+        prompt_tokens: usize,
+        start_time: Instant,
+        end_of_turn_ids: &[u32],
+    ) -> Self {
+        let request = job.request;
+        let max_tokens = request.max_tokens.unwrap_or(512);
+        let repetition = request
+            .stopping_config
+            .as_ref()
+            .and_then(|config| config.repetition_detection.clone())
+            .unwrap_or_default();
+        let mut sequence = Self {
+            job,
+            prompt_tokens: prompt_tokens as u32,
+            start_time,
+            stop_conditions: StopConditions::for_request(request, end_of_turn_ids)
+                .with_deadline(start_time, request.effective_max_duration()),
+            repetition: RepetitionStopper::new(repetition),
+            budget: GenerationBudget::new(max_tokens, job.max_conversion_failures),
+            max_tokens,
+            generated_text: String::new(),
+            tokens_generated: 0,
+            time_to_first_token: None,
+            token_log: TokenLog::for_request(request),
+            n_cur: prompt_tokens,
+            finish: None,
+        };
+        if request.is_prefill_only() {
+            sequence.stop("Prefill only");
+        }
+        sequence
+    }
+
+    fn stop(&mut self, reason: impl Into<String>) {
+        self.finish = Some((FinishReason::Stopped(reason.into()), Instant::now()));
+    }
+
+    /// Sample the next token of sequence `seq`, returning it if it is to be decoded;
+    /// `None` once the sequence has finished
+    fn next_token<B: BatchBackend + ?Sized>(&mut self, seq: usize, backend: &mut B) -> Option<u32> {
+        loop {
+            if self.tokens_generated >= self.max_tokens {
+                self.stop("Maximum tokens reached");
+                return None;
+            }
+            if let Some(reason) = self.budget.next_iteration() {
+                self.stop(reason);
+                return None;
+            }
+            if self.stop_conditions.time_up() {
+                self.stop("Time limit reached");
+                return None;
+            }
+            if self.job.cancellation_token.is_cancelled() {
+                debug!(
+                    "Worker {} batched request {} cancelled during token generation",
+                    self.job.worker_id, self.job.request_id
+                );
+                self.stop("Error: Request cancelled");
+                return None;
+            }
+
+            let token = backend.sample_next_token(seq);
+            if backend.is_eog(token) {
+                self.stop("End of sequence token detected");
+                return None;
+            }
+            if self.stop_conditions.matches_token(token) {
+                self.stop("Stop token detected");
+                return None;
+            }
+
+            let token_str = match backend.token_to_str(token) {
+                Ok(s) => {
+                    self.budget.record_conversion_success();
+                    s
+                }
+                Err(e) => {
+                    warn!("Failed to convert token {} to string: {}", token, e);
+                    // Skip this token, unless conversions keep failing
+                    match self.budget.record_conversion_failure(token as i32) {
+                        Some(reason) => {
+                            self.stop(reason);
+                            return None;
+                        }
+                        None => continue,
+                    }
+                }
+            };
+
+            self.generated_text.push_str(&token_str);
+            self.tokens_generated += 1;
+            self.token_log.record(token, &token_str);
+            self.time_to_first_token
+                .get_or_insert_with(|| self.start_time.elapsed());
+
+            self.repetition.add_token(token);
+            self.repetition.add_token_text(token_str.clone());
+            if let Some(FinishReason::Stopped(reason)) = self.repetition.check() {
+                self.stop(reason);
+                return None;
+            }
+            if self
+                .stop_conditions
+                .matches_text(&self.generated_text, token_str.len())
+            {
+                self.stop("Stop token detected");
+                return None;
+            }
+            return Some(token);
+        }
+    }
+
+    fn into_response(self, prompt_time: Duration) -> GenerationResponse {
+        let (finish_reason, finished_at) = self.finish.unwrap_or_else(|| {
+            (
+                FinishReason::Stopped("Maximum tokens reached".to_string()),
+                Instant::now(),
+            )
+        });
+        let generation_time = finished_at.duration_since(self.start_time);
+        let finish_reason = self
+            .job
+            .final_finish_reason(&self.generated_text, finish_reason);
+        debug!(
+            "Worker {} completed batched request {} in {:?} ({} tokens, finish_reason: {:?})",
+            self.job.worker_id,
+            self.job.request_id,
+            generation_time,
+            self.tokens_generated,
+            finish_reason
+        );
+
+        GenerationResponse {
+            generated_text: self.generated_text,
+            tokens_generated: self.tokens_generated,
+            generation_time,
+            finish_reason,
+            prompt_tokens: self.prompt_tokens,
+            prompt_time,
+            decode_time: generation_time.saturating_sub(prompt_time),
+            time_to_first_token: self.time_to_first_token,
+            candidates: Vec::new(),
+            generated_tokens: self.token_log.tokens,
+        }
+    }
+}
+
+/// Split tokenized prompts, in order, into groups whose tokens fit in one batch
+/// together
+fn prompt_groups<T>(prompts: Vec<(T, Vec<u32>)>, batch_size: usize) -> Vec<Vec<(T, Vec<u32>)>> {
+    let mut groups: Vec<Vec<(T, Vec<u32>)>> = Vec::new();
+    let mut group_tokens = 0;
+    for (key, prompt) in prompts {
+        match groups.last_mut() {
+            Some(group) if group_tokens + prompt.len() <= batch_size => {
+                group_tokens += prompt.len();
+                group.push((key, prompt));
+            }
+            _ => {
+                group_tokens = prompt.len();
+                groups.push(vec![(key, prompt)]);
+            }
+        }
+    }
+    groups
+}
+
+/// Generation loop iterations allowed per requested token; iterations that
+/// produce no token (failed text conversions) count against this cap
+const MAX_ITERATIONS_PER_TOKEN: u64 = 4;
+
+/// Loop control for the generation loops.
+///
+/// A token whose text conversion fails is skipped without counting towards
+/// `max_tokens`, and with greedy sampling the same token comes back on the next
+/// iteration. The budget ends such loops: after `max_consecutive_failures`
+/// failures in a row, or after `MAX_ITERATIONS_PER_TOKEN * max_tokens`
+/// iterations in total.
+#[derive(Debug)]
+struct GenerationBudget {
+    max_iterations: u64,
+    iterations: u64,
+    max_consecutive_failures: u32,
+    consecutive_failures: u32,
+    conversion_failures: u32,
+}
+
+impl GenerationBudget {
+    fn new(max_tokens: u32, max_consecutive_failures: u32) -> Self {
+        Self {
+            max_iterations: MAX_ITERATIONS_PER_TOKEN * u64::from(max_tokens.max(1)),
+            iterations: 0,
+            max_consecutive_failures: max_consecutive_failures.max(1),
+            consecutive_failures: 0,
+            conversion_failures: 0,
+        }
+    }
 
-        let generation_time = start_time.elapsed();
-        let reason_suffix = if has_tool_calls {
-            " (with tool calls)"
-        } else {
-            ""
-        };
-        debug!(
-            "Worker {} completed streaming inference for request {} in {:?} ({} tokens, reason: {}{})",
-            worker_id, request_id, generation_time, tokens_generated, base_reason, reason_suffix
-        );
+    /// Start a loop iteration; returns the finish reason once the iteration cap is reached
+    fn next_iteration(&mut self) -> Option<String> {
+        if self.iterations >= self.max_iterations {
+            warn!(
+                "Generation stopped after {} iterations ({} token conversion failures)",
+                self.iterations, self.conversion_failures
+            );
+            return Some(format!(
+                "Error: Generation iteration limit reached ({} token conversion failures)",
+                self.conversion_failures
+            ));
+        }
+        self.iterations += 1;
+        None
+    }
 
-        Ok(())
+    fn record_conversion_success(&mut self) {
+        self.consecutive_failures = 0;
     }
-}
 
-impl RequestQueue {
-    /// Gracefully shutdown the queue, waiting for all workers to complete
-    pub async fn shutdown(mut self) {
-        info!("RequestQueue shutting down gracefully");
-        let shutdown_start = Instant::now();
-        let stats = self.get_stats();
+    /// Count a failed token conversion; returns the finish reason once too many failed in a row
+    fn record_conversion_failure(&mut self, token_id: i32) -> Option<String> {
+        self.conversion_failures += 1;
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < self.max_consecutive_failures {
+            return None;
+        }
 
-        info!(
-            "Shutdown initiated with {} requests in queue, {} total processed",
-            stats.current_queue_size, stats.total_requests
+        error!(
+            "Aborting generation: token {} could not be converted to text {} times in a row",
+            token_id, self.consecutive_failures
         );
+        Some(format!(
+            "Error: Token conversion failed {} times in a row (token id {})",
+            self.consecutive_failures, token_id
+        ))
+    }
+}
 
-        // Close the sender to signal workers to shutdown
-        // (sender will be dropped when this method ends)
+/// Number of completions a request asks for, checked against the configured maximum
+fn sequence_count(request: &GenerationRequest, max_sequences: u32) -> Result<u32, QueueError> {
+    match request.n.unwrap_or(1) {
+        0 => Err(QueueError::WorkerError("n must be at least 1".to_string())),
+        n if n > max_sequences => Err(QueueError::WorkerError(format!(
+            "Requested n = {} exceeds max_sequences_per_request ({})",
+            n, max_sequences
+        ))),
+        n => Ok(n),
+    }
+}
 
-        // Wait for all worker handles to complete with individual timeouts
-        let worker_timeout = Duration::from_secs(15);
-        let mut successful_shutdowns = 0;
-        let total_workers = self.worker_handles.len();
+/// Request-level stop conditions, checked for every sampled token
+#[derive(Debug, Default)]
+struct StopConditions {
+    token_ids: HashSet<u32>,
+    strings: Vec<String>,
+    /// Length in bytes of the longest stop string
+    max_string_len: usize,
+    /// When the request's time budget runs out
+    deadline: Option<Instant>,
+}
 
-        for (i, handle) in self.worker_handles.drain(..).enumerate() {
-            match tokio::time::timeout(worker_timeout, handle).await {
-                Ok(Ok(())) => {
-                    debug!("Worker {} shutdown successfully", i);
-                    successful_shutdowns += 1;
-                }
-                Ok(Err(join_error)) => {
-                    warn!("Worker {} panicked during shutdown: {:?}", i, join_error);
-                }
-                Err(_) => {
-                    warn!("Worker {} shutdown timed out after {:?}", i, worker_timeout);
-                }
-            }
+impl StopConditions {
+    fn new(token_ids: impl IntoIterator<Item = u32>, strings: &[String]) -> Self {
+        Self {
+            token_ids: token_ids.into_iter().collect(),
+            strings: strings.to_vec(),
+            max_string_len: strings.iter().map(String::len).max().unwrap_or(0),
+            deadline: None,
         }
+    }
 
-        let shutdown_duration = shutdown_start.elapsed();
+    /// Stop ids from the request and its stopping config, plus the template's end-of-turn ids
+    fn for_request(request: &GenerationRequest, end_of_turn_ids: &[u32]) -> Self {
+        let config_ids = request
+            .stopping_config
+            .iter()
+            .flat_map(|config| config.stop_token_ids.iter());
+        let token_ids = request
+            .stop_token_ids
+            .iter()
+            .chain(config_ids)
+            .chain(end_of_turn_ids)
+            .copied();
+        Self::new(token_ids, &request.stop_tokens)
+    }
 
-        info!(
-            "RequestQueue shutdown complete in {:?}: {}/{} workers successful",
-            shutdown_duration, successful_shutdowns, total_workers
-        );
+    /// End generation `max_duration` after `start`
+    fn with_deadline(mut self, start: Instant, max_duration: Option<Duration>) -> Self {
+        self.deadline = max_duration.map(|limit| start + limit);
+        self
     }
 
-    /// Shutdown with timeout and return statistics
-    pub async fn shutdown_with_timeout(self, timeout: Duration) -> QueueStats {
-        let stats_before = self.get_stats();
-        info!("Starting RequestQueue shutdown with {:?} timeout", timeout);
+    /// Whether the time budget has run out
+    fn time_up(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
 
-        let shutdown_future = async {
-            self.shutdown().await;
-        };
+    /// Whether a sampled token id ends generation
+    fn matches_token(&self, token_id: u32) -> bool {
+        self.token_ids.contains(&token_id)
+    }
 
-        match tokio::time::timeout(timeout, shutdown_future).await {
-            Ok(()) => {
-                info!("RequestQueue shutdown completed within timeout");
-            }
-            Err(_) => {
-                warn!(
-                    "RequestQueue shutdown timed out after {:?} (had {} requests in queue)",
-                    timeout, stats_before.current_queue_size
-                );
-            }
+    /// Whether a stop string occurs in `text`, of which only the last `appended` bytes are new.
+    ///
+    /// Earlier text was already checked, so only the tail that could hold a new
+    /// match is scanned instead of the whole text.
+    fn matches_text(&self, text: &str, appended: usize) -> bool {
+        if self.strings.is_empty() {
+            return false;
         }
 
-        stats_before
+        let mut start = text
+            .len()
+            .saturating_sub(appended + self.max_string_len.saturating_sub(1));
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+        let tail = &text[start..];
+        self.strings.iter().any(|stop| tail.contains(stop.as_str()))
+    }
+}
+
+/// The request's stop sequences followed by the model's, within the stop token caps
+/// of `config`. The request's own sequences are always kept; model sequences beyond
+/// the caps are left out.
+fn merge_stop_sequences(
+    requested: &[String],
+    model: Vec<String>,
+    config: &QueueConfig,
+) -> Vec<String> {
+    let mut merged = requested.to_vec();
+    for stop in model {
+        if merged.len() >= config.max_stop_tokens {
+            break;
+        }
+        if stop.len() <= config.max_stop_token_length && !merged.contains(&stop) {
+            merged.push(stop);
+        }
+    }
+    merged
+}
+
+/// Scale a vector to unit length; zero vectors are left unchanged
+fn l2_normalize(values: &mut [f32]) {
+    let magnitude = values.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if magnitude > 0.0 {
+        for value in values.iter_mut() {
+            *value /= magnitude;
+        }
     }
 }
 
@@ -1157,12 +3028,28 @@ impl Drop for RequestQueue {
     }
 }
 
+/// Queue error for a request whose inference context could not be created
+fn context_failed(error: ModelError) -> QueueError {
+    error!("Failed to create context: {}", error);
+    QueueError::WorkerError(format!("Context creation failed: {}", error))
+}
+
+/// Queue error for a request that cannot run because no model is available
+fn model_unavailable(error: ModelError) -> QueueError {
+    match error {
+        ModelError::NotLoaded => QueueError::ModelLoading,
+        other => QueueError::WorkerError(format!("Model error: {}", other)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::FakeModel;
     use crate::types::{
         Message, MessageRole, ModelConfig, ModelError, ModelSource, QueueConfig, RetryConfig,
-        Session, SessionId,
+        Session, SessionId, SessionUsage, StoppingConfig, StreamChunking, ToolPolicy,
+        ValidationSeverity,
     };
     use std::path::PathBuf;
     use std::time::SystemTime;
@@ -1178,6 +3065,15 @@ mod tests {
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         }
     }
 
@@ -1186,6 +3082,15 @@ mod tests {
             max_queue_size: 10,
             request_timeout: Duration::from_secs(5),
             worker_threads: 2,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         }
     }
 
@@ -1198,15 +3103,54 @@ mod tests {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now(),
+                attachments: Vec::new(),
             }],
             mcp_servers: Vec::new(),
             available_tools: Vec::new(),
             available_prompts: Vec::new(),
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
+    /// A long agent session: `message_count` messages and tools with sizeable schemas
+    fn create_large_session(message_count: usize) -> Session {
+        let mut session = create_test_session();
+        session.messages = (0..message_count)
+            .map(|i| Message {
+                role: if i % 2 == 0 {
+                    MessageRole::User
+                } else {
+                    MessageRole::Assistant
+                },
+                content: format!("message {} ", i).repeat(100),
+                tool_call_id: None,
+                tool_name: None,
+                timestamp: SystemTime::now(),
+                attachments: Vec::new(),
+            })
+            .collect();
+        let properties: serde_json::Map<_, _> = (0..50)
+            .map(|p| {
+                let schema = serde_json::json!({"type": "string", "description": "x".repeat(100)});
+                (format!("param_{}", p), schema)
+            })
+            .collect();
+        let parameters = serde_json::json!({"type": "object", "properties": properties});
+        session.available_tools = (0..20)
+            .map(|i| crate::types::ToolDefinition {
+                name: format!("tool_{}", i),
+                description: "A tool with a large parameter schema".to_string(),
+                parameters: parameters.clone(),
+                server_name: "test".to_string(),
+                original_name: None,
+            })
+            .collect();
+        session
+    }
+
     async fn setup_loaded_model_manager() -> Arc<ModelManager> {
         let temp_dir = TempDir::new().unwrap();
         let model_file = temp_dir.path().join("test.gguf");
@@ -1223,6 +3167,15 @@ mod tests {
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         };
 
         let manager = Arc::new(ModelManager::new(config).expect("Failed to create ModelManager"));
@@ -1282,11 +3235,20 @@ mod tests {
             max_tokens: Some(100),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: Vec::new(),
+            stop_token_ids: Vec::new(),
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
-        let result = queue.submit_request(request, &session).await;
+        let result = queue.submit_request(request, Arc::new(session)).await;
         assert!(matches!(result, Err(QueueError::WorkerError(_))));
     }
 
@@ -1302,19 +3264,22 @@ mod tests {
             max_tokens: Some(100),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: Vec::new(),
+            stop_token_ids: Vec::new(),
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
-        let result = queue.submit_request(request, &session).await;
+        let result = queue.submit_request(request, Arc::new(session)).await;
         // Should fail because model is not actually loaded in test setup
-        assert!(result.is_err());
-        match result.unwrap_err() {
-            QueueError::WorkerError(msg) => {
-                assert!(msg.contains("Model not loaded") || msg.contains("Model error"));
-            }
-            _ => panic!("Expected WorkerError for unloaded model"),
-        }
+        assert!(matches!(result, Err(QueueError::ModelLoading)));
     }
 
     #[tokio::test]
@@ -1329,26 +3294,30 @@ mod tests {
             max_tokens: Some(100),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: Vec::new(),
+            stop_token_ids: Vec::new(),
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
         let mut receiver = queue
-            .submit_streaming_request(request, &session)
+            .submit_streaming_request(request, Arc::new(session))
             .await
             .unwrap();
 
-        // Should receive an error since streaming is not yet implemented
+        // Should receive an error since no model is loaded
         let chunk_result = receiver.recv().await;
         assert!(chunk_result.is_some());
         match chunk_result.unwrap() {
-            Err(QueueError::WorkerError(msg)) => {
-                assert!(
-                    msg.contains("Streaming with real models not yet implemented")
-                        || msg.contains("Model not loaded")
-                );
-            }
-            Ok(_) => panic!("Expected error for streaming not implemented"),
+            Err(QueueError::ModelLoading) => {}
+            Ok(_) => panic!("Expected error for unloaded model"),
             Err(other) => panic!("Unexpected error type: {:?}", other),
         }
     }
@@ -1361,6 +3330,15 @@ mod tests {
             max_queue_size: 10,
             request_timeout: Duration::from_millis(10), // Very short timeout
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         };
         let queue = RequestQueue::new(model_manager, config);
 
@@ -1370,18 +3348,25 @@ mod tests {
             max_tokens: Some(100),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: Vec::new(),
+            stop_token_ids: Vec::new(),
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
-        let result = queue.submit_request(request, &session).await;
+        let result = queue.submit_request(request, Arc::new(session)).await;
         // Should fail because model is not loaded, not due to timeout in this test setup
         assert!(result.is_err());
-        // The error should be WorkerError about model not loaded, not timeout
+        // The error should be about the model not being loaded, not timeout
         match result.unwrap_err() {
-            QueueError::WorkerError(msg) => {
-                assert!(msg.contains("Model not loaded") || msg.contains("Model error"));
-            }
+            QueueError::ModelLoading => {}
             QueueError::Timeout => {
                 // This could also happen if the timeout is very short
             }
@@ -1389,21 +3374,427 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_submit_embedding_request_model_not_loaded() {
+        let model_manager = match ModelManager::new(create_test_model_config()) {
+            Ok(manager) => Arc::new(manager),
+            Err(ModelError::LoadingFailed(msg))
+                if msg.contains("Backend already initialized by external code") =>
+            {
+                println!("Skipping test due to backend already initialized by parallel test");
+                return;
+            }
+            Err(e) => panic!("Failed to create ModelManager: {:?}", e),
+        };
+        let queue = RequestQueue::new(model_manager, create_test_queue_config());
+
+        // Empty input never touches the model
+        let result = queue.submit_embedding_request(&[], true).await;
+        assert_eq!(result.unwrap(), Vec::<Vec<f32>>::new());
+        assert_eq!(queue.get_stats().total_requests, 0);
+
+        let texts = vec!["hello".to_string()];
+        let result = queue.submit_embedding_request(&texts, true).await;
+        assert!(matches!(result, Err(QueueError::ModelLoading)));
+
+        let stats = queue.get_stats();
+        assert_eq!(stats.total_requests, 1);
+        assert_eq!(stats.failed_requests, 1);
+        assert_eq!(stats.current_queue_size, 0);
+    }
+
+    #[test]
+    fn test_collect_embeddings_normalizes() {
+        let texts = vec!["a".to_string(), "bb".to_string()];
+        let mut seen = Vec::new();
+
+        let embeddings = collect_embeddings(&texts, true, |text| {
+            seen.push(text.to_string());
+            Ok(vec![3.0, 4.0 * text.len() as f32])
+        })
+        .unwrap();
+
+        assert_eq!(seen, texts);
+        assert_eq!(embeddings.len(), 2);
+        assert_eq!(embeddings[0], vec![0.6, 0.8]);
+        for embedding in &embeddings {
+            let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-6);
+        }
+
+        // Without normalization the raw vectors are returned
+        let raw = collect_embeddings(&texts, false, |_| Ok(vec![3.0, 4.0])).unwrap();
+        assert_eq!(raw[0], vec![3.0, 4.0]);
+
+        // Zero vectors stay zero instead of becoming NaN
+        let mut zero = vec![0.0, 0.0];
+        l2_normalize(&mut zero);
+        assert_eq!(zero, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_collect_embeddings_rejects_unsupported_output() {
+        let texts = vec!["a".to_string(), "b".to_string()];
+
+        // Errors from the model stop processing and are passed through
+        let mut calls = 0;
+        let result = collect_embeddings(&texts, true, |_| {
+            calls += 1;
+            Err(QueueError::EmbeddingsNotSupported("no pooling".to_string()))
+        });
+        assert!(matches!(result, Err(QueueError::EmbeddingsNotSupported(_))));
+        assert_eq!(calls, 1);
+
+        // Garbage output is reported as unsupported rather than returned
+        let result = collect_embeddings(&texts, false, |_| Ok(vec![f32::NAN, 1.0]));
+        assert!(matches!(result, Err(QueueError::EmbeddingsNotSupported(_))));
+
+        let result = collect_embeddings(&texts, false, |_| Ok(Vec::new()));
+        assert!(matches!(result, Err(QueueError::EmbeddingsNotSupported(_))));
+    }
+
+    #[test]
+    fn test_stop_conditions_for_request() {
+        let mut request = GenerationRequest::new(SessionId::new())
+            .with_stop_token_ids(vec![7])
+            .with_stopping_config(StoppingConfig {
+                stop_token_ids: vec![8],
+                ..StoppingConfig::default()
+            });
+        request.stop_tokens = vec!["END".to_string()];
+
+        let stops = StopConditions::for_request(&request, &[128009]);
+        for id in [7, 8, 128009] {
+            assert!(stops.matches_token(id));
+        }
+        assert!(!stops.matches_token(2));
+        assert!(stops.matches_text("the END", 4));
+    }
+
+    #[test]
+    fn test_prompt_groups() {
+        let prompts = vec![
+            ('a', vec![1; 3]),
+            ('b', vec![1; 2]),
+            ('c', vec![1; 4]),
+            ('d', vec![1]),
+        ];
+        let groups: Vec<Vec<char>> = prompt_groups(prompts, 5)
+            .into_iter()
+            .map(|group| group.into_iter().map(|(key, _)| key).collect())
+            .collect();
+        assert_eq!(groups, vec![vec!['a', 'b'], vec!['c', 'd']]);
+    }
+
+    /// Generate `requests` together on `model`, returning their responses in order
+    fn generate_group(
+        model: &FakeModel,
+        requests: &[GenerationRequest],
+    ) -> Vec<GenerationResponse> {
+        let session = create_test_session();
+        let model_config = create_test_model_config();
+        let chat_template = ChatTemplateEngine::new();
+        let metrics = QueueMetrics::new();
+        let cancellation_token = CancellationToken::new();
+        let jobs: Vec<GenerationJob> = requests
+            .iter()
+            .enumerate()
+            .map(|(index, request)| GenerationJob {
+                worker_id: 0,
+                request_id: index.to_string(),
+                request,
+                session: &session,
+                model_config: &model_config,
+                batch_size: 512,
+                cancellation_token: &cancellation_token,
+                chat_template: &chat_template,
+                max_conversion_failures: 8,
+                metrics: &metrics,
+            })
+            .collect();
+
+        let results = RequestQueue::generate_request_groups_sync(
+            &jobs,
+            |prompt| model.tokenize(prompt),
+            |group, prompts| {
+                let mut backend = model.create_batch_backend(group.len()).unwrap();
+                RequestQueue::generate_request_group_sync(group, prompts, &mut *backend)
+            },
+        );
+        assert_eq!(
+            metrics.get_stats().average_batch_size,
+            requests.len() as f64
+        );
+        results.into_iter().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_grouped_requests_get_their_own_tokens() {
+        let model = FakeModel::new()
+            .with_reply(["one", " two", " three"])
+            .with_reply(["alpha", " beta", "STOP", " gamma"])
+            .with_reply(["x", "y", "z"]);
+        let session_id = SessionId::new();
+        let requests = [
+            GenerationRequest::new(session_id),
+            GenerationRequest::new(session_id).with_stop_tokens(vec!["STOP".to_string()]),
+            GenerationRequest::new(session_id).with_max_tokens(2),
+        ];
+
+        let outcomes: Vec<(String, u32, FinishReason)> = generate_group(&model, &requests)
+            .into_iter()
+            .map(|response| {
+                (
+                    response.generated_text,
+                    response.tokens_generated,
+                    response.finish_reason,
+                )
+            })
+            .collect();
+        let stopped = |reason: &str| FinishReason::Stopped(reason.to_string());
+        assert_eq!(
+            outcomes,
+            vec![
+                (
+                    "one two three".to_string(),
+                    3,
+                    stopped("End of sequence token detected")
+                ),
+                (
+                    "alpha betaSTOP".to_string(),
+                    3,
+                    stopped("Stop token detected")
+                ),
+                ("xy".to_string(), 2, stopped("Maximum tokens reached")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_grouped_requests_take_turns() {
+        let model = FakeModel::new()
+            .with_reply(["a", "b", "c", "d"])
+            .with_reply(["e"])
+            .with_reply(["f", "g"]);
+        let session_id = SessionId::new();
+        let requests = vec![GenerationRequest::new(session_id); 3];
+        generate_group(&model, &requests);
+
+        // Every unfinished sequence samples one token per step, so a long reply
+        // does not hold up the others
+        assert_eq!(model.batch_samples(), vec![0, 1, 2, 0, 1, 2, 0, 2, 0, 0]);
+    }
+
+    /// Generate `n` completions of `request` on `model`
+    fn generate_completions(
+        model: &FakeModel,
+        request: &GenerationRequest,
+        n: usize,
+        cancellation_token: &CancellationToken,
+    ) -> GenerationResponse {
+        let session = create_test_session();
+        let model_config = create_test_model_config();
+        let chat_template = ChatTemplateEngine::new();
+        let metrics = QueueMetrics::new();
+        let job = GenerationJob {
+            worker_id: 0,
+            request_id: "0".to_string(),
+            request,
+            session: &session,
+            model_config: &model_config,
+            batch_size: 512,
+            cancellation_token,
+            chat_template: &chat_template,
+            max_conversion_failures: 8,
+            metrics: &metrics,
+        };
+
+        let prompt = job
+            .tokenize_prompt(|prompt| model.tokenize(prompt))
+            .unwrap();
+        let mut backend = model.create_batch_backend(n).unwrap();
+        RequestQueue::process_multi_sequence_request_sync(&job, &prompt, n, &mut *backend).unwrap()
+    }
+
+    #[test]
+    fn test_completions_finish_independently() {
+        let model = FakeModel::new()
+            .with_reply(["t1"])
+            .with_reply(["t2", "t3", "t4"])
+            .with_reply(["t5"]);
+        let request = GenerationRequest::new(SessionId::new()).with_debug_tokens(true);
+        let response = generate_completions(&model, &request, 3, &CancellationToken::new());
+
+        let texts: Vec<_> = response
+            .candidates
+            .iter()
+            .map(|c| c.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["t1", "t2t3t4", "t5"]);
+        let counts: Vec<_> = response
+            .candidates
+            .iter()
+            .map(|c| c.tokens_generated)
+            .collect();
+        assert_eq!(counts, vec![1, 3, 1]);
+        assert!(response.candidates.iter().all(|c| c.finish_reason
+            == FinishReason::Stopped("End of sequence token detected".to_string())));
+
+        // Finished sequences drop out, the others keep sampling one token per step
+        assert_eq!(model.batch_samples(), vec![0, 1, 2, 0, 1, 2, 1, 1]);
+
+        // The response itself reports the first completion
+        assert_eq!(response.generated_text, "t1");
+        assert_eq!(response.tokens_generated, 1);
+        assert!(response.time_to_first_token.is_some());
+        let ids: Vec<u32> = response.generated_tokens.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![model.token_id("t1").unwrap()]);
+    }
+
+    #[test]
+    fn test_completions_stop_conditions() {
+        let model = FakeModel::new()
+            .with_reply(["a", "STOP", "b"])
+            .with_reply(["c"])
+            .with_reply(["d", "e", "f", "g"]);
+        let request = GenerationRequest::new(SessionId::new())
+            .with_max_tokens(3)
+            .with_stop_tokens(vec!["STOP".to_string()]);
+        let candidates =
+            generate_completions(&model, &request, 3, &CancellationToken::new()).candidates;
+
+        let stopped = |reason: &str| FinishReason::Stopped(reason.to_string());
+        assert_eq!(candidates[0].text, "aSTOP");
+        assert_eq!(candidates[0].finish_reason, stopped("Stop token detected"));
+        assert_eq!(candidates[1].text, "c");
+        assert_eq!(
+            candidates[1].finish_reason,
+            stopped("End of sequence token detected")
+        );
+        assert_eq!(candidates[2].text, "def");
+        assert_eq!(
+            candidates[2].finish_reason,
+            stopped("Maximum tokens reached")
+        );
+    }
+
+    #[test]
+    fn test_completions_prefill_only() {
+        let model = FakeModel::new().with_reply(["a"]).with_reply(["b"]);
+        let request = GenerationRequest::new(SessionId::new()).with_max_tokens(0);
+        let response = generate_completions(&model, &request, 2, &CancellationToken::new());
+
+        assert_eq!(model.tokens_sampled(), 0);
+        assert!(response.candidates.iter().all(|c| c.text.is_empty()
+            && c.finish_reason == FinishReason::Stopped("Prefill only".to_string())));
+    }
+
+    #[test]
+    fn test_completions_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let model = FakeModel::new().with_reply(["a"]).with_reply(["b"]);
+        let request = GenerationRequest::new(SessionId::new());
+        let response = generate_completions(&model, &request, 2, &token);
+
+        assert_eq!(model.tokens_sampled(), 0);
+        assert!(response.candidates.iter().all(|c| c.tokens_generated == 0
+            && c.finish_reason == FinishReason::Stopped("Error: Request cancelled".to_string())));
+    }
+
+    #[test]
+    fn test_sequence_count() {
+        let mut request = GenerationRequest::new(SessionId::new());
+        assert_eq!(sequence_count(&request, 4).unwrap(), 1);
+
+        request = request.with_n(4);
+        assert_eq!(sequence_count(&request, 4).unwrap(), 4);
+
+        request.n = Some(5);
+        let error = sequence_count(&request, 4).unwrap_err().to_string();
+        assert!(error.contains("max_sequences_per_request (4)"));
+
+        request.n = Some(0);
+        assert!(sequence_count(&request, 4).is_err());
+    }
+
+    #[test]
+    fn test_estimated_wait() {
+        let metrics = QueueMetrics::for_workers(2);
+        // Nothing has completed yet, so there is no average to go on
+        metrics.record_request_submitted();
+        assert_eq!(metrics.get_stats().estimated_wait(2), None);
+
+        metrics.record_request_completed(Duration::from_millis(3000), 10);
+        for _ in 0..8 {
+            metrics.record_request_submitted();
+        }
+        let stats = metrics.get_stats();
+        assert_eq!(stats.current_queue_size, 8);
+        // 3s per request, 8 requests over 2 workers
+        assert_eq!(stats.estimated_wait(2), Some(Duration::from_secs(12)));
+        assert_eq!(stats.estimated_wait(1), Some(Duration::from_secs(24)));
+        // A queue without workers is treated as having one
+        assert_eq!(stats.estimated_wait(0), Some(Duration::from_secs(24)));
+    }
+
+    #[test]
+    fn test_respawn_limit() {
+        let limiter = RespawnLimiter::default();
+        let start = Instant::now();
+        for i in 0..MAX_WORKER_RESPAWNS {
+            assert!(limiter.allow(start + Duration::from_secs(i as u64)));
+        }
+        assert!(!limiter.allow(start + Duration::from_secs(10)));
+        // The first respawn has left the window
+        assert!(limiter.allow(start + WORKER_RESPAWN_WINDOW));
+        assert!(!limiter.allow(start + WORKER_RESPAWN_WINDOW));
+    }
+
+    #[test]
+    fn test_queue_full_error_message() {
+        let error = QueueError::Full {
+            current_depth: 5,
+            max_depth: 5,
+            estimated_wait: Some(Duration::from_millis(11_200)),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Queue is full (5 of 5 requests queued), try again in ~12s"
+        );
+
+        let error = QueueError::Full {
+            current_depth: 5,
+            max_depth: 5,
+            estimated_wait: None,
+        };
+        assert_eq!(error.to_string(), "Queue is full (5 of 5 requests queued)");
+    }
+
     #[test]
     fn test_queued_request_debug() {
         let (sender, _) = oneshot::channel();
         let session = create_test_session();
+        let ticket = RequestTicket::new();
         let request = QueuedRequest {
-            id: "test-123".to_string(),
+            ticket: ticket.clone(),
             request: GenerationRequest {
                 session_id: session.id.clone(),
                 max_tokens: Some(100),
                 temperature: Some(0.7),
                 top_p: Some(0.9),
+                seed: None,
                 stop_tokens: Vec::new(),
+                stop_token_ids: Vec::new(),
+                n: None,
+                append_to_session: None,
+                chunking: StreamChunking::Token,
+                block_on_full: false,
+                model: None,
+                max_duration: None,
                 stopping_config: None,
             },
-            session,
+            session: Arc::new(session),
             response_sender: sender,
             stream_sender: None,
             submitted_at: Instant::now(),
@@ -1411,6 +3802,230 @@ mod tests {
         };
 
         let debug_str = format!("{:?}", request);
-        assert!(debug_str.contains("test-123"));
+        assert!(debug_str.contains(ticket.id()));
+    }
+
+    fn stream_chunk(text: &str, is_complete: bool) -> Result<StreamChunk, QueueError> {
+        Ok(StreamChunk {
+            text: text.to_string(),
+            is_complete,
+            token_count: 1,
+            response: None,
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
+        })
+    }
+
+    /// A stream fed `chunks`, with the sender kept open like a running worker
+    async fn scripted_stream(
+        chunks: Vec<Result<StreamChunk, QueueError>>,
+    ) -> (
+        RequestStream,
+        mpsc::Sender<Result<StreamChunk, QueueError>>,
+        CancellationToken,
+    ) {
+        let (sender, receiver) = mpsc::channel(chunks.len().max(1));
+        for chunk in chunks {
+            sender.send(chunk).await.unwrap();
+        }
+        let token = CancellationToken::new();
+        (RequestStream::new(receiver, token.clone()), sender, token)
+    }
+
+    #[tokio::test]
+    async fn test_dropped_stream_cancels_request() {
+        use futures::StreamExt;
+
+        let metrics = QueueMetrics::new();
+        metrics.record_request_submitted();
+        let (mut stream, _sender, token) = scripted_stream(vec![
+            stream_chunk("a", false),
+            stream_chunk("b", false),
+            stream_chunk("c", false),
+        ])
+        .await;
+
+        assert_eq!(stream.next().await.unwrap().unwrap().text, "a");
+        assert_eq!(stream.next().await.unwrap().unwrap().text, "b");
+        drop(stream);
+        assert!(token.is_cancelled());
+
+        record_streaming_outcome(&metrics, &token, Duration::from_millis(5));
+        let stats = metrics.get_stats();
+        assert_eq!(stats.cancelled_requests, 1);
+        assert_eq!(stats.completed_requests, 0);
+        assert_eq!(stats.current_queue_size, 0);
+    }
+
+    #[tokio::test]
+    async fn test_finished_stream_is_not_cancelled() {
+        let metrics = QueueMetrics::new();
+        metrics.record_request_submitted();
+        let (mut stream, _sender, token) =
+            scripted_stream(vec![stream_chunk("a", false), stream_chunk("", true)]).await;
+
+        while let Some(chunk) = stream.recv().await {
+            if chunk.unwrap().is_complete {
+                break;
+            }
+        }
+        drop(stream);
+        assert!(!token.is_cancelled());
+
+        record_streaming_outcome(&metrics, &token, Duration::from_millis(5));
+        let stats = metrics.get_stats();
+        assert_eq!(stats.cancelled_requests, 0);
+        assert_eq!(stats.completed_requests, 1);
+
+        // An error also ends the stream
+        let (mut stream, _sender, token) =
+            scripted_stream(vec![Err(QueueError::WorkerError("failed".to_string()))]).await;
+        assert!(stream.recv().await.unwrap().is_err());
+        drop(stream);
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn test_generation_timing_averages() {
+        let metrics = QueueMetrics::new();
+        let stats = metrics.get_stats();
+        assert_eq!(stats.average_prompt_time, Duration::ZERO);
+        assert_eq!(stats.average_decode_time, Duration::ZERO);
+
+        metrics.record_generation_timing(Duration::from_millis(10), Duration::from_millis(100));
+        metrics.record_generation_timing(Duration::from_millis(30), Duration::ZERO);
+        let stats = metrics.get_stats();
+        assert_eq!(stats.average_prompt_time, Duration::from_millis(20));
+        assert_eq!(stats.average_decode_time, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_worker_utilization() {
+        let metrics = QueueMetrics::for_workers(2);
+        metrics.record_worker_busy(1, Duration::from_secs(3600));
+        assert_eq!(metrics.get_stats().worker_utilization, vec![0.0, 1.0]);
+
+        // Workers beyond the configured count are added as they report
+        metrics.record_worker_busy(3, Duration::ZERO);
+        assert_eq!(metrics.get_stats().worker_utilization.len(), 4);
+    }
+
+    #[test]
+    fn test_send_chunk_waits_for_reader() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        assert!(send_chunk(&sender, stream_chunk("a", false)));
+
+        // The buffer is full; the send completes once the reader catches up
+        let reader = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            let first = receiver.blocking_recv().unwrap().unwrap().text;
+            let second = receiver.blocking_recv().unwrap().unwrap().text;
+            (first, second)
+        });
+        assert!(send_chunk(&sender, stream_chunk("b", false)));
+        assert_eq!(reader.join().unwrap(), ("a".to_string(), "b".to_string()));
+
+        // The reader is gone
+        assert!(!send_chunk(&sender, stream_chunk("c", false)));
+    }
+
+    #[tokio::test]
+    async fn test_submission_shares_large_session() {
+        const REQUESTS: u32 = 200;
+        let model_manager = setup_loaded_model_manager().await;
+        let queue = RequestQueue::new(model_manager, create_test_queue_config());
+        let session = Arc::new(create_large_session(200));
+        let request = || GenerationRequest::new(session.id).with_max_tokens(16);
+
+        // Requests share the caller's snapshot
+        let start = Instant::now();
+        for _ in 0..REQUESTS {
+            let _ = queue
+                .submit_streaming_request(request(), Arc::clone(&session))
+                .await;
+        }
+        let shared = start.elapsed();
+
+        // A deep copy per request, as QueuedRequest used to hold
+        let start = Instant::now();
+        for _ in 0..REQUESTS {
+            let _ = queue
+                .submit_streaming_request(request(), Arc::new(Session::clone(&session)))
+                .await;
+        }
+        let copied = start.elapsed();
+
+        info!(
+            "Submitted {} requests for a 200-message session: {:?} shared, {:?} copied",
+            REQUESTS, shared, copied
+        );
+        assert!(
+            shared < copied,
+            "Sharing the session ({:?}) should be cheaper than copying it ({:?})",
+            shared,
+            copied
+        );
+        // Queued requests never modify the snapshot
+        assert_eq!(session.messages.len(), 200);
+    }
+
+    /// Mirror of the generation loop: `converts(iteration)` says whether that
+    /// iteration's token converts to text. Returns the tokens generated and
+    /// the finish reason if the budget stopped the loop.
+    fn run_budgeted_loop(
+        max_tokens: u32,
+        max_failures: u32,
+        converts: impl Fn(u64) -> bool,
+    ) -> (u32, Option<String>) {
+        let mut budget = GenerationBudget::new(max_tokens, max_failures);
+        let mut tokens_generated = 0;
+        let mut iteration = 0;
+        while tokens_generated < max_tokens {
+            if let Some(reason) = budget.next_iteration() {
+                return (tokens_generated, Some(reason));
+            }
+            iteration += 1;
+            if converts(iteration) {
+                budget.record_conversion_success();
+                tokens_generated += 1;
+            } else if let Some(reason) = budget.record_conversion_failure(42) {
+                return (tokens_generated, Some(reason));
+            }
+        }
+        (tokens_generated, None)
+    }
+
+    #[test]
+    fn test_generation_budget_stops_repeated_conversion_failures() {
+        let (tokens, reason) = run_budgeted_loop(512, 8, |_| false);
+        assert_eq!(tokens, 0);
+        assert_eq!(
+            reason.as_deref(),
+            Some("Error: Token conversion failed 8 times in a row (token id 42)")
+        );
+
+        // Failures after some output are still caught
+        let (tokens, reason) = run_budgeted_loop(512, 3, |i| i <= 5);
+        assert_eq!(tokens, 5);
+        assert!(reason.unwrap().contains("3 times in a row"));
+    }
+
+    #[test]
+    fn test_generation_budget_caps_iterations() {
+        // Failures never reach the consecutive threshold, but the loop still ends
+        let (tokens, reason) = run_budgeted_loop(10, 100, |i| i % 10 == 0);
+        assert!(tokens < 10);
+        assert_eq!(
+            reason.as_deref(),
+            Some("Error: Generation iteration limit reached (36 token conversion failures)")
+        );
+    }
+
+    #[test]
+    fn test_generation_budget_tolerates_occasional_failures() {
+        // Every third token fails to convert
+        let (tokens, reason) = run_budgeted_loop(20, 2, |i| i % 3 != 0);
+        assert_eq!(tokens, 20);
+        assert_eq!(reason, None);
     }
 }
@@ -0,0 +1,324 @@
+//! Admission control for generation requests
+//!
+//! Each request is checked against the session's in-flight cap and request rate and
+//! against a rate shared by all sessions before it reaches the queue. Rates are token
+//! buckets refilled from a [`Clock`], so limits can be tested with
+//! [`MockClock`](crate::test_support::MockClock).
+
+use crate::clock::Clock;
+use crate::types::{AgentError, LimitsConfig, RateLimit, SessionId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// Bucket of request tokens refilled continuously up to its capacity
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    updated_at: SystemTime,
+}
+
+impl TokenBucket {
+    /// A full bucket refilling at `requests_per_minute`, holding `burst` requests
+    /// or, by default, a minute's worth
+    fn new(requests_per_minute: u32, burst: Option<u32>, now: SystemTime) -> Self {
+        let capacity = burst.unwrap_or(requests_per_minute) as f64;
+        Self {
+            capacity,
+            per_second: requests_per_minute as f64 / 60.0,
+            tokens: capacity,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: SystemTime) {
+        // A clock that moved backwards adds nothing
+        if let Ok(elapsed) = now.duration_since(self.updated_at) {
+            self.tokens =
+                (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.capacity);
+            self.updated_at = now;
+        }
+    }
+
+    /// Time until a token is available, zero if one is available now
+    fn wait(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.per_second)
+        }
+    }
+
+    fn is_full(&self, now: SystemTime) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(now);
+        bucket.tokens >= bucket.capacity
+    }
+}
+
+#[derive(Debug)]
+struct SessionState {
+    bucket: Option<TokenBucket>,
+    in_flight: u32,
+}
+
+impl SessionState {
+    /// Whether forgetting the session would change nothing
+    fn is_idle(&self, now: SystemTime) -> bool {
+        self.in_flight == 0
+            && self
+                .bucket
+                .as_ref()
+                .is_none_or(|bucket| bucket.is_full(now))
+    }
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    global: Option<TokenBucket>,
+    sessions: HashMap<SessionId, SessionState>,
+}
+
+/// Enforces a [`LimitsConfig`] on generation requests; clones share their state
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: LimitsConfig,
+    clock: Arc<dyn Clock>,
+    state: Arc<Mutex<LimiterState>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: LimitsConfig, clock: Arc<dyn Clock>) -> Self {
+        let global = config
+            .global_requests_per_minute
+            .map(|rate| TokenBucket::new(rate, config.global_burst, clock.now()));
+        Self {
+            config,
+            clock,
+            state: Arc::new(Mutex::new(LimiterState {
+                global,
+                sessions: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Admit a request for `session_id`, or report the limit it exceeds.
+    ///
+    /// The request counts as in flight until the returned permit is dropped. A rejected
+    /// request uses up nothing.
+    pub fn admit(&self, session_id: &SessionId) -> Result<AdmissionPermit, AgentError> {
+        let now = self.clock.now();
+        let mut state = lock(&self.state);
+        state.sessions.retain(|_, session| !session.is_idle(now));
+
+        let LimiterState { global, sessions } = &mut *state;
+        let session = sessions.entry(*session_id).or_insert_with(|| SessionState {
+            bucket: self
+                .config
+                .session_requests_per_minute
+                .map(|rate| TokenBucket::new(rate, self.config.session_burst, now)),
+            in_flight: 0,
+        });
+
+        if let Some(max_in_flight) = self.config.session_max_in_flight {
+            if session.in_flight >= max_in_flight {
+                return Err(AgentError::RateLimited {
+                    limit: RateLimit::SessionInFlight,
+                    retry_after: None,
+                });
+            }
+        }
+        for (bucket, limit) in [
+            (session.bucket.as_mut(), RateLimit::SessionRate),
+            (global.as_mut(), RateLimit::GlobalRate),
+        ] {
+            if let Some(bucket) = bucket {
+                bucket.refill(now);
+                let wait = bucket.wait();
+                if !wait.is_zero() {
+                    return Err(AgentError::RateLimited {
+                        limit,
+                        retry_after: Some(wait),
+                    });
+                }
+            }
+        }
+
+        for bucket in [session.bucket.as_mut(), global.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            bucket.tokens -= 1.0;
+        }
+        session.in_flight += 1;
+
+        Ok(AdmissionPermit {
+            state: self.state.clone(),
+            session_id: *session_id,
+        })
+    }
+
+    /// Requests of `session_id` currently holding a permit
+    pub fn in_flight(&self, session_id: &SessionId) -> u32 {
+        lock(&self.state)
+            .sessions
+            .get(session_id)
+            .map_or(0, |session| session.in_flight)
+    }
+}
+
+fn lock(state: &Mutex<LimiterState>) -> MutexGuard<'_, LimiterState> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// An admitted request; releases its in-flight slot when dropped
+#[derive(Debug)]
+pub struct AdmissionPermit {
+    state: Arc<Mutex<LimiterState>>,
+    session_id: SessionId,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        if let Some(session) = lock(&self.state).sessions.get_mut(&self.session_id) {
+            session.in_flight = session.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockClock;
+
+    fn limiter(config: LimitsConfig) -> (RateLimiter, MockClock) {
+        let clock = MockClock::default();
+        (RateLimiter::new(config, Arc::new(clock.clone())), clock)
+    }
+
+    fn rejected_by(result: Result<AdmissionPermit, AgentError>) -> (RateLimit, Option<Duration>) {
+        match result {
+            Err(AgentError::RateLimited { limit, retry_after }) => (limit, retry_after),
+            other => panic!("Expected rate limit rejection, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let (limiter, _clock) = limiter(LimitsConfig::default());
+        let session = SessionId::new();
+
+        let permits: Vec<_> = (0..100).map(|_| limiter.admit(&session).unwrap()).collect();
+        assert_eq!(limiter.in_flight(&session), 100);
+        drop(permits);
+        assert_eq!(limiter.in_flight(&session), 0);
+    }
+
+    #[test]
+    fn test_session_burst_then_refill() {
+        let (limiter, clock) = limiter(LimitsConfig {
+            session_requests_per_minute: Some(60),
+            session_burst: Some(3),
+            ..Default::default()
+        });
+        let session = SessionId::new();
+
+        for _ in 0..3 {
+            limiter.admit(&session).unwrap();
+        }
+        let (limit, retry_after) = rejected_by(limiter.admit(&session));
+        assert_eq!(limit, RateLimit::SessionRate);
+        assert_eq!(retry_after, Some(Duration::from_secs(1)));
+
+        // Other sessions have their own bucket
+        assert!(limiter.admit(&SessionId::new()).is_ok());
+
+        clock.advance(Duration::from_millis(500));
+        let (_, retry_after) = rejected_by(limiter.admit(&session));
+        assert_eq!(retry_after, Some(Duration::from_millis(500)));
+
+        clock.advance(Duration::from_millis(500));
+        assert!(limiter.admit(&session).is_ok());
+    }
+
+    #[test]
+    fn test_steady_state_rate() {
+        let (limiter, clock) = limiter(LimitsConfig {
+            session_requests_per_minute: Some(120),
+            session_burst: Some(1),
+            ..Default::default()
+        });
+        let session = SessionId::new();
+
+        // One request every 500ms is sustained indefinitely, anything faster is not
+        for _ in 0..10 {
+            assert!(limiter.admit(&session).is_ok());
+            clock.advance(Duration::from_millis(250));
+            assert!(limiter.admit(&session).is_err());
+            clock.advance(Duration::from_millis(250));
+        }
+
+        // Idle time refills the bucket only up to the burst size
+        clock.advance(Duration::from_secs(60));
+        assert!(limiter.admit(&session).is_ok());
+        assert!(limiter.admit(&session).is_err());
+    }
+
+    #[test]
+    fn test_session_in_flight_cap() {
+        let (limiter, _clock) = limiter(LimitsConfig {
+            session_max_in_flight: Some(2),
+            ..Default::default()
+        });
+        let session = SessionId::new();
+
+        let first = limiter.admit(&session).unwrap();
+        let _second = limiter.admit(&session).unwrap();
+        let (limit, retry_after) = rejected_by(limiter.admit(&session));
+        assert_eq!(limit, RateLimit::SessionInFlight);
+        assert_eq!(retry_after, None);
+
+        drop(first);
+        assert!(limiter.admit(&session).is_ok());
+    }
+
+    #[test]
+    fn test_global_rate_shared_between_sessions() {
+        let (limiter, clock) = limiter(LimitsConfig {
+            session_requests_per_minute: Some(60),
+            global_requests_per_minute: Some(30),
+            global_burst: Some(2),
+            ..Default::default()
+        });
+        let (a, b) = (SessionId::new(), SessionId::new());
+
+        limiter.admit(&a).unwrap();
+        limiter.admit(&b).unwrap();
+        let (limit, retry_after) = rejected_by(limiter.admit(&a));
+        assert_eq!(limit, RateLimit::GlobalRate);
+        assert_eq!(retry_after, Some(Duration::from_secs(2)));
+
+        // The rejection did not use up a token of the session's own bucket
+        clock.advance(Duration::from_secs(2));
+        assert!(limiter.admit(&a).is_ok());
+    }
+
+    #[test]
+    fn test_idle_sessions_are_forgotten() {
+        let (limiter, clock) = limiter(LimitsConfig {
+            session_requests_per_minute: Some(60),
+            ..Default::default()
+        });
+        let session = SessionId::new();
+
+        drop(limiter.admit(&session).unwrap());
+        clock.advance(Duration::from_secs(1));
+        limiter.admit(&SessionId::new()).unwrap();
+
+        assert!(!lock(&limiter.state).sessions.contains_key(&session));
+    }
+}
@@ -0,0 +1,129 @@
+//! How prompt and response content appears in logs and the audit log
+//!
+//! Log statements that include prompts, message content, generated text, tool
+//! arguments or tool results wrap that text in [`redact`], which formats it through
+//! the process-wide [`RedactionPolicy`]. `AgentServer` installs the policy from
+//! `AgentConfig::redaction` when it is created, and applies it to the audit log too.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Rewrites text before it is logged or written to the audit log, e.g. to scrub secrets
+pub type Redactor = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// What of prompt and response content reaches logs and the audit log
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionPolicy {
+    /// Content is replaced by `[redacted]`
+    LogNothing,
+    /// Content is replaced by its length, e.g. `[42 chars]`; recommended when prompts
+    /// may contain personal data
+    LogLengthsOnly,
+    /// Content is logged as is
+    #[default]
+    LogFull,
+    /// Content is replaced by what the function returns; set in code only
+    #[serde(skip)]
+    Custom(Redactor),
+}
+
+impl fmt::Debug for RedactionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LogNothing => f.write_str("LogNothing"),
+            Self::LogLengthsOnly => f.write_str("LogLengthsOnly"),
+            Self::LogFull => f.write_str("LogFull"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+impl RedactionPolicy {
+    /// `text` as this policy allows it to be recorded
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Self::LogNothing => "[redacted]".to_string(),
+            Self::LogLengthsOnly => format!("[{} chars]", text.chars().count()),
+            Self::LogFull => text.to_string(),
+            Self::Custom(redactor) => redactor(text),
+        }
+    }
+
+    /// Whether content is recorded unchanged
+    pub fn is_full(&self) -> bool {
+        matches!(self, Self::LogFull)
+    }
+
+    /// Wrap `content` so it is formatted through this policy
+    pub fn redact<'a, T: fmt::Display + ?Sized>(&self, content: &'a T) -> Redacted<'a, T> {
+        Redacted {
+            content,
+            policy: self.clone(),
+        }
+    }
+}
+
+static POLICY: RwLock<RedactionPolicy> = RwLock::new(RedactionPolicy::LogFull);
+
+/// Apply `policy` to every log statement that includes content, from now on
+pub fn set_policy(policy: RedactionPolicy) {
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = policy;
+}
+
+/// The policy log statements currently apply
+pub fn policy() -> RedactionPolicy {
+    POLICY.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Wrap `content` for a log statement so it is formatted through the current policy
+pub fn redact<T: fmt::Display + ?Sized>(content: &T) -> Redacted<'_, T> {
+    policy().redact(content)
+}
+
+/// Content formatted through a [`RedactionPolicy`]; see [`redact`]
+pub struct Redacted<'a, T: ?Sized> {
+    content: &'a T,
+    policy: RedactionPolicy,
+}
+
+impl<T: fmt::Display + ?Sized> fmt::Display for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.policy.is_full() {
+            self.content.fmt(f)
+        } else {
+            f.write_str(&self.policy.apply(&self.content.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies_format_content() {
+        let text = "call me on 555-0100";
+        assert_eq!(RedactionPolicy::LogNothing.apply(text), "[redacted]");
+        assert_eq!(RedactionPolicy::LogLengthsOnly.apply(text), "[19 chars]");
+        assert_eq!(RedactionPolicy::LogFull.apply(text), text);
+        let custom = RedactionPolicy::Custom(Arc::new(|text| text.replace("555-0100", "***")));
+        assert_eq!(custom.apply(text), "call me on ***");
+
+        let arguments = serde_json::json!({"phone": "555-0100"});
+        let logged = format!("{}", RedactionPolicy::LogLengthsOnly.redact(&arguments));
+        assert_eq!(logged, "[20 chars]");
+        assert_eq!(
+            format!("{}", RedactionPolicy::LogFull.redact(&arguments)),
+            arguments.to_string()
+        );
+    }
+
+    #[test]
+    fn test_policy_config_names() {
+        let policy: RedactionPolicy = serde_json::from_str("\"log_lengths_only\"").unwrap();
+        assert!(matches!(policy, RedactionPolicy::LogLengthsOnly));
+        assert!(serde_json::from_str::<RedactionPolicy>("\"custom\"").is_err());
+    }
+}
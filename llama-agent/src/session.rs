@@ -1,13 +1,26 @@
-use crate::types::{Message, Session, SessionConfig, SessionError, SessionId};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::clock::{Clock, SystemClock};
+use crate::types::{
+    Message, MessageRole, Session, SessionConfig, SessionError, SessionEvictionPolicy,
+    SessionFilter, SessionId, SessionSummary, SessionUsage, ToolDefinition, ToolPolicy,
+};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
 
+/// Number of requests in progress for each session that has any
+type InFlightRegistry = Arc<Mutex<HashMap<SessionId, usize>>>;
+
 pub struct SessionManager {
     sessions: Arc<RwLock<HashMap<SessionId, Session>>>,
     config: SessionConfig,
+    clock: Arc<dyn Clock>,
+    audit_log: Option<AuditLog>,
+    in_flight: InFlightRegistry,
+    evicted: AtomicU64,
 }
 
 impl SessionManager {
@@ -15,33 +28,185 @@ impl SessionManager {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
             config,
+            clock: Arc::new(SystemClock),
+            audit_log: None,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            evicted: AtomicU64::new(0),
         }
     }
 
-    pub async fn create_session(&self) -> Result<Session, SessionError> {
-        let mut sessions = self.sessions.write().await;
+    /// Use `clock` for session and message timestamps and for expiry
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-        // Check if we've reached the session limit
-        if sessions.len() >= self.config.max_sessions {
-            warn!("Session limit reached: {}", self.config.max_sessions);
-            return Err(SessionError::LimitExceeded);
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Record session creation and deletion and every stored message in `audit_log`
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit_log.as_ref()
+    }
+
+    /// Record an event in the audit log, if there is one
+    pub fn audit(&self, event: AuditEvent) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(self.clock.now(), event);
+        }
+    }
+
+    fn audit_messages(&self, session_id: &SessionId, messages: &[Message]) {
+        if self.audit_log.is_some() {
+            for message in messages {
+                self.audit(AuditEvent::message_added(*session_id, message));
+            }
         }
+    }
+
+    /// Current time according to the session clock
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
 
-        let now = SystemTime::now();
+    /// Whether a session was last updated longer ago than the session timeout
+    fn is_expired(&self, session: &Session) -> bool {
+        match self.clock.now().duration_since(session.updated_at) {
+            Ok(age) => age > self.config.session_timeout,
+            Err(_) => false,
+        }
+    }
+
+    /// Mark a session as having a request in progress until the guard is dropped.
+    ///
+    /// Sessions with a request in progress are never evicted, expired or deleted.
+    pub fn begin_request(&self, session_id: SessionId) -> InFlightGuard {
+        *lock(&self.in_flight).entry(session_id).or_insert(0) += 1;
+        InFlightGuard {
+            registry: self.in_flight.clone(),
+            session_id,
+        }
+    }
+
+    /// Whether a session has a request in progress
+    pub fn is_in_flight(&self, session_id: &SessionId) -> bool {
+        lock(&self.in_flight).contains_key(session_id)
+    }
+
+    /// Sessions removed to make room for new ones since the manager was created
+    pub fn evicted_count(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    pub async fn create_session(&self) -> Result<Session, SessionError> {
+        Ok(self.create_session_evicting().await?.0)
+    }
+
+    /// Create a session, also returning the session evicted to make room for it, if any
+    pub async fn create_session_evicting(
+        &self,
+    ) -> Result<(Session, Option<SessionId>), SessionError> {
+        let mut sessions = self.sessions.write().await;
+        let evicted = self.make_room(&mut sessions)?;
+
+        let now = self.clock.now();
+        let messages = self
+            .config
+            .system_prompt
+            .iter()
+            .map(|prompt| system_message(prompt, now))
+            .collect();
         let session = Session {
             id: SessionId::new(),
-            messages: Vec::new(),
+            messages,
             mcp_servers: Vec::new(),
             available_tools: Vec::new(),
             available_prompts: Vec::new(),
             created_at: now,
             updated_at: now,
+            tool_policy: self.config.default_tool_policy.clone(),
+            usage: SessionUsage::default(),
         };
 
         info!("Created new session: {}", session.id);
         sessions.insert(session.id, session.clone());
+        self.audit(AuditEvent::SessionCreated {
+            session_id: session.id,
+        });
+
+        Ok((session, evicted))
+    }
+
+    /// Put back a session saved earlier, e.g. from a checkpoint, keeping its id and
+    /// messages. It replaces a session with the same id and counts as updated now, so it
+    /// does not expire straight away. Also returns the session evicted to make room for it.
+    pub async fn restore_session(
+        &self,
+        mut session: Session,
+    ) -> Result<(Session, Option<SessionId>), SessionError> {
+        let mut sessions = self.sessions.write().await;
+        let evicted = if sessions.contains_key(&session.id) {
+            None
+        } else {
+            self.make_room(&mut sessions)?
+        };
+
+        session.updated_at = self.clock.now();
+        info!(
+            "Restored session {} with {} messages",
+            session.id,
+            session.messages.len()
+        );
+        sessions.insert(session.id, session.clone());
+        self.audit(AuditEvent::SessionCreated {
+            session_id: session.id,
+        });
+
+        Ok((session, evicted))
+    }
+
+    /// Make room for one more session under the session limit, evicting one if the
+    /// policy allows; returns the evicted session
+    fn make_room(
+        &self,
+        sessions: &mut HashMap<SessionId, Session>,
+    ) -> Result<Option<SessionId>, SessionError> {
+        if sessions.len() < self.config.max_sessions {
+            return Ok(None);
+        }
+        let evicted = match self.config.eviction_policy {
+            SessionEvictionPolicy::Reject => None,
+            SessionEvictionPolicy::EvictLru => self.evict_lru(sessions),
+        };
+        if evicted.is_none() {
+            warn!("Session limit reached: {}", self.config.max_sessions);
+            return Err(SessionError::LimitExceeded);
+        }
+        Ok(evicted)
+    }
 
-        Ok(session)
+    /// Remove the least recently updated session without a request in progress
+    /// (the oldest by id among equally recent ones)
+    fn evict_lru(&self, sessions: &mut HashMap<SessionId, Session>) -> Option<SessionId> {
+        let in_flight = lock(&self.in_flight);
+        let session_id = sessions
+            .values()
+            .filter(|session| !in_flight.contains_key(&session.id))
+            .min_by(|a, b| a.updated_at.cmp(&b.updated_at).then(a.id.cmp(&b.id)))
+            .map(|session| session.id)?;
+        drop(in_flight);
+
+        sessions.remove(&session_id);
+        self.evicted.fetch_add(1, Ordering::Relaxed);
+        info!("Evicted least recently used session: {}", session_id);
+        self.audit(AuditEvent::SessionEvicted { session_id });
+        Some(session_id)
     }
 
     pub async fn get_session(
@@ -53,11 +218,9 @@ impl SessionManager {
         match sessions.get(session_id) {
             Some(session) => {
                 // Check if session has expired
-                if let Ok(age) = session.updated_at.elapsed() {
-                    if age > self.config.session_timeout {
-                        debug!("Session {} has expired (age: {:?})", session_id, age);
-                        return Ok(None);
-                    }
+                if self.is_expired(session) {
+                    debug!("Session {} has expired", session_id);
+                    return Ok(None);
                 }
                 Ok(Some(session.clone()))
             }
@@ -70,43 +233,184 @@ impl SessionManager {
         session_id: &SessionId,
         message: Message,
     ) -> Result<(), SessionError> {
-        let mut sessions = self.sessions.write().await;
+        self.add_messages(session_id, vec![message]).await
+    }
 
-        match sessions.get_mut(session_id) {
-            Some(session) => {
-                session.messages.push(message);
-                session.updated_at = SystemTime::now();
+    /// Append messages as one contiguous batch; other writers cannot interleave with it
+    pub async fn add_messages(
+        &self,
+        session_id: &SessionId,
+        messages: Vec<Message>,
+    ) -> Result<(), SessionError> {
+        self.mutate_session(session_id, |session, now| {
+            self.audit_messages(session_id, &messages);
+            append_to_session(session, messages, now);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Append messages only if the session still holds `expected_len` messages.
+    ///
+    /// Returns `SessionError::ConcurrentModification` when another writer got there first, so
+    /// the caller can reload the session and retry.
+    pub async fn compare_and_append(
+        &self,
+        session_id: &SessionId,
+        expected_len: usize,
+        messages: Vec<Message>,
+    ) -> Result<(), SessionError> {
+        self.mutate_session(session_id, |session, now| {
+            if session.messages.len() != expected_len {
                 debug!(
-                    "Added message to session {}, total messages: {}",
+                    "Session {} changed concurrently: expected {} messages, found {}",
                     session_id,
+                    expected_len,
                     session.messages.len()
                 );
-                Ok(())
+                return Err(SessionError::ConcurrentModification {
+                    session_id: session_id.to_string(),
+                    expected: expected_len,
+                    actual: session.messages.len(),
+                });
             }
-            None => Err(SessionError::NotFound(session_id.to_string())),
-        }
+            self.audit_messages(session_id, &messages);
+            append_to_session(session, messages, now);
+            Ok(())
+        })
+        .await
     }
 
+    /// Replace a whole session. Prefer the methods changing one part of it: they cannot
+    /// drop messages appended since `updated_session` was read.
     pub async fn update_session(&self, updated_session: Session) -> Result<(), SessionError> {
+        let session_id = updated_session.id;
+        self.mutate_session(&session_id, |session, _| {
+            *session = updated_session;
+            debug!("Updated session: {}", session_id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Record activity on a session without changing it, so it neither expires nor is
+    /// evicted before sessions used less recently. For callers that read a session and
+    /// then act on it elsewhere, such as generating without storing the reply.
+    pub async fn touch_session(&self, session_id: &SessionId) -> Result<(), SessionError> {
+        self.mutate_session(session_id, |_, _| Ok(())).await
+    }
+
+    /// Replace the tools a session can call
+    pub async fn set_available_tools(
+        &self,
+        session_id: &SessionId,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<(), SessionError> {
+        self.mutate_session(session_id, |session, _| {
+            session.available_tools = tools;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Replace the tool policy of a session
+    pub async fn set_tool_policy(
+        &self,
+        session_id: &SessionId,
+        policy: ToolPolicy,
+    ) -> Result<(), SessionError> {
+        self.mutate_session(session_id, |session, _| {
+            debug!("Session {} tool policy set to {}", session_id, policy);
+            session.tool_policy = policy;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Replace the session's leading System message, or insert one before the rest of
+    /// the history if it has none
+    pub async fn set_system_prompt(
+        &self,
+        session_id: &SessionId,
+        prompt: &str,
+    ) -> Result<(), SessionError> {
+        self.mutate_session(session_id, |session, now| {
+            match session.messages.first_mut() {
+                Some(first) if first.role == MessageRole::System => {
+                    first.content = prompt.to_string();
+                    first.attachments.clear();
+                }
+                first => {
+                    // Dated no later than the history it is placed before
+                    let timestamp = first
+                        .map_or(now, |message| message.timestamp)
+                        .max(session.created_at);
+                    session
+                        .messages
+                        .insert(0, system_message(prompt, timestamp));
+                }
+            }
+            debug!("Session {} system prompt set", session_id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Add a completed generation request to a session's token usage, returning the new
+    /// totals. Usage is bookkeeping, so unlike every other change it leaves the session's
+    /// `updated_at` alone.
+    pub async fn record_usage(
+        &self,
+        session_id: &SessionId,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) -> Result<SessionUsage, SessionError> {
         let mut sessions = self.sessions.write().await;
 
-        match sessions.get_mut(&updated_session.id) {
+        match sessions.get_mut(session_id) {
             Some(session) => {
-                *session = updated_session;
-                session.updated_at = SystemTime::now();
-                debug!("Updated session: {}", session.id);
-                Ok(())
+                session.usage.record(prompt_tokens, completion_tokens);
+                Ok(session.usage)
             }
-            None => Err(SessionError::NotFound(updated_session.id.to_string())),
+            None => Err(SessionError::NotFound(session_id.to_string())),
         }
     }
 
+    /// Apply `mutate` to a session, then record the change as activity at the current
+    /// time. Every change to a session except usage bookkeeping goes through here, so
+    /// `updated_at` is the time of the last change and never earlier than the session's
+    /// creation or messages. Nothing is recorded when `mutate` fails.
+    ///
+    /// `mutate` receives the time to stamp the change with.
+    async fn mutate_session<R>(
+        &self,
+        session_id: &SessionId,
+        mutate: impl FnOnce(&mut Session, SystemTime) -> Result<R, SessionError>,
+    ) -> Result<R, SessionError> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| SessionError::NotFound(session_id.to_string()))?;
+
+        let now = latest_activity(session, self.clock.now());
+        let result = mutate(session, now)?;
+        session.updated_at = latest_activity(session, now);
+        Ok(result)
+    }
+
+    /// Delete a session; fails with `SessionError::InUse` while it has a request in progress
     pub async fn delete_session(&self, session_id: &SessionId) -> Result<bool, SessionError> {
         let mut sessions = self.sessions.write().await;
+        if sessions.contains_key(session_id) && self.is_in_flight(session_id) {
+            return Err(SessionError::InUse(session_id.to_string()));
+        }
 
         match sessions.remove(session_id) {
             Some(_) => {
                 info!("Deleted session: {}", session_id);
+                self.audit(AuditEvent::SessionDeleted {
+                    session_id: *session_id,
+                });
                 Ok(true)
             }
             None => Ok(false),
@@ -118,37 +422,62 @@ impl SessionManager {
         Ok(sessions.keys().cloned().collect())
     }
 
+    /// Summaries of the unexpired sessions matching `filter`, ordered by `updated_at`
+    /// descending and then by id, newest first, with the filter's page applied
+    pub async fn query_sessions(&self, filter: &SessionFilter) -> Vec<SessionSummary> {
+        let sessions = self.sessions.read().await;
+        let mut summaries: Vec<SessionSummary> = sessions
+            .values()
+            .filter(|session| !self.is_expired(session) && filter.matches(session))
+            .map(SessionSummary::from)
+            .collect();
+        drop(sessions);
+
+        summaries.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then(b.id.cmp(&a.id)));
+        summaries
+            .into_iter()
+            .skip(filter.offset)
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+
     pub async fn get_session_count(&self) -> usize {
         let sessions = self.sessions.read().await;
         sessions.len()
     }
 
     pub async fn cleanup_expired_sessions(&self) -> Result<usize, SessionError> {
+        Ok(self.remove_expired_sessions().await?.len())
+    }
+
+    /// Remove expired sessions and return their IDs.
+    ///
+    /// Sessions with a request in progress are kept until a later cleanup.
+    pub async fn remove_expired_sessions(&self) -> Result<Vec<SessionId>, SessionError> {
         let mut sessions = self.sessions.write().await;
         let mut expired_sessions = Vec::new();
 
         // Find expired sessions
         for (session_id, session) in sessions.iter() {
-            if let Ok(age) = session.updated_at.elapsed() {
-                if age > self.config.session_timeout {
-                    expired_sessions.push(*session_id);
-                }
+            if self.is_expired(session) && !self.is_in_flight(session_id) {
+                expired_sessions.push(*session_id);
             }
         }
 
         // Remove expired sessions
-        let mut removed_count = 0;
-        for session_id in expired_sessions {
-            sessions.remove(&session_id);
-            removed_count += 1;
+        for session_id in &expired_sessions {
+            sessions.remove(session_id);
             debug!("Removed expired session: {}", session_id);
+            self.audit(AuditEvent::SessionExpired {
+                session_id: *session_id,
+            });
         }
 
-        if removed_count > 0 {
-            info!("Cleaned up {} expired sessions", removed_count);
+        if !expired_sessions.is_empty() {
+            info!("Cleaned up {} expired sessions", expired_sessions.len());
         }
 
-        Ok(removed_count)
+        Ok(expired_sessions)
     }
 
     pub async fn get_session_stats(&self) -> SessionStats {
@@ -161,12 +490,10 @@ impl SessionManager {
         for session in sessions.values() {
             total_messages += session.messages.len();
 
-            if let Ok(age) = session.updated_at.elapsed() {
-                if age <= self.config.session_timeout {
-                    active_sessions += 1;
-                } else {
-                    expired_sessions += 1;
-                }
+            if self.is_expired(session) {
+                expired_sessions += 1;
+            } else {
+                active_sessions += 1;
             }
         }
 
@@ -177,10 +504,83 @@ impl SessionManager {
             total_messages,
             max_sessions: self.config.max_sessions,
             session_timeout: self.config.session_timeout,
+            in_flight_sessions: lock(&self.in_flight).len(),
+            evicted_sessions: self.evicted_count(),
+        }
+    }
+}
+
+fn lock(registry: &Mutex<HashMap<SessionId, usize>>) -> MutexGuard<'_, HashMap<SessionId, usize>> {
+    registry
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A request in progress for a session; see [`SessionManager::begin_request`]
+#[derive(Debug)]
+#[must_use = "the session stops counting as in flight when the guard is dropped"]
+pub struct InFlightGuard {
+    registry: InFlightRegistry,
+    session_id: SessionId,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut registry = lock(&self.registry);
+        if let Some(count) = registry.get_mut(&self.session_id) {
+            *count -= 1;
+            if *count == 0 {
+                registry.remove(&self.session_id);
+            }
         }
     }
 }
 
+/// Timestamp to record as a session's last update at `now`: never earlier than its
+/// creation, its last message or its previous update
+fn latest_activity(session: &Session, now: SystemTime) -> SystemTime {
+    let last_message = session.messages.last().map(|message| message.timestamp);
+    now.max(session.created_at)
+        .max(session.updated_at)
+        .max(last_message.unwrap_or(session.created_at))
+}
+
+fn system_message(prompt: &str, timestamp: SystemTime) -> Message {
+    Message {
+        role: MessageRole::System,
+        content: prompt.to_string(),
+        tool_call_id: None,
+        tool_name: None,
+        timestamp,
+        attachments: Vec::new(),
+    }
+}
+
+/// Append messages, keeping `created_at <= message timestamps <= updated_at` with message
+/// timestamps in append order. Timestamps outside that range are clamped into it.
+fn append_to_session(session: &mut Session, messages: Vec<Message>, now: SystemTime) {
+    let added = messages.len();
+    let now = latest_activity(session, now);
+    let mut earliest = session
+        .messages
+        .last()
+        .map_or(session.created_at, |message| message.timestamp)
+        .max(session.created_at);
+
+    for mut message in messages {
+        message.timestamp = message.timestamp.clamp(earliest, now);
+        earliest = message.timestamp;
+        session.messages.push(message);
+    }
+    session.updated_at = now;
+    debug!(
+        "Added {} messages to session {}, total messages: {}",
+        added,
+        session.id,
+        session.messages.len()
+    );
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionStats {
     pub total_sessions: usize,
@@ -189,18 +589,28 @@ pub struct SessionStats {
     pub total_messages: usize,
     pub max_sessions: usize,
     pub session_timeout: Duration,
+    /// Sessions with a request in progress
+    pub in_flight_sessions: usize,
+    /// Sessions removed to make room for new ones
+    pub evicted_sessions: u64,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{MessageRole, SessionConfig};
+    use crate::test_support::MockClock;
+    use crate::types::{MessageRole, SessionConfig, ToolDefinition};
     use std::time::Duration;
 
     fn create_test_config() -> SessionConfig {
         SessionConfig {
             max_sessions: 5,
             session_timeout: Duration::from_secs(10),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         }
     }
 
@@ -211,9 +621,128 @@ mod tests {
             tool_call_id: None,
             tool_name: None,
             timestamp: SystemTime::now(),
+            attachments: Vec::new(),
+        }
+    }
+
+    fn tagged_message(content: String) -> Message {
+        Message {
+            content,
+            ..create_test_message()
         }
     }
 
+    /// Assert that each run of `batch_len` messages came from one writer's batch, in order
+    fn assert_batches_contiguous(messages: &[Message], batch_len: usize) {
+        for batch in messages.chunks(batch_len) {
+            let (prefix, _) = batch[0].content.rsplit_once('-').unwrap();
+            for (i, message) in batch.iter().enumerate() {
+                assert_eq!(message.content, format!("{}-{}", prefix, i));
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_add_messages_batches_do_not_interleave() {
+        let manager = Arc::new(SessionManager::new(create_test_config()));
+        let session = manager.create_session().await.unwrap();
+
+        let writers = (0..2).map(|writer| {
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                for batch in 0..50 {
+                    let messages = (0..3)
+                        .map(|i| tagged_message(format!("w{}-b{}-{}", writer, batch, i)))
+                        .collect();
+                    manager.add_messages(&session.id, messages).await.unwrap();
+                    tokio::task::yield_now().await;
+                }
+            })
+        });
+        for writer in writers.collect::<Vec<_>>() {
+            writer.await.unwrap();
+        }
+
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.messages.len(), 300);
+        assert_batches_contiguous(&stored.messages, 3);
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_append_detects_concurrent_modification() {
+        let manager = SessionManager::new(create_test_config());
+        let session = manager.create_session().await.unwrap();
+
+        manager
+            .compare_and_append(&session.id, 0, vec![create_test_message()])
+            .await
+            .unwrap();
+
+        // A writer that loaded the session before the append sees a conflict
+        let result = manager
+            .compare_and_append(&session.id, 0, vec![create_test_message()])
+            .await;
+        match result {
+            Err(SessionError::ConcurrentModification {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, 0);
+                assert_eq!(actual, 1);
+            }
+            other => panic!("expected ConcurrentModification, got {:?}", other),
+        }
+
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.messages.len(), 1);
+
+        let missing = manager
+            .compare_and_append(&SessionId::new(), 0, vec![create_test_message()])
+            .await;
+        assert!(matches!(missing, Err(SessionError::NotFound(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_compare_and_append_retry_keeps_batches_whole() {
+        let manager = Arc::new(SessionManager::new(create_test_config()));
+        let session = manager.create_session().await.unwrap();
+
+        let writers = (0..2).map(|writer| {
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                for batch in 0..25 {
+                    loop {
+                        let expected = manager
+                            .get_session(&session.id)
+                            .await
+                            .unwrap()
+                            .unwrap()
+                            .messages
+                            .len();
+                        tokio::task::yield_now().await;
+                        let messages = (0..2)
+                            .map(|i| tagged_message(format!("w{}-b{}-{}", writer, batch, i)))
+                            .collect();
+                        match manager
+                            .compare_and_append(&session.id, expected, messages)
+                            .await
+                        {
+                            Ok(()) => break,
+                            Err(SessionError::ConcurrentModification { .. }) => continue,
+                            Err(e) => panic!("unexpected error: {}", e),
+                        }
+                    }
+                }
+            })
+        });
+        for writer in writers.collect::<Vec<_>>() {
+            writer.await.unwrap();
+        }
+
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.messages.len(), 100);
+        assert_batches_contiguous(&stored.messages, 2);
+    }
+
     #[tokio::test]
     async fn test_session_manager_creation() {
         let config = create_test_config();
@@ -311,6 +840,11 @@ mod tests {
         let config = SessionConfig {
             max_sessions: 2,
             session_timeout: Duration::from_secs(10),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         };
         let manager = SessionManager::new(config);
 
@@ -324,6 +858,136 @@ mod tests {
         assert_eq!(manager.get_session_count().await, 2);
     }
 
+    #[tokio::test]
+    async fn test_evict_lru_session() {
+        let clock = MockClock::default();
+        let config = SessionConfig {
+            max_sessions: 3,
+            eviction_policy: SessionEvictionPolicy::EvictLru,
+            system_prompt: None,
+            ..create_test_config()
+        };
+        let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(manager.create_session().await.unwrap().id);
+            clock.advance(Duration::from_secs(1));
+        }
+        // Touching the oldest session makes the second one least recently used
+        manager
+            .add_message(&ids[0], create_test_message())
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let (fourth, evicted) = manager.create_session_evicting().await.unwrap();
+        assert_eq!(evicted, Some(ids[1]));
+        let (_, evicted) = manager.create_session_evicting().await.unwrap();
+        assert_eq!(evicted, Some(ids[2]));
+
+        assert_eq!(manager.get_session_count().await, 3);
+        assert!(manager.get_session(&ids[0]).await.unwrap().is_some());
+        assert!(manager.get_session(&fourth.id).await.unwrap().is_some());
+        assert_eq!(manager.get_session_stats().await.evicted_sessions, 2);
+    }
+
+    #[tokio::test]
+    async fn test_restore_session_keeps_id_and_messages() {
+        let clock = MockClock::default();
+        let config = SessionConfig {
+            max_sessions: 1,
+            system_prompt: None,
+            ..create_test_config()
+        };
+        let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
+        let session = manager.create_session().await.unwrap();
+        manager
+            .add_message(&session.id, create_test_message())
+            .await
+            .unwrap();
+        let saved = manager.get_session(&session.id).await.unwrap().unwrap();
+
+        // Replacing the session itself needs no room
+        clock.advance(Duration::from_secs(60));
+        let (restored, evicted) = manager.restore_session(saved.clone()).await.unwrap();
+        assert_eq!(evicted, None);
+        assert_eq!(restored.id, session.id);
+        assert_eq!(restored.messages.len(), 1);
+        assert_eq!(restored.updated_at, clock.now());
+
+        // Another session is held to the limit
+        manager.delete_session(&session.id).await.unwrap();
+        manager.create_session().await.unwrap();
+        assert!(matches!(
+            manager.restore_session(saved).await,
+            Err(SessionError::LimitExceeded)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_eviction_skips_in_flight_sessions() {
+        let clock = MockClock::default();
+        let config = SessionConfig {
+            max_sessions: 2,
+            eviction_policy: SessionEvictionPolicy::EvictLru,
+            system_prompt: None,
+            ..create_test_config()
+        };
+        let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
+
+        let oldest = manager.create_session().await.unwrap().id;
+        clock.advance(Duration::from_secs(1));
+        let newer = manager.create_session().await.unwrap().id;
+        clock.advance(Duration::from_secs(1));
+
+        let guard = manager.begin_request(oldest);
+        let _second_request = manager.begin_request(oldest);
+        assert!(manager.is_in_flight(&oldest));
+        let (_, evicted) = manager.create_session_evicting().await.unwrap();
+        assert_eq!(evicted, Some(newer));
+
+        assert_eq!(manager.get_session_stats().await.in_flight_sessions, 1);
+
+        // Once every session is busy there is nothing to evict
+        let replacement = manager
+            .list_sessions()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|id| *id != oldest)
+            .unwrap();
+        let _replacement_request = manager.begin_request(replacement);
+        let result = manager.create_session().await;
+        assert!(matches!(result, Err(SessionError::LimitExceeded)));
+
+        // The oldest session stays in flight until both of its requests finish
+        drop(guard);
+        assert!(manager.is_in_flight(&oldest));
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_session_is_not_deleted_or_expired() {
+        let clock = MockClock::default();
+        let manager = SessionManager::new(create_test_config()).with_clock(Arc::new(clock.clone()));
+        let session = manager.create_session().await.unwrap();
+
+        let guard = manager.begin_request(session.id);
+        let result = manager.delete_session(&session.id).await;
+        assert!(matches!(result, Err(SessionError::InUse(_))));
+
+        clock.advance(Duration::from_secs(60));
+        assert!(manager.remove_expired_sessions().await.unwrap().is_empty());
+        assert_eq!(manager.get_session_count().await, 1);
+
+        drop(guard);
+        assert!(!manager.is_in_flight(&session.id));
+        assert_eq!(
+            manager.remove_expired_sessions().await.unwrap(),
+            vec![session.id]
+        );
+    }
+
     #[tokio::test]
     async fn test_list_sessions() {
         let config = create_test_config();
@@ -343,18 +1007,25 @@ mod tests {
         let config = SessionConfig {
             max_sessions: 10,
             session_timeout: Duration::from_millis(50), // Very short timeout
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         };
-        let manager = SessionManager::new(config);
+        let clock = MockClock::default();
+        let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
 
         let session = manager.create_session().await.unwrap();
         let session_id = session.id;
 
-        // Session should exist initially
+        // Session should exist initially, up to the timeout itself
         let retrieved = manager.get_session(&session_id).await.unwrap();
         assert!(retrieved.is_some());
+        clock.advance(Duration::from_millis(50));
+        assert!(manager.get_session(&session_id).await.unwrap().is_some());
 
-        // Wait for session to expire
-        tokio::time::sleep(Duration::from_millis(100)).await;
+        clock.advance(Duration::from_millis(1));
 
         // Session should now be considered expired
         let expired = manager.get_session(&session_id).await.unwrap();
@@ -366,16 +1037,22 @@ mod tests {
         let config = SessionConfig {
             max_sessions: 10,
             session_timeout: Duration::from_millis(50), // Very short timeout
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         };
-        let manager = SessionManager::new(config);
+        let clock = MockClock::default();
+        let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
 
         // Create some sessions
         manager.create_session().await.unwrap();
         manager.create_session().await.unwrap();
         assert_eq!(manager.get_session_count().await, 2);
 
-        // Wait for sessions to expire
-        tokio::time::sleep(Duration::from_millis(100)).await;
+        // Let the sessions expire
+        clock.advance(Duration::from_millis(100));
 
         // Cleanup expired sessions
         let removed = manager.cleanup_expired_sessions().await.unwrap();
@@ -383,6 +1060,115 @@ mod tests {
         assert_eq!(manager.get_session_count().await, 0);
     }
 
+    #[tokio::test]
+    async fn test_remove_expired_sessions_returns_ids() {
+        let config = SessionConfig {
+            max_sessions: 10,
+            session_timeout: Duration::from_millis(50),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
+        };
+        let clock = MockClock::default();
+        let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
+
+        let expired = manager.create_session().await.unwrap();
+        clock.advance(Duration::from_millis(100));
+        let fresh = manager.create_session().await.unwrap();
+
+        let removed = manager.remove_expired_sessions().await.unwrap();
+        assert_eq!(removed, vec![expired.id]);
+        assert!(manager.get_session(&fresh.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_set_tool_policy() {
+        let config = SessionConfig {
+            default_tool_policy: ToolPolicy::DenyList(vec!["shell".to_string()]),
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
+            ..create_test_config()
+        };
+        let manager = SessionManager::new(config);
+
+        let session = manager.create_session().await.unwrap();
+        assert_eq!(
+            session.tool_policy,
+            ToolPolicy::DenyList(vec!["shell".to_string()])
+        );
+
+        let policy = ToolPolicy::AllowList(vec!["read_file".to_string()]);
+        manager
+            .set_tool_policy(&session.id, policy.clone())
+            .await
+            .unwrap();
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.tool_policy, policy);
+
+        let missing = manager
+            .set_tool_policy(&SessionId::new(), ToolPolicy::AllowAll)
+            .await;
+        assert!(matches!(missing, Err(SessionError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_create_session_with_system_prompt() {
+        let manager = SessionManager::new(SessionConfig {
+            system_prompt: Some("You are terse.".to_string()),
+            ..create_test_config()
+        });
+
+        let session = manager.create_session().await.unwrap();
+        assert_eq!(session.messages.len(), 1);
+        assert_eq!(session.messages[0].role, MessageRole::System);
+        assert_eq!(session.messages[0].content, "You are terse.");
+
+        let plain = SessionManager::new(create_test_config())
+            .create_session()
+            .await
+            .unwrap();
+        assert!(plain.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_set_system_prompt_replaces_or_inserts() {
+        let manager = SessionManager::new(create_test_config());
+        let session = manager.create_session().await.unwrap();
+        manager
+            .add_message(&session.id, create_test_message())
+            .await
+            .unwrap();
+
+        // Inserted ahead of the existing history
+        manager
+            .set_system_prompt(&session.id, "First prompt")
+            .await
+            .unwrap();
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.messages.len(), 2);
+        assert_eq!(stored.messages[0].role, MessageRole::System);
+        assert_eq!(stored.messages[0].content, "First prompt");
+        assert!(stored.messages[0].timestamp <= stored.messages[1].timestamp);
+        assert_eq!(stored.messages[1].content, "Hello, world!");
+
+        // Replaced in place without touching the rest
+        manager
+            .set_system_prompt(&session.id, "Second prompt")
+            .await
+            .unwrap();
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.messages.len(), 2);
+        assert_eq!(stored.messages[0].content, "Second prompt");
+        assert_eq!(stored.messages[1].content, "Hello, world!");
+
+        let missing = manager.set_system_prompt(&SessionId::new(), "x").await;
+        assert!(matches!(missing, Err(SessionError::NotFound(_))));
+    }
+
     #[tokio::test]
     async fn test_get_session_stats() {
         let config = create_test_config();
@@ -422,6 +1208,8 @@ mod tests {
             total_messages: 10,
             max_sessions: 10,
             session_timeout: Duration::from_secs(3600),
+            in_flight_sessions: 1,
+            evicted_sessions: 4,
         };
 
         let debug_str = format!("{:?}", stats);
@@ -429,4 +1217,440 @@ mod tests {
         assert!(debug_str.contains("active_sessions: 3"));
         assert!(debug_str.contains("total_messages: 10"));
     }
+
+    #[tokio::test]
+    async fn test_timestamps_follow_clock_and_stay_ordered() {
+        let clock = MockClock::default();
+        let manager = SessionManager::new(create_test_config()).with_clock(Arc::new(clock.clone()));
+        let session = manager.create_session().await.unwrap();
+        assert_eq!(session.created_at, clock.now());
+        assert_eq!(session.updated_at, clock.now());
+
+        // A message stamped before the session existed, one from the future and one
+        // older than the previous message
+        clock.advance(Duration::from_secs(5));
+        let at = |time: SystemTime| Message {
+            timestamp: time,
+            ..create_test_message()
+        };
+        manager
+            .add_messages(
+                &session.id,
+                vec![
+                    at(session.created_at - Duration::from_secs(60)),
+                    at(clock.now() + Duration::from_secs(3600)),
+                    at(session.created_at + Duration::from_secs(1)),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        let timestamps: Vec<_> = stored.messages.iter().map(|m| m.timestamp).collect();
+        assert_eq!(
+            timestamps,
+            vec![session.created_at, clock.now(), clock.now()]
+        );
+        assert_eq!(stored.updated_at, clock.now());
+
+        // Setting the clock back never moves updated_at behind the messages
+        clock.set(session.created_at);
+        manager
+            .set_tool_policy(&session.id, ToolPolicy::AllowAll)
+            .await
+            .unwrap();
+        manager
+            .add_message(&session.id, create_test_message())
+            .await
+            .unwrap();
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert!(stored.created_at <= stored.messages[0].timestamp);
+        assert!(stored
+            .messages
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        assert!(stored.messages.last().unwrap().timestamp <= stored.updated_at);
+    }
+
+    /// 300 sessions created a second apart: session `i` has `i % 5` messages and tools
+    /// when `i % 4 == 0`; every tenth session is touched again after all were created
+    async fn synthetic_sessions() -> (SessionManager, MockClock, Vec<SessionId>, SystemTime) {
+        let clock = MockClock::default();
+        let config = SessionConfig {
+            max_sessions: 1000,
+            session_timeout: Duration::from_secs(24 * 60 * 60),
+            ..create_test_config()
+        };
+        let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
+        let mut ids = Vec::new();
+        for i in 0..300 {
+            clock.advance(Duration::from_secs(1));
+            let mut session = manager.create_session().await.unwrap();
+            if i % 4 == 0 {
+                session.available_tools.push(ToolDefinition {
+                    name: "list_files".to_string(),
+                    description: "List files".to_string(),
+                    parameters: serde_json::json!({}),
+                    server_name: "fs".to_string(),
+                    original_name: None,
+                });
+                manager.update_session(session.clone()).await.unwrap();
+            }
+            let messages = (0..i % 5).map(|_| create_test_message()).collect();
+            manager.add_messages(&session.id, messages).await.unwrap();
+            ids.push(session.id);
+        }
+
+        clock.advance(Duration::from_secs(1000));
+        let touched_after = clock.now();
+        for id in ids.iter().step_by(10) {
+            clock.advance(Duration::from_secs(1));
+            manager
+                .set_tool_policy(id, ToolPolicy::AllowAll)
+                .await
+                .unwrap();
+        }
+        (manager, clock, ids, touched_after)
+    }
+
+    async fn count(manager: &SessionManager, filter: SessionFilter) -> usize {
+        manager.query_sessions(&filter).await.len()
+    }
+
+    async fn page(manager: &SessionManager, offset: usize, limit: usize) -> Vec<SessionSummary> {
+        manager
+            .query_sessions(&SessionFilter::new().with_page(offset, limit))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_query_sessions_orders_by_last_update() {
+        let (manager, _, ids, _) = synthetic_sessions().await;
+
+        let all = manager.query_sessions(&SessionFilter::new()).await;
+        assert_eq!(all.len(), 300);
+        assert!(all.windows(2).all(|pair| {
+            pair[0].updated_at > pair[1].updated_at
+                || (pair[0].updated_at == pair[1].updated_at && pair[0].id > pair[1].id)
+        }));
+        // The last session touched comes first, the first untouched one created comes last
+        assert_eq!(all[0].id, ids[290]);
+        assert_eq!(all[299].id, ids[1]);
+
+        let summary = all.iter().find(|s| s.id == ids[7]).unwrap();
+        assert_eq!(summary.message_count, 2);
+        assert_eq!(summary.last_role, Some(MessageRole::User));
+        let summary = all.iter().find(|s| s.id == ids[5]).unwrap();
+        assert_eq!(summary.message_count, 0);
+        assert_eq!(summary.last_role, None);
+
+        // Ties on updated_at are broken by id
+        let clock = MockClock::default();
+        let manager = SessionManager::new(create_test_config()).with_clock(Arc::new(clock));
+        let first = manager.create_session().await.unwrap();
+        let second = manager.create_session().await.unwrap();
+        let listed = manager.query_sessions(&SessionFilter::new()).await;
+        let mut expected = [first.id, second.id];
+        expected.sort_by(|a, b| b.cmp(a));
+        assert_eq!(listed.iter().map(|s| s.id).collect::<Vec<_>>(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_query_sessions_filters() {
+        let (manager, clock, ids, touched_after) = synthetic_sessions().await;
+        // Session i was created i + 1 seconds after the clock's starting point
+        let start = MockClock::default().now();
+        let created_after =
+            SessionFilter::new().with_created_after(start + Duration::from_secs(100));
+        assert_eq!(count(&manager, created_after).await, 200);
+        assert_eq!(
+            count(
+                &manager,
+                SessionFilter::new().with_created_after(clock.now())
+            )
+            .await,
+            0
+        );
+
+        let updated = manager
+            .query_sessions(&SessionFilter::new().with_updated_after(touched_after))
+            .await;
+        assert_eq!(updated.len(), 30);
+        assert!(updated
+            .iter()
+            .all(|s| ids.iter().step_by(10).any(|id| *id == s.id)));
+
+        assert_eq!(
+            count(&manager, SessionFilter::new().with_has_tools(true)).await,
+            75
+        );
+        assert_eq!(
+            count(&manager, SessionFilter::new().with_has_tools(false)).await,
+            225
+        );
+
+        let between = manager
+            .query_sessions(&SessionFilter::new().with_message_count(Some(2), Some(3)))
+            .await;
+        assert_eq!(between.len(), 120);
+        assert!(between.iter().all(|s| (2..=3).contains(&s.message_count)));
+        assert_eq!(
+            count(
+                &manager,
+                SessionFilter::new().with_message_count(Some(4), None)
+            )
+            .await,
+            60
+        );
+        assert_eq!(
+            count(
+                &manager,
+                SessionFilter::new().with_message_count(None, Some(0))
+            )
+            .await,
+            60
+        );
+        assert_eq!(
+            count(
+                &manager,
+                SessionFilter::new().with_message_count(Some(3), Some(2))
+            )
+            .await,
+            0
+        );
+
+        // Conditions combine: sessions with tools have i % 4 == 0, so i % 5 == 4 leaves 15
+        let combined = SessionFilter::new()
+            .with_has_tools(true)
+            .with_message_count(Some(4), None);
+        assert_eq!(count(&manager, combined).await, 15);
+
+        // Expired sessions are left out
+        clock.advance(Duration::from_secs(24 * 60 * 60 - 1000));
+        assert_eq!(count(&manager, SessionFilter::new()).await, 30);
+    }
+
+    #[tokio::test]
+    async fn test_query_sessions_pagination() {
+        let (manager, _, _, _) = synthetic_sessions().await;
+        let all = manager.query_sessions(&SessionFilter::new()).await;
+        assert_eq!(page(&manager, 0, 50).await, all[..50]);
+        assert_eq!(page(&manager, 50, 50).await, all[50..100]);
+        assert_eq!(page(&manager, 299, 50).await, all[299..]);
+        assert_eq!(page(&manager, 290, 50).await.len(), 10);
+        assert!(page(&manager, 300, 10).await.is_empty());
+        assert!(page(&manager, 1000, 10).await.is_empty());
+        assert!(page(&manager, 0, 0).await.is_empty());
+
+        // Walking the pages visits every session once
+        let mut walked = Vec::new();
+        for offset in (0..300).step_by(64) {
+            walked.extend(page(&manager, offset, 64).await);
+        }
+        assert_eq!(walked, all);
+
+        // Pages apply to the filtered sessions
+        let with_tools = SessionFilter::new().with_has_tools(true).with_page(70, 10);
+        let tail = manager.query_sessions(&with_tools).await;
+        assert_eq!(tail.len(), 5);
+        let all_with_tools = manager
+            .query_sessions(&SessionFilter::new().with_has_tools(true))
+            .await;
+        assert_eq!(tail, all_with_tools[70..]);
+    }
+
+    #[tokio::test]
+    async fn test_record_usage_accumulates() {
+        let clock = MockClock::default();
+        let manager = SessionManager::new(create_test_config()).with_clock(Arc::new(clock.clone()));
+        let session = manager.create_session().await.unwrap();
+        assert_eq!(session.usage, SessionUsage::default());
+        clock.advance(Duration::from_secs(1));
+
+        manager.record_usage(&session.id, 100, 20).await.unwrap();
+        let usage = manager.record_usage(&session.id, 130, 5).await.unwrap();
+        let expected = SessionUsage {
+            prompt_tokens: 230,
+            completion_tokens: 25,
+            requests: 2,
+        };
+        assert_eq!(usage, expected);
+        assert_eq!(usage.total_tokens(), 255);
+
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.usage, expected);
+        // Bookkeeping is not activity that keeps a session alive
+        assert_eq!(stored.updated_at, session.updated_at);
+        let summaries = manager.query_sessions(&SessionFilter::new()).await;
+        assert_eq!(summaries[0].usage, expected);
+
+        assert!(matches!(
+            manager.record_usage(&SessionId::new(), 1, 1).await,
+            Err(SessionError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_touch_and_set_tools_record_activity() {
+        let clock = MockClock::default();
+        let manager = SessionManager::new(create_test_config()).with_clock(Arc::new(clock.clone()));
+        let session = manager.create_session().await.unwrap();
+        manager
+            .add_message(&session.id, create_test_message())
+            .await
+            .unwrap();
+
+        clock.advance(Duration::from_secs(8));
+        manager.touch_session(&session.id).await.unwrap();
+        // Without the touch the session would have expired by now
+        clock.advance(Duration::from_secs(8));
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.updated_at, clock.now() - Duration::from_secs(8));
+        assert_eq!(stored.messages.len(), 1);
+
+        let tool = ToolDefinition {
+            name: "list_files".to_string(),
+            description: "List files".to_string(),
+            parameters: serde_json::json!({}),
+            server_name: "filesystem".to_string(),
+            original_name: None,
+        };
+        manager
+            .set_available_tools(&session.id, vec![tool])
+            .await
+            .unwrap();
+        let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+        assert_eq!(stored.available_tools.len(), 1);
+        assert_eq!(stored.available_tools[0].name, "list_files");
+        assert_eq!(stored.updated_at, clock.now());
+        assert_eq!(stored.messages.len(), 1);
+
+        let missing = SessionId::new();
+        assert!(matches!(
+            manager.touch_session(&missing).await,
+            Err(SessionError::NotFound(_))
+        ));
+        assert!(matches!(
+            manager.set_available_tools(&missing, Vec::new()).await,
+            Err(SessionError::NotFound(_))
+        ));
+    }
+
+    /// A public change to a session, for the property test below
+    #[derive(Debug, Clone)]
+    enum Mutation {
+        AddMessage,
+        CompareAndAppend,
+        StaleCompareAndAppend,
+        SetToolPolicy,
+        SetSystemPrompt,
+        SetAvailableTools,
+        UpdateSession,
+        Touch,
+        RecordUsage,
+    }
+
+    fn arb_mutation() -> impl proptest::strategy::Strategy<Value = Mutation> {
+        proptest::sample::select(vec![
+            Mutation::AddMessage,
+            Mutation::CompareAndAppend,
+            Mutation::StaleCompareAndAppend,
+            Mutation::SetToolPolicy,
+            Mutation::SetSystemPrompt,
+            Mutation::SetAvailableTools,
+            Mutation::UpdateSession,
+            Mutation::Touch,
+            Mutation::RecordUsage,
+        ])
+    }
+
+    /// Apply `mutation`, returning whether it changed the session's activity
+    async fn apply_mutation(
+        manager: &SessionManager,
+        session_id: &SessionId,
+        mutation: &Mutation,
+    ) -> bool {
+        let len = manager
+            .get_session(session_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .messages
+            .len();
+        match mutation {
+            Mutation::AddMessage => manager
+                .add_message(session_id, create_test_message())
+                .await
+                .unwrap(),
+            Mutation::CompareAndAppend => manager
+                .compare_and_append(session_id, len, vec![create_test_message()])
+                .await
+                .unwrap(),
+            Mutation::StaleCompareAndAppend => {
+                let result = manager
+                    .compare_and_append(session_id, len + 1, vec![create_test_message()])
+                    .await;
+                assert!(matches!(
+                    result,
+                    Err(SessionError::ConcurrentModification { .. })
+                ));
+                return false;
+            }
+            Mutation::SetToolPolicy => manager
+                .set_tool_policy(session_id, ToolPolicy::AllowList(Vec::new()))
+                .await
+                .unwrap(),
+            Mutation::SetSystemPrompt => manager
+                .set_system_prompt(session_id, "Be brief.")
+                .await
+                .unwrap(),
+            Mutation::SetAvailableTools => manager
+                .set_available_tools(session_id, Vec::new())
+                .await
+                .unwrap(),
+            Mutation::UpdateSession => {
+                let session = manager.get_session(session_id).await.unwrap().unwrap();
+                manager.update_session(session).await.unwrap()
+            }
+            Mutation::Touch => manager.touch_session(session_id).await.unwrap(),
+            Mutation::RecordUsage => {
+                manager.record_usage(session_id, 10, 5).await.unwrap();
+                return false;
+            }
+        }
+        true
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_updated_at_is_time_of_last_mutation(
+            steps in proptest::collection::vec((0u64..3, arb_mutation()), 1..30),
+        ) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let clock = MockClock::default();
+                let config = SessionConfig {
+                    session_timeout: Duration::from_secs(3600),
+                    ..create_test_config()
+                };
+                let manager = SessionManager::new(config).with_clock(Arc::new(clock.clone()));
+                let session = manager.create_session().await.unwrap();
+                let mut last_mutation = clock.now();
+
+                for (seconds, mutation) in &steps {
+                    clock.advance(Duration::from_secs(*seconds));
+                    if apply_mutation(&manager, &session.id, mutation).await {
+                        last_mutation = clock.now();
+                    }
+                    let stored = manager.get_session(&session.id).await.unwrap().unwrap();
+                    proptest::prop_assert_eq!(stored.updated_at, last_mutation);
+                    proptest::prop_assert!(stored
+                        .messages
+                        .iter()
+                        .all(|m| stored.created_at <= m.timestamp && m.timestamp <= stored.updated_at));
+                }
+                Ok(())
+            })?;
+        }
+    }
 }
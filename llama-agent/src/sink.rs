@@ -0,0 +1,229 @@
+//! Push-based consumption of streamed generations, for callback-oriented
+//! integrations such as FFI bindings and GUI event loops
+
+use crate::types::{AgentError, GenerationResponse, StreamChunk, StreamEvent};
+use futures::{Stream, StreamExt};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// Receives the progress of a generation started with
+/// [`AgentServer::generate_with_sink`](crate::AgentServer::generate_with_sink).
+///
+/// Callbacks run in order on one task and must not block: generation pauses while
+/// they run once the stream's buffer is full, as it does for a slow stream consumer.
+/// Exactly one of `on_complete`, `on_error` and `on_cancelled` ends every generation.
+pub trait GenerationSink: Send + Sync {
+    /// A chunk of generated text
+    fn on_chunk(&self, chunk: StreamChunk);
+
+    /// A tool call started, reported progress or completed, or generation resumed
+    /// after tool calls
+    fn on_tool_event(&self, _event: StreamEvent) {}
+
+    /// The generation finished; `response` summarizes all of its passes
+    fn on_complete(&self, response: GenerationResponse);
+
+    /// The generation failed
+    fn on_error(&self, error: AgentError);
+
+    /// The generation was cancelled before it finished
+    fn on_cancelled(&self) {}
+}
+
+/// A generation feeding a [`GenerationSink`]
+#[derive(Debug)]
+pub struct SinkGeneration {
+    cancel: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl SinkGeneration {
+    /// Start feeding `stream` to `sink` on a new task
+    pub(crate) fn spawn<S>(stream: S, sink: Arc<dyn GenerationSink>) -> Self
+    where
+        S: Stream<Item = Result<StreamChunk, AgentError>> + Send + Unpin + 'static,
+    {
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(feed_sink(stream, sink, cancel.clone()));
+        Self { cancel, task }
+    }
+
+    /// Cancel the generation; the sink's `on_cancelled` is called unless it
+    /// already finished
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Wait until the sink has received the generation's last callback
+    pub async fn finished(self) {
+        if let Err(e) = self.task.await {
+            warn!("Generation sink task failed: {}", e);
+        }
+    }
+}
+
+/// Dispatch the items of a generation stream to `sink` until it ends or `cancel`
+/// fires. Dropping the stream on cancellation cancels the queued request.
+async fn feed_sink<S>(mut stream: S, sink: Arc<dyn GenerationSink>, cancel: CancellationToken)
+where
+    S: Stream<Item = Result<StreamChunk, AgentError>> + Unpin,
+{
+    loop {
+        let item = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => None,
+            item = stream.next() => item,
+        };
+        match item {
+            Some(Ok(chunk)) if chunk.is_complete => {
+                match chunk.response {
+                    Some(response) => sink.on_complete(response),
+                    None => sink.on_cancelled(),
+                }
+                return;
+            }
+            Some(Ok(chunk)) => match chunk.event {
+                StreamEvent::TextChunk => sink.on_chunk(chunk),
+                event => sink.on_tool_event(event),
+            },
+            Some(Err(e)) => {
+                sink.on_error(e);
+                return;
+            }
+            // Cancelled, or the generation stopped without finishing
+            None => {
+                sink.on_cancelled();
+                return;
+            }
+        }
+    }
+}
+
+/// A [`GenerationSink`] forwarding everything to a channel as stream items, in the
+/// form [`AgentAPI::generate_stream`](crate::types::AgentAPI::generate_stream) yields
+/// them. Cancellation closes the channel without a final chunk.
+pub struct ChannelSink {
+    sender: mpsc::UnboundedSender<Result<StreamChunk, AgentError>>,
+    /// Token count of the latest chunk, for tool event chunks
+    token_count: AtomicU32,
+}
+
+impl ChannelSink {
+    pub fn new(sender: mpsc::UnboundedSender<Result<StreamChunk, AgentError>>) -> Self {
+        Self {
+            sender,
+            token_count: AtomicU32::new(0),
+        }
+    }
+
+    fn send(&self, item: Result<StreamChunk, AgentError>) {
+        // A receiver that went away has stopped listening; nothing is left to tell it
+        let _ = self.sender.send(item);
+    }
+}
+
+impl GenerationSink for ChannelSink {
+    fn on_chunk(&self, chunk: StreamChunk) {
+        self.token_count.store(chunk.token_count, Ordering::Relaxed);
+        self.send(Ok(chunk));
+    }
+
+    fn on_tool_event(&self, event: StreamEvent) {
+        let token_count = self.token_count.load(Ordering::Relaxed);
+        self.send(Ok(StreamChunk::for_event(event, token_count)));
+    }
+
+    fn on_complete(&self, response: GenerationResponse) {
+        self.send(Ok(StreamChunk {
+            text: String::new(),
+            is_complete: true,
+            token_count: response.tokens_generated,
+            response: Some(response),
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
+        }));
+    }
+
+    fn on_error(&self, error: AgentError) {
+        self.send(Err(error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FinishReason;
+    use std::time::Duration;
+
+    fn text_chunk(text: &str, token_count: u32) -> Result<StreamChunk, AgentError> {
+        Ok(StreamChunk {
+            text: text.to_string(),
+            is_complete: false,
+            token_count,
+            response: None,
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
+        })
+    }
+
+    fn final_chunk(text: &str, tokens: u32) -> Result<StreamChunk, AgentError> {
+        Ok(StreamChunk {
+            text: String::new(),
+            is_complete: true,
+            token_count: tokens,
+            response: Some(GenerationResponse {
+                generated_text: text.to_string(),
+                tokens_generated: tokens,
+                generation_time: Duration::from_millis(10),
+                finish_reason: FinishReason::Stopped("End of sequence token detected".into()),
+                prompt_tokens: 3,
+                prompt_time: Duration::from_millis(2),
+                decode_time: Duration::from_millis(8),
+                time_to_first_token: Some(Duration::from_millis(3)),
+                candidates: Vec::new(),
+                generated_tokens: Vec::new(),
+            }),
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_channel_sink_round_trips_stream_items() {
+        let items = vec![
+            text_chunk("Hi", 1),
+            Ok(StreamChunk::for_event(StreamEvent::GenerationResumed, 1)),
+            text_chunk(" there", 2),
+            final_chunk("Hi there", 2),
+        ];
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let sink: Arc<dyn GenerationSink> = Arc::new(ChannelSink::new(sender));
+        SinkGeneration::spawn(futures::stream::iter(items), sink)
+            .finished()
+            .await;
+
+        let mut received = Vec::new();
+        while let Some(item) = receiver.recv().await {
+            let chunk = item.unwrap();
+            received.push((
+                chunk.text,
+                chunk.token_count,
+                chunk.event,
+                chunk.is_complete,
+            ));
+        }
+        assert_eq!(
+            received,
+            vec![
+                ("Hi".to_string(), 1, StreamEvent::TextChunk, false),
+                (String::new(), 1, StreamEvent::GenerationResumed, false),
+                (" there".to_string(), 2, StreamEvent::TextChunk, false),
+                (String::new(), 2, StreamEvent::TextChunk, true),
+            ]
+        );
+    }
+}
@@ -57,6 +57,12 @@ pub struct EosStopper {
     /// - 50256: GPT-2/GPT-3 style models  
     /// - 128001: Some newer models with extended vocabularies
     eos_token_id: u32,
+
+    /// Every token ID that ends generation, including `eos_token_id`.
+    ///
+    /// Chat models often end turns with tokens other than EOS, such as
+    /// `<|eot_id|>` or `<|im_end|>`.
+    eog_token_ids: Vec<u32>,
 }
 
 impl EosStopper {
@@ -101,7 +107,42 @@ impl EosStopper {
     /// happens during generation when the model's token vocabulary is available.
     pub fn new(eos_token_id: u32) -> Self {
         debug!("Creating EosStopper with token ID: {}", eos_token_id);
-        Self { eos_token_id }
+        Self {
+            eos_token_id,
+            eog_token_ids: vec![eos_token_id],
+        }
+    }
+
+    /// Set every token ID that ends generation using builder pattern.
+    ///
+    /// The EOS token ID is always kept in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use llama_agent::stopper::EosStopper;
+    ///
+    /// // Llama 3: <|end_of_text|>, <|eot_id|> and <|eom_id|>
+    /// let stopper = EosStopper::new(128001).with_eog_token_ids(vec![128008, 128009]);
+    /// assert!(stopper.is_eog_token(128009));
+    /// assert!(stopper.is_eog_token(128001));
+    /// ```
+    pub fn with_eog_token_ids(mut self, eog_token_ids: Vec<u32>) -> Self {
+        self.eog_token_ids = eog_token_ids;
+        if !self.eog_token_ids.contains(&self.eos_token_id) {
+            self.eog_token_ids.push(self.eos_token_id);
+        }
+        self
+    }
+
+    /// Get every token ID that ends generation, including the EOS token ID.
+    pub fn eog_token_ids(&self) -> &[u32] {
+        &self.eog_token_ids
+    }
+
+    /// Check whether `token_id` ends generation.
+    pub fn is_eog_token(&self, token_id: u32) -> bool {
+        self.eog_token_ids.contains(&token_id)
     }
 
     /// Get the configured EOS token ID.
@@ -257,6 +298,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_eos_stopper_eog_token_ids() {
+        let stopper = EosStopper::new(2);
+        assert_eq!(stopper.eog_token_ids(), &[2]);
+
+        let stopper = EosStopper::new(128001).with_eog_token_ids(vec![128008, 128009]);
+        assert_eq!(stopper.eos_token_id(), 128001);
+        assert_eq!(stopper.eog_token_ids(), &[128008, 128009, 128001]);
+        assert!(stopper.is_eog_token(128008));
+        assert!(!stopper.is_eog_token(2));
+
+        // The EOS token is not duplicated
+        let stopper = EosStopper::new(2).with_eog_token_ids(vec![2, 7]);
+        assert_eq!(stopper.eog_token_ids(), &[2, 7]);
+    }
+
     // Note: Integration tests with actual LlamaContext and LlamaBatch
     // are implemented in the integration_tests.rs file to avoid
     // requiring model loading in unit tests.
@@ -0,0 +1,165 @@
+use super::{EosStopper, MaxTokensStopper, RepetitionStopper, Stopper};
+use crate::types::StoppingConfig;
+use llama_cpp_2::{model::LlamaModel, token::LlamaToken};
+use tracing::debug;
+
+/// Builds the stopper set described by a [`StoppingConfig`].
+///
+/// This is the single place that knows how each stopper is constructed, so the
+/// queue, tests and external consumers get the same stoppers for the same
+/// configuration. Disabled components are skipped:
+///
+/// - [`EosStopper`] when `eos_detection` is set, with the model's end-of-generation tokens
+/// - [`MaxTokensStopper`] when `max_tokens` is set
+/// - [`RepetitionStopper`] when `repetition_detection` is set
+///
+/// # Examples
+///
+/// ```rust
+/// use llama_agent::stopper::StopperFactory;
+/// use llama_agent::types::StoppingConfig;
+///
+/// let config = StoppingConfig {
+///     max_tokens: Some(100),
+///     ..StoppingConfig::default()
+/// };
+///
+/// // With a loaded model: StopperFactory::from_config(&config, &model)
+/// let stoppers = StopperFactory::from_token_ids(&config, 2, vec![2]);
+/// assert_eq!(stoppers.len(), 2);
+/// ```
+pub struct StopperFactory;
+
+impl StopperFactory {
+    /// Create the stoppers for `config`, using `model` for the end-of-generation tokens.
+    pub fn from_config(config: &StoppingConfig, model: &LlamaModel) -> Vec<Box<dyn Stopper>> {
+        let eog_token_ids = if config.eos_detection {
+            model_eog_token_ids(model)
+        } else {
+            Vec::new()
+        };
+        Self::from_token_ids(config, model.token_eos().0 as u32, eog_token_ids)
+    }
+
+    /// Create the stoppers for `config` with explicit EOS and end-of-generation token IDs.
+    pub fn from_token_ids(
+        config: &StoppingConfig,
+        eos_token_id: u32,
+        eog_token_ids: Vec<u32>,
+    ) -> Vec<Box<dyn Stopper>> {
+        let mut stoppers: Vec<Box<dyn Stopper>> = Vec::new();
+
+        if config.eos_detection {
+            stoppers.push(Box::new(
+                EosStopper::new(eos_token_id).with_eog_token_ids(eog_token_ids),
+            ));
+        }
+        if let Some(max_tokens) = config.max_tokens {
+            stoppers.push(Box::new(MaxTokensStopper::new(max_tokens)));
+        }
+        if let Some(repetition) = &config.repetition_detection {
+            stoppers.push(Box::new(RepetitionStopper::new(repetition.clone())));
+        }
+
+        debug!("Created {} stoppers from stopping config", stoppers.len());
+        stoppers
+    }
+}
+
+/// Every token ID the model treats as end of generation
+fn model_eog_token_ids(model: &LlamaModel) -> Vec<u32> {
+    (0..model.n_vocab())
+        .map(LlamaToken)
+        .filter(|token| model.is_eog_token(*token))
+        .map(|token| token.0 as u32)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stopper::repetition::RepetitionConfig;
+
+    /// Names of the stoppers in a set, in order
+    fn kinds(stoppers: &mut [Box<dyn Stopper>]) -> Vec<&'static str> {
+        stoppers
+            .iter_mut()
+            .map(|stopper| {
+                let any = stopper.as_any_mut();
+                if any.is::<EosStopper>() {
+                    "eos"
+                } else if any.is::<MaxTokensStopper>() {
+                    "max_tokens"
+                } else if any.is::<RepetitionStopper>() {
+                    "repetition"
+                } else {
+                    "unknown"
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_config_permutations() {
+        for eos_detection in [false, true] {
+            for max_tokens in [None, Some(64)] {
+                for repetition_detection in [None, Some(RepetitionConfig::default())] {
+                    let config = StoppingConfig {
+                        max_tokens,
+                        repetition_detection: repetition_detection.clone(),
+                        eos_detection,
+                        stop_token_ids: Vec::new(),
+                        max_duration: None,
+                    };
+
+                    let mut expected = Vec::new();
+                    if eos_detection {
+                        expected.push("eos");
+                    }
+                    if max_tokens.is_some() {
+                        expected.push("max_tokens");
+                    }
+                    if repetition_detection.is_some() {
+                        expected.push("repetition");
+                    }
+
+                    let mut stoppers = StopperFactory::from_token_ids(&config, 2, vec![2]);
+                    assert_eq!(kinds(&mut stoppers), expected, "config: {:?}", config);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stoppers_are_configured() {
+        let config = StoppingConfig {
+            max_tokens: Some(64),
+            repetition_detection: Some(RepetitionConfig {
+                min_pattern_length: 4,
+                ..RepetitionConfig::default()
+            }),
+            eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
+        };
+        let mut stoppers = StopperFactory::from_token_ids(&config, 128001, vec![128009]);
+
+        let eos = stoppers[0]
+            .as_any_mut()
+            .downcast_mut::<EosStopper>()
+            .unwrap();
+        assert_eq!(eos.eos_token_id(), 128001);
+        assert_eq!(eos.eog_token_ids(), &[128009, 128001]);
+
+        let max_tokens = stoppers[1]
+            .as_any_mut()
+            .downcast_mut::<MaxTokensStopper>()
+            .unwrap();
+        assert_eq!(max_tokens.max_tokens(), 64);
+
+        assert!(stoppers[2]
+            .as_any_mut()
+            .downcast_mut::<RepetitionStopper>()
+            .is_some());
+    }
+}
@@ -29,11 +29,14 @@
 //!     max_tokens: Some(100),
 //!     repetition_detection: Some(RepetitionConfig::default()),
 //!     eos_detection: true,
+//!     stop_token_ids: Vec::new(),
+//!     max_duration: None,
 //! };
 //!
-//! // Stoppers are created from the configuration during generation
-//! let max_tokens_stopper = MaxTokensStopper::new(100);
-//! let eos_stopper = EosStopper::new(2); // EOS token ID
+//! // Stoppers are created from the configuration during generation; with a
+//! // loaded model use `StopperFactory::from_config(&config, &model)`
+//! let stoppers = StopperFactory::from_token_ids(&config, 2, vec![2]); // EOS token ID
+//! assert_eq!(stoppers.len(), 3);
 //! ```
 //!
 //! ## Performance Characteristics
@@ -61,11 +64,13 @@ use crate::types::FinishReason;
 
 // Stopper implementations
 pub mod eos;
+pub mod factory;
 pub mod max_tokens;
 pub mod repetition;
 
 // Re-export stopper implementations
 pub use eos::EosStopper;
+pub use factory::StopperFactory;
 pub use max_tokens::MaxTokensStopper;
 pub use repetition::RepetitionStopper;
 
@@ -42,6 +42,7 @@ use tracing::{debug, info, warn};
 ///     max_pattern_length: 20,
 ///     min_repetitions: 2,
 ///     window_size: 500,
+///     ..RepetitionConfig::default()
 /// };
 ///
 /// // Balanced detection (default)
@@ -53,6 +54,7 @@ use tracing::{debug, info, warn};
 ///     max_pattern_length: 200,
 ///     min_repetitions: 4,
 ///     window_size: 2000,
+///     ..RepetitionConfig::default()
 /// };
 /// ```
 #[derive(Debug, Clone)]
@@ -101,6 +103,27 @@ pub struct RepetitionConfig {
     ///
     /// Must be > 0. Memory usage scales linearly with this value.
     pub window_size: usize,
+
+    /// What patterns are made of: token IDs (default) or decoded text.
+    ///
+    /// In [`RepetitionMode::Tokens`] mode, pattern lengths and the window size
+    /// are counted in tokens instead of characters.
+    pub mode: RepetitionMode,
+}
+
+/// Unit that [`RepetitionStopper`] looks for repeated patterns in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepetitionMode {
+    /// Repeated substrings of the decoded text, fed with
+    /// [`RepetitionStopper::add_token_text`]
+    Text,
+
+    /// Repeated cycles of token IDs, fed with [`RepetitionStopper::add_token`].
+    ///
+    /// Catches loops whose text varies only in whitespace or token boundaries, and
+    /// costs O(`max_pattern_length`) per token instead of rescanning the window.
+    #[default]
+    Tokens,
 }
 
 impl Default for RepetitionConfig {
@@ -133,6 +156,7 @@ impl Default for RepetitionConfig {
             max_pattern_length: 100,
             min_repetitions: 3,
             window_size: 1000,
+            mode: RepetitionMode::Tokens,
         }
     }
 }
@@ -172,6 +196,7 @@ impl RepetitionConfig {
     ///     max_pattern_length: 100,
     ///     min_repetitions: 3,
     ///     window_size: 1000,
+    ///     ..RepetitionConfig::default()
     /// };
     /// assert!(bad_config.validate().is_err());
     /// ```
@@ -263,6 +288,7 @@ impl RepetitionConfig {
 ///     max_pattern_length: 50,
 ///     min_repetitions: 2,
 ///     window_size: 500,
+///     ..RepetitionConfig::default()
 /// };
 /// let sensitive_stopper = RepetitionStopper::new(sensitive_config);
 ///
@@ -299,6 +325,15 @@ pub struct RepetitionStopper {
     /// the total character count rather than the number of token strings.
     /// Used to enforce the window_size limit efficiently.
     current_window_size: usize,
+
+    /// Ring buffer of the last `window_size` token IDs, in Tokens mode.
+    token_window: VecDeque<u32>,
+
+    /// For each cycle length from `min_pattern_length` to `max_pattern_length`, the
+    /// number of consecutive recent tokens equal to the token that many positions
+    /// earlier. A cycle of length `p` has repeated `n` times once its run reaches
+    /// `p * (n - 1)`.
+    cycle_runs: Vec<usize>,
 }
 
 impl RepetitionStopper {
@@ -326,6 +361,7 @@ impl RepetitionStopper {
     ///     max_pattern_length: 50,
     ///     min_repetitions: 2,
     ///     window_size: 800,
+    ///     ..RepetitionConfig::default()
     /// };
     ///
     /// // Validate configuration before use
@@ -351,13 +387,70 @@ impl RepetitionStopper {
             warn!("RepetitionStopper created with invalid config: {}", err);
         }
 
+        let cycle_lengths = (config.min_pattern_length..=config.max_pattern_length).count();
         Self {
             config,
             text_window: VecDeque::new(),
             current_window_size: 0,
+            token_window: VecDeque::new(),
+            cycle_runs: vec![0; cycle_lengths],
         }
     }
 
+    /// Add a newly generated token ID for cycle detection in Tokens mode.
+    ///
+    /// Each call updates one run counter per cycle length, so the cost per token is
+    /// O(`max_pattern_length` - `min_pattern_length`) and memory stays bounded by
+    /// `window_size`. Ignored in Text mode.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use llama_agent::stopper::{RepetitionStopper, repetition::RepetitionConfig};
+    ///
+    /// let mut stopper = RepetitionStopper::new(RepetitionConfig::default());
+    ///
+    /// // Add token IDs as they're sampled
+    /// for token_id in [9906, 1917, 13] {
+    ///     stopper.add_token(token_id);
+    /// }
+    /// ```
+    pub fn add_token(&mut self, token_id: u32) {
+        if self.config.mode != RepetitionMode::Tokens {
+            return;
+        }
+
+        let len = self.token_window.len();
+        for (offset, run) in self.cycle_runs.iter_mut().enumerate() {
+            let cycle_length = self.config.min_pattern_length + offset;
+            let repeats = cycle_length > 0
+                && cycle_length <= len
+                && self.token_window[len - cycle_length] == token_id;
+            *run = if repeats { *run + 1 } else { 0 };
+        }
+
+        self.token_window.push_back(token_id);
+        if self.token_window.len() > self.config.window_size {
+            self.token_window.pop_front();
+        }
+    }
+
+    /// Find the shortest token cycle repeated at least `min_repetitions` times
+    /// within the window, returning its length and repetition count
+    fn detect_token_cycle(&self) -> Option<(usize, usize)> {
+        self.cycle_runs
+            .iter()
+            .enumerate()
+            .map(|(offset, run)| (self.config.min_pattern_length + offset, *run))
+            .find(|&(cycle_length, run)| {
+                cycle_length > 0
+                    && cycle_length <= self.token_window.len()
+                    && cycle_length * self.config.min_repetitions <= self.config.window_size
+                    && run >= cycle_length * self.config.min_repetitions.saturating_sub(1)
+            })
+            .map(|(cycle_length, run)| (cycle_length, run / cycle_length + 1))
+    }
+
     /// Add newly generated token text to the sliding window.
     ///
     /// This method is called as new tokens are generated to maintain the sliding window
@@ -487,23 +580,25 @@ impl RepetitionStopper {
 
         None
     }
-}
 
-impl Stopper for RepetitionStopper {
-    fn should_stop(
-        &mut self,
-        _context: &LlamaContext,
-        _batch: &LlamaBatch,
-    ) -> Option<FinishReason> {
-        // RepetitionStopper requires integration with token-to-text conversion since
-        // it analyzes textual patterns rather than raw token IDs. This integration
-        // happens in the generation queue where decoded token text is available.
-        //
-        // The design rationale:
-        // 1. Token batches contain token IDs, not decoded text
-        // 2. Text decoding requires the model's tokenizer
-        // 3. Pattern detection needs actual text content, not token sequences
-        // 4. Integration point is in queue.rs where both tokens and text are available
+    /// Evaluate the tokens and text fed so far for the configured mode.
+    ///
+    /// This is what [`Stopper::should_stop`] does; it needs no model context, so
+    /// generation backends without one can call it directly.
+    pub fn check(&mut self) -> Option<FinishReason> {
+        if self.config.mode == RepetitionMode::Tokens {
+            let (cycle_length, count) = self.detect_token_cycle()?;
+            info!(
+                pattern_length = cycle_length,
+                repetition_count = count,
+                window_size = self.token_window.len(),
+                "RepetitionStopper triggered on token cycle - stopping generation"
+            );
+            return Some(FinishReason::Stopped(format!(
+                "Repetition detected: {}-token cycle repeated {} times",
+                cycle_length, count
+            )));
+        }
 
         // Early return if insufficient text for analysis
         if self.text_window.is_empty() {
@@ -553,6 +648,19 @@ impl Stopper for RepetitionStopper {
             None => None,
         }
     }
+}
+
+impl Stopper for RepetitionStopper {
+    fn should_stop(
+        &mut self,
+        _context: &LlamaContext,
+        _batch: &LlamaBatch,
+    ) -> Option<FinishReason> {
+        // Token batches do not carry the sampled token IDs or decoded text, so the
+        // generation queue feeds both through add_token and add_token_text and this
+        // only evaluates what was fed for the configured mode.
+        self.check()
+    }
 
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
@@ -619,6 +727,7 @@ mod tests {
             max_pattern_length: 20,
             min_repetitions: 2,
             window_size: 10, // Very small window for testing
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -640,6 +749,7 @@ mod tests {
             max_pattern_length: 10,
             min_repetitions: 3,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -663,6 +773,7 @@ mod tests {
             max_pattern_length: 10,
             min_repetitions: 3,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -681,6 +792,7 @@ mod tests {
             max_pattern_length: 10,
             min_repetitions: 3,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -699,6 +811,7 @@ mod tests {
             max_pattern_length: 20,
             min_repetitions: 2,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -722,6 +835,7 @@ mod tests {
             max_pattern_length: 10,
             min_repetitions: 2,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -747,6 +861,7 @@ mod tests {
             max_pattern_length: 20,
             min_repetitions: 2,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -765,6 +880,7 @@ mod tests {
             max_pattern_length: 20,
             min_repetitions: 2,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -782,6 +898,7 @@ mod tests {
             max_pattern_length: 15,
             min_repetitions: 3,
             window_size: 200,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -806,6 +923,7 @@ mod tests {
             max_pattern_length: 15,
             min_repetitions: 3,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -866,6 +984,7 @@ mod tests {
             max_pattern_length: 10,
             min_repetitions: 2,
             window_size: 10000,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -886,6 +1005,7 @@ mod tests {
             max_pattern_length: 0,
             min_repetitions: 0,
             window_size: 0,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -905,6 +1025,7 @@ mod tests {
             max_pattern_length: 10,
             min_repetitions: 2,
             window_size: 100,
+            mode: RepetitionMode::Text,
         };
         let mut stopper = RepetitionStopper::new(config);
 
@@ -919,4 +1040,127 @@ mod tests {
         assert_eq!(pattern, "🔥");
         assert_eq!(count, 2);
     }
+
+    fn token_config(min: usize, max: usize, repetitions: usize) -> RepetitionConfig {
+        RepetitionConfig {
+            min_pattern_length: min,
+            max_pattern_length: max,
+            min_repetitions: repetitions,
+            window_size: 200,
+            mode: RepetitionMode::Tokens,
+        }
+    }
+
+    fn feed_tokens(stopper: &mut RepetitionStopper, tokens: &[u32]) -> Option<(usize, usize)> {
+        for &token in tokens {
+            stopper.add_token(token);
+            if let Some(detected) = stopper.detect_token_cycle() {
+                return Some(detected);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_token_mode_is_default() {
+        assert_eq!(RepetitionConfig::default().mode, RepetitionMode::Tokens);
+    }
+
+    #[test]
+    fn test_token_cycle_detection() {
+        let mut stopper = RepetitionStopper::new(token_config(3, 10, 3));
+        let cycle = [11, 12, 13, 14];
+
+        // Detected on the last token of the third repetition, not before
+        let tokens: Vec<u32> = cycle.iter().cycle().take(12).copied().collect();
+        assert_eq!(feed_tokens(&mut stopper, &tokens[..11]), None);
+        assert_eq!(feed_tokens(&mut stopper, &tokens[11..]), Some((4, 3)));
+    }
+
+    #[test]
+    fn test_token_cycle_shorter_than_minimum_is_found_as_multiple() {
+        // A 2-token loop repeats as a 4-token cycle once it is long enough
+        let mut stopper = RepetitionStopper::new(token_config(4, 10, 2));
+        assert_eq!(feed_tokens(&mut stopper, &[1, 2, 1, 2, 1, 2, 1]), None);
+        assert_eq!(feed_tokens(&mut stopper, &[2]), Some((4, 2)));
+    }
+
+    #[test]
+    fn test_modes_ignore_other_input() {
+        let mut text_stopper = RepetitionStopper::new(RepetitionConfig {
+            mode: RepetitionMode::Text,
+            ..token_config(1, 10, 2)
+        });
+        for _ in 0..4 {
+            text_stopper.add_token(7);
+        }
+        assert!(text_stopper.token_window.is_empty());
+        assert_eq!(text_stopper.detect_token_cycle(), None);
+
+        let mut token_stopper = RepetitionStopper::new(token_config(1, 10, 2));
+        for _ in 0..4 {
+            token_stopper.add_token_text("again ".to_string());
+        }
+        assert_eq!(token_stopper.detect_token_cycle(), None);
+    }
+
+    #[test]
+    fn test_token_window_is_bounded() {
+        let mut stopper = RepetitionStopper::new(RepetitionConfig {
+            window_size: 16,
+            ..token_config(2, 8, 3)
+        });
+        // A cycle that needs more than the window to repeat enough is not reported
+        let cycle: Vec<u32> = (0..8).collect();
+        let tokens: Vec<u32> = cycle.iter().cycle().take(100).copied().collect();
+        assert_eq!(feed_tokens(&mut stopper, &tokens), None);
+        assert_eq!(stopper.token_window.len(), 16);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn prop_repeated_cycle_is_detected(
+            prefix in proptest::collection::vec(1000u32..2000, 0..20),
+            cycle in proptest::collection::vec(0u32..50, 3..=12),
+            repetitions in 2usize..5,
+        ) {
+            let mut stopper = RepetitionStopper::new(token_config(3, 12, repetitions));
+            let mut tokens = prefix;
+            for _ in 0..repetitions {
+                tokens.extend(&cycle);
+            }
+
+            // The reported cycle is no longer than the generated one and repeats enough
+            let (cycle_length, count) = feed_tokens(&mut stopper, &tokens).unwrap();
+            proptest::prop_assert!(cycle_length <= cycle.len());
+            proptest::prop_assert!(count >= repetitions);
+        }
+
+        #[test]
+        fn prop_distinct_tokens_never_repeat(start in 0u32..1000, len in 0usize..300) {
+            let mut stopper = RepetitionStopper::new(token_config(1, 20, 2));
+            let tokens: Vec<u32> = (start..start + len as u32).collect();
+            proptest::prop_assert_eq!(feed_tokens(&mut stopper, &tokens), None);
+        }
+
+        #[test]
+        fn prop_near_miss_is_not_detected(
+            cycle_length in 3usize..=12,
+            repetitions in 2usize..5,
+            changed in 0usize..12,
+        ) {
+            // Distinct tokens, so the only cycles are the generated one and its multiples
+            let cycle: Vec<u32> = (0..cycle_length as u32).collect();
+            let mut tokens: Vec<u32> = (1000..1010).collect();
+            for _ in 0..repetitions {
+                tokens.extend(&cycle);
+            }
+            // Break the last repetition at one position
+            let last = tokens.len() - cycle_length + changed % cycle_length;
+            tokens[last] = 9999;
+
+            let mut stopper = RepetitionStopper::new(token_config(3, 12, repetitions));
+            proptest::prop_assert_eq!(feed_tokens(&mut stopper, &tokens), None);
+        }
+    }
 }
@@ -0,0 +1,408 @@
+//! Scripted model backend for generation tests
+
+use crate::agent::session_manager_for;
+use crate::backend::{BackendFactory, BatchBackend, ModelBackend};
+use crate::clock::SystemClock;
+use crate::dependency_analysis::DependencyAnalyzer;
+use crate::mcp::MCPClient;
+use crate::model::ModelManager;
+use crate::queue::RequestQueue;
+use crate::stopper::{RepetitionStopper, Stopper, StopperFactory};
+use crate::types::{
+    AgentConfig, AgentError, FinishReason, ModelConfig, ModelMetadata, QueueError, StoppingConfig,
+};
+use crate::AgentServer;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Scripted stand-in for a model, for testing generation without a GGUF file.
+///
+/// Each request takes the next queued reply and samples its tokens in order, then
+/// [`FakeModel::EOS_TOKEN`]; once the replies run out, requests end right away.
+/// Batched requests, and the completions of an `n > 1` request, take replies in the
+/// order their sequences are numbered.
+/// Clones share the replies, so a test can keep one handle after giving another
+/// to [`agent_with_fake_model`].
+#[derive(Debug, Clone, Default)]
+pub struct FakeModel {
+    state: Arc<Mutex<FakeModelState>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeModelState {
+    replies: VecDeque<Vec<u32>>,
+    /// Text of each token, indexed from `FakeModel::FIRST_PIECE_ID`
+    pieces: Vec<String>,
+    ids: HashMap<String, u32>,
+    token_delay: Duration,
+    /// Backends panic on prompts containing this text
+    panic_on: Option<String>,
+    tokens_sampled: usize,
+    /// Sequence of each token sampled by batched requests
+    batch_samples: Vec<usize>,
+    in_flight: usize,
+    peak_in_flight: usize,
+    /// Reported as the loaded model's
+    metadata: Option<ModelMetadata>,
+}
+
+impl FakeModelState {
+    /// Count `requests` more requests generating
+    fn start(&mut self, requests: usize) {
+        self.in_flight += requests;
+        self.peak_in_flight = self.peak_in_flight.max(self.in_flight);
+    }
+
+    /// Text of a token
+    fn piece(&self, token: u32) -> Result<String, String> {
+        token
+            .checked_sub(FakeModel::FIRST_PIECE_ID)
+            .and_then(|index| self.pieces.get(index as usize))
+            .cloned()
+            .ok_or_else(|| format!("token {} has no text", token))
+    }
+
+    fn intern(&mut self, piece: String) -> u32 {
+        if let Some(&id) = self.ids.get(&piece) {
+            return id;
+        }
+        let id = FakeModel::FIRST_PIECE_ID + self.pieces.len() as u32;
+        self.pieces.push(piece.clone());
+        self.ids.insert(piece, id);
+        id
+    }
+}
+
+impl FakeModel {
+    /// Token starting every prompt
+    pub const BOS_TOKEN: u32 = 1;
+    /// Token ending every reply
+    pub const EOS_TOKEN: u32 = 2;
+    /// Token standing for each word of a prompt
+    pub const PROMPT_TOKEN: u32 = 3;
+    const FIRST_PIECE_ID: u32 = 4;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a reply made of tokens with the given texts
+    pub fn with_reply<I, S>(self, pieces: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.push_reply(pieces);
+        self
+    }
+
+    /// Queue a reply made of tokens with the given texts
+    pub fn push_reply<I, S>(&self, pieces: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut state = self.state.lock().unwrap();
+        let tokens = pieces
+            .into_iter()
+            .map(|piece| state.intern(piece.into()))
+            .collect();
+        state.replies.push_back(tokens);
+    }
+
+    /// Sleep before sampling each token, so a test can act while a request generates
+    pub fn with_token_delay(self, delay: Duration) -> Self {
+        self.state.lock().unwrap().token_delay = delay;
+        self
+    }
+
+    /// Panic while tokenizing any prompt containing `text`, as a bug in generation would
+    pub fn with_panic_on(self, text: impl Into<String>) -> Self {
+        self.state.lock().unwrap().panic_on = Some(text.into());
+        self
+    }
+
+    /// Report `metadata` as the loaded model's, as the loader would after loading a file
+    pub fn with_metadata(self, metadata: ModelMetadata) -> Self {
+        self.state.lock().unwrap().metadata = Some(metadata);
+        self
+    }
+
+    /// Id of the token with this text, if a queued reply used it
+    pub fn token_id(&self, piece: &str) -> Option<u32> {
+        self.state.lock().unwrap().ids.get(piece).copied()
+    }
+
+    /// Tokens sampled by all requests so far, end tokens included
+    pub fn tokens_sampled(&self) -> usize {
+        self.state.lock().unwrap().tokens_sampled
+    }
+
+    /// Sequence of each token sampled by batched requests so far, in sampling order
+    pub fn batch_samples(&self) -> Vec<usize> {
+        self.state.lock().unwrap().batch_samples.clone()
+    }
+
+    /// Most requests generating at the same time so far
+    pub fn peak_in_flight(&self) -> usize {
+        self.state.lock().unwrap().peak_in_flight
+    }
+
+    /// Replies no request has taken yet
+    pub fn remaining_replies(&self) -> usize {
+        self.state.lock().unwrap().replies.len()
+    }
+}
+
+impl BackendFactory for FakeModel {
+    fn create_backend(&self) -> Box<dyn ModelBackend + Send> {
+        let mut state = self.state.lock().unwrap();
+        state.start(1);
+        Box::new(FakeModelBackend {
+            model: self.clone(),
+            reply: state.replies.pop_front().unwrap_or_default().into(),
+            token_delay: state.token_delay,
+            panic_on: state.panic_on.clone(),
+        })
+    }
+
+    fn tokenize(&self, text: &str) -> Result<Vec<u32>, QueueError> {
+        panic_if_triggered(self.state.lock().unwrap().panic_on.as_deref(), text);
+        Ok(tokenize_prompt(text))
+    }
+
+    fn create_batch_backend(&self, sequences: usize) -> Option<Box<dyn BatchBackend + Send>> {
+        let mut state = self.state.lock().unwrap();
+        state.start(sequences);
+        let replies = (0..sequences)
+            .map(|_| state.replies.pop_front().unwrap_or_default().into())
+            .collect();
+        Some(Box::new(FakeBatchBackend {
+            model: self.clone(),
+            replies,
+            token_delay: state.token_delay,
+        }))
+    }
+}
+
+/// Panic as [`FakeModel::with_panic_on`] asks when `text` contains `panic_on`
+fn panic_if_triggered(panic_on: Option<&str>, text: &str) {
+    if let Some(trigger) = panic_on.filter(|t| text.contains(t)) {
+        panic!("fake model panicked on '{}'", trigger);
+    }
+}
+
+/// One [`FakeModel::PROMPT_TOKEN`] per word, after [`FakeModel::BOS_TOKEN`]
+fn tokenize_prompt(text: &str) -> Vec<u32> {
+    let words = text.split_whitespace().map(|_| FakeModel::PROMPT_TOKEN);
+    std::iter::once(FakeModel::BOS_TOKEN).chain(words).collect()
+}
+
+/// [`ModelBackend`] replaying one reply of a [`FakeModel`]
+#[derive(Debug)]
+pub struct FakeModelBackend {
+    model: FakeModel,
+    reply: VecDeque<u32>,
+    token_delay: Duration,
+    panic_on: Option<String>,
+}
+
+impl Drop for FakeModelBackend {
+    fn drop(&mut self) {
+        self.model.state.lock().unwrap().in_flight -= 1;
+    }
+}
+
+impl ModelBackend for FakeModelBackend {
+    fn tokenize(&self, text: &str) -> Result<Vec<u32>, QueueError> {
+        panic_if_triggered(self.panic_on.as_deref(), text);
+        Ok(tokenize_prompt(text))
+    }
+
+    fn decode(&mut self, _tokens: &[u32], _start_pos: usize) -> Result<(), QueueError> {
+        Ok(())
+    }
+
+    fn sample_next_token(&mut self) -> u32 {
+        if !self.token_delay.is_zero() {
+            std::thread::sleep(self.token_delay);
+        }
+        self.model.state.lock().unwrap().tokens_sampled += 1;
+        self.reply.pop_front().unwrap_or(FakeModel::EOS_TOKEN)
+    }
+
+    fn token_to_str(&self, token: u32) -> Result<String, String> {
+        self.model.state.lock().unwrap().piece(token)
+    }
+
+    fn is_eog(&self, token: u32) -> bool {
+        token == FakeModel::EOS_TOKEN
+    }
+
+    fn stoppers(&self, config: &StoppingConfig) -> Vec<Box<dyn Stopper>> {
+        StopperFactory::from_token_ids(config, FakeModel::EOS_TOKEN, vec![FakeModel::EOS_TOKEN])
+    }
+
+    fn should_stop(&self, stopper: &mut dyn Stopper) -> Option<FinishReason> {
+        // Only repetition detection works without a llama.cpp context; end tokens and
+        // the token limit are also checked by the generation loop itself
+        stopper
+            .as_any_mut()
+            .downcast_mut::<RepetitionStopper>()
+            .and_then(RepetitionStopper::check)
+    }
+
+    fn end_of_turn_token_ids(&self, _config: &ModelConfig) -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+/// [`BatchBackend`] replaying one reply of a [`FakeModel`] per sequence
+#[derive(Debug)]
+struct FakeBatchBackend {
+    model: FakeModel,
+    replies: Vec<VecDeque<u32>>,
+    token_delay: Duration,
+}
+
+impl Drop for FakeBatchBackend {
+    fn drop(&mut self) {
+        self.model.state.lock().unwrap().in_flight -= self.replies.len();
+    }
+}
+
+impl BatchBackend for FakeBatchBackend {
+    fn decode(&mut self, _inputs: &[(usize, &[u32], usize)]) -> Result<(), QueueError> {
+        Ok(())
+    }
+
+    fn sample_next_token(&mut self, seq: usize) -> u32 {
+        if !self.token_delay.is_zero() {
+            std::thread::sleep(self.token_delay);
+        }
+        let mut state = self.model.state.lock().unwrap();
+        state.tokens_sampled += 1;
+        state.batch_samples.push(seq);
+        self.replies[seq]
+            .pop_front()
+            .unwrap_or(FakeModel::EOS_TOKEN)
+    }
+
+    fn token_to_str(&self, token: u32) -> Result<String, String> {
+        self.model.state.lock().unwrap().piece(token)
+    }
+
+    fn is_eog(&self, token: u32) -> bool {
+        token == FakeModel::EOS_TOKEN
+    }
+
+    fn end_of_turn_token_ids(&self, _config: &ModelConfig) -> Vec<u32> {
+        Vec::new()
+    }
+}
+
+/// An [`AgentServer`] whose generation requests run on `model`.
+///
+/// No model is loaded and configured MCP servers are not started.
+pub fn agent_with_fake_model(
+    config: AgentConfig,
+    model: FakeModel,
+) -> Result<AgentServer, AgentError> {
+    agent_with_fake_models(config, model, Vec::new())
+}
+
+/// Like [`agent_with_fake_model`], also serving each of `named` under its name.
+///
+/// A name listed in `AgentConfig::models` takes that model config; others take
+/// `AgentConfig::model`.
+pub fn agent_with_fake_models(
+    config: AgentConfig,
+    model: FakeModel,
+    named: Vec<(&str, FakeModel)>,
+) -> Result<AgentServer, AgentError> {
+    config.validate()?;
+
+    let (model_manager, request_queue) = fake_route(&config, &config.model, model)?;
+    let mut agent = AgentServer::new(
+        model_manager,
+        request_queue,
+        Arc::new(session_manager_for(&config, Arc::new(SystemClock))?),
+        Arc::new(MCPClient::new()),
+        Arc::new(DependencyAnalyzer::new(
+            config.parallel_execution_config.clone(),
+        )),
+        config.clone(),
+    );
+    for (name, model) in named {
+        let model_config = config
+            .models
+            .iter()
+            .find(|named| named.name == name)
+            .map_or(&config.model, |named| &named.model);
+        let (model_manager, request_queue) = fake_route(&config, model_config, model)?;
+        agent = agent.with_model(name, model_manager, request_queue);
+    }
+    Ok(agent)
+}
+
+/// A model manager that never loads, and a queue generating on `model`
+fn fake_route(
+    config: &AgentConfig,
+    model_config: &ModelConfig,
+    model: FakeModel,
+) -> Result<(Arc<ModelManager>, Arc<RequestQueue>), AgentError> {
+    let mut model_manager = ModelManager::new(model_config.clone())?
+        .with_context_pool_size(config.queue_config.effective_context_pool_size());
+    if let Some(metadata) = model.state.lock().unwrap().metadata.clone() {
+        model_manager = model_manager.with_metadata(metadata);
+    }
+    let model_manager = Arc::new(model_manager);
+    let request_queue = Arc::new(RequestQueue::with_backend_factory(
+        model_manager.clone(),
+        config.queue_config.clone(),
+        Arc::new(model),
+    ));
+    Ok((model_manager, request_queue))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_model_replays_replies_in_order() {
+        let model = FakeModel::new()
+            .with_reply(["Hello", " world", "Hello"])
+            .with_reply(["Bye"]);
+        assert_eq!(model.token_id("Hello"), Some(FakeModel::FIRST_PIECE_ID));
+        assert_eq!(model.token_id("missing"), None);
+
+        let mut first = model.create_backend();
+        assert_eq!(
+            first.tokenize("two words").unwrap(),
+            [
+                FakeModel::BOS_TOKEN,
+                FakeModel::PROMPT_TOKEN,
+                FakeModel::PROMPT_TOKEN
+            ]
+        );
+        let tokens: Vec<u32> = (0..4).map(|_| first.sample_next_token()).collect();
+        let hello = model.token_id("Hello").unwrap();
+        assert_eq!(tokens[0], hello);
+        assert_eq!(tokens[2], hello);
+        assert!(first.is_eog(tokens[3]));
+        assert_eq!(first.token_to_str(tokens[1]).unwrap(), " world");
+        assert!(first.token_to_str(FakeModel::EOS_TOKEN).is_err());
+
+        let mut second = model.create_backend();
+        let token = second.sample_next_token();
+        assert_eq!(second.token_to_str(token).unwrap(), "Bye");
+        assert_eq!(model.remaining_replies(), 0);
+        assert_eq!(model.tokens_sampled(), 5);
+        assert_eq!(
+            model.create_backend().sample_next_token(),
+            FakeModel::EOS_TOKEN
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Helpers for testing code built on this crate
+//!
+//! [`FakeModel`], [`agent_with_fake_model`] and [`agent_with_fake_models`] need the
+//! `fake-backend` feature.
+
+use crate::clock::Clock;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+#[cfg(any(test, feature = "fake-backend"))]
+mod fake_model;
+
+#[cfg(any(test, feature = "fake-backend"))]
+pub use fake_model::{agent_with_fake_model, agent_with_fake_models, FakeModel, FakeModelBackend};
+
+/// [`Clock`] that only moves when told to.
+///
+/// Clones share the same time, so a test can keep one handle and advance the
+/// clock given to a `SessionManager` or `AgentServer`.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl MockClock {
+    /// Create a clock stopped at `now`
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// Set the clock to `now`, which may be earlier than the current time
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl Default for MockClock {
+    /// A fixed point in time, so test results do not depend on when they run
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_is_shared_between_clones() {
+        let clock = MockClock::default();
+        let handle = clock.clone();
+        let start = clock.now();
+
+        handle.advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), start + Duration::from_secs(5));
+
+        handle.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
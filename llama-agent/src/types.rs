@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use futures::Stream;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::time::{Duration, SystemTime};
 use thiserror::Error;
@@ -10,9 +11,20 @@ use ulid::Ulid;
 use std::path::PathBuf;
 
 // Re-export model types from llama-loader
-pub use llama_loader::{ModelConfig, ModelError, ModelSource, RetryConfig};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub use llama_loader::resolver::detect_quantization;
+
+use crate::postprocess::{PostProcessing, PostProcessor};
+use crate::redaction::RedactionPolicy;
+use crate::validation::generation_request::ParameterConfig;
+pub use crate::validation::ValidationSeverity;
+pub use llama_loader::{
+    CustomTemplate, HfGenerationDefaults, ModelConfig, ModelError, ModelMetadata, ModelSource,
+    RetryConfig, RoleFormat, TemplateOverride,
+};
+
+/// Session identifier. Ids compare by creation time to the millisecond (ids created in the
+/// same millisecond compare in random order), and their serialized strings sort the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct SessionId(Ulid);
 
 impl SessionId {
@@ -27,6 +39,23 @@ impl SessionId {
     pub fn as_ulid(&self) -> Ulid {
         self.0
     }
+
+    /// When the id was created, to the millisecond
+    pub fn timestamp(&self) -> SystemTime {
+        self.0.datetime()
+    }
+}
+
+impl From<Ulid> for SessionId {
+    fn from(ulid: Ulid) -> Self {
+        Self(ulid)
+    }
+}
+
+impl AsRef<Ulid> for SessionId {
+    fn as_ref(&self) -> &Ulid {
+        &self.0
+    }
 }
 
 impl std::fmt::Display for SessionId {
@@ -49,10 +78,12 @@ impl Default for SessionId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Tool call identifier, ordered by creation time like [`SessionId`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ToolCallId(Ulid);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Prompt identifier, ordered by creation time like [`SessionId`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct PromptId(Ulid);
 
 impl PromptId {
@@ -67,6 +98,23 @@ impl PromptId {
     pub fn as_ulid(&self) -> Ulid {
         self.0
     }
+
+    /// When the id was created, to the millisecond
+    pub fn timestamp(&self) -> SystemTime {
+        self.0.datetime()
+    }
+}
+
+impl From<Ulid> for PromptId {
+    fn from(ulid: Ulid) -> Self {
+        Self(ulid)
+    }
+}
+
+impl AsRef<Ulid> for PromptId {
+    fn as_ref(&self) -> &Ulid {
+        &self.0
+    }
 }
 
 impl std::fmt::Display for PromptId {
@@ -101,6 +149,23 @@ impl ToolCallId {
     pub fn as_ulid(&self) -> Ulid {
         self.0
     }
+
+    /// When the id was created, to the millisecond
+    pub fn timestamp(&self) -> SystemTime {
+        self.0.datetime()
+    }
+}
+
+impl From<Ulid> for ToolCallId {
+    fn from(ulid: Ulid) -> Self {
+        Self(ulid)
+    }
+}
+
+impl AsRef<Ulid> for ToolCallId {
+    fn as_ref(&self) -> &Ulid {
+        &self.0
+    }
 }
 
 impl std::fmt::Display for ToolCallId {
@@ -130,6 +195,135 @@ pub struct Message {
     pub tool_call_id: Option<ToolCallId>,
     pub tool_name: Option<String>,
     pub timestamp: SystemTime,
+    /// Structured content (images, resources, text blocks) kept alongside `content`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<MessageAttachment>,
+}
+
+impl Message {
+    /// Text rendered into prompts: `content` followed by a placeholder line for each
+    /// non-text attachment
+    pub fn rendered_content(&self) -> String {
+        let placeholders: Vec<String> = self
+            .attachments
+            .iter()
+            .filter_map(MessageAttachment::placeholder)
+            .collect();
+
+        if placeholders.is_empty() {
+            return self.content.clone();
+        }
+
+        let mut rendered = self.content.clone();
+        for placeholder in placeholders {
+            if !rendered.is_empty() {
+                rendered.push('\n');
+            }
+            rendered.push_str(&placeholder);
+        }
+        rendered
+    }
+}
+
+/// Structured content attached to a message, as returned by MCP prompts and tools
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageAttachment {
+    Text {
+        text: String,
+    },
+    /// Base64-encoded image data
+    Image {
+        data: String,
+        mime_type: String,
+    },
+    ResourceLink {
+        uri: String,
+        mime_type: Option<String>,
+        text: Option<String>,
+    },
+}
+
+impl MessageAttachment {
+    /// Placeholder shown to the model for non-text attachments, e.g. "[image image/png 34KB]"
+    pub fn placeholder(&self) -> Option<String> {
+        match self {
+            MessageAttachment::Text { .. } => None,
+            MessageAttachment::Image { data, mime_type } => Some(format!(
+                "[image {} {}]",
+                mime_type,
+                format_attachment_size(base64_decoded_len(data))
+            )),
+            MessageAttachment::ResourceLink { uri, mime_type, .. } => Some(match mime_type {
+                Some(mime_type) => format!("[resource {} {}]", uri, mime_type),
+                None => format!("[resource {}]", uri),
+            }),
+        }
+    }
+
+    /// Text carried by this attachment, if any
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            MessageAttachment::Text { text } => Some(text),
+            MessageAttachment::ResourceLink { text, .. } => text.as_deref(),
+            MessageAttachment::Image { .. } => None,
+        }
+    }
+
+    /// Convert the content blocks of an MCP `tools/call` result, one attachment per block
+    pub fn from_tool_result(result: &serde_json::Value) -> Vec<MessageAttachment> {
+        let Some(blocks) = result.get("content").and_then(|c| c.as_array()) else {
+            return Vec::new();
+        };
+
+        blocks
+            .iter()
+            .filter_map(|block| {
+                let str_field = |value: &serde_json::Value, key: &str| {
+                    value.get(key).and_then(|v| v.as_str()).map(str::to_string)
+                };
+
+                match block.get("type").and_then(|t| t.as_str())? {
+                    "text" => Some(MessageAttachment::Text {
+                        text: str_field(block, "text")?,
+                    }),
+                    "image" => Some(MessageAttachment::Image {
+                        data: str_field(block, "data")?,
+                        mime_type: str_field(block, "mimeType")
+                            .unwrap_or_else(|| "application/octet-stream".to_string()),
+                    }),
+                    "resource" => {
+                        let resource = block.get("resource")?;
+                        Some(MessageAttachment::ResourceLink {
+                            uri: str_field(resource, "uri")?,
+                            mime_type: str_field(resource, "mimeType"),
+                            text: str_field(resource, "text"),
+                        })
+                    }
+                    "resource_link" => Some(MessageAttachment::ResourceLink {
+                        uri: str_field(block, "uri")?,
+                        mime_type: str_field(block, "mimeType"),
+                        text: None,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Size in bytes of base64-encoded data once decoded
+fn base64_decoded_len(data: &str) -> usize {
+    let trimmed = data.trim_end_matches('=');
+    trimmed.len() * 3 / 4
+}
+
+fn format_attachment_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{}B", bytes)
+    } else {
+        format!("{}KB", (bytes + 512) / 1024)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -160,14 +354,194 @@ pub struct Session {
     pub available_prompts: Vec<PromptDefinition>,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
+    #[serde(default)]
+    pub tool_policy: ToolPolicy,
+    /// Tokens used by the session's generation requests so far
+    #[serde(default)]
+    pub usage: SessionUsage,
+}
+
+/// Cumulative token usage of a session, across every completed generation request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionUsage {
+    /// Prompt tokens decoded; each request counts its whole prompt, including earlier turns
+    pub prompt_tokens: u64,
+    /// Tokens generated
+    pub completion_tokens: u64,
+    /// Completed generation requests
+    pub requests: u64,
+}
+
+impl SessionUsage {
+    /// Add one completed generation request
+    pub fn record(&mut self, prompt_tokens: u32, completion_tokens: u32) {
+        self.prompt_tokens += u64::from(prompt_tokens);
+        self.completion_tokens += u64::from(completion_tokens);
+        self.requests += 1;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// A session without its message bodies, as returned by [`AgentAPI::list_sessions`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub id: SessionId,
+    pub message_count: usize,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+    /// Role of the last message, `None` for an empty session
+    pub last_role: Option<MessageRole>,
+    pub usage: SessionUsage,
+}
+
+impl From<&Session> for SessionSummary {
+    fn from(session: &Session) -> Self {
+        Self {
+            id: session.id,
+            message_count: session.messages.len(),
+            created_at: session.created_at,
+            updated_at: session.updated_at,
+            last_role: session.messages.last().map(|message| message.role.clone()),
+            usage: session.usage,
+        }
+    }
+}
+
+/// Which sessions [`AgentAPI::list_sessions`] returns, and which page of them.
+///
+/// Every condition that is set must hold. Sessions are ordered by `updated_at`,
+/// most recent first, then by id; `offset` and `limit` select a page of that order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionFilter {
+    /// Only sessions created strictly after this time
+    pub created_after: Option<SystemTime>,
+    /// Only sessions updated strictly after this time
+    pub updated_after: Option<SystemTime>,
+    /// Only sessions with (`true`) or without (`false`) discovered tools
+    pub has_tools: Option<bool>,
+    /// Only sessions with at least this many messages
+    pub min_messages: Option<usize>,
+    /// Only sessions with at most this many messages
+    pub max_messages: Option<usize>,
+    /// Matching sessions skipped before the first one returned
+    pub offset: usize,
+    /// Most sessions returned; `None` returns all from `offset` on
+    pub limit: Option<usize>,
+}
+
+impl SessionFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_created_after(mut self, time: SystemTime) -> Self {
+        self.created_after = Some(time);
+        self
+    }
+
+    pub fn with_updated_after(mut self, time: SystemTime) -> Self {
+        self.updated_after = Some(time);
+        self
+    }
+
+    pub fn with_has_tools(mut self, has_tools: bool) -> Self {
+        self.has_tools = Some(has_tools);
+        self
+    }
+
+    pub fn with_message_count(mut self, min: Option<usize>, max: Option<usize>) -> Self {
+        self.min_messages = min;
+        self.max_messages = max;
+        self
+    }
+
+    /// Return at most `limit` sessions, skipping the first `offset` matches
+    pub fn with_page(mut self, offset: usize, limit: usize) -> Self {
+        self.offset = offset;
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Whether `session` meets every condition of the filter, ignoring pagination
+    pub fn matches(&self, session: &Session) -> bool {
+        let message_count = session.messages.len();
+        self.created_after.is_none_or(|t| session.created_at > t)
+            && self.updated_after.is_none_or(|t| session.updated_at > t)
+            && self
+                .has_tools
+                .is_none_or(|has_tools| has_tools != session.available_tools.is_empty())
+            && self.min_messages.is_none_or(|min| message_count >= min)
+            && self.max_messages.is_none_or(|max| message_count <= max)
+    }
+}
+
+/// Which tools a session may execute
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(tag = "mode", content = "tools", rename_all = "snake_case")]
+pub enum ToolPolicy {
+    #[default]
+    AllowAll,
+    /// Only the listed tools may run
+    AllowList(Vec<String>),
+    /// Every tool except the listed ones may run
+    DenyList(Vec<String>),
+}
+
+impl ToolPolicy {
+    pub fn allows(&self, tool_name: &str) -> bool {
+        match self {
+            ToolPolicy::AllowAll => true,
+            ToolPolicy::AllowList(tools) => tools.iter().any(|t| t == tool_name),
+            ToolPolicy::DenyList(tools) => !tools.iter().any(|t| t == tool_name),
+        }
+    }
+}
+
+impl std::fmt::Display for ToolPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolPolicy::AllowAll => write!(f, "allow-all"),
+            ToolPolicy::AllowList(tools) => write!(f, "allow-list [{}]", tools.join(", ")),
+            ToolPolicy::DenyList(tools) => write!(f, "deny-list [{}]", tools.join(", ")),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MCPServerConfig {
     pub name: String,
     pub command: String,
+    #[serde(default)]
     pub args: Vec<String>,
+    #[serde(default)]
     pub timeout_secs: Option<u64>,
+    /// Fail initialization when the server replies with a protocol version this client does
+    /// not support, instead of logging a warning
+    #[serde(default)]
+    pub strict_protocol_version: bool,
+}
+
+/// What an MCP server reported about itself in its initialize response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MCPServerInfo {
+    /// Name from the server's `serverInfo`
+    pub name: String,
+    /// Version from the server's `serverInfo`
+    pub version: String,
+    /// Protocol revision the server chose, e.g. "2024-11-05"
+    pub protocol_version: String,
+    /// Capabilities the server advertised, e.g. "tools" and "prompts"
+    pub capabilities: Vec<String>,
+}
+
+impl MCPServerInfo {
+    /// Whether the server advertised a capability
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -176,6 +550,17 @@ pub struct ToolDefinition {
     pub description: String,
     pub parameters: serde_json::Value,
     pub server_name: String,
+    /// The server's own name for the tool when `name` was qualified as
+    /// `server.tool` because another server offers a tool of the same name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_name: Option<String>,
+}
+
+impl ToolDefinition {
+    /// Name to call the tool by on its server
+    pub fn server_tool_name(&self) -> &str {
+        self.original_name.as_deref().unwrap_or(&self.name)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -183,6 +568,11 @@ pub struct StoppingConfig {
     pub max_tokens: Option<usize>,
     pub repetition_detection: Option<RepetitionConfig>,
     pub eos_detection: bool,
+    /// Token ids that end generation as soon as one is sampled
+    pub stop_token_ids: Vec<u32>,
+    /// Time after which generation stops with "Time limit reached", keeping the
+    /// text generated so far
+    pub max_duration: Option<Duration>,
 }
 
 impl Default for StoppingConfig {
@@ -191,6 +581,8 @@ impl Default for StoppingConfig {
             max_tokens: None,
             repetition_detection: None,
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         }
     }
 }
@@ -207,6 +599,9 @@ impl StoppingConfig {
                 return Err("max_tokens cannot exceed 100,000 for safety".to_string());
             }
         }
+        if self.max_duration == Some(Duration::ZERO) {
+            return Err("max_duration must be greater than 0".to_string());
+        }
 
         // Validate repetition_detection config
         if let Some(ref repetition_config) = self.repetition_detection {
@@ -240,23 +635,67 @@ impl StoppingConfig {
             max_tokens,
             repetition_detection,
             eos_detection,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         config.validate()?;
         Ok(config)
     }
 }
 
-// Re-export RepetitionConfig from stopper module to avoid duplication
-pub use crate::stopper::repetition::RepetitionConfig;
+// Re-export repetition types from stopper module to avoid duplication
+pub use crate::stopper::repetition::{RepetitionConfig, RepetitionMode};
+
+/// Largest `max_tokens` a generation request may ask for
+pub const MAX_TOKENS_LIMIT: u32 = 32_768;
+
+/// How streamed text is split into chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamChunking {
+    /// One chunk per generated token
+    #[default]
+    Token,
+    /// Chunks end after whitespace, or after each character of scripts written
+    /// without spaces such as Chinese and Japanese
+    Word,
+    /// Chunks end after a sentence or line; see [`crate::chunking`]
+    Sentence,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GenerationRequest {
     pub session_id: SessionId,
     pub max_tokens: Option<u32>,
     pub temperature: Option<f32>,
     pub top_p: Option<f32>,
+    /// Seed of the random draw when sampling with temperature or top_p (default 1234);
+    /// completion `i` of an `n > 1` request draws with `seed + i`
+    pub seed: Option<u32>,
     pub stop_tokens: Vec<String>,
+    /// Token ids that end generation, checked before the token is converted to text
+    pub stop_token_ids: Vec<u32>,
+    /// Number of completions to sample from one prompt decode (default 1)
+    pub n: Option<u32>,
+    /// Whether to store the response in the session, overriding
+    /// `SessionConfig::append_responses`
+    pub append_to_session: Option<bool>,
+    /// How streamed text is split into chunks; other requests ignore it
+    pub chunking: StreamChunking,
+    /// When the queue is full, wait for a free slot (up to the request timeout) instead
+    /// of failing with `QueueError::Full`
+    pub block_on_full: bool,
     pub stopping_config: Option<StoppingConfig>,
+    /// Name of the model to generate with, one of `AgentConfig::models`; `None` or
+    /// [`DEFAULT_MODEL_NAME`] selects `AgentConfig::model`
+    pub model: Option<String>,
+    /// Time after which generation stops with "Time limit reached", returning the
+    /// text generated so far; counted from when a worker starts on the request and
+    /// combined with `max_tokens`, whichever is reached first
+    pub max_duration: Option<Duration>,
+    /// Record every generated token in [`GenerationResponse::generated_tokens`] and
+    /// the token ids of each [`StreamChunk`], for debugging garbled output. For
+    /// `n > 1`, the tokens of the first completion are recorded.
+    pub debug_tokens: bool,
 }
 
 impl GenerationRequest {
@@ -267,8 +706,17 @@ impl GenerationRequest {
             max_tokens: None,
             temperature: None,
             top_p: None,
+            seed: None,
             stop_tokens: Vec::new(),
+            stop_token_ids: Vec::new(),
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
             stopping_config: None,
+            model: None,
+            max_duration: None,
+            debug_tokens: false,
         }
     }
 
@@ -314,12 +762,66 @@ impl GenerationRequest {
         self
     }
 
+    /// Set the sampling seed using builder pattern
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
     /// Set stop_tokens using builder pattern
     pub fn with_stop_tokens(mut self, stop_tokens: Vec<String>) -> Self {
         self.stop_tokens = stop_tokens;
         self
     }
 
+    /// Set stop_token_ids using builder pattern
+    pub fn with_stop_token_ids(mut self, stop_token_ids: Vec<u32>) -> Self {
+        self.stop_token_ids = stop_token_ids;
+        self
+    }
+
+    /// Set the number of completions using builder pattern
+    pub fn with_n(mut self, n: u32) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    /// Set whether the response is stored in the session using builder pattern
+    pub fn with_append_to_session(mut self, append: bool) -> Self {
+        self.append_to_session = Some(append);
+        self
+    }
+
+    /// Set how streamed text is split into chunks using builder pattern
+    pub fn with_chunking(mut self, chunking: StreamChunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Set whether a full queue is waited on rather than rejected using builder pattern
+    pub fn with_block_on_full(mut self, block_on_full: bool) -> Self {
+        self.block_on_full = block_on_full;
+        self
+    }
+
+    /// Set the model generating the response by name using builder pattern
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Set the time budget for generation using builder pattern
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Record the generated tokens using builder pattern; see `debug_tokens`
+    pub fn with_debug_tokens(mut self, debug_tokens: bool) -> Self {
+        self.debug_tokens = debug_tokens;
+        self
+    }
+
     /// Get the effective max_tokens considering both the direct field and stopping_config
     pub fn effective_max_tokens(&self) -> Option<u32> {
         // Priority: direct max_tokens field, then stopping_config max_tokens, then None
@@ -330,6 +832,20 @@ impl GenerationRequest {
         })
     }
 
+    /// Get the effective time budget, from the direct field or else stopping_config
+    pub fn effective_max_duration(&self) -> Option<Duration> {
+        self.max_duration.or_else(|| {
+            self.stopping_config
+                .as_ref()
+                .and_then(|config| config.max_duration)
+        })
+    }
+
+    /// Whether the request only decodes the prompt, generating no tokens (`max_tokens` of 0)
+    pub fn is_prefill_only(&self) -> bool {
+        self.max_tokens == Some(0)
+    }
+
     /// Migrate max_tokens to stopping_config for consistency
     pub fn migrate_max_tokens_to_stopping_config(mut self) -> Self {
         if let Some(max_tokens) = self.max_tokens {
@@ -356,6 +872,37 @@ impl GenerationRequest {
         }
         self
     }
+
+    /// Fill the fields left unset from a model's `generation_config.json` defaults.
+    ///
+    /// The defaults are the lowest-precedence layer: anything set on the request,
+    /// including limits in `stopping_config`, wins. Defaults outside the bounds a
+    /// request is validated against are skipped, and `max_tokens` is capped at
+    /// [`MAX_TOKENS_LIMIT`].
+    pub fn with_model_defaults(mut self, defaults: &HfGenerationDefaults) -> Self {
+        if self.effective_max_tokens().is_none() {
+            self.max_tokens = defaults
+                .max_tokens()
+                .map(|max_tokens| max_tokens.min(MAX_TOKENS_LIMIT));
+        }
+        if self.temperature.is_none() {
+            self.temperature = defaults
+                .temperature
+                .filter(|temperature| (0.0..=2.0).contains(temperature));
+        }
+        if self.top_p.is_none() {
+            self.top_p = defaults.top_p.filter(|top_p| (0.0..=1.0).contains(top_p));
+        }
+        let has_stop_token_ids = !self.stop_token_ids.is_empty()
+            || self
+                .stopping_config
+                .as_ref()
+                .is_some_and(|config| !config.stop_token_ids.is_empty());
+        if !has_stop_token_ids {
+            self.stop_token_ids = defaults.eos_token_ids.clone();
+        }
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -364,6 +911,59 @@ pub struct GenerationResponse {
     pub tokens_generated: u32,
     pub generation_time: Duration,
     pub finish_reason: FinishReason,
+    /// Number of tokens in the rendered prompt
+    pub prompt_tokens: u32,
+    /// Time spent rendering, tokenizing and decoding the prompt; included in `generation_time`
+    pub prompt_time: Duration,
+    /// Time spent generating tokens after the prompt; with `prompt_time` it makes up
+    /// nearly all of `generation_time`
+    pub decode_time: Duration,
+    /// Time from the start of processing to the first generated token, not counting
+    /// time spent queued. `None` when no token was generated.
+    pub time_to_first_token: Option<Duration>,
+    /// Every completion when the request asked for `n > 1`; the first one is
+    /// also reported in the fields above. Empty for single completions.
+    pub candidates: Vec<GenerationCandidate>,
+    /// Every generated token in order, when the request set `debug_tokens`; at most
+    /// `max_tokens` per generation pass
+    pub generated_tokens: Vec<GeneratedToken>,
+}
+
+/// A generated token as the model produced it, before post-processing
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedToken {
+    pub id: u32,
+    /// Text of the token
+    pub piece: String,
+    /// Log-probability of the token; `None` when the backend does not report it
+    pub logprob: Option<f32>,
+}
+
+/// A session rendered with the chat template, as a worker would decode it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedPrompt {
+    pub prompt: String,
+    /// Tokens of `prompt`, including the beginning-of-sequence token
+    pub prompt_tokens: usize,
+    /// Tokens a model context holds
+    pub context_size: usize,
+    /// Longest prompt a request may decode
+    pub batch_size: usize,
+}
+
+impl RenderedPrompt {
+    /// Whether a generation request would reject the prompt as too long
+    pub fn exceeds_batch_size(&self) -> bool {
+        self.prompt_tokens > self.batch_size
+    }
+}
+
+/// One of several completions sampled for the same prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationCandidate {
+    pub text: String,
+    pub tokens_generated: u32,
+    pub finish_reason: FinishReason,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -385,6 +985,30 @@ pub struct ToolResult {
     pub error: Option<String>,
 }
 
+impl ToolResult {
+    /// The result of a call cancelled before the tool finished
+    pub fn cancelled(call_id: ToolCallId) -> Self {
+        Self {
+            call_id,
+            result: serde_json::Value::Null,
+            error: Some(MCPError::Cancelled.to_string()),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.error.as_ref() == Some(&MCPError::Cancelled.to_string())
+    }
+}
+
+/// Progress a running tool call reported, as in an MCP `notifications/progress`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolProgress {
+    /// Increases with every report, even when `total` is unknown
+    pub progress: f64,
+    pub total: Option<f64>,
+    pub message: Option<String>,
+}
+
 // MCP Prompt types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PromptDefinition {
@@ -393,6 +1017,17 @@ pub struct PromptDefinition {
     pub description: Option<String>,
     pub arguments: Option<Vec<PromptArgument>>,
     pub server_name: String,
+    /// The server's own name for the prompt when `name` was qualified as
+    /// `server.prompt` because another server offers a prompt of the same name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_name: Option<String>,
+}
+
+impl PromptDefinition {
+    /// Name to get the prompt by on its server
+    pub fn server_prompt_name(&self) -> &str {
+        self.original_name.as_deref().unwrap_or(&self.name)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -442,8 +1077,48 @@ pub struct GetPromptResult {
     pub messages: Vec<PromptMessage>,
 }
 
+impl PromptMessage {
+    /// Convert to a session message stamped with `timestamp`. Images and resources are
+    /// kept as attachments; a resource's text is also used as the message content.
+    pub fn to_message(&self, timestamp: SystemTime) -> Message {
+        let role = match self.role {
+            PromptRole::User => MessageRole::User,
+            PromptRole::Assistant => MessageRole::Assistant,
+        };
+
+        let (content, attachments) = match &self.content {
+            PromptContent::Text { text } => (text.clone(), Vec::new()),
+            PromptContent::Image { data, mime_type } => (
+                String::new(),
+                vec![MessageAttachment::Image {
+                    data: data.clone(),
+                    mime_type: mime_type.clone(),
+                }],
+            ),
+            PromptContent::Resource { resource } => (
+                resource.text.clone().unwrap_or_default(),
+                vec![MessageAttachment::ResourceLink {
+                    uri: resource.uri.clone(),
+                    mime_type: Some(resource.mime_type.clone()),
+                    text: resource.text.clone(),
+                }],
+            ),
+        };
+
+        Message {
+            role,
+            content,
+            tool_call_id: None,
+            tool_name: None,
+            timestamp,
+            attachments,
+        }
+    }
+}
+
 // Dependency Analysis types
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ParallelExecutionConfig {
     pub max_parallel_tools: usize,
     pub conflict_detection: bool,
@@ -452,6 +1127,10 @@ pub struct ParallelExecutionConfig {
     pub never_parallel: Vec<(String, String)>,
     pub tool_conflicts: Vec<ToolConflict>,
     pub resource_access_patterns: std::collections::HashMap<String, Vec<ResourceAccess>>,
+    /// Largest tool result text kept in the session; longer results are truncated
+    pub max_tool_result_bytes: usize,
+    /// Directory to save the full text of truncated tool results to
+    pub tool_result_spill_dir: Option<PathBuf>,
 }
 
 impl Default for ParallelExecutionConfig {
@@ -464,6 +1143,8 @@ impl Default for ParallelExecutionConfig {
             never_parallel: Vec::new(),
             tool_conflicts: Vec::new(),
             resource_access_patterns: std::collections::HashMap::new(),
+            max_tool_result_bytes: 64 * 1024,
+            tool_result_spill_dir: None,
         }
     }
 }
@@ -532,28 +1213,221 @@ pub struct StreamChunk {
     pub text: String,
     pub is_complete: bool,
     pub token_count: u32,
+    /// Ids of the tokens generated since the previous chunk, when the request set
+    /// `debug_tokens`
+    pub token_ids: Vec<u32>,
+    /// Summary of the whole generation, set on the final chunk only
+    pub response: Option<GenerationResponse>,
+    /// What the chunk reports; only `TextChunk` chunks carry text
+    pub event: StreamEvent,
+}
+
+impl StreamChunk {
+    /// A chunk reporting a tool loop event, without text
+    pub fn for_event(event: StreamEvent, token_count: u32) -> Self {
+        Self {
+            text: String::new(),
+            is_complete: false,
+            token_count,
+            token_ids: Vec::new(),
+            response: None,
+            event,
+        }
+    }
+}
+
+/// Progress of a streamed generation, including the tool calls run between
+/// generation passes
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// Generated text, in [`StreamChunk::text`]
+    TextChunk,
+    /// The model called a tool, which is now running
+    ToolCallStarted {
+        name: String,
+        arguments: serde_json::Value,
+    },
+    /// A tool call finished; `is_error` is set when it failed or was denied
+    ToolCallCompleted {
+        name: String,
+        duration: Duration,
+        is_error: bool,
+    },
+    /// A running tool call reported progress
+    ToolCallProgress {
+        name: String,
+        progress: ToolProgress,
+    },
+    /// Generation continues with the tool results in the prompt
+    GenerationResumed,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct AgentConfig {
+    /// The default model, named [`DEFAULT_MODEL_NAME`]
     pub model: ModelConfig,
+    /// Further models loaded alongside `model`, each with its own queue workers and
+    /// context pool; requests choose one with `GenerationRequest::model`
+    pub models: Vec<NamedModelConfig>,
     pub queue_config: QueueConfig,
     pub mcp_servers: Vec<MCPServerConfig>,
     pub session_config: SessionConfig,
     pub parallel_execution_config: ParallelExecutionConfig,
+    /// Whether the model is loaded during initialization or on first use
+    pub load_mode: LoadMode,
+    /// Rate limits applied before generation requests are queued
+    pub limits: LimitsConfig,
+    /// JSONL file recording session events, if any
+    pub audit_log: Option<AuditLogConfig>,
+    /// System message placed first in every new session, unless
+    /// `session_config.system_prompt` overrides it
+    pub default_system_prompt: Option<String>,
+    /// What of prompts, messages, generated text and tool arguments reaches logs and
+    /// the audit log; see [`crate::redaction`]
+    pub redaction: RedactionPolicy,
+    /// Clean-up steps applied in order to generated text; see [`crate::postprocess`]
+    pub post_processors: Vec<PostProcessor>,
 }
 
+/// Name under which `AgentConfig::model` is served
+pub const DEFAULT_MODEL_NAME: &str = "default";
+
+/// A model served under `name` in addition to the default model
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedModelConfig {
+    pub name: String,
+    pub model: ModelConfig,
+}
+
+/// Where the audit log is written and when it is rotated; see [`crate::audit`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLogConfig {
+    /// File events are appended to, one JSON object per line
+    pub path: PathBuf,
+    /// Size in bytes past which the file is moved to `<path>.1` and a new one started
+    #[serde(default = "AuditLogConfig::default_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated files kept; older ones are deleted
+    #[serde(default = "AuditLogConfig::default_max_files")]
+    pub max_files: usize,
+}
+
+impl AuditLogConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes: Self::default_max_bytes(),
+            max_files: Self::default_max_files(),
+        }
+    }
+
+    /// Rotate the file once it would grow past `max_bytes`, keeping `max_files` old ones
+    pub fn with_rotation(mut self, max_bytes: u64, max_files: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self.max_files = max_files;
+        self
+    }
+
+    fn default_max_bytes() -> u64 {
+        10 * 1024 * 1024
+    }
+
+    fn default_max_files() -> usize {
+        5
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.path.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid(
+                "audit_log.path cannot be empty".to_string(),
+            ));
+        }
+        if self.max_bytes == 0 {
+            return Err(ConfigError::Invalid(
+                "audit_log.max_bytes must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// When `AgentServer::initialize` loads the model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadMode {
+    /// Load the model before `initialize` returns
+    #[default]
+    Eager,
+    /// Defer loading until the first generate or embed request
+    Lazy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct QueueConfig {
     pub max_queue_size: usize,
+    #[serde(with = "crate::config::duration")]
     pub request_timeout: Duration,
     pub worker_threads: usize,
+    /// Largest `n` a generation request may ask for; each sequence needs its own KV cache space
+    pub max_sequences_per_request: u32,
+    /// Consecutive sampled tokens that may fail to convert to text before a
+    /// request is stopped with an error finish reason
+    pub max_token_conversion_failures: u32,
+    /// Model contexts that may exist at once, one per in-flight request; defaults to
+    /// `worker_threads`. Workers beyond this wait for a free context.
+    pub context_pool_size: Option<usize>,
+    /// Stop generation at the chat template's own turn markers, such as `<|im_end|>`,
+    /// in addition to the request's stop tokens
+    pub use_template_stop_tokens: bool,
+    /// Let a worker take up to `max_batched_requests` queued non-streaming requests at
+    /// once and generate them side by side in one context
+    pub enable_request_batching: bool,
+    /// Most requests a worker takes together when request batching is enabled;
+    /// requests whose prompts do not fit in the model's `batch_size` together
+    /// generate in a following batch
+    pub max_batched_requests: usize,
+    /// Whether a stop token that is part of the chat template's markup, such as `<|`
+    /// or `im_start`, rejects the request or is logged as a warning
+    pub stop_token_collisions: ValidationSeverity,
+    /// Most stop sequences a request may have; the chat template's turn markers are
+    /// only added while a request is below it
+    pub max_stop_tokens: usize,
+    /// Longest stop sequence, in bytes; longer template markers are not added
+    pub max_stop_token_length: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SessionConfig {
     pub max_sessions: usize,
+    #[serde(with = "crate::config::duration")]
     pub session_timeout: Duration,
+    /// Tool policy given to newly created sessions
+    pub default_tool_policy: ToolPolicy,
+    /// Append each generated response to its session as an Assistant message
+    pub append_responses: bool,
+    /// Keep generating and append the response when a stream is dropped early;
+    /// otherwise dropping the stream cancels the request
+    pub append_on_stream_drop: bool,
+    /// What creating a session does once `max_sessions` exist
+    pub eviction_policy: SessionEvictionPolicy,
+    /// System message placed first in every new session; takes precedence over
+    /// `AgentConfig::default_system_prompt`
+    pub system_prompt: Option<String>,
+}
+
+/// How [`SessionConfig::max_sessions`] is enforced when a session is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionEvictionPolicy {
+    /// Fail with `SessionError::LimitExceeded`
+    #[default]
+    Reject,
+    /// Remove the least recently updated session without a request in progress; fail
+    /// only if every session is busy
+    EvictLru,
 }
 
 impl Default for SessionConfig {
@@ -561,6 +1435,11 @@ impl Default for SessionConfig {
         Self {
             max_sessions: 1000,
             session_timeout: Duration::from_secs(3600), // 1 hour
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: false,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         }
     }
 }
@@ -571,11 +1450,25 @@ impl Default for QueueConfig {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(30),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: ParameterConfig::default().max_stop_tokens,
+            max_stop_token_length: ParameterConfig::default().max_stop_token_length,
         }
     }
 }
 
 impl QueueConfig {
+    /// Size of the model's context pool
+    pub fn effective_context_pool_size(&self) -> usize {
+        self.context_pool_size.unwrap_or(self.worker_threads)
+    }
+
     pub fn validate(&self) -> Result<(), QueueError> {
         if self.max_queue_size == 0 {
             return Err(QueueError::WorkerError(
@@ -601,10 +1494,114 @@ impl QueueConfig {
             ));
         }
 
+        if self.max_sequences_per_request == 0 {
+            return Err(QueueError::WorkerError(
+                "Max sequences per request must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.max_token_conversion_failures == 0 {
+            return Err(QueueError::WorkerError(
+                "Max token conversion failures must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.context_pool_size == Some(0) {
+            return Err(QueueError::WorkerError(
+                "Context pool size must be greater than 0\n💡 Leave it unset to use one context per worker".to_string(),
+            ));
+        }
+
+        if self.max_batched_requests == 0 {
+            return Err(QueueError::WorkerError(
+                "Max batched requests must be greater than 0\n💡 Set enable_request_batching to false to turn batching off".to_string(),
+            ));
+        }
+
         Ok(())
     }
 }
 
+/// Request admission limits; each limit is off unless set.
+///
+/// Request rates are token buckets: a bucket holds up to `burst` requests (by default a
+/// full minute's worth) and refills at `requests_per_minute`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Generation requests one session may start per minute
+    pub session_requests_per_minute: Option<u32>,
+    /// Requests one session may start back to back
+    pub session_burst: Option<u32>,
+    /// Generation requests one session may have in flight at once
+    pub session_max_in_flight: Option<u32>,
+    /// Generation requests all sessions together may start per minute
+    pub global_requests_per_minute: Option<u32>,
+    /// Requests all sessions together may start back to back
+    pub global_burst: Option<u32>,
+}
+
+impl LimitsConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let limits = [
+            (
+                "session_requests_per_minute",
+                self.session_requests_per_minute,
+            ),
+            ("session_burst", self.session_burst),
+            ("session_max_in_flight", self.session_max_in_flight),
+            (
+                "global_requests_per_minute",
+                self.global_requests_per_minute,
+            ),
+            ("global_burst", self.global_burst),
+        ];
+        for (name, limit) in limits {
+            if limit == Some(0) {
+                return Err(ConfigError::Invalid(format!(
+                    "limits.{} must be greater than 0",
+                    name
+                )));
+            }
+        }
+
+        if self.session_burst.is_some() && self.session_requests_per_minute.is_none() {
+            return Err(ConfigError::Invalid(
+                "limits.session_burst requires limits.session_requests_per_minute".to_string(),
+            ));
+        }
+        if self.global_burst.is_some() && self.global_requests_per_minute.is_none() {
+            return Err(ConfigError::Invalid(
+                "limits.global_burst requires limits.global_requests_per_minute".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Which admission limit rejected a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimit {
+    /// `limits.session_requests_per_minute`
+    SessionRate,
+    /// `limits.session_max_in_flight`
+    SessionInFlight,
+    /// `limits.global_requests_per_minute`
+    GlobalRate,
+}
+
+impl std::fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateLimit::SessionRate => write!(f, "per-session request rate"),
+            RateLimit::SessionInFlight => write!(f, "per-session in-flight request"),
+            RateLimit::GlobalRate => write!(f, "global request rate"),
+        }
+    }
+}
+
 impl SessionConfig {
     pub fn validate(&self) -> Result<(), SessionError> {
         if self.max_sessions == 0 {
@@ -619,10 +1616,31 @@ impl SessionConfig {
             ));
         }
 
+        if let Some(prompt) = &self.system_prompt {
+            validate_system_prompt(prompt).map_err(SessionError::InvalidState)?;
+        }
+
         Ok(())
     }
 }
 
+/// Check a system prompt against the message length limit applied to requests
+pub fn validate_system_prompt(prompt: &str) -> Result<(), String> {
+    let max_length =
+        crate::validation::generation_request::MessageContentConfig::default().max_length;
+    if prompt.trim().is_empty() {
+        return Err("System prompt cannot be empty".to_string());
+    }
+    if prompt.len() > max_length {
+        return Err(format!(
+            "System prompt is {} characters; the maximum is {}",
+            prompt.len(),
+            max_length
+        ));
+    }
+    Ok(())
+}
+
 impl MCPServerConfig {
     pub fn validate(&self) -> Result<(), MCPError> {
         if self.name.is_empty() {
@@ -653,27 +1671,80 @@ impl MCPServerConfig {
 }
 
 impl AgentConfig {
+    /// Check every setting, reporting all problems found rather than the first
     pub fn validate(&self) -> Result<(), AgentError> {
-        self.model.validate()?;
-        self.queue_config.validate()?;
-        self.session_config.validate()?;
+        config_result(self.problems())
+    }
 
-        for server_config in &self.mcp_servers {
-            server_config.validate()?;
+    /// Every problem with the settings, in the order they appear in the config
+    pub(crate) fn problems(&self) -> Vec<AgentError> {
+        let mut problems = Vec::new();
+        let mut check = |result: Result<(), AgentError>| {
+            if let Err(e) = result {
+                problems.push(e);
+            }
+        };
+
+        check(self.model.validate().map_err(Into::into));
+        check(self.queue_config.validate().map_err(Into::into));
+        check(self.session_config.validate().map_err(Into::into));
+        check(self.limits.validate().map_err(Into::into));
+        if let Some(audit_log) = &self.audit_log {
+            check(audit_log.validate().map_err(Into::into));
+        }
+        if let Some(prompt) = &self.default_system_prompt {
+            check(
+                validate_system_prompt(prompt).map_err(|e| {
+                    ConfigError::Invalid(format!("default_system_prompt: {}", e)).into()
+                }),
+            );
+        }
+        check(
+            PostProcessing::new(&self.post_processors)
+                .map(drop)
+                .map_err(Into::into),
+        );
+
+        let mut model_names = std::collections::HashSet::new();
+        for named in &self.models {
+            if named.name.trim().is_empty() || named.name == DEFAULT_MODEL_NAME {
+                check(Err(ConfigError::Invalid(format!(
+                    "Model name '{}' is reserved or empty; name each entry of models uniquely",
+                    named.name
+                ))
+                .into()));
+            } else if !model_names.insert(&named.name) {
+                check(Err(ConfigError::Invalid(format!(
+                    "Duplicate model name: {}",
+                    named.name
+                ))
+                .into()));
+            }
+            check(named.model.validate().map_err(Into::into));
         }
 
-        // Check for duplicate MCP server names
         let mut server_names = std::collections::HashSet::new();
         for server_config in &self.mcp_servers {
+            check(server_config.validate().map_err(Into::into));
             if !server_names.insert(&server_config.name) {
-                return Err(AgentError::MCP(MCPError::Protocol(format!(
+                check(Err(AgentError::MCP(MCPError::Protocol(format!(
                     "Duplicate MCP server name: {}",
                     server_config.name
-                ))));
+                )))));
             }
         }
 
-        Ok(())
+        problems
+    }
+}
+
+/// `Ok` without problems, the problem itself when there is one, and
+/// [`AgentError::InvalidConfig`] listing them all otherwise
+pub(crate) fn config_result(mut problems: Vec<AgentError>) -> Result<(), AgentError> {
+    match problems.len() {
+        0 => Ok(()),
+        1 => Err(problems.remove(0)),
+        _ => Err(AgentError::InvalidConfig(problems)),
     }
 }
 
@@ -684,16 +1755,126 @@ pub struct HealthStatus {
     pub queue_size: usize,
     pub active_sessions: usize,
     pub uptime: Duration,
+    pub model: Option<ModelInfo>,
+    /// True while a model reload is in progress
+    pub reloading: bool,
+    /// True while a lazily loaded model is loading for the first time
+    #[serde(default)]
+    pub loading: bool,
+    /// What each initialized MCP server reported about itself, by server name
+    #[serde(default)]
+    pub mcp_servers: HashMap<String, MCPServerInfo>,
+    /// Sessions removed to make room for new ones since startup
+    #[serde(default)]
+    pub evicted_sessions: u64,
+    /// Status of every configured model, by name; the fields above describe the
+    /// default model
+    #[serde(default)]
+    pub models: HashMap<String, ModelStatus>,
+}
+
+/// Load state and queue depth of one model, reported by health checks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelStatus {
+    pub loaded: bool,
+    /// True while a lazily loaded model is loading for the first time
+    pub loading: bool,
+    /// True while a model reload is in progress
+    pub reloading: bool,
+    /// Requests queued or running on this model
+    pub queue_size: usize,
+    pub model: Option<ModelInfo>,
+    /// Queue worker panics since startup
+    #[serde(default)]
+    pub worker_panics: u64,
+    /// Message of the most recent worker panic
+    #[serde(default)]
+    pub last_worker_panic: Option<String>,
+}
+
+/// A step of `AgentServer::shutdown`, listed in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownPhase {
+    /// New generation requests and sessions fail with `AgentError::ShuttingDown`
+    StopAccepting,
+    /// Queued and running requests finish, or are cancelled at the drain deadline
+    DrainQueue,
+    /// Every MCP server, global and session-scoped, is shut down
+    StopMcpServers,
+    /// The model is freed
+    UnloadModel,
+    /// Buffered audit events are written out
+    FlushAuditLog,
+}
+
+/// What `AgentServer::shutdown` did
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownReport {
+    /// Each phase that ran and how long it took, in order
+    pub phases: Vec<(ShutdownPhase, Duration)>,
+    /// Requests still outstanding when the drain deadline passed, which were cancelled
+    pub cancelled_requests: usize,
+    /// True if MCP servers had not finished shutting down by the deadline
+    pub mcp_timed_out: bool,
+}
+
+/// Details about the currently loaded model, reported by health checks
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ModelInfo {
+    /// "huggingface", "local" or "url"
+    pub source_type: String,
+    /// HuggingFace repo, local folder or URL the model was loaded from
+    pub location: String,
+    pub filename: String,
+    /// Local path of the loaded file
+    #[serde(default)]
+    pub path: PathBuf,
+    /// HuggingFace revision; `None` for local models
+    #[serde(default)]
+    pub revision: Option<String>,
+    pub size_bytes: u64,
+    pub loaded_at: SystemTime,
+    #[serde(default)]
+    pub download_duration: Option<Duration>,
+    pub load_duration: Duration,
+    pub cache_hit: bool,
+    /// Quantization detected from the filename (e.g. "Q4_K_M", "BF16")
+    pub quantization: Option<String>,
+}
+
+impl ModelInfo {
+    pub fn from_metadata(metadata: &ModelMetadata, loaded_at: SystemTime) -> Self {
+        let (source_type, location) = match &metadata.source {
+            ModelSource::HuggingFace { repo, .. } => ("huggingface", repo.clone()),
+            ModelSource::Local { folder, .. } => ("local", folder.display().to_string()),
+            ModelSource::Url { url, .. } => ("url", url.clone()),
+        };
+
+        Self {
+            source_type: source_type.to_string(),
+            location,
+            filename: metadata.filename.clone(),
+            path: metadata.path.clone(),
+            revision: metadata.revision.clone(),
+            size_bytes: metadata.size_bytes,
+            loaded_at,
+            download_duration: metadata.download_time,
+            load_duration: metadata.load_time,
+            cache_hit: metadata.cache_hit,
+            quantization: detect_quantization(&metadata.filename),
+        }
+    }
 }
 
 // Error types
 #[derive(Debug, Error)]
 pub enum AgentError {
-    #[error("Model error: {0}\n💡 Check model file exists, is valid GGUF format, and sufficient memory is available")]
+    #[error("Model error: {0}\n💡 {}", model_error_hint(.0))]
     Model(#[from] ModelError),
 
     #[error("Request processing error: {0}\n💡 Try reducing concurrent requests, increasing queue size, or adding more system resources")]
-    Queue(#[from] QueueError),
+    Queue(QueueError),
 
     #[error(
         "Session error: {0}\n💡 Verify session ID is valid and session limits are not exceeded"
@@ -709,20 +1890,206 @@ pub enum AgentError {
     #[error("Request timeout: processing took longer than {timeout:?}\n💡 Increase timeout settings, reduce max_tokens, or check system performance")]
     Timeout { timeout: Duration },
 
-    #[error("Queue overloaded: {capacity} requests queued (max capacity)\n💡 Wait and retry, or increase max_queue_size configuration")]
-    QueueFull { capacity: usize },
+    #[error("Queue overloaded: {current_depth} of {max_depth} requests queued{}\n💡 Wait and retry, submit with block_on_full, or increase max_queue_size configuration", estimated_wait_hint(.estimated_wait))]
+    QueueFull {
+        current_depth: usize,
+        max_depth: usize,
+        /// Rough time until a slot frees up; `None` before any request has completed
+        estimated_wait: Option<Duration>,
+    },
+
+    #[error("Configuration error: {0}\n💡 Check the configuration file syntax and LLAMA_AGENT__* environment variables")]
+    Config(#[from] ConfigError),
+
+    #[error("Embeddings unavailable: {0}\n💡 Load a model with embedding support, or use llama-embedding with a dedicated embedding model")]
+    EmbeddingsNotSupported(String),
+
+    #[error("Tool '{tool}' denied by session tool policy ({policy})\n💡 Change the session's policy with set_tool_policy or session_config.default_tool_policy")]
+    ToolDenied { tool: String, policy: ToolPolicy },
+
+    #[error("Rate limited by the {limit} limit{}\n💡 Retry later, or raise the matching setting in the limits configuration", retry_after_hint(.retry_after))]
+    RateLimited {
+        limit: RateLimit,
+        /// When the request would be admitted; `None` when that depends on other
+        /// requests finishing
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Invalid OpenAI messages: {0}\n💡 Pass an array of {{\"role\": ..., \"content\": ...}} objects with role system, user, assistant or tool")]
+    OpenAIFormat(#[from] OpenAIFormatError),
+
+    #[error("Agent is shutting down and accepts no new work\n💡 Retry against a running instance")]
+    ShuttingDown,
+
+    #[error("Unknown model '{name}'\n💡 Use one of the configured models: {}", available.join(", "))]
+    UnknownModel {
+        name: String,
+        available: Vec<String>,
+    },
+
+    /// Several problems with a configuration, each with its own hint
+    #[error("Configuration has {} problems:\n{}", .0.len(), list_problems(.0))]
+    InvalidConfig(Vec<AgentError>),
+
+    /// Another error, with the ids of the request it failed; see [`AgentError::root`]
+    #[error("[{context}] {source}")]
+    WithContext {
+        context: ErrorContext,
+        source: Box<AgentError>,
+    },
+}
+
+/// Identifies the request an error came from, to find it in the logs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorContext {
+    /// Id the queue logs the request under
+    pub request_id: Option<String>,
+    pub session_id: Option<SessionId>,
+    /// Queue worker that last ran the request
+    pub worker_id: Option<usize>,
+}
+
+impl std::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(request_id) = &self.request_id {
+            parts.push(format!("request {}", request_id));
+        }
+        if let Some(session_id) = &self.session_id {
+            parts.push(format!("session {}", session_id));
+        }
+        if let Some(worker_id) = self.worker_id {
+            parts.push(format!("worker {}", worker_id));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl AgentError {
+    /// Attach the ids of the request that failed; an error that already has
+    /// them keeps its own
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            AgentError::WithContext { .. } => self,
+            error => AgentError::WithContext {
+                context,
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// Ids of the request that failed, when known
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            AgentError::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context, for matching on what went wrong
+    pub fn root(&self) -> &AgentError {
+        match self {
+            AgentError::WithContext { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Owned form of [`AgentError::root`]
+    pub fn into_root(self) -> AgentError {
+        match self {
+            AgentError::WithContext { source, .. } => source.into_root(),
+            error => error,
+        }
+    }
+}
+
+/// A full queue is reported as `AgentError::QueueFull`, other queue errors as `AgentError::Queue`
+impl From<QueueError> for AgentError {
+    fn from(error: QueueError) -> Self {
+        match error {
+            QueueError::Full {
+                current_depth,
+                max_depth,
+                estimated_wait,
+            } => AgentError::QueueFull {
+                current_depth,
+                max_depth,
+                estimated_wait,
+            },
+            error => AgentError::Queue(error),
+        }
+    }
+}
+
+fn model_error_hint(error: &ModelError) -> &'static str {
+    match error {
+        ModelError::InvalidFormat { .. } => {
+            "Use a GGUF model file; models in other formats must be converted first"
+        }
+        ModelError::Truncated { .. } => {
+            "Delete the partial file from the model cache and download it again"
+        }
+        ModelError::OutOfMemory { .. } => {
+            "Pick a smaller quantization or a smaller model, or free memory before loading"
+        }
+        ModelError::InsufficientMemory { .. } => {
+            "Use a smaller quantization or context, free memory, or set force_load to load anyway"
+        }
+        ModelError::UnsupportedArchitecture { .. } => {
+            "Upgrade to a release with newer llama.cpp support, or use a model with a supported architecture"
+        }
+        ModelError::NotFound(_) => "Check the model path, repository name and filename",
+        ModelError::Cancelled => "Load the model again to restart the download",
+        ModelError::NotLoaded => "Wait for the model to load, or reload it if it was unloaded",
+        _ => "Check model file exists, is valid GGUF format, and sufficient memory is available",
+    }
+}
+
+/// One bullet per problem, with the lines after each problem's first indented under it
+fn list_problems(problems: &[AgentError]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("- {}", problem.to_string().replace('\n', "\n  ")))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn estimated_wait_hint(estimated_wait: &Option<Duration>) -> String {
+    match estimated_wait {
+        // Whole seconds, never "~0s"
+        Some(wait) => format!(", try again in ~{}s", wait.as_secs_f64().ceil().max(1.0)),
+        None => String::new(),
+    }
+}
+
+fn retry_after_hint(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(wait) => format!(" (retry after {:.1}s)", wait.as_secs_f64()),
+        None => String::new(),
+    }
 }
 
 #[derive(Debug, Clone, Error)]
 pub enum QueueError {
-    #[error("Queue is full")]
-    Full,
+    #[error("Queue is full ({current_depth} of {max_depth} requests queued){}", estimated_wait_hint(.estimated_wait))]
+    Full {
+        current_depth: usize,
+        max_depth: usize,
+        /// Rough time until a slot frees up; `None` before any request has completed
+        estimated_wait: Option<Duration>,
+    },
 
     #[error("Request timeout")]
     Timeout,
 
     #[error("Worker thread error: {0}")]
     WorkerError(String),
+
+    #[error("Loaded model does not support embeddings: {0}")]
+    EmbeddingsNotSupported(String),
+
+    #[error("Model is not loaded yet")]
+    ModelLoading,
 }
 
 #[derive(Debug, Error)]
@@ -733,11 +2100,37 @@ pub enum SessionError {
     #[error("Session limit exceeded")]
     LimitExceeded,
 
+    #[error("Session {0} has a request in progress")]
+    InUse(String),
+
     #[error("Session timeout")]
     Timeout,
 
     #[error("Invalid session state: {0}")]
     InvalidState(String),
+
+    #[error("Session {session_id} was modified concurrently: expected {expected} messages, found {actual}")]
+    ConcurrentModification {
+        session_id: String,
+        expected: usize,
+        actual: usize,
+    },
+
+    #[error("Session checkpoint {0}")]
+    Checkpoint(String),
+}
+
+/// Why messages in the OpenAI chat format could not be imported
+#[derive(Debug, Error)]
+pub enum OpenAIFormatError {
+    #[error("expected an array of messages, found {0}")]
+    NotAnArray(String),
+
+    #[error("message {index}: unsupported role '{role}'")]
+    InvalidRole { index: usize, role: String },
+
+    #[error("message {index}: {reason}")]
+    InvalidMessage { index: usize, reason: String },
 }
 
 #[derive(Debug, Error)]
@@ -753,6 +2146,30 @@ pub enum MCPError {
 
     #[error("Protocol error: {0}")]
     Protocol(String),
+
+    #[error("MCP server error {code}: {message}")]
+    Rpc { code: i64, message: String },
+
+    #[error("Tool call cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read configuration file: {0}")]
+    Io(String),
+
+    #[error("Unsupported configuration format: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Failed to parse configuration: {0}")]
+    Parse(String),
+
+    #[error("Invalid environment override: {0}")]
+    EnvOverride(String),
+
+    #[error("Invalid configuration value: {0}")]
+    Invalid(String),
 }
 
 #[derive(Debug, Error)]
@@ -785,11 +2202,32 @@ pub trait AgentAPI {
 
     async fn get_session(&self, session_id: &SessionId) -> Result<Option<Session>, AgentError>;
 
+    /// Summaries of the unexpired sessions matching `filter`, most recently updated first
+    async fn list_sessions(&self, filter: SessionFilter)
+        -> Result<Vec<SessionSummary>, AgentError>;
+
+    /// Token usage of a session across its completed generation requests
+    async fn get_usage(&self, session_id: &SessionId) -> Result<SessionUsage, AgentError>;
+
     async fn add_message(&self, session_id: &SessionId, message: Message)
         -> Result<(), AgentError>;
 
+    /// Replace the session's leading System message, or insert one ahead of its history
+    async fn set_system_prompt(&self, session_id: &SessionId, text: &str)
+        -> Result<(), AgentError>;
+
     async fn discover_tools(&self, session: &mut Session) -> Result<(), AgentError>;
 
+    /// Render an MCP prompt and append its messages to the session
+    async fn apply_prompt(
+        &self,
+        session_id: &SessionId,
+        prompt_name: &str,
+        arguments: Option<serde_json::Value>,
+    ) -> Result<(), AgentError>;
+
+    /// Execute a tool call from `session`'s tools, under the tool policy of the stored
+    /// session rather than that of `session`
     async fn execute_tool(
         &self,
         tool_call: ToolCall,
@@ -820,6 +2258,7 @@ mod tests {
             tool_call_id: None,
             tool_name: None,
             timestamp: SystemTime::now(),
+            attachments: Vec::new(),
         };
 
         assert_eq!(message.role.as_str(), "user");
@@ -838,6 +2277,8 @@ mod tests {
             available_prompts: Vec::new(),
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         };
 
         assert!(!session.id.to_string().is_empty());
@@ -857,6 +2298,7 @@ mod tests {
                 "@modelcontextprotocol/server-filesystem".to_string(),
             ],
             timeout_secs: None,
+            strict_protocol_version: false,
         };
 
         assert_eq!(config.name, "filesystem");
@@ -871,6 +2313,7 @@ mod tests {
             description: "List files in a directory".to_string(),
             parameters: serde_json::json!({"type": "object"}),
             server_name: "filesystem".to_string(),
+            original_name: None,
         };
 
         assert_eq!(tool.name, "list_files");
@@ -887,6 +2330,8 @@ mod tests {
             available_prompts: Vec::new(),
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         };
 
         let request = GenerationRequest {
@@ -894,8 +2339,17 @@ mod tests {
             max_tokens: Some(100),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: vec!["</s>".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
             stopping_config: None,
+            model: None,
+            max_duration: None,
+            debug_tokens: false,
         };
 
         assert_eq!(request.max_tokens, Some(100));
@@ -958,6 +2412,9 @@ mod tests {
             text: "Hello".to_string(),
             is_complete: false,
             token_count: 1,
+            response: None,
+            event: StreamEvent::TextChunk,
+            token_ids: Vec::new(),
         };
 
         assert_eq!(chunk.text, "Hello");
@@ -1016,6 +2473,108 @@ mod tests {
         assert!(!format!("{}", tool_call_id).is_empty());
     }
 
+    #[test]
+    fn test_ids_order_by_creation_time() {
+        let before = SystemTime::now();
+        let (session, tool_call, prompt) = (SessionId::new(), ToolCallId::new(), PromptId::new());
+        std::thread::sleep(Duration::from_millis(2));
+        let later = (SessionId::new(), ToolCallId::new(), PromptId::new());
+
+        assert!(later.0 > session && later.1 > tool_call && later.2 > prompt);
+        // Serialized ids sort the same way
+        assert!(later.0.to_string() > session.to_string());
+        assert!(later.1.to_string() > tool_call.to_string());
+        assert!(later.2.to_string() > prompt.to_string());
+
+        // Timestamps are truncated to the millisecond
+        let tolerance = Duration::from_millis(1);
+        for timestamp in [
+            session.timestamp(),
+            tool_call.timestamp(),
+            prompt.timestamp(),
+        ] {
+            assert!(timestamp + tolerance >= before && timestamp <= later.0.timestamp());
+        }
+
+        let ulid = Ulid::new();
+        assert_eq!(SessionId::from(ulid).as_ref(), &ulid);
+        assert_eq!(ToolCallId::from(ulid).as_ulid(), ulid);
+        assert_eq!(*PromptId::from(ulid).as_ref(), ulid);
+    }
+
+    #[test]
+    fn test_message_attachments_round_trip() {
+        let message = Message {
+            role: MessageRole::Tool,
+            content: "Two blocks".to_string(),
+            tool_call_id: Some(ToolCallId::new()),
+            tool_name: None,
+            timestamp: SystemTime::now(),
+            attachments: vec![
+                MessageAttachment::Text {
+                    text: "Two blocks".to_string(),
+                },
+                MessageAttachment::Image {
+                    data: "aGVsbG8=".to_string(),
+                    mime_type: "image/png".to_string(),
+                },
+                MessageAttachment::ResourceLink {
+                    uri: "file:///notes.md".to_string(),
+                    mime_type: Some("text/markdown".to_string()),
+                    text: None,
+                },
+            ],
+        };
+
+        let serialized = serde_json::to_string(&message).unwrap();
+        assert!(serialized.contains(r#""type":"resource_link""#));
+
+        let deserialized: Message = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.attachments, message.attachments);
+        assert_eq!(deserialized.content, message.content);
+    }
+
+    #[test]
+    fn test_message_without_attachments_is_backward_compatible() {
+        let message = Message {
+            role: MessageRole::User,
+            content: "Hello".to_string(),
+            tool_call_id: None,
+            tool_name: None,
+            timestamp: SystemTime::now(),
+            attachments: Vec::new(),
+        };
+
+        // Empty attachments are omitted, so older readers see the same shape as before
+        let value = serde_json::to_value(&message).unwrap();
+        assert!(value.get("attachments").is_none());
+
+        let deserialized: Message = serde_json::from_value(value).unwrap();
+        assert!(deserialized.attachments.is_empty());
+        assert_eq!(deserialized.rendered_content(), "Hello");
+    }
+
+    #[test]
+    fn test_attachment_placeholders() {
+        let image = MessageAttachment::Image {
+            data: "A".repeat(46_424),
+            mime_type: "image/png".to_string(),
+        };
+        assert_eq!(image.placeholder().unwrap(), "[image image/png 34KB]");
+
+        let link = MessageAttachment::ResourceLink {
+            uri: "file:///a.bin".to_string(),
+            mime_type: None,
+            text: None,
+        };
+        assert_eq!(link.placeholder().unwrap(), "[resource file:///a.bin]");
+
+        let text = MessageAttachment::Text {
+            text: "inline".to_string(),
+        };
+        assert!(text.placeholder().is_none());
+    }
+
     #[test]
     fn test_message_with_tool_call() {
         let tool_call_id = ToolCallId::new();
@@ -1025,6 +2584,7 @@ mod tests {
             tool_call_id: Some(tool_call_id),
             tool_name: Some("test_tool".to_string()),
             timestamp: SystemTime::now(),
+            attachments: Vec::new(),
         };
 
         assert_eq!(message.role.as_str(), "tool");
@@ -1105,6 +2665,15 @@ mod tests {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         };
 
         assert!(config.validate().is_ok());
@@ -1121,6 +2690,15 @@ mod tests {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         };
 
         assert!(config.validate().is_err());
@@ -1134,6 +2712,15 @@ mod tests {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         };
 
         assert!(config.validate().is_err());
@@ -1211,6 +2798,15 @@ mod tests {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(30),
             worker_threads: 2,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         };
         assert!(config.validate().is_ok());
 
@@ -1219,6 +2815,15 @@ mod tests {
             max_queue_size: 0,
             request_timeout: Duration::from_secs(30),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         };
         assert!(config.validate().is_err());
 
@@ -1227,6 +2832,15 @@ mod tests {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(30),
             worker_threads: 0,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         };
         assert!(config.validate().is_err());
 
@@ -1235,6 +2849,15 @@ mod tests {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(30),
             worker_threads: 20,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         };
         assert!(config.validate().is_err());
 
@@ -1243,6 +2866,45 @@ mod tests {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(0),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
+        };
+        assert!(config.validate().is_err());
+
+        // No sequences per request
+        let config = QueueConfig {
+            max_sequences_per_request: 0,
+            ..QueueConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        // No token conversion failures tolerated
+        let config = QueueConfig {
+            max_token_conversion_failures: 0,
+            ..QueueConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        // Empty context pool
+        let config = QueueConfig {
+            context_pool_size: Some(0),
+            ..QueueConfig::default()
+        };
+        assert!(config.validate().is_err());
+        assert_eq!(QueueConfig::default().effective_context_pool_size(), 1);
+
+        // Batches of no requests
+        let config = QueueConfig {
+            max_batched_requests: 0,
+            stop_token_collisions: ValidationSeverity::Error,
+            ..QueueConfig::default()
         };
         assert!(config.validate().is_err());
     }
@@ -1253,6 +2915,11 @@ mod tests {
         let config = SessionConfig {
             max_sessions: 1000,
             session_timeout: Duration::from_secs(3600),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         };
         assert!(config.validate().is_ok());
 
@@ -1260,6 +2927,11 @@ mod tests {
         let config = SessionConfig {
             max_sessions: 0,
             session_timeout: Duration::from_secs(3600),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         };
         assert!(config.validate().is_err());
 
@@ -1267,10 +2939,61 @@ mod tests {
         let config = SessionConfig {
             max_sessions: 1000,
             session_timeout: Duration::from_secs(0),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         };
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_system_prompt_validation() {
+        assert!(validate_system_prompt("You are a helpful assistant.").is_ok());
+        assert!(validate_system_prompt("   ").is_err());
+        assert!(validate_system_prompt(&"a".repeat(100_001)).is_err());
+
+        let mut config = AgentConfig::default();
+        config.default_system_prompt = Some("a".repeat(100_001));
+        assert!(matches!(
+            config.validate(),
+            Err(AgentError::Config(ConfigError::Invalid(_)))
+        ));
+
+        config.default_system_prompt = None;
+        config.session_config.system_prompt = Some(String::new());
+        assert!(matches!(
+            config.validate(),
+            Err(AgentError::Session(SessionError::InvalidState(_)))
+        ));
+    }
+
+    #[test]
+    fn test_named_model_validation() {
+        let named = |name: &str| NamedModelConfig {
+            name: name.to_string(),
+            model: ModelConfig::default(),
+        };
+        let mut config = AgentConfig {
+            models: vec![named("small"), named("large")],
+            ..AgentConfig::default()
+        };
+        assert!(config.validate().is_ok());
+
+        for models in [
+            vec![named("small"), named("small")],
+            vec![named(DEFAULT_MODEL_NAME)],
+            vec![named(" ")],
+        ] {
+            config.models = models;
+            assert!(matches!(
+                config.validate(),
+                Err(AgentError::Config(ConfigError::Invalid(_)))
+            ));
+        }
+    }
+
     #[test]
     fn test_mcp_server_config_validation() {
         // Valid config
@@ -1279,6 +3002,7 @@ mod tests {
             command: "npx".to_string(),
             args: vec!["-y".to_string()],
             timeout_secs: None,
+            strict_protocol_version: false,
         };
         assert!(config.validate().is_ok());
 
@@ -1288,6 +3012,7 @@ mod tests {
             command: "npx".to_string(),
             args: vec![],
             timeout_secs: None,
+            strict_protocol_version: false,
         };
         assert!(config.validate().is_err());
 
@@ -1297,6 +3022,7 @@ mod tests {
             command: "".to_string(),
             args: vec![],
             timeout_secs: None,
+            strict_protocol_version: false,
         };
         assert!(config.validate().is_err());
 
@@ -1306,6 +3032,7 @@ mod tests {
             command: "npx".to_string(),
             args: vec![],
             timeout_secs: None,
+            strict_protocol_version: false,
         };
         assert!(config.validate().is_err());
     }
@@ -1324,12 +3051,14 @@ mod tests {
                     command: "npx".to_string(),
                     args: vec![],
                     timeout_secs: None,
+                    strict_protocol_version: false,
                 },
                 MCPServerConfig {
                     name: "filesystem".to_string(),
                     command: "another".to_string(),
                     args: vec![],
                     timeout_secs: None,
+                    strict_protocol_version: false,
                 },
             ],
             ..Default::default()
@@ -1342,6 +3071,54 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_limits_config_validation() {
+        assert!(LimitsConfig::default().validate().is_ok());
+
+        let limits = LimitsConfig {
+            session_requests_per_minute: Some(30),
+            session_burst: Some(5),
+            session_max_in_flight: Some(2),
+            global_requests_per_minute: Some(600),
+            global_burst: None,
+        };
+        assert!(limits.validate().is_ok());
+
+        let mut config = AgentConfig::default();
+        config.limits.session_max_in_flight = Some(0);
+        assert!(matches!(
+            config.validate(),
+            Err(AgentError::Config(ConfigError::Invalid(_)))
+        ));
+
+        let burst_without_rate = LimitsConfig {
+            global_burst: Some(10),
+            ..Default::default()
+        };
+        assert!(burst_without_rate.validate().is_err());
+    }
+
+    #[test]
+    fn test_audit_log_config_validation() {
+        let audit_log = AuditLogConfig::new("/tmp/audit.jsonl");
+        assert!(audit_log.validate().is_ok());
+        assert_eq!(audit_log.max_bytes, 10 * 1024 * 1024);
+
+        let mut config = AgentConfig::default();
+        config.audit_log = Some(AuditLogConfig::new(""));
+        assert!(matches!(
+            config.validate(),
+            Err(AgentError::Config(ConfigError::Invalid(_)))
+        ));
+
+        config.audit_log = Some(AuditLogConfig::new("/tmp/audit.jsonl").with_rotation(0, 3));
+        assert!(config.validate().is_err());
+
+        // Rotation settings default when only the path is given
+        let parsed: AuditLogConfig = serde_json::from_str(r#"{"path": "audit.jsonl"}"#).unwrap();
+        assert_eq!(parsed, AuditLogConfig::new("audit.jsonl"));
+    }
+
     #[test]
     fn test_model_source_serialization() {
         let hf_source = ModelSource::HuggingFace {
@@ -1384,6 +3161,8 @@ mod tests {
             max_tokens: Some(100),
             repetition_detection: Some(RepetitionConfig::default()),
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         assert!(config.validate().is_ok());
 
@@ -1392,6 +3171,8 @@ mod tests {
             max_tokens: Some(0),
             repetition_detection: None,
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         assert!(config.validate().is_err());
 
@@ -1400,6 +3181,8 @@ mod tests {
             max_tokens: Some(200_000),
             repetition_detection: None,
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         assert!(config.validate().is_err());
 
@@ -1411,8 +3194,11 @@ mod tests {
                 max_pattern_length: 10,
                 min_repetitions: 3,
                 window_size: 1000,
+                ..RepetitionConfig::default()
             }),
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         assert!(config.validate().is_err());
 
@@ -1424,8 +3210,11 @@ mod tests {
                 max_pattern_length: 10,
                 min_repetitions: 3,
                 window_size: 1000,
+                ..RepetitionConfig::default()
             }),
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         assert!(config.validate().is_err());
 
@@ -1437,8 +3226,11 @@ mod tests {
                 max_pattern_length: 100,
                 min_repetitions: 1,
                 window_size: 1000,
+                ..RepetitionConfig::default()
             }),
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         assert!(config.validate().is_err());
 
@@ -1450,8 +3242,11 @@ mod tests {
                 max_pattern_length: 100,
                 min_repetitions: 3,
                 window_size: 0,
+                ..RepetitionConfig::default()
             }),
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         assert!(config.validate().is_err());
     }
@@ -1501,6 +3296,8 @@ mod tests {
             max_tokens: Some(0), // Invalid
             repetition_detection: None,
             eos_detection: true,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         let request =
             GenerationRequest::new(session_id).with_validated_stopping_config(invalid_config);
@@ -1518,6 +3315,8 @@ mod tests {
                 max_tokens: Some(100),
                 repetition_detection: None,
                 eos_detection: true,
+                stop_token_ids: Vec::new(),
+                max_duration: None,
             });
         assert_eq!(request.effective_max_tokens(), Some(200));
 
@@ -1527,6 +3326,8 @@ mod tests {
                 max_tokens: Some(150),
                 repetition_detection: None,
                 eos_detection: true,
+                stop_token_ids: Vec::new(),
+                max_duration: None,
             });
         assert_eq!(request.effective_max_tokens(), Some(150));
 
@@ -1555,6 +3356,8 @@ mod tests {
             max_tokens: None,
             repetition_detection: Some(RepetitionConfig::default()),
             eos_detection: false,
+            stop_token_ids: Vec::new(),
+            max_duration: None,
         };
         let request = GenerationRequest::new(session_id)
             .with_max_tokens(400)
@@ -1567,4 +3370,184 @@ mod tests {
         assert!(stopping_config.repetition_detection.is_some());
         assert!(!stopping_config.eos_detection);
     }
+
+    #[test]
+    fn test_generation_request_with_model_defaults() {
+        let session_id = SessionId::new();
+        let defaults = HfGenerationDefaults {
+            temperature: Some(0.6),
+            top_p: Some(0.9),
+            eos_token_ids: vec![128001, 128009],
+            max_new_tokens: Some(512),
+            ..HfGenerationDefaults::default()
+        };
+
+        // Unset fields take the model's defaults
+        let request = GenerationRequest::new(session_id).with_model_defaults(&defaults);
+        assert_eq!(request.temperature, Some(0.6));
+        assert_eq!(request.top_p, Some(0.9));
+        assert_eq!(request.max_tokens, Some(512));
+        assert_eq!(request.stop_token_ids, vec![128001, 128009]);
+
+        // Anything set on the request wins
+        let request = GenerationRequest::new(session_id)
+            .with_temperature(0.1)
+            .with_top_p(0.5)
+            .with_max_tokens(16)
+            .with_stop_token_ids(vec![7])
+            .with_model_defaults(&defaults);
+        assert_eq!(request.temperature, Some(0.1));
+        assert_eq!(request.top_p, Some(0.5));
+        assert_eq!(request.max_tokens, Some(16));
+        assert_eq!(request.stop_token_ids, vec![7]);
+
+        // Including limits and stop ids given through the stopping config
+        let request = GenerationRequest::new(session_id)
+            .with_stopping_config(StoppingConfig {
+                max_tokens: Some(64),
+                stop_token_ids: vec![9],
+                ..StoppingConfig::default()
+            })
+            .with_model_defaults(&defaults);
+        assert_eq!(request.max_tokens, None);
+        assert_eq!(request.effective_max_tokens(), Some(64));
+        assert!(request.stop_token_ids.is_empty());
+
+        // No defaults leaves the request untouched
+        let request = GenerationRequest::new(session_id)
+            .with_model_defaults(&HfGenerationDefaults::default());
+        assert_eq!(request.temperature, None);
+        assert_eq!(request.max_tokens, None);
+        assert!(request.stop_token_ids.is_empty());
+    }
+
+    #[test]
+    fn test_model_defaults_outside_request_bounds() {
+        let defaults = HfGenerationDefaults {
+            temperature: Some(3.5),
+            top_p: Some(1.5),
+            max_length: Some(131_072),
+            ..HfGenerationDefaults::default()
+        };
+        let request = GenerationRequest::new(SessionId::new()).with_model_defaults(&defaults);
+        assert_eq!(request.temperature, None);
+        assert_eq!(request.top_p, None);
+        assert_eq!(request.max_tokens, Some(MAX_TOKENS_LIMIT));
+    }
+
+    #[test]
+    fn test_detect_quantization() {
+        assert_eq!(
+            detect_quantization("Qwen3-0.6B-Q4_K_M.gguf"),
+            Some("Q4_K_M".to_string())
+        );
+        assert_eq!(
+            detect_quantization("Qwen3-0.6B-BF16.gguf"),
+            Some("BF16".to_string())
+        );
+        assert_eq!(
+            detect_quantization("llama-2-7b.q8_0.gguf"),
+            Some("Q8_0".to_string())
+        );
+        assert_eq!(
+            detect_quantization("model-IQ2_XS-00001-of-00002.gguf"),
+            Some("IQ2_XS".to_string())
+        );
+        assert_eq!(detect_quantization("model.gguf"), None);
+        assert_eq!(detect_quantization("qwen-instruct.gguf"), None);
+    }
+
+    #[test]
+    fn test_model_info_from_metadata() {
+        let metadata = ModelMetadata {
+            source: ModelSource::HuggingFace {
+                repo: "unsloth/Qwen3-0.6B-GGUF".to_string(),
+                filename: None,
+            },
+            filename: "Qwen3-0.6B-Q4_K_M.gguf".to_string(),
+            path: PathBuf::from("/cache/Qwen3-0.6B-Q4_K_M.gguf"),
+            revision: Some("main".to_string()),
+            size_bytes: 1024,
+            download_time: Some(Duration::from_millis(40)),
+            load_time: Duration::from_millis(250),
+            cache_hit: true,
+        };
+        let loaded_at = SystemTime::now();
+
+        let info = ModelInfo::from_metadata(&metadata, loaded_at);
+        assert_eq!(info.source_type, "huggingface");
+        assert_eq!(info.location, "unsloth/Qwen3-0.6B-GGUF");
+        assert_eq!(info.filename, "Qwen3-0.6B-Q4_K_M.gguf");
+        assert_eq!(info.size_bytes, 1024);
+        assert_eq!(info.loaded_at, loaded_at);
+        assert_eq!(info.load_duration, Duration::from_millis(250));
+        assert_eq!(info.download_duration, Some(Duration::from_millis(40)));
+        assert_eq!(info.revision.as_deref(), Some("main"));
+        assert_eq!(info.path, PathBuf::from("/cache/Qwen3-0.6B-Q4_K_M.gguf"));
+        assert!(info.cache_hit);
+        assert_eq!(info.quantization.as_deref(), Some("Q4_K_M"));
+
+        let local = ModelMetadata {
+            source: ModelSource::Local {
+                folder: PathBuf::from("/models"),
+                filename: Some("model.gguf".to_string()),
+            },
+            filename: "model.gguf".to_string(),
+            ..metadata
+        };
+        let info = ModelInfo::from_metadata(&local, loaded_at);
+        assert_eq!(info.source_type, "local");
+        assert_eq!(info.location, "/models");
+        assert_eq!(info.quantization, None);
+
+        // Survives serialization so it can be exposed by health endpoints
+        let json = serde_json::to_string(&info).unwrap();
+        let deserialized: ModelInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, info);
+    }
+
+    #[test]
+    fn test_tool_policy_modes() {
+        let tools = vec!["read_file".to_string()];
+
+        assert!(ToolPolicy::AllowAll.allows("anything"));
+        assert!(ToolPolicy::AllowList(tools.clone()).allows("read_file"));
+        assert!(!ToolPolicy::AllowList(tools.clone()).allows("write_file"));
+        assert!(!ToolPolicy::DenyList(tools.clone()).allows("read_file"));
+        assert!(ToolPolicy::DenyList(tools.clone()).allows("write_file"));
+
+        assert_eq!(ToolPolicy::AllowAll.to_string(), "allow-all");
+        assert_eq!(
+            ToolPolicy::DenyList(vec!["a".to_string(), "b".to_string()]).to_string(),
+            "deny-list [a, b]"
+        );
+
+        let json = serde_json::to_value(ToolPolicy::AllowList(tools.clone())).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"mode": "allow_list", "tools": ["read_file"]})
+        );
+        let parsed: ToolPolicy = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, ToolPolicy::AllowList(tools));
+    }
+
+    #[test]
+    fn test_session_without_tool_policy_deserializes() {
+        let session = Session {
+            id: SessionId::new(),
+            messages: Vec::new(),
+            mcp_servers: Vec::new(),
+            available_tools: Vec::new(),
+            available_prompts: Vec::new(),
+            created_at: SystemTime::now(),
+            updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::DenyList(vec!["shell".to_string()]),
+            usage: SessionUsage::default(),
+        };
+        let mut json = serde_json::to_value(&session).unwrap();
+        json.as_object_mut().unwrap().remove("tool_policy");
+
+        let restored: Session = serde_json::from_value(json).unwrap();
+        assert_eq!(restored.tool_policy, ToolPolicy::AllowAll);
+    }
 }
@@ -64,5 +64,45 @@ impl ValidationError {
     }
 }
 
+/// How serious a validation finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationSeverity {
+    /// Allowed but probably unintended; logged and the request proceeds
+    Warning,
+    /// The request is rejected
+    Error,
+}
+
+/// A finding from checks that can warn as well as reject
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub severity: ValidationSeverity,
+    pub error: ValidationError,
+}
+
+impl ValidationIssue {
+    /// Create a finding that is logged but does not reject the request
+    pub fn warning(error: ValidationError) -> Self {
+        Self {
+            severity: ValidationSeverity::Warning,
+            error,
+        }
+    }
+
+    /// Create a finding that rejects the request
+    pub fn error(error: ValidationError) -> Self {
+        Self {
+            severity: ValidationSeverity::Error,
+            error,
+        }
+    }
+
+    /// Whether this finding rejects the request
+    pub fn is_error(&self) -> bool {
+        self.severity == ValidationSeverity::Error
+    }
+}
+
 /// Result type for validation operations
 pub type ValidationResult<T = ()> = Result<T, ValidationError>;
@@ -2,7 +2,7 @@
 
 use super::{
     MessageContentConfig, MessageContentValidator, ParameterConfig, ParameterValidator,
-    SessionStateValidator,
+    SessionStateValidator, StopTokenConfig, StopTokenValidator,
 };
 use crate::types::{GenerationRequest, Session};
 use crate::validation::{ValidationError, ValidationResult, Validator};
@@ -14,6 +14,26 @@ pub struct ValidationConfig {
     pub message_content: MessageContentConfig,
     /// Configuration for parameter validation
     pub parameters: ParameterConfig,
+    /// How template control sequences in user and tool messages are handled
+    pub control_tokens: ControlTokenPolicy,
+    /// Configuration for checking stop tokens against the chat template
+    pub stop_tokens: StopTokenConfig,
+}
+
+/// Handling of chat template control sequences (such as `<|im_end|>` or
+/// `### Assistant:`) found inside user and tool message content
+///
+/// Left in place, such sequences let a message close its own turn and inject
+/// text the model reads as coming from another role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlTokenPolicy {
+    /// Render content unchanged
+    Off,
+    /// Render content unchanged but log a warning
+    Warn,
+    /// Remove the control sequences before rendering
+    #[default]
+    Strip,
 }
 
 /// Composite validator that performs comprehensive validation of generation requests
@@ -22,6 +42,7 @@ pub struct ValidationConfig {
 /// - Session state validation (ensures session is valid for generation)
 /// - Message content validation (validates all messages in session)
 /// - Parameter validation (validates generation parameters)
+/// - Stop token validation (checks stop tokens against the chat template)
 ///
 /// This provides a single entry point for complete generation request validation.
 #[derive(Debug, Clone)]
@@ -29,6 +50,7 @@ pub struct CompositeGenerationRequestValidator {
     session_validator: SessionStateValidator,
     message_validator: MessageContentValidator,
     parameter_validator: ParameterValidator,
+    stop_token_validator: StopTokenValidator,
 }
 
 impl CompositeGenerationRequestValidator {
@@ -43,10 +65,12 @@ impl CompositeGenerationRequestValidator {
             session_validator: SessionStateValidator::new(),
             message_validator: MessageContentValidator::with_config(config.message_content),
             parameter_validator: ParameterValidator::with_config(config.parameters),
+            stop_token_validator: StopTokenValidator::with_config(config.stop_tokens),
         }
     }
 
-    /// Create a composite validator with individual validator configurations
+    /// Create a composite validator with individual validator configurations; stop
+    /// tokens are only checked for being blank
     pub fn with_validators(
         session_validator: SessionStateValidator,
         message_validator: MessageContentValidator,
@@ -56,6 +80,7 @@ impl CompositeGenerationRequestValidator {
             session_validator,
             message_validator,
             parameter_validator,
+            stop_token_validator: StopTokenValidator::new(),
         }
     }
 
@@ -73,6 +98,24 @@ impl CompositeGenerationRequestValidator {
     pub fn parameter_validator(&self) -> &ParameterValidator {
         &self.parameter_validator
     }
+
+    /// Get a reference to the stop token validator
+    pub fn stop_token_validator(&self) -> &StopTokenValidator {
+        &self.stop_token_validator
+    }
+
+    /// Fill unset parameters from the configured defaults, then validate the request.
+    ///
+    /// Defaults are injected before parameter bounds are checked, so configured defaults
+    /// are held to the same limits as values set on the request.
+    pub fn validate_with_defaults(
+        &self,
+        session: &Session,
+        request: &mut GenerationRequest,
+    ) -> ValidationResult {
+        self.parameter_validator.apply_defaults(request);
+        self.validate(session, request)
+    }
 }
 
 impl Default for CompositeGenerationRequestValidator {
@@ -96,6 +139,9 @@ impl Validator<GenerationRequest> for CompositeGenerationRequestValidator {
         // Step 3: Validate generation parameters
         self.parameter_validator.validate(session, request)?;
 
+        // Step 4: Check stop tokens against the chat template
+        self.stop_token_validator.validate(session, request)?;
+
         Ok(())
     }
 }
@@ -103,7 +149,7 @@ impl Validator<GenerationRequest> for CompositeGenerationRequestValidator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{Message, MessageRole, SessionId};
+    use crate::types::{Message, MessageRole, SessionId, SessionUsage, StreamChunking, ToolPolicy};
     use std::time::{Duration, SystemTime};
 
     fn create_test_session_with_messages(messages: Vec<Message>) -> Session {
@@ -115,6 +161,8 @@ mod tests {
             available_prompts: vec![],
             created_at: SystemTime::now() - Duration::from_secs(10),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
@@ -125,6 +173,7 @@ mod tests {
             tool_call_id: None,
             tool_name: None,
             timestamp: SystemTime::now(),
+            attachments: Vec::new(),
         }
     }
 
@@ -134,8 +183,17 @@ mod tests {
             max_tokens: Some(150),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: vec!["Human:".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         }
     }
 
@@ -222,6 +280,8 @@ mod tests {
                 temperature_range: (0.1, 1.0),
                 ..Default::default()
             },
+            control_tokens: ControlTokenPolicy::Strip,
+            stop_tokens: StopTokenConfig::default(),
         };
 
         let validator = CompositeGenerationRequestValidator::with_config(config);
@@ -313,6 +373,7 @@ mod tests {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now() - Duration::from_secs(60),
+                attachments: Vec::new(),
             },
             Message {
                 role: MessageRole::User,
@@ -320,6 +381,7 @@ mod tests {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now() - Duration::from_secs(30),
+                attachments: Vec::new(),
             },
             Message {
                 role: MessageRole::Assistant,
@@ -327,6 +389,7 @@ mod tests {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now() - Duration::from_secs(15),
+                attachments: Vec::new(),
             },
         ]);
 
@@ -335,8 +398,17 @@ mod tests {
             max_tokens: Some(500),
             temperature: Some(0.7),
             top_p: Some(0.9),
-            stop_tokens: vec!["Human:".to_string(), "\n\n".to_string()],
+            seed: None,
+            stop_tokens: vec!["Human:".to_string(), "\n\nHuman:".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
         assert!(validator.validate(&session, &request).is_ok());
@@ -418,6 +490,8 @@ mod tests {
                 repetition_threshold: 5,
             },
             parameters: ParameterConfig::default(),
+            control_tokens: ControlTokenPolicy::Strip,
+            stop_tokens: StopTokenConfig::default(),
         };
 
         let validator = CompositeGenerationRequestValidator::with_config(config);
@@ -433,4 +507,69 @@ mod tests {
             .to_string()
             .contains("unsafe content patterns"));
     }
+
+    #[test]
+    fn test_stop_tokens_checked_against_template() {
+        let config = ValidationConfig {
+            stop_tokens: StopTokenConfig {
+                control_sequences: vec!["<|im_start|>".to_string(), "<|im_end|>".to_string()],
+                assistant_header: "<|im_start|>assistant\n".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let validator = CompositeGenerationRequestValidator::with_config(config);
+        let session = create_test_session_with_messages(vec![create_valid_message("Hello")]);
+
+        let mut request = create_test_request();
+        request.stop_tokens = vec!["<|im_end|>".to_string()];
+        assert!(validator.validate(&session, &request).is_ok());
+
+        request.stop_tokens = vec!["im_start".to_string()];
+        let error = validator.validate(&session, &request).unwrap_err();
+        assert!(error.to_string().contains("\"<|im_start|>\""), "{}", error);
+
+        // Blank stop tokens are rejected even without a template
+        request.stop_tokens = vec!["\n\n".to_string()];
+        let result = CompositeGenerationRequestValidator::new().validate(&session, &request);
+        assert!(result.unwrap_err().to_string().contains("whitespace only"));
+    }
+
+    #[test]
+    fn test_validate_with_defaults_injects_before_bounds_check() {
+        let config = ValidationConfig {
+            parameters: ParameterConfig {
+                default_temperature: Some(0.3),
+                default_max_tokens: Some(200),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let validator = CompositeGenerationRequestValidator::with_config(config);
+        let session = create_test_session_with_messages(vec![create_valid_message("Hello")]);
+
+        let mut request = GenerationRequest::new(session.id);
+        assert!(validator
+            .validate_with_defaults(&session, &mut request)
+            .is_ok());
+        assert_eq!(request.temperature, Some(0.3));
+        assert_eq!(request.max_tokens, Some(200));
+        assert_eq!(request.top_p, None);
+
+        // A default outside the bounds is rejected like an explicit value
+        let config = ValidationConfig {
+            parameters: ParameterConfig {
+                default_temperature: Some(5.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let validator = CompositeGenerationRequestValidator::with_config(config);
+        let mut request = GenerationRequest::new(session.id);
+        let result = validator.validate_with_defaults(&session, &mut request);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("temperature must be between"));
+    }
 }
@@ -171,7 +171,7 @@ impl Validator<Message> for MessageContentValidator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{MessageRole, SessionId};
+    use crate::types::{MessageRole, SessionId, SessionUsage, ToolPolicy};
     use std::time::SystemTime;
 
     fn create_test_message(content: &str) -> Message {
@@ -181,6 +181,7 @@ mod tests {
             tool_call_id: None,
             tool_name: None,
             timestamp: SystemTime::now(),
+            attachments: Vec::new(),
         }
     }
 
@@ -193,6 +194,8 @@ mod tests {
             available_prompts: vec![],
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
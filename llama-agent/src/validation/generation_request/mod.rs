@@ -6,16 +6,23 @@ mod composite_validator;
 mod message_validator;
 mod parameter_validator;
 mod session_validator;
+mod stop_token_validator;
 
-pub use composite_validator::{CompositeGenerationRequestValidator, ValidationConfig};
+pub use composite_validator::{
+    CompositeGenerationRequestValidator, ControlTokenPolicy, ValidationConfig,
+};
 pub use message_validator::{MessageContentConfig, MessageContentValidator};
 pub use parameter_validator::{ParameterConfig, ParameterValidator};
 pub use session_validator::SessionStateValidator;
+pub use stop_token_validator::{StopTokenConfig, StopTokenValidator};
 
 #[cfg(test)]
 mod integration_tests {
     use super::*;
-    use crate::types::{GenerationRequest, Message, MessageRole, Session, SessionId};
+    use crate::types::{
+        GenerationRequest, Message, MessageRole, Session, SessionId, SessionUsage, StreamChunking,
+        ToolPolicy,
+    };
     use crate::validation::Validator;
     use std::time::SystemTime;
 
@@ -32,12 +39,15 @@ mod integration_tests {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now(),
+                attachments: Vec::new(),
             }],
             mcp_servers: vec![],
             available_tools: vec![],
             available_prompts: vec![],
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         };
 
         // Create a realistic generation request
@@ -46,8 +56,17 @@ mod integration_tests {
             max_tokens: Some(150),
             temperature: Some(0.8),
             top_p: None,
+            seed: None,
             stop_tokens: vec!["Human:".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
         // Validation should pass
@@ -68,6 +87,7 @@ mod integration_tests {
                     tool_call_id: None,
                     tool_name: None,
                     timestamp: SystemTime::now() - std::time::Duration::from_secs(120),
+                    attachments: Vec::new(),
                 },
                 Message {
                     role: MessageRole::User,
@@ -75,6 +95,7 @@ mod integration_tests {
                     tool_call_id: None,
                     tool_name: None,
                     timestamp: SystemTime::now() - std::time::Duration::from_secs(60),
+                    attachments: Vec::new(),
                 },
                 Message {
                     role: MessageRole::Assistant,
@@ -82,6 +103,7 @@ mod integration_tests {
                     tool_call_id: None,
                     tool_name: None,
                     timestamp: SystemTime::now() - std::time::Duration::from_secs(30),
+                    attachments: Vec::new(),
                 },
             ],
             mcp_servers: vec![],
@@ -89,6 +111,8 @@ mod integration_tests {
             available_prompts: vec![],
             created_at: SystemTime::now() - std::time::Duration::from_secs(180),
             updated_at: SystemTime::now() - std::time::Duration::from_secs(30),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         };
 
         let request = GenerationRequest {
@@ -96,8 +120,17 @@ mod integration_tests {
             max_tokens: Some(800),
             temperature: Some(0.8),
             top_p: Some(0.95),
+            seed: None,
             stop_tokens: vec!["User:".to_string(), "Human:".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
         // This should pass all validation stages
@@ -119,6 +152,8 @@ mod integration_tests {
             available_prompts: vec![],
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         };
 
         let request = GenerationRequest {
@@ -126,8 +161,17 @@ mod integration_tests {
             max_tokens: Some(800),
             temperature: Some(0.8),
             top_p: Some(0.95),
+            seed: None,
             stop_tokens: vec!["User:".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
         let result = validator.validate(&session, &request);
@@ -154,7 +198,10 @@ mod integration_tests {
                 top_p_range: (0.1, 0.9),
                 max_stop_tokens: 5,
                 max_stop_token_length: 20,
+                ..Default::default()
             },
+            control_tokens: ControlTokenPolicy::Strip,
+            stop_tokens: StopTokenConfig::default(),
         };
 
         let validator = CompositeGenerationRequestValidator::with_config(config);
@@ -168,12 +215,15 @@ mod integration_tests {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now(),
+                attachments: Vec::new(),
             }],
             mcp_servers: vec![],
             available_tools: vec![],
             available_prompts: vec![],
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         };
 
         let request = GenerationRequest {
@@ -181,8 +231,17 @@ mod integration_tests {
             max_tokens: Some(100),
             temperature: Some(0.8),
             top_p: Some(0.5),
+            seed: None,
             stop_tokens: vec!["stop".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         };
 
         let result = validator.validate(&session, &request);
@@ -1,7 +1,11 @@
 //! Generation parameter validation for requests
 
 use crate::types::{GenerationRequest, Session};
-use crate::validation::{ValidationError, ValidationResult, Validator};
+use crate::validation::{ValidationError, ValidationIssue, ValidationResult, Validator};
+use tracing::warn;
+
+/// Rough number of characters per token, for estimating prompt size without a tokenizer
+const CHARS_PER_TOKEN_ESTIMATE: usize = 4;
 
 /// Configuration for generation parameter validation
 #[derive(Debug, Clone)]
@@ -16,6 +20,15 @@ pub struct ParameterConfig {
     pub max_stop_tokens: usize,
     /// Maximum length for individual stop tokens
     pub max_stop_token_length: usize,
+    /// Temperature given to requests that leave it unset, by `apply_defaults`
+    pub default_temperature: Option<f32>,
+    /// top_p given to requests that leave it unset, by `apply_defaults`
+    pub default_top_p: Option<f32>,
+    /// max_tokens given to requests that leave it unset, by `apply_defaults`
+    pub default_max_tokens: Option<u32>,
+    /// Context window of the model in tokens; enables checking that the prompt and
+    /// max_tokens fit in it
+    pub context_size: Option<u32>,
 }
 
 impl Default for ParameterConfig {
@@ -26,6 +39,10 @@ impl Default for ParameterConfig {
             top_p_range: (0.0, 1.0),
             max_stop_tokens: 20,
             max_stop_token_length: 100,
+            default_temperature: None,
+            default_top_p: None,
+            default_max_tokens: None,
+            context_size: None,
         }
     }
 }
@@ -37,6 +54,9 @@ impl Default for ParameterConfig {
 /// - temperature validation for finite values and ranges  
 /// - top_p validation for finite values and ranges
 /// - stop tokens count and length validation
+/// - checks on parameter combinations, some of which only warn
+///
+/// It can also fill in unset parameters from the configured defaults.
 #[derive(Debug, Clone)]
 pub struct ParameterValidator {
     config: ParameterConfig,
@@ -58,14 +78,90 @@ impl ParameterValidator {
         &self.config
     }
 
-    /// Validate max_tokens parameter
+    /// Fill parameters the request leaves unset from the configured defaults.
+    ///
+    /// Values set on the request are never replaced.
+    pub fn apply_defaults(&self, request: &mut GenerationRequest) {
+        if request.temperature.is_none() {
+            request.temperature = self.config.default_temperature;
+        }
+        if request.top_p.is_none() {
+            request.top_p = self.config.default_top_p;
+        }
+        if request.effective_max_tokens().is_none() {
+            request.max_tokens = self.config.default_max_tokens;
+        }
+    }
+
+    /// Check combinations of parameters that are each within bounds.
+    ///
+    /// Combinations that cannot work are errors; ones where a parameter has no effect
+    /// are warnings.
+    pub fn check_combinations(
+        &self,
+        session: &Session,
+        request: &GenerationRequest,
+    ) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+
+        if request.temperature == Some(0.0) {
+            if let Some(top_p) = request.top_p.filter(|&top_p| top_p < 1.0) {
+                issues.push(ValidationIssue::warning(ValidationError::parameter_bounds(
+                    format!(
+                        "top_p {} has no effect with temperature 0, which always picks the most likely token",
+                        top_p
+                    ),
+                )));
+            }
+            if let Some(n) = request.n.filter(|&n| n > 1) {
+                issues.push(ValidationIssue::warning(ValidationError::parameter_bounds(
+                    format!(
+                        "n = {} with temperature 0 produces identical completions",
+                        n
+                    ),
+                )));
+            }
+        }
+
+        if let (Some(0.0), Some(temperature)) = (request.top_p, request.temperature) {
+            if temperature > 0.0 {
+                issues.push(ValidationIssue::warning(ValidationError::parameter_bounds(
+                    format!(
+                        "top_p 0 keeps only the most likely token, so temperature {} has no effect",
+                        temperature
+                    ),
+                )));
+            }
+        }
+
+        if let (Some(context_size), Some(max_tokens)) =
+            (self.config.context_size, request.effective_max_tokens())
+        {
+            let prompt_tokens = estimate_prompt_tokens(session);
+            if max_tokens >= context_size {
+                issues.push(ValidationIssue::error(ValidationError::parameter_bounds(
+                    format!(
+                        "max_tokens {} leaves no room for the prompt in a context of {} tokens",
+                        max_tokens, context_size
+                    ),
+                )));
+            } else if u64::from(max_tokens) + prompt_tokens > u64::from(context_size) {
+                // The prompt size is only estimated, so this may still fit
+                issues.push(ValidationIssue::warning(ValidationError::parameter_bounds(
+                    format!(
+                        "max_tokens {} plus a prompt of about {} tokens exceeds the context of {} tokens",
+                        max_tokens, prompt_tokens, context_size
+                    ),
+                )));
+            }
+        }
+
+        issues
+    }
+
+    /// Validate max_tokens parameter; 0 asks for the prompt to be decoded only
     fn validate_max_tokens(&self, max_tokens: Option<u32>) -> ValidationResult {
         if let Some(max_tokens) = max_tokens {
-            if max_tokens == 0 {
-                return Err(ValidationError::parameter_bounds(
-                    "max_tokens must be greater than 0",
-                ));
-            }
             if max_tokens > self.config.max_tokens_limit {
                 return Err(ValidationError::security_violation(format!(
                     "max_tokens exceeds security limit of {} (requested: {})",
@@ -136,6 +232,30 @@ impl ParameterValidator {
 
         Ok(())
     }
+
+    /// Validate stop token ids
+    fn validate_stop_token_ids(&self, stop_token_ids: &[u32]) -> ValidationResult {
+        // Security: Validate stop token id count
+        if stop_token_ids.len() > self.config.max_stop_tokens {
+            return Err(ValidationError::security_violation(format!(
+                "Too many stop token ids: {} (max {} allowed)",
+                stop_token_ids.len(),
+                self.config.max_stop_tokens
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Estimated prompt size of a session's messages, in tokens
+fn estimate_prompt_tokens(session: &Session) -> u64 {
+    let chars: usize = session
+        .messages
+        .iter()
+        .map(|message| message.rendered_content().chars().count())
+        .sum();
+    chars.div_ceil(CHARS_PER_TOKEN_ESTIMATE) as u64
 }
 
 impl Default for ParameterValidator {
@@ -147,7 +267,7 @@ impl Default for ParameterValidator {
 impl Validator<GenerationRequest> for ParameterValidator {
     type Error = ValidationError;
 
-    fn validate(&self, _session: &Session, request: &GenerationRequest) -> ValidationResult {
+    fn validate(&self, session: &Session, request: &GenerationRequest) -> ValidationResult {
         // Validate max_tokens
         self.validate_max_tokens(request.max_tokens)?;
 
@@ -159,6 +279,20 @@ impl Validator<GenerationRequest> for ParameterValidator {
 
         // Validate stop tokens
         self.validate_stop_tokens(&request.stop_tokens)?;
+        self.validate_stop_token_ids(&request.stop_token_ids)?;
+
+        // Validate parameter combinations; warnings are logged and do not fail
+        let mut errors = Vec::new();
+        for issue in self.check_combinations(session, request) {
+            if issue.is_error() {
+                errors.push(issue.error);
+            } else {
+                warn!("Generation request {}: {}", request.session_id, issue.error);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ValidationError::multiple(errors));
+        }
 
         Ok(())
     }
@@ -167,7 +301,8 @@ impl Validator<GenerationRequest> for ParameterValidator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::SessionId;
+    use crate::types::{SessionId, SessionUsage, StreamChunking, ToolPolicy};
+    use crate::validation::ValidationSeverity;
     use std::time::SystemTime;
 
     fn create_test_session() -> Session {
@@ -179,6 +314,8 @@ mod tests {
             available_prompts: vec![],
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
@@ -188,8 +325,17 @@ mod tests {
             max_tokens: Some(100),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: vec!["Human:".to_string()],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         }
     }
 
@@ -207,12 +353,10 @@ mod tests {
         let validator = ParameterValidator::new();
         let session = create_test_session();
 
-        // Test zero max_tokens
+        // Zero max_tokens is a prefill-only request
         let mut request = create_test_request();
         request.max_tokens = Some(0);
-        let result = validator.validate(&session, &request);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("greater than 0"));
+        assert!(validator.validate(&session, &request).is_ok());
 
         // Test excessive max_tokens
         request.max_tokens = Some(50_000);
@@ -319,6 +463,14 @@ mod tests {
         // Test empty stop tokens (should pass)
         request.stop_tokens = vec![];
         assert!(validator.validate(&session, &request).is_ok());
+
+        // Test too many stop token ids
+        request.stop_token_ids = (0..25).collect();
+        let result = validator.validate(&session, &request);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Too many stop token ids"));
     }
 
     #[test]
@@ -329,6 +481,7 @@ mod tests {
             top_p_range: (0.1, 0.9),
             max_stop_tokens: 5,
             max_stop_token_length: 10,
+            ..Default::default()
         };
 
         let validator = ParameterValidator::with_config(config);
@@ -393,4 +546,159 @@ mod tests {
         let validator = ParameterValidator::with_config(custom_config.clone());
         assert_eq!(validator.config().max_tokens_limit, 5000);
     }
+
+    fn session_with_prompt(chars: usize) -> Session {
+        Session {
+            messages: vec![crate::types::Message {
+                role: crate::types::MessageRole::User,
+                content: "a".repeat(chars),
+                tool_call_id: None,
+                tool_name: None,
+                timestamp: SystemTime::now(),
+                attachments: Vec::new(),
+            }],
+            ..create_test_session()
+        }
+    }
+
+    fn severities(issues: &[ValidationIssue]) -> Vec<ValidationSeverity> {
+        issues.iter().map(|issue| issue.severity).collect()
+    }
+
+    #[test]
+    fn test_top_p_with_zero_temperature_warns() {
+        let validator = ParameterValidator::new();
+        let session = create_test_session();
+        let mut request = create_test_request();
+        request.temperature = Some(0.0);
+        request.top_p = Some(0.5);
+
+        let issues = validator.check_combinations(&session, &request);
+        assert_eq!(severities(&issues), [ValidationSeverity::Warning]);
+        assert!(issues[0].error.to_string().contains("temperature 0"));
+        // Warnings do not fail validation
+        assert!(validator.validate(&session, &request).is_ok());
+
+        // top_p 1.0 disables nucleus sampling, so there is nothing to warn about
+        request.top_p = Some(1.0);
+        assert!(validator.check_combinations(&session, &request).is_empty());
+    }
+
+    #[test]
+    fn test_zero_top_p_with_temperature_warns() {
+        let validator = ParameterValidator::new();
+        let session = create_test_session();
+        let mut request = create_test_request();
+        request.top_p = Some(0.0);
+
+        let issues = validator.check_combinations(&session, &request);
+        assert_eq!(severities(&issues), [ValidationSeverity::Warning]);
+        assert!(issues[0].error.to_string().contains("top_p 0"));
+    }
+
+    #[test]
+    fn test_multiple_completions_with_zero_temperature_warns() {
+        let validator = ParameterValidator::new();
+        let session = create_test_session();
+        let mut request = create_test_request();
+        request.temperature = Some(0.0);
+        request.top_p = None;
+        request.n = Some(3);
+
+        let issues = validator.check_combinations(&session, &request);
+        assert_eq!(severities(&issues), [ValidationSeverity::Warning]);
+        assert!(issues[0]
+            .error
+            .to_string()
+            .contains("identical completions"));
+
+        request.n = Some(1);
+        assert!(validator.check_combinations(&session, &request).is_empty());
+    }
+
+    #[test]
+    fn test_max_tokens_against_context_size() {
+        let validator = ParameterValidator::with_config(ParameterConfig {
+            context_size: Some(1024),
+            ..Default::default()
+        });
+        let mut request = create_test_request();
+
+        // max_tokens alone fills the context: rejected
+        request.max_tokens = Some(1024);
+        let session = create_test_session();
+        let issues = validator.check_combinations(&session, &request);
+        assert_eq!(severities(&issues), [ValidationSeverity::Error]);
+        let error = validator.validate(&session, &request).unwrap_err();
+        assert!(error.to_string().contains("leaves no room for the prompt"));
+
+        // About 250 prompt tokens plus 800 does not fit: warned, as the prompt is estimated
+        request.max_tokens = Some(800);
+        let session = session_with_prompt(1000);
+        let issues = validator.check_combinations(&session, &request);
+        assert_eq!(severities(&issues), [ValidationSeverity::Warning]);
+        assert!(issues[0].error.to_string().contains("about 250 tokens"));
+        assert!(validator.validate(&session, &request).is_ok());
+
+        // Fits
+        request.max_tokens = Some(500);
+        assert!(validator.check_combinations(&session, &request).is_empty());
+
+        // Without a context size there is nothing to check against
+        request.max_tokens = Some(30_000);
+        assert!(ParameterValidator::new()
+            .check_combinations(&session, &request)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_apply_defaults_fills_unset_fields() {
+        let validator = ParameterValidator::with_config(ParameterConfig {
+            default_temperature: Some(0.2),
+            default_top_p: Some(0.95),
+            default_max_tokens: Some(256),
+            ..Default::default()
+        });
+
+        let mut request = GenerationRequest::new(SessionId::new());
+        validator.apply_defaults(&mut request);
+        assert_eq!(request.temperature, Some(0.2));
+        assert_eq!(request.top_p, Some(0.95));
+        assert_eq!(request.max_tokens, Some(256));
+    }
+
+    #[test]
+    fn test_apply_defaults_keeps_explicit_values() {
+        let validator = ParameterValidator::with_config(ParameterConfig {
+            default_temperature: Some(0.2),
+            default_top_p: Some(0.95),
+            default_max_tokens: Some(256),
+            ..Default::default()
+        });
+
+        let mut request = create_test_request();
+        request.temperature = Some(0.0);
+        validator.apply_defaults(&mut request);
+        assert_eq!(request.temperature, Some(0.0));
+        assert_eq!(request.top_p, Some(0.9));
+        assert_eq!(request.max_tokens, Some(100));
+
+        // max_tokens from the stopping config counts as set
+        let mut request = GenerationRequest::new(SessionId::new()).with_stopping_config(
+            crate::types::StoppingConfig {
+                max_tokens: Some(64),
+                ..Default::default()
+            },
+        );
+        validator.apply_defaults(&mut request);
+        assert_eq!(request.max_tokens, None);
+        assert_eq!(request.effective_max_tokens(), Some(64));
+
+        // Without configured defaults nothing changes
+        let mut request = GenerationRequest::new(SessionId::new());
+        ParameterValidator::new().apply_defaults(&mut request);
+        assert_eq!(request.temperature, None);
+        assert_eq!(request.top_p, None);
+        assert_eq!(request.max_tokens, None);
+    }
 }
@@ -46,10 +46,15 @@ impl Validator<GenerationRequest> for SessionStateValidator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{Message, MessageRole, SessionId};
-    use std::time::{Duration, SystemTime};
-
-    fn create_test_session() -> Session {
+    use crate::clock::Clock;
+    use crate::test_support::MockClock;
+    use crate::types::{Message, MessageRole, SessionId, SessionUsage, StreamChunking, ToolPolicy};
+    use std::time::Duration;
+
+    /// Session created at the clock's current time, with a message 10 seconds later
+    fn session_at(clock: &MockClock) -> Session {
+        let created_at = clock.now();
+        clock.advance(Duration::from_secs(10));
         Session {
             id: SessionId::new(),
             messages: vec![Message {
@@ -57,24 +62,40 @@ mod tests {
                 content: "Hello".to_string(),
                 tool_call_id: None,
                 tool_name: None,
-                timestamp: SystemTime::now(),
+                timestamp: clock.now(),
+                attachments: Vec::new(),
             }],
             mcp_servers: vec![],
             available_tools: vec![],
             available_prompts: vec![],
-            created_at: SystemTime::now() - Duration::from_secs(10),
-            updated_at: SystemTime::now(),
+            created_at,
+            updated_at: clock.now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
+    fn create_test_session() -> Session {
+        session_at(&MockClock::default())
+    }
+
     fn create_test_request() -> GenerationRequest {
         GenerationRequest {
             session_id: SessionId::new(),
             max_tokens: Some(100),
             temperature: Some(0.7),
             top_p: Some(0.9),
+            seed: None,
             stop_tokens: vec![],
+            stop_token_ids: vec![],
+            n: None,
+            append_to_session: None,
+            chunking: StreamChunking::Token,
+            block_on_full: false,
+            model: None,
+            max_duration: None,
             stopping_config: None,
+            debug_tokens: false,
         }
     }
 
@@ -105,11 +126,16 @@ mod tests {
     #[test]
     fn test_invalid_timestamps_fail() {
         let validator = SessionStateValidator::new();
-        let mut session = create_test_session();
-        session.created_at = SystemTime::now();
-        session.updated_at = SystemTime::now() - Duration::from_secs(10);
+        let clock = MockClock::default();
+        let mut session = session_at(&clock);
+        session.updated_at = session.created_at - Duration::from_secs(1);
         let request = create_test_request();
 
+        // Equal timestamps are fine
+        let mut fresh = session.clone();
+        fresh.updated_at = fresh.created_at;
+        assert!(validator.validate(&fresh, &request).is_ok());
+
         let result = validator.validate(&session, &request);
         assert!(result.is_err());
         assert!(result
@@ -0,0 +1,274 @@
+//! Stop token validation against the chat template's own markup
+
+use crate::types::{GenerationRequest, Session};
+use crate::validation::{
+    ValidationError, ValidationIssue, ValidationResult, ValidationSeverity, Validator,
+};
+use tracing::warn;
+
+/// Configuration for stop token validation
+#[derive(Debug, Clone)]
+pub struct StopTokenConfig {
+    /// Sequences that delimit messages in the active chat template, such as `<|im_end|>`
+    pub control_sequences: Vec<String>,
+    /// Text the rendered prompt ends with, opening the assistant's turn
+    pub assistant_header: String,
+    /// Whether a stop token colliding with the template rejects the request or is logged
+    pub collision_severity: ValidationSeverity,
+}
+
+impl Default for StopTokenConfig {
+    fn default() -> Self {
+        Self {
+            control_sequences: Vec::new(),
+            assistant_header: String::new(),
+            collision_severity: ValidationSeverity::Error,
+        }
+    }
+}
+
+/// Validates stop tokens against the chat template
+///
+/// A stop token that is only part of a control sequence (`<|`, `im_start`) or that
+/// appears in the assistant header ending the prompt matches the template's own markup,
+/// so generation stops at once with no output. Such collisions are reported with the
+/// configured severity; empty and whitespace-only stop tokens are always rejected.
+/// A stop token equal to a whole control sequence, such as `<|im_end|>`, is fine.
+#[derive(Debug, Clone)]
+pub struct StopTokenValidator {
+    config: StopTokenConfig,
+}
+
+impl StopTokenValidator {
+    /// Create a validator that knows no template, checking only for blank stop tokens
+    pub fn new() -> Self {
+        Self::with_config(StopTokenConfig::default())
+    }
+
+    /// Create a validator with custom configuration
+    pub fn with_config(config: StopTokenConfig) -> Self {
+        Self { config }
+    }
+
+    /// Get the current configuration
+    pub fn config(&self) -> &StopTokenConfig {
+        &self.config
+    }
+
+    /// Findings for each problem stop token
+    pub fn check(&self, stop_tokens: &[String]) -> Vec<ValidationIssue> {
+        stop_tokens
+            .iter()
+            .enumerate()
+            .filter_map(|(i, stop_token)| {
+                if stop_token.trim().is_empty() {
+                    return Some(ValidationIssue::error(ValidationError::parameter_bounds(
+                        format!("stop token {} is empty or whitespace only", i),
+                    )));
+                }
+                self.collision(stop_token).map(|reason| ValidationIssue {
+                    severity: self.config.collision_severity,
+                    error: ValidationError::parameter_bounds(reason),
+                })
+            })
+            .collect()
+    }
+
+    /// Why `stop_token` matches the template's own markup, if it does
+    fn collision(&self, stop_token: &str) -> Option<String> {
+        let is_marker = self
+            .config
+            .control_sequences
+            .iter()
+            .any(|sequence| sequence == stop_token);
+        if is_marker {
+            return None;
+        }
+
+        if let Some(sequence) = self
+            .config
+            .control_sequences
+            .iter()
+            .find(|sequence| sequence.contains(stop_token))
+        {
+            return Some(format!(
+                "stop token {:?} is part of the chat template control sequence {:?}, so generation would stop on the template's own markup; use the whole sequence or another stop token",
+                stop_token, sequence
+            ));
+        }
+
+        let header = &self.config.assistant_header;
+        if stop_token != header.trim() && header.contains(stop_token) {
+            return Some(format!(
+                "stop token {:?} appears in the assistant header {:?} that ends the prompt, so generation would stop on the template's own markup",
+                stop_token, header
+            ));
+        }
+
+        None
+    }
+}
+
+impl Default for StopTokenValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Validator<GenerationRequest> for StopTokenValidator {
+    type Error = ValidationError;
+
+    fn validate(&self, _session: &Session, request: &GenerationRequest) -> ValidationResult {
+        // Warnings are logged and do not fail
+        let mut errors = Vec::new();
+        for issue in self.check(&request.stop_tokens) {
+            if issue.is_error() {
+                errors.push(issue.error);
+            } else {
+                warn!("Generation request {}: {}", request.session_id, issue.error);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ValidationError::multiple(errors));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat_template::ChatTemplateEngine;
+    use crate::types::{ModelConfig, TemplateOverride};
+
+    /// Validator for the builtin template `name`, as the agent builds it
+    fn validator_for(name: &str, collision_severity: ValidationSeverity) -> StopTokenValidator {
+        let engine = ChatTemplateEngine::new();
+        let config = ModelConfig {
+            chat_template: Some(TemplateOverride::Named(name.to_string())),
+            ..Default::default()
+        };
+        StopTokenValidator::with_config(StopTokenConfig {
+            control_sequences: engine.control_sequences(Some(&config)),
+            assistant_header: engine.assistant_header(Some(&config)),
+            collision_severity,
+        })
+    }
+
+    fn stops(stop_tokens: &[&str]) -> Vec<String> {
+        stop_tokens.iter().map(|stop| stop.to_string()).collect()
+    }
+
+    #[test]
+    fn test_collisions_per_template() {
+        let cases: [(&str, &[&str], &str, &[&str]); 4] = [
+            (
+                "qwen",
+                &["<|", "im_start", "|>"],
+                "<|im_start|>",
+                &["<|im_end|>", "<|im_start|>", "User:", "END"],
+            ),
+            (
+                "phi3",
+                &["assistant", "<|end"],
+                "<|assistant|>",
+                &["<|end|>", "<|user|>", "User:", "END"],
+            ),
+            (
+                "llama3",
+                &["header_id", "<|eot"],
+                "<|start_header_id|>",
+                &["<|eot_id|>", "<|start_header_id|>", "User:", "END"],
+            ),
+            (
+                "generic",
+                &["Human:", "### "],
+                "### Human:",
+                &["### Human:", "### Assistant:", "User:", "END"],
+            ),
+        ];
+
+        for (template, colliding, sequence, safe) in cases {
+            let validator = validator_for(template, ValidationSeverity::Error);
+            let issues = validator.check(&stops(colliding));
+            assert_eq!(issues.len(), colliding.len(), "{}", template);
+            assert!(issues.iter().all(ValidationIssue::is_error));
+            assert!(
+                issues[0].error.to_string().contains(sequence),
+                "{}: {}",
+                template,
+                issues[0].error
+            );
+            assert!(validator.check(&stops(safe)).is_empty(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_assistant_header_collision() {
+        // "assistant" is in the Qwen header but in none of its control sequences
+        let validator = validator_for("qwen", ValidationSeverity::Error);
+        let issues = validator.check(&stops(&["assistant"]));
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].error.to_string().contains("assistant header"));
+    }
+
+    #[test]
+    fn test_custom_template_collisions() {
+        let engine = ChatTemplateEngine::new();
+        let config = ModelConfig {
+            chat_template: Some(TemplateOverride::Custom(crate::types::CustomTemplate {
+                assistant_header: "<bot>: ".to_string(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let validator = StopTokenValidator::with_config(StopTokenConfig {
+            control_sequences: engine.control_sequences(Some(&config)),
+            assistant_header: engine.assistant_header(Some(&config)),
+            ..Default::default()
+        });
+        assert_eq!(validator.check(&stops(&["bot"])).len(), 1);
+        assert!(validator.check(&stops(&["<bot>:"])).is_empty());
+    }
+
+    #[test]
+    fn test_collision_severity_warning() {
+        let validator = validator_for("qwen", ValidationSeverity::Warning);
+        let issues = validator.check(&stops(&["<|"]));
+        assert_eq!(issues[0].severity, ValidationSeverity::Warning);
+
+        let session = Session {
+            id: crate::types::SessionId::new(),
+            messages: vec![],
+            mcp_servers: vec![],
+            available_tools: vec![],
+            available_prompts: vec![],
+            created_at: std::time::SystemTime::now(),
+            updated_at: std::time::SystemTime::now(),
+            tool_policy: crate::types::ToolPolicy::AllowAll,
+            usage: crate::types::SessionUsage::default(),
+        };
+        let request = GenerationRequest::new(session.id).with_stop_tokens(stops(&["<|"]));
+        assert!(validator.validate(&session, &request).is_ok());
+        let strict = validator_for("qwen", ValidationSeverity::Error);
+        assert!(strict.validate(&session, &request).is_err());
+    }
+
+    #[test]
+    fn test_blank_stop_tokens_are_rejected() {
+        // Rejected whatever the severity, even without a template
+        for validator in [
+            StopTokenValidator::new(),
+            validator_for("qwen", ValidationSeverity::Warning),
+        ] {
+            let issues = validator.check(&stops(&["", " \n\t", "ok"]));
+            assert_eq!(issues.len(), 2);
+            assert!(issues.iter().all(ValidationIssue::is_error));
+            assert!(issues[1]
+                .error
+                .to_string()
+                .contains("stop token 1 is empty or whitespace only"));
+        }
+    }
+}
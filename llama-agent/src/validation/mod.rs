@@ -9,13 +9,13 @@ pub mod tool_call;
 pub mod traits;
 
 // Re-export main validation types
-pub use errors::{ValidationError, ValidationResult};
+pub use errors::{ValidationError, ValidationIssue, ValidationResult, ValidationSeverity};
 pub use traits::{CompositeValidator, ValidatesGenerationRequest, ValidatesToolCall, Validator};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{Message, MessageRole, Session, SessionId};
+    use crate::types::{Message, MessageRole, Session, SessionId, SessionUsage, ToolPolicy};
     use std::time::SystemTime;
 
     /// Simple test validator for testing the trait system
@@ -48,12 +48,15 @@ mod tests {
                 tool_call_id: None,
                 tool_name: None,
                 timestamp: SystemTime::now(),
+                attachments: Vec::new(),
             }],
             mcp_servers: vec![],
             available_tools: vec![],
             available_prompts: vec![],
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
@@ -225,7 +225,7 @@ impl Validator<ToolCall> for ToolArgumentValidator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{SessionId, ToolCallId, ToolDefinition};
+    use crate::types::{SessionId, SessionUsage, ToolCallId, ToolDefinition, ToolPolicy};
     use serde_json::json;
     use std::time::SystemTime;
 
@@ -238,6 +238,8 @@ mod tests {
             available_prompts: vec![],
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
@@ -252,6 +254,7 @@ mod tests {
                 }
             }),
             server_name: "test_server".to_string(),
+            original_name: None,
         }
     }
 
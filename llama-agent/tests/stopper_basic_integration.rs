@@ -1,6 +1,6 @@
 use llama_agent::{
     stopper::{EosStopper, MaxTokensStopper, RepetitionStopper, Stopper},
-    types::{FinishReason, RepetitionConfig},
+    types::{FinishReason, RepetitionConfig, RepetitionMode},
 };
 use std::time::Instant;
 use tracing::info;
@@ -27,6 +27,7 @@ async fn test_stopper_creation_and_interface() -> Result<(), Box<dyn std::error:
         max_pattern_length: 50,
         min_repetitions: 3,
         window_size: 1000,
+        mode: RepetitionMode::Text,
     };
     let repetition_stopper = RepetitionStopper::new(config);
 
@@ -79,6 +80,7 @@ async fn test_repetition_stopper_pattern_detection() -> Result<(), Box<dyn std::
         max_pattern_length: 20,
         min_repetitions: 3,
         window_size: 200,
+        mode: RepetitionMode::Text,
     };
 
     let mut stopper = RepetitionStopper::new(config);
@@ -149,6 +151,7 @@ async fn test_stopper_memory_usage() -> Result<(), Box<dyn std::error::Error>> {
         max_pattern_length: 20,
         min_repetitions: 2,
         window_size: 100, // Small window for testing
+        mode: RepetitionMode::Text,
     };
 
     let mut stopper = RepetitionStopper::new(config);
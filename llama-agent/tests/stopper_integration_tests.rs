@@ -1,6 +1,6 @@
 use llama_agent::{
     stopper::{EosStopper, MaxTokensStopper, RepetitionStopper, Stopper},
-    types::{FinishReason, RepetitionConfig},
+    types::{FinishReason, RepetitionConfig, RepetitionMode},
 };
 use llama_cpp_2::{
     context::params::LlamaContextParams,
@@ -369,6 +369,7 @@ async fn test_repetition_stopper_integration() -> Result<(), Box<dyn std::error:
         max_pattern_length: 20,
         min_repetitions: 3, // Trigger on 3 repetitions
         window_size: 200,   // Small window for testing
+        mode: RepetitionMode::Text,
     };
 
     let mut repetition_stopper = RepetitionStopper::new(config);
@@ -458,6 +459,7 @@ async fn test_combined_stoppers_integration() -> Result<(), Box<dyn std::error::
             max_pattern_length: 10,
             min_repetitions: 3,
             window_size: 100,
+            mode: RepetitionMode::Text,
         })),
     ];
 
@@ -763,6 +765,7 @@ async fn test_edge_cases_and_error_handling() -> Result<(), Box<dyn std::error::
         max_pattern_length: 999,  // Smaller than min (invalid)
         min_repetitions: 1,
         window_size: 10,
+        mode: RepetitionMode::Text,
     };
 
     let mut extreme_stopper = RepetitionStopper::new(extreme_config);
@@ -782,6 +785,7 @@ async fn test_edge_cases_and_error_handling() -> Result<(), Box<dyn std::error::
         max_pattern_length: 10,
         min_repetitions: 2,
         window_size: 50, // Small window
+        mode: RepetitionMode::Text,
     };
 
     let mut memory_stopper = RepetitionStopper::new(memory_test_config);
@@ -1,6 +1,6 @@
 use llama_agent::{
     stopper::{EosStopper, MaxTokensStopper, RepetitionStopper, Stopper},
-    types::{FinishReason, RepetitionConfig},
+    types::{FinishReason, RepetitionConfig, RepetitionMode},
 };
 use llama_cpp_2::{
     context::params::LlamaContextParams,
@@ -168,6 +168,7 @@ async fn test_stopper_implementations_with_real_model() -> Result<(), Box<dyn st
         max_pattern_length: 20,
         min_repetitions: 3, // Trigger on 3 repetitions
         window_size: 200,   // Small window for testing
+        mode: RepetitionMode::Text,
     };
 
     let mut repetition_stopper = RepetitionStopper::new(config);
@@ -221,6 +222,7 @@ async fn test_stopper_implementations_with_real_model() -> Result<(), Box<dyn st
             max_pattern_length: 10,
             min_repetitions: 2,
             window_size: 100,
+            mode: RepetitionMode::Text,
         })),
     ];
 
@@ -413,6 +415,7 @@ async fn test_repetition_stopper_memory_bounds() -> Result<(), Box<dyn std::erro
         max_pattern_length: 10,
         min_repetitions: 2,
         window_size: 100, // Small window to test bounds
+        mode: RepetitionMode::Text,
     };
 
     let mut stopper = RepetitionStopper::new(config);
@@ -527,6 +530,7 @@ async fn test_comprehensive_edge_cases() -> Result<(), Box<dyn std::error::Error
         max_pattern_length: 999,  // Smaller than min (invalid)
         min_repetitions: 1,
         window_size: 10,
+        mode: RepetitionMode::Text,
     };
 
     let mut extreme_stopper = RepetitionStopper::new(extreme_config);
@@ -544,6 +548,7 @@ async fn test_comprehensive_edge_cases() -> Result<(), Box<dyn std::error::Error
         max_pattern_length: 0,
         min_repetitions: 0,
         window_size: 0,
+        mode: RepetitionMode::Text,
     };
 
     let mut zero_stopper = RepetitionStopper::new(zero_config);
@@ -557,6 +562,7 @@ async fn test_comprehensive_edge_cases() -> Result<(), Box<dyn std::error::Error
         max_pattern_length: 10,
         min_repetitions: 2,
         window_size: 100,
+        mode: RepetitionMode::Text,
     };
 
     let mut unicode_stopper = RepetitionStopper::new(unicode_config);
@@ -0,0 +1,287 @@
+//! Batch generation over a file of prompts given with `--prompts-file`.
+//!
+//! Each non-blank line is either a plain prompt or a JSON object
+//! `{"prompt": ..., "id": ...}`; `id` defaults to the line number. Results are written
+//! as JSON lines in the order prompts complete.
+
+use crate::error::CliError;
+use crate::generate::{generation_request, GenerateArgs};
+use futures::StreamExt;
+use llama_agent::{
+    types::{AgentAPI, FinishReason, GenerationResponse, Message, MessageRole},
+    AgentServer,
+};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+use tracing::warn;
+
+/// One prompt of a prompts file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchPrompt {
+    pub id: String,
+    pub prompt: String,
+}
+
+/// Outcome of one prompt, written as a line of the results file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub id: String,
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<u32>,
+    /// Time spent generating, once a worker started on the prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_ms: Option<u64>,
+    /// Time from submitting the prompt to its result, queueing included
+    pub elapsed_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    fn succeeded(prompt: BatchPrompt, response: GenerationResponse, start: Instant) -> Self {
+        let FinishReason::Stopped(reason) = response.finish_reason;
+        Self {
+            id: prompt.id,
+            prompt: prompt.prompt,
+            generated_text: Some(response.generated_text),
+            finish_reason: Some(reason),
+            prompt_tokens: Some(response.prompt_tokens),
+            tokens: Some(response.tokens_generated),
+            generation_ms: Some(response.generation_time.as_millis() as u64),
+            elapsed_ms: start.elapsed().as_millis() as u64,
+            error: None,
+        }
+    }
+
+    fn failed(prompt: BatchPrompt, error: impl ToString, start: Instant) -> Self {
+        Self {
+            id: prompt.id,
+            prompt: prompt.prompt,
+            generated_text: None,
+            finish_reason: None,
+            prompt_tokens: None,
+            tokens: None,
+            generation_ms: None,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Success and failure counts of a batch run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatchSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl std::fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Prompts: {} succeeded, {} failed",
+            self.succeeded, self.failed
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonPrompt {
+    prompt: String,
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+}
+
+/// Parse a prompts file. A line that is not a valid prompt comes back as an error
+/// for that line, so the rest of the file still runs.
+pub fn parse_prompts(text: &str) -> Vec<Result<BatchPrompt, (BatchPrompt, String)>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| parse_prompt_line(index + 1, line))
+        .collect()
+}
+
+fn parse_prompt_line(line_number: usize, line: &str) -> Result<BatchPrompt, (BatchPrompt, String)> {
+    let plain = BatchPrompt {
+        id: line_number.to_string(),
+        prompt: line.to_string(),
+    };
+    if !line.trim_start().starts_with('{') {
+        return Ok(plain);
+    }
+
+    let parsed: JsonPrompt = serde_json::from_str(line).map_err(|e| {
+        (
+            plain.clone(),
+            format!("Line {}: invalid JSON prompt: {}", line_number, e),
+        )
+    })?;
+    let id = match parsed.id {
+        None | Some(serde_json::Value::Null) => line_number.to_string(),
+        Some(serde_json::Value::String(id)) => id,
+        Some(id) => id.to_string(),
+    };
+    let prompt = BatchPrompt {
+        id,
+        prompt: parsed.prompt,
+    };
+    if prompt.prompt.trim().is_empty() {
+        return Err((prompt, format!("Line {}: prompt is empty", line_number)));
+    }
+    Ok(prompt)
+}
+
+/// Run every prompt of `path` in a fresh session, `--concurrency` at a time, writing
+/// results to `--output` or else `out` as they complete
+pub(crate) async fn run_prompts_file<W: Write>(
+    agent: &AgentServer,
+    args: &GenerateArgs,
+    path: &Path,
+    out: &mut W,
+) -> Result<BatchSummary, CliError> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        CliError::Validation(anyhow::anyhow!(
+            "Failed to read prompts file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let mut file;
+    let writer: &mut dyn Write = match &args.output {
+        Some(output) => {
+            file = std::fs::File::create(output).map_err(|e| {
+                CliError::Runtime(anyhow::anyhow!(
+                    "Failed to create {}: {}",
+                    output.display(),
+                    e
+                ))
+            })?;
+            &mut file
+        }
+        None => out,
+    };
+
+    let mut results = futures::stream::iter(parse_prompts(&text))
+        .map(|prompt| run_prompt(agent, args, prompt))
+        .buffer_unordered(args.concurrency);
+
+    let mut summary = BatchSummary::default();
+    while let Some(result) = results.next().await {
+        match &result.error {
+            None => summary.succeeded += 1,
+            Some(error) => {
+                warn!("Prompt '{}' failed: {}", result.id, error);
+                summary.failed += 1;
+            }
+        }
+        let line = serde_json::to_string(&result).map_err(|e| CliError::Runtime(e.into()))?;
+        writeln!(writer, "{}", line)
+            .and_then(|()| writer.flush())
+            .map_err(|e| CliError::Runtime(anyhow::anyhow!("Failed to write result: {}", e)))?;
+    }
+
+    Ok(summary)
+}
+
+/// Generate a reply to one prompt in its own session
+async fn run_prompt(
+    agent: &AgentServer,
+    args: &GenerateArgs,
+    prompt: Result<BatchPrompt, (BatchPrompt, String)>,
+) -> BatchResult {
+    let start = Instant::now();
+    let prompt = match prompt {
+        Ok(prompt) => prompt,
+        Err((prompt, error)) => return BatchResult::failed(prompt, error, start),
+    };
+
+    let session = match agent.create_session().await {
+        Ok(session) => session,
+        Err(e) => return BatchResult::failed(prompt, e, start),
+    };
+    let message = Message {
+        role: MessageRole::User,
+        content: prompt.prompt.clone(),
+        tool_call_id: None,
+        tool_name: None,
+        timestamp: std::time::SystemTime::now(),
+        attachments: Vec::new(),
+    };
+    let response = match agent.add_message(&session.id, message).await {
+        Ok(()) => {
+            let request = generation_request(args, session.id).with_block_on_full(true);
+            agent.generate(request).await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = agent.delete_session(&session.id).await {
+        warn!("Failed to delete batch session {}: {}", session.id, e);
+    }
+
+    match response {
+        Ok(response) => BatchResult::succeeded(prompt, response, start),
+        Err(e) => BatchResult::failed(prompt, e, start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prompts_plain_and_json() {
+        let text = "What is an apple?\n\n{\"prompt\": \"Name a pear\", \"id\": \"pear\"}\n{\"id\": 7, \"prompt\": \"Seven\"}\n{\"prompt\": \"No id\"}\n";
+        let prompts: Vec<BatchPrompt> = parse_prompts(text)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            prompts,
+            vec![
+                BatchPrompt {
+                    id: "1".to_string(),
+                    prompt: "What is an apple?".to_string()
+                },
+                BatchPrompt {
+                    id: "pear".to_string(),
+                    prompt: "Name a pear".to_string()
+                },
+                BatchPrompt {
+                    id: "7".to_string(),
+                    prompt: "Seven".to_string()
+                },
+                BatchPrompt {
+                    id: "5".to_string(),
+                    prompt: "No id".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_prompts_reports_bad_lines() {
+        let parsed = parse_prompts("{\"id\": \"a\"}\n{\"prompt\": \" \", \"id\": \"b\"}\nfine\n");
+        let (prompt, error) = parsed[0].clone().unwrap_err();
+        assert_eq!(prompt.id, "1");
+        assert!(
+            error.starts_with("Line 1: invalid JSON prompt"),
+            "{}",
+            error
+        );
+        let (prompt, error) = parsed[1].clone().unwrap_err();
+        assert_eq!(prompt.id, "b");
+        assert_eq!(error, "Line 2: prompt is empty");
+        assert!(parsed[2].is_ok());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::bench_stats::{BenchReport, IterationTiming};
+use crate::error::CliError;
+use crate::generate::{describe_model_source, initialize_interruptible, ModelArgs};
+use anyhow::Result;
+use clap::builder::PossibleValuesParser;
+use clap::{Args, ValueEnum};
+use futures::StreamExt;
+use llama_agent::{
+    types::{AgentAPI, GenerationRequest, Message, MessageRole, SessionId, TemplateOverride},
+    AgentServer,
+};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Output format of the benchmark report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum BenchOutputFormat {
+    /// Aligned human-readable table
+    #[default]
+    Table,
+    /// Single JSON object
+    Json,
+}
+
+#[derive(Args, Clone)]
+#[command(about = "Measure generation throughput and latency")]
+pub struct BenchArgs {
+    /// Model source and loading flags shared with the other model commands
+    #[command(flatten)]
+    pub model_args: ModelArgs,
+
+    /// Prompt sent on every iteration
+    #[arg(
+        long,
+        required_unless_present = "prompt_file",
+        conflicts_with = "prompt_file",
+        help = "Prompt sent on every iteration"
+    )]
+    pub prompt: Option<String>,
+
+    /// File whose contents are sent as the prompt
+    #[arg(long, value_name = "PATH", help = "Read the prompt from a file")]
+    pub prompt_file: Option<PathBuf>,
+
+    /// Number of measured requests (default: 10)
+    #[arg(long, default_value = "10", help = "Number of measured requests")]
+    pub iterations: usize,
+
+    /// Max tokens to generate per request (default: 128)
+    #[arg(
+        long,
+        default_value = "128",
+        help = "Max tokens to generate per request",
+        long_help = "Max tokens to generate per request. 0 only decodes the prompt, measuring prompt processing alone"
+    )]
+    pub max_tokens: u32,
+
+    /// Requests submitted in parallel (default: 1)
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Requests submitted in parallel",
+        long_help = "Number of requests in flight at once. Values above 1 submit in parallel to the request queue, so time to first token includes time spent queued"
+    )]
+    pub concurrency: usize,
+
+    /// Report format
+    #[arg(long, value_enum, default_value_t = BenchOutputFormat::Table)]
+    pub output_format: BenchOutputFormat,
+}
+
+pub fn validate_bench_args(args: &BenchArgs) -> Result<()> {
+    args.model_args.validate()?;
+
+    if args.iterations == 0 {
+        return Err(anyhow::anyhow!("Iterations must be greater than 0"));
+    }
+    if args.concurrency == 0 {
+        return Err(anyhow::anyhow!("Concurrency must be greater than 0"));
+    }
+    if args.prompt.is_none() && args.prompt_file.is_none() {
+        return Err(anyhow::anyhow!(
+            "No prompt given\n💡 Pass --prompt or --prompt-file"
+        ));
+    }
+
+    Ok(())
+}
+
+/// The prompt text from `--prompt` or the contents of `--prompt-file`
+fn load_prompt(args: &BenchArgs) -> Result<String> {
+    let prompt = match (&args.prompt, &args.prompt_file) {
+        (Some(prompt), _) => prompt.clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read prompt file {}: {}", path.display(), e))?,
+        (None, None) => String::new(),
+    };
+
+    if prompt.trim().is_empty() {
+        return Err(anyhow::anyhow!("Prompt cannot be empty"));
+    }
+    Ok(prompt)
+}
+
+/// Send one request in a fresh session and time its stream
+async fn run_iteration(
+    agent: &AgentServer,
+    prompt: &str,
+    max_tokens: u32,
+) -> Result<IterationTiming> {
+    let session = agent.create_session().await?;
+    let result = time_request(agent, &session.id, prompt, max_tokens).await;
+    if let Err(e) = agent.delete_session(&session.id).await {
+        warn!("Failed to delete benchmark session {}: {}", session.id, e);
+    }
+    result
+}
+
+async fn time_request(
+    agent: &AgentServer,
+    session_id: &SessionId,
+    prompt: &str,
+    max_tokens: u32,
+) -> Result<IterationTiming> {
+    let message = Message {
+        role: MessageRole::User,
+        content: prompt.to_string(),
+        tool_call_id: None,
+        tool_name: None,
+        timestamp: std::time::SystemTime::now(),
+        attachments: Vec::new(),
+    };
+    agent.add_message(session_id, message).await?;
+
+    let request = GenerationRequest::new(*session_id)
+        .with_max_tokens(max_tokens)
+        .with_append_to_session(false)
+        .with_default_stopping();
+
+    let start = Instant::now();
+    let mut stream = agent.generate_stream(request).await?;
+    let mut time_to_first_token = None;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if time_to_first_token.is_none() && !chunk.text.is_empty() {
+            time_to_first_token = Some(start.elapsed());
+        }
+        if chunk.is_complete {
+            let total_time = start.elapsed();
+            let response = chunk
+                .response
+                .ok_or_else(|| anyhow::anyhow!("Final chunk carried no generation summary"))?;
+            return Ok(IterationTiming {
+                time_to_first_token: time_to_first_token.unwrap_or(total_time),
+                total_time,
+                prompt_tokens: response.prompt_tokens,
+                prompt_time: response.prompt_time,
+                tokens_generated: response.tokens_generated,
+            });
+        }
+    }
+
+    Err(anyhow::anyhow!("Stream ended before generation completed"))
+}
+
+/// Run the benchmark and print its report
+pub async fn run_bench(args: BenchArgs) -> Result<BenchReport, CliError> {
+    validate_bench_args(&args).map_err(CliError::Validation)?;
+    let prompt = load_prompt(&args).map_err(CliError::Validation)?;
+
+    let mut config = args
+        .model_args
+        .agent_config()
+        .map_err(CliError::Validation)?;
+    // Every in-flight request holds a queue slot and a session
+    config.queue_config.max_queue_size = config.queue_config.max_queue_size.max(args.concurrency);
+    config.session_config.max_sessions = config.session_config.max_sessions.max(args.concurrency);
+
+    info!(
+        "Loading model from {}...",
+        describe_model_source(&config.model.source)
+    );
+    let agent = initialize_interruptible(config)
+        .await
+        .map_err(CliError::from_initialization)?;
+
+    info!(
+        "Running {} iterations with concurrency {}",
+        args.iterations, args.concurrency
+    );
+    let start = Instant::now();
+    let results: Vec<Result<IterationTiming>> = futures::stream::iter(0..args.iterations)
+        .map(|_| run_iteration(&agent, &prompt, args.max_tokens))
+        .buffer_unordered(args.concurrency)
+        .collect()
+        .await;
+    let wall_time: Duration = start.elapsed();
+
+    let mut timings = Vec::with_capacity(results.len());
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(timing) => timings.push(timing),
+            Err(e) => {
+                warn!("Benchmark request failed: {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    if let Err(e) = agent.shutdown().await {
+        warn!("Error during shutdown: {}", e);
+    }
+
+    if timings.is_empty() {
+        return Err(CliError::Runtime(anyhow::anyhow!(
+            "All {} benchmark requests failed",
+            failed
+        )));
+    }
+
+    let report = BenchReport::from_timings(&timings, failed, args.concurrency, wall_time);
+    match args.output_format {
+        BenchOutputFormat::Table => print!("{}", report.to_table()),
+        BenchOutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(|e| CliError::Runtime(e.into()))?
+        ),
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bench_args() -> BenchArgs {
+        BenchArgs {
+            model_args: ModelArgs {
+                config: None,
+                model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+                filename: None,
+                debug: false,
+                allow_http: false,
+                force_load: false,
+                refresh_model_metadata: false,
+                cache_dir: None,
+                local_search_depth: None,
+                chat_template: None,
+            },
+            prompt: Some("Hello".to_string()),
+            prompt_file: None,
+            iterations: 10,
+            max_tokens: 128,
+            concurrency: 1,
+            output_format: BenchOutputFormat::Table,
+        }
+    }
+
+    #[test]
+    fn test_validate_bench_args() {
+        assert!(validate_bench_args(&bench_args()).is_ok());
+        // Prefill only
+        assert!(validate_bench_args(&BenchArgs {
+            max_tokens: 0,
+            ..bench_args()
+        })
+        .is_ok());
+
+        for args in [
+            BenchArgs {
+                iterations: 0,
+                ..bench_args()
+            },
+            BenchArgs {
+                concurrency: 0,
+                ..bench_args()
+            },
+            BenchArgs {
+                prompt: None,
+                ..bench_args()
+            },
+        ] {
+            assert!(validate_bench_args(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_load_prompt_from_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"Summarize this text.\n").unwrap();
+
+        let args = BenchArgs {
+            prompt: None,
+            prompt_file: Some(file.path().to_path_buf()),
+            ..bench_args()
+        };
+        assert_eq!(load_prompt(&args).unwrap(), "Summarize this text.\n");
+
+        let empty = BenchArgs {
+            prompt: Some("  ".to_string()),
+            ..bench_args()
+        };
+        assert!(load_prompt(&empty).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Aggregation of per-request timings collected by `llama-cli bench`.
+
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+/// Timings of a single benchmark request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IterationTiming {
+    /// From submission until the first generated text arrived, including time spent queued
+    pub time_to_first_token: Duration,
+    /// From submission until the final chunk arrived
+    pub total_time: Duration,
+    /// Tokens in the rendered prompt
+    pub prompt_tokens: u32,
+    /// Time the worker spent processing the prompt
+    pub prompt_time: Duration,
+    /// Tokens generated for the response
+    pub tokens_generated: u32,
+}
+
+impl IterationTiming {
+    /// Tokens decoded after the first one, and the time spent on them
+    fn decode(&self) -> (u32, Duration) {
+        (
+            self.tokens_generated.saturating_sub(1),
+            self.total_time.saturating_sub(self.time_to_first_token),
+        )
+    }
+}
+
+/// Summary of a benchmark run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchReport {
+    pub iterations: usize,
+    pub failed: usize,
+    pub concurrency: usize,
+    pub ttft_p50_ms: f64,
+    pub ttft_p95_ms: f64,
+    pub decode_tokens_per_second: f64,
+    pub prompt_tokens_per_second: f64,
+    pub total_tokens_generated: u64,
+    pub wall_time_ms: f64,
+}
+
+/// Nearest-rank percentile (`p` in 0-100) of the given samples
+pub fn percentile(samples: &[Duration], p: f64) -> Option<Duration> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+
+    let rank = ((p.clamp(0.0, 100.0) / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.saturating_sub(1)])
+}
+
+/// Tokens per second, or zero when no time was measured
+fn rate(tokens: u64, time: Duration) -> f64 {
+    let secs = time.as_secs_f64();
+    if secs > 0.0 {
+        tokens as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl BenchReport {
+    /// Aggregate the timings of the successful requests of a run.
+    ///
+    /// Throughputs are totals over the run (all tokens divided by all time spent
+    /// on them), so long requests weigh more than short ones.
+    pub fn from_timings(
+        timings: &[IterationTiming],
+        failed: usize,
+        concurrency: usize,
+        wall_time: Duration,
+    ) -> Self {
+        let ttfts: Vec<Duration> = timings.iter().map(|t| t.time_to_first_token).collect();
+
+        let (decode_tokens, decode_time) = timings
+            .iter()
+            .map(IterationTiming::decode)
+            .fold((0u64, Duration::ZERO), |(tokens, time), (t, d)| {
+                (tokens + t as u64, time + d)
+            });
+        let prompt_tokens: u64 = timings.iter().map(|t| t.prompt_tokens as u64).sum();
+        let prompt_time: Duration = timings.iter().map(|t| t.prompt_time).sum();
+
+        Self {
+            iterations: timings.len() + failed,
+            failed,
+            concurrency,
+            ttft_p50_ms: percentile(&ttfts, 50.0).map_or(0.0, millis),
+            ttft_p95_ms: percentile(&ttfts, 95.0).map_or(0.0, millis),
+            decode_tokens_per_second: rate(decode_tokens, decode_time),
+            prompt_tokens_per_second: rate(prompt_tokens, prompt_time),
+            total_tokens_generated: timings.iter().map(|t| t.tokens_generated as u64).sum(),
+            wall_time_ms: millis(wall_time),
+        }
+    }
+
+    /// Render the report as an aligned two-column table
+    pub fn to_table(&self) -> String {
+        let rows = [
+            (
+                "Iterations",
+                format!("{} ({} failed)", self.iterations, self.failed),
+            ),
+            ("Concurrency", self.concurrency.to_string()),
+            ("TTFT p50", format!("{:.1} ms", self.ttft_p50_ms)),
+            ("TTFT p95", format!("{:.1} ms", self.ttft_p95_ms)),
+            (
+                "Decode",
+                format!("{:.1} tokens/s", self.decode_tokens_per_second),
+            ),
+            (
+                "Prompt",
+                format!("{:.1} tokens/s", self.prompt_tokens_per_second),
+            ),
+            ("Tokens generated", self.total_tokens_generated.to_string()),
+            ("Wall time", format!("{:.2} s", self.wall_time_ms / 1000.0)),
+        ];
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+        let mut table = String::new();
+        for (name, value) in rows {
+            let _ = writeln!(table, "{:<width$}  {}", name, value, width = width);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn timing(ttft: u64, total: u64, tokens: u32) -> IterationTiming {
+        IterationTiming {
+            time_to_first_token: ms(ttft),
+            total_time: ms(total),
+            prompt_tokens: 20,
+            prompt_time: ms(10),
+            tokens_generated: tokens,
+        }
+    }
+
+    #[test]
+    fn test_percentile_nearest_rank() {
+        let samples: Vec<Duration> = (1..=20).map(ms).collect();
+        assert_eq!(percentile(&samples, 50.0), Some(ms(10)));
+        assert_eq!(percentile(&samples, 95.0), Some(ms(19)));
+        assert_eq!(percentile(&samples, 100.0), Some(ms(20)));
+        assert_eq!(percentile(&samples, 0.0), Some(ms(1)));
+    }
+
+    #[test]
+    fn test_percentile_unsorted_and_empty() {
+        assert_eq!(percentile(&[ms(30), ms(10), ms(20)], 50.0), Some(ms(20)));
+        assert_eq!(percentile(&[ms(7)], 95.0), Some(ms(7)));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn test_report_from_timings() {
+        // Decode: 10 + 20 tokens after the first, over 100ms + 200ms
+        let timings = [timing(50, 150, 11), timing(100, 300, 21)];
+        let report = BenchReport::from_timings(&timings, 1, 2, ms(400));
+
+        assert_eq!(report.iterations, 3);
+        assert_eq!(report.failed, 1);
+        assert_eq!(report.concurrency, 2);
+        assert_eq!(report.ttft_p50_ms, 50.0);
+        assert_eq!(report.ttft_p95_ms, 100.0);
+        assert!((report.decode_tokens_per_second - 100.0).abs() < 1e-9);
+        // 40 prompt tokens in 20ms
+        assert!((report.prompt_tokens_per_second - 2000.0).abs() < 1e-9);
+        assert_eq!(report.total_tokens_generated, 32);
+        assert_eq!(report.wall_time_ms, 400.0);
+    }
+
+    #[test]
+    fn test_report_without_successful_requests() {
+        let report = BenchReport::from_timings(&[], 4, 1, ms(5));
+
+        assert_eq!(report.iterations, 4);
+        assert_eq!(report.ttft_p50_ms, 0.0);
+        assert_eq!(report.decode_tokens_per_second, 0.0);
+        assert_eq!(report.prompt_tokens_per_second, 0.0);
+    }
+
+    #[test]
+    fn test_single_token_response_has_no_decode_time() {
+        let report = BenchReport::from_timings(&[timing(40, 40, 1)], 0, 1, ms(40));
+        assert_eq!(report.decode_tokens_per_second, 0.0);
+    }
+
+    #[test]
+    fn test_report_table_and_json() {
+        let report = BenchReport::from_timings(&[timing(50, 150, 11)], 0, 1, ms(150));
+
+        let table = report.to_table();
+        assert!(table.contains("TTFT p50          50.0 ms"));
+        assert!(table.contains("Decode            100.0 tokens/s"));
+        assert!(table.contains("Wall time         0.15 s"));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["ttft_p95_ms"], 50.0);
+        assert_eq!(json["total_tokens_generated"], 11);
+    }
+}
@@ -0,0 +1,764 @@
+use crate::error::CliError;
+use crate::generate::ModelArgs;
+use clap::Args;
+use llama_agent::types::MCPServerConfig;
+use llama_agent::{MCPClient, RetryConfig};
+use llama_loader::detection::{explain_model_choice, find_local_model_file};
+use llama_loader::gguf::read_header;
+use llama_loader::memory::{
+    available_memory_bytes, estimate_model_memory, format_bytes, MemoryEstimate, ModelShape,
+};
+use llama_loader::multipart::ShardName;
+use llama_loader::{CacheManager, ModelConfig, ModelSource};
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Endpoint used when `HF_ENDPOINT` is not set
+const DEFAULT_HF_ENDPOINT: &str = "https://huggingface.co";
+
+/// Free space in the cache directory below which a warning is shown
+const MIN_FREE_CACHE_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+/// How long to wait for a TCP connection to the HuggingFace endpoint
+const HF_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Name of the file written to test that the cache directory is writable
+const WRITE_PROBE_FILENAME: &str = ".llama-cli-doctor-probe";
+
+#[derive(Args, Clone)]
+#[command(about = "Check the environment for problems before loading a model")]
+pub struct DoctorArgs {
+    /// Model source and loading flags shared with the other model commands
+    #[command(flatten)]
+    pub model_args: ModelArgs,
+
+    /// Skip every check that needs the network
+    #[arg(long, help = "Skip every check that needs the network")]
+    pub offline: bool,
+
+    /// Seconds to wait for each MCP server to complete its handshake (default: 10)
+    #[arg(
+        long,
+        default_value = "10",
+        value_name = "SECS",
+        help = "Seconds to wait for each MCP server to complete its handshake"
+    )]
+    pub mcp_timeout: u64,
+}
+
+/// Outcome of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    /// Not a problem yet, but worth a look
+    Warn,
+    Fail,
+    /// Not run, e.g. network checks with `--offline`
+    Skip,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        })
+    }
+}
+
+/// Result of one check, printed as a line of the report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Pass, detail)
+    }
+
+    pub fn warn(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Warn, detail)
+    }
+
+    pub fn fail(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Fail, detail)
+    }
+
+    pub fn skip(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Skip, detail)
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.name, self.detail)
+    }
+}
+
+/// Run every check, print one line per check and fail when any check failed
+pub async fn run_doctor(args: DoctorArgs) -> Result<Vec<CheckResult>, CliError> {
+    let results = run_checks(&args).await?;
+    for result in &results {
+        println!("{}", result);
+    }
+
+    let failed = results
+        .iter()
+        .filter(|r| r.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(CliError::Runtime(anyhow::anyhow!(
+            "{} of {} checks failed",
+            failed,
+            results.len()
+        )));
+    }
+    Ok(results)
+}
+
+/// Run the checks that apply to `args`, in report order
+pub async fn run_checks(args: &DoctorArgs) -> Result<Vec<CheckResult>, CliError> {
+    let config = args
+        .model_args
+        .agent_config()
+        .map_err(CliError::Validation)?;
+    let model_given = args.model_args.model.is_some() || args.model_args.config.is_some();
+
+    let mut results = Vec::new();
+
+    match CacheManager::resolve_cache_dir(config.model.cache_dir.as_deref()) {
+        Ok(cache_dir) => {
+            results.push(check_cache_dir(&cache_dir));
+            results.push(check_free_space(free_space_bytes(&cache_dir)));
+        }
+        Err(e) => results.push(CheckResult::fail("Cache directory", e.to_string())),
+    }
+
+    results.push(if args.offline {
+        CheckResult::skip("HuggingFace reachability", "--offline")
+    } else {
+        let endpoint =
+            std::env::var("HF_ENDPOINT").unwrap_or_else(|_| DEFAULT_HF_ENDPOINT.to_string());
+        check_hf_reachable(&endpoint, HF_CONNECT_TIMEOUT).await
+    });
+
+    let model_file = if model_given {
+        let (result, file) = check_model(&config.model, args.offline).await;
+        results.push(result);
+        file
+    } else {
+        results.push(CheckResult::skip(
+            "Model resolution",
+            "no --model or --config given",
+        ));
+        None
+    };
+
+    let path_var = std::env::var_os("PATH").unwrap_or_default();
+    let timeout = Duration::from_secs(args.mcp_timeout);
+    for server in &config.mcp_servers {
+        let command = check_mcp_command(server, &path_var);
+        let found = command.status == CheckStatus::Pass;
+        results.push(command);
+        if found {
+            results.push(probe_mcp_server(server, timeout).await);
+        }
+    }
+
+    results.push(if model_given {
+        let n_ctx = u64::from(config.model.context_size())
+            * config.queue_config.effective_context_pool_size() as u64;
+        let estimate = model_file.map(|file| {
+            estimate_model_memory(
+                file.size,
+                n_ctx,
+                &file.shape,
+                config.model.memory_overhead_factor,
+            )
+        });
+        check_memory(estimate, available_memory_bytes())
+    } else {
+        CheckResult::skip("Memory", "no model to compare against")
+    });
+
+    Ok(results)
+}
+
+/// Whether the cache directory exists and accepts new files
+pub fn check_cache_dir(dir: &Path) -> CheckResult {
+    const NAME: &str = "Cache directory";
+    if !dir.exists() {
+        return CheckResult::warn(
+            NAME,
+            format!(
+                "{} does not exist yet; it is created on the first download",
+                dir.display()
+            ),
+        );
+    }
+    if !dir.is_dir() {
+        return CheckResult::fail(NAME, format!("{} is not a directory", dir.display()));
+    }
+
+    let probe = dir.join(WRITE_PROBE_FILENAME);
+    match std::fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            CheckResult::pass(NAME, format!("{} is writable", dir.display()))
+        }
+        Err(e) => CheckResult::fail(NAME, format!("{} is not writable: {}", dir.display(), e)),
+    }
+}
+
+/// Whether the disk holding the cache has room for downloads
+pub fn check_free_space(free_bytes: Option<u64>) -> CheckResult {
+    const NAME: &str = "Cache free space";
+    match free_bytes {
+        None => CheckResult::warn(NAME, "could not determine free disk space"),
+        Some(free) if free < MIN_FREE_CACHE_BYTES => CheckResult::warn(
+            NAME,
+            format!(
+                "only {} free; model downloads may not fit",
+                format_bytes(free)
+            ),
+        ),
+        Some(free) => CheckResult::pass(NAME, format!("{} free", format_bytes(free))),
+    }
+}
+
+/// Free space on the disk holding `dir`, or its nearest existing ancestor, from `df`
+fn free_space_bytes(dir: &Path) -> Option<u64> {
+    let existing = dir.ancestors().find(|p| p.exists())?;
+    let output = std::process::Command::new("df")
+        .arg("-Pk")
+        .arg(existing)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_df_available(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Available bytes from POSIX `df -Pk` output: the fourth column of the first data row
+pub fn parse_df_available(output: &str) -> Option<u64> {
+    let row = output.lines().nth(1)?;
+    let kib: u64 = row.split_whitespace().nth(3)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Host and port of an endpoint URL like `https://huggingface.co`
+pub fn endpoint_host(endpoint: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = endpoint.split_once("://")?;
+    let authority = rest.split('/').next().filter(|a| !a.is_empty())?;
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host.to_string(), port.parse().ok()?)),
+        None => {
+            let port = match scheme {
+                "https" => 443,
+                "http" => 80,
+                _ => return None,
+            };
+            Some((authority.to_string(), port))
+        }
+    }
+}
+
+/// Whether a TCP connection to the HuggingFace endpoint succeeds within `timeout`
+pub async fn check_hf_reachable(endpoint: &str, timeout: Duration) -> CheckResult {
+    const NAME: &str = "HuggingFace reachability";
+    let Some((host, port)) = endpoint_host(endpoint) else {
+        return CheckResult::fail(NAME, format!("Invalid HF_ENDPOINT: {}", endpoint));
+    };
+
+    let hint = "\n💡 Pass --offline to skip network checks, or use a local model path";
+    match tokio::time::timeout(
+        timeout,
+        tokio::net::TcpStream::connect((host.as_str(), port)),
+    )
+    .await
+    {
+        Ok(Ok(_)) => CheckResult::pass(NAME, format!("{}:{} is reachable", host, port)),
+        Ok(Err(e)) => CheckResult::fail(
+            NAME,
+            format!("Cannot connect to {}:{}: {}{}", host, port, e, hint),
+        ),
+        Err(_) => CheckResult::fail(
+            NAME,
+            format!(
+                "No connection to {}:{} within {}s{}",
+                host,
+                port,
+                timeout.as_secs(),
+                hint
+            ),
+        ),
+    }
+}
+
+/// A local model file found by [`check_model`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelFile {
+    /// Size of every shard together
+    pub size: u64,
+    /// Shape from the GGUF header; empty when the header cannot be read
+    pub shape: ModelShape,
+}
+
+/// Resolve the model file the way loading would, without downloading or loading it.
+///
+/// Returns the size and shape of the model when they are known, which is only the
+/// case for local files.
+pub async fn check_model(config: &ModelConfig, offline: bool) -> (CheckResult, Option<ModelFile>) {
+    const NAME: &str = "Model resolution";
+    if let Err(e) = config.validate() {
+        return (CheckResult::fail(NAME, e.to_string()), None);
+    }
+
+    match &config.source {
+        ModelSource::Local { folder, filename } => {
+            match find_local_model_file(folder, filename.as_deref(), config.local_search_depth) {
+                Ok(path) => {
+                    let file = model_size_bytes(&path).map(|size| ModelFile {
+                        size,
+                        shape: read_header(&path)
+                            .map(|header| header.shape)
+                            .unwrap_or_default(),
+                    });
+                    let detail = match file {
+                        Some(file) => format!("{} ({})", path.display(), format_bytes(file.size)),
+                        None => path.display().to_string(),
+                    };
+                    (CheckResult::pass(NAME, detail), file)
+                }
+                Err(e) => (CheckResult::fail(NAME, e.to_string()), None),
+            }
+        }
+        ModelSource::HuggingFace {
+            repo,
+            filename: Some(filename),
+        } => (
+            CheckResult::pass(
+                NAME,
+                format!("{} from {}, downloaded on first load", filename, repo),
+            ),
+            None,
+        ),
+        ModelSource::HuggingFace { repo, .. } if offline => (
+            CheckResult::skip(NAME, format!("listing {} needs the network", repo)),
+            None,
+        ),
+        ModelSource::HuggingFace { repo, .. } => match explain_model_choice(config).await {
+            Ok(Some(resolution)) => match resolution.into_chosen("HuggingFace repository") {
+                Ok(chosen) => (
+                    CheckResult::pass(
+                        NAME,
+                        format!("{} from {}, downloaded on first load", chosen, repo),
+                    ),
+                    None,
+                ),
+                Err(e) => (CheckResult::fail(NAME, e.to_string()), None),
+            },
+            Ok(None) => (CheckResult::pass(NAME, repo.clone()), None),
+            Err(e) => (CheckResult::fail(NAME, e.to_string()), None),
+        },
+        ModelSource::Url { url, .. } => (
+            CheckResult::pass(NAME, format!("{}, downloaded on first load", url)),
+            None,
+        ),
+    }
+}
+
+/// Size of a model file, counting every shard of a multi-part model
+pub fn model_size_bytes(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    match ShardName::parse(name) {
+        Some(shard) => shard
+            .all_filenames()
+            .iter()
+            .map(|f| {
+                std::fs::metadata(path.with_file_name(f))
+                    .ok()
+                    .map(|m| m.len())
+            })
+            .sum(),
+        None => std::fs::metadata(path).ok().map(|m| m.len()),
+    }
+}
+
+/// Where `command` would be run from, searching `path_var` like `which` does.
+///
+/// A command containing a path separator is only checked as given.
+pub fn find_on_path(command: &str, path_var: &OsStr) -> Option<PathBuf> {
+    if Path::new(command).components().count() > 1 {
+        let path = PathBuf::from(command);
+        return is_executable(&path).then_some(path);
+    }
+    std::env::split_paths(path_var)
+        .map(|dir| dir.join(command))
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() || path.with_extension("exe").is_file()
+}
+
+/// Whether an MCP server's command can be found
+pub fn check_mcp_command(server: &MCPServerConfig, path_var: &OsStr) -> CheckResult {
+    let name = format!("MCP server '{}' command", server.name);
+    match find_on_path(&server.command, path_var) {
+        Some(path) => CheckResult::pass(name, path.display().to_string()),
+        None => CheckResult::fail(
+            name,
+            format!(
+                "{} not found on PATH\n💡 Install it or give its full path in the config",
+                server.command
+            ),
+        ),
+    }
+}
+
+/// Start an MCP server, complete its initialize handshake and shut it down again
+pub async fn probe_mcp_server(server: &MCPServerConfig, timeout: Duration) -> CheckResult {
+    let name = format!("MCP server '{}' handshake", server.name);
+    let client = MCPClient::with_retry_config(RetryConfig {
+        max_retries: 0,
+        ..RetryConfig::default()
+    });
+
+    let result = match tokio::time::timeout(timeout, client.add_server(server.clone())).await {
+        Ok(Ok(())) => {
+            let infos = client.server_infos().await;
+            let detail = match infos.get(&server.name) {
+                Some(info) => format!(
+                    "{} {} (protocol {})",
+                    info.name, info.version, info.protocol_version
+                ),
+                None => "initialized".to_string(),
+            };
+            CheckResult::pass(name, detail)
+        }
+        Ok(Err(e)) => CheckResult::fail(name, e.to_string()),
+        Err(_) => CheckResult::fail(name, format!("no handshake within {}s", timeout.as_secs())),
+    };
+    let _ = client.shutdown_all().await;
+    result
+}
+
+/// Compare available memory with the memory the model is estimated to need, as
+/// loading does before it starts (see [`estimate_model_memory`])
+pub fn check_memory(estimate: Option<MemoryEstimate>, available_bytes: Option<u64>) -> CheckResult {
+    const NAME: &str = "Memory";
+    let Some(available) = available_bytes else {
+        return CheckResult::warn(NAME, "could not determine available memory");
+    };
+    let Some(estimate) = estimate else {
+        return CheckResult::pass(
+            NAME,
+            format!(
+                "{} available; model size is not known before download",
+                format_bytes(available)
+            ),
+        );
+    };
+
+    let needed = estimate.total();
+    let detail = format!(
+        "about {} needed, {} available",
+        format_bytes(needed),
+        format_bytes(available)
+    );
+    if needed > available {
+        CheckResult::fail(
+            NAME,
+            format!(
+                "{}\n💡 Use a smaller quantization of the model or a smaller context",
+                detail
+            ),
+        )
+    } else if needed > available / 10 * 8 {
+        CheckResult::warn(NAME, format!("{}; little room for anything else", detail))
+    } else {
+        CheckResult::pass(NAME, detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    fn server(name: &str, command: &str) -> MCPServerConfig {
+        MCPServerConfig {
+            name: name.to_string(),
+            command: command.to_string(),
+            args: vec![],
+            timeout_secs: None,
+            strict_protocol_version: false,
+        }
+    }
+
+    #[cfg(unix)]
+    fn write_executable(path: &Path) {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_check_cache_dir() {
+        let dir = tempdir().unwrap();
+        let result = check_cache_dir(dir.path());
+        assert_eq!(result.status, CheckStatus::Pass);
+        // The probe file is cleaned up
+        assert!(!dir.path().join(WRITE_PROBE_FILENAME).exists());
+
+        let missing = dir.path().join("missing");
+        assert_eq!(check_cache_dir(&missing).status, CheckStatus::Warn);
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(check_cache_dir(&file).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        assert_eq!(check_free_space(None).status, CheckStatus::Warn);
+        assert_eq!(check_free_space(Some(GIB)).status, CheckStatus::Warn);
+        let result = check_free_space(Some(100 * GIB));
+        assert_eq!(result.status, CheckStatus::Pass);
+        assert_eq!(result.detail, "100.0 GiB free");
+    }
+
+    #[test]
+    fn test_parse_df_available() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+                      /dev/nvme0n1p2   490617784 123456789 342107084      27% /\n";
+        assert_eq!(parse_df_available(output), Some(342107084 * 1024));
+        assert_eq!(parse_df_available("Filesystem\n"), None);
+        assert_eq!(parse_df_available(""), None);
+    }
+
+    /// Estimate for a model without metadata: the weights plus a fifth
+    fn estimate(weights: u64) -> Option<MemoryEstimate> {
+        Some(estimate_model_memory(
+            weights,
+            8192,
+            &ModelShape::default(),
+            0.0,
+        ))
+    }
+
+    #[test]
+    fn test_check_memory() {
+        assert_eq!(check_memory(estimate(GIB), None).status, CheckStatus::Warn);
+        assert_eq!(check_memory(None, Some(GIB)).status, CheckStatus::Pass);
+        assert_eq!(
+            check_memory(estimate(4 * GIB), Some(16 * GIB)).status,
+            CheckStatus::Pass
+        );
+        // 4.8 GiB needed of 5.5 GiB
+        assert_eq!(
+            check_memory(estimate(4 * GIB), Some(11 * GIB / 2)).status,
+            CheckStatus::Warn
+        );
+        let result = check_memory(estimate(8 * GIB), Some(8 * GIB));
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.detail.contains("9.6 GiB needed, 8.0 GiB available"));
+    }
+
+    #[test]
+    fn test_endpoint_host() {
+        assert_eq!(
+            endpoint_host("https://huggingface.co"),
+            Some(("huggingface.co".to_string(), 443))
+        );
+        assert_eq!(
+            endpoint_host("http://mirror.local:8080/"),
+            Some(("mirror.local".to_string(), 8080))
+        );
+        assert_eq!(endpoint_host("not a url"), None);
+    }
+
+    #[tokio::test]
+    async fn test_check_hf_reachable_invalid_endpoint() {
+        let result = check_hf_reachable("not a url", Duration::from_secs(1)).await;
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert!(result.detail.contains("Invalid HF_ENDPOINT"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_on_path() {
+        let dir = tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        write_executable(&bin.join("mcp-server"));
+        std::fs::write(bin.join("not-executable"), "").unwrap();
+
+        let path_var = std::env::join_paths([dir.path().join("empty"), bin.clone()]).unwrap();
+        assert_eq!(
+            find_on_path("mcp-server", &path_var),
+            Some(bin.join("mcp-server"))
+        );
+        assert_eq!(find_on_path("not-executable", &path_var), None);
+        assert_eq!(find_on_path("missing", &path_var), None);
+
+        // Paths are checked as given, not searched for
+        let full = bin.join("mcp-server");
+        assert_eq!(
+            find_on_path(full.to_str().unwrap(), OsStr::new("")),
+            Some(full)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_mcp_command() {
+        let dir = tempdir().unwrap();
+        write_executable(&dir.path().join("fs-server"));
+        let path_var = dir.path().as_os_str();
+
+        let found = check_mcp_command(&server("filesystem", "fs-server"), path_var);
+        assert_eq!(found.status, CheckStatus::Pass);
+        assert_eq!(found.name, "MCP server 'filesystem' command");
+
+        let missing = check_mcp_command(&server("git", "git-mcp"), path_var);
+        assert_eq!(missing.status, CheckStatus::Fail);
+        assert!(missing.detail.contains("git-mcp not found on PATH"));
+    }
+
+    #[tokio::test]
+    async fn test_check_model_local_folder() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("model-Q4_K_M.gguf"), vec![0u8; 2048]).unwrap();
+
+        let config = ModelConfig {
+            source: ModelSource::local(dir.path(), None),
+            ..ModelConfig::default()
+        };
+        let (result, file) = check_model(&config, true).await;
+        assert_eq!(result.status, CheckStatus::Pass, "{}", result.detail);
+        assert!(result.detail.contains("model-Q4_K_M.gguf"));
+        // Not a valid GGUF file, so its shape is unknown
+        assert_eq!(
+            file,
+            Some(ModelFile {
+                size: 2048,
+                shape: ModelShape::default()
+            })
+        );
+
+        let config = ModelConfig {
+            source: ModelSource::local(dir.path(), Some("other.gguf".to_string())),
+            ..ModelConfig::default()
+        };
+        let (result, file) = check_model(&config, true).await;
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert_eq!(file, None);
+    }
+
+    #[tokio::test]
+    async fn test_check_model_offline_huggingface() {
+        let config = ModelConfig {
+            source: ModelSource::HuggingFace {
+                repo: "org/model".to_string(),
+                filename: None,
+            },
+            ..ModelConfig::default()
+        };
+        let (result, file) = check_model(&config, true).await;
+        assert_eq!(result.status, CheckStatus::Skip);
+        assert_eq!(file, None);
+
+        let config = ModelConfig {
+            source: ModelSource::HuggingFace {
+                repo: "org/model".to_string(),
+                filename: Some("model.gguf".to_string()),
+            },
+            ..ModelConfig::default()
+        };
+        let (result, _) = check_model(&config, true).await;
+        assert_eq!(result.status, CheckStatus::Pass);
+    }
+
+    #[test]
+    fn test_model_size_counts_every_shard() {
+        let dir = tempdir().unwrap();
+        for (i, len) in [(1, 100), (2, 50)] {
+            let name = format!("model-{:05}-of-00002.gguf", i);
+            std::fs::write(dir.path().join(name), vec![0u8; len]).unwrap();
+        }
+        assert_eq!(
+            model_size_bytes(&dir.path().join("model-00001-of-00002.gguf")),
+            Some(150)
+        );
+        assert_eq!(model_size_bytes(&dir.path().join("missing.gguf")), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_checks_reports_missing_mcp_command() {
+        let dir = tempdir().unwrap();
+        let config = dir.path().join("agent.toml");
+        std::fs::write(
+            &config,
+            format!(
+                "[model.source.Local]\nfolder = '{}'\n\n\
+                 [[mcp_servers]]\nname = \"missing\"\ncommand = \"llama-cli-doctor-no-such-command\"\n",
+                dir.path().join("model.gguf").display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("model.gguf"), vec![0u8; 16]).unwrap();
+
+        let args = DoctorArgs {
+            model_args: ModelArgs {
+                config: Some(config),
+                cache_dir: Some(dir.path().to_path_buf()),
+                ..ModelArgs::default()
+            },
+            offline: true,
+            mcp_timeout: 1,
+        };
+        let results = run_checks(&args).await.unwrap();
+        let status = |name: &str| {
+            results
+                .iter()
+                .find(|r| r.name == name)
+                .unwrap_or_else(|| panic!("no {} check in {:?}", name, results))
+                .status
+        };
+        assert_eq!(status("Cache directory"), CheckStatus::Pass);
+        assert_eq!(status("HuggingFace reachability"), CheckStatus::Skip);
+        assert_eq!(status("Model resolution"), CheckStatus::Pass);
+        assert_eq!(status("MCP server 'missing' command"), CheckStatus::Fail);
+        // The handshake is only attempted when the command exists
+        assert!(results.iter().all(|r| !r.name.ends_with("handshake")));
+    }
+}
@@ -1,13 +1,29 @@
+use crate::parquet_writer::{OutputColumn, ParquetCodec};
 use clap::Args;
+use llama_embedding::{HashAlgo, InvalidVectorPolicy, Pooling};
+use llama_loader::http::is_model_url;
 use llama_loader::ModelSource;
 use std::path::PathBuf;
 
 #[derive(Args, Clone, Debug)]
 #[command(about = "Generate embeddings for input texts")]
 pub struct EmbedArgs {
+    /// Agent configuration file providing the model source
+    #[arg(
+        long,
+        help = "Agent configuration file (TOML, YAML or JSON) providing the model source",
+        long_help = "Agent configuration file (TOML, YAML or JSON). The model section is used when --model is not given; explicit flags take precedence"
+    )]
+    pub config: Option<PathBuf>,
+
     /// Model source (HuggingFace repo or local path)
-    #[arg(long, short, help = "Model source (HuggingFace repo or local path)")]
-    pub model: String,
+    #[arg(
+        long,
+        short,
+        required_unless_present = "config",
+        help = "Model source (HuggingFace repo, local path or https:// URL)"
+    )]
+    pub model: Option<String>,
 
     /// Optional model filename
     #[arg(long, help = "Optional specific model filename")]
@@ -36,12 +52,112 @@ pub struct EmbedArgs {
     /// Enable debug output
     #[arg(long, help = "Enable debug output")]
     pub debug: bool,
+
+    #[arg(
+        long,
+        default_value = "md5",
+        help = "Text hash algorithm: md5, xxh3, sha256 or none",
+        long_help = "Algorithm for the per-text hash column: md5 (column text_hash), xxh3 (text_xxh3), sha256 (text_sha256), or none to skip hashing and omit the column"
+    )]
+    pub hash: HashAlgo,
+
+    #[arg(
+        long,
+        default_value = "fail",
+        help = "What to do with embedding vectors containing NaN/Inf or of the wrong dimension: fail, skip or zero-fill",
+        long_help = "What to do with embedding vectors containing NaN/Inf or of the wrong dimension: fail stops the run, skip drops the record and counts it, zero-fill writes a zero vector and marks the row in a zero_filled column"
+    )]
+    pub invalid_vectors: InvalidVectorPolicy,
+
+    #[arg(
+        long,
+        conflicts_with = "columns",
+        help = "Leave the original text out of the output (requires a hash column)"
+    )]
+    pub omit_text: bool,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "COLUMNS",
+        help = "Comma-separated columns to write: text, hash, token_count, processing_time, embedding",
+        long_help = "Comma-separated columns to write: text, hash, token_count, processing_time, embedding. Columns keep that order in the output. Without text, hash is required so rows can be joined back to their source"
+    )]
+    pub columns: Option<Vec<OutputColumn>>,
+
+    #[arg(long, help = "Skip texts identical to one already embedded")]
+    pub dedupe: bool,
+
+    #[arg(
+        long,
+        value_name = "PARQUET",
+        help = "Also skip texts already present in this earlier output file (implies --dedupe)"
+    )]
+    pub dedupe_against: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "zstd",
+        help = "Parquet compression: uncompressed, snappy, gzip, lz4, zstd or brotli"
+    )]
+    pub compression: ParquetCodec,
+
+    #[arg(long, value_name = "ROWS", help = "Rows per Parquet row group")]
+    pub row_group_size: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "ROWS",
+        help = "Split output into shards of this many rows (out-00001.parquet, ...)"
+    )]
+    pub shard_size_rows: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Continue an interrupted run from the manifest saved next to --output"
+    )]
+    pub resume: bool,
+
+    /// Allow plain http:// model URLs
+    #[arg(long, help = "Allow --model to be a plain http:// link")]
+    pub allow_http: bool,
+
+    /// Load the model even when it is estimated not to fit in available memory
+    #[arg(
+        long,
+        help = "Load the model even when it is estimated not to fit in memory"
+    )]
+    pub force_load: bool,
+
+    /// List the HuggingFace repository again instead of using the cached listing
+    #[arg(
+        long,
+        help = "List the HuggingFace repository again instead of using the cached listing"
+    )]
+    pub refresh_model_metadata: bool,
+
+    /// Directory for downloaded models
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Directory for downloaded models",
+        long_help = "Directory for downloaded models. Overrides model.cache_dir and the LLAMA_CACHE_DIR environment variable; defaults to llama-loader/models in the platform cache directory"
+    )]
+    pub cache_dir: Option<PathBuf>,
 }
 
 /// Comprehensive validation function for EmbedArgs
 pub fn validate_embed_args(args: &EmbedArgs) -> anyhow::Result<()> {
-    // 1. Validate model using ModelSource validation
-    validate_model_source(&args.model, &args.filename)?;
+    // 1. Validate model using ModelSource validation; without --model it comes from --config
+    match &args.model {
+        Some(model) => validate_model_source(model, &args.filename, args.allow_http)?,
+        None if args.config.is_none() => {
+            return Err(anyhow::anyhow!(
+                "Model path cannot be empty\n💡 Provide --model or a --config file with a model section"
+            ));
+        }
+        None => {}
+    }
 
     // 2. Validate input file
     validate_input_file(&args.input)?;
@@ -52,36 +168,71 @@ pub fn validate_embed_args(args: &EmbedArgs) -> anyhow::Result<()> {
     // 4. Validate parameters
     validate_parameters(args.batch_size, args.max_length)?;
 
-    Ok(())
-}
+    // 5. Validate output layout
+    validate_output_layout(args.row_group_size, args.shard_size_rows)?;
 
-/// Validate model source using ModelSource validation from llama-loader
-fn validate_model_source(model: &str, filename: &Option<String>) -> anyhow::Result<()> {
-    if model.is_empty() {
+    // 6. A resumed run needs the manifest of the interrupted one
+    if args.resume && !manifest_path(&args.output).is_file() {
         return Err(anyhow::anyhow!(
-            "Model path cannot be empty\n💡 Provide either a HuggingFace repo (e.g., 'microsoft/DialoGPT-medium') or local path"
+            "No run manifest found at {}\n💡 Only runs of this version can be resumed; drop --resume to start over",
+            manifest_path(&args.output).display()
         ));
     }
 
-    // Create ModelSource based on CLI logic from to_embedding_config
-    let model_source = if model.contains('/') && !std::path::Path::new(model).exists() {
+    // 7. Validate the earlier output used for dedupe
+    if let Some(path) = &args.dedupe_against {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!(
+                "Dedupe file does not exist: {}\n💡 Point --dedupe-against at a Parquet file written by a previous run",
+                path.display()
+            ));
+        }
+    }
+
+    // 8. Validate the column selection
+    validate_columns(&args.output_columns(), args.columns.is_some(), args.hash)?;
+
+    Ok(())
+}
+
+/// Build the model source for a `--model` argument: URL, HuggingFace repo or local path
+fn model_source_from_arg(model: &str, filename: Option<String>) -> ModelSource {
+    if is_model_url(model) {
+        ModelSource::Url {
+            url: model.to_string(),
+            filename,
+            sha256: None,
+        }
+    } else if model.contains('/') && !std::path::Path::new(model).exists() {
         // Looks like HuggingFace repo
         ModelSource::HuggingFace {
             repo: model.to_string(),
-            filename: filename.clone(),
+            filename,
         }
     } else {
-        // Local path
-        ModelSource::Local {
-            folder: std::path::PathBuf::from(model),
-            filename: filename.clone(),
-        }
-    };
+        // Local folder or .gguf file
+        ModelSource::local(model, filename)
+    }
+}
+
+/// Validate model source using ModelSource validation from llama-loader
+fn validate_model_source(
+    model: &str,
+    filename: &Option<String>,
+    allow_http: bool,
+) -> anyhow::Result<()> {
+    if model.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Model path cannot be empty\n💡 Provide either a HuggingFace repo (e.g., 'microsoft/DialoGPT-medium') or local path"
+        ));
+    }
+
+    let model_source = model_source_from_arg(model, filename.clone());
 
     // Use ModelSource validation with better error handling
-    model_source.validate().map_err(|e| {
+    model_source.validate_with_options(0, allow_http).map_err(|e| {
         anyhow::anyhow!(
-            "Model validation failed: {}\n💡 For local models: ensure path exists and contains .gguf files\n💡 For HuggingFace: use format 'org/repo' and verify repo exists",
+            "Model validation failed: {}\n💡 For local models: ensure path exists and contains .gguf files\n💡 For HuggingFace: use format 'org/repo' and verify repo exists\n💡 For URLs: use https://, or pass --allow-http for plain http",
             e
         )
     })
@@ -151,23 +302,20 @@ fn validate_output_path(output: &std::path::Path) -> anyhow::Result<()> {
     // Ensure output directory exists, creating it if necessary
     if let Some(parent) = output.parent() {
         if !parent.exists() {
-            std::fs::create_dir_all(parent).map_err(|e| {
-                anyhow::anyhow!(
-                    "Failed to create output directory '{}': {}\n💡 Check parent directory permissions and disk space",
-                    parent.display(),
-                    e
-                )
+            std::fs::create_dir_all(parent).map_err(|e| EmbedError::OutputWrite {
+                path: parent.to_path_buf(),
+                reason: format!("cannot create the directory: {}", e),
             })?;
         }
 
         // Test write permissions by attempting to create a temporary file
         let temp_file = parent.join(".embed_test_write");
         if let Err(e) = std::fs::File::create(&temp_file) {
-            return Err(anyhow::anyhow!(
-                "Cannot write to output directory '{}': {}\n💡 Check directory permissions and disk space",
-                parent.display(),
-                e
-            ));
+            return Err(EmbedError::OutputWrite {
+                path: parent.to_path_buf(),
+                reason: format!("cannot write to the directory: {}", e),
+            }
+            .into());
         }
         // Clean up test file
         let _ = std::fs::remove_file(temp_file);
@@ -176,6 +324,49 @@ fn validate_output_path(output: &std::path::Path) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Validate row group and shard sizes
+fn validate_output_layout(
+    row_group_size: Option<usize>,
+    shard_size_rows: Option<usize>,
+) -> anyhow::Result<()> {
+    if row_group_size == Some(0) {
+        return Err(anyhow::anyhow!(
+            "Row group size must be greater than 0\n💡 Omit --row-group-size to use the default"
+        ));
+    }
+    if shard_size_rows == Some(0) {
+        return Err(anyhow::anyhow!(
+            "Shard size must be greater than 0\n💡 Omit --shard-size-rows to write a single file"
+        ));
+    }
+    Ok(())
+}
+
+/// Validate the output columns against the hash algorithm; `explicit` when set by `--columns`
+fn validate_columns(
+    columns: &[OutputColumn],
+    explicit: bool,
+    hash: HashAlgo,
+) -> anyhow::Result<()> {
+    if columns.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No output columns selected\n💡 Pass --columns with at least one of: text, hash, token_count, processing_time, embedding"
+        ));
+    }
+    let has_hash = columns.contains(&OutputColumn::Hash) && hash != HashAlgo::None;
+    if explicit && columns.contains(&OutputColumn::Hash) && hash == HashAlgo::None {
+        return Err(anyhow::anyhow!(
+            "The hash column was requested with --hash none\n💡 Choose a hash algorithm such as md5 or xxh3, or drop hash from --columns"
+        ));
+    }
+    if !columns.contains(&OutputColumn::Text) && !has_hash {
+        return Err(anyhow::anyhow!(
+            "Output without the text column must include the hash column\n💡 Keep hash in the output, with a --hash algorithm other than none, so rows can be joined back to their source texts"
+        ));
+    }
+    Ok(())
+}
+
 /// Validate embedding parameters
 fn validate_parameters(batch_size: usize, max_length: Option<usize>) -> anyhow::Result<()> {
     // Validate batch size
@@ -216,121 +407,367 @@ fn validate_parameters(batch_size: usize, max_length: Option<usize>) -> anyhow::
     Ok(())
 }
 
-use crate::parquet_writer::ParquetWriter;
+use crate::error::CliError;
+use crate::manifest::{manifest_path, RunManifest, RunSettings};
+use crate::parquet_writer::ParquetError;
+use crate::parquet_writer::{column_names, read_dedupe_keys, ParquetWriter};
 use indicatif::{ProgressBar, ProgressStyle};
-use llama_embedding::{BatchProcessor, EmbeddingConfig, EmbeddingModel};
+use llama_agent::AgentConfig;
+use llama_embedding::{
+    BatchProcessor, EmbeddingConfig, EmbeddingError, EmbeddingModel, EmbeddingResult,
+};
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Instant;
+use thiserror::Error;
 use tracing::info;
 
+/// Embed failures with an exit code of their own, so scripts can tell bad input, unwritable
+/// output and unsuitable models apart
+#[derive(Debug, Error)]
+pub enum EmbedError {
+    /// A line of the input file could not be read
+    #[error("Cannot read input file {} at line {line}: {reason}\n💡 Input must be UTF-8 text with one text per line", .path.display())]
+    InputRead {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+
+    /// The output, its directory or its run manifest could not be written
+    #[error("Cannot write output {}: {reason}\n💡 Check directory permissions and disk space", .path.display())]
+    OutputWrite { path: PathBuf, reason: String },
+
+    /// The model loads but does not produce embeddings
+    #[error("Model does not produce embeddings: {0}\n💡 Use an embedding model such as Qwen3-Embedding instead of a chat model")]
+    ModelNotEmbedding(String),
+
+    /// The model produced vectors of another dimension than the output was created with
+    #[error("Embedding dimension mismatch: expected {expected}, got {actual}\n💡 Check the model file was not replaced during the run")]
+    DimensionMismatch { expected: usize, actual: usize },
+}
+
+impl TryFrom<EmbeddingError> for EmbedError {
+    type Error = EmbeddingError;
+
+    /// The embed failure `err` amounts to, or `err` back when it has no class of its own
+    fn try_from(err: EmbeddingError) -> Result<Self, EmbeddingError> {
+        match err {
+            EmbeddingError::InputRead { path, line, source } => Ok(EmbedError::InputRead {
+                path,
+                line,
+                reason: source.to_string(),
+            }),
+            EmbeddingError::NotEmbeddingModel(reason) => Ok(EmbedError::ModelNotEmbedding(reason)),
+            EmbeddingError::DimensionMismatch { expected, actual } => {
+                Ok(EmbedError::DimensionMismatch { expected, actual })
+            }
+            other => Err(other),
+        }
+    }
+}
+
+impl EmbedError {
+    /// Failure to write a Parquet batch to `path`
+    fn from_parquet(path: &Path, err: ParquetError) -> Self {
+        match err {
+            ParquetError::SchemaMismatch { expected, actual } => {
+                EmbedError::DimensionMismatch { expected, actual }
+            }
+            other => EmbedError::OutputWrite {
+                path: path.to_path_buf(),
+                reason: other.to_string(),
+            },
+        }
+    }
+
+    fn output_write(path: &Path, err: impl std::fmt::Display) -> Self {
+        EmbedError::OutputWrite {
+            path: path.to_path_buf(),
+            reason: err.to_string(),
+        }
+    }
+}
+
+/// Classify a failed argument check: output directory problems keep their own class
+fn validation_error(err: anyhow::Error) -> CliError {
+    match err.downcast::<EmbedError>() {
+        Ok(err) => err.into(),
+        Err(err) => CliError::Validation(err),
+    }
+}
+
 impl EmbedArgs {
+    /// Columns to write, from `--columns` or every column except those `--omit-text` drops
+    fn output_columns(&self) -> Vec<OutputColumn> {
+        match &self.columns {
+            Some(columns) => columns.clone(),
+            None => OutputColumn::ALL
+                .into_iter()
+                .filter(|column| !(self.omit_text && *column == OutputColumn::Text))
+                .collect(),
+        }
+    }
+
     /// Convert CLI args to embedding configuration
-    fn to_embedding_config(&self) -> EmbeddingConfig {
-        let model_source =
-            if self.model.contains('/') && !std::path::Path::new(&self.model).exists() {
-                // Looks like HuggingFace repo
-                ModelSource::HuggingFace {
-                    repo: self.model.clone(),
-                    filename: self.filename.clone(),
-                }
-            } else {
-                // Local path
-                ModelSource::Local {
-                    folder: std::path::PathBuf::from(&self.model),
-                    filename: self.filename.clone(),
+    ///
+    /// An explicit `--model` wins over the model section of `--config`.
+    fn to_embedding_config(&self) -> anyhow::Result<EmbeddingConfig> {
+        let file_model = match &self.config {
+            Some(path) => Some(
+                AgentConfig::from_file(path)
+                    .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e))?
+                    .model,
+            ),
+            None => None,
+        };
+
+        let model_source = match (&self.model, &file_model) {
+            (Some(model), _) => model_source_from_arg(model, self.filename.clone()),
+            (None, Some(file_model)) => {
+                let mut source = file_model.source.clone();
+                if let Some(filename) = &self.filename {
+                    match &mut source {
+                        ModelSource::HuggingFace { filename: f, .. }
+                        | ModelSource::Local { filename: f, .. }
+                        | ModelSource::Url { filename: f, .. } => *f = Some(filename.clone()),
+                    }
                 }
-            };
+                source
+            }
+            (None, None) => {
+                return Err(anyhow::anyhow!(
+                    "Model path cannot be empty\n💡 Provide --model or a --config file with a model section"
+                ))
+            }
+        };
 
-        EmbeddingConfig {
+        Ok(EmbeddingConfig {
             model_source,
             normalize_embeddings: self.normalize,
             max_sequence_length: self.max_length,
-            debug: self.debug,
+            debug: self.debug || file_model.as_ref().is_some_and(|m| m.debug),
+            hash: self.hash,
+            allow_http: self.allow_http || file_model.as_ref().is_some_and(|m| m.allow_http),
+            refresh_metadata: self.refresh_model_metadata
+                || file_model.as_ref().is_some_and(|m| m.refresh_metadata),
+            cache_dir: self
+                .cache_dir
+                .clone()
+                .or_else(|| file_model.as_ref().and_then(|m| m.cache_dir.clone())),
+            force_load: self.force_load || file_model.as_ref().is_some_and(|m| m.force_load),
+            metadata_ttl_secs: file_model.map(|m| m.metadata_ttl_secs),
+            pooling: Pooling::default(),
+            invalid_vectors: self.invalid_vectors,
+            cache: None,
+        })
+    }
+
+    /// Settings recorded in the run manifest, which a resumed run must repeat
+    fn run_settings(&self, config: &EmbeddingConfig) -> RunSettings {
+        RunSettings {
+            input: std::fs::canonicalize(&self.input).unwrap_or_else(|_| self.input.clone()),
+            model_source: config.model_source.clone(),
+            normalize: config.normalize_embeddings,
+            max_length: config.max_sequence_length,
+            hash: config.hash,
+            dedupe: self.dedupe || self.dedupe_against.is_some(),
+            compression: self.compression,
+            shard_size_rows: self.shard_size_rows,
+            columns: column_names(&self.output_columns(), config.hash),
         }
     }
 }
 
+/// Write a batch and checkpoint the manifest, so a resumed run continues after `lines` more texts
+fn record_batch(
+    writer: &mut ParquetWriter,
+    output: &Path,
+    manifest: &mut RunManifest,
+    manifest_file: &Path,
+    results: Vec<EmbeddingResult>,
+    lines: usize,
+) -> Result<(), EmbedError> {
+    writer
+        .write_batch(results)
+        .map_err(|e| EmbedError::from_parquet(output, e))?;
+    manifest.lines_processed += lines;
+    manifest.records_written = writer.records_written();
+    manifest
+        .save(manifest_file)
+        .map_err(|e| EmbedError::output_write(manifest_file, e))?;
+    Ok(())
+}
+
 /// Main embed command implementation
-pub async fn run_embed_command(args: EmbedArgs) -> anyhow::Result<()> {
+pub async fn run_embed_command(args: EmbedArgs) -> Result<(), CliError> {
     // 1. Validate input arguments
-    validate_embed_args(&args)?;
+    validate_embed_args(&args).map_err(validation_error)?;
+
+    // 2. Create embedding config from CLI args and optional config file
+    let config = args.to_embedding_config().map_err(CliError::Validation)?;
+
+    // 3. Load the manifest of the interrupted run when resuming; settings must match
+    let manifest_file = manifest_path(&args.output);
+    let settings = args.run_settings(&config);
+    let mut manifest = if args.resume {
+        let manifest = RunManifest::load(&manifest_file).map_err(|e| {
+            CliError::Validation(anyhow::anyhow!(
+                "Failed to read run manifest {}: {}",
+                manifest_file.display(),
+                e
+            ))
+        })?;
+        manifest.check_resumable(&settings).map_err(|e| {
+            CliError::Validation(anyhow::anyhow!(
+                "Cannot resume: {}\n💡 Rerun with the original settings, or drop --resume to start over",
+                e
+            ))
+        })?;
+        manifest
+    } else {
+        RunManifest::new(settings)
+    };
+    let model_name = match &config.model_source {
+        ModelSource::HuggingFace { repo, .. } => repo.clone(),
+        ModelSource::Local { folder, .. } => folder.display().to_string(),
+        ModelSource::Url { url, .. } => url.clone(),
+    };
 
     info!("Starting embed command");
-    info!("Model: {}", args.model);
+    info!("Model: {}", model_name);
     info!("Input: {:?}", args.input);
     info!("Output: {:?}", args.output);
     info!("Batch size: {}", args.batch_size);
 
-    println!("Loading model: {}", args.model);
-    let load_start = Instant::now();
+    // 4. Count the texts for progress tracking, which also finds unreadable input before
+    //    the model is loaded
+    let total_lines = count_non_empty_lines(&args.input).await?;
 
-    // 2. Create embedding config from CLI args
-    let config = args.to_embedding_config();
+    println!("Loading model: {}", model_name);
+    let load_start = Instant::now();
 
-    // 3. Initialize embedding model
-    let mut embedding_model = EmbeddingModel::new(config)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to initialize embedding model: {}", e))?;
+    // 5. Initialize embedding model
+    let mut embedding_model = EmbeddingModel::new(config).await.map_err(|e| {
+        CliError::ModelLoad(anyhow::anyhow!(
+            "Failed to initialize embedding model: {}",
+            e
+        ))
+    })?;
 
-    // 4. Load the model
-    embedding_model
-        .load_model()
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to load model: {}", e))?;
+    // 6. Load the model
+    embedding_model.load_model().await.map_err(|e| {
+        if let EmbeddingError::ModelLoader(model_error) = &e {
+            if let Some(report) = model_error.retry_report() {
+                eprintln!("{}", report);
+            }
+        }
+        CliError::ModelLoad(anyhow::anyhow!("Failed to load model: {}", e))
+    })?;
 
     let load_time = load_start.elapsed();
 
-    // 5. Get embedding dimensions for Parquet schema
-    let embedding_dim = embedding_model
-        .get_embedding_dimension()
-        .ok_or_else(|| anyhow::anyhow!("Could not determine embedding dimensions"))?;
+    // 7. Warm up to verify the model embeds and learn the dimension for the Parquet schema,
+    //    before any output is written
+    let embedding_dim =
+        embedding_model
+            .warm_up()
+            .await
+            .map_err(|e| match EmbedError::try_from(e) {
+                Ok(e) => e.into(),
+                Err(e) => CliError::ModelLoad(anyhow::anyhow!("Model warm-up failed: {}", e)),
+            })?;
 
     println!(
         "Model loaded successfully in {:.1}s ({} dimensions)",
         load_time.as_secs_f64(),
         embedding_dim
     );
+    if let Some(metadata) = embedding_model.get_metadata() {
+        println!("Model file: {}", metadata.summary());
+    }
 
-    // 6. Set up batch processor and Parquet writer
+    // 8. Set up batch processor and Parquet writer
     let model = Arc::new(embedding_model);
     let mut processor = BatchProcessor::new(model.clone(), args.batch_size);
+    if args.dedupe || args.dedupe_against.is_some() {
+        processor.set_dedupe(true);
+    }
+    if let (Some(path), Some(dedup)) = (&args.dedupe_against, processor.deduplicator_mut()) {
+        let loaded = read_dedupe_keys(path, dedup).map_err(|e| {
+            anyhow::anyhow!("Failed to read --dedupe-against {}: {}", path.display(), e)
+        })?;
+        println!("Loaded {} existing texts from {}", loaded, path.display());
+    }
     let mut parquet_writer = ParquetWriter::new(&args.output, embedding_dim, args.batch_size)
-        .map_err(|e| anyhow::anyhow!("Failed to create Parquet writer: {}", e))?;
-
-    // 7. Count total lines for progress tracking
-    let total_lines = count_non_empty_lines(&args.input).await?;
+        .map_err(|e| EmbedError::output_write(&args.output, e))?
+        .with_hash(args.hash)
+        .with_columns(&args.output_columns())
+        .with_compression(args.compression)
+        .with_row_group_size(args.row_group_size.unwrap_or(0))
+        .with_shard_size(args.shard_size_rows.unwrap_or(0))
+        .with_zero_filled_column(args.invalid_vectors == InvalidVectorPolicy::ZeroFill);
+    if args.resume {
+        parquet_writer = parquet_writer
+            .resume(manifest.records_written)
+            .map_err(|e| anyhow::anyhow!("Failed to resume Parquet output: {}", e))?;
+        if let Some(dedup) = processor.deduplicator_mut() {
+            for file in parquet_writer.output_files() {
+                read_dedupe_keys(&file, dedup).map_err(|e| {
+                    anyhow::anyhow!("Failed to read existing output {}: {}", file.display(), e)
+                })?;
+            }
+        }
+        println!(
+            "Resuming after {} texts ({} rows already written)",
+            manifest.lines_processed, manifest.records_written
+        );
+    }
+    manifest
+        .save(&manifest_file)
+        .map_err(|e| EmbedError::output_write(&manifest_file, e))?;
     println!(
         "Processing {} texts with batch size {}...",
         total_lines, args.batch_size
     );
 
-    // 8. Create progress bar
+    // 9. Create progress bar
     let progress_bar = ProgressBar::new(total_lines as u64);
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) {msg}")?
             .progress_chars("██▌ "),
     );
+    progress_bar.set_position(manifest.lines_processed as u64);
 
     let processing_start = Instant::now();
     let mut total_processed = 0;
 
-    // 9. Process file and write to Parquet with progress tracking
+    // 10. Process file and write to Parquet with progress tracking
+    let skip = manifest.lines_processed;
+    // A write failure stops processing and is reported with its own class afterwards
+    let mut write_error = None;
     processor
-        .process_file_streaming(&args.input, |batch| {
+        .process_file_streaming_from(&args.input, skip, |batch, lines| {
             let batch_size = batch.len();
             total_processed += batch_size;
 
-            // Write batch to Parquet
-            parquet_writer.write_batch(batch).map_err(|e| {
-                llama_embedding::EmbeddingError::batch_processing(format!(
-                    "Parquet write error: {}",
-                    e
-                ))
-            })?;
+            // Write batch to Parquet and checkpoint the manifest
+            if let Err(e) = record_batch(
+                &mut parquet_writer,
+                &args.output,
+                &mut manifest,
+                &manifest_file,
+                batch,
+                lines,
+            ) {
+                let message = e.to_string();
+                write_error = Some(e);
+                return Err(EmbeddingError::batch_processing(message));
+            }
 
             // Update progress
-            progress_bar.set_position(total_processed as u64);
+            progress_bar.set_position(manifest.lines_processed as u64);
             if total_processed % (args.batch_size * 5) == 0 {
                 // Update message every 5 batches
                 let elapsed = processing_start.elapsed();
@@ -345,13 +782,26 @@ pub async fn run_embed_command(args: EmbedArgs) -> anyhow::Result<()> {
             Ok(())
         })
         .await
-        .map_err(|e| anyhow::anyhow!("Failed to process file: {}", e))?;
+        .map_err(|e| match write_error.take() {
+            Some(write_error) => write_error.into(),
+            None => match EmbedError::try_from(e) {
+                Ok(e) => e.into(),
+                Err(e) => CliError::Runtime(anyhow::anyhow!("Failed to process file: {}", e)),
+            },
+        })?;
 
-    // 10. Finalize progress bar and close writer
+    // 11. Finalize progress bar and close writer
+    let duplicates_skipped = processor.stats().duplicates_skipped;
+    let invalid_vectors = processor.stats().invalid_vectors;
     progress_bar.finish_with_message("Processing complete");
+    parquet_writer
+        .flush()
+        .map_err(|e| EmbedError::from_parquet(&args.output, e))?;
+    let output_files = parquet_writer.output_files();
+    let written_columns = parquet_writer.column_names();
     let records_written = parquet_writer
         .close()
-        .map_err(|e| anyhow::anyhow!("Failed to close Parquet writer: {}", e))?;
+        .map_err(|e| EmbedError::from_parquet(&args.output, e))?;
 
     let total_time = processing_start.elapsed();
     let throughput = if total_time.as_secs() > 0 {
@@ -360,7 +810,7 @@ pub async fn run_embed_command(args: EmbedArgs) -> anyhow::Result<()> {
         0.0
     };
 
-    // 11. Show summary
+    // 12. Show summary
     println!();
     println!("Processing complete!");
     println!("Total embeddings: {}", total_processed);
@@ -370,15 +820,42 @@ pub async fn run_embed_command(args: EmbedArgs) -> anyhow::Result<()> {
         total_time.as_millis() as f64 / total_processed as f64
     );
     println!("Throughput: {:.1} texts/s", throughput);
-    println!(
-        "Output written to: {} ({} records)",
-        args.output.display(),
-        records_written
-    );
+    if processor.config().dedupe {
+        println!("Duplicates skipped: {}", duplicates_skipped);
+    }
+    if invalid_vectors > 0 {
+        println!(
+            "Invalid vectors: {} ({})",
+            invalid_vectors, args.invalid_vectors
+        );
+    }
+    if args.shard_size_rows.is_some() {
+        println!(
+            "Output written to {} shards ({} records):",
+            output_files.len(),
+            records_written
+        );
+        for file in &output_files {
+            println!("  {}", file.display());
+        }
+    } else {
+        println!(
+            "Output written to: {} ({} records)",
+            args.output.display(),
+            records_written
+        );
+    }
+
+    println!("Columns: {}", written_columns.join(", "));
 
     // Calculate and show file size
-    if let Ok(metadata) = std::fs::metadata(&args.output) {
-        let size_mb = metadata.len() as f64 / (1024.0 * 1024.0);
+    let total_bytes: u64 = output_files
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    if !output_files.is_empty() {
+        let size_mb = total_bytes as f64 / (1024.0 * 1024.0);
         println!("File size: {:.1} MB", size_mb);
     }
 
@@ -386,18 +863,30 @@ pub async fn run_embed_command(args: EmbedArgs) -> anyhow::Result<()> {
 }
 
 /// Count non-empty lines in a file for progress tracking
-async fn count_non_empty_lines(input_path: &std::path::Path) -> anyhow::Result<usize> {
+async fn count_non_empty_lines(input_path: &std::path::Path) -> Result<usize, EmbedError> {
     use tokio::fs::File;
     use tokio::io::{AsyncBufReadExt, BufReader};
 
-    let file = File::open(input_path).await?;
+    let input_error = |line, e: std::io::Error| EmbedError::InputRead {
+        path: input_path.to_path_buf(),
+        line,
+        reason: e.to_string(),
+    };
+    let file = File::open(input_path)
+        .await
+        .map_err(|e| input_error(1, e))?;
     let reader = BufReader::new(file);
     let mut lines = reader.lines();
+    let mut line_number = 0;
     let mut count = 0;
 
-    while let Some(line) = lines.next_line().await? {
-        if !line.trim().is_empty() {
-            count += 1;
+    loop {
+        line_number += 1;
+        match lines.next_line().await {
+            Ok(Some(line)) if !line.trim().is_empty() => count += 1,
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(e) => return Err(input_error(line_number, e)),
         }
     }
 
@@ -405,7 +894,7 @@ async fn count_non_empty_lines(input_path: &std::path::Path) -> anyhow::Result<u
 }
 
 /// Legacy function name for compatibility
-pub async fn run_embed(args: EmbedArgs) -> anyhow::Result<()> {
+pub async fn run_embed(args: EmbedArgs) -> Result<(), CliError> {
     run_embed_command(args).await
 }
 
@@ -424,7 +913,8 @@ mod tests {
         fs::write(&input_file, "Hello world\nTest content\n")?;
 
         let args = EmbedArgs {
-            model: "microsoft/DialoGPT-medium".to_string(),
+            config: None,
+            model: Some("microsoft/DialoGPT-medium".to_string()),
             filename: None,
             input: input_file,
             output: temp_dir.path().join("output.parquet"),
@@ -432,6 +922,20 @@ mod tests {
             normalize: false,
             max_length: Some(512),
             debug: false,
+            hash: HashAlgo::Md5,
+            invalid_vectors: InvalidVectorPolicy::Fail,
+            omit_text: false,
+            columns: None,
+            dedupe: false,
+            dedupe_against: None,
+            compression: ParquetCodec::Zstd,
+            row_group_size: None,
+            shard_size_rows: None,
+            resume: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
         };
 
         Ok((args, temp_dir))
@@ -444,9 +948,29 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_validate_embed_args_missing_dedupe_file() {
+        let (mut args, temp_dir) = create_valid_embed_args().expect("Failed to create test args");
+        args.dedupe_against = Some(temp_dir.path().join("previous.parquet"));
+
+        let error = validate_embed_args(&args).unwrap_err().to_string();
+        assert!(error.contains("Dedupe file does not exist"));
+    }
+
+    #[test]
+    fn test_validate_embed_args_zero_shard_size() {
+        let (mut args, _temp_dir) = create_valid_embed_args().expect("Failed to create test args");
+        args.shard_size_rows = Some(0);
+        assert!(validate_embed_args(&args).is_err());
+
+        args.shard_size_rows = Some(1000);
+        args.row_group_size = Some(0);
+        assert!(validate_embed_args(&args).is_err());
+    }
+
     #[test]
     fn test_validate_model_source_empty() {
-        let result = validate_model_source("", &None);
+        let result = validate_model_source("", &None, false);
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -456,7 +980,7 @@ mod tests {
 
     #[test]
     fn test_validate_model_source_invalid_huggingface() {
-        let result = validate_model_source("invalid-repo", &None);
+        let result = validate_model_source("invalid-repo", &None, false);
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -466,14 +990,25 @@ mod tests {
 
     #[test]
     fn test_validate_model_source_valid_huggingface() {
-        let result = validate_model_source("microsoft/DialoGPT-medium", &None);
+        let result = validate_model_source("microsoft/DialoGPT-medium", &None, false);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_validate_model_source_url() {
+        assert!(validate_model_source("https://example.com/m.gguf", &None, false).is_ok());
+
+        let error = validate_model_source("http://example.com/m.gguf", &None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("--allow-http"));
+        assert!(validate_model_source("http://example.com/m.gguf", &None, true).is_ok());
+    }
+
     #[test]
     fn test_validate_model_source_nonexistent_local() {
         // Use a path without '/' so it's treated as local, not HuggingFace
-        let result = validate_model_source("nonexistent_local_model", &None);
+        let result = validate_model_source("nonexistent_local_model", &None, false);
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -484,11 +1019,59 @@ mod tests {
     #[test]
     fn test_validate_model_source_valid_local() -> anyhow::Result<()> {
         let temp_dir = tempdir()?;
-        let result = validate_model_source(temp_dir.path().to_str().unwrap(), &None);
+        let result = validate_model_source(temp_dir.path().to_str().unwrap(), &None, false);
+        assert!(result.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_model_source_local_gguf_file() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let model_file = temp_dir.path().join("model.gguf");
+        std::fs::write(&model_file, b"GGUF")?;
+
+        let result = validate_model_source(model_file.to_str().unwrap(), &None, false);
         assert!(result.is_ok());
         Ok(())
     }
 
+    #[test]
+    fn test_validate_model_source_local_wrong_extension() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let notes = temp_dir.path().join("notes.txt");
+        std::fs::write(&notes, b"notes")?;
+
+        let error = validate_model_source(notes.to_str().unwrap(), &None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains(".gguf extension"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_model_source_local_missing_file() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+
+        // The folder exists, the file does not
+        let missing = temp_dir.path().join("missing.gguf");
+        let error = validate_model_source(missing.to_str().unwrap(), &None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("missing.gguf does not exist in folder"),
+            "{}",
+            error
+        );
+
+        // Neither exists
+        let missing = temp_dir.path().join("nowhere/missing.gguf");
+        let error = validate_model_source(missing.to_str().unwrap(), &None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Local model path not found"), "{}", error);
+        Ok(())
+    }
+
     #[test]
     fn test_validate_input_file_nonexistent() {
         let nonexistent_path = PathBuf::from("/nonexistent/file.txt");
@@ -639,7 +1222,8 @@ mod tests {
         let test_cases = vec![
             // Valid HuggingFace model
             EmbedArgs {
-                model: "microsoft/DialoGPT-medium".to_string(),
+                config: None,
+                model: Some("microsoft/DialoGPT-medium".to_string()),
                 filename: None,
                 input: input_file.clone(),
                 output: temp_dir.path().join("output1.parquet"),
@@ -647,10 +1231,25 @@ mod tests {
                 normalize: false,
                 max_length: Some(512),
                 debug: false,
+                hash: HashAlgo::Md5,
+                invalid_vectors: InvalidVectorPolicy::Fail,
+                omit_text: false,
+                columns: None,
+                dedupe: false,
+                dedupe_against: None,
+                compression: ParquetCodec::Zstd,
+                row_group_size: None,
+                shard_size_rows: None,
+                resume: false,
+                allow_http: false,
+                force_load: false,
+                refresh_model_metadata: false,
+                cache_dir: None,
             },
             // Valid local model (using temp dir as placeholder)
             EmbedArgs {
-                model: temp_dir.path().to_string_lossy().to_string(),
+                config: None,
+                model: Some(temp_dir.path().to_string_lossy().to_string()),
                 filename: None,
                 input: input_file.clone(),
                 output: temp_dir.path().join("output2.parquet"),
@@ -658,6 +1257,20 @@ mod tests {
                 normalize: true,
                 max_length: None,
                 debug: true,
+                hash: HashAlgo::Md5,
+                invalid_vectors: InvalidVectorPolicy::Fail,
+                omit_text: false,
+                columns: None,
+                dedupe: false,
+                dedupe_against: None,
+                compression: ParquetCodec::Zstd,
+                row_group_size: None,
+                shard_size_rows: None,
+                resume: false,
+                allow_http: false,
+                force_load: false,
+                refresh_model_metadata: false,
+                cache_dir: None,
             },
         ];
 
@@ -673,7 +1286,7 @@ mod tests {
     #[test]
     fn test_error_messages_contain_suggestions() {
         // Test empty model
-        let result = validate_model_source("", &None);
+        let result = validate_model_source("", &None, false);
         assert!(result.is_err());
         let error_msg = result.unwrap_err().to_string();
         assert!(error_msg.contains("💡"));
@@ -694,4 +1307,394 @@ mod tests {
         assert!(error_msg.contains("💡"));
         assert!(error_msg.contains("reasonable batch size"));
     }
+
+    #[test]
+    fn test_to_embedding_config_from_config_file() -> anyhow::Result<()> {
+        let (mut args, temp_dir) = create_valid_embed_args()?;
+        let config_path = temp_dir.path().join("agent.yaml");
+        fs::write(
+            &config_path,
+            "model:\n  debug: true\n  cache_dir: /data/models\n  source:\n    HuggingFace:\n      repo: Qwen/Qwen3-Embedding-0.6B-GGUF\n",
+        )?;
+
+        // The config file provides the model when --model is omitted
+        args.config = Some(config_path);
+        args.model = None;
+        args.filename = Some("model.gguf".to_string());
+        let config = args.to_embedding_config()?;
+        match &config.model_source {
+            ModelSource::HuggingFace { repo, filename } => {
+                assert_eq!(repo, "Qwen/Qwen3-Embedding-0.6B-GGUF");
+                assert_eq!(filename.as_deref(), Some("model.gguf"));
+            }
+            _ => panic!("Expected HuggingFace source"),
+        }
+        assert!(config.debug);
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/data/models")));
+
+        // --cache-dir takes precedence over the config file
+        args.cache_dir = Some(PathBuf::from("/scratch/models"));
+        let config = args.to_embedding_config()?;
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/scratch/models")));
+
+        // An explicit --model takes precedence over the config file
+        args.model = Some("microsoft/DialoGPT-medium".to_string());
+        let config = args.to_embedding_config()?;
+        assert!(matches!(
+            &config.model_source,
+            ModelSource::HuggingFace { repo, .. } if repo == "microsoft/DialoGPT-medium"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_flag() -> anyhow::Result<()> {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            args: EmbedArgs,
+        }
+
+        let base = [
+            "llama-cli",
+            "--model",
+            "org/repo",
+            "-i",
+            "in.txt",
+            "-o",
+            "out.parquet",
+        ];
+        let cli = Cli::try_parse_from(base)?;
+        assert_eq!(cli.args.hash, HashAlgo::Md5);
+
+        let cli = Cli::try_parse_from(base.iter().copied().chain(["--hash", "xxh3"]))?;
+        assert_eq!(cli.args.hash, HashAlgo::Xxh3);
+        assert_eq!(cli.args.to_embedding_config()?.hash, HashAlgo::Xxh3);
+
+        assert!(Cli::try_parse_from(base.iter().copied().chain(["--hash", "crc32"])).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_vectors_flag() -> anyhow::Result<()> {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            args: EmbedArgs,
+        }
+
+        let base = [
+            "llama-cli",
+            "--model",
+            "org/repo",
+            "-i",
+            "in.txt",
+            "-o",
+            "out.parquet",
+        ];
+        let cli = Cli::try_parse_from(base)?;
+        assert_eq!(cli.args.invalid_vectors, InvalidVectorPolicy::Fail);
+
+        let cli = Cli::try_parse_from(
+            base.iter()
+                .copied()
+                .chain(["--invalid-vectors", "zero-fill"]),
+        )?;
+        assert_eq!(
+            cli.args.to_embedding_config()?.invalid_vectors,
+            InvalidVectorPolicy::ZeroFill
+        );
+
+        assert!(
+            Cli::try_parse_from(base.iter().copied().chain(["--invalid-vectors", "drop"])).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_layout_flags() -> anyhow::Result<()> {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            args: EmbedArgs,
+        }
+
+        let base = [
+            "llama-cli",
+            "--model",
+            "org/repo",
+            "-i",
+            "in.txt",
+            "-o",
+            "out.parquet",
+        ];
+        let cli = Cli::try_parse_from(base)?;
+        assert_eq!(cli.args.compression, ParquetCodec::Zstd);
+        assert_eq!(cli.args.row_group_size, None);
+        assert_eq!(cli.args.shard_size_rows, None);
+
+        let cli = Cli::try_parse_from(base.iter().copied().chain([
+            "--compression",
+            "snappy",
+            "--row-group-size",
+            "5000",
+            "--shard-size-rows",
+            "100000",
+        ]))?;
+        assert_eq!(cli.args.compression, ParquetCodec::Snappy);
+        assert_eq!(cli.args.row_group_size, Some(5000));
+        assert_eq!(cli.args.shard_size_rows, Some(100000));
+
+        assert!(
+            Cli::try_parse_from(base.iter().copied().chain(["--compression", "lzma"])).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_after_interrupt() {
+        use crate::manifest::RunSettings;
+        use llama_loader::ModelSource;
+        use polars::prelude::{LazyFrame, ScanArgsParquet};
+
+        let texts: Vec<String> = (0..10).map(|i| format!("text {}", i)).collect();
+        let results = |range: std::ops::Range<usize>| -> Vec<EmbeddingResult> {
+            texts[range]
+                .iter()
+                .map(|text| EmbeddingResult::new(text.clone(), vec![1.0, 2.0], 2, 1))
+                .collect()
+        };
+
+        for shard_size in [None, Some(4)] {
+            let temp_dir = tempdir().unwrap();
+            let output = temp_dir.path().join("out.parquet");
+            let manifest_file = manifest_path(&output);
+            let settings = RunSettings {
+                input: temp_dir.path().join("input.txt"),
+                model_source: ModelSource::HuggingFace {
+                    repo: "org/embedder".to_string(),
+                    filename: None,
+                },
+                normalize: false,
+                max_length: None,
+                hash: HashAlgo::Md5,
+                dedupe: false,
+                compression: ParquetCodec::Zstd,
+                shard_size_rows: shard_size,
+                columns: column_names(&OutputColumn::ALL, HashAlgo::Md5),
+            };
+            let new_writer = || {
+                ParquetWriter::new(&output, 2, 3)
+                    .unwrap()
+                    .with_shard_size(shard_size.unwrap_or(0))
+            };
+
+            // Two batches are checkpointed; the third reaches the output but the run dies
+            // before its manifest update
+            {
+                let mut writer = new_writer();
+                let mut manifest = RunManifest::new(settings.clone());
+                record_batch(&mut writer, &mut manifest, &manifest_file, results(0..3), 3).unwrap();
+                record_batch(&mut writer, &mut manifest, &manifest_file, results(3..6), 3).unwrap();
+                writer.write_batch(results(6..9)).unwrap();
+            }
+
+            let mut manifest = RunManifest::load(&manifest_file).unwrap();
+            manifest.check_resumable(&settings).unwrap();
+            assert_eq!(manifest.lines_processed, 6);
+            assert_eq!(manifest.records_written, 6);
+
+            let mut writer = new_writer().resume(manifest.records_written).unwrap();
+            let skip = manifest.lines_processed;
+            record_batch(
+                &mut writer,
+                &mut manifest,
+                &manifest_file,
+                results(skip..10),
+                10 - skip,
+            )
+            .unwrap();
+            writer.flush().unwrap();
+            let files = writer.output_files();
+            assert_eq!(writer.close().unwrap(), 10);
+            assert_eq!(files.len(), if shard_size.is_some() { 3 } else { 1 });
+
+            let mut written = Vec::new();
+            for file in &files {
+                let df = LazyFrame::scan_parquet(file, ScanArgsParquet::default())
+                    .unwrap()
+                    .collect()
+                    .unwrap();
+                let column = df.column("text").unwrap().str().unwrap();
+                written.extend(column.into_iter().flatten().map(str::to_string));
+            }
+            assert_eq!(written, texts, "shard size {:?}", shard_size);
+            assert_eq!(
+                RunManifest::load(&manifest_file).unwrap().records_written,
+                10
+            );
+        }
+    }
+
+    #[test]
+    fn test_column_flags() -> anyhow::Result<()> {
+        use clap::Parser;
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            args: EmbedArgs,
+        }
+
+        let base = [
+            "llama-cli",
+            "--model",
+            "org/repo",
+            "-i",
+            "in.txt",
+            "-o",
+            "out.parquet",
+        ];
+        let cli = Cli::try_parse_from(base)?;
+        assert_eq!(cli.args.output_columns(), OutputColumn::ALL.to_vec());
+
+        let cli = Cli::try_parse_from(base.iter().copied().chain(["--omit-text"]))?;
+        assert!(!cli.args.output_columns().contains(&OutputColumn::Text));
+        assert_eq!(cli.args.output_columns().len(), 4);
+
+        let cli = Cli::try_parse_from(
+            base.iter()
+                .copied()
+                .chain(["--columns", "hash,embedding,token_count"]),
+        )?;
+        assert_eq!(
+            cli.args.output_columns(),
+            vec![
+                OutputColumn::Hash,
+                OutputColumn::Embedding,
+                OutputColumn::TokenCount
+            ]
+        );
+
+        let error =
+            match Cli::try_parse_from(base.iter().copied().chain(["--columns", "hash,vector"])) {
+                Ok(_) => panic!("unknown column accepted"),
+                Err(error) => error.to_string(),
+            };
+        assert!(error.contains("unknown column 'vector'"));
+        assert!(error.contains("text, hash, token_count, processing_time, embedding"));
+
+        assert!(Cli::try_parse_from(base.iter().copied().chain([
+            "--omit-text",
+            "--columns",
+            "hash"
+        ]))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_columns() {
+        let (mut args, _temp_dir) = create_valid_embed_args().expect("Failed to create test args");
+        args.omit_text = true;
+        assert!(validate_embed_args(&args).is_ok());
+
+        args.hash = HashAlgo::None;
+        let error = validate_embed_args(&args).unwrap_err().to_string();
+        assert!(error.contains("must include the hash column"));
+
+        args.omit_text = false;
+        assert!(validate_embed_args(&args).is_ok());
+        args.columns = Some(vec![OutputColumn::Hash, OutputColumn::Embedding]);
+        let error = validate_embed_args(&args).unwrap_err().to_string();
+        assert!(error.contains("--hash none"));
+
+        args.hash = HashAlgo::Md5;
+        args.columns = Some(vec![OutputColumn::Embedding]);
+        let error = validate_embed_args(&args).unwrap_err().to_string();
+        assert!(error.contains("must include the hash column"));
+
+        args.columns = Some(vec![OutputColumn::Text, OutputColumn::Embedding]);
+        assert!(validate_embed_args(&args).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_non_utf8_input_is_an_input_error() {
+        let (mut args, _temp_dir) = create_valid_embed_args().expect("Failed to create test args");
+        args.input = PathBuf::from(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/non_utf8.txt"
+        ));
+
+        // Found before the model is loaded
+        let error = run_embed_command(args).await.unwrap_err();
+        assert_eq!(error.exit_code(), 5, "{}", error);
+        let message = error.to_string();
+        assert!(message.contains("non_utf8.txt at line 3"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_output_dir_is_an_output_error() {
+        let (mut args, temp_dir) = create_valid_embed_args().expect("Failed to create test args");
+        let not_a_dir = temp_dir.path().join("not-a-dir");
+        fs::write(&not_a_dir, "").unwrap();
+        args.output = not_a_dir.join("embeddings.parquet");
+
+        let error = run_embed_command(args).await.unwrap_err();
+        assert_eq!(error.exit_code(), 6, "{}", error);
+        assert!(error.to_string().contains("not-a-dir"), "{}", error);
+    }
+
+    #[test]
+    fn test_embedding_errors_keep_their_class() {
+        let error = EmbedError::try_from(EmbeddingError::InputRead {
+            path: PathBuf::from("texts.txt"),
+            line: 4,
+            source: std::io::Error::new(std::io::ErrorKind::InvalidData, "bad UTF-8"),
+        })
+        .unwrap();
+        assert!(matches!(error, EmbedError::InputRead { line: 4, .. }));
+
+        let error =
+            EmbedError::try_from(EmbeddingError::NotEmbeddingModel("no pooling".to_string()))
+                .unwrap();
+        assert!(matches!(error, EmbedError::ModelNotEmbedding(_)));
+
+        let error = EmbedError::from_parquet(
+            Path::new("out.parquet"),
+            ParquetError::SchemaMismatch {
+                expected: 384,
+                actual: 768,
+            },
+        );
+        assert!(matches!(
+            error,
+            EmbedError::DimensionMismatch {
+                expected: 384,
+                actual: 768
+            }
+        ));
+
+        assert!(EmbedError::try_from(EmbeddingError::ModelNotLoaded).is_err());
+    }
+
+    #[test]
+    fn test_resume_requires_manifest() {
+        let (mut args, _temp_dir) = create_valid_embed_args().expect("Failed to create test args");
+        args.resume = true;
+
+        let error = validate_embed_args(&args).unwrap_err().to_string();
+        assert!(error.contains("No run manifest found"));
+    }
 }
@@ -0,0 +1,303 @@
+use crate::embed::EmbedError;
+use clap::ValueEnum;
+use llama_agent::types::AgentError;
+use llama_loader::ModelError;
+use thiserror::Error;
+
+/// Error returned by the CLI commands, classified by what went wrong
+#[derive(Debug, Error)]
+pub enum CliError {
+    /// Invalid arguments, configuration or input files
+    #[error(transparent)]
+    Validation(anyhow::Error),
+
+    /// The model could not be loaded or initialized
+    #[error(transparent)]
+    ModelLoad(anyhow::Error),
+
+    /// An MCP server, tool or prompt failed
+    #[error(transparent)]
+    Mcp(anyhow::Error),
+
+    /// An input file could not be read
+    #[error(transparent)]
+    Input(anyhow::Error),
+
+    /// An output file could not be written
+    #[error(transparent)]
+    Output(anyhow::Error),
+
+    /// Any other failure while running the command
+    #[error(transparent)]
+    Runtime(anyhow::Error),
+
+    /// The command was interrupted, e.g. by Ctrl-C while the model downloaded
+    #[error(transparent)]
+    Interrupted(anyhow::Error),
+}
+
+impl CliError {
+    /// Process exit code for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // MCP failures keep the code they had before they got a class of their own
+            CliError::Runtime(_) | CliError::Mcp(_) => 1,
+            CliError::Validation(_) => 2,
+            CliError::ModelLoad(_) => 3,
+            CliError::Input(_) => 5,
+            CliError::Output(_) => 6,
+            CliError::Interrupted(_) => 130,
+        }
+    }
+
+    /// Stable identifier used in JSON error output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CliError::Validation(_) => "validation",
+            CliError::ModelLoad(_) => "model_load",
+            CliError::Mcp(_) => "mcp",
+            CliError::Input(_) => "input",
+            CliError::Output(_) => "output",
+            CliError::Runtime(_) => "runtime",
+            CliError::Interrupted(_) => "interrupted",
+        }
+    }
+
+    /// Render the error for stderr in the requested format
+    pub fn render(&self, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Text => {
+                let label = match self {
+                    CliError::Validation(_) => "Error",
+                    CliError::ModelLoad(_) => "Model Error",
+                    CliError::Mcp(_) => "MCP Error",
+                    CliError::Input(_) => "Input Error",
+                    CliError::Output(_) => "Output Error",
+                    CliError::Runtime(_) => "Runtime Error",
+                    CliError::Interrupted(_) => "Interrupted",
+                };
+                format!("{}: {}", label, self)
+            }
+            ErrorFormat::Json => serde_json::json!({
+                "code": self.exit_code(),
+                "kind": self.kind(),
+                "message": self.to_string(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for CliError {
+    fn from(err: anyhow::Error) -> Self {
+        CliError::Runtime(err)
+    }
+}
+
+impl CliError {
+    /// Classify a failure to initialize the agent, which loads the model: configuration
+    /// and MCP errors keep their own class, anything else is a model load error
+    pub fn from_initialization(err: AgentError) -> Self {
+        match CliError::from(err) {
+            CliError::Runtime(err) => {
+                CliError::ModelLoad(anyhow::anyhow!("Failed to initialize agent: {}", err))
+            }
+            classified => classified,
+        }
+    }
+}
+
+impl From<AgentError> for CliError {
+    fn from(err: AgentError) -> Self {
+        // Classified by the underlying error; the message keeps any request context
+        match err.root() {
+            AgentError::MCP(_) => CliError::Mcp(err.into()),
+            AgentError::Model(ModelError::Cancelled) => CliError::Interrupted(err.into()),
+            AgentError::Config(_)
+            | AgentError::InvalidConfig(_)
+            | AgentError::Model(ModelError::InvalidConfig(_)) => CliError::Validation(err.into()),
+            AgentError::Model(
+                ModelError::InvalidFormat { .. }
+                | ModelError::Truncated { .. }
+                | ModelError::OutOfMemory { .. }
+                | ModelError::InsufficientMemory { .. }
+                | ModelError::UnsupportedArchitecture { .. }
+                | ModelError::NotFound(_)
+                | ModelError::LoadingFailed(_)
+                | ModelError::NotLoaded
+                | ModelError::AmbiguousModelFile { .. }
+                | ModelError::IncompleteMultipart { .. }
+                | ModelError::ChecksumMismatch { .. }
+                | ModelError::DownloadFailed { .. },
+            ) => CliError::ModelLoad(err.into()),
+            _ => CliError::Runtime(err.into()),
+        }
+    }
+}
+
+impl From<EmbedError> for CliError {
+    fn from(err: EmbedError) -> Self {
+        match err {
+            EmbedError::InputRead { .. } => CliError::Input(err.into()),
+            EmbedError::OutputWrite { .. } => CliError::Output(err.into()),
+            EmbedError::ModelNotEmbedding(_) | EmbedError::DimensionMismatch { .. } => {
+                CliError::ModelLoad(err.into())
+            }
+        }
+    }
+}
+
+/// Format of the error printed when a command fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ErrorFormat {
+    /// Human-readable message
+    #[default]
+    Text,
+    /// One JSON object: `{"code": .., "kind": .., "message": ..}`
+    Json,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llama_agent::types::MCPError;
+
+    fn all_variants() -> Vec<CliError> {
+        vec![
+            CliError::Validation(anyhow::anyhow!("bad flag")),
+            CliError::ModelLoad(anyhow::anyhow!("bad model")),
+            CliError::Mcp(anyhow::anyhow!("bad server")),
+            CliError::Input(anyhow::anyhow!("bad input")),
+            CliError::Output(anyhow::anyhow!("bad output")),
+            CliError::Runtime(anyhow::anyhow!("bad luck")),
+            CliError::Interrupted(anyhow::anyhow!("stopped")),
+        ]
+    }
+
+    #[test]
+    fn test_exit_codes_and_kinds() {
+        let mapped: Vec<_> = all_variants()
+            .iter()
+            .map(|e| (e.exit_code(), e.kind()))
+            .collect();
+        assert_eq!(
+            mapped,
+            vec![
+                (2, "validation"),
+                (3, "model_load"),
+                (1, "mcp"),
+                (5, "input"),
+                (6, "output"),
+                (1, "runtime"),
+                (130, "interrupted"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_format() {
+        for error in all_variants() {
+            let json: serde_json::Value =
+                serde_json::from_str(&error.render(ErrorFormat::Json)).unwrap();
+            assert_eq!(json["code"], error.exit_code());
+            assert_eq!(json["kind"], error.kind());
+            assert_eq!(json["message"], error.to_string());
+        }
+    }
+
+    #[test]
+    fn test_agent_error_conversion() {
+        let mcp: CliError = AgentError::MCP(MCPError::Connection("down".to_string())).into();
+        assert!(matches!(mcp, CliError::Mcp(_)));
+
+        // Cancelling a download keeps its class through initialization
+        let cancelled = CliError::from_initialization(AgentError::Model(ModelError::Cancelled));
+        assert_eq!(cancelled.exit_code(), 130);
+
+        let timeout: CliError = AgentError::Timeout {
+            timeout: std::time::Duration::from_secs(1),
+        }
+        .into();
+        assert!(matches!(timeout, CliError::Runtime(_)));
+
+        let full: CliError = AgentError::QueueFull {
+            current_depth: 4,
+            max_depth: 4,
+            estimated_wait: Some(std::time::Duration::from_secs(12)),
+        }
+        .into();
+        assert!(full.render(ErrorFormat::Text).starts_with(
+            "Runtime Error: Queue overloaded: 4 of 4 requests queued, try again in ~12s"
+        ));
+
+        let truncated: CliError = AgentError::Model(ModelError::Truncated {
+            path: "/models/model.gguf".into(),
+            expected: 4096,
+            actual: 1024,
+        })
+        .into();
+        assert_eq!(truncated.exit_code(), 3);
+        assert!(truncated
+            .to_string()
+            .contains("💡 Delete the partial file from the model cache"));
+
+        let arch: CliError = AgentError::Model(ModelError::UnsupportedArchitecture {
+            arch: "quantum-llm".to_string(),
+        })
+        .into();
+        assert!(matches!(arch, CliError::ModelLoad(_)));
+        assert!(arch.to_string().contains("'quantum-llm'"));
+
+        let invalid: CliError =
+            AgentError::Model(ModelError::InvalidConfig("batch_size is 0".to_string())).into();
+        assert!(matches!(invalid, CliError::Validation(_)));
+
+        let inference: CliError =
+            AgentError::Model(ModelError::InferenceFailed("decode failed".to_string())).into();
+        assert!(matches!(inference, CliError::Runtime(_)));
+
+        let init = CliError::from_initialization(AgentError::Timeout {
+            timeout: std::time::Duration::from_secs(1),
+        });
+        assert!(matches!(init, CliError::ModelLoad(_)));
+        assert!(init.to_string().starts_with("Failed to initialize agent: "));
+        let init = CliError::from_initialization(AgentError::MCP(MCPError::Connection(
+            "down".to_string(),
+        )));
+        assert!(matches!(init, CliError::Mcp(_)));
+
+        let other: CliError = anyhow::anyhow!("anything").into();
+        assert!(matches!(other, CliError::Runtime(_)));
+    }
+
+    #[test]
+    fn test_embed_error_conversion() {
+        let input: CliError = EmbedError::InputRead {
+            path: "texts.txt".into(),
+            line: 7,
+            reason: "stream did not contain valid UTF-8".to_string(),
+        }
+        .into();
+        assert_eq!(input.exit_code(), 5);
+        assert!(input
+            .render(ErrorFormat::Text)
+            .starts_with("Input Error: Cannot read input file texts.txt at line 7: "));
+
+        let output: CliError = EmbedError::OutputWrite {
+            path: "out/embeddings.parquet".into(),
+            reason: "disk full".to_string(),
+        }
+        .into();
+        assert_eq!(output.exit_code(), 6);
+        assert!(output.to_string().contains("out/embeddings.parquet"));
+
+        let chat: CliError = EmbedError::ModelNotEmbedding("no pooling".to_string()).into();
+        assert_eq!(chat.exit_code(), 3);
+        let dimension: CliError = EmbedError::DimensionMismatch {
+            expected: 384,
+            actual: 768,
+        }
+        .into();
+        assert_eq!(dimension.kind(), "model_load");
+    }
+}
@@ -1,45 +1,114 @@
+use crate::batch::run_prompts_file;
+use crate::error::CliError;
+use crate::stop_sequences::{fired_stop_sequence, parse_stop_sequence, validate_stop_sequences};
+use crate::stream_output::{print_status_line, tool_status_line, ResponseWriter, StreamFlush};
+use crate::transcript::load_transcript;
 use anyhow::Result;
+use clap::builder::PossibleValuesParser;
 use clap::Args;
 use futures::StreamExt;
 use llama_agent::{
     types::{
-        AgentAPI, AgentConfig, FinishReason, GenerationRequest, Message, MessageRole, ModelConfig,
-        ModelSource, QueueConfig, RetryConfig, SessionConfig,
+        AgentAPI, AgentConfig, AgentError, FinishReason, GeneratedToken, GenerationRequest,
+        GenerationResponse, LimitsConfig, LoadMode, Message, MessageRole, ModelConfig, ModelInfo,
+        ModelSource, ParallelExecutionConfig, QueueConfig, SessionConfig, SessionEvictionPolicy,
+        SessionId, TemplateOverride, ToolPolicy,
     },
-    AgentServer,
+    AgentServer, PostProcessor, RedactionPolicy,
+};
+use llama_loader::detection::explain_model_choice;
+use llama_loader::http::is_model_url;
+use llama_loader::types::{describe_missing_local_path, has_gguf_extension};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
 };
-use std::{io::Write, path::PathBuf, time::Duration};
 use tokio::signal;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
 const SEPARATOR_WIDTH: usize = 60;
 
+// Defaults used when a value comes neither from a config file nor from a flag
+const DEFAULT_BATCH_SIZE: u32 = 512;
+const DEFAULT_MAX_QUEUE_SIZE: usize = 10;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 120;
+const DEFAULT_WORKER_THREADS: usize = 1;
+const DEFAULT_MAX_SESSIONS: usize = 10;
+const DEFAULT_SESSION_TIMEOUT_SECS: u64 = 3600;
+
 #[derive(Args, Clone)]
 #[command(about = "Generate text using a language model")]
 pub struct GenerateArgs {
-    /// Model source: HuggingFace repo (org/model) or local folder path
+    /// Model source and loading flags shared with the other model commands
+    #[command(flatten)]
+    pub model_args: ModelArgs,
+
+    /// Prompt text to generate from
     #[arg(
         long,
-        help = "Model source: HuggingFace repo (org/model) or local folder path"
+        required_unless_present_any = ["transcript", "prompts_file"],
+        help = "Prompt text to generate from",
+        long_help = "Prompt text to generate from. Optional with --transcript when the transcript ends with a User turn"
     )]
-    pub model: String,
+    pub prompt: Option<String>,
 
-    /// Prompt text to generate from
-    #[arg(long, help = "Prompt text to generate from")]
-    pub prompt: String,
+    /// Plain-text conversation to seed the session with
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Plain-text conversation to seed the session with",
+        long_help = "Text file of turns starting with User:, Assistant: or System: at the start of a line. Its messages are added to the session before --prompt"
+    )]
+    pub transcript: Option<PathBuf>,
 
-    /// Optional filename to use from repo or folder
+    /// File of prompts to run one after another with a single model load
     #[arg(
         long,
-        help = "Optional filename to use from repo or folder",
-        long_help = "Optional specific filename to use from the repo or folder. If not provided, will auto-detect with BF16 preference"
+        value_name = "PATH",
+        conflicts_with_all = ["prompt", "transcript", "prompt_template", "embed_prompt", "dry_run"],
+        help = "Run every prompt of a file and write JSON line results",
+        long_help = "File with one prompt per line, or JSON lines {\"prompt\": ..., \"id\": ...}. The model is loaded once and each prompt runs in a fresh session; results are written as JSON lines in the order they complete. A failed prompt is recorded and the run continues"
     )]
-    pub filename: Option<String>,
+    pub prompts_file: Option<PathBuf>,
+
+    /// Prompts of --prompts-file generated in parallel (default: 1)
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Prompts of --prompts-file generated in parallel",
+        long_help = "Number of --prompts-file prompts in flight at once. Queue and session limits are raised to at least this"
+    )]
+    pub concurrency: usize,
+
+    /// File for the --prompts-file results
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "prompts_file",
+        help = "File for the --prompts-file results (default: stdout)"
+    )]
+    pub output: Option<PathBuf>,
 
     /// Max tokens to generate (default: 512)
-    #[arg(long, default_value = "512", help = "Max tokens to generate")]
+    #[arg(
+        long,
+        default_value = "512",
+        help = "Max tokens to generate",
+        long_help = "Max tokens to generate. 0 only decodes the prompt and generates nothing"
+    )]
     pub limit: u32,
 
+    /// Time budget for generation in milliseconds
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Stop generating after this many milliseconds",
+        long_help = "Stop generating after this many milliseconds and keep the text generated so far. Combined with --limit, whichever is reached first; must not exceed the request timeout"
+    )]
+    pub max_time_ms: Option<u64>,
+
     /// Temperature for generation (0.0-2.0, default: 0.7)
     #[arg(
         long,
@@ -56,110 +125,772 @@ pub struct GenerateArgs {
     )]
     pub top_p: f32,
 
-    /// Enable debug logging
-    #[arg(long, default_value = "false", help = "Enable debug logging")]
-    pub debug: bool,
-
-    /// Model batch size for processing
+    /// Model batch size for processing (default: 512)
     #[arg(
         long,
-        default_value = "512",
         help = "Model batch size",
-        long_help = "Model batch size for processing"
+        long_help = "Model batch size for processing (default: 512)"
     )]
-    pub batch_size: u32,
+    pub batch_size: Option<u32>,
 
-    /// Maximum queue size for pending requests
+    /// Maximum queue size for pending requests (default: 10)
     #[arg(
         long,
-        default_value = "10",
         help = "Max queue size",
-        long_help = "Maximum queue size for pending requests"
+        long_help = "Maximum queue size for pending requests (default: 10)"
     )]
-    pub max_queue_size: usize,
+    pub max_queue_size: Option<usize>,
 
-    /// Request timeout in seconds
+    /// Request timeout in seconds (default: 120)
     #[arg(
         long,
-        default_value = "120",
         help = "Request timeout (seconds)",
-        long_help = "Request timeout in seconds"
+        long_help = "Request timeout in seconds (default: 120)"
     )]
-    pub request_timeout: u64,
+    pub request_timeout: Option<u64>,
 
-    /// Number of worker threads
+    /// Number of worker threads (default: 1)
     #[arg(
         long,
-        default_value = "1",
         help = "Worker threads",
-        long_help = "Number of worker threads"
+        long_help = "Number of worker threads (default: 1)"
     )]
-    pub worker_threads: usize,
+    pub worker_threads: Option<usize>,
 
-    /// Maximum number of concurrent sessions
+    /// Maximum number of concurrent sessions (default: 10)
     #[arg(
         long,
-        default_value = "10",
         help = "Max sessions",
-        long_help = "Maximum number of concurrent sessions"
+        long_help = "Maximum number of concurrent sessions (default: 10)"
     )]
-    pub max_sessions: usize,
+    pub max_sessions: Option<usize>,
 
-    /// Session timeout in seconds
+    /// Session timeout in seconds (default: 3600)
     #[arg(
         long,
-        default_value = "3600",
         help = "Session timeout (seconds)",
-        long_help = "Session timeout in seconds"
+        long_help = "Session timeout in seconds (default: 3600)"
+    )]
+    pub session_timeout: Option<u64>,
+
+    /// Embed the prompt with the loaded model instead of generating text
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Embed the prompt instead of generating",
+        long_help = "Embed the prompt with the loaded model and print the vector length and first values instead of generating text"
+    )]
+    pub embed_prompt: bool,
+
+    /// MCP prompt to apply to the session before the user prompt
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "MCP prompt to apply first",
+        long_help = "Name of a prompt provided by a configured MCP server; its messages are added to the session before --prompt"
+    )]
+    pub prompt_template: Option<String>,
+
+    /// Arguments for --prompt-template as key=value pairs
+    #[arg(
+        long = "prompt-arg",
+        value_name = "KEY=VALUE",
+        help = "Prompt template argument (repeatable)",
+        long_help = "Argument passed to the --prompt-template prompt, as key=value. May be given multiple times"
+    )]
+    pub prompt_args: Vec<String>,
+
+    /// Print why the model file was auto-detected as it was
+    #[arg(
+        long,
+        help = "Explain which model file auto-detection picks and why",
+        long_help = "When --model has no --filename, print every file considered for auto-detection to stderr with the reason it was chosen or passed over, before loading the model"
+    )]
+    pub explain_model_choice: bool,
+
+    /// Token ids that end generation
+    #[arg(
+        long = "stop-token-id",
+        value_name = "ID",
+        help = "Stop when this token id is sampled (repeatable)",
+        long_help = "Token id that ends generation as soon as it is sampled, e.g. 128009 for Llama 3's <|eot_id|>. May be given multiple times"
     )]
-    pub session_timeout: u64,
+    pub stop_token_ids: Vec<u32>,
+
+    /// Text sequences that end generation
+    #[arg(
+        long = "stop",
+        value_name = "STRING",
+        value_parser = parse_stop_sequence,
+        help = "Stop when this text is generated (repeatable)",
+        long_help = "Text that ends generation once it appears in the output, e.g. '\\n\\nUser:' or '```'. Escapes \\n, \\r, \\t, \\0, \\\\, \\\" and \\' are supported. The stop sequence is kept at the end of the response. May be given multiple times"
+    )]
+    pub stop: Vec<String>,
+
+    /// Print only the response on stdout
+    #[arg(
+        long,
+        help = "Print only the response on stdout",
+        long_help = "Suppress decorative output such as separators and progress lines. Status lines and statistics are written to stderr so stdout carries only the generated text"
+    )]
+    pub quiet: bool,
+
+    /// Wait for the full response instead of streaming tokens
+    #[arg(
+        long,
+        help = "Print the response once generation finishes",
+        long_help = "Use the non-streaming generate API and print only the final text once generation finishes"
+    )]
+    pub no_stream: bool,
+
+    /// When streamed text is flushed to stdout
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value_t = StreamFlush::Token,
+        help = "Stream flushing: token, line or interval:<ms>",
+        long_help = "When streamed text is flushed to stdout: token flushes every token, line waits for complete lines, interval:<ms> coalesces tokens and flushes on a timer (useful over slow terminals such as SSH)"
+    )]
+    pub stream_flush: StreamFlush,
+
+    /// Print the generated text exactly as the model produced it
+    #[arg(
+        long,
+        help = "Skip post-processing of the generated text",
+        long_help = "Print the generated text exactly as the model produced it, skipping the post_processors of the config file or, without one, the default of removing chat template control tokens and trimming whitespace"
+    )]
+    pub raw_output: bool,
+
+    /// Print every generated token after the response
+    #[arg(
+        long,
+        help = "Print the generated tokens to stderr",
+        long_help = "After the response, print a table of every generated token to stderr: its index, id, text and log probability when known. Useful for diagnosing garbled output, such as a tokenizer mismatch"
+    )]
+    pub debug_tokens: bool,
+
+    /// Print model info and generation statistics after the response
+    #[arg(
+        long,
+        help = "Print model info and generation statistics to stderr",
+        long_help = "After the response, print the loaded model (source, file, size, quantization, load time) and the generation statistics to stderr, without the rest of the --debug output"
+    )]
+    pub stats: bool,
+
+    /// Render the prompt and count its tokens without generating
+    #[arg(
+        long,
+        conflicts_with = "embed_prompt",
+        help = "Print the rendered prompt and its token count, then exit",
+        long_help = "Render the prompt with the model's chat template, including --prompt-template messages and the tool instructions of MCP servers from --config, and print it with its token count and the context size. Nothing is generated"
+    )]
+    pub dry_run: bool,
+
+    /// Write the rendered prompt of --dry-run to a file instead of stdout
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "dry_run",
+        help = "File for the --dry-run prompt"
+    )]
+    pub dry_run_output: Option<PathBuf>,
 }
 
-pub fn validate_generate_args(args: &GenerateArgs) -> Result<()> {
-    // Validate model path
-    if args.model.is_empty() {
-        return Err(anyhow::anyhow!("Model path cannot be empty"));
+/// Returns true when a model argument refers to a local path rather than a HuggingFace repo
+fn is_local_model_path(model: &str) -> bool {
+    model.starts_with('/')
+        || model.starts_with("./")
+        || model.starts_with("../")
+        || model.contains('\\')
+        || model.to_lowercase().ends_with(".gguf")
+}
+
+/// Build the model source for a `--model` argument
+fn model_config_from_arg(model: &str, filename: Option<String>, base: ModelConfig) -> ModelConfig {
+    if is_model_url(model) {
+        ModelConfig {
+            source: ModelSource::Url {
+                url: model.to_string(),
+                filename,
+                sha256: None,
+            },
+            use_hf_params: false,
+            ..base
+        }
+    } else if is_local_model_path(model) {
+        ModelConfig {
+            source: ModelSource::local(model, filename),
+            use_hf_params: false,
+            ..base
+        }
+    } else {
+        ModelConfig {
+            source: ModelSource::HuggingFace {
+                repo: model.to_string(),
+                filename,
+            },
+            use_hf_params: true,
+            ..base
+        }
+    }
+}
+
+/// Agent configuration from the `--config` file plus `LLAMA_AGENT__*` environment
+/// overrides, or the CLI defaults when no file is given
+fn base_agent_config(config_file: Option<&Path>) -> Result<AgentConfig> {
+    match config_file {
+        Some(path) => AgentConfig::from_file(path)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e)),
+        None => Ok(AgentConfig {
+            model: ModelConfig {
+                batch_size: DEFAULT_BATCH_SIZE,
+                ..ModelConfig::default()
+            },
+            queue_config: QueueConfig {
+                max_queue_size: DEFAULT_MAX_QUEUE_SIZE,
+                request_timeout: Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+                worker_threads: DEFAULT_WORKER_THREADS,
+                ..QueueConfig::default()
+            },
+            session_config: SessionConfig {
+                max_sessions: DEFAULT_MAX_SESSIONS,
+                session_timeout: Duration::from_secs(DEFAULT_SESSION_TIMEOUT_SECS),
+                default_tool_policy: ToolPolicy::AllowAll,
+                append_responses: true,
+                append_on_stream_drop: true,
+                eviction_policy: SessionEvictionPolicy::Reject,
+                system_prompt: None,
+            },
+            mcp_servers: vec![], // No MCP servers for basic CLI
+            parallel_execution_config: ParallelExecutionConfig::default(),
+            load_mode: LoadMode::Eager,
+            limits: LimitsConfig::default(),
+            audit_log: None,
+            default_system_prompt: None,
+            models: Vec::new(),
+            redaction: RedactionPolicy::LogFull,
+            post_processors: vec![
+                PostProcessor::StripControlTokens,
+                PostProcessor::TrimWhitespace,
+            ],
+        }),
     }
+}
 
-    // Check if local path exists (starts with / or ./ or contains \)
-    if args.model.starts_with('/')
-        || args.model.starts_with("./")
-        || args.model.starts_with("../")
-        || args.model.contains('\\')
-    {
-        let path = PathBuf::from(&args.model);
-        if !path.exists() {
-            return Err(anyhow::anyhow!(
-                "Local model path does not exist: {}. Please check that the path is correct.",
-                args.model
-            ));
+/// Apply `--model` and `--filename` on top of the configured model source
+fn apply_model_args(config: &mut AgentConfig, model: Option<&str>, filename: Option<&str>) {
+    if let Some(model) = model {
+        config.model =
+            model_config_from_arg(model, filename.map(str::to_string), config.model.clone());
+    } else if let Some(filename) = filename {
+        // --filename without --model refines the source from the config file
+        match &mut config.model.source {
+            ModelSource::HuggingFace { filename: f, .. }
+            | ModelSource::Local { filename: f, .. }
+            | ModelSource::Url { filename: f, .. } => {
+                *f = Some(filename.to_string());
+            }
         }
-        if !path.is_dir() {
-            return Err(anyhow::anyhow!(
-                "Local model path must be a directory: {}. Please provide a folder containing model files.",
-                args.model
-            ));
+    }
+}
+
+/// Model flags of every command that loads a chat model, flattened into the arguments of
+/// `generate`, `serve`, `bench` and `doctor` so they resolve the model the same way
+#[derive(Args, Debug, Clone, Default)]
+pub struct ModelArgs {
+    /// Agent configuration file (TOML, YAML or JSON)
+    #[arg(
+        long,
+        help = "Agent configuration file (TOML, YAML or JSON)",
+        long_help = "Agent configuration file (TOML, YAML or JSON). LLAMA_AGENT__* environment variables are applied on top of the file, and explicit command-line flags override both"
+    )]
+    pub config: Option<PathBuf>,
+
+    /// Model source: HuggingFace repo (org/model), local folder path or https:// URL
+    #[arg(
+        long,
+        help = "Model source: HuggingFace repo (org/model), local folder path or https:// URL",
+        long_help = "Model source: HuggingFace repo (org/model), local folder path or https:// URL. Required unless --config names the model"
+    )]
+    pub model: Option<String>,
+
+    /// Optional filename to use from repo or folder
+    #[arg(
+        long,
+        help = "Optional filename to use from repo or folder",
+        long_help = "Optional specific filename to use from the repo or folder. If not provided, will auto-detect with BF16 preference"
+    )]
+    pub filename: Option<String>,
+
+    /// Enable debug logging
+    #[arg(long, default_value = "false", help = "Enable debug logging")]
+    pub debug: bool,
+
+    /// Allow plain http:// model URLs
+    #[arg(
+        long,
+        help = "Allow plain http:// model URLs",
+        long_help = "Allow --model to be a plain http:// link; only https:// is accepted by default"
+    )]
+    pub allow_http: bool,
+
+    /// Load the model even when it is estimated not to fit in available memory
+    #[arg(
+        long,
+        help = "Load the model even when it is estimated not to fit in memory",
+        long_help = "Skip the check that refuses to load a model whose weights, KV cache and overhead are estimated to exceed available memory"
+    )]
+    pub force_load: bool,
+
+    /// List the HuggingFace repository again instead of using the cached listing
+    #[arg(
+        long,
+        help = "List the HuggingFace repository again instead of using the cached listing",
+        long_help = "When --model is a HuggingFace repo without --filename, list the repository to pick the model file even if the cached listing (kept for model.metadata_ttl_secs, one day by default) is still fresh"
+    )]
+    pub refresh_model_metadata: bool,
+
+    /// Directory for downloaded models
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Directory for downloaded models",
+        long_help = "Directory for downloaded models. Overrides model.cache_dir and the LLAMA_CACHE_DIR environment variable; defaults to llama-loader/models in the platform cache directory"
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Directory levels below a local --model folder searched for model files
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Directory levels below a local model folder searched for model files",
+        long_help = "When the model is a local folder, also look this many levels of subfolders deep for model files. Overrides model.local_search_depth; by default only the folder itself is searched"
+    )]
+    pub local_search_depth: Option<usize>,
+
+    /// Chat template used instead of the one detected from the model
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = PossibleValuesParser::new(TemplateOverride::BUILTIN_NAMES),
+        help = "Chat template used instead of the one detected from the model",
+        long_help = "Chat template used instead of the one detected from the model name. Overrides model.chat_template; a custom template can only be given in a config file"
+    )]
+    pub chat_template: Option<String>,
+}
+
+impl ModelArgs {
+    /// Check `--model`, which may only be left out when a config file names the model
+    pub fn validate(&self) -> Result<()> {
+        match &self.model {
+            Some(model) => validate_model_arg(model, self.filename.as_deref(), self.allow_http),
+            None if self.config.is_none() => Err(anyhow::anyhow!(
+                "Model path cannot be empty\n💡 Provide --model or a --config file with a model section"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The base configuration with the model flags applied on top
+    pub fn agent_config(&self) -> Result<AgentConfig> {
+        let mut config = base_agent_config(self.config.as_deref())?;
+        apply_model_args(&mut config, self.model.as_deref(), self.filename.as_deref());
+
+        config.model.debug |= self.debug;
+        config.model.allow_http |= self.allow_http;
+        config.model.force_load |= self.force_load;
+        config.model.refresh_metadata |= self.refresh_model_metadata;
+        if let Some(cache_dir) = &self.cache_dir {
+            config.model.cache_dir = Some(cache_dir.clone());
+        }
+        if let Some(local_search_depth) = self.local_search_depth {
+            config.model.local_search_depth = local_search_depth;
         }
+        if let Some(chat_template) = &self.chat_template {
+            config.model.chat_template = Some(TemplateOverride::Named(chat_template.clone()));
+        }
+        Ok(config)
+    }
+}
+
+/// Build the agent configuration for a generate run.
+///
+/// Values are layered: CLI defaults (or the `--config` file plus `LLAMA_AGENT__*`
+/// environment overrides when given), then any flag passed explicitly on the command line.
+pub fn build_agent_config(args: &GenerateArgs) -> Result<AgentConfig> {
+    let mut config = args.model_args.agent_config()?;
+    if let Some(batch_size) = args.batch_size {
+        config.model.batch_size = batch_size;
+    }
+    if let Some(max_queue_size) = args.max_queue_size {
+        config.queue_config.max_queue_size = max_queue_size;
+    }
+    if let Some(request_timeout) = args.request_timeout {
+        config.queue_config.request_timeout = Duration::from_secs(request_timeout);
+    }
+    if let Some(worker_threads) = args.worker_threads {
+        config.queue_config.worker_threads = worker_threads;
+    }
+    if let Some(max_sessions) = args.max_sessions {
+        config.session_config.max_sessions = max_sessions;
+    }
+    if let Some(session_timeout) = args.session_timeout {
+        config.session_config.session_timeout = Duration::from_secs(session_timeout);
+    }
+    if args.raw_output {
+        config.post_processors.clear();
+    }
+    if args.prompts_file.is_some() {
+        // Every prompt in flight holds a queue slot and a session
+        config.queue_config.max_queue_size =
+            config.queue_config.max_queue_size.max(args.concurrency);
+        config.session_config.max_sessions =
+            config.session_config.max_sessions.max(args.concurrency);
+    }
+    validate_max_time(args.max_time_ms, config.queue_config.request_timeout)?;
+
+    Ok(config)
+}
+
+/// Check a `--max-time-ms` budget, which must end before the request times out
+pub fn validate_max_time(max_time_ms: Option<u64>, request_timeout: Duration) -> Result<()> {
+    let Some(max_time_ms) = max_time_ms else {
+        return Ok(());
+    };
+    if max_time_ms == 0 {
+        return Err(anyhow::anyhow!(
+            "Max time must be greater than 0 milliseconds"
+        ));
+    }
+    if Duration::from_millis(max_time_ms) > request_timeout {
+        return Err(anyhow::anyhow!(
+            "Max time of {}ms exceeds the request timeout of {}s\n💡 Lower --max-time-ms or raise the request timeout",
+            max_time_ms,
+            request_timeout.as_secs_f64()
+        ));
+    }
+    Ok(())
+}
+
+/// Print the per-attempt download history when model loading failed after retries
+fn print_retry_report(error: &AgentError) {
+    if let AgentError::Model(model_error) = error.root() {
+        if let Some(report) = model_error.retry_report() {
+            eprintln!("{}", report);
+        }
+    }
+}
+
+/// Log details about the loaded model reported by the health check
+fn log_model_info(model: Option<&ModelInfo>) {
+    let Some(model) = model else {
+        warn!("Health check did not report a loaded model");
+        return;
+    };
+
+    info!("Model Info:");
+    for line in model_info_lines(model) {
+        info!("  {}", line);
+    }
+}
+
+/// Details about the loaded model, one per line
+fn model_info_lines(model: &ModelInfo) -> Vec<String> {
+    let mut lines = vec![
+        format!("Source: {} ({})", model.location, model.source_type),
+        format!("File: {}", model.filename),
+        format!("Path: {}", model.path.display()),
+        format!(
+            "Size: {:.1} MB",
+            model.size_bytes as f64 / (1024.0 * 1024.0)
+        ),
+    ];
+    if let Some(quantization) = &model.quantization {
+        lines.push(format!("Quantization: {}", quantization));
+    }
+    if let Some(download) = model.download_duration {
+        lines.push(format!("Download time: {:.2}s", download.as_secs_f32()));
+    }
+    lines.push(format!(
+        "Load time: {:.2}s (cache hit: {})",
+        model.load_duration.as_secs_f32(),
+        model.cache_hit
+    ));
+    lines
+}
+
+/// The `--stats` report: the loaded model, as the health check reports it, and the
+/// statistics of the generation
+fn stats_report(model: Option<&ModelInfo>, generation_stats: &[String]) -> String {
+    let model_lines = match model {
+        Some(model) => model_info_lines(model),
+        None => vec!["No loaded model reported".to_string()],
+    };
+    let indented = |lines: &[String]| {
+        lines
+            .iter()
+            .map(|line| format!("\n  {}", line))
+            .collect::<String>()
+    };
+    format!(
+        "Model Info:{}\nGeneration Statistics:{}",
+        indented(&model_lines),
+        indented(generation_stats)
+    )
+}
+
+/// Number of leading embedding values printed by `--embed-prompt`
+const EMBED_PREVIEW_VALUES: usize = 8;
+
+/// Embed the prompt with the loaded model and print a short summary for smoke testing
+async fn run_embed_prompt<W: Write>(
+    agent: &AgentServer,
+    prompt: &str,
+    out: &mut W,
+) -> Result<String> {
+    let embeddings = agent
+        .embed(&[prompt.to_string()], true)
+        .await
+        .map_err(|e| anyhow::anyhow!("Embedding failed: {}", e))?;
+    let embedding = embeddings
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("Embedding failed: no vector returned"))?;
+
+    let preview = embedding
+        .iter()
+        .take(EMBED_PREVIEW_VALUES)
+        .map(|value| format!("{:.6}", value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let summary = format!(
+        "Embedding length: {}\nFirst values: [{}]",
+        embedding.len(),
+        preview
+    );
+    writeln!(out, "{}", summary)?;
+
+    Ok(summary)
+}
+
+/// Print the rendered prompt of a session, its token count and the context size
+/// for `--dry-run`. The counts go to stderr in quiet mode.
+async fn write_dry_run<W: Write>(
+    agent: &AgentServer,
+    session_id: &SessionId,
+    args: &GenerateArgs,
+    out: &mut W,
+) -> Result<String, CliError> {
+    let rendered = agent.render_prompt(session_id).await?;
+
+    match &args.dry_run_output {
+        Some(path) => std::fs::write(path, &rendered.prompt).map_err(|e| {
+            CliError::Runtime(anyhow::anyhow!(
+                "Failed to write prompt to {}: {}",
+                path.display(),
+                e
+            ))
+        })?,
+        None => writeln!(out, "{}", rendered.prompt).map_err(write_failed)?,
+    }
+
+    let mut report = format!(
+        "Prompt tokens: {}\nContext size: {} tokens (batch size {})",
+        rendered.prompt_tokens, rendered.context_size, rendered.batch_size
+    );
+    if rendered.exceeds_batch_size() {
+        report
+            .push_str("\nWarning: the prompt is longer than the batch size; generation would fail");
+    }
+    if args.quiet {
+        eprintln!("{}", report);
     } else {
-        // Validate HuggingFace repo format
-        if !args.model.contains('/') {
-            return Err(anyhow::anyhow!(
-                "HuggingFace model repo must be in format 'organization/model': {}. Example: microsoft/DialoGPT-medium",
-                args.model
-            ));
+        writeln!(out, "{}", report).map_err(write_failed)?;
+    }
+
+    Ok(rendered.prompt)
+}
+
+fn write_failed(error: std::io::Error) -> CliError {
+    CliError::Runtime(anyhow::anyhow!("Failed to write response: {}", error))
+}
+
+/// Table of the tokens of `--debug-tokens`, one row per token with its text escaped
+fn format_token_table(tokens: &[GeneratedToken]) -> String {
+    let rows: Vec<[String; 4]> = tokens
+        .iter()
+        .enumerate()
+        .map(|(index, token)| {
+            [
+                index.to_string(),
+                token.id.to_string(),
+                format!("{:?}", token.piece),
+                token
+                    .logprob
+                    .map_or_else(|| "-".to_string(), |logprob| format!("{:.4}", logprob)),
+            ]
+        })
+        .collect();
+    let header = ["#", "id", "piece", "logprob"].map(str::to_string);
+
+    let mut widths = header.clone().map(|cell| cell.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
         }
-        if args.model.split('/').count() != 2 {
-            return Err(anyhow::anyhow!(
-                "Invalid HuggingFace repo format: {}. Must be exactly 'organization/model'",
-                args.model
-            ));
+    }
+    std::iter::once(&header)
+        .chain(&rows)
+        .map(|[index, id, piece, logprob]| {
+            format!(
+                "{:>w0$}  {:>w1$}  {:<w2$}  {:>w3$}",
+                index,
+                id,
+                piece,
+                logprob,
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3]
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Statistics of a finished generation, one per line
+fn generation_stats_lines(args: &GenerateArgs, summary: &GenerationResponse) -> Vec<String> {
+    let token_count = summary.tokens_generated;
+    let generation_time = summary.generation_time;
+    let finish_reason = &summary.finish_reason;
+
+    let mut lines = vec![
+        format!("Prompt tokens: {}", summary.prompt_tokens),
+        format!("Tokens generated: {}", token_count),
+        format!("Time taken: {:.2}s", generation_time.as_secs_f32()),
+        format!(
+            "Prompt time: {:.2}s, decode time: {:.2}s",
+            summary.prompt_time.as_secs_f32(),
+            summary.decode_time.as_secs_f32()
+        ),
+    ];
+    if let Some(first_token) = summary.time_to_first_token {
+        lines.push(format!(
+            "Time to first token: {:.2}s",
+            first_token.as_secs_f32()
+        ));
+    }
+    if token_count > 0 {
+        lines.push(format!(
+            "Tokens per second: {:.1}",
+            token_count as f32 / generation_time.as_secs_f32()
+        ));
+    }
+    lines.push(format!("Finish reason: {:?}", finish_reason));
+    if matches!(finish_reason, FinishReason::Stopped(reason) if reason == "Stop token detected") {
+        if let Some(stop) = fired_stop_sequence(&summary.generated_text, &args.stop) {
+            lines.push(format!("Stop sequence: {:?}", stop));
         }
     }
+    lines
+}
+
+/// Log generation statistics and warnings in debug mode, print the model and the
+/// statistics for `--stats` and the generated tokens for `--debug-tokens`
+async fn report_generation(agent: &AgentServer, args: &GenerateArgs, summary: &GenerationResponse) {
+    if args.model_args.debug_tokens {
+        eprintln!("{}", format_token_table(&summary.generated_tokens));
+    }
+    let generation_stats = generation_stats_lines(args, summary);
+    if args.stats {
+        let model = match agent.health().await {
+            Ok(health) => health.model,
+            Err(e) => {
+                warn!("Health check failed: {}", e);
+                None
+            }
+        };
+        eprintln!("{}", stats_report(model.as_ref(), &generation_stats));
+    }
+    if !args.model_args.debug {
+        return;
+    }
+    let response = summary.generated_text.as_str();
+    let token_count = summary.tokens_generated;
+
+    info!("Generation Statistics:");
+    for line in &generation_stats {
+        info!("  {}", line);
+    }
+
+    // Handle warnings based on finish reason or token count
+    if args.limit > 0 && token_count >= args.limit {
+        warn!(
+            "Response may have been truncated due to token limit ({})",
+            args.limit
+        );
+    }
+
+    // Check if the response looks like it contains tool calls
+    if response.contains("```")
+        && (response.contains("function_call") || response.contains("tool_call"))
+    {
+        warn!("Model wants to call tools, but basic CLI doesn't support tool execution yet.");
+    }
+}
+
+/// Human-readable description of a model source for log output
+pub(crate) fn describe_model_source(source: &ModelSource) -> String {
+    match source {
+        ModelSource::HuggingFace { repo, .. } => repo.clone(),
+        ModelSource::Local { folder, .. } => folder.display().to_string(),
+        ModelSource::Url { url, .. } => url.clone(),
+    }
+}
+
+/// Without `--prompt`, the transcript's last turn is the one answered, so it must be
+/// the user's
+fn check_transcript_prompt(messages: &[Message], has_prompt: bool) -> Result<()> {
+    match messages.last() {
+        Some(last) if !has_prompt && last.role != MessageRole::User => Err(anyhow::anyhow!(
+            "The transcript's last turn is from the {}; end it with a User turn or pass --prompt",
+            last.role.as_str()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Turn `--prompt-arg key=value` pairs into the JSON object passed to the MCP prompt
+fn parse_prompt_args(prompt_args: &[String]) -> Result<Option<serde_json::Value>> {
+    if prompt_args.is_empty() {
+        return Ok(None);
+    }
 
-    // Validate token limit
-    if args.limit == 0 {
-        return Err(anyhow::anyhow!("Token limit must be greater than 0"));
+    let mut arguments = serde_json::Map::new();
+    for arg in prompt_args {
+        match arg.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                arguments.insert(
+                    key.trim().to_string(),
+                    serde_json::Value::String(value.to_string()),
+                );
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Invalid --prompt-arg '{}'. Expected key=value",
+                    arg
+                ));
+            }
+        }
     }
+
+    Ok(Some(serde_json::Value::Object(arguments)))
+}
+
+pub fn validate_generate_args(args: &GenerateArgs) -> Result<()> {
+    // Validate model path; without --model the source comes from the config file
+    args.model_args.validate()?;
+
+    // Validate token limit; 0 only decodes the prompt
     if args.limit > 8192 {
         return Err(anyhow::anyhow!(
             "Token limit is too large: {}. Maximum recommended limit is 8192 tokens.",
@@ -168,44 +899,69 @@ pub fn validate_generate_args(args: &GenerateArgs) -> Result<()> {
     }
 
     // Validate prompt is not empty
-    if args.prompt.trim().is_empty() {
+    if args.prompt.as_deref().is_some_and(|p| p.trim().is_empty()) {
         return Err(anyhow::anyhow!("Prompt cannot be empty"));
     }
-
-    // Validate batch size
-    if args.batch_size == 0 {
-        return Err(anyhow::anyhow!("Batch size must be greater than 0"));
-    }
-    if args.batch_size > 2048 {
+    if let Some(path) = &args.transcript {
+        let messages = load_transcript(path)?;
+        check_transcript_prompt(&messages, args.prompt.is_some())?;
+    } else if args.prompt.is_none() && args.prompts_file.is_none() {
         return Err(anyhow::anyhow!(
-            "Batch size is too large: {}. Maximum recommended is 2048",
-            args.batch_size
+            "A prompt is required: pass --prompt, --transcript or --prompts-file"
         ));
     }
+    if args.concurrency == 0 {
+        return Err(anyhow::anyhow!("Concurrency must be greater than 0"));
+    }
+    if args.embed_prompt && args.prompt.is_none() {
+        return Err(anyhow::anyhow!("--embed-prompt requires --prompt"));
+    }
+
+    // Validate batch size
+    if let Some(batch_size) = args.batch_size {
+        if batch_size == 0 {
+            return Err(anyhow::anyhow!("Batch size must be greater than 0"));
+        }
+        if batch_size > 2048 {
+            return Err(anyhow::anyhow!(
+                "Batch size is too large: {}. Maximum recommended is 2048",
+                batch_size
+            ));
+        }
+    }
 
     // Validate queue configuration
-    if args.max_queue_size == 0 {
+    if args.max_queue_size == Some(0) {
         return Err(anyhow::anyhow!("Max queue size must be greater than 0"));
     }
-    if args.request_timeout == 0 {
+    if args.request_timeout == Some(0) {
         return Err(anyhow::anyhow!(
             "Request timeout must be greater than 0 seconds"
         ));
     }
-    if args.worker_threads == 0 {
+    if args.worker_threads == Some(0) {
         return Err(anyhow::anyhow!("Worker threads must be greater than 0"));
     }
 
     // Validate session configuration
-    if args.max_sessions == 0 {
+    if args.max_sessions == Some(0) {
         return Err(anyhow::anyhow!("Max sessions must be greater than 0"));
     }
-    if args.session_timeout == 0 {
+    if args.session_timeout == Some(0) {
         return Err(anyhow::anyhow!(
             "Session timeout must be greater than 0 seconds"
         ));
     }
 
+    // Validate prompt template arguments
+    if !args.prompt_args.is_empty() && args.prompt_template.is_none() {
+        return Err(anyhow::anyhow!("--prompt-arg requires --prompt-template"));
+    }
+    parse_prompt_args(&args.prompt_args)?;
+
+    // Validate stop sequences
+    validate_stop_sequences(&args.stop)?;
+
     // Validate generation parameters
     if args.temperature < 0.0 || args.temperature > 2.0 {
         return Err(anyhow::anyhow!(
@@ -223,75 +979,125 @@ pub fn validate_generate_args(args: &GenerateArgs) -> Result<()> {
     Ok(())
 }
 
-pub async fn run_generate(args: GenerateArgs) -> Result<String> {
-    let debug_mode = args.debug;
-    // Validate arguments
-    validate_generate_args(&args)?;
+fn validate_model_arg(model: &str, filename: Option<&str>, allow_http: bool) -> Result<()> {
+    if model.is_empty() {
+        return Err(anyhow::anyhow!("Model path cannot be empty"));
+    }
 
-    // Create model configuration
-    let model_config = if args.model.starts_with('/')
-        || args.model.starts_with("./")
-        || args.model.starts_with("../")
-        || args.model.contains('\\')
-    {
-        // Local path
-        ModelConfig {
-            source: ModelSource::Local {
-                folder: PathBuf::from(&args.model),
-                filename: args.filename,
-            },
-            batch_size: args.batch_size,
-            use_hf_params: false,
-            retry_config: RetryConfig::default(),
-            debug: args.debug,
+    if is_model_url(model) {
+        let source = ModelSource::Url {
+            url: model.to_string(),
+            filename: filename.map(str::to_string),
+            sha256: None,
+        };
+        return source.validate_with_options(0, allow_http).map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid model URL: {}. Use an https:// link, or pass --allow-http for plain http",
+                e
+            )
+        });
+    }
+
+    // Check if local path exists (starts with / or ./ or contains \)
+    if is_local_model_path(model) {
+        let path = PathBuf::from(model);
+        if !path.exists() {
+            return Err(anyhow::anyhow!(
+                "{}. Please check that the path is correct.",
+                describe_missing_local_path(&path)
+            ));
+        }
+        // Accept either a folder containing models or a .gguf file directly
+        if path.is_file() && !has_gguf_extension(&path) {
+            return Err(anyhow::anyhow!(
+                "Local model file must have a .gguf extension: {}. Please provide a .gguf file or a folder containing model files.",
+                model
+            ));
         }
     } else {
-        // Assume HuggingFace repo
-        ModelConfig {
-            source: ModelSource::HuggingFace {
-                repo: args.model.clone(),
-                filename: args.filename,
-            },
-            batch_size: args.batch_size,
-            use_hf_params: true,
-            retry_config: RetryConfig::default(),
-            debug: args.debug,
+        // Validate HuggingFace repo format
+        if !model.contains('/') {
+            return Err(anyhow::anyhow!(
+                "HuggingFace model repo must be in format 'organization/model': {}. Example: microsoft/DialoGPT-medium",
+                model
+            ));
         }
-    };
+        if model.split('/').count() != 2 {
+            return Err(anyhow::anyhow!(
+                "Invalid HuggingFace repo format: {}. Must be exactly 'organization/model'",
+                model
+            ));
+        }
+    }
 
-    // Create agent configuration
-    let agent_config = AgentConfig {
-        model: model_config,
-        queue_config: QueueConfig {
-            max_queue_size: args.max_queue_size,
-            request_timeout: Duration::from_secs(args.request_timeout),
-            worker_threads: args.worker_threads,
-        },
-        session_config: SessionConfig {
-            max_sessions: args.max_sessions,
-            session_timeout: Duration::from_secs(args.session_timeout),
-        },
-        mcp_servers: vec![], // No MCP servers for basic CLI
-    };
+    Ok(())
+}
+
+/// Print the auto-detection explanation for `config` to stderr
+async fn print_model_choice(config: &ModelConfig) {
+    match explain_model_choice(config).await {
+        Ok(Some(resolution)) => eprintln!("{}", resolution),
+        Ok(None) => eprintln!("The model file is named, so nothing is auto-detected"),
+        Err(e) => warn!("Could not explain the model file choice: {}", e),
+    }
+}
+
+pub async fn run_generate(args: GenerateArgs) -> Result<String, CliError> {
+    run_generate_with_writer(args, &mut std::io::stdout()).await
+}
+
+/// Run a generation, writing the response to `out` instead of stdout.
+///
+/// Log output is not affected; with `--quiet` the caller routes it to stderr.
+pub async fn run_generate_with_writer<W: Write>(
+    args: GenerateArgs,
+    out: &mut W,
+) -> Result<String, CliError> {
+    let debug_mode = args.model_args.debug;
+    // Decorative output is dropped entirely in quiet mode
+    let decorate = debug_mode && !args.quiet;
+    // Validate arguments
+    validate_generate_args(&args).map_err(CliError::Validation)?;
+
+    // Create agent configuration from defaults, config file and CLI flags
+    let agent_config = build_agent_config(&args).map_err(CliError::Validation)?;
 
     if debug_mode {
         info!("Initializing AgentServer (this may take a while for model loading)...");
-        info!("Loading model from {}...", args.model);
+        info!(
+            "Loading model from {}...",
+            describe_model_source(&agent_config.model.source)
+        );
+    }
+
+    if args.explain_model_choice {
+        print_model_choice(&agent_config.model).await;
     }
 
     // Initialize agent server with progress indication
-    let agent = match AgentServer::initialize(agent_config).await {
+    let agent = match initialize_interruptible(agent_config).await {
         Ok(agent) => {
-            if debug_mode {
+            if decorate {
                 info!("✓ Model loaded successfully!");
+                if let Some(metadata) = agent.model_metadata().await {
+                    info!("  {}", metadata.summary());
+                }
             }
             agent
         }
         Err(e) => {
-            return Err(anyhow::anyhow!("Failed to initialize agent: {}", e));
+            print_retry_report(&e);
+            return Err(CliError::from_initialization(e));
         }
     };
 
+    if debug_mode {
+        match agent.health().await {
+            Ok(health) => log_model_info(health.model.as_ref()),
+            Err(e) => warn!("Health check failed: {}", e),
+        }
+    }
+
     // Set up graceful shutdown handler using channels
     let (shutdown_tx, mut shutdown_rx) = tokio::sync::mpsc::channel::<()>(1);
 
@@ -325,6 +1131,69 @@ pub async fn run_generate(args: GenerateArgs) -> Result<String> {
     }
 
     let agent = agent_option.take().unwrap();
+    run_generate_with_agent(&agent, &args, out).await
+}
+
+/// Initialize the agent, abandoning the model download on Ctrl-C. Partial downloads
+/// are removed and the error converts to [`CliError::Interrupted`].
+pub(crate) async fn initialize_interruptible(
+    config: AgentConfig,
+) -> Result<AgentServer, AgentError> {
+    let cancel = CancellationToken::new();
+    let interrupt = tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            if signal::ctrl_c().await.is_ok() {
+                warn!("Interrupt signal received, cancelling model load...");
+                cancel.cancel();
+            }
+        }
+    });
+    let initialized = AgentServer::initialize_cancellable(config, cancel).await;
+    interrupt.abort();
+    initialized
+}
+
+/// The generation request for a session, with the sampling and stopping flags applied
+pub(crate) fn generation_request(args: &GenerateArgs, session_id: SessionId) -> GenerationRequest {
+    let request = GenerationRequest::new(session_id)
+        .with_max_tokens(args.limit)
+        .with_temperature(args.temperature)
+        .with_top_p(args.top_p)
+        .with_stop_tokens(args.stop.clone())
+        .with_stop_token_ids(args.stop_token_ids.clone())
+        .with_debug_tokens(args.model_args.debug_tokens)
+        .with_default_stopping();
+    match args.max_time_ms {
+        Some(max_time_ms) => request.with_max_duration(Duration::from_millis(max_time_ms)),
+        None => request,
+    }
+}
+
+/// Run a generation on an initialized agent, writing the response to `out`.
+///
+/// Creates the session, applies `--prompt-template` and adds the prompt, then
+/// generates, or with `--dry-run` only renders the prompt. With `--prompts-file`, runs
+/// every prompt of the file instead and returns the summary line.
+pub async fn run_generate_with_agent<W: Write>(
+    agent: &AgentServer,
+    args: &GenerateArgs,
+    out: &mut W,
+) -> Result<String, CliError> {
+    let debug_mode = args.model_args.debug;
+    let decorate = debug_mode && !args.quiet;
+
+    if let (true, Some(prompt)) = (args.embed_prompt, &args.prompt) {
+        return Ok(run_embed_prompt(agent, prompt, out).await?);
+    }
+
+    if let Some(path) = &args.prompts_file {
+        let summary = run_prompts_file(agent, args, path, out).await?;
+        if !args.quiet {
+            eprintln!("{}", summary);
+        }
+        return Ok(summary.to_string());
+    }
 
     // Create a session
     let mut session = agent.create_session().await?;
@@ -342,105 +1211,374 @@ pub async fn run_generate(args: GenerateArgs) -> Result<String> {
         }
     }
 
+    if let Some(prompt_template) = &args.prompt_template {
+        let arguments = parse_prompt_args(&args.prompt_args).map_err(CliError::Validation)?;
+        // Prompt templates are served by MCP servers
+        agent
+            .apply_prompt(&session.id, prompt_template, arguments)
+            .await
+            .map_err(|e| CliError::Mcp(e.into()))?;
+        if debug_mode {
+            info!("Applied prompt template '{}'", prompt_template);
+        }
+    }
+
+    if let Some(path) = &args.transcript {
+        let messages = load_transcript(path).map_err(CliError::Validation)?;
+        check_transcript_prompt(&messages, args.prompt.is_some()).map_err(CliError::Validation)?;
+        if debug_mode {
+            info!("Loaded {} messages from {}", messages.len(), path.display());
+        }
+        for message in messages {
+            agent.add_message(&session.id, message).await?;
+        }
+    }
+
     // Add the user message
-    let message = Message {
-        role: MessageRole::User,
-        content: args.prompt.clone(),
-        tool_call_id: None,
-        tool_name: None,
-        timestamp: std::time::SystemTime::now(),
-    };
+    if let Some(prompt) = &args.prompt {
+        let message = Message {
+            role: MessageRole::User,
+            content: prompt.clone(),
+            tool_call_id: None,
+            tool_name: None,
+            timestamp: std::time::SystemTime::now(),
+            attachments: Vec::new(),
+        };
+
+        // Add message to session (this also updates the session timestamp)
+        agent.add_message(&session.id, message).await?;
+    }
 
-    // Add message to session (this also updates the session timestamp)
-    agent.add_message(&session.id, message).await?;
+    if args.dry_run {
+        return write_dry_run(agent, &session.id, args, out).await;
+    }
 
     // Create generation request
-    let request = GenerationRequest::new(session.id)
-        .with_max_tokens(args.limit)
-        .with_temperature(args.temperature)
-        .with_top_p(args.top_p)
-        .with_default_stopping();
+    let request = generation_request(args, session.id);
 
-    if debug_mode {
-        info!("Generating response (streaming)...");
+    if decorate {
+        if args.no_stream {
+            info!("Generating response...");
+        } else {
+            info!("Generating response (streaming)...");
+        }
         info!("{}", "=".repeat(SEPARATOR_WIDTH));
     }
     let start_time = std::time::Instant::now();
 
+    if args.no_stream {
+        let response = agent.generate(request).await.map_err(|e| {
+            error!("Generation failed: {}", e);
+            CliError::Runtime(anyhow::anyhow!("Generation failed: {}", e))
+        })?;
+
+        let mut writer = ResponseWriter::new(out, StreamFlush::Token);
+        writer
+            .write(&response.generated_text)
+            .map_err(write_failed)?;
+        writer.finish().map_err(write_failed)?;
+
+        report_generation(agent, args, &response).await;
+        return Ok(response.generated_text);
+    }
+
     // Use streaming generation for real-time token output
     match agent.generate_stream(request).await {
         Ok(mut stream) => {
-            let mut token_count = 0;
+            let mut writer = ResponseWriter::new(out, args.stream_flush);
+            let mut ticker = args.stream_flush.interval().map(tokio::time::interval);
             let mut full_response = String::new();
-            let mut finish_reason =
-                FinishReason::Stopped("End of sequence token detected".to_string()); // Default finish reason
+            let mut summary = None;
+            let mut stream_error = None;
+
+            // Process each chunk as it arrives, flushing coalesced text on the timer if any
+            loop {
+                let next = match ticker.as_mut() {
+                    Some(ticker) => tokio::select! {
+                        next = stream.next() => next,
+                        _ = ticker.tick() => {
+                            writer.tick().map_err(write_failed)?;
+                            continue;
+                        }
+                    },
+                    None => stream.next().await,
+                };
+                let Some(chunk_result) = next else {
+                    break;
+                };
 
-            // Process each chunk as it arrives
-            while let Some(chunk_result) = stream.next().await {
                 match chunk_result {
                     Ok(chunk) => {
-                        // Print the new text immediately (real-time streaming)
-                        print!("{}", chunk.text);
-                        std::io::stdout().flush().unwrap_or_else(|e| {
-                            warn!("Failed to flush stdout: {}", e);
-                        });
+                        // Tool calls run between passes show up as status lines
+                        if let Some(line) = tool_status_line(&chunk.event) {
+                            if !args.quiet {
+                                writer.tick().map_err(write_failed)?;
+                                print_status_line(&line);
+                            }
+                            continue;
+                        }
+
+                        // Print the new text as the flush mode allows
+                        writer.write(&chunk.text).map_err(write_failed)?;
 
-                        // Accumulate for final statistics
                         full_response.push_str(&chunk.text);
-                        token_count += chunk.token_count;
 
-                        // Check if generation is complete
+                        // The final chunk carries the statistics of the whole generation
                         if chunk.is_complete {
+                            summary = chunk.response;
                             break;
                         }
                     }
                     Err(e) => {
                         error!("Streaming error: {}", e);
-                        finish_reason = FinishReason::Stopped(format!("Error: {}", e));
+                        stream_error = Some(e.to_string());
                         break;
                     }
                 }
             }
 
-            let generation_time = start_time.elapsed();
+            writer.finish().map_err(write_failed)?;
 
-            // Display generation statistics only in debug mode
-            if debug_mode {
-                info!("Generation Statistics:");
-                info!("  Tokens generated: {}", token_count);
-                info!("  Time taken: {:.2}s", generation_time.as_secs_f32());
-                if token_count > 0 {
-                    info!(
-                        "  Tokens per second: {:.1}",
-                        token_count as f32 / generation_time.as_secs_f32()
-                    );
-                }
-                info!("  Finish reason: {:?}", finish_reason);
-            }
+            let summary = summary.unwrap_or_else(|| GenerationResponse {
+                generated_text: full_response.clone(),
+                tokens_generated: 0,
+                generation_time: start_time.elapsed(),
+                finish_reason: FinishReason::Stopped(format!(
+                    "Error: {}",
+                    stream_error.unwrap_or_else(|| "stream ended early".to_string())
+                )),
+                prompt_tokens: 0,
+                prompt_time: Duration::ZERO,
+                decode_time: Duration::ZERO,
+                time_to_first_token: None,
+                candidates: Vec::new(),
+                generated_tokens: Vec::new(),
+            });
 
-            // Handle warnings based on finish reason or token count
-            if token_count >= args.limit && debug_mode {
-                warn!(
-                    "Response may have been truncated due to token limit ({})",
-                    args.limit
-                );
-            }
-
-            // Check if the response looks like it contains tool calls
-            if full_response.contains("```")
-                && (full_response.contains("function_call") || full_response.contains("tool_call"))
-                && debug_mode
-            {
-                warn!(
-                    "Model wants to call tools, but basic CLI doesn't support tool execution yet."
-                );
-            }
+            report_generation(agent, args, &summary).await;
 
             Ok(full_response)
         }
         Err(e) => {
             error!("Generation failed: {}", e);
-            Err(anyhow::anyhow!("Generation failed: {}", e))
+            Err(CliError::Runtime(anyhow::anyhow!(
+                "Generation failed: {}",
+                e
+            )))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{validate_bench_args, BenchArgs};
+    use crate::serve::{validate_serve_args, ServeArgs};
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli<T: clap::Args> {
+        #[command(flatten)]
+        args: T,
+    }
+
+    fn parse<T: clap::Args>(flags: &[String]) -> T {
+        let argv = std::iter::once("llama-cli".to_string()).chain(flags.iter().cloned());
+        Cli::<T>::try_parse_from(argv).unwrap().args
+    }
+
+    /// Validation result and model configuration of every command for the same flags:
+    /// `(generate, serve, bench)`
+    fn frontends(flags: &[String]) -> [Result<String, String>; 3] {
+        let with_prompt: Vec<String> = flags
+            .iter()
+            .cloned()
+            .chain(["--prompt".to_string(), "Hi".to_string()])
+            .collect();
+
+        let generate: GenerateArgs = parse(&with_prompt);
+        let serve: ServeArgs = parse(flags);
+        let bench: BenchArgs = parse(&with_prompt);
+        let model = |args: &ModelArgs| {
+            args.agent_config()
+                .map(|config| format!("{:?}", config.model))
+                .map_err(|e| e.to_string())
+        };
+        [
+            validate_generate_args(&generate)
+                .map_err(|e| e.to_string())
+                .and_then(|()| build_agent_config(&generate).map_err(|e| e.to_string()))
+                .map(|config| format!("{:?}", config.model)),
+            validate_serve_args(&serve)
+                .map_err(|e| e.to_string())
+                .and_then(|()| model(&serve.model_args)),
+            validate_bench_args(&bench)
+                .map_err(|e| e.to_string())
+                .and_then(|()| model(&bench.model_args)),
+        ]
+    }
+
+    fn flags(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_frontends_agree_on_model_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("model-Q4_K_M.gguf");
+        std::fs::write(&file, b"GGUF").unwrap();
+        let text_file = dir.path().join("notes.txt");
+        std::fs::write(&text_file, b"notes").unwrap();
+        let dir_arg = dir.path().display().to_string();
+        let file_arg = file.display().to_string();
+        let text_arg = text_file.display().to_string();
+
+        // Flags, and whether they are accepted
+        let table: Vec<(Vec<String>, bool)> = vec![
+            (flags(&[]), false),
+            (flags(&["--model", "unsloth/Qwen3-0.6B-GGUF"]), true),
+            (
+                flags(&[
+                    "--model",
+                    "unsloth/Qwen3-0.6B-GGUF",
+                    "--filename",
+                    "Qwen3-0.6B-Q4_K_M.gguf",
+                ]),
+                true,
+            ),
+            (flags(&["--model", "not-a-repo"]), false),
+            (flags(&["--model", "https://example.com/model.gguf"]), true),
+            (flags(&["--model", "http://example.com/model.gguf"]), false),
+            (
+                flags(&["--model", "http://example.com/model.gguf", "--allow-http"]),
+                true,
+            ),
+            (flags(&["--model", &dir_arg]), true),
+            (flags(&["--model", &file_arg]), true),
+            (flags(&["--model", &text_arg]), false),
+            (flags(&["--model", "/nonexistent/models/folder"]), false),
+            (
+                flags(&[
+                    "--model",
+                    &dir_arg,
+                    "--debug",
+                    "--force-load",
+                    "--refresh-model-metadata",
+                    "--cache-dir",
+                    "/tmp/models",
+                    "--local-search-depth",
+                    "2",
+                    "--chat-template",
+                    "llama3",
+                ]),
+                true,
+            ),
+        ];
+
+        for (flags, accepted) in table {
+            let [generate, serve, bench] = frontends(&flags);
+            assert_eq!(generate.is_ok(), accepted, "{:?}: {:?}", flags, generate);
+            assert_eq!(serve, generate, "serve differs for {:?}", flags);
+            assert_eq!(bench, generate, "bench differs for {:?}", flags);
+        }
+    }
+
+    #[test]
+    fn test_model_flags_build_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("model-Q4_K_M.gguf");
+        std::fs::write(&file, b"GGUF").unwrap();
+        let config = |model: &str, filename: Option<&str>| {
+            ModelArgs {
+                model: Some(model.to_string()),
+                filename: filename.map(String::from),
+                ..ModelArgs::default()
+            }
+            .agent_config()
+            .unwrap()
+            .model
+        };
+
+        let hf = config("unsloth/Qwen3-0.6B-GGUF", Some("Qwen3-0.6B-Q4_K_M.gguf"));
+        assert_eq!(
+            hf.source,
+            ModelSource::HuggingFace {
+                repo: "unsloth/Qwen3-0.6B-GGUF".to_string(),
+                filename: Some("Qwen3-0.6B-Q4_K_M.gguf".to_string()),
+            }
+        );
+        assert!(hf.use_hf_params);
+        assert_eq!(hf.batch_size, DEFAULT_BATCH_SIZE);
+
+        let folder = config(&dir.path().display().to_string(), None);
+        assert_eq!(folder.source, ModelSource::local(dir.path(), None));
+        assert!(!folder.use_hf_params);
+
+        // A file path names its folder and file
+        let local_file = config(&file.display().to_string(), Some("ignored.gguf"));
+        assert_eq!(
+            local_file.source,
+            ModelSource::Local {
+                folder: dir.path().to_path_buf(),
+                filename: Some("model-Q4_K_M.gguf".to_string()),
+            }
+        );
+
+        let url = config("https://example.com/model.gguf", None);
+        assert!(matches!(url.source, ModelSource::Url { .. }));
+        assert!(!url.use_hf_params);
+    }
+
+    #[test]
+    fn test_format_token_table_escapes_pieces() {
+        let token = |id, piece: &str, logprob| GeneratedToken {
+            id,
+            piece: piece.to_string(),
+            logprob,
+        };
+        let table = format_token_table(&[token(9906, "Hello", None), token(13, "\n", Some(-0.25))]);
+        assert_eq!(
+            table.lines().collect::<Vec<_>>(),
+            vec![
+                "#    id  piece    logprob",
+                "0  9906  \"Hello\"        -",
+                "1    13  \"\\n\"     -0.2500",
+            ]
+        );
+    }
+    #[test]
+    fn test_stats_report_includes_model_info() {
+        let model = ModelInfo {
+            source_type: "huggingface".to_string(),
+            location: "org/model".to_string(),
+            filename: "model-Q4_K_M.gguf".to_string(),
+            path: PathBuf::from("/cache/model-Q4_K_M.gguf"),
+            revision: Some("main".to_string()),
+            size_bytes: 3 * 1024 * 1024,
+            loaded_at: std::time::SystemTime::now(),
+            download_duration: None,
+            load_duration: Duration::from_millis(1500),
+            cache_hit: true,
+            quantization: Some("Q4_K_M".to_string()),
+        };
+        let stats = vec!["Tokens generated: 3".to_string()];
+
+        assert_eq!(
+            stats_report(Some(&model), &stats)
+                .lines()
+                .collect::<Vec<_>>(),
+            vec![
+                "Model Info:",
+                "  Source: org/model (huggingface)",
+                "  File: model-Q4_K_M.gguf",
+                "  Path: /cache/model-Q4_K_M.gguf",
+                "  Size: 3.0 MB",
+                "  Quantization: Q4_K_M",
+                "  Load time: 1.50s (cache hit: true)",
+                "Generation Statistics:",
+                "  Tokens generated: 3",
+            ]
+        );
+        assert!(stats_report(None, &stats).contains("  No loaded model reported\n"));
+    }
+}
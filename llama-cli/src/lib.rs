@@ -1,6 +1,16 @@
+pub mod batch;
+pub mod bench;
+pub mod bench_stats;
+pub mod doctor;
 pub mod embed;
+pub mod error;
 pub mod generate;
+pub mod manifest;
 pub mod parquet_writer;
+pub mod serve;
+pub mod stop_sequences;
+pub mod stream_output;
+pub mod transcript;
 
 #[cfg(test)]
 mod test_embedding_dimensions;
@@ -8,6 +18,17 @@ mod test_embedding_dimensions;
 #[cfg(test)]
 mod test_parquet_compatibility;
 
-pub use embed::{run_embed, validate_embed_args, EmbedArgs};
-pub use generate::{run_generate, validate_generate_args, GenerateArgs};
+pub use batch::{parse_prompts, BatchPrompt, BatchResult, BatchSummary};
+pub use bench::{run_bench, validate_bench_args, BenchArgs, BenchOutputFormat};
+pub use bench_stats::{BenchReport, IterationTiming};
+pub use doctor::{run_doctor, CheckResult, CheckStatus, DoctorArgs};
+pub use embed::{run_embed, validate_embed_args, EmbedArgs, EmbedError};
+pub use error::{CliError, ErrorFormat};
+pub use generate::{
+    build_agent_config, run_generate, run_generate_with_agent, run_generate_with_writer,
+    validate_generate_args, GenerateArgs, ModelArgs,
+};
 pub use parquet_writer::{ParquetError, ParquetWriter};
+pub use serve::{router, run_serve, serve, validate_serve_args, ServeArgs, ServeOptions};
+pub use stream_output::StreamFlush;
+pub use transcript::{load_transcript, parse_transcript, TranscriptError};
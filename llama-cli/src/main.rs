@@ -1,8 +1,11 @@
-use anyhow::Result;
 use clap::{Parser, Subcommand};
 use llama_cli::{
+    bench::{run_bench, BenchArgs},
+    doctor::{run_doctor, DoctorArgs},
     embed::EmbedArgs,
     generate::{run_generate, GenerateArgs},
+    serve::{run_serve, ServeArgs},
+    ErrorFormat,
 };
 use tracing::info;
 
@@ -13,6 +16,10 @@ use tracing::info;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Format of the error printed to stderr on failure
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
 }
 
 #[derive(Subcommand)]
@@ -21,30 +28,53 @@ pub enum Commands {
     Generate(GenerateArgs),
     /// Generate embeddings for input texts
     Embed(EmbedArgs),
+    /// Measure generation throughput and latency
+    Bench(BenchArgs),
+    /// Check the environment for problems before loading a model
+    Doctor(DoctorArgs),
+    /// Serve the model over an OpenAI-compatible HTTP API
+    Serve(ServeArgs),
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     let cli = Cli::parse();
+    let error_format = cli.error_format;
 
     let result = match cli.command {
         Commands::Generate(args) => {
             // Configure logging level based on debug flag
-            if args.debug {
-                tracing_subscriber::fmt()
-                    .with_max_level(tracing::Level::DEBUG)
-                    .init();
+            let level = if args.model_args.debug {
+                tracing::Level::DEBUG
             } else {
+                tracing::Level::WARN
+            };
+            // In quiet mode stdout carries only the response, so logs go to stderr
+            if args.quiet {
                 tracing_subscriber::fmt()
-                    .with_max_level(tracing::Level::WARN)
+                    .with_max_level(level)
+                    .with_writer(std::io::stderr)
                     .init();
+            } else {
+                tracing_subscriber::fmt().with_max_level(level).init();
             }
 
-            if args.debug {
+            if args.model_args.debug {
                 info!("Starting llama-cli generate");
-                info!("Model: {}", args.model);
-                info!("Filename: {:?}", args.filename);
-                info!("Prompt: {}", args.prompt);
+                info!(
+                    "Model: {}",
+                    args.model_args
+                        .model
+                        .as_deref()
+                        .unwrap_or("(from config file)")
+                );
+                info!("Filename: {:?}", args.model_args.filename);
+                if let Some(prompt) = &args.prompt {
+                    info!("Prompt: {}", prompt);
+                }
+                if let Some(transcript) = &args.transcript {
+                    info!("Transcript: {}", transcript.display());
+                }
                 info!("Limit: {}", args.limit);
             }
 
@@ -65,7 +95,10 @@ async fn main() -> Result<()> {
 
             if args.debug {
                 info!("Starting llama-cli embed");
-                info!("Model: {}", args.model);
+                info!(
+                    "Model: {}",
+                    args.model.as_deref().unwrap_or("(from config file)")
+                );
                 info!("Input: {:?}", args.input);
                 info!("Output: {:?}", args.output);
             }
@@ -73,42 +106,53 @@ async fn main() -> Result<()> {
             // Run embed command implementation
             llama_cli::embed::run_embed_command(args).await.map(|_| ())
         }
-    };
+        Commands::Bench(args) => {
+            // Logs go to stderr so a JSON report on stdout stays parseable
+            let level = if args.model_args.debug {
+                tracing::Level::DEBUG
+            } else {
+                tracing::Level::WARN
+            };
+            tracing_subscriber::fmt()
+                .with_max_level(level)
+                .with_writer(std::io::stderr)
+                .init();
 
-    // Handle errors and set appropriate exit codes after all cleanup has occurred
-    match result {
-        Ok(_) => {
-            // Success - normal exit (code 0)
-            Ok(())
+            run_bench(args).await.map(|_| ())
         }
-        Err(e) => {
-            let error_msg = e.to_string();
+        Commands::Doctor(args) => {
+            // The report goes to stdout, so logs go to stderr
+            let level = if args.model_args.debug {
+                tracing::Level::DEBUG
+            } else {
+                tracing::Level::ERROR
+            };
+            tracing_subscriber::fmt()
+                .with_max_level(level)
+                .with_writer(std::io::stderr)
+                .init();
 
-            // Check error type for appropriate exit codes
-            let exit_code = if error_msg.contains("does not exist")
-                || error_msg.contains("Invalid HuggingFace")
-                || error_msg.contains("Token limit")
-                || error_msg.contains("cannot be empty")
-                || error_msg.contains("HuggingFace model repo must be")
-                || error_msg.contains("Invalid")
-                || error_msg.contains("Batch size")
-                || error_msg.contains("Max length")
-            {
-                eprintln!("Error: {}", e);
-                2 // Validation error
-            } else if error_msg.contains("Failed to load model")
-                || error_msg.contains("Failed to initialize agent")
-                || error_msg.contains("Failed to initialize")
-                || error_msg.contains("Model loading failed")
-            {
-                eprintln!("Model Error: {}", e);
-                3 // Model loading error
+            run_doctor(args).await.map(|_| ())
+        }
+        Commands::Serve(args) => {
+            // The listening address is logged at info level, so show it by default
+            let level = if args.model_args.debug {
+                tracing::Level::DEBUG
             } else {
-                eprintln!("Runtime Error: {}", e);
-                1 // General runtime error
+                tracing::Level::INFO
             };
+            tracing_subscriber::fmt()
+                .with_max_level(level)
+                .with_writer(std::io::stderr)
+                .init();
 
-            std::process::exit(exit_code);
+            run_serve(args).await
         }
+    };
+
+    // Report errors and set the exit code after all cleanup has occurred
+    if let Err(e) = result {
+        eprintln!("{}", e.render(error_format));
+        std::process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,202 @@
+use crate::parquet_writer::ParquetCodec;
+use llama_embedding::HashAlgo;
+use llama_loader::ModelSource;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::debug;
+
+/// Error types for run manifests
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid manifest: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("settings differ from the interrupted run: {}", .0.join("; "))]
+    Mismatch(Vec<String>),
+}
+
+/// Settings of an `embed` run that must not change when it is resumed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunSettings {
+    pub input: PathBuf,
+    pub model_source: ModelSource,
+    pub normalize: bool,
+    pub max_length: Option<usize>,
+    pub hash: HashAlgo,
+    pub dedupe: bool,
+    pub compression: ParquetCodec,
+    pub shard_size_rows: Option<usize>,
+    /// Names of the columns written to the output
+    pub columns: Vec<String>,
+}
+
+impl RunSettings {
+    /// Describe each setting that differs from `previous`
+    pub fn differences(&self, previous: &RunSettings) -> Vec<String> {
+        let mut diffs = Vec::new();
+        diff(&mut diffs, "input", &previous.input, &self.input);
+        diff(
+            &mut diffs,
+            "model",
+            &previous.model_source,
+            &self.model_source,
+        );
+        diff(
+            &mut diffs,
+            "normalize",
+            &previous.normalize,
+            &self.normalize,
+        );
+        diff(
+            &mut diffs,
+            "max_length",
+            &previous.max_length,
+            &self.max_length,
+        );
+        diff(&mut diffs, "hash", &previous.hash, &self.hash);
+        diff(&mut diffs, "dedupe", &previous.dedupe, &self.dedupe);
+        diff(
+            &mut diffs,
+            "compression",
+            &previous.compression,
+            &self.compression,
+        );
+        diff(
+            &mut diffs,
+            "shard_size_rows",
+            &previous.shard_size_rows,
+            &self.shard_size_rows,
+        );
+        diff(&mut diffs, "columns", &previous.columns, &self.columns);
+        diffs
+    }
+}
+
+fn diff<T: PartialEq + Debug>(diffs: &mut Vec<String>, name: &str, previous: &T, current: &T) {
+    if previous != current {
+        diffs.push(format!("{} was {:?}, now {:?}", name, previous, current));
+    }
+}
+
+/// Progress of an `embed` run, saved next to the output after every batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    #[serde(flatten)]
+    pub settings: RunSettings,
+    /// Non-empty input lines fully processed
+    pub lines_processed: usize,
+    /// Rows in the output
+    pub records_written: usize,
+}
+
+/// Manifest location for an output path, e.g. `out.parquet` -> `out.manifest.json`
+pub fn manifest_path(output: &Path) -> PathBuf {
+    output.with_extension("manifest.json")
+}
+
+impl RunManifest {
+    /// Manifest for a run that has not processed anything yet
+    pub fn new(settings: RunSettings) -> Self {
+        Self {
+            settings,
+            lines_processed: 0,
+            records_written: 0,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ManifestError> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Write the manifest atomically, so a crash leaves either the old or the new version
+    pub fn save(&self, path: &Path) -> Result<(), ManifestError> {
+        let temp_path = path.with_extension("json.tmp");
+        let file = std::fs::File::create(&temp_path)?;
+        serde_json::to_writer_pretty(&file, self)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+
+        debug!(
+            "Saved manifest {:?}: {} lines, {} records",
+            path, self.lines_processed, self.records_written
+        );
+        Ok(())
+    }
+
+    /// Check that a run with `settings` may continue this one
+    pub fn check_resumable(&self, settings: &RunSettings) -> Result<(), ManifestError> {
+        let diffs = settings.differences(&self.settings);
+        if diffs.is_empty() {
+            Ok(())
+        } else {
+            Err(ManifestError::Mismatch(diffs))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn settings() -> RunSettings {
+        RunSettings {
+            input: PathBuf::from("/data/texts.txt"),
+            model_source: ModelSource::HuggingFace {
+                repo: "org/embedder".to_string(),
+                filename: None,
+            },
+            normalize: false,
+            max_length: None,
+            hash: HashAlgo::Md5,
+            dedupe: false,
+            compression: ParquetCodec::Zstd,
+            shard_size_rows: Some(1000),
+            columns: vec!["text_hash".to_string(), "embedding".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let temp_dir = tempdir().unwrap();
+        let path = manifest_path(&temp_dir.path().join("out.parquet"));
+        assert_eq!(path, temp_dir.path().join("out.manifest.json"));
+
+        let mut manifest = RunManifest::new(settings());
+        manifest.save(&path).unwrap();
+        manifest.lines_processed = 64;
+        manifest.records_written = 60;
+        manifest.save(&path).unwrap();
+
+        assert_eq!(RunManifest::load(&path).unwrap(), manifest);
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn test_check_resumable() {
+        let manifest = RunManifest::new(settings());
+        assert!(manifest.check_resumable(&settings()).is_ok());
+
+        let mut changed = settings();
+        changed.normalize = true;
+        changed.model_source = ModelSource::HuggingFace {
+            repo: "org/other".to_string(),
+            filename: None,
+        };
+        let error = manifest.check_resumable(&changed).unwrap_err().to_string();
+        assert!(error.contains("normalize was false, now true"));
+        assert!(error.contains("org/other"));
+        assert!(!error.contains("hash"));
+
+        let mut changed = settings();
+        changed.columns.insert(0, "text".to_string());
+        let error = manifest.check_resumable(&changed).unwrap_err().to_string();
+        assert!(error.contains("columns was"));
+    }
+}
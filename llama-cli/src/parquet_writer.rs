@@ -1,6 +1,8 @@
-use llama_embedding::types::EmbeddingResult;
+use llama_embedding::types::{EmbeddingResult, HashAlgo};
+use llama_embedding::TextDeduplicator;
 use polars::prelude::*;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use tracing::{debug, info};
 
@@ -27,11 +29,169 @@ pub enum ParquetError {
 
     #[error("Conversion error: {0}")]
     Conversion(String),
+
+    #[error("Cannot resume from {path:?}: expected {expected} rows, found {actual}")]
+    ResumeMismatch {
+        path: PathBuf,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// Compression codec for Parquet output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParquetCodec {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lz4,
+    #[default]
+    Zstd,
+    Brotli,
+}
+
+impl ParquetCodec {
+    fn to_polars(self) -> ParquetCompression {
+        match self {
+            ParquetCodec::Uncompressed => ParquetCompression::Uncompressed,
+            ParquetCodec::Snappy => ParquetCompression::Snappy,
+            ParquetCodec::Gzip => ParquetCompression::Gzip(None),
+            ParquetCodec::Lz4 => ParquetCompression::Lz4Raw,
+            ParquetCodec::Zstd => ParquetCompression::Zstd(None),
+            ParquetCodec::Brotli => ParquetCompression::Brotli(None),
+        }
+    }
+}
+
+impl std::fmt::Display for ParquetCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ParquetCodec::Uncompressed => "uncompressed",
+            ParquetCodec::Snappy => "snappy",
+            ParquetCodec::Gzip => "gzip",
+            ParquetCodec::Lz4 => "lz4",
+            ParquetCodec::Zstd => "zstd",
+            ParquetCodec::Brotli => "brotli",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for ParquetCodec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "uncompressed" | "none" => Ok(ParquetCodec::Uncompressed),
+            "snappy" => Ok(ParquetCodec::Snappy),
+            "gzip" => Ok(ParquetCodec::Gzip),
+            "lz4" => Ok(ParquetCodec::Lz4),
+            "zstd" => Ok(ParquetCodec::Zstd),
+            "brotli" => Ok(ParquetCodec::Brotli),
+            other => Err(format!(
+                "unknown compression codec '{}' (expected uncompressed, snappy, gzip, lz4, zstd or brotli)",
+                other
+            )),
+        }
+    }
+}
+
+/// Column of the embedding output, as named on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputColumn {
+    Text,
+    Hash,
+    TokenCount,
+    ProcessingTime,
+    Embedding,
+}
+
+impl OutputColumn {
+    /// Every column, in the order they are written
+    pub const ALL: [OutputColumn; 5] = [
+        OutputColumn::Text,
+        OutputColumn::Hash,
+        OutputColumn::TokenCount,
+        OutputColumn::ProcessingTime,
+        OutputColumn::Embedding,
+    ];
+
+    /// Name used by `--columns`
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputColumn::Text => "text",
+            OutputColumn::Hash => "hash",
+            OutputColumn::TokenCount => "token_count",
+            OutputColumn::ProcessingTime => "processing_time",
+            OutputColumn::Embedding => "embedding",
+        }
+    }
+
+    /// Name of the column in the output file; `None` for the hash column of `HashAlgo::None`
+    pub fn column_name(self, hash: HashAlgo) -> Option<&'static str> {
+        match self {
+            OutputColumn::Text => Some("text"),
+            OutputColumn::Hash => hash.column_name(),
+            OutputColumn::TokenCount => Some("sequence_length"),
+            OutputColumn::ProcessingTime => Some("processing_time_ms"),
+            OutputColumn::Embedding => Some("embedding"),
+        }
+    }
+}
+
+impl std::fmt::Display for OutputColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::str::FromStr for OutputColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        OutputColumn::ALL
+            .into_iter()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| {
+                let valid: Vec<&str> = OutputColumn::ALL.iter().map(|c| c.name()).collect();
+                format!(
+                    "unknown column '{}' (expected one of: {})",
+                    s.trim(),
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+/// File column names written for `columns`, in output order
+pub fn column_names(columns: &[OutputColumn], hash: HashAlgo) -> Vec<String> {
+    OutputColumn::ALL
+        .into_iter()
+        .filter(|column| columns.contains(column))
+        .filter_map(|column| column.column_name(hash))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Path of shard `index` (1-based) of `output_path`, e.g. `out.parquet` -> `out-00001.parquet`
+pub fn shard_path(output_path: &Path, index: usize) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match output_path.extension() {
+        Some(ext) => format!("{}-{:05}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}-{:05}", stem, index),
+    };
+    output_path.with_file_name(name)
 }
 
 /// Writer for efficiently writing embedding results to Parquet files
 pub struct ParquetWriter {
-    /// Output file path  
+    /// Output file path, or the base name of the shards when sharding
     output_path: std::path::PathBuf,
     /// Batch buffer to accumulate results before writing
     batch_buffer: Vec<EmbeddingResult>,
@@ -43,6 +203,24 @@ pub struct ParquetWriter {
     records_written: usize,
     /// Whether file has been written (for append mode)
     file_written: bool,
+    /// Hash algorithm of the results, which names the hash column
+    hash: HashAlgo,
+    /// Columns to write; the rest of each result is dropped
+    columns: Vec<OutputColumn>,
+    /// Whether to add a `zero_filled` column marking zero-filled vectors
+    zero_filled_column: bool,
+    /// Compression codec for every column
+    codec: ParquetCodec,
+    /// Rows per row group; `None` uses the Polars default
+    row_group_size: Option<usize>,
+    /// Rows per output shard; `None` writes a single file
+    shard_size_rows: Option<usize>,
+    /// 1-based index of the shard being written
+    shard_index: usize,
+    /// Rows in the shard being written
+    shard_rows: usize,
+    /// Files finalized so far
+    finished_files: Vec<PathBuf>,
 }
 
 impl ParquetWriter {
@@ -85,9 +263,62 @@ impl ParquetWriter {
             embedding_dim,
             records_written: 0,
             file_written: false,
+            hash: HashAlgo::default(),
+            columns: OutputColumn::ALL.to_vec(),
+            zero_filled_column: false,
+            codec: ParquetCodec::default(),
+            row_group_size: None,
+            shard_size_rows: None,
+            shard_index: 1,
+            shard_rows: 0,
+            finished_files: Vec::new(),
         })
     }
 
+    /// Set the hash algorithm the results were produced with.
+    ///
+    /// The hash column is named after it, and omitted for `HashAlgo::None`.
+    pub fn with_hash(mut self, hash: HashAlgo) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// Write only `columns` (all of them by default).
+    ///
+    /// Columns are always written in the order of `OutputColumn::ALL`.
+    pub fn with_columns(mut self, columns: &[OutputColumn]) -> Self {
+        self.columns = columns.to_vec();
+        self
+    }
+
+    /// Add a `zero_filled` column after the selected ones, true for rows whose
+    /// vector was replaced by zeros under `InvalidVectorPolicy::ZeroFill`
+    pub fn with_zero_filled_column(mut self, enabled: bool) -> Self {
+        self.zero_filled_column = enabled;
+        self
+    }
+
+    /// Set the compression codec (zstd by default)
+    pub fn with_compression(mut self, codec: ParquetCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Set the number of rows per row group; 0 keeps the Polars default
+    pub fn with_row_group_size(mut self, rows: usize) -> Self {
+        self.row_group_size = (rows > 0).then_some(rows);
+        self
+    }
+
+    /// Split the output into shards of at most `rows` rows, named like `out-00001.parquet`.
+    ///
+    /// Each shard is synced to disk once full, so completed shards survive a crash. 0 disables
+    /// sharding.
+    pub fn with_shard_size(mut self, rows: usize) -> Self {
+        self.shard_size_rows = (rows > 0).then_some(rows);
+        self
+    }
+
     /// Write a batch of embedding results
     ///
     /// # Arguments
@@ -175,8 +406,11 @@ impl ParquetWriter {
     pub fn close(mut self) -> Result<usize, ParquetError> {
         debug!("Closing ParquetWriter");
 
-        // Flush any remaining data
+        // Flush any remaining data and finalize the last file
         self.flush()?;
+        if self.file_written {
+            self.finish_file()?;
+        }
 
         info!(
             "ParquetWriter closed successfully: {} records written to {:?}",
@@ -201,6 +435,152 @@ impl ParquetWriter {
         self.batch_size
     }
 
+    /// Names of the columns in the output files
+    pub fn column_names(&self) -> Vec<String> {
+        let mut names = column_names(&self.columns, self.hash);
+        if self.zero_filled_column {
+            names.push("zero_filled".to_string());
+        }
+        names
+    }
+
+    /// Files written so far, in order, including the shard still being filled
+    pub fn output_files(&self) -> Vec<PathBuf> {
+        let mut files = self.finished_files.clone();
+        if self.file_written {
+            files.push(self.current_path());
+        }
+        files
+    }
+
+    /// Path of the file currently being written
+    fn current_path(&self) -> PathBuf {
+        match self.shard_size_rows {
+            Some(_) => shard_path(&self.output_path, self.shard_index),
+            None => self.output_path.clone(),
+        }
+    }
+
+    /// Sync the current file to disk and move on to the next shard
+    fn finish_file(&mut self) -> Result<(), ParquetError> {
+        let path = self.current_path();
+        std::fs::File::open(&path)?.sync_all()?;
+        debug!("Finalized {:?} with {} rows", path, self.shard_rows);
+
+        self.finished_files.push(path);
+        self.shard_index += 1;
+        self.shard_rows = 0;
+        self.file_written = false;
+        Ok(())
+    }
+
+    /// Continue a previous run whose output holds `records` rows.
+    ///
+    /// Rows past `records`, written after the run's last checkpoint, are dropped, as are any
+    /// later shards. Set the sharding options before calling this.
+    pub fn resume(mut self, records: usize) -> Result<Self, ParquetError> {
+        let limit = self.shard_size_rows.unwrap_or(usize::MAX);
+        let (finished, current_rows) = match self.shard_size_rows {
+            Some(limit) => (records / limit, records % limit),
+            None => (0, records),
+        };
+
+        for index in 1..=finished {
+            let path = shard_path(&self.output_path, index);
+            if !path.exists() {
+                return Err(ParquetError::ResumeMismatch {
+                    path,
+                    expected: limit,
+                    actual: 0,
+                });
+            }
+            self.finished_files.push(path);
+        }
+        self.shard_index = finished + 1;
+
+        if current_rows > 0 {
+            self.truncate_current(current_rows)?;
+        }
+        if self.shard_size_rows.is_some() {
+            let mut stale = self.shard_index + usize::from(current_rows > 0);
+            while shard_path(&self.output_path, stale).exists() {
+                std::fs::remove_file(shard_path(&self.output_path, stale))?;
+                stale += 1;
+            }
+        }
+
+        self.shard_rows = current_rows;
+        self.file_written = current_rows > 0;
+        self.records_written = records;
+        info!(
+            "Resuming {:?} after {} records",
+            self.output_path, self.records_written
+        );
+        Ok(self)
+    }
+
+    /// Cut the current file down to its first `rows` rows
+    fn truncate_current(&self, rows: usize) -> Result<(), ParquetError> {
+        let path = self.current_path();
+        let df = if path.exists() {
+            LazyFrame::scan_parquet(&path, ScanArgsParquet::default())?.collect()?
+        } else {
+            DataFrame::empty()
+        };
+        let actual = df.height();
+        if actual < rows {
+            return Err(ParquetError::ResumeMismatch {
+                path,
+                expected: rows,
+                actual,
+            });
+        }
+        if actual > rows {
+            self.write_file(&path, &mut df.slice(0, rows))?;
+            debug!("Dropped {} unrecorded rows from {:?}", actual - rows, path);
+        }
+        Ok(())
+    }
+
+    /// Write results, splitting them across shards when sharding is enabled
+    fn write_dataframe(&mut self, results: &[EmbeddingResult]) -> Result<(), ParquetError> {
+        let mut remaining = results;
+        while !remaining.is_empty() {
+            let take = match self.shard_size_rows {
+                Some(limit) => remaining.len().min(limit - self.shard_rows),
+                None => remaining.len(),
+            };
+            let (chunk, rest) = remaining.split_at(take);
+            self.write_chunk(chunk)?;
+            self.shard_rows += chunk.len();
+            remaining = rest;
+
+            if self
+                .shard_size_rows
+                .is_some_and(|limit| self.shard_rows >= limit)
+            {
+                self.finish_file()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `df` to `path` with the configured codec and row group size, replacing the file.
+    ///
+    /// The data goes to a sibling temp file that is synced and renamed over `path`, like the
+    /// manifest, so a crash mid-write leaves the previous rows in place for `--resume`.
+    fn write_file(&self, path: &Path, df: &mut DataFrame) -> Result<(), ParquetError> {
+        let temp_path = temp_path(path);
+        let file = std::fs::File::create(&temp_path)?;
+        polars::prelude::ParquetWriter::new(&file)
+            .with_compression(self.codec.to_polars())
+            .with_row_group_size(self.row_group_size)
+            .finish(df)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
     /// Flush the internal buffer
     fn flush_buffer(&mut self) -> Result<(), ParquetError> {
         if self.batch_buffer.is_empty() {
@@ -215,8 +595,8 @@ impl ParquetWriter {
         Ok(())
     }
 
-    /// Write embedding results as a DataFrame to Parquet
-    fn write_dataframe(&mut self, results: &[EmbeddingResult]) -> Result<(), ParquetError> {
+    /// Write embedding results as a DataFrame to the current file
+    fn write_chunk(&mut self, results: &[EmbeddingResult]) -> Result<(), ParquetError> {
         if results.is_empty() {
             return Ok(());
         }
@@ -224,30 +604,49 @@ impl ParquetWriter {
         let num_records = results.len();
         debug!("Converting {} results to DataFrame", num_records);
 
-        // Extract fields into separate vectors
-        let texts: Vec<&str> = results.iter().map(|r| r.text.as_str()).collect();
-        let text_hashes: Vec<&str> = results.iter().map(|r| r.text_hash.as_str()).collect();
-        let sequence_lengths: Vec<u32> = results.iter().map(|r| r.sequence_length as u32).collect();
-        let processing_times: Vec<u64> = results.iter().map(|r| r.processing_time_ms).collect();
-
-        // Create embedding arrays as a List column
-        // Convert Vec<Vec<f32>> to a List Series by creating sub-series
-        let mut embedding_series_builder = Vec::new();
-        for result in results {
-            let embedding_series = Series::new("", &result.embedding);
-            embedding_series_builder.push(embedding_series);
+        // Build only the selected columns, in output order
+        let mut columns = Vec::with_capacity(self.columns.len());
+        for column in OutputColumn::ALL {
+            if !self.columns.contains(&column) {
+                continue;
+            }
+            let Some(name) = column.column_name(self.hash) else {
+                continue;
+            };
+            let series = match column {
+                OutputColumn::Text => {
+                    let texts: Vec<&str> = results.iter().map(|r| r.text.as_str()).collect();
+                    Series::new(name, texts)
+                }
+                OutputColumn::Hash => {
+                    let hashes: Vec<&str> = results.iter().map(|r| r.text_hash.as_str()).collect();
+                    Series::new(name, hashes)
+                }
+                OutputColumn::TokenCount => {
+                    let lengths: Vec<u32> =
+                        results.iter().map(|r| r.sequence_length as u32).collect();
+                    Series::new(name, lengths)
+                }
+                OutputColumn::ProcessingTime => {
+                    let times: Vec<u64> = results.iter().map(|r| r.processing_time_ms).collect();
+                    Series::new(name, times)
+                }
+                OutputColumn::Embedding => {
+                    // Convert Vec<Vec<f32>> to a List Series by creating sub-series
+                    let embeddings: Vec<Series> = results
+                        .iter()
+                        .map(|r| Series::new("", &r.embedding))
+                        .collect();
+                    Series::new(name, embeddings)
+                }
+            };
+            columns.push(series);
         }
-
-        let embedding_series = Series::new("embedding", embedding_series_builder);
-
-        // Create the main DataFrame with embedding as a single array column
-        let df = DataFrame::new(vec![
-            Series::new("text", texts),
-            Series::new("text_hash", text_hashes),
-            Series::new("sequence_length", sequence_lengths),
-            Series::new("processing_time_ms", processing_times),
-            embedding_series,
-        ])?;
+        if self.zero_filled_column {
+            let flags: Vec<bool> = results.iter().map(|r| r.zero_filled).collect();
+            columns.push(Series::new("zero_filled", flags));
+        }
+        let mut df = DataFrame::new(columns)?;
 
         debug!(
             "DataFrame created: {} rows, {} columns",
@@ -256,26 +655,23 @@ impl ParquetWriter {
         );
 
         // Write to Parquet file
+        let path = self.current_path();
         if self.file_written {
             // Append mode - read existing file, concatenate, and write
             let existing_df =
-                LazyFrame::scan_parquet(&self.output_path, ScanArgsParquet::default())?
-                    .collect()?;
+                LazyFrame::scan_parquet(&path, ScanArgsParquet::default())?.collect()?;
 
-            let combined_df =
+            let mut combined_df =
                 concat([existing_df.lazy(), df.lazy()], UnionArgs::default())?.collect()?;
 
             let rows = combined_df.height();
-            combined_df
-                .lazy()
-                .sink_parquet(&self.output_path, ParquetWriteOptions::default())?;
+            self.write_file(&path, &mut combined_df)?;
 
             debug!("DataFrame written to Parquet: {} rows (appended)", rows);
         } else {
             // First write
             let rows = df.height();
-            df.lazy()
-                .sink_parquet(&self.output_path, ParquetWriteOptions::default())?;
+            self.write_file(&path, &mut df)?;
             self.file_written = true;
             debug!("DataFrame written to Parquet: {} rows (first write)", rows);
         };
@@ -284,6 +680,36 @@ impl ParquetWriter {
     }
 }
 
+/// Sibling of `path` that a file is written to before replacing `path`
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Mark every text in an earlier output file as already embedded.
+///
+/// Reads the hash column matching the deduplicator's algorithm when the file has one, and
+/// otherwise hashes the `text` column. Returns the number of rows read.
+pub fn read_dedupe_keys(path: &Path, dedup: &mut TextDeduplicator) -> Result<usize, ParquetError> {
+    let df = LazyFrame::scan_parquet(path, ScanArgsParquet::default())?.collect()?;
+    let hash_column = dedup.key_column().filter(|name| df.column(name).is_ok());
+
+    match hash_column {
+        Some(name) => {
+            let keys = df.column(name)?.str()?;
+            dedup.preload_keys(keys.into_iter().flatten().map(str::to_string));
+        }
+        None => {
+            let texts = df.column("text")?.str()?;
+            dedup.preload_texts(texts.into_iter().flatten());
+        }
+    }
+
+    debug!("Loaded {} dedupe keys from {:?}", df.height(), path);
+    Ok(df.height())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -353,6 +779,29 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_appends_replace_the_file_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.parquet");
+        let mut writer = ParquetWriter::new(&path, 2, 1).unwrap();
+
+        for i in 0..3 {
+            let text = format!("text{}", i);
+            writer
+                .add_result(EmbeddingResult::new(text, vec![0.1, 0.2], 1, 50))
+                .unwrap();
+        }
+        writer.close().unwrap();
+
+        let df = LazyFrame::scan_parquet(&path, ScanArgsParquet::default())
+            .unwrap()
+            .collect()
+            .unwrap();
+        assert_eq!(df.height(), 3);
+        assert!(!temp_path(&path).exists());
+        assert_eq!(temp_path(&path), dir.path().join("out.parquet.tmp"));
+    }
+
     #[test]
     fn test_add_result_with_auto_flush() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -407,4 +856,148 @@ mod tests {
         assert!(df.get_column_names().contains(&"processing_time_ms"));
         assert!(df.get_column_names().contains(&"embedding"));
     }
+
+    #[test]
+    fn test_hash_column_follows_algorithm() {
+        let read_columns = |hash: HashAlgo| {
+            let temp_file = NamedTempFile::new().unwrap();
+            let temp_path = temp_file.path().to_path_buf();
+
+            let mut writer = ParquetWriter::new(&temp_path, 2, 10)
+                .unwrap()
+                .with_hash(hash);
+            let result =
+                EmbeddingResult::with_hash("test".to_string(), vec![1.0, 2.0], 1, 10, hash);
+            writer.write_batch(vec![result]).unwrap();
+            writer.close().unwrap();
+
+            let df = LazyFrame::scan_parquet(&temp_path, ScanArgsParquet::default())
+                .unwrap()
+                .collect()
+                .unwrap();
+            df.get_column_names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let columns = read_columns(HashAlgo::Xxh3);
+        assert!(columns.contains(&"text_xxh3".to_string()));
+        assert!(!columns.contains(&"text_hash".to_string()));
+
+        let columns = read_columns(HashAlgo::Sha256);
+        assert!(columns.contains(&"text_sha256".to_string()));
+
+        let columns = read_columns(HashAlgo::None);
+        assert_eq!(
+            columns,
+            vec!["text", "sequence_length", "processing_time_ms", "embedding"]
+        );
+    }
+
+    #[test]
+    fn test_column_projection() {
+        let write = |columns: &[OutputColumn]| {
+            let temp_file = NamedTempFile::new().unwrap();
+            let temp_path = temp_file.path().to_path_buf();
+
+            let mut writer = ParquetWriter::new(&temp_path, 4, 100)
+                .unwrap()
+                .with_columns(columns)
+                .with_compression(ParquetCodec::Uncompressed);
+            let results = (0..50)
+                .map(|i| {
+                    let text = format!("document {} with a fairly long body of source text", i);
+                    EmbeddingResult::new(text, vec![i as f32, 1.0, 2.0, 3.0], 12, 5)
+                })
+                .collect();
+            writer.write_batch(results).unwrap();
+            let names = writer.column_names();
+            writer.close().unwrap();
+
+            let df = LazyFrame::scan_parquet(&temp_path, ScanArgsParquet::default())
+                .unwrap()
+                .collect()
+                .unwrap();
+            let schema: Vec<String> = df
+                .get_column_names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect();
+            assert_eq!(schema, names);
+            assert_eq!(df.height(), 50);
+            (schema, std::fs::metadata(&temp_path).unwrap().len())
+        };
+
+        let (all, full_size) = write(&OutputColumn::ALL);
+        assert_eq!(
+            all,
+            vec![
+                "text",
+                "text_hash",
+                "sequence_length",
+                "processing_time_ms",
+                "embedding"
+            ]
+        );
+
+        // Order on the command line does not change the output order
+        let (projected, projected_size) = write(&[OutputColumn::Embedding, OutputColumn::Hash]);
+        assert_eq!(projected, vec!["text_hash", "embedding"]);
+        assert!(
+            projected_size < full_size,
+            "{} >= {}",
+            projected_size,
+            full_size
+        );
+    }
+
+    #[test]
+    fn test_output_column_parse() {
+        assert_eq!(
+            "token_count".parse::<OutputColumn>(),
+            Ok(OutputColumn::TokenCount)
+        );
+        assert_eq!(" Hash ".parse::<OutputColumn>(), Ok(OutputColumn::Hash));
+
+        let error = "tokens".parse::<OutputColumn>().unwrap_err();
+        assert!(error.contains("unknown column 'tokens'"));
+        assert!(error.contains("text, hash, token_count, processing_time, embedding"));
+
+        assert_eq!(
+            column_names(&OutputColumn::ALL, HashAlgo::None),
+            vec!["text", "sequence_length", "processing_time_ms", "embedding"]
+        );
+    }
+
+    #[test]
+    fn test_read_dedupe_keys() {
+        for hash in [HashAlgo::Md5, HashAlgo::None] {
+            let temp_file = NamedTempFile::new().unwrap();
+            let temp_path = temp_file.path().to_path_buf();
+
+            let mut writer = ParquetWriter::new(&temp_path, 2, 10)
+                .unwrap()
+                .with_hash(hash);
+            let results = ["seen 1", "seen 2"]
+                .iter()
+                .map(|text| {
+                    EmbeddingResult::with_hash(text.to_string(), vec![1.0, 2.0], 1, 1, hash)
+                })
+                .collect();
+            writer.write_batch(results).unwrap();
+            writer.close().unwrap();
+
+            let mut dedup = TextDeduplicator::new(hash);
+            assert_eq!(read_dedupe_keys(&temp_path, &mut dedup).unwrap(), 2);
+
+            let texts = vec![
+                "seen 2".to_string(),
+                "new".to_string(),
+                "seen 1".to_string(),
+            ];
+            assert_eq!(dedup.filter(&texts), vec!["new".to_string()]);
+            assert_eq!(dedup.duplicates_skipped(), 2);
+        }
+    }
 }
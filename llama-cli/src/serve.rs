@@ -0,0 +1,885 @@
+//! OpenAI-compatible HTTP server over an [`AgentServer`]
+//!
+//! Serves `POST /v1/chat/completions`, streamed as server-sent events when `stream` is
+//! true, `GET /v1/models` and `GET /health`. Each completion runs in a session of its own
+//! that is deleted once the response is complete. With an API key set, the `/v1` routes
+//! require it as a bearer token; `/health` stays open for load balancers.
+
+use crate::error::CliError;
+use crate::generate::{
+    describe_model_source, initialize_interruptible, validate_max_time, ModelArgs,
+};
+use crate::stop_sequences::{fired_stop_sequence, partial_stop_len, validate_stop_sequences};
+use anyhow::Result;
+use axum::{
+    extract::{rejection::JsonRejection, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use clap::builder::PossibleValuesParser;
+use clap::Args;
+use futures::{Stream, StreamExt};
+use llama_agent::{
+    types::{
+        AgentAPI, AgentError, FinishReason, GenerationRequest, GenerationResponse, ModelSource,
+        QueueError, SessionError, SessionId, StreamChunk, TemplateOverride,
+    },
+    validation::generation_request::ParameterConfig,
+    AgentServer,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    convert::Infallible,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::TcpListener, signal, sync::mpsc};
+use tracing::{info, warn};
+
+/// Environment variable read for the API key when `--api-key` is not given
+pub const API_KEY_ENV: &str = "LLAMA_SERVE_API_KEY";
+
+/// Owner reported for the model in `/v1/models`
+const MODEL_OWNER: &str = "llama-agent";
+
+#[derive(Args, Clone)]
+#[command(about = "Serve the model over an OpenAI-compatible HTTP API")]
+pub struct ServeArgs {
+    /// Model source and loading flags shared with the other model commands
+    #[command(flatten)]
+    pub model_args: ModelArgs,
+
+    /// Address to listen on (default: 127.0.0.1)
+    #[arg(long, default_value = "127.0.0.1", help = "Address to listen on")]
+    pub host: String,
+
+    /// Port to listen on (default: 8080)
+    #[arg(
+        long,
+        default_value = "8080",
+        help = "Port to listen on",
+        long_help = "Port to listen on. 0 picks a free port, which is logged at startup"
+    )]
+    pub port: u16,
+
+    /// Bearer token clients must send to the /v1 routes
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        help = "Bearer token clients must send to the /v1 routes",
+        long_help = "Bearer token clients must send in the Authorization header of the /v1 routes. Defaults to the LLAMA_SERVE_API_KEY environment variable; without either, the routes are open"
+    )]
+    pub api_key: Option<String>,
+
+    /// Model ID reported by /v1/models and in responses
+    #[arg(
+        long,
+        value_name = "ID",
+        help = "Model ID reported by /v1/models and in responses",
+        long_help = "Model ID reported by /v1/models and in responses. Defaults to the model filename, or the repository name when no filename is set"
+    )]
+    pub model_id: Option<String>,
+
+    /// Time budget of every completion in milliseconds
+    #[arg(
+        long,
+        value_name = "MS",
+        help = "Stop each completion after this many milliseconds",
+        long_help = "Stop each completion after this many milliseconds and return the text generated so far, with finish_reason \"length\". Must not exceed the request timeout"
+    )]
+    pub max_time_ms: Option<u64>,
+}
+
+/// Settings of the HTTP API that are independent of the agent
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Model ID reported by `/v1/models` and in responses
+    pub model_id: String,
+    /// Bearer token required on the `/v1` routes; `None` leaves them open
+    pub api_key: Option<String>,
+    /// Time budget of every completion; `None` leaves them unbounded
+    pub max_duration: Option<Duration>,
+}
+
+pub fn validate_serve_args(args: &ServeArgs) -> Result<()> {
+    args.model_args.validate()?;
+
+    if args.host.trim().is_empty() {
+        return Err(anyhow::anyhow!("Host cannot be empty"));
+    }
+    if args
+        .api_key
+        .as_deref()
+        .is_some_and(|key| key.trim().is_empty())
+    {
+        return Err(anyhow::anyhow!(
+            "API key cannot be empty\n💡 Omit --api-key to leave the API open"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Model ID derived from the model source: its filename, or the repository or folder name
+pub fn default_model_id(source: &ModelSource) -> String {
+    let filename = match source {
+        ModelSource::HuggingFace { filename, .. }
+        | ModelSource::Local { filename, .. }
+        | ModelSource::Url { filename, .. } => filename.as_deref(),
+    };
+    if let Some(filename) = filename {
+        return filename.to_string();
+    }
+
+    match source {
+        ModelSource::HuggingFace { repo, .. } => repo.clone(),
+        ModelSource::Local { folder, .. } => folder
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| folder.display().to_string()),
+        ModelSource::Url { url, .. } => url
+            .rsplit('/')
+            .find(|segment| !segment.is_empty())
+            .unwrap_or(url)
+            .to_string(),
+    }
+}
+
+/// Load the model and serve it until interrupted
+pub async fn run_serve(args: ServeArgs) -> Result<(), CliError> {
+    validate_serve_args(&args).map_err(CliError::Validation)?;
+
+    let mut config = args
+        .model_args
+        .agent_config()
+        .map_err(CliError::Validation)?;
+    validate_max_time(args.max_time_ms, config.queue_config.request_timeout)
+        .map_err(CliError::Validation)?;
+
+    let options = ServeOptions {
+        model_id: args
+            .model_id
+            .clone()
+            .unwrap_or_else(|| default_model_id(&config.model.source)),
+        api_key: args.api_key.clone().or_else(|| {
+            std::env::var(API_KEY_ENV)
+                .ok()
+                .filter(|key| !key.is_empty())
+        }),
+        max_duration: args.max_time_ms.map(Duration::from_millis),
+    };
+
+    let listener = TcpListener::bind((args.host.as_str(), args.port))
+        .await
+        .map_err(|e| {
+            CliError::Validation(anyhow::anyhow!(
+                "Failed to listen on {}:{}: {}\n💡 Pick a free port with --port",
+                args.host,
+                args.port,
+                e
+            ))
+        })?;
+
+    info!(
+        "Loading model from {}...",
+        describe_model_source(&config.model.source)
+    );
+    let agent = Arc::new(
+        initialize_interruptible(config)
+            .await
+            .map_err(CliError::from_initialization)?,
+    );
+
+    if let Ok(address) = listener.local_addr() {
+        info!("Serving '{}' on http://{}", options.model_id, address);
+    }
+    if options.api_key.is_none() {
+        warn!("No API key set; the /v1 routes accept any client");
+    }
+
+    let shutdown = async {
+        if let Err(e) = signal::ctrl_c().await {
+            warn!("Failed to listen for shutdown signal: {}", e);
+            std::future::pending::<()>().await;
+        }
+        info!("Interrupt signal received, shutting down gracefully...");
+    };
+    serve(listener, agent.clone(), options, shutdown)
+        .await
+        .map_err(|e| CliError::Runtime(anyhow::anyhow!("HTTP server failed: {}", e)))?;
+
+    // Responses still streaming finish during the queue drain
+    if let Err(e) = agent.shutdown().await {
+        warn!("Error during shutdown: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Serve the API on `listener` until `shutdown` completes
+pub async fn serve(
+    listener: TcpListener,
+    agent: Arc<AgentServer>,
+    options: ServeOptions,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    axum::serve(listener, router(agent, options))
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+/// Routes of the API, for serving on a listener of your own
+pub fn router(agent: Arc<AgentServer>, options: ServeOptions) -> Router {
+    let state = Arc::new(AppState {
+        agent,
+        options,
+        created: unix_time(SystemTime::now()),
+    });
+
+    let api = Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/models", get(list_models))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_api_key,
+        ));
+
+    Router::new()
+        .route("/health", get(health))
+        .merge(api)
+        .with_state(state)
+}
+
+struct AppState {
+    agent: Arc<AgentServer>,
+    options: ServeOptions,
+    /// When the server started, reported as the model's creation time
+    created: u64,
+}
+
+type ChunkStream = Pin<Box<dyn Stream<Item = Result<StreamChunk, AgentError>> + Send>>;
+
+/// A generation that has started, depending on whether the client asked for a stream
+enum Completion {
+    Streamed(ChunkStream),
+    Whole(GenerationResponse),
+}
+
+/// Fields of an OpenAI chat completion request that map onto a generation request;
+/// the others are ignored
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    messages: Value,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u32>,
+    /// Newer name of `max_tokens`, preferred when both are set
+    max_completion_tokens: Option<u32>,
+    stop: Option<StopSequences>,
+    stream: Option<bool>,
+    stream_options: Option<StreamOptions>,
+}
+
+/// `stop` may be a single string or a list of them
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+impl ChatCompletionRequest {
+    fn stop_sequences(&self) -> Vec<String> {
+        match &self.stop {
+            None => Vec::new(),
+            Some(StopSequences::One(stop)) => vec![stop.clone()],
+            Some(StopSequences::Many(stops)) => stops.clone(),
+        }
+    }
+
+    /// Check the parameters against the same limits as `llama-cli generate`
+    fn validate(&self) -> Result<(), ApiError> {
+        let limits = ParameterConfig::default();
+        let in_range = |value: f32, (min, max): (f32, f32)| (min..=max).contains(&value);
+
+        if let Some(temperature) = self.temperature {
+            if !in_range(temperature, limits.temperature_range) {
+                return Err(ApiError::invalid_request(format!(
+                    "temperature must be between {} and {}, got {}",
+                    limits.temperature_range.0, limits.temperature_range.1, temperature
+                )));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !in_range(top_p, limits.top_p_range) {
+                return Err(ApiError::invalid_request(format!(
+                    "top_p must be between {} and {}, got {}",
+                    limits.top_p_range.0, limits.top_p_range.1, top_p
+                )));
+            }
+        }
+        if let Some(max_tokens) = self.max_completion_tokens.or(self.max_tokens) {
+            if max_tokens > limits.max_tokens_limit {
+                return Err(ApiError::invalid_request(format!(
+                    "max_tokens must be at most {}, got {}",
+                    limits.max_tokens_limit, max_tokens
+                )));
+            }
+        }
+        validate_stop_sequences(&self.stop_sequences())
+            .map_err(|e| ApiError::invalid_request(e.to_string()))
+    }
+
+    fn generation_request(&self, session_id: SessionId) -> GenerationRequest {
+        let mut request = GenerationRequest::new(session_id)
+            .with_stop_tokens(self.stop_sequences())
+            .with_default_stopping();
+        if let Some(max_tokens) = self.max_completion_tokens.or(self.max_tokens) {
+            request = request.with_max_tokens(max_tokens);
+        }
+        if let Some(temperature) = self.temperature {
+            request = request.with_temperature(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            request = request.with_top_p(top_p);
+        }
+        request
+    }
+}
+
+/// An error in the OpenAI `{"error": {...}}` shape
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    kind: &'static str,
+    code: Option<&'static str>,
+    message: String,
+    retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, kind: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            kind,
+            code: None,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request_error", message)
+    }
+
+    fn body(&self) -> Value {
+        json!({
+            "error": {
+                "message": self.message,
+                "type": self.kind,
+                "param": null,
+                "code": self.code,
+            }
+        })
+    }
+}
+
+impl From<AgentError> for ApiError {
+    fn from(error: AgentError) -> Self {
+        let message = error.to_string();
+        match error.root() {
+            AgentError::OpenAIFormat(_)
+            | AgentError::Template(_)
+            | AgentError::Session(SessionError::InvalidState(_)) => Self::invalid_request(message),
+            AgentError::RateLimited { retry_after, .. }
+            | AgentError::QueueFull {
+                estimated_wait: retry_after,
+                ..
+            } => Self {
+                retry_after: *retry_after,
+                ..Self::new(StatusCode::TOO_MANY_REQUESTS, "rate_limit_error", message)
+            },
+            AgentError::Session(SessionError::LimitExceeded) => {
+                Self::new(StatusCode::TOO_MANY_REQUESTS, "rate_limit_error", message)
+            }
+            AgentError::Session(SessionError::InUse(_)) => {
+                Self::new(StatusCode::CONFLICT, "conflict_error", message)
+            }
+            AgentError::Timeout { .. } | AgentError::Queue(QueueError::Timeout) => {
+                Self::new(StatusCode::GATEWAY_TIMEOUT, "timeout_error", message)
+            }
+            AgentError::Queue(QueueError::ModelLoading) | AgentError::ShuttingDown => {
+                Self::new(StatusCode::SERVICE_UNAVAILABLE, "server_error", message)
+            }
+            _ => Self::new(StatusCode::INTERNAL_SERVER_ERROR, "server_error", message),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(self.body())).into_response();
+        if let Some(retry_after) = self.retry_after {
+            let secs = retry_after.as_secs_f64().ceil() as u64;
+            if let Ok(value) = HeaderValue::from_str(&secs.to_string()) {
+                response.headers_mut().insert(header::RETRY_AFTER, value);
+            }
+        }
+        response
+    }
+}
+
+async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(api_key) = &state.options.api_key {
+        let presented = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !presented.is_some_and(|token| tokens_match(token.trim(), api_key)) {
+            return ApiError {
+                code: Some("invalid_api_key"),
+                ..ApiError::new(
+                    StatusCode::UNAUTHORIZED,
+                    "invalid_request_error",
+                    "Missing or invalid API key in the Authorization header",
+                )
+            }
+            .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Compare tokens in time independent of where they first differ
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn health(State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
+    let health = state.agent.health().await?;
+    let status = if health.status == "healthy" {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((status, Json(health)).into_response())
+}
+
+async fn list_models(State(state): State<Arc<AppState>>) -> Json<Value> {
+    Json(json!({
+        "object": "list",
+        "data": [{
+            "id": state.options.model_id,
+            "object": "model",
+            "created": state.created,
+            "owned_by": MODEL_OWNER,
+        }],
+    }))
+}
+
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<ChatCompletionRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(body) = body.map_err(|e| ApiError::invalid_request(e.body_text()))?;
+    body.validate()?;
+
+    let session = state.agent.create_session().await?;
+    let started = async {
+        state
+            .agent
+            .import_openai_messages(&session.id, body.messages.clone())
+            .await?;
+        let mut request = body.generation_request(session.id);
+        if let Some(max_duration) = state.options.max_duration {
+            request = request.with_max_duration(max_duration);
+        }
+        // Template and model stops end generation too, so they are trimmed like the request's
+        let stop_sequences = state.agent.stop_sequences(&request)?;
+        let completion = if body.stream.unwrap_or(false) {
+            Completion::Streamed(state.agent.generate_stream(request).await?)
+        } else {
+            Completion::Whole(state.agent.generate(request).await?)
+        };
+        Ok::<_, AgentError>((completion, stop_sequences))
+    }
+    .await;
+
+    match started {
+        Ok((Completion::Streamed(chunks), stop_sequences)) => {
+            let include_usage = body
+                .stream_options
+                .as_ref()
+                .is_some_and(|options| options.include_usage);
+            // The session is deleted once the stream ends
+            Ok(stream_completion(
+                state,
+                session.id,
+                chunks,
+                stop_sequences,
+                include_usage,
+            ))
+        }
+        Ok((Completion::Whole(response), stop_sequences)) => {
+            discard_session(&state.agent, &session.id).await;
+            let body = completion_body(
+                &state.options.model_id,
+                &session.id,
+                &response,
+                &stop_sequences,
+            );
+            Ok(Json(body).into_response())
+        }
+        Err(e) => {
+            discard_session(&state.agent, &session.id).await;
+            Err(ApiError::from(e))
+        }
+    }
+}
+
+/// Delete the session of a finished request
+async fn discard_session(agent: &AgentServer, session_id: &SessionId) {
+    if let Err(e) = agent.delete_session(session_id).await {
+        warn!("Failed to delete request session {}: {}", session_id, e);
+    }
+}
+
+/// Body of a non-streamed chat completion.
+///
+/// As in the OpenAI API, the stop sequence that ended the response is not part of it.
+fn completion_body(
+    model_id: &str,
+    session_id: &SessionId,
+    response: &GenerationResponse,
+    stop_sequences: &[String],
+) -> Value {
+    let text = &response.generated_text;
+    let content = &text[..stop_sequence_start(text, &response.finish_reason, stop_sequences)
+        .unwrap_or(text.len())];
+
+    json!({
+        "id": completion_id(session_id),
+        "object": "chat.completion",
+        "created": unix_time(SystemTime::now()),
+        "model": model_id,
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": openai_finish_reason(&response.finish_reason),
+        }],
+        "usage": usage(response),
+    })
+}
+
+/// Stream a completion as `chat.completion.chunk` events ending with `[DONE]`
+fn stream_completion(
+    state: Arc<AppState>,
+    session_id: SessionId,
+    chunks: ChunkStream,
+    stop_sequences: Vec<String>,
+    include_usage: bool,
+) -> Response {
+    let (events, received) = mpsc::channel(16);
+    tokio::spawn(async move {
+        // An error means the client went away; dropping the chunks ends generation
+        let forwarded = forward_chunks(
+            &events,
+            &state.options.model_id,
+            &session_id,
+            chunks,
+            &stop_sequences,
+            include_usage,
+        )
+        .await;
+        // The session is gone by the time the client sees the end of the stream
+        discard_session(&state.agent, &session_id).await;
+        if forwarded.is_ok() {
+            let _ = events.send(Event::default().data("[DONE]")).await;
+        }
+    });
+
+    let events = futures::stream::unfold(received, |mut received| async move {
+        received
+            .recv()
+            .await
+            .map(|event| (Ok::<_, Infallible>(event), received))
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Send the chunks of a completion as events.
+///
+/// As in [`completion_body`], the stop sequence that ended the response is not sent.
+async fn forward_chunks(
+    events: &mpsc::Sender<Event>,
+    model_id: &str,
+    session_id: &SessionId,
+    mut chunks: ChunkStream,
+    stop_sequences: &[String],
+    include_usage: bool,
+) -> Result<(), mpsc::error::SendError<Event>> {
+    let id = completion_id(session_id);
+    let created = unix_time(SystemTime::now());
+    let chunk_event = |choices: Value, usage: Option<Value>| {
+        let mut chunk = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model_id,
+            "choices": choices,
+        });
+        if let Some(usage) = usage {
+            chunk["usage"] = usage;
+        }
+        Event::default().data(chunk.to_string())
+    };
+    let choice = |delta: Value, finish_reason: Option<&str>| json!([{"index": 0, "delta": delta, "finish_reason": finish_reason}]);
+
+    events
+        .send(chunk_event(
+            choice(json!({"role": "assistant", "content": ""}), None),
+            None,
+        ))
+        .await?;
+
+    // All text so far, of which the first `sent` bytes have been sent
+    let mut text = String::new();
+    let mut sent = 0;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("Streaming error: {}", e);
+                let error = ApiError::from(e);
+                events
+                    .send(Event::default().data(error.body().to_string()))
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        text.push_str(&chunk.text);
+        let end = match &chunk.response {
+            Some(response) if chunk.is_complete => {
+                stop_sequence_start(&text, &response.finish_reason, stop_sequences)
+                    .unwrap_or(text.len())
+                    .max(sent)
+            }
+            _ if chunk.is_complete => text.len(),
+            _ => text.len() - partial_stop_len(&text[sent..], stop_sequences),
+        };
+        if end > sent {
+            events
+                .send(chunk_event(
+                    choice(json!({"content": &text[sent..end]}), None),
+                    None,
+                ))
+                .await?;
+            sent = end;
+        }
+
+        if chunk.is_complete {
+            let Some(response) = chunk.response else {
+                break;
+            };
+            let finish_reason = openai_finish_reason(&response.finish_reason);
+            events
+                .send(chunk_event(choice(json!({}), Some(finish_reason)), None))
+                .await?;
+            if include_usage {
+                events
+                    .send(chunk_event(json!([]), Some(usage(&response))))
+                    .await?;
+            }
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// The OpenAI `finish_reason` for how a generation ended
+fn openai_finish_reason(finish_reason: &FinishReason) -> &'static str {
+    match finish_reason {
+        FinishReason::Stopped(reason)
+            if reason.starts_with("Maximum tokens reached") || reason == "Time limit reached" =>
+        {
+            "length"
+        }
+        FinishReason::Stopped(_) => "stop",
+    }
+}
+
+fn is_stop_sequence(finish_reason: &FinishReason) -> bool {
+    matches!(finish_reason, FinishReason::Stopped(reason) if reason == "Stop token detected")
+}
+
+/// Where the stop sequence that ended a response starts in its text
+fn stop_sequence_start(
+    text: &str,
+    finish_reason: &FinishReason,
+    stop_sequences: &[String],
+) -> Option<usize> {
+    if !is_stop_sequence(finish_reason) {
+        return None;
+    }
+    fired_stop_sequence(text, stop_sequences).and_then(|stop| text.rfind(stop))
+}
+
+fn usage(response: &GenerationResponse) -> Value {
+    json!({
+        "prompt_tokens": response.prompt_tokens,
+        "completion_tokens": response.tokens_generated,
+        "total_tokens": response.prompt_tokens + response.tokens_generated,
+    })
+}
+
+/// Each request has a session of its own, so its ID identifies the completion
+fn completion_id(session_id: &SessionId) -> String {
+    format!("chatcmpl-{}", session_id)
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serve_args() -> ServeArgs {
+        ServeArgs {
+            model_args: ModelArgs {
+                config: None,
+                model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+                filename: None,
+                debug: false,
+                allow_http: false,
+                force_load: false,
+                refresh_model_metadata: false,
+                cache_dir: None,
+                local_search_depth: None,
+                chat_template: None,
+            },
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            api_key: None,
+            model_id: None,
+            max_time_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_serve_args() {
+        assert!(validate_serve_args(&serve_args()).is_ok());
+
+        for args in [
+            ServeArgs {
+                model_args: ModelArgs {
+                    model: None,
+                    ..serve_args().model_args
+                },
+                ..serve_args()
+            },
+            ServeArgs {
+                host: " ".to_string(),
+                ..serve_args()
+            },
+            ServeArgs {
+                api_key: Some(String::new()),
+                ..serve_args()
+            },
+        ] {
+            assert!(validate_serve_args(&args).is_err());
+        }
+    }
+
+    #[test]
+    fn test_default_model_id() {
+        let repo = ModelSource::HuggingFace {
+            repo: "unsloth/Qwen3-0.6B-GGUF".to_string(),
+            filename: None,
+        };
+        assert_eq!(default_model_id(&repo), "unsloth/Qwen3-0.6B-GGUF");
+
+        let file = ModelSource::Local {
+            folder: PathBuf::from("/models/qwen"),
+            filename: Some("Qwen3-0.6B-Q4_K_M.gguf".to_string()),
+        };
+        assert_eq!(default_model_id(&file), "Qwen3-0.6B-Q4_K_M.gguf");
+
+        let folder = ModelSource::Local {
+            folder: PathBuf::from("/models/qwen/"),
+            filename: None,
+        };
+        assert_eq!(default_model_id(&folder), "qwen");
+    }
+
+    #[test]
+    fn test_finish_reasons() {
+        let reason = |r: &str| openai_finish_reason(&FinishReason::Stopped(r.to_string()));
+        assert_eq!(reason("Maximum tokens reached"), "length");
+        assert_eq!(reason("Maximum tokens reached exactly (16)"), "length");
+        assert_eq!(reason("Time limit reached"), "length");
+        assert_eq!(reason("Stop token detected"), "stop");
+        assert_eq!(reason("End of sequence token detected"), "stop");
+    }
+
+    #[test]
+    fn test_stop_field_forms() {
+        let request = |body: Value| serde_json::from_value::<ChatCompletionRequest>(body).unwrap();
+
+        let one = request(json!({"messages": [], "stop": "\n"}));
+        assert_eq!(one.stop_sequences(), ["\n"]);
+        let many = request(json!({"messages": [], "stop": ["a", "b"], "model": "x", "n": 1}));
+        assert_eq!(many.stop_sequences(), ["a", "b"]);
+        assert!(request(json!({"messages": [], "stop": null}))
+            .stop_sequences()
+            .is_empty());
+
+        assert!(request(json!({"messages": [], "temperature": 2.5}))
+            .validate()
+            .is_err());
+        assert!(request(json!({"messages": [], "stop": [""]}))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret ", "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
@@ -0,0 +1,173 @@
+//! Stop sequences given on the command line with `--stop`.
+
+use anyhow::Result;
+use llama_agent::validation::generation_request::ParameterConfig;
+
+/// Parse a `--stop` value, turning escapes such as `\n` into the characters they stand for.
+///
+/// Supported escapes are `\n`, `\r`, `\t`, `\0`, `\\`, `\"` and `\'`.
+pub fn parse_stop_sequence(value: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some('0') => unescaped.push('\0'),
+            Some(c @ ('\\' | '"' | '\'')) => unescaped.push(c),
+            Some(other) => {
+                return Err(format!(
+                    "unknown escape '\\{}' (supported: \\n \\r \\t \\0 \\\\ \\\" \\')",
+                    other
+                ))
+            }
+            None => return Err("trailing backslash; use \\\\ for a literal backslash".to_string()),
+        }
+    }
+
+    if unescaped.is_empty() {
+        return Err("stop sequence cannot be empty".to_string());
+    }
+    Ok(unescaped)
+}
+
+/// Check stop sequences against the limits the request validator enforces
+pub fn validate_stop_sequences(stop_sequences: &[String]) -> Result<()> {
+    let limits = ParameterConfig::default();
+
+    if stop_sequences.len() > limits.max_stop_tokens {
+        return Err(anyhow::anyhow!(
+            "Too many stop sequences: {} (max {} allowed)",
+            stop_sequences.len(),
+            limits.max_stop_tokens
+        ));
+    }
+    for stop in stop_sequences {
+        if stop.trim().is_empty() {
+            return Err(anyhow::anyhow!(
+                "Stop sequences cannot be empty or whitespace only"
+            ));
+        }
+        if stop.len() > limits.max_stop_token_length {
+            return Err(anyhow::anyhow!(
+                "Stop sequence {:?} is {} bytes long (max {} allowed)",
+                stop,
+                stop.len(),
+                limits.max_stop_token_length
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The stop sequence that ended a response, found at its end.
+///
+/// Generation stops on the token that completes a stop sequence, so the sequence is the one
+/// ending last in the response; on a tie the longer one wins.
+pub fn fired_stop_sequence<'a>(response: &str, stop_sequences: &'a [String]) -> Option<&'a str> {
+    stop_sequences
+        .iter()
+        .filter_map(|stop| {
+            response
+                .rfind(stop.as_str())
+                .map(|start| (start + stop.len(), stop.len(), stop.as_str()))
+        })
+        .max_by_key(|&(end, len, _)| (end, len))
+        .map(|(_, _, stop)| stop)
+}
+
+/// Length of the longest end of `text` that a stop sequence starts with.
+///
+/// Streamed text this long may be the start of a stop sequence, so it is held back until the
+/// next chunk shows whether it is.
+pub fn partial_stop_len(text: &str, stop_sequences: &[String]) -> usize {
+    text.char_indices()
+        .map(|(start, _)| start)
+        .find(|&start| {
+            stop_sequences
+                .iter()
+                .any(|stop| stop.starts_with(&text[start..]))
+        })
+        .map_or(0, |start| text.len() - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stop_sequence_escapes() {
+        assert_eq!(parse_stop_sequence("\\n\\nUser:").unwrap(), "\n\nUser:");
+        assert_eq!(parse_stop_sequence("```").unwrap(), "```");
+        assert_eq!(parse_stop_sequence("a\\tb\\r\\n").unwrap(), "a\tb\r\n");
+        assert_eq!(parse_stop_sequence("\\\\n").unwrap(), "\\n");
+        assert_eq!(parse_stop_sequence("say \\\"hi\\'").unwrap(), "say \"hi'");
+        assert_eq!(parse_stop_sequence("née").unwrap(), "née");
+    }
+
+    #[test]
+    fn test_parse_stop_sequence_rejects_invalid() {
+        assert!(parse_stop_sequence("").is_err());
+        assert!(parse_stop_sequence("end\\").is_err());
+        assert!(parse_stop_sequence("\\q").is_err());
+    }
+
+    #[test]
+    fn test_validate_stop_sequences_bounds() {
+        let limits = ParameterConfig::default();
+        assert!(validate_stop_sequences(&[]).is_ok());
+
+        let at_limit = vec!["x".to_string(); limits.max_stop_tokens];
+        assert!(validate_stop_sequences(&at_limit).is_ok());
+        let too_many = vec!["x".to_string(); limits.max_stop_tokens + 1];
+        assert!(validate_stop_sequences(&too_many).is_err());
+
+        let longest = "y".repeat(limits.max_stop_token_length);
+        assert!(validate_stop_sequences(&[longest]).is_ok());
+        let too_long = "y".repeat(limits.max_stop_token_length + 1);
+        assert!(validate_stop_sequences(&[too_long]).is_err());
+
+        assert!(validate_stop_sequences(&[String::new()]).is_err());
+        assert!(validate_stop_sequences(&["\n".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_fired_stop_sequence() {
+        let stops = vec![
+            "\n\nUser:".to_string(),
+            "```".to_string(),
+            "User:".to_string(),
+        ];
+
+        assert_eq!(
+            fired_stop_sequence("Sure.\n\nUser:", &stops),
+            Some("\n\nUser:")
+        );
+        assert_eq!(
+            fired_stop_sequence("User: hi\nAnswer ```", &stops),
+            Some("```")
+        );
+        assert_eq!(fired_stop_sequence("No stop here", &stops), None);
+        assert_eq!(fired_stop_sequence("anything", &[]), None);
+    }
+
+    #[test]
+    fn test_partial_stop_len() {
+        let stops = vec!["\n\nUser:".to_string(), "```".to_string()];
+
+        assert_eq!(partial_stop_len("Sure.\n", &stops), 1);
+        assert_eq!(partial_stop_len("Sure.\n\nUs", &stops), 4);
+        assert_eq!(partial_stop_len("code ``", &stops), 2);
+        assert_eq!(partial_stop_len("Sure.\n\nUser:", &stops), 7);
+        assert_eq!(partial_stop_len("Sure.", &stops), 0);
+        assert_eq!(partial_stop_len("née", &["é!".to_string()]), 2);
+        assert_eq!(partial_stop_len("anything", &[]), 0);
+    }
+}
@@ -0,0 +1,341 @@
+//! Writing a generated response to the terminal as it streams in.
+
+use llama_agent::types::StreamEvent;
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// When streamed text is flushed to the output (`--stream-flush`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamFlush {
+    /// Write and flush every chunk as it arrives
+    #[default]
+    Token,
+    /// Buffer until a complete line is available
+    Line,
+    /// Coalesce chunks and flush on a timer
+    Interval(Duration),
+}
+
+impl StreamFlush {
+    /// Period of the flush timer, for `Interval` only
+    pub fn interval(&self) -> Option<Duration> {
+        match self {
+            StreamFlush::Interval(period) => Some(*period),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for StreamFlush {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "token" => Ok(StreamFlush::Token),
+            "line" => Ok(StreamFlush::Line),
+            _ => {
+                let millis = value
+                    .strip_prefix("interval:")
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid stream flush mode '{}'; expected token, line or interval:<ms>",
+                            value
+                        )
+                    })?
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid interval in '{}': {}", value, e))?;
+                if millis == 0 {
+                    return Err("interval must be at least 1 ms".to_string());
+                }
+                Ok(StreamFlush::Interval(Duration::from_millis(millis)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for StreamFlush {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamFlush::Token => write!(f, "token"),
+            StreamFlush::Line => write!(f, "line"),
+            StreamFlush::Interval(period) => write!(f, "interval:{}", period.as_millis()),
+        }
+    }
+}
+
+/// Writes the response to the output so that it ends with exactly one newline,
+/// coalescing streamed chunks according to a [`StreamFlush`] mode.
+///
+/// Trailing newlines of each piece are held back until more text follows, so
+/// streamed chunks and a final response come out the same.
+pub struct ResponseWriter<'a, W: Write> {
+    out: &'a mut W,
+    mode: StreamFlush,
+    /// Text waiting to be written
+    buffer: String,
+    /// Trailing newlines held back from the buffer
+    pending: String,
+}
+
+impl<'a, W: Write> ResponseWriter<'a, W> {
+    pub fn new(out: &'a mut W, mode: StreamFlush) -> Self {
+        Self {
+            out,
+            mode,
+            buffer: String::new(),
+            pending: String::new(),
+        }
+    }
+
+    /// Add the next piece of the response, writing whatever the flush mode allows
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        let body = text.trim_end_matches(['\n', '\r']);
+        if body.is_empty() {
+            self.pending.push_str(text);
+            return Ok(());
+        }
+
+        self.buffer.push_str(&self.pending);
+        self.buffer.push_str(body);
+        self.pending = text[body.len()..].to_string();
+
+        match self.mode {
+            StreamFlush::Token => self.emit(self.buffer.len()),
+            StreamFlush::Line => match self.buffer.rfind('\n') {
+                Some(end) => self.emit(end + 1),
+                None => Ok(()),
+            },
+            StreamFlush::Interval(_) => Ok(()),
+        }
+    }
+
+    /// Write everything buffered so far; called by the `Interval` timer
+    pub fn tick(&mut self) -> io::Result<()> {
+        self.emit(self.buffer.len())
+    }
+
+    /// Write the rest of the response, terminated by a single newline
+    pub fn finish(mut self) -> io::Result<()> {
+        self.buffer.push('\n');
+        self.emit(self.buffer.len())
+    }
+
+    /// Write and flush the first `end` bytes of the buffer
+    fn emit(&mut self, end: usize) -> io::Result<()> {
+        if end == 0 {
+            return Ok(());
+        }
+        self.out.write_all(self.buffer[..end].as_bytes())?;
+        self.out.flush()?;
+        self.buffer.drain(..end);
+        Ok(())
+    }
+}
+
+/// Status line describing a tool loop event, `None` for streamed text
+pub fn tool_status_line(event: &StreamEvent) -> Option<String> {
+    match event {
+        StreamEvent::TextChunk => None,
+        StreamEvent::ToolCallStarted { name, arguments } => {
+            Some(format!("[tool] {} {}", name, arguments))
+        }
+        StreamEvent::ToolCallCompleted {
+            name,
+            duration,
+            is_error,
+        } => Some(format!(
+            "[tool] {} {} in {:.1}s",
+            name,
+            if *is_error { "failed" } else { "finished" },
+            duration.as_secs_f64()
+        )),
+        StreamEvent::ToolCallProgress { name, progress } => {
+            let amount = match progress.total {
+                Some(total) => format!("{}/{}", progress.progress, total),
+                None => progress.progress.to_string(),
+            };
+            Some(match &progress.message {
+                Some(message) => format!("[tool] {} {} {}", name, amount, message),
+                None => format!("[tool] {} {}", name, amount),
+            })
+        }
+        StreamEvent::GenerationResumed => Some("[tool] resuming generation".to_string()),
+    }
+}
+
+/// Print a status line on stderr, dimmed when stderr is a terminal
+pub fn print_status_line(line: &str) {
+    if io::stderr().is_terminal() {
+        eprintln!("\x1b[2m{}\x1b[0m", line);
+    } else {
+        eprintln!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llama_agent::types::ToolProgress;
+
+    /// Records what was written between consecutive flushes
+    #[derive(Default)]
+    struct RecordingWriter {
+        unflushed: Vec<u8>,
+        segments: Vec<String>,
+    }
+
+    impl Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.unflushed.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let segment = String::from_utf8(std::mem::take(&mut self.unflushed)).unwrap();
+            self.segments.push(segment);
+            Ok(())
+        }
+    }
+
+    fn segments(mode: StreamFlush, pieces: &[&str]) -> Vec<String> {
+        let mut out = RecordingWriter::default();
+        let mut writer = ResponseWriter::new(&mut out, mode);
+        for piece in pieces {
+            writer.write(piece).unwrap();
+        }
+        writer.finish().unwrap();
+        assert!(out.unflushed.is_empty());
+        out.segments
+    }
+
+    fn write_pieces(pieces: &[&str]) -> String {
+        segments(StreamFlush::Token, pieces).concat()
+    }
+
+    #[test]
+    fn test_response_writer_adds_single_newline() {
+        assert_eq!(write_pieces(&["Hello", ", world"]), "Hello, world\n");
+    }
+
+    #[test]
+    fn test_response_writer_collapses_trailing_newlines() {
+        assert_eq!(write_pieces(&["Hello\n\n", "\n", "\r\n"]), "Hello\n");
+        assert_eq!(write_pieces(&["Hello world\n\n"]), "Hello world\n");
+    }
+
+    #[test]
+    fn test_response_writer_keeps_inner_newlines() {
+        assert_eq!(
+            write_pieces(&["line one\n", "\n", "line two\n"]),
+            "line one\n\nline two\n"
+        );
+    }
+
+    #[test]
+    fn test_response_writer_empty_response() {
+        assert_eq!(write_pieces(&[]), "\n");
+        assert_eq!(write_pieces(&["", "\n"]), "\n");
+    }
+
+    #[test]
+    fn test_token_mode_flushes_every_chunk() {
+        assert_eq!(
+            segments(StreamFlush::Token, &["Hel", "lo", " world"]),
+            ["Hel", "lo", " world", "\n"]
+        );
+    }
+
+    #[test]
+    fn test_line_mode_flushes_complete_lines() {
+        assert_eq!(
+            segments(
+                StreamFlush::Line,
+                &["Hello", " wor", "ld\n", "next", " line\nand", " more"]
+            ),
+            ["Hello world\n", "next line\n", "and more\n"]
+        );
+    }
+
+    #[test]
+    fn test_line_mode_writes_partial_line_on_finish() {
+        // A stream that ends early, e.g. after an error, still gets its partial text out
+        assert_eq!(
+            segments(StreamFlush::Line, &["no", " newline"]),
+            ["no newline\n"]
+        );
+    }
+
+    #[test]
+    fn test_interval_mode_flushes_on_tick() {
+        let mut out = RecordingWriter::default();
+        let mut writer =
+            ResponseWriter::new(&mut out, StreamFlush::Interval(Duration::from_millis(50)));
+        writer.write("a").unwrap();
+        writer.write("b\n").unwrap();
+        writer.tick().unwrap();
+        // Nothing new since the last tick
+        writer.tick().unwrap();
+        writer.write("c").unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(out.segments, ["ab", "\nc\n"]);
+    }
+
+    #[test]
+    fn test_parse_stream_flush() {
+        assert_eq!("token".parse(), Ok(StreamFlush::Token));
+        assert_eq!("line".parse(), Ok(StreamFlush::Line));
+        assert_eq!(
+            "interval:250".parse(),
+            Ok(StreamFlush::Interval(Duration::from_millis(250)))
+        );
+        assert_eq!(
+            StreamFlush::Interval(Duration::from_millis(250)).to_string(),
+            "interval:250"
+        );
+
+        for invalid in ["", "words", "interval:", "interval:abc", "interval:0"] {
+            assert!(invalid.parse::<StreamFlush>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_tool_status_lines() {
+        assert_eq!(tool_status_line(&StreamEvent::TextChunk), None);
+        let started = StreamEvent::ToolCallStarted {
+            name: "list_files".to_string(),
+            arguments: serde_json::json!({"path": "."}),
+        };
+        assert_eq!(
+            tool_status_line(&started).unwrap(),
+            r#"[tool] list_files {"path":"."}"#
+        );
+        let failed = StreamEvent::ToolCallCompleted {
+            name: "list_files".to_string(),
+            duration: Duration::from_millis(1500),
+            is_error: true,
+        };
+        assert_eq!(
+            tool_status_line(&failed).unwrap(),
+            "[tool] list_files failed in 1.5s"
+        );
+        let progress = StreamEvent::ToolCallProgress {
+            name: "index".to_string(),
+            progress: ToolProgress {
+                progress: 2.0,
+                total: Some(5.0),
+                message: Some("scanning src".to_string()),
+            },
+        };
+        assert_eq!(
+            tool_status_line(&progress).unwrap(),
+            "[tool] index 2/5 scanning src"
+        );
+        assert_eq!(
+            tool_status_line(&StreamEvent::GenerationResumed).unwrap(),
+            "[tool] resuming generation"
+        );
+    }
+}
@@ -1,7 +1,9 @@
 #[cfg(test)]
 mod parquet_compatibility_tests {
-    use crate::parquet_writer::ParquetWriter as MyParquetWriter;
+    use crate::parquet_writer::{shard_path, ParquetCodec, ParquetWriter as MyParquetWriter};
     use llama_embedding::types::EmbeddingResult;
+    use parquet::basic::Compression;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
     use polars::prelude::*;
     use tempfile::NamedTempFile;
 
@@ -174,4 +176,98 @@ mod parquet_compatibility_tests {
         let count = stats.column("len").unwrap().u32().unwrap().get(0).unwrap();
         assert_eq!(count, 500);
     }
+
+    /// Row count, per-column codecs and column names of a Parquet file, via the parquet crate
+    fn parquet_metadata(path: &std::path::Path) -> (i64, Vec<Compression>, Vec<String>) {
+        let reader = SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        let codecs = metadata
+            .row_groups()
+            .iter()
+            .flat_map(|row_group| row_group.columns().iter().map(|c| c.compression()))
+            .collect();
+        let columns = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|c| c.path().string())
+            .collect();
+        (metadata.file_metadata().num_rows(), codecs, columns)
+    }
+
+    fn sample_results(range: std::ops::Range<usize>) -> Vec<EmbeddingResult> {
+        range
+            .map(|i| EmbeddingResult::new(format!("text {}", i), vec![i as f32, 0.5], 4, 10))
+            .collect()
+    }
+
+    #[test]
+    fn test_sharded_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output = temp_dir.path().join("out.parquet");
+
+        let mut writer = MyParquetWriter::new(&output, 2, 10)
+            .unwrap()
+            .with_compression(ParquetCodec::Zstd)
+            .with_shard_size(4);
+        writer.write_batch(sample_results(0..3)).unwrap();
+        writer.write_batch(sample_results(3..10)).unwrap();
+
+        // The first two shards are complete before the writer is closed
+        let shards = writer.output_files();
+        assert_eq!(shards.len(), 3);
+        assert_eq!(parquet_metadata(&shards[0]).0, 4);
+        assert_eq!(parquet_metadata(&shards[1]).0, 4);
+
+        assert_eq!(writer.close().unwrap(), 10);
+        assert!(!output.exists());
+
+        let expected = [4, 4, 2];
+        let mut schema = None;
+        for (i, rows) in expected.iter().enumerate() {
+            let path = temp_dir.path().join(format!("out-{:05}.parquet", i + 1));
+            assert_eq!(path, shards[i]);
+
+            let (num_rows, codecs, columns) = parquet_metadata(&path);
+            assert_eq!(num_rows, *rows);
+            assert!(codecs.iter().all(|c| matches!(c, Compression::ZSTD(_))));
+            assert_eq!(*schema.get_or_insert_with(|| columns.clone()), columns);
+        }
+        assert!(!temp_dir.path().join("out-00004.parquet").exists());
+    }
+
+    #[test]
+    fn test_compression_and_row_group_size() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        let mut writer = MyParquetWriter::new(&temp_path, 2, 10)
+            .unwrap()
+            .with_compression(ParquetCodec::Snappy)
+            .with_row_group_size(5);
+        writer.write_batch(sample_results(0..20)).unwrap();
+        assert_eq!(writer.close().unwrap(), 20);
+
+        let (num_rows, codecs, _) = parquet_metadata(&temp_path);
+        assert_eq!(num_rows, 20);
+        assert!(codecs.iter().all(|c| *c == Compression::SNAPPY));
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&temp_path).unwrap()).unwrap();
+        // Polars treats the size as a target, so only check that the rows were split
+        assert!(reader.metadata().num_row_groups() > 1);
+    }
+
+    #[test]
+    fn test_shard_path() {
+        let base = std::path::Path::new("/data/out.parquet");
+        assert_eq!(
+            shard_path(base, 1),
+            std::path::Path::new("/data/out-00001.parquet")
+        );
+        assert_eq!(
+            shard_path(std::path::Path::new("out"), 12),
+            std::path::Path::new("out-00012")
+        );
+    }
 }
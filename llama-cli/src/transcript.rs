@@ -0,0 +1,210 @@
+//! Conversations seeded from plain-text transcripts given with `--transcript`.
+//!
+//! A transcript is a sequence of turns, each starting with `User:`, `Assistant:` or
+//! `System:` at the start of a line:
+//!
+//! ```text
+//! System: You are terse.
+//! User: What is 2 + 2?
+//! Assistant: 4
+//! User:
+//! And squared?
+//! ```
+//!
+//! A turn's content runs until the next prefix, may start on the prefix line and keeps
+//! its blank lines; blank lines around it are dropped. Any other capitalized word
+//! followed by a colon at the start of a line is an unknown prefix, so indent content
+//! lines such as `Note: ...` to keep them literal.
+
+use llama_agent::types::{Message, MessageRole};
+use std::path::Path;
+use std::time::SystemTime;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TranscriptError {
+    #[error("Transcript line {line}: unknown role prefix '{prefix}:'\n💡 Start turns with User:, Assistant: or System:, or indent the line to keep it as content")]
+    UnknownPrefix { line: usize, prefix: String },
+
+    #[error("Transcript line {line}: text before the first turn\n💡 Start the transcript with a User:, Assistant: or System: line")]
+    MissingPrefix { line: usize },
+
+    #[error("Transcript line {line}: {role} turn has no content")]
+    EmptyTurn { line: usize, role: &'static str },
+
+    #[error("Transcript contains no turns\n💡 Add at least one User:, Assistant: or System: line")]
+    Empty,
+}
+
+/// Read and parse the transcript at `path`
+pub fn load_transcript(path: &Path) -> anyhow::Result<Vec<Message>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read transcript {}: {}", path.display(), e))?;
+    parse_transcript(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+/// Parse a transcript into messages, in order
+pub fn parse_transcript(text: &str) -> Result<Vec<Message>, TranscriptError> {
+    let mut messages = Vec::new();
+    // Role, line number and content lines of the turn being read
+    let mut turn: Option<(MessageRole, usize, Vec<&str>)> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        match role_prefix(line, line_number)? {
+            Some((role, rest)) => {
+                if let Some(finished) = turn.take() {
+                    messages.push(finish_turn(finished)?);
+                }
+                turn = Some((role, line_number, vec![rest.trim_start()]));
+            }
+            None => match &mut turn {
+                Some((_, _, lines)) => lines.push(line),
+                None if line.trim().is_empty() => {}
+                None => return Err(TranscriptError::MissingPrefix { line: line_number }),
+            },
+        }
+    }
+    if let Some(finished) = turn {
+        messages.push(finish_turn(finished)?);
+    }
+
+    if messages.is_empty() {
+        return Err(TranscriptError::Empty);
+    }
+    Ok(messages)
+}
+
+/// The role a line starts a turn for and the rest of the line, or `None` for a
+/// content line
+fn role_prefix(
+    line: &str,
+    line_number: usize,
+) -> Result<Option<(MessageRole, &str)>, TranscriptError> {
+    let Some((word, rest)) = line.split_once(':') else {
+        return Ok(None);
+    };
+    let is_prefix = word.starts_with(|c: char| c.is_ascii_uppercase())
+        && word.chars().all(|c| c.is_ascii_alphabetic())
+        && (rest.is_empty() || rest.starts_with(char::is_whitespace));
+    if !is_prefix {
+        return Ok(None);
+    }
+
+    let role = match word {
+        "User" => MessageRole::User,
+        "Assistant" => MessageRole::Assistant,
+        "System" => MessageRole::System,
+        _ => {
+            return Err(TranscriptError::UnknownPrefix {
+                line: line_number,
+                prefix: word.to_string(),
+            })
+        }
+    };
+    Ok(Some((role, rest)))
+}
+
+fn finish_turn(
+    (role, line, lines): (MessageRole, usize, Vec<&str>),
+) -> Result<Message, TranscriptError> {
+    let first = lines.iter().position(|line| !line.trim().is_empty());
+    let last = lines.iter().rposition(|line| !line.trim().is_empty());
+    let (Some(first), Some(last)) = (first, last) else {
+        return Err(TranscriptError::EmptyTurn {
+            line,
+            role: role.as_str(),
+        });
+    };
+
+    Ok(Message {
+        role,
+        content: lines[first..=last].join("\n").trim_end().to_string(),
+        tool_call_id: None,
+        tool_name: None,
+        timestamp: SystemTime::now(),
+        attachments: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turns(text: &str) -> Vec<(MessageRole, String)> {
+        parse_transcript(text)
+            .unwrap()
+            .into_iter()
+            .map(|message| (message.role, message.content))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_transcript_turns() {
+        let text = include_str!("../tests/data/transcripts/conversation.txt");
+        assert_eq!(
+            turns(text),
+            vec![
+                (
+                    MessageRole::System,
+                    "You are a terse assistant.".to_string()
+                ),
+                (MessageRole::User, "What is 2 + 2?".to_string()),
+                (MessageRole::Assistant, "4".to_string()),
+                (
+                    MessageRole::User,
+                    "Now write a haiku about it.\n\nKeep it short.\n  Note: no rhyming."
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_transcript_trailing_system_turn() {
+        let text = include_str!("../tests/data/transcripts/trailing_system.txt");
+        let parsed = turns(text);
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(
+            parsed[2],
+            (
+                MessageRole::System,
+                "Answer in French from now on.".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_transcript_errors() {
+        assert_eq!(
+            parse_transcript("User: Hi\nBot: Hello").unwrap_err(),
+            TranscriptError::UnknownPrefix {
+                line: 2,
+                prefix: "Bot".to_string()
+            }
+        );
+        assert_eq!(
+            parse_transcript("\nHello\nUser: Hi").unwrap_err(),
+            TranscriptError::MissingPrefix { line: 2 }
+        );
+        assert_eq!(
+            parse_transcript("User: Hi\nAssistant:\n\nUser: Still there?").unwrap_err(),
+            TranscriptError::EmptyTurn {
+                line: 2,
+                role: "assistant"
+            }
+        );
+        assert_eq!(
+            parse_transcript("\n\n").unwrap_err(),
+            TranscriptError::Empty
+        );
+        // Only capitalized words followed by whitespace are prefixes
+        assert_eq!(
+            turns("User: see https://example.com\nnote: fine\nTime:12:30"),
+            vec![(
+                MessageRole::User,
+                "see https://example.com\nnote: fine\nTime:12:30".to_string()
+            )]
+        );
+    }
+}
@@ -1,5 +1,11 @@
 use anyhow::Result;
-use llama_cli::{run_generate, GenerateArgs};
+use llama_agent::test_support::{agent_with_fake_model, FakeModel};
+use llama_agent::types::ModelSource;
+use llama_cli::{
+    build_agent_config, run_generate, run_generate_with_agent, run_generate_with_writer,
+    validate_generate_args, BatchResult, CliError, GenerateArgs, ModelArgs, StreamFlush,
+};
+use std::time::Duration;
 use tokio::test;
 use tracing_subscriber;
 
@@ -15,19 +21,47 @@ async fn test_cli_integration_with_qwen_model() -> Result<()> {
     // Create Args struct with the same parameters as the manual test
     // cargo run --package llama-agent-cli -- --model unsloth/Qwen3-0.6B-GGUF --prompt "What is an apple?" --limit 64
     let args = GenerateArgs {
-        model: "unsloth/Qwen3-0.6B-GGUF".to_string(),
-        filename: None,
-        prompt: "What is an apple?".to_string(),
+        model_args: ModelArgs {
+            config: None,
+            model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+            filename: None,
+            debug: false, // Keep debug off to avoid verbose output in tests
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("What is an apple?".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
         limit: 64,
+        max_time_ms: None,
         temperature: 0.7,
         top_p: 0.9,
-        debug: false, // Keep debug off to avoid verbose output in tests
-        batch_size: 512,
-        max_queue_size: 10,
-        request_timeout: 120,
-        worker_threads: 1,
-        max_sessions: 10,
-        session_timeout: 3600,
+        batch_size: Some(512),
+        max_queue_size: Some(10),
+        request_timeout: Some(120),
+        worker_threads: Some(1),
+        max_sessions: Some(10),
+        session_timeout: Some(3600),
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
     };
 
     // Run the agent and verify it completes successfully
@@ -55,6 +89,130 @@ async fn test_cli_integration_with_qwen_model() -> Result<()> {
     }
 }
 
+/// With --quiet --no-stream the writer receives only the final text and a single newline
+#[test]
+async fn test_quiet_no_stream_writes_only_response() -> Result<()> {
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::WARN)
+        .with_writer(std::io::stderr)
+        .try_init();
+
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+            filename: None,
+            debug: true,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("What is an apple?".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: Some(512),
+        max_queue_size: Some(10),
+        request_timeout: Some(120),
+        worker_threads: Some(1),
+        max_sessions: Some(10),
+        session_timeout: Some(3600),
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: true,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: true,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+
+    let mut out = Vec::new();
+    let response = run_generate_with_writer(args, &mut out).await?;
+    let printed = String::from_utf8(out)?;
+
+    assert_eq!(
+        printed,
+        format!("{}\n", response.trim_end_matches(['\n', '\r']))
+    );
+    assert!(
+        !printed.contains(&"=".repeat(20)),
+        "No separators on stdout"
+    );
+
+    Ok(())
+}
+
+/// A failed run writes nothing to the output and keeps its exit code
+#[test]
+async fn test_failed_run_writes_nothing() -> Result<()> {
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("   ".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: None,
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: true,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: true,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+
+    let mut out = Vec::new();
+    let result = run_generate_with_writer(args, &mut out).await;
+
+    assert_eq!(result.unwrap_err().exit_code(), 2);
+    assert!(out.is_empty());
+
+    Ok(())
+}
+
 /// Test CLI argument validation
 #[test]
 async fn test_cli_argument_validation() -> Result<()> {
@@ -64,19 +222,47 @@ async fn test_cli_argument_validation() -> Result<()> {
 
     // Test with empty model - should fail validation
     let args_empty_model = GenerateArgs {
-        model: "".to_string(),
-        filename: None,
-        prompt: "Test prompt".to_string(),
+        model_args: ModelArgs {
+            config: None,
+            model: Some("".to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("Test prompt".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
         limit: 64,
+        max_time_ms: None,
         temperature: 0.7,
         top_p: 0.9,
-        debug: false,
-        batch_size: 512,
-        max_queue_size: 10,
-        request_timeout: 120,
-        worker_threads: 1,
-        max_sessions: 10,
-        session_timeout: 3600,
+        batch_size: Some(512),
+        max_queue_size: Some(10),
+        request_timeout: Some(120),
+        worker_threads: Some(1),
+        max_sessions: Some(10),
+        session_timeout: Some(3600),
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
     };
 
     let result = run_generate(args_empty_model).await;
@@ -84,47 +270,129 @@ async fn test_cli_argument_validation() -> Result<()> {
 
     // Test with empty prompt - should fail validation
     let args_empty_prompt = GenerateArgs {
-        model: "unsloth/Qwen3-0.6B-GGUF".to_string(),
-        filename: None,
-        prompt: "".to_string(),
+        model_args: ModelArgs {
+            config: None,
+            model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
         limit: 64,
+        max_time_ms: None,
         temperature: 0.7,
         top_p: 0.9,
-        debug: false,
-        batch_size: 512,
-        max_queue_size: 10,
-        request_timeout: 120,
-        worker_threads: 1,
-        max_sessions: 10,
-        session_timeout: 3600,
+        batch_size: Some(512),
+        max_queue_size: Some(10),
+        request_timeout: Some(120),
+        worker_threads: Some(1),
+        max_sessions: Some(10),
+        session_timeout: Some(3600),
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
     };
 
     let result = run_generate(args_empty_prompt).await;
-    assert!(result.is_err(), "Should fail validation with empty prompt");
+    assert!(
+        matches!(result, Err(CliError::Validation(_))),
+        "Should fail validation with empty prompt"
+    );
+    assert_eq!(result.unwrap_err().exit_code(), 2);
 
     // Test with invalid temperature - should fail validation
     let args_invalid_temp = GenerateArgs {
-        model: "unsloth/Qwen3-0.6B-GGUF".to_string(),
-        filename: None,
-        prompt: "Test prompt".to_string(),
+        model_args: ModelArgs {
+            config: None,
+            model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("Test prompt".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
         limit: 64,
+        max_time_ms: None,
         temperature: 3.0, // Invalid - should be <= 2.0
         top_p: 0.9,
-        debug: false,
-        batch_size: 512,
-        max_queue_size: 10,
-        request_timeout: 120,
-        worker_threads: 1,
-        max_sessions: 10,
-        session_timeout: 3600,
+        batch_size: Some(512),
+        max_queue_size: Some(10),
+        request_timeout: Some(120),
+        worker_threads: Some(1),
+        max_sessions: Some(10),
+        session_timeout: Some(3600),
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
     };
 
-    let result = run_generate(args_invalid_temp).await;
+    let result = run_generate(args_invalid_temp.clone()).await;
     assert!(
         result.is_err(),
         "Should fail validation with invalid temperature"
     );
 
+    // Test too many stop sequences
+    let args_many_stops = GenerateArgs {
+        temperature: 0.7,
+        stop: vec!["x".to_string(); 21],
+        ..args_invalid_temp.clone()
+    };
+    assert!(
+        validate_generate_args(&args_many_stops).is_err(),
+        "Should fail validation with too many stop sequences"
+    );
+
+    // A limit of 0 only decodes the prompt
+    let args_prefill_only = GenerateArgs {
+        temperature: 0.7,
+        limit: 0,
+        ..args_invalid_temp.clone()
+    };
+    assert!(
+        validate_generate_args(&args_prefill_only).is_ok(),
+        "Should accept a token limit of 0"
+    );
+
     Ok(())
 }
 
@@ -137,19 +405,47 @@ async fn test_cli_with_different_token_limits() -> Result<()> {
 
     // Test with a very small token limit
     let args_small_limit = GenerateArgs {
-        model: "unsloth/Qwen3-0.6B-GGUF".to_string(),
-        filename: None,
-        prompt: "What is an apple?".to_string(),
+        model_args: ModelArgs {
+            config: None,
+            model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("What is an apple?".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
         limit: 10, // Very small limit
+        max_time_ms: None,
         temperature: 0.7,
         top_p: 0.9,
-        debug: false,
-        batch_size: 512,
-        max_queue_size: 10,
-        request_timeout: 120,
-        worker_threads: 1,
-        max_sessions: 10,
-        session_timeout: 3600,
+        batch_size: Some(512),
+        max_queue_size: Some(10),
+        request_timeout: Some(120),
+        worker_threads: Some(1),
+        max_sessions: Some(10),
+        session_timeout: Some(3600),
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
     };
 
     // This should still work, just with a shorter response
@@ -166,3 +462,740 @@ async fn test_cli_with_different_token_limits() -> Result<()> {
 
     Ok(())
 }
+
+/// Test that explicit CLI flags take precedence over values from a --config file
+#[test]
+async fn test_config_file_precedence_with_cli_flags() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let config_path = temp_dir.path().join("agent.toml");
+    std::fs::write(
+        &config_path,
+        r#"
+[model]
+batch_size = 256
+metadata_ttl_secs = 3600
+
+[model.source.HuggingFace]
+repo = "unsloth/Qwen3-0.6B-GGUF"
+
+[queue_config]
+max_queue_size = 42
+request_timeout = "45s"
+worker_threads = 2
+
+[session_config]
+session_timeout = "10m"
+"#,
+    )?;
+
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: Some(config_path),
+            model: None,
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("Test prompt".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: None,
+        max_queue_size: Some(7),
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: Some(60),
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+
+    let config = build_agent_config(&args)?;
+
+    // Values only in the file are kept
+    assert_eq!(config.model.batch_size, 256);
+    assert_eq!(config.queue_config.request_timeout, Duration::from_secs(45));
+    assert_eq!(config.queue_config.worker_threads, 2);
+    assert!(matches!(
+        &config.model.source,
+        ModelSource::HuggingFace { repo, .. } if repo == "unsloth/Qwen3-0.6B-GGUF"
+    ));
+
+    // Explicit flags override the file
+    assert_eq!(config.queue_config.max_queue_size, 7);
+    assert_eq!(
+        config.session_config.session_timeout,
+        Duration::from_secs(60)
+    );
+
+    // An explicit --model replaces the file's model source but keeps its other settings
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            model: Some("org/other-model".to_string()),
+            ..args.model_args
+        },
+        ..args
+    };
+    let config = build_agent_config(&args)?;
+    assert!(matches!(
+        &config.model.source,
+        ModelSource::HuggingFace { repo, .. } if repo == "org/other-model"
+    ));
+    assert_eq!(config.model.batch_size, 256);
+    assert_eq!(config.model.metadata_ttl_secs, 3600);
+    assert!(!config.model.refresh_metadata);
+
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            refresh_model_metadata: true,
+            cache_dir: None,
+            ..args.model_args
+        },
+        ..args
+    };
+    assert!(build_agent_config(&args)?.model.refresh_metadata);
+
+    // --cache-dir is passed on to the loader, which prefers it to LLAMA_CACHE_DIR
+    assert_eq!(config.model.cache_dir, None);
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            cache_dir: Some(temp_dir.path().join("models")),
+            ..args.model_args
+        },
+        ..args
+    };
+    assert_eq!(
+        build_agent_config(&args)?.model.cache_dir,
+        Some(temp_dir.path().join("models"))
+    );
+
+    // --local-search-depth overrides model.local_search_depth
+    assert_eq!(config.model.local_search_depth, 0);
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            local_search_depth: Some(2),
+            ..args.model_args
+        },
+        ..args
+    };
+    assert_eq!(build_agent_config(&args)?.model.local_search_depth, 2);
+
+    Ok(())
+}
+
+/// A --model pointing straight at a .gguf file is accepted and split into folder and filename
+#[test]
+async fn test_model_arg_accepts_gguf_file_path() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let model_file = temp_dir.path().join("qwen-q4_k_m.gguf");
+    std::fs::write(&model_file, b"GGUF")?;
+
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some(model_file.to_string_lossy().to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("Test prompt".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: None,
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+
+    validate_generate_args(&args)?;
+
+    let config = build_agent_config(&args)?;
+    match &config.model.source {
+        ModelSource::Local { folder, filename } => {
+            assert_eq!(folder, temp_dir.path());
+            assert_eq!(filename.as_deref(), Some("qwen-q4_k_m.gguf"));
+        }
+        other => panic!("Expected a local model source, got {:?}", other),
+    }
+    config.model.validate()?;
+
+    // A file that is not GGUF is still rejected
+    let text_file = temp_dir.path().join("notes.txt");
+    std::fs::write(&text_file, b"not a model")?;
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            model: Some(text_file.to_string_lossy().to_string()),
+            ..args.model_args
+        },
+        ..args
+    };
+    let error = validate_generate_args(&args).unwrap_err().to_string();
+    assert!(error.contains(".gguf extension"), "{}", error);
+
+    // A missing file reports whether its folder exists
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            model: Some(
+                temp_dir
+                    .path()
+                    .join("missing.gguf")
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            ..args.model_args
+        },
+        ..args
+    };
+    let error = validate_generate_args(&args).unwrap_err().to_string();
+    assert!(
+        error.contains("missing.gguf does not exist in folder"),
+        "{}",
+        error
+    );
+
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            model: Some(
+                temp_dir
+                    .path()
+                    .join("nowhere/missing.gguf")
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            ..args.model_args
+        },
+        ..args
+    };
+    let error = validate_generate_args(&args).unwrap_err().to_string();
+    assert!(error.contains("Local model path not found"), "{}", error);
+
+    Ok(())
+}
+
+/// A --model URL becomes a Url source; plain http needs --allow-http
+#[test]
+async fn test_model_arg_accepts_url() -> Result<()> {
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("https://models.example.com/qwen-q4_k_m.gguf".to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("Test prompt".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: None,
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+
+    validate_generate_args(&args)?;
+    let config = build_agent_config(&args)?;
+    assert_eq!(
+        config.model.source,
+        ModelSource::Url {
+            url: "https://models.example.com/qwen-q4_k_m.gguf".to_string(),
+            filename: None,
+            sha256: None,
+        }
+    );
+    config.model.validate()?;
+
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            model: Some("http://models.example.com/qwen-q4_k_m.gguf".to_string()),
+            ..args.model_args
+        },
+        ..args
+    };
+    let error = validate_generate_args(&args).unwrap_err().to_string();
+    assert!(error.contains("--allow-http"));
+
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            allow_http: true,
+            force_load: false,
+            ..args.model_args
+        },
+        ..args
+    };
+    validate_generate_args(&args)?;
+    let config = build_agent_config(&args)?;
+    assert!(config.model.allow_http);
+    config.model.validate()?;
+
+    Ok(())
+}
+
+/// --prompt-arg values must be key=value pairs and need a --prompt-template
+#[test]
+async fn test_prompt_template_args_validation() -> Result<()> {
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("unsloth/Qwen3-0.6B-GGUF".to_string()),
+            filename: None,
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("Test prompt".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: None,
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: Some("code_review".to_string()),
+        prompt_args: vec!["language=rust".to_string(), "style=a=b".to_string()],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+    validate_generate_args(&args)?;
+
+    let malformed = GenerateArgs {
+        prompt_args: vec!["language".to_string()],
+        ..args.clone()
+    };
+    assert!(validate_generate_args(&malformed).is_err());
+
+    let without_template = GenerateArgs {
+        prompt_template: None,
+        ..args
+    };
+    assert!(validate_generate_args(&without_template).is_err());
+
+    Ok(())
+}
+
+#[test]
+async fn test_dry_run_renders_prompt_without_generating() -> Result<()> {
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("/tmp".to_string()),
+            filename: Some("test.gguf".to_string()),
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("What is an apple?".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: Some(64),
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: true,
+        dry_run_output: None,
+    };
+    let model = FakeModel::new().with_reply(["Never", " sampled"]);
+    let agent = agent_with_fake_model(build_agent_config(&args)?, model.clone())?;
+
+    let mut out = Vec::new();
+    let prompt = run_generate_with_agent(&agent, &args, &mut out).await?;
+    let printed = String::from_utf8(out)?;
+
+    assert!(prompt.contains("What is an apple?"));
+    assert!(printed.starts_with(&prompt));
+    // The fake tokenizer counts words plus the beginning-of-sequence token
+    let tokens = prompt.split_whitespace().count() + 1;
+    assert!(printed.contains(&format!("Prompt tokens: {}\n", tokens)));
+    assert!(printed.contains("Context size: 8192 tokens (batch size 64)"));
+    assert_eq!(model.tokens_sampled(), 0);
+    assert_eq!(model.remaining_replies(), 1);
+
+    // The prompt goes to the file; in quiet mode the counts go to stderr
+    let dir = tempfile::TempDir::new()?;
+    let path = dir.path().join("prompt.txt");
+    let args = GenerateArgs {
+        dry_run_output: Some(path.clone()),
+        quiet: true,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        ..args
+    };
+    let mut out = Vec::new();
+    run_generate_with_agent(&agent, &args, &mut out).await?;
+    assert_eq!(std::fs::read_to_string(&path)?, prompt);
+    assert!(out.is_empty());
+
+    Ok(())
+}
+
+/// --transcript seeds the session before --prompt, which it makes optional when the
+/// transcript ends with a User turn
+#[test]
+async fn test_transcript_seeds_session() -> Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let transcript = dir.path().join("chat.txt");
+    std::fs::write(
+        &transcript,
+        "System: Be brief.\nUser: Name a fruit.\nAssistant: Apple.\n\nUser:\nAnother one?\n",
+    )?;
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("/tmp".to_string()),
+            filename: Some("test.gguf".to_string()),
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: None,
+        transcript: Some(transcript.clone()),
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: Some(64),
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: false,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: true,
+        dry_run_output: None,
+    };
+    validate_generate_args(&args)?;
+    let agent = agent_with_fake_model(build_agent_config(&args)?, FakeModel::new())?;
+
+    let mut out = Vec::new();
+    let prompt = run_generate_with_agent(&agent, &args, &mut out).await?;
+    let turns = ["Be brief.", "Name a fruit.", "Apple.", "Another one?"];
+    let positions: Vec<usize> = turns.iter().map(|t| prompt.find(t).unwrap()).collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", prompt);
+
+    // Ending with an Assistant turn needs a prompt to answer
+    std::fs::write(&transcript, "User: Name a fruit.\nAssistant: Apple.\n")?;
+    let error = validate_generate_args(&args).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("last turn is from the assistant"));
+    let args = GenerateArgs {
+        prompt: Some("Another one?".to_string()),
+        ..args
+    };
+    validate_generate_args(&args)?;
+
+    // Parse errors name the file and line
+    std::fs::write(&transcript, "User: Hi\nBot: Hello\n")?;
+    let error = validate_generate_args(&args).unwrap_err().to_string();
+    assert!(error.contains("chat.txt: Transcript line 2: unknown role prefix 'Bot:'"));
+
+    Ok(())
+}
+
+/// --prompts-file runs every prompt on one agent; a failing prompt is recorded and the
+/// rest still run
+#[test]
+async fn test_prompts_file_writes_results_per_prompt() -> Result<()> {
+    let dir = tempfile::TempDir::new()?;
+    let prompts = dir.path().join("prompts.jsonl");
+    std::fs::write(
+        &prompts,
+        "Name a fruit\n{\"id\": \"pear\", \"prompt\": \"Describe a pear\"}\n\n{\"prompt\": \"Please explode\", \"id\": \"boom\"}\n{\"id\": \"broken\"\n",
+    )?;
+    let output = dir.path().join("results.jsonl");
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("/tmp".to_string()),
+            filename: Some("test.gguf".to_string()),
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: None,
+        transcript: None,
+        prompts_file: Some(prompts),
+        concurrency: 2,
+        output: Some(output.clone()),
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: Some(64),
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: true,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: false,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+    validate_generate_args(&args)?;
+    // Every request takes a reply, the one that panics included
+    let model = FakeModel::new()
+        .with_reply(["A", " reply"])
+        .with_reply(["A", " reply"])
+        .with_reply(["A", " reply"])
+        .with_panic_on("explode");
+    let agent = agent_with_fake_model(build_agent_config(&args)?, model)?;
+
+    let mut out = Vec::new();
+    let summary = run_generate_with_agent(&agent, &args, &mut out).await?;
+    assert_eq!(summary, "Prompts: 2 succeeded, 2 failed");
+    assert!(out.is_empty());
+
+    let mut results: Vec<BatchResult> = std::fs::read_to_string(&output)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    results.sort_by(|a, b| a.id.cmp(&b.id));
+    let ids: Vec<&str> = results.iter().map(|r| r.id.as_str()).collect();
+    assert_eq!(ids, ["1", "5", "boom", "pear"]);
+
+    let fruit = &results[0];
+    assert_eq!(fruit.prompt, "Name a fruit");
+    assert_eq!(fruit.generated_text.as_deref(), Some("A reply"));
+    assert_eq!(fruit.tokens, Some(2));
+    assert!(fruit.finish_reason.is_some() && fruit.error.is_none());
+    assert!(results[1]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("invalid JSON prompt"));
+    assert!(results[2]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("Worker panicked"));
+    assert_eq!(results[3].generated_text.as_deref(), Some("A reply"));
+
+    Ok(())
+}
+
+/// The generated text is post-processed unless --raw-output is given
+#[test]
+async fn test_raw_output_skips_post_processing() -> Result<()> {
+    let args = GenerateArgs {
+        model_args: ModelArgs {
+            config: None,
+            model: Some("/tmp".to_string()),
+            filename: Some("test.gguf".to_string()),
+            debug: false,
+            allow_http: false,
+            force_load: false,
+            refresh_model_metadata: false,
+            cache_dir: None,
+            local_search_depth: None,
+            chat_template: None,
+        },
+        prompt: Some("Name a city".to_string()),
+        transcript: None,
+        prompts_file: None,
+        concurrency: 1,
+        output: None,
+        limit: 64,
+        max_time_ms: None,
+        temperature: 0.7,
+        top_p: 0.9,
+        batch_size: Some(64),
+        max_queue_size: None,
+        request_timeout: None,
+        worker_threads: None,
+        max_sessions: None,
+        session_timeout: None,
+        embed_prompt: false,
+        prompt_template: None,
+        prompt_args: vec![],
+        explain_model_choice: false,
+        stop_token_ids: vec![],
+        stop: vec![],
+        quiet: true,
+        raw_output: false,
+        debug_tokens: false,
+        stats: false,
+        no_stream: true,
+        stream_flush: StreamFlush::Token,
+        dry_run: false,
+        dry_run_output: None,
+    };
+    let reply = [" ", "Paris", "\n", "<|im_end|>"];
+
+    let model = FakeModel::new().with_reply(reply);
+    let agent = agent_with_fake_model(build_agent_config(&args)?, model)?;
+    let mut out = Vec::new();
+    assert_eq!(
+        run_generate_with_agent(&agent, &args, &mut out).await?,
+        "Paris"
+    );
+
+    let args = GenerateArgs {
+        raw_output: true,
+        debug_tokens: false,
+        stats: false,
+        ..args
+    };
+    let model = FakeModel::new().with_reply(reply);
+    let agent = agent_with_fake_model(build_agent_config(&args)?, model)?;
+    let mut out = Vec::new();
+    assert_eq!(
+        run_generate_with_agent(&agent, &args, &mut out).await?,
+        " Paris\n<|im_end|>"
+    );
+
+    Ok(())
+}
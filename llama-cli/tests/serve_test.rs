@@ -0,0 +1,340 @@
+use llama_agent::test_support::{agent_with_fake_model, FakeModel};
+use llama_agent::types::{AgentAPI, AgentConfig, SessionFilter};
+use llama_agent::AgentServer;
+use llama_cli::{serve, ServeOptions};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+const MODEL_ID: &str = "fake-model";
+
+/// A server on an ephemeral port, stopped when dropped
+struct TestServer {
+    base_url: String,
+    agent: Arc<AgentServer>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl TestServer {
+    async fn start(model: &FakeModel, api_key: Option<&str>) -> Self {
+        Self::start_with_config(model, api_key, AgentConfig::default()).await
+    }
+
+    async fn start_with_config(
+        model: &FakeModel,
+        api_key: Option<&str>,
+        config: AgentConfig,
+    ) -> Self {
+        let agent = Arc::new(
+            agent_with_fake_model(config, model.clone())
+                .expect("Agent with a fake model should start without a model file"),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let options = ServeOptions {
+            model_id: MODEL_ID.to_string(),
+            api_key: api_key.map(String::from),
+            max_duration: None,
+        };
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(serve(listener, agent.clone(), options, async {
+            let _ = stopped.await;
+        }));
+
+        Self {
+            base_url,
+            agent,
+            _shutdown: shutdown,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    async fn chat(&self, body: Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(self.url("/v1/chat/completions"))
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Requests run in sessions of their own that must be gone once answered
+    async fn assert_no_sessions(&self) {
+        let sessions = self
+            .agent
+            .list_sessions(SessionFilter::default())
+            .await
+            .unwrap();
+        assert!(sessions.is_empty(), "leftover sessions: {:?}", sessions);
+    }
+}
+
+fn chat_request(prompt: &str) -> Value {
+    json!({
+        "model": MODEL_ID,
+        "messages": [
+            {"role": "system", "content": "You are terse."},
+            {"role": "user", "content": prompt},
+        ],
+    })
+}
+
+/// The JSON payloads of a server-sent event stream, up to and excluding `[DONE]`
+fn sse_payloads(body: &str) -> Vec<Value> {
+    let data: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(data.last(), Some(&"[DONE]"), "stream must end with [DONE]");
+    data[..data.len() - 1]
+        .iter()
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_chat_completion() {
+    let model = FakeModel::new().with_reply(["Hello", ",", " world", "!"]);
+    let server = TestServer::start(&model, None).await;
+
+    let response = server.chat(chat_request("Say hello")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["model"], MODEL_ID);
+    assert!(body["id"].as_str().unwrap().starts_with("chatcmpl-"));
+    let choice = &body["choices"][0];
+    assert_eq!(choice["message"]["role"], "assistant");
+    assert_eq!(choice["message"]["content"], "Hello, world!");
+    assert_eq!(choice["finish_reason"], "stop");
+
+    let usage = &body["usage"];
+    let prompt_tokens = usage["prompt_tokens"].as_u64().unwrap();
+    assert!(prompt_tokens > 0);
+    assert_eq!(usage["completion_tokens"], 4);
+    assert_eq!(usage["total_tokens"], prompt_tokens + 4);
+
+    server.assert_no_sessions().await;
+}
+
+#[tokio::test]
+async fn test_chat_completion_maps_limits_and_stop() {
+    let model = FakeModel::new()
+        .with_reply(["one", " two", " three"])
+        .with_reply(["Answer", ".", "\n\n", "User", ":", " more"]);
+    let server = TestServer::start(&model, None).await;
+
+    let mut request = chat_request("Count");
+    request["max_tokens"] = json!(2);
+    request["temperature"] = json!(0.2);
+    request["top_p"] = json!(0.9);
+    let body: Value = server.chat(request).await.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "one two");
+    assert_eq!(body["choices"][0]["finish_reason"], "length");
+    assert_eq!(body["usage"]["completion_tokens"], 2);
+
+    // As in the OpenAI API, the stop sequence is not part of the content
+    let mut request = chat_request("Answer briefly");
+    request["stop"] = json!("\n\nUser:");
+    let body: Value = server.chat(request).await.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "Answer.");
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+}
+
+#[tokio::test]
+async fn test_streamed_chat_completion() {
+    let model = FakeModel::new().with_reply(["Hi", " there"]);
+    let server = TestServer::start(&model, None).await;
+
+    let mut request = chat_request("Greet me");
+    request["stream"] = json!(true);
+    request["stream_options"] = json!({"include_usage": true});
+    let response = server.chat(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/event-stream"));
+
+    let chunks = sse_payloads(&response.text().await.unwrap());
+    assert!(chunks
+        .iter()
+        .all(|chunk| chunk["object"] == "chat.completion.chunk"));
+
+    // The role comes first, then the content, then the finish reason and the usage
+    assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+    let content: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(content, "Hi there");
+
+    let finish = &chunks[chunks.len() - 2];
+    assert_eq!(finish["choices"][0]["finish_reason"], "stop");
+    let usage = &chunks[chunks.len() - 1];
+    assert_eq!(usage["choices"], json!([]));
+    assert_eq!(usage["usage"]["completion_tokens"], 2);
+
+    server.assert_no_sessions().await;
+}
+
+#[tokio::test]
+async fn test_streamed_chat_completion_omits_stop_sequence() {
+    let model = FakeModel::new()
+        .with_reply(["Answer", ".", "\n\n", "User", ":", " more"])
+        .with_reply(["Use", " `", "`", " twice", ":", " ``", "`"]);
+    let server = TestServer::start(&model, None).await;
+
+    let streamed_content = |body: String| -> (String, Value) {
+        let chunks = sse_payloads(&body);
+        let content = chunks
+            .iter()
+            .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        let finish = chunks.last().unwrap()["choices"][0]["finish_reason"].clone();
+        (content, finish)
+    };
+
+    // As for a whole completion, the stop sequence is never sent
+    let mut request = chat_request("Answer briefly");
+    request["stream"] = json!(true);
+    request["stop"] = json!("\n\nUser:");
+    let body = server.chat(request).await.text().await.unwrap();
+    assert_eq!(
+        streamed_content(body),
+        ("Answer.".to_string(), json!("stop"))
+    );
+
+    // Text held back as a possible stop sequence is sent once it turns out not to be one
+    let mut request = chat_request("Explain");
+    request["stream"] = json!(true);
+    request["stop"] = json!("```");
+    let body = server.chat(request).await.text().await.unwrap();
+    assert_eq!(
+        streamed_content(body),
+        ("Use `` twice: ".to_string(), json!("stop"))
+    );
+
+    server.assert_no_sessions().await;
+}
+
+#[tokio::test]
+async fn test_model_stop_sequences_are_omitted() {
+    let model = FakeModel::new()
+        .with_reply(["Done", ".", "###", " more"])
+        .with_reply(["Done", ".", "###", " more"]);
+    let mut config = AgentConfig::default();
+    config.model.extra_stop_sequences = vec!["###".to_string()];
+    let server = TestServer::start_with_config(&model, None, config).await;
+
+    // Stops the request did not ask for end the reply and are left out all the same
+    let response: Value = server
+        .chat(chat_request("Answer briefly"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response["choices"][0]["message"]["content"], "Done.");
+
+    let mut request = chat_request("Answer briefly");
+    request["stream"] = json!(true);
+    let body = server.chat(request).await.text().await.unwrap();
+    let content: String = sse_payloads(&body)
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(content, "Done.");
+
+    server.assert_no_sessions().await;
+}
+
+#[tokio::test]
+async fn test_models_and_health() {
+    let server = TestServer::start(&FakeModel::new(), None).await;
+
+    let models: Value = reqwest::get(server.url("/v1/models"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(models["object"], "list");
+    assert_eq!(models["data"][0]["id"], MODEL_ID);
+    assert_eq!(models["data"][0]["object"], "model");
+
+    // The fake backend loads no model, so the agent reports itself unhealthy
+    let response = reqwest::get(server.url("/health")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let health: Value = response.json().await.unwrap();
+    assert_eq!(health["model_loaded"], false);
+}
+
+#[tokio::test]
+async fn test_bearer_token_required() {
+    let model = FakeModel::new().with_reply(["Hi"]);
+    let server = TestServer::start(&model, Some("secret")).await;
+    let client = reqwest::Client::new();
+
+    let response = client.get(server.url("/v1/models")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "invalid_api_key");
+
+    let response = client
+        .post(server.url("/v1/chat/completions"))
+        .bearer_auth("wrong")
+        .json(&chat_request("Hi"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(model.remaining_replies(), 1);
+
+    let response = client
+        .post(server.url("/v1/chat/completions"))
+        .bearer_auth("secret")
+        .json(&chat_request("Hi"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Health checks need no token
+    let response = client.get(server.url("/health")).send().await.unwrap();
+    assert_ne!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_invalid_requests() {
+    let server = TestServer::start(&FakeModel::new(), None).await;
+
+    for body in [
+        json!({"messages": "not a list"}),
+        json!({"messages": [{"role": "wizard", "content": "Hi"}]}),
+        json!({"messages": [{"role": "user", "content": "Hi"}], "temperature": 3.0}),
+        json!({"model": MODEL_ID}),
+    ] {
+        let response = server.chat(body.clone()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+        let error: Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["type"], "invalid_request_error", "{}", body);
+    }
+
+    let response = reqwest::Client::new()
+        .post(server.url("/v1/chat/completions"))
+        .header("content-type", "application/json")
+        .body("{not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    server.assert_no_sessions().await;
+}
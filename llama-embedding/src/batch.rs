@@ -1,11 +1,14 @@
+use crate::cache::{CachedEmbedding, EmbeddingCache};
+use crate::dedup::TextDeduplicator;
 use crate::error::{EmbeddingError, EmbeddingResult as Result};
 use crate::model::EmbeddingModel;
-use crate::types::EmbeddingResult;
+use crate::types::{EmbeddingResult, HashAlgo};
+use std::borrow::Cow;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
 use tokio::sync::mpsc;
 use tokio_stream::Stream;
 use tracing::{debug, info, warn};
@@ -41,6 +44,15 @@ pub struct BatchStats {
     pub average_batch_time_ms: f64,
     pub peak_memory_usage_bytes: usize,
     pub total_characters_processed: usize,
+    /// Texts skipped because an identical text was already embedded
+    pub duplicates_skipped: usize,
+    /// Texts whose vector had NaN/Inf components or the wrong dimension, whether they
+    /// failed, were skipped or were zero-filled
+    pub invalid_vectors: usize,
+    /// Texts served from the embedding cache
+    pub cache_hits: usize,
+    /// Texts looked up in the embedding cache and embedded because they were not in it
+    pub cache_misses: usize,
 }
 
 impl BatchStats {
@@ -82,6 +94,7 @@ impl BatchStats {
         self.total_processing_time_ms += processing_time_ms;
         self.total_tokens_processed += token_count;
         self.total_characters_processed += char_count;
+        self.invalid_vectors += batch_results.iter().filter(|r| r.zero_filled).count();
         self.batches_processed += 1;
 
         // Update averages
@@ -142,6 +155,75 @@ impl BatchStats {
     }
 }
 
+/// Whether `error` is a record dropped by `InvalidVectorPolicy::SkipRecord`, which
+/// is not a failure
+fn is_skipped_record(error: &EmbeddingError) -> bool {
+    matches!(error, EmbeddingError::SkippedRecord(_))
+}
+
+/// Read the next line of `path`, counting lines in `line` so a read error can name
+/// the line that failed
+async fn next_input_line(
+    lines: &mut Lines<BufReader<File>>,
+    path: &Path,
+    line: &mut usize,
+) -> Result<Option<String>> {
+    *line += 1;
+    lines
+        .next_line()
+        .await
+        .map_err(|source| EmbeddingError::InputRead {
+            path: path.to_path_buf(),
+            line: *line,
+            source,
+        })
+}
+
+/// Drop the texts `dedup` has already seen, counting them in `stats`
+fn skip_duplicates<'a>(
+    dedup: Option<&mut TextDeduplicator>,
+    stats: &mut BatchStats,
+    texts: &'a [String],
+) -> Cow<'a, [String]> {
+    match dedup {
+        Some(dedup) => {
+            let unique = dedup.filter(texts);
+            stats.duplicates_skipped += texts.len() - unique.len();
+            Cow::Owned(unique)
+        }
+        None => Cow::Borrowed(texts),
+    }
+}
+
+/// Put cached embeddings back among the embedded ones in input order.
+///
+/// `cached` holds the input index of each hit and `embedded` the results for the
+/// other texts in order, missing those that failed or were skipped.
+fn merge_cached(
+    texts: &[String],
+    cached: Vec<(usize, CachedEmbedding)>,
+    embedded: Vec<EmbeddingResult>,
+    hash: HashAlgo,
+) -> Vec<EmbeddingResult> {
+    let mut merged = Vec::with_capacity(cached.len() + embedded.len());
+    let mut cached = cached.into_iter().peekable();
+    let mut embedded = embedded.into_iter().peekable();
+    for (index, text) in texts.iter().enumerate() {
+        if let Some((_, hit)) = cached.next_if(|(hit_index, _)| *hit_index == index) {
+            merged.push(EmbeddingResult::with_hash(
+                text.clone(),
+                hit.embedding,
+                hit.sequence_length,
+                0,
+                hash,
+            ));
+        } else if let Some(result) = embedded.next_if(|result| result.text == *text) {
+            merged.push(result);
+        }
+    }
+    merged
+}
+
 /// Configuration for batch processing behavior
 #[derive(Debug, Clone)]
 pub struct BatchConfig {
@@ -152,6 +234,8 @@ pub struct BatchConfig {
     pub progress_report_interval_batches: usize,
     pub memory_limit_mb: Option<usize>,
     pub enable_memory_monitoring: bool,
+    /// Skip texts identical to one already embedded, keeping first occurrences
+    pub dedupe: bool,
 }
 
 impl Default for BatchConfig {
@@ -164,6 +248,7 @@ impl Default for BatchConfig {
             progress_report_interval_batches: 10,
             memory_limit_mb: None,
             enable_memory_monitoring: true,
+            dedupe: false,
         }
     }
 }
@@ -174,6 +259,10 @@ pub struct BatchProcessor {
     config: BatchConfig,
     stats: BatchStats,
     progress_callback: Option<ProgressCallback>,
+    dedup: Option<TextDeduplicator>,
+    /// Opened on the first batch, once the model dimension is known
+    cache: Option<EmbeddingCache>,
+    cache_opened: bool,
 }
 
 impl BatchProcessor {
@@ -183,24 +272,58 @@ impl BatchProcessor {
             batch_size,
             ..Default::default()
         };
-        Self {
-            model,
-            config,
-            stats: BatchStats::new(),
-            progress_callback: None,
-        }
+        Self::with_config(model, config)
     }
 
     /// Create a new BatchProcessor with custom configuration
     pub fn with_config(model: Arc<EmbeddingModel>, config: BatchConfig) -> Self {
+        let dedup = config
+            .dedupe
+            .then(|| TextDeduplicator::new(model.config().hash));
         Self {
             model,
             config,
             stats: BatchStats::new(),
             progress_callback: None,
+            dedup,
+            cache: None,
+            cache_opened: false,
         }
     }
 
+    /// Turn duplicate skipping on or off; turning it off forgets the texts seen so far
+    pub fn set_dedupe(&mut self, dedupe: bool) {
+        self.config.dedupe = dedupe;
+        self.dedup = dedupe.then(|| TextDeduplicator::new(self.model.config().hash));
+        debug!("Updated dedupe to {}", dedupe);
+    }
+
+    /// Duplicate tracker, present when dedupe is enabled; use it to preload earlier output
+    pub fn deduplicator_mut(&mut self) -> Option<&mut TextDeduplicator> {
+        self.dedup.as_mut()
+    }
+
+    /// Embedding cache from the model config, opened on first use; `None` when no cache
+    /// is configured or the configured one does not match the model
+    pub fn cache(&mut self) -> Result<Option<&mut EmbeddingCache>> {
+        if !self.cache_opened {
+            self.cache = self.open_cache()?;
+            self.cache_opened = true;
+        }
+        Ok(self.cache.as_mut())
+    }
+
+    fn open_cache(&self) -> Result<Option<EmbeddingCache>> {
+        let Some(config) = &self.model.config().cache else {
+            return Ok(None);
+        };
+        let Some(dimension) = self.model.dimension() else {
+            warn!("Embedding dimension unknown, running without the embedding cache");
+            return Ok(None);
+        };
+        EmbeddingCache::open(config, &self.model.cache_identity(), dimension)
+    }
+
     /// Set a progress callback for monitoring batch processing
     pub fn set_progress_callback(&mut self, callback: ProgressCallback) {
         self.progress_callback = Some(callback);
@@ -243,15 +366,66 @@ impl BatchProcessor {
             }
         }
 
+        let unique = skip_duplicates(self.dedup.as_mut(), &mut self.stats, texts);
+        let texts = &unique[..];
+
+        // Serve cached texts and embed only the others
+        let mut cached = Vec::new();
+        let mut missed = Vec::new();
+        let all_texts = texts;
+        let texts = match self.cache()? {
+            Some(cache) => {
+                for (index, text) in texts.iter().enumerate() {
+                    match cache.get(text) {
+                        Ok(Some(hit)) => cached.push((index, hit)),
+                        Ok(None) => missed.push(text.clone()),
+                        Err(e) => {
+                            warn!("Embedding cache lookup failed: {}", e);
+                            missed.push(text.clone());
+                        }
+                    }
+                }
+                self.stats.cache_hits += cached.len();
+                self.stats.cache_misses += missed.len();
+                &missed[..]
+            }
+            None => texts,
+        };
+
         let mut results = Vec::new();
         let mut failures = 0;
 
-        for text in texts {
+        // Embed the whole batch at once; on failure, fall back to one text at a time so
+        // a single bad text does not fail the others
+        let batch: Vec<&str> = texts.iter().map(String::as_str).collect();
+        let fallback = match self.model.embed_batch(&batch).await {
+            Ok(batch_results) => {
+                results = batch_results;
+                &[][..]
+            }
+            Err(e) if self.config.continue_on_error || is_skipped_record(&e) => {
+                warn!(
+                    "Batched embedding failed, embedding texts one by one: {}",
+                    e
+                );
+                texts
+            }
+            Err(e) => return Err(e),
+        };
+
+        for text in fallback {
             match self.model.embed_text(text).await {
                 Ok(result) => {
                     results.push(result);
                 }
+                Err(e) if is_skipped_record(&e) => {
+                    self.stats.invalid_vectors += 1;
+                    warn!("Skipped text: {}", e);
+                }
                 Err(e) => {
+                    if matches!(e, EmbeddingError::InvalidVector(_)) {
+                        self.stats.invalid_vectors += 1;
+                    }
                     failures += 1;
                     let preview = text.chars().take(50).collect::<String>();
                     warn!("Failed to embed text '{}...': {}", preview, e);
@@ -264,6 +438,20 @@ impl BatchProcessor {
             }
         }
 
+        if let Some(cache) = self.cache.as_mut() {
+            for result in results.iter().filter(|result| !result.zero_filled) {
+                if let Err(e) =
+                    cache.insert(&result.text, &result.embedding, result.sequence_length)
+                {
+                    warn!("Failed to write to the embedding cache: {}", e);
+                }
+            }
+        }
+        if !cached.is_empty() {
+            let hash = self.model.config().hash;
+            results = merge_cached(all_texts, cached, results, hash);
+        }
+
         let processing_time = start_time.elapsed().as_millis() as u64;
         self.stats
             .update_with_details(&results, processing_time, failures);
@@ -360,8 +548,9 @@ impl BatchProcessor {
         let file = File::open(input_path).await?;
         let reader = BufReader::new(file);
         let mut lines = reader.lines();
+        let mut line_number = 0;
 
-        while let Some(line) = lines.next_line().await? {
+        while let Some(line) = next_input_line(&mut lines, input_path, &mut line_number).await? {
             let trimmed = line.trim();
             if !trimmed.is_empty() {
                 current_batch.push(trimmed.to_string());
@@ -396,6 +585,23 @@ impl BatchProcessor {
     ) -> Result<()>
     where
         F: FnMut(Vec<EmbeddingResult>) -> std::result::Result<(), EmbeddingError>,
+    {
+        self.process_file_streaming_from(input_path, 0, |results, _| callback(results))
+            .await
+    }
+
+    /// Process a file with streaming results, skipping the first `skip` non-empty lines.
+    ///
+    /// The callback also receives how many input texts the batch consumed, which can exceed the
+    /// number of results when duplicates are skipped or embeddings fail.
+    pub async fn process_file_streaming_from<F>(
+        &mut self,
+        input_path: &Path,
+        skip: usize,
+        mut callback: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Vec<EmbeddingResult>, usize) -> std::result::Result<(), EmbeddingError>,
     {
         if !input_path.exists() {
             return Err(EmbeddingError::Io(std::io::Error::new(
@@ -404,22 +610,32 @@ impl BatchProcessor {
             )));
         }
 
-        info!("Processing file with streaming: {}", input_path.display());
+        info!(
+            "Processing file with streaming: {} (skipping {} texts)",
+            input_path.display(),
+            skip
+        );
         let mut current_batch = Vec::new();
+        let mut skipped = 0;
 
         let file = File::open(input_path).await?;
         let reader = BufReader::new(file);
         let mut lines = reader.lines();
+        let mut line_number = 0;
 
-        while let Some(line) = lines.next_line().await? {
+        while let Some(line) = next_input_line(&mut lines, input_path, &mut line_number).await? {
             let trimmed = line.trim();
             if !trimmed.is_empty() {
+                if skipped < skip {
+                    skipped += 1;
+                    continue;
+                }
                 current_batch.push(trimmed.to_string());
 
                 // Process and yield batch when it reaches the configured size
                 if current_batch.len() >= self.config.batch_size {
                     let batch_results = self.process_batch(&current_batch).await?;
-                    callback(batch_results)?;
+                    callback(batch_results, current_batch.len())?;
                     current_batch.clear();
                 }
             }
@@ -428,7 +644,7 @@ impl BatchProcessor {
         // Process and yield remaining texts in the final batch
         if !current_batch.is_empty() {
             let batch_results = self.process_batch(&current_batch).await?;
-            callback(batch_results)?;
+            callback(batch_results, current_batch.len())?;
         }
 
         info!("Completed streaming processing of file");
@@ -453,10 +669,18 @@ impl BatchProcessor {
         let batch_size = self.config.batch_size;
         let model = self.model.clone();
         let continue_on_error = self.config.continue_on_error;
+        let dedup = self.dedup.clone();
+        // The open cache moves to the task; this processor reopens it once the task ends
+        let cache = self.cache.take();
+        let cache_opened = std::mem::take(&mut self.cache_opened);
 
         tokio::spawn(async move {
             let mut processor = BatchProcessor::new(model, batch_size);
             processor.config.continue_on_error = continue_on_error;
+            processor.config.dedupe = dedup.is_some();
+            processor.dedup = dedup;
+            processor.cache = cache;
+            processor.cache_opened = cache_opened;
 
             let result = processor
                 .process_file_streaming(&input_path, |batch_results| {
@@ -551,7 +775,8 @@ impl BatchProcessor {
             - Batches processed: {}\n\
             - Average batch time: {:.1}ms\n\
             - Peak memory usage: {:.2}MB\n\
-            - Total characters: {}",
+            - Total characters: {}\n\
+            - Duplicates skipped: {}",
             self.stats.total_texts,
             self.stats.success_rate() * 100.0,
             self.stats.total_processing_time_ms as f64 / 1000.0,
@@ -562,7 +787,8 @@ impl BatchProcessor {
             self.stats.batches_processed,
             self.stats.average_batch_time_ms,
             self.stats.peak_memory_usage_bytes as f64 / (1024.0 * 1024.0),
-            self.stats.total_characters_processed
+            self.stats.total_characters_processed,
+            self.stats.duplicates_skipped
         )
     }
 }
@@ -570,6 +796,8 @@ impl BatchProcessor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::EmbeddingCacheConfig;
+    use crate::types::{HashAlgo, InvalidVectorPolicy};
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -652,6 +880,9 @@ mod tests {
         pub processing_time_ms: u64,
         pub fail_on_text: Option<String>,
         pub custom_embedding: Option<Vec<f32>>,
+        /// Texts containing this get a NaN vector
+        pub bad_vector_on_text: Option<String>,
+        pub invalid_vectors: InvalidVectorPolicy,
     }
 
     impl MockEmbeddingModel {
@@ -663,6 +894,8 @@ mod tests {
                 processing_time_ms: 10,
                 fail_on_text: None,
                 custom_embedding: None,
+                bad_vector_on_text: None,
+                invalid_vectors: InvalidVectorPolicy::Fail,
             }
         }
 
@@ -674,6 +907,8 @@ mod tests {
                 processing_time_ms: 10,
                 fail_on_text: None,
                 custom_embedding: None,
+                bad_vector_on_text: None,
+                invalid_vectors: InvalidVectorPolicy::Fail,
             }
         }
 
@@ -685,6 +920,16 @@ mod tests {
                 processing_time_ms: 10,
                 fail_on_text: Some(fail_text),
                 custom_embedding: None,
+                bad_vector_on_text: None,
+                invalid_vectors: InvalidVectorPolicy::Fail,
+            }
+        }
+
+        pub fn with_bad_vectors(bad_text: &str, policy: InvalidVectorPolicy) -> Self {
+            Self {
+                bad_vector_on_text: Some(bad_text.to_string()),
+                invalid_vectors: policy,
+                ..Self::new()
             }
         }
 
@@ -696,6 +941,8 @@ mod tests {
                 processing_time_ms: 10,
                 fail_on_text: None,
                 custom_embedding: None,
+                bad_vector_on_text: None,
+                invalid_vectors: InvalidVectorPolicy::Fail,
             }
         }
 
@@ -741,14 +988,26 @@ mod tests {
                 embedding
             };
 
+            let embedding = match &self.bad_vector_on_text {
+                Some(bad_text) if text.contains(bad_text) => {
+                    vec![f32::NAN; self.embedding_dimension]
+                }
+                _ => embedding,
+            };
+            let (embedding, zero_filled) = self
+                .invalid_vectors
+                .apply(embedding, self.embedding_dimension)?;
+
             let sequence_length = text.split_whitespace().count().max(1); // Approximate tokenization
 
-            Ok(EmbeddingResult::new(
+            let mut result = EmbeddingResult::new(
                 text.to_string(),
                 embedding,
                 sequence_length,
                 self.processing_time_ms,
-            ))
+            );
+            result.zero_filled = zero_filled;
+            Ok(result)
         }
 
         pub fn is_loaded(&self) -> bool {
@@ -814,6 +1073,8 @@ mod tests {
         config: BatchConfig,
         stats: BatchStats,
         progress_callback: Option<ProgressCallback>,
+        dedup: Option<TextDeduplicator>,
+        cache: Option<EmbeddingCache>,
     }
 
     impl TestBatchProcessor {
@@ -827,6 +1088,8 @@ mod tests {
                 config,
                 stats: BatchStats::new(),
                 progress_callback: None,
+                dedup: None,
+                cache: None,
             }
         }
 
@@ -836,6 +1099,8 @@ mod tests {
                 config,
                 stats: BatchStats::new(),
                 progress_callback: None,
+                dedup: None,
+                cache: None,
             }
         }
 
@@ -843,6 +1108,17 @@ mod tests {
             self.progress_callback = Some(callback);
         }
 
+        pub fn with_dedupe(mut self, hash: HashAlgo) -> Self {
+            self.config.dedupe = true;
+            self.dedup = Some(TextDeduplicator::new(hash));
+            self
+        }
+
+        pub fn with_cache(mut self, cache: EmbeddingCache) -> Self {
+            self.cache = Some(cache);
+            self
+        }
+
         /// Process a batch of texts and return embedding results with error recovery
         pub async fn process_batch(&mut self, texts: &[String]) -> Result<Vec<EmbeddingResult>> {
             if !self.model.is_loaded() {
@@ -875,6 +1151,27 @@ mod tests {
                 }
             }
 
+            let unique = skip_duplicates(self.dedup.as_mut(), &mut self.stats, texts);
+            let texts = &unique[..];
+
+            let mut cached = Vec::new();
+            let mut missed = Vec::new();
+            let all_texts = texts;
+            let texts = match self.cache.as_mut() {
+                Some(cache) => {
+                    for (index, text) in texts.iter().enumerate() {
+                        match cache.get(text)? {
+                            Some(hit) => cached.push((index, hit)),
+                            None => missed.push(text.clone()),
+                        }
+                    }
+                    self.stats.cache_hits += cached.len();
+                    self.stats.cache_misses += missed.len();
+                    &missed[..]
+                }
+                None => texts,
+            };
+
             let mut results = Vec::new();
             let mut failures = 0;
 
@@ -883,7 +1180,14 @@ mod tests {
                     Ok(result) => {
                         results.push(result);
                     }
+                    Err(e) if is_skipped_record(&e) => {
+                        self.stats.invalid_vectors += 1;
+                        warn!("Skipped text: {}", e);
+                    }
                     Err(e) => {
+                        if matches!(e, EmbeddingError::InvalidVector(_)) {
+                            self.stats.invalid_vectors += 1;
+                        }
                         failures += 1;
                         let preview = text.chars().take(50).collect::<String>();
                         warn!("Failed to embed text '{}...': {}", preview, e);
@@ -896,6 +1200,15 @@ mod tests {
                 }
             }
 
+            if let Some(cache) = self.cache.as_mut() {
+                for result in results.iter().filter(|result| !result.zero_filled) {
+                    cache.insert(&result.text, &result.embedding, result.sequence_length)?;
+                }
+            }
+            if !cached.is_empty() {
+                results = merge_cached(all_texts, cached, results, HashAlgo::Md5);
+            }
+
             let processing_time = start_time.elapsed().as_millis() as u64;
             self.stats
                 .update_with_details(&results, processing_time, failures);
@@ -976,6 +1289,50 @@ mod tests {
             Ok(all_results)
         }
 
+        /// Process a file with streaming results, skipping the first `skip` non-empty lines
+        pub async fn process_file_streaming_from<F>(
+            &mut self,
+            input_path: &Path,
+            skip: usize,
+            mut callback: F,
+        ) -> Result<()>
+        where
+            F: FnMut(Vec<EmbeddingResult>, usize) -> std::result::Result<(), EmbeddingError>,
+        {
+            let mut current_batch = Vec::new();
+            let mut skipped = 0;
+
+            let file = File::open(input_path).await?;
+            let reader = BufReader::new(file);
+            let mut lines = reader.lines();
+            let mut line_number = 0;
+
+            while let Some(line) = next_input_line(&mut lines, input_path, &mut line_number).await?
+            {
+                let trimmed = line.trim();
+                if !trimmed.is_empty() {
+                    if skipped < skip {
+                        skipped += 1;
+                        continue;
+                    }
+                    current_batch.push(trimmed.to_string());
+
+                    if current_batch.len() >= self.config.batch_size {
+                        let batch_results = self.process_batch(&current_batch).await?;
+                        callback(batch_results, current_batch.len())?;
+                        current_batch.clear();
+                    }
+                }
+            }
+
+            if !current_batch.is_empty() {
+                let batch_results = self.process_batch(&current_batch).await?;
+                callback(batch_results, current_batch.len())?;
+            }
+
+            Ok(())
+        }
+
         fn estimate_current_memory_usage(&self, texts: &[String]) -> usize {
             let text_memory = texts.iter().map(|t| t.len()).sum::<usize>();
             let embeddings_memory = if let Some(dim) = self.model.get_embedding_dimension() {
@@ -1088,6 +1445,7 @@ mod tests {
             progress_report_interval_batches: 10,
             memory_limit_mb: None,
             enable_memory_monitoring: true,
+            dedupe: false,
         };
         let mut processor = TestBatchProcessor::with_config_mock(mock_model, config);
 
@@ -1107,6 +1465,69 @@ mod tests {
         ));
     }
 
+    fn bad_vector_texts() -> Vec<String> {
+        vec![
+            "Hello world".to_string(),
+            "This is bad".to_string(),
+            "This is fine".to_string(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_invalid_vector_fail() {
+        let mock_model = Arc::new(MockEmbeddingModel::with_bad_vectors(
+            "bad",
+            InvalidVectorPolicy::Fail,
+        ));
+        let mut processor = TestBatchProcessor::new_mock(mock_model, 4);
+
+        let results = processor.process_batch(&bad_vector_texts()).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(processor.stats.failed_embeddings, 1);
+        assert_eq!(processor.stats.invalid_vectors, 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_vector_skip_record() {
+        let mock_model = Arc::new(MockEmbeddingModel::with_bad_vectors(
+            "bad",
+            InvalidVectorPolicy::SkipRecord,
+        ));
+        let config = BatchConfig {
+            continue_on_error: false,
+            ..Default::default()
+        };
+        let mut processor = TestBatchProcessor::with_config_mock(mock_model, config);
+
+        // A skipped record is not an error, even when errors stop the batch
+        let results = processor.process_batch(&bad_vector_texts()).await.unwrap();
+        let texts: Vec<&str> = results.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(texts, ["Hello world", "This is fine"]);
+        assert_eq!(processor.stats.failed_embeddings, 0);
+        assert_eq!(processor.stats.invalid_vectors, 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_vector_zero_fill() {
+        let mock_model = Arc::new(MockEmbeddingModel::with_bad_vectors(
+            "bad",
+            InvalidVectorPolicy::ZeroFill,
+        ));
+        let mut processor = TestBatchProcessor::new_mock(mock_model, 4);
+
+        let results = processor.process_batch(&bad_vector_texts()).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[1].zero_filled);
+        assert_eq!(results[1].embedding, vec![0.0; 384]);
+        assert!(!results[0].zero_filled && !results[2].zero_filled);
+        assert_eq!(processor.stats.invalid_vectors, 1);
+
+        // Normalizing the zero vector must not produce NaN
+        let mut zero_filled = results[1].clone();
+        zero_filled.normalize();
+        assert!(zero_filled.embedding.iter().all(|value| *value == 0.0));
+    }
+
     #[tokio::test]
     async fn test_batch_processor_empty_input() {
         let mock_model = Arc::new(MockEmbeddingModel::new());
@@ -1149,6 +1570,7 @@ mod tests {
             progress_report_interval_batches: 10,
             memory_limit_mb: Some(1), // Very small memory limit
             enable_memory_monitoring: true,
+            dedupe: false,
         };
         let mut processor = TestBatchProcessor::with_config_mock(mock_model, config);
 
@@ -1179,6 +1601,7 @@ mod tests {
             progress_report_interval_batches: 1, // Report every batch
             memory_limit_mb: None,
             enable_memory_monitoring: true,
+            dedupe: false,
         };
         let mut processor = TestBatchProcessor::with_config_mock(mock_model, config);
 
@@ -1222,6 +1645,7 @@ mod tests {
             progress_report_interval_batches: 5,
             memory_limit_mb: Some(100),
             enable_memory_monitoring: false,
+            dedupe: false,
         };
         let processor = TestBatchProcessor::with_config_mock(mock_model, config);
 
@@ -1419,4 +1843,145 @@ mod tests {
         assert!(expected > text_bytes); // Should be larger than just text
         assert!(expected > embedding_bytes); // Should be larger than just embeddings
     }
+
+    #[tokio::test]
+    async fn test_dedupe_fixture_file() {
+        // 11 lines: one blank and 10 texts, 4 of them distinct; duplicates span batch
+        // boundaries
+        let mut temp_file = NamedTempFile::new().unwrap();
+        for line in [
+            "alpha", "beta", "alpha", "gamma", "beta", "", "alpha", "delta", "gamma", "delta",
+            "alpha",
+        ] {
+            writeln!(temp_file, "{}", line).unwrap();
+        }
+
+        let file = File::open(temp_file.path()).await.unwrap();
+        let mut lines = BufReader::new(file).lines();
+        let mut texts = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if !line.trim().is_empty() {
+                texts.push(line.trim().to_string());
+            }
+        }
+        assert_eq!(texts.len(), 10);
+
+        let mock_model = Arc::new(MockEmbeddingModel::new());
+        let mut processor = TestBatchProcessor::new_mock(mock_model, 3).with_dedupe(HashAlgo::Md5);
+        let results = processor.process_texts(texts).await.unwrap();
+
+        let embedded: Vec<&str> = results.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(embedded, vec!["alpha", "beta", "gamma", "delta"]);
+        assert_eq!(processor.stats.duplicates_skipped, 6);
+        assert_eq!(processor.stats.successful_embeddings, 4);
+    }
+
+    #[tokio::test]
+    async fn test_cache_skips_texts_embedded_in_an_earlier_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = EmbeddingCacheConfig::new(dir.path().join("embeddings.bin"));
+        let texts = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect();
+
+        let mock_model = Arc::new(MockEmbeddingModel::new());
+        let cache = EmbeddingCache::open(&config, "mock", 384).unwrap().unwrap();
+        let mut processor = TestBatchProcessor::new_mock(mock_model.clone(), 2).with_cache(cache);
+        let first = processor
+            .process_texts(texts(&["alpha", "beta", "gamma"]))
+            .await
+            .unwrap();
+        assert_eq!(processor.stats.cache_hits, 0);
+        assert_eq!(processor.stats.cache_misses, 3);
+        drop(processor);
+
+        // A second run over a grown input only embeds the new lines
+        let cache = EmbeddingCache::open(&config, "mock", 384).unwrap().unwrap();
+        let mut processor = TestBatchProcessor::new_mock(mock_model, 2).with_cache(cache);
+        let second = processor
+            .process_texts(texts(&["alpha", "delta", "beta", "gamma", "epsilon"]))
+            .await
+            .unwrap();
+        assert_eq!(processor.stats.cache_hits, 3);
+        assert_eq!(processor.stats.cache_misses, 2);
+
+        let embedded: Vec<&str> = second.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(embedded, vec!["alpha", "delta", "beta", "gamma", "epsilon"]);
+        assert_eq!(second[0].embedding, first[0].embedding);
+        assert_eq!(second[0].text_hash, first[0].text_hash);
+        assert_eq!(second[2].sequence_length, first[1].sequence_length);
+        assert_eq!(second[0].processing_time_ms, 0);
+        assert_eq!(processor.stats.successful_embeddings, 5);
+    }
+
+    #[test]
+    fn test_merge_cached_keeps_input_order_without_failed_texts() {
+        let texts: Vec<String> = ["a", "b", "c", "d"].iter().map(|t| t.to_string()).collect();
+        let hit = |value| CachedEmbedding {
+            embedding: vec![value],
+            sequence_length: 1,
+        };
+        // "c" failed to embed
+        let embedded = vec![EmbeddingResult::new("b".to_string(), vec![2.0], 1, 5)];
+        let merged = merge_cached(
+            &texts,
+            vec![(0, hit(1.0)), (3, hit(4.0))],
+            embedded,
+            HashAlgo::Md5,
+        );
+
+        let merged: Vec<(&str, f32)> = merged
+            .iter()
+            .map(|r| (r.text.as_str(), r.embedding[0]))
+            .collect();
+        assert_eq!(merged, vec![("a", 1.0), ("b", 2.0), ("d", 4.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_process_file_streaming_from_offset() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        for line in ["t0", "t1", "", "t2", "t3", "t4", "t5", "t6"] {
+            writeln!(temp_file, "{}", line).unwrap();
+        }
+
+        let mock_model = Arc::new(MockEmbeddingModel::new());
+        let mut processor = TestBatchProcessor::new_mock(mock_model, 2);
+
+        // Resume after the first 4 texts; the blank line does not count
+        let mut embedded = Vec::new();
+        let mut consumed = Vec::new();
+        processor
+            .process_file_streaming_from(temp_file.path(), 4, |results, lines| {
+                embedded.extend(results.into_iter().map(|r| r.text));
+                consumed.push(lines);
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(embedded, vec!["t4", "t5", "t6"]);
+        assert_eq!(consumed, vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_line_names_file_and_line() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .write_all(b"first\n\nbad \xff\xfe text\nlast\n")
+            .unwrap();
+
+        let mock_model = Arc::new(MockEmbeddingModel::new());
+        let mut processor = TestBatchProcessor::new_mock(mock_model, 2);
+        let error = processor
+            .process_file_streaming_from(temp_file.path(), 0, |_, _| Ok(()))
+            .await
+            .unwrap_err();
+
+        match error {
+            EmbeddingError::InputRead { path, line, source } => {
+                assert_eq!(path, temp_file.path());
+                assert_eq!(line, 3);
+                assert_eq!(source.kind(), std::io::ErrorKind::InvalidData);
+            }
+            other => panic!("expected an input read error, got {}", other),
+        }
+    }
 }
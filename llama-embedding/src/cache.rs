@@ -0,0 +1,445 @@
+//! Persistent embedding cache, so re-running a job over a grown corpus only embeds
+//! the new texts.
+//!
+//! The cache is one append-only file: a header naming the model and dimension, then
+//! fixed-size records of a key, a token count and a vector. The key is a SHA-256 of
+//! the model identity and the text. The index from key to record is rebuilt in
+//! memory when the file is opened, and a record cut short by a crash is dropped.
+//! An exclusive lock on the file keeps a second writer out.
+
+use crate::error::{EmbeddingError, EmbeddingResult as Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Identifies the file format and its version
+const MAGIC: &[u8; 8] = b"LLEMBC1\n";
+
+/// Length of a record key
+const KEY_LEN: usize = 32;
+
+/// Where embeddings are cached between runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmbeddingCacheConfig {
+    /// Cache file, created on first use
+    pub path: PathBuf,
+    /// New embeddings are not added once the file reaches this size; `None` for no limit
+    #[serde(default)]
+    pub max_size_bytes: Option<u64>,
+    /// What happens when the file was written for another model or dimension
+    #[serde(default)]
+    pub on_mismatch: CacheMismatchPolicy,
+}
+
+impl EmbeddingCacheConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size_bytes: None,
+            on_mismatch: CacheMismatchPolicy::default(),
+        }
+    }
+}
+
+/// What happens when a cache file was written for another model or dimension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheMismatchPolicy {
+    /// Warn and run without the cache, leaving the file as it is
+    #[default]
+    Ignore,
+    /// Fail with `EmbeddingError::Configuration`
+    Fail,
+}
+
+/// A cached embedding
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedEmbedding {
+    pub embedding: Vec<f32>,
+    pub sequence_length: usize,
+}
+
+/// An open embedding cache file
+#[derive(Debug)]
+pub struct EmbeddingCache {
+    file: File,
+    path: PathBuf,
+    model: String,
+    dimension: usize,
+    /// Offset of each record by key
+    index: HashMap<[u8; KEY_LEN], u64>,
+    size: u64,
+    max_size_bytes: Option<u64>,
+    full_reported: bool,
+}
+
+impl EmbeddingCache {
+    /// Open or create the cache file for `model` embedding into `dimension`.
+    ///
+    /// Returns `None` when the file belongs to another model or dimension and the
+    /// config says to ignore it.
+    pub fn open(
+        config: &EmbeddingCacheConfig,
+        model: &str,
+        dimension: usize,
+    ) -> Result<Option<Self>> {
+        let path = &config.path;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(EmbeddingError::configuration(format!(
+                    "Embedding cache {} is in use by another process",
+                    path.display()
+                )));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        let mut size = file.metadata()?.len();
+        if size == 0 {
+            write_header(&mut file, model, dimension)?;
+            size = file.metadata()?.len();
+            info!("Created embedding cache {}", path.display());
+        } else if let Some(problem) = header_mismatch(&mut file, model, dimension)? {
+            let message = format!(
+                "Embedding cache {} does not match the model: {}",
+                path.display(),
+                problem
+            );
+            return match config.on_mismatch {
+                CacheMismatchPolicy::Ignore => {
+                    warn!("{}; running without it", message);
+                    Ok(None)
+                }
+                CacheMismatchPolicy::Fail => Err(EmbeddingError::configuration(message)),
+            };
+        }
+
+        let mut cache = Self {
+            path: path.clone(),
+            model: model.to_string(),
+            dimension,
+            index: HashMap::new(),
+            size,
+            max_size_bytes: config.max_size_bytes,
+            full_reported: false,
+            file,
+        };
+        cache.load_index()?;
+        info!(
+            "Opened embedding cache {} with {} embeddings",
+            cache.path.display(),
+            cache.len()
+        );
+        Ok(Some(cache))
+    }
+
+    /// The cached embedding of `text`, if any
+    pub fn get(&mut self, text: &str) -> Result<Option<CachedEmbedding>> {
+        let Some(&offset) = self.index.get(&self.key(text)) else {
+            return Ok(None);
+        };
+        let mut record = vec![0; self.record_len() - KEY_LEN];
+        self.file.seek(SeekFrom::Start(offset + KEY_LEN as u64))?;
+        self.file.read_exact(&mut record)?;
+
+        let (length, vector) = record.split_at(4);
+        let sequence_length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let embedding = vector
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        Ok(Some(CachedEmbedding {
+            embedding,
+            sequence_length,
+        }))
+    }
+
+    /// Add the embedding of `text`; returns whether it was added. Known texts, and
+    /// any text once the size limit is reached, are not.
+    pub fn insert(
+        &mut self,
+        text: &str,
+        embedding: &[f32],
+        sequence_length: usize,
+    ) -> Result<bool> {
+        if embedding.len() != self.dimension {
+            return Err(EmbeddingError::DimensionMismatch {
+                expected: self.dimension,
+                actual: embedding.len(),
+            });
+        }
+        let key = self.key(text);
+        if self.index.contains_key(&key) {
+            return Ok(false);
+        }
+        let record_len = self.record_len() as u64;
+        if self
+            .max_size_bytes
+            .is_some_and(|max| self.size + record_len > max)
+        {
+            if !self.full_reported {
+                warn!(
+                    "Embedding cache {} reached its size limit, new embeddings are not cached",
+                    self.path.display()
+                );
+                self.full_reported = true;
+            }
+            return Ok(false);
+        }
+
+        let mut record = Vec::with_capacity(record_len as usize);
+        record.extend_from_slice(&key);
+        record.extend_from_slice(&(sequence_length as u32).to_le_bytes());
+        for value in embedding {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        self.file.seek(SeekFrom::Start(self.size))?;
+        self.file.write_all(&record)?;
+        self.index.insert(key, self.size);
+        self.size += record_len;
+        Ok(true)
+    }
+
+    /// Number of cached embeddings
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn key(&self, text: &str) -> [u8; KEY_LEN] {
+        let mut hasher = Sha256::new();
+        hasher.update((self.model.len() as u64).to_le_bytes());
+        hasher.update(self.model.as_bytes());
+        hasher.update(text.as_bytes());
+        hasher.finalize().into()
+    }
+
+    fn record_len(&self) -> usize {
+        KEY_LEN + 4 + self.dimension * 4
+    }
+
+    /// Index the records after the header, dropping a partial record at the end
+    fn load_index(&mut self) -> Result<()> {
+        let start = self.file.stream_position()?;
+        let record_len = self.record_len() as u64;
+        let records = (self.size - start) / record_len;
+        let end = start + records * record_len;
+        if end < self.size {
+            warn!(
+                "Dropping a partly written record at the end of embedding cache {}",
+                self.path.display()
+            );
+            self.file.set_len(end)?;
+            self.size = end;
+        }
+
+        let mut key = [0; KEY_LEN];
+        for record in 0..records {
+            let offset = start + record * record_len;
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut key)?;
+            self.index.insert(key, offset);
+        }
+        debug!("Indexed {} cached embeddings", records);
+        Ok(())
+    }
+}
+
+fn write_header(file: &mut File, model: &str, dimension: usize) -> Result<()> {
+    let mut header = Vec::with_capacity(MAGIC.len() + 8 + model.len());
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&(dimension as u32).to_le_bytes());
+    header.extend_from_slice(&(model.len() as u32).to_le_bytes());
+    header.extend_from_slice(model.as_bytes());
+    file.write_all(&header)?;
+    Ok(())
+}
+
+/// How the header of `file` differs from `model` and `dimension`, if it does. Leaves
+/// the file positioned after the header.
+fn header_mismatch(file: &mut File, model: &str, dimension: usize) -> Result<Option<String>> {
+    let mut magic = [0; MAGIC.len()];
+    let mut numbers = [0; 8];
+    if file.read_exact(&mut magic).is_err()
+        || &magic != MAGIC
+        || file.read_exact(&mut numbers).is_err()
+    {
+        return Ok(Some("not an embedding cache file".to_string()));
+    }
+    let cached_dimension = u32::from_le_bytes(numbers[..4].try_into().unwrap()) as usize;
+    let model_len = u32::from_le_bytes(numbers[4..].try_into().unwrap()) as usize;
+    let mut cached_model = vec![0; model_len];
+    if file.read_exact(&mut cached_model).is_err() {
+        return Ok(Some("not an embedding cache file".to_string()));
+    }
+    let cached_model = String::from_utf8_lossy(&cached_model);
+
+    if cached_model != model {
+        return Ok(Some(format!("written for model {}", cached_model)));
+    }
+    if cached_dimension != dimension {
+        return Ok(Some(format!(
+            "written for dimension {}, the model has {}",
+            cached_dimension, dimension
+        )));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(
+        config: &EmbeddingCacheConfig,
+        model: &str,
+        dimension: usize,
+    ) -> Result<Option<EmbeddingCache>> {
+        EmbeddingCache::open(config, model, dimension)
+    }
+
+    #[test]
+    fn test_cached_embeddings_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = EmbeddingCacheConfig::new(dir.path().join("cache/embeddings.bin"));
+
+        let mut cache = open(&config, "model-a", 3).unwrap().unwrap();
+        assert!(cache.insert("hello", &[0.5, -1.0, 2.0], 4).unwrap());
+        assert!(!cache.insert("hello", &[0.5, -1.0, 2.0], 4).unwrap());
+        assert!(cache.insert("world", &[1.0, 0.0, 0.0], 1).unwrap());
+        assert!(matches!(
+            cache.insert("bad", &[1.0], 1),
+            Err(EmbeddingError::DimensionMismatch {
+                expected: 3,
+                actual: 1
+            })
+        ));
+        drop(cache);
+
+        let mut cache = open(&config, "model-a", 3).unwrap().unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.get("hello").unwrap(),
+            Some(CachedEmbedding {
+                embedding: vec![0.5, -1.0, 2.0],
+                sequence_length: 4,
+            })
+        );
+        assert_eq!(cache.get("world").unwrap().unwrap().sequence_length, 1);
+        assert_eq!(cache.get("unknown").unwrap(), None);
+    }
+
+    #[test]
+    fn test_mismatched_cache_is_ignored_or_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = EmbeddingCacheConfig::new(dir.path().join("embeddings.bin"));
+        let mut cache = open(&config, "model-a", 3).unwrap().unwrap();
+        cache.insert("hello", &[1.0, 2.0, 3.0], 1).unwrap();
+        drop(cache);
+
+        assert!(open(&config, "model-b", 3).unwrap().is_none());
+        assert!(open(&config, "model-a", 4).unwrap().is_none());
+
+        config.on_mismatch = CacheMismatchPolicy::Fail;
+        let error = open(&config, "model-b", 3).unwrap_err();
+        assert!(
+            matches!(&error, EmbeddingError::Configuration(message) if message.contains("model-a")),
+            "{}",
+            error
+        );
+
+        // Ignoring leaves the file intact
+        assert_eq!(open(&config, "model-a", 3).unwrap().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_partial_record_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = EmbeddingCacheConfig::new(dir.path().join("embeddings.bin"));
+        let mut cache = open(&config, "model-a", 2).unwrap().unwrap();
+        cache.insert("a", &[1.0, 2.0], 1).unwrap();
+        drop(cache);
+
+        // A crash in the middle of the next append
+        let mut file = OpenOptions::new().append(true).open(&config.path).unwrap();
+        file.write_all(&[7; KEY_LEN + 3]).unwrap();
+        drop(file);
+
+        let mut cache = open(&config, "model-a", 2).unwrap().unwrap();
+        assert_eq!(cache.len(), 1);
+        cache.insert("b", &[3.0, 4.0], 2).unwrap();
+        drop(cache);
+        let mut cache = open(&config, "model-a", 2).unwrap().unwrap();
+        assert_eq!(cache.get("b").unwrap().unwrap().embedding, vec![3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_size_limit_stops_inserts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = EmbeddingCacheConfig::new(dir.path().join("embeddings.bin"));
+        let header_len = (MAGIC.len() + 8 + "model-a".len()) as u64;
+        let record_len = (KEY_LEN + 4 + 2 * 4) as u64;
+        config.max_size_bytes = Some(header_len + 2 * record_len);
+
+        let mut cache = open(&config, "model-a", 2).unwrap().unwrap();
+        assert!(cache.insert("a", &[1.0, 2.0], 1).unwrap());
+        assert!(cache.insert("b", &[1.0, 2.0], 1).unwrap());
+        assert!(!cache.insert("c", &[1.0, 2.0], 1).unwrap());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            std::fs::metadata(&config.path).unwrap().len(),
+            header_len + 2 * record_len
+        );
+    }
+
+    #[test]
+    fn test_second_writer_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = EmbeddingCacheConfig::new(dir.path().join("embeddings.bin"));
+        let _first = open(&config, "model-a", 2).unwrap().unwrap();
+        let error = open(&config, "model-a", 2).unwrap_err();
+        assert!(error.to_string().contains("in use"), "{}", error);
+    }
+
+    #[test]
+    fn test_keys_depend_on_the_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = open(
+            &EmbeddingCacheConfig::new(dir.path().join("a.bin")),
+            "model-a",
+            2,
+        )
+        .unwrap()
+        .unwrap();
+        let b = open(
+            &EmbeddingCacheConfig::new(dir.path().join("b.bin")),
+            "model-b",
+            2,
+        )
+        .unwrap()
+        .unwrap();
+        assert_ne!(a.key("hello"), b.key("hello"));
+        assert_ne!(a.key("hello"), a.key("hello "));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::types::HashAlgo;
+use std::collections::HashMap;
+
+/// Tracks which texts have already been embedded so duplicates can be skipped
+///
+/// Texts are keyed by the configured hash, and a text whose key was seen is only skipped
+/// if it matches the text seen under that key, so a hash collision never drops a distinct
+/// text. Keys preloaded without their text, such as the hash column of earlier output,
+/// cannot be checked: a new text colliding with one is skipped. For 64-bit xxh3 that
+/// becomes likely only past billions of texts; use md5 or sha256 for larger inputs.
+#[derive(Debug, Clone)]
+pub struct TextDeduplicator {
+    hash: HashAlgo,
+    /// Text seen under each key; `None` when the key is the text itself or was
+    /// preloaded without it
+    seen: HashMap<String, Option<String>>,
+    duplicates_skipped: usize,
+}
+
+impl TextDeduplicator {
+    /// Create a deduplicator keyed by `hash`; with `HashAlgo::None` the full text is the key
+    pub fn new(hash: HashAlgo) -> Self {
+        Self {
+            hash,
+            seen: HashMap::new(),
+            duplicates_skipped: 0,
+        }
+    }
+
+    /// Key under which `text` is remembered
+    pub fn key(&self, text: &str) -> String {
+        self.hash.hash(text).unwrap_or_else(|| text.to_string())
+    }
+
+    /// Output column holding keys in the same form as `key`, if hashing is enabled
+    pub fn key_column(&self) -> Option<&'static str> {
+        self.hash.column_name()
+    }
+
+    /// Mark keys (as returned by `key`) as already embedded, e.g. from a previous output file
+    pub fn preload_keys<I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        for key in keys {
+            self.seen.entry(key).or_insert(None);
+        }
+    }
+
+    /// Mark texts as already embedded
+    pub fn preload_texts<'a, I>(&mut self, texts: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for text in texts {
+            self.remember(text);
+        }
+    }
+
+    /// Remember `text`, returning false if it was already seen
+    fn remember(&mut self, text: &str) -> bool {
+        let key = self.key(text);
+        match self.seen.get(&key) {
+            None => {
+                // With no hash the key is the text already
+                let seen_text = self.hash.column_name().is_some().then(|| text.to_string());
+                self.seen.insert(key, seen_text);
+                true
+            }
+            // A different text with the same hash is kept; its own repeats are not caught
+            Some(Some(seen_text)) => seen_text != text,
+            Some(None) => false,
+        }
+    }
+
+    /// Return the texts not seen before, in their original order.
+    ///
+    /// Repeats within `texts` are skipped as well; only the first occurrence is kept.
+    pub fn filter(&mut self, texts: &[String]) -> Vec<String> {
+        let mut unique = Vec::with_capacity(texts.len());
+        for text in texts {
+            if self.remember(text) {
+                unique.push(text.clone());
+            } else {
+                self.duplicates_skipped += 1;
+            }
+        }
+        unique
+    }
+
+    /// Number of texts skipped as duplicates so far
+    pub fn duplicates_skipped(&self) -> usize {
+        self.duplicates_skipped
+    }
+
+    /// Number of distinct texts seen, including preloaded ones
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_filter_keeps_first_occurrences_in_order() {
+        let mut dedup = TextDeduplicator::new(HashAlgo::Md5);
+
+        let unique = dedup.filter(&texts(&["b", "a", "b", "c", "a"]));
+        assert_eq!(unique, texts(&["b", "a", "c"]));
+        assert_eq!(dedup.duplicates_skipped(), 2);
+
+        // Texts seen in an earlier batch are skipped too
+        let unique = dedup.filter(&texts(&["c", "d"]));
+        assert_eq!(unique, texts(&["d"]));
+        assert_eq!(dedup.duplicates_skipped(), 3);
+        assert_eq!(dedup.len(), 4);
+    }
+
+    #[test]
+    fn test_preloaded_texts_are_skipped() {
+        for hash in [HashAlgo::Xxh3, HashAlgo::None] {
+            let mut dedup = TextDeduplicator::new(hash);
+            dedup.preload_texts(["a", "b"]);
+            let key = dedup.key("c");
+            dedup.preload_keys([key]);
+
+            let unique = dedup.filter(&texts(&["a", "c", "d"]));
+            assert_eq!(unique, texts(&["d"]));
+            assert_eq!(dedup.duplicates_skipped(), 2);
+        }
+    }
+
+    #[test]
+    fn test_hash_collision_keeps_distinct_text() {
+        let mut dedup = TextDeduplicator::new(HashAlgo::Xxh3);
+        // Pretend "a" and "b" hash alike
+        let key = dedup.key("b");
+        dedup.seen.insert(key, Some("a".to_string()));
+
+        let unique = dedup.filter(&texts(&["b"]));
+        assert_eq!(unique, texts(&["b"]));
+        assert_eq!(dedup.duplicates_skipped(), 0);
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use thiserror::Error;
 
 /// Errors that can occur during embedding operations
@@ -31,6 +32,14 @@ pub enum EmbeddingError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
+    /// A line of an input file could not be read, e.g. because it is not valid UTF-8
+    #[error("Cannot read {} at line {line}: {source}", .path.display())]
+    InputRead {
+        path: PathBuf,
+        line: usize,
+        source: std::io::Error,
+    },
+
     /// Error when model is not loaded
     #[error("Model not loaded - call load_model() first")]
     ModelNotLoaded,
@@ -38,6 +47,20 @@ pub enum EmbeddingError {
     /// Error when embedding dimensions don't match expectations
     #[error("Embedding dimension mismatch: expected {expected}, got {actual}")]
     DimensionMismatch { expected: usize, actual: usize },
+
+    /// A produced vector has NaN/Inf components or the wrong dimension, under
+    /// `InvalidVectorPolicy::Fail`
+    #[error("Invalid embedding vector: {0}")]
+    InvalidVector(String),
+
+    /// A produced vector was invalid and its record dropped, under
+    /// `InvalidVectorPolicy::SkipRecord`
+    #[error("Record skipped, invalid embedding vector: {0}")]
+    SkippedRecord(String),
+
+    /// The loaded model cannot produce embeddings (e.g. a decoder-only chat model)
+    #[error("Model does not produce embeddings: {0} - use an embedding model such as Qwen3-Embedding instead of a chat model")]
+    NotEmbeddingModel(String),
 }
 
 impl EmbeddingError {
@@ -100,6 +123,31 @@ mod tests {
         assert!(matches!(embedding_error, EmbeddingError::Io(_)));
     }
 
+    #[test]
+    fn test_input_read_error() {
+        let error = EmbeddingError::InputRead {
+            path: PathBuf::from("texts.txt"),
+            line: 3,
+            source: io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            ),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Cannot read texts.txt at line 3: stream did not contain valid UTF-8"
+        );
+    }
+
+    #[test]
+    fn test_not_embedding_model_error() {
+        let error = EmbeddingError::NotEmbeddingModel("no pooling".to_string());
+        assert_eq!(
+            error.to_string(),
+            "Model does not produce embeddings: no pooling - use an embedding model such as Qwen3-Embedding instead of a chat model"
+        );
+    }
+
     #[test]
     fn test_dimension_mismatch_error() {
         let error = EmbeddingError::DimensionMismatch {
@@ -12,11 +12,12 @@
 //! - **File Processing**: Stream processing of large text files
 //! - **Configurable**: Support for normalization, sequence limits, and debug output
 //! - **MD5 Hashing**: Automatic text hashing for deduplication
+//! - **Embedding Cache**: Optional on-disk cache so unchanged texts are not embedded again
 //!
 //! ## Quick Start
 //!
 //! ```rust,no_run
-//! use llama_embedding::{EmbeddingModel, EmbeddingConfig};
+//! use llama_embedding::{EmbeddingModel, EmbeddingConfig, HashAlgo, InvalidVectorPolicy, Pooling};
 //! use llama_loader::ModelSource;
 //!
 //! #[tokio::main]
@@ -30,11 +31,22 @@
 //!         normalize_embeddings: true,
 //!         max_sequence_length: Some(512),
 //!         debug: false,
+//!         hash: HashAlgo::Xxh3,
+//!         allow_http: false,
+//!         metadata_ttl_secs: None,
+//!         refresh_metadata: false,
+//!         cache_dir: None,
+//!         force_load: false,
+//!         pooling: Pooling::Native,
+//!         invalid_vectors: InvalidVectorPolicy::Fail,
+//!         cache: None,
 //!     };
 //!
-//!     // Create and load the model
+//!     // Create and load the model, then check it really is an embedding model
 //!     let mut model = EmbeddingModel::new(config).await?;
 //!     model.load_model().await?;
+//!     let dimension = model.warm_up().await?;
+//!     println!("Model embeds into {} dimensions", dimension);
 //!
 //!     // Generate embedding for a single text
 //!     let result = model.embed_text("Hello, world!").await?;
@@ -70,15 +82,19 @@
 //! ```
 
 pub mod batch;
+pub mod cache;
+pub mod dedup;
 pub mod error;
 pub mod model;
 pub mod types;
 
 // Re-export main types for convenience
 pub use batch::{BatchConfig, BatchProcessor, BatchStats, ProgressCallback, ProgressInfo};
+pub use cache::{CacheMismatchPolicy, EmbeddingCache, EmbeddingCacheConfig};
+pub use dedup::TextDeduplicator;
 pub use error::{EmbeddingError, EmbeddingResult as Result};
 pub use model::EmbeddingModel;
-pub use types::{EmbeddingConfig, EmbeddingResult};
+pub use types::{EmbeddingConfig, EmbeddingResult, HashAlgo, InvalidVectorPolicy, Pooling};
 
 // Re-export commonly used types from dependencies
 pub use llama_loader::ModelSource;
@@ -1,25 +1,43 @@
 use crate::error::{EmbeddingError, EmbeddingResult as Result};
-use crate::types::{EmbeddingConfig, EmbeddingResult};
+use crate::types::{EmbeddingConfig, EmbeddingResult, Pooling};
 use llama_cpp_2::{
-    context::{params::LlamaContextParams, LlamaContext},
+    context::{
+        params::{LlamaContextParams, LlamaPoolingType},
+        LlamaContext,
+    },
     llama_backend::LlamaBackend,
     model::LlamaModel,
-    send_logs_to_tracing, LogOptions,
+    send_logs_to_tracing, EmbeddingsError, LogOptions,
 };
 
 // High-level llama-cpp-2 types for embedding processing
 use llama_cpp_2::llama_batch::LlamaBatch;
 use llama_cpp_2::token::LlamaToken;
 use llama_loader::{ModelConfig, ModelLoader, ModelMetadata, RetryConfig};
+use std::num::NonZeroU32;
+use std::ops::Range;
 use std::sync::{Arc, OnceLock};
 use std::time::Instant;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 // Need access to raw FFI bindings for llama_log_set
 use std::ffi::c_void;
 use std::os::raw::c_char;
 
 static GLOBAL_BACKEND: OnceLock<Arc<LlamaBackend>> = OnceLock::new();
 
+/// Sequence length used when neither the config nor a loaded model provides one
+const DEFAULT_MAX_SEQUENCE_LENGTH: usize = 512;
+
+/// Text embedded by `warm_up` to probe the model
+const WARM_UP_TEXT: &str = "warm-up";
+
+/// Tokens `embed_batch` packs into one decode; raised to the sequence length limit so
+/// every text fits
+const BATCH_TOKEN_LIMIT: usize = 2048;
+
+/// Sequences `embed_batch` packs into one decode
+const MAX_SEQUENCES_PER_DECODE: usize = 32;
+
 // Null log callback to suppress llama.cpp verbose output
 extern "C" fn null_log_callback(_level: i32, _text: *const c_char, _user_data: *mut c_void) {
     // Do nothing - this suppresses all llama.cpp logging
@@ -52,6 +70,8 @@ pub struct EmbeddingModel {
     config: EmbeddingConfig,
     metadata: Option<ModelMetadata>,
     backend: Arc<LlamaBackend>,
+    /// Dimension observed by `warm_up`
+    verified_dimension: Option<usize>,
 }
 
 impl EmbeddingModel {
@@ -78,6 +98,7 @@ impl EmbeddingModel {
             config,
             metadata: None,
             backend,
+            verified_dimension: None,
         })
     }
 
@@ -97,13 +118,28 @@ impl EmbeddingModel {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: self.config.debug,
+            local_search_depth: 0,
+            allow_http: self.config.allow_http,
+            metadata_ttl_secs: self
+                .config
+                .metadata_ttl_secs
+                .unwrap_or(ModelConfig::DEFAULT_METADATA_TTL_SECS),
+            refresh_metadata: self.config.refresh_metadata,
+            cache_dir: self.config.cache_dir.clone(),
+            force_load: self.config.force_load,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         };
 
         // Load the model using the loader
         let loaded_model = {
             // Create a new loader for model loading since we need mutable access
-            let mut loader =
-                ModelLoader::new(self.backend.clone()).map_err(EmbeddingError::ModelLoader)?;
+            let mut loader = ModelLoader::with_cache_dir(
+                self.backend.clone(),
+                model_config.cache_dir.as_deref(),
+            )
+            .map_err(EmbeddingError::ModelLoader)?;
             loader
                 .initialize()
                 .await
@@ -120,6 +156,7 @@ impl EmbeddingModel {
         // Store the model and metadata
         self.model = Some(loaded_model.model);
         self.metadata = Some(loaded_model.metadata);
+        self.verified_dimension = None;
 
         info!("Embedding model loaded successfully in {:?}", load_time);
 
@@ -128,6 +165,27 @@ impl EmbeddingModel {
 
     /// Generate embedding for a single text
     pub async fn embed_text(&self, text: &str) -> Result<EmbeddingResult> {
+        let start_time = Instant::now();
+        let (embedding, sequence_length) = self.embed_raw(text)?;
+        let result = self.finish_result(
+            text,
+            embedding,
+            sequence_length,
+            start_time.elapsed().as_millis() as u64,
+        )?;
+
+        debug!(
+            "Generated embedding: {} dimensions, {} tokens, {}ms",
+            result.dimension(),
+            result.sequence_length,
+            result.processing_time_ms
+        );
+
+        Ok(result)
+    }
+
+    /// Vector the model produces for `text`, unchecked, and its token count
+    fn embed_raw(&self, text: &str) -> Result<(Vec<f32>, usize)> {
         let model = self.model.as_ref().ok_or(EmbeddingError::ModelNotLoaded)?;
 
         if text.is_empty() {
@@ -136,8 +194,6 @@ impl EmbeddingModel {
             ));
         }
 
-        let start_time = Instant::now();
-
         debug!("Generating embedding for text: {} chars", text.len());
 
         // Create context for this embedding operation
@@ -146,43 +202,110 @@ impl EmbeddingModel {
         // Tokenize the text
         let tokens = self.tokenize_text(&context, text)?;
 
-        // Apply sequence length limit if configured
-        let final_tokens = if let Some(max_len) = self.config.max_sequence_length {
-            if tokens.len() > max_len {
-                debug!("Truncating tokens from {} to {}", tokens.len(), max_len);
-                tokens[..max_len].to_vec()
-            } else {
-                tokens
-            }
+        // Apply sequence length limit
+        let max_len = self.max_sequence_length();
+        let final_tokens = if tokens.len() > max_len {
+            debug!("Truncating tokens from {} to {}", tokens.len(), max_len);
+            tokens[..max_len].to_vec()
         } else {
             tokens
         };
 
         // Generate embedding using the tokenized text
         let embedding = self.generate_embedding_from_tokens(&mut context, &final_tokens)?;
+        Ok((embedding, final_tokens.len()))
+    }
 
-        let processing_time_ms = start_time.elapsed().as_millis() as u64;
+    /// Check a produced vector against the invalid vector policy, then normalize it if
+    /// configured
+    fn finish_result(
+        &self,
+        text: &str,
+        embedding: Vec<f32>,
+        sequence_length: usize,
+        processing_time_ms: u64,
+    ) -> Result<EmbeddingResult> {
+        let dimension = self.dimension().unwrap_or(embedding.len());
+        let (embedding, zero_filled) = self.config.invalid_vectors.apply(embedding, dimension)?;
+        if zero_filled {
+            warn!(
+                "Zero-filled the invalid embedding vector of a {} char text",
+                text.len()
+            );
+        }
 
-        let mut result = EmbeddingResult::new(
+        let mut result = EmbeddingResult::with_hash(
             text.to_string(),
             embedding,
-            final_tokens.len(),
+            sequence_length,
             processing_time_ms,
+            self.config.hash,
         );
+        result.zero_filled = zero_filled;
 
         // Apply normalization if requested
         if self.config.normalize_embeddings {
             result.normalize();
         }
+        Ok(result)
+    }
+
+    /// Generate embeddings for several texts, returned in input order.
+    ///
+    /// Texts are tokenized and packed into as few decodes as the batch limits allow,
+    /// one sequence per text, instead of one decode per text. Each result's
+    /// `processing_time_ms` is its share of the whole call.
+    pub async fn embed_batch(&self, texts: &[&str]) -> Result<Vec<EmbeddingResult>> {
+        let model = self.model.as_ref().ok_or(EmbeddingError::ModelNotLoaded)?;
+
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        if texts.iter().any(|text| text.is_empty()) {
+            return Err(EmbeddingError::text_processing(
+                "Input text cannot be empty",
+            ));
+        }
+
+        let start_time = Instant::now();
+        let max_len = self.max_sequence_length();
+        let token_limit = BATCH_TOKEN_LIMIT.max(max_len);
+        let max_sequences = MAX_SEQUENCES_PER_DECODE.min(texts.len());
+
+        let mut context = self.create_batch_context(model, token_limit, max_sequences)?;
+        let sequences = texts
+            .iter()
+            .map(|text| {
+                let mut tokens = self.tokenize_text(&context, text)?;
+                if tokens.len() > max_len {
+                    debug!("Truncating tokens from {} to {}", tokens.len(), max_len);
+                    tokens.truncate(max_len);
+                }
+                Ok(tokens)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let embeddings =
+            embed_token_sequences(&mut context, &sequences, token_limit, max_sequences)?;
+
+        let processing_time_ms = start_time.elapsed().as_millis() as u64 / texts.len() as u64;
+
+        let results = texts
+            .iter()
+            .zip(&sequences)
+            .zip(embeddings)
+            .map(|((text, tokens), embedding)| {
+                self.finish_result(text, embedding, tokens.len(), processing_time_ms)
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         debug!(
-            "Generated embedding: {} dimensions, {} tokens, {}ms",
-            result.dimension(),
-            result.sequence_length,
-            result.processing_time_ms
+            "Generated {} embeddings in {:?}",
+            results.len(),
+            start_time.elapsed()
         );
 
-        Ok(result)
+        Ok(results)
     }
 
     /// Get the embedding dimension of the loaded model
@@ -198,6 +321,76 @@ impl EmbeddingModel {
         })
     }
 
+    /// Embedding dimension, known once the model is loaded.
+    ///
+    /// Returns the dimension verified by `warm_up` when available, otherwise the one the model
+    /// reports. Unlike `get_embedding_dimension`, this never guesses.
+    pub fn dimension(&self) -> Option<usize> {
+        self.verified_dimension.or_else(|| {
+            self.model
+                .as_ref()
+                .and_then(|model| usize::try_from(model.n_embd()).ok())
+                .filter(|&n_embd| n_embd > 0)
+        })
+    }
+
+    /// Longest token sequence embedded per text; longer texts are truncated.
+    ///
+    /// Uses `max_sequence_length` from the config, capped at the model's training context.
+    pub fn max_sequence_length(&self) -> usize {
+        let n_ctx_train = self
+            .model
+            .as_ref()
+            .map(|model| model.n_ctx_train() as usize)
+            .filter(|&n| n > 0);
+        match (self.config.max_sequence_length, n_ctx_train) {
+            (Some(configured), Some(trained)) => configured.min(trained),
+            (Some(configured), None) => configured,
+            (None, Some(trained)) => trained,
+            (None, None) => DEFAULT_MAX_SEQUENCE_LENGTH,
+        }
+    }
+
+    /// Embed a short probe text to check the model can produce embeddings.
+    ///
+    /// Returns the embedding dimension, which `dimension` reports from then on. Fails with
+    /// `EmbeddingError::NotEmbeddingModel` for models without pooled embeddings, such as
+    /// decoder-only chat models.
+    pub async fn warm_up(&mut self) -> Result<usize> {
+        // The probe is checked here, not by the invalid vector policy
+        let probe = self.embed_raw(WARM_UP_TEXT);
+        let dimension = check_warm_up(probe.map(|(embedding, _)| embedding))?;
+        self.verified_dimension = Some(dimension);
+        info!("Embedding model warmed up ({} dimensions)", dimension);
+        Ok(dimension)
+    }
+
+    /// Configuration the model was created with
+    pub fn config(&self) -> &EmbeddingConfig {
+        &self.config
+    }
+
+    /// Identifies the model and the settings that shape its vectors, so a cache never
+    /// serves vectors from another model, pooling or truncation length
+    pub fn cache_identity(&self) -> String {
+        let model = match &self.metadata {
+            Some(metadata) => format!(
+                "{} ({} bytes, revision {})",
+                metadata.filename,
+                metadata.size_bytes,
+                metadata.revision.as_deref().unwrap_or("none")
+            ),
+            None => format!("{:?}", self.config.model_source),
+        };
+        format!(
+            "{}; pooling {:?}; normalize {}; max length {}",
+            model,
+            self.config.pooling,
+            self.config.normalize_embeddings,
+            self.max_sequence_length()
+        )
+    }
+
     /// Get model metadata if loaded
     pub fn get_metadata(&self) -> Option<&ModelMetadata> {
         self.metadata.as_ref()
@@ -227,8 +420,35 @@ impl EmbeddingModel {
         }
     }
 
+    fn context_params(&self) -> LlamaContextParams {
+        let params = LlamaContextParams::default().with_embeddings(true);
+        match self.config.pooling {
+            Pooling::Native => params,
+            Pooling::Mean => params.with_pooling_type(LlamaPoolingType::Mean),
+        }
+    }
+
     fn create_context<'a>(&self, model: &'a LlamaModel) -> Result<LlamaContext<'a>> {
-        let context_params = LlamaContextParams::default().with_embeddings(true);
+        model
+            .new_context(&self.backend, self.context_params())
+            .map_err(|e| EmbeddingError::model(format!("Failed to create context: {}", e)))
+    }
+
+    /// Context decoding up to `max_sequences` sequences of `token_limit` tokens in total
+    /// at once; a whole decode must fit one micro-batch for pooled embeddings
+    fn create_batch_context<'a>(
+        &self,
+        model: &'a LlamaModel,
+        token_limit: usize,
+        max_sequences: usize,
+    ) -> Result<LlamaContext<'a>> {
+        let n_tokens = token_limit as u32;
+        let context_params = self
+            .context_params()
+            .with_n_ctx(NonZeroU32::new(n_tokens))
+            .with_n_batch(n_tokens)
+            .with_n_ubatch(n_tokens)
+            .with_n_seq_max(max_sequences as u32);
 
         model
             .new_context(&self.backend, context_params)
@@ -272,11 +492,6 @@ impl EmbeddingModel {
             ));
         }
 
-        // Get embedding dimension from the model
-        let embedding_dim = self.get_embedding_dimension().ok_or_else(|| {
-            EmbeddingError::model("Could not determine embedding dimension".to_string())
-        })?;
-
         // Convert i32 tokens to LlamaToken
         let llama_tokens: Vec<LlamaToken> = tokens.iter().map(|&t| LlamaToken(t)).collect();
 
@@ -300,25 +515,12 @@ impl EmbeddingModel {
 
         // Extract embeddings for the sequence
         // Use sequence 0 since we only have one sequence
-        let embeddings = context.embeddings_seq_ith(0).map_err(|e| {
-            EmbeddingError::text_processing(format!(
-                "Failed to extract embeddings from context: {}",
-                e
-            ))
-        })?;
-
-        // Validate embedding dimension matches expectation
-        if embeddings.len() != embedding_dim {
-            return Err(EmbeddingError::text_processing(format!(
-                "Embedding dimension mismatch: expected {}, got {}",
-                embedding_dim,
-                embeddings.len()
-            )));
-        }
+        let embeddings = context.embeddings_seq_ith(0).map_err(embeddings_error)?;
 
+        // The dimension is checked by the invalid vector policy
         debug!(
             "Successfully extracted embedding of dimension {} for {} tokens",
-            embedding_dim,
+            embeddings.len(),
             tokens.len()
         );
 
@@ -327,9 +529,118 @@ impl EmbeddingModel {
     }
 }
 
+/// Decodes several token sequences at once, returning one embedding per sequence
+trait SequenceDecoder {
+    fn decode_sequences(&mut self, sequences: &[Vec<i32>]) -> Result<Vec<Vec<f32>>>;
+}
+
+impl SequenceDecoder for LlamaContext<'_> {
+    fn decode_sequences(&mut self, sequences: &[Vec<i32>]) -> Result<Vec<Vec<f32>>> {
+        let n_tokens = sequences.iter().map(Vec::len).sum();
+        let mut batch = LlamaBatch::new(n_tokens, sequences.len() as i32);
+        for (seq_id, tokens) in sequences.iter().enumerate() {
+            let tokens: Vec<LlamaToken> = tokens.iter().map(|&t| LlamaToken(t)).collect();
+            batch
+                .add_sequence(&tokens, seq_id as i32, false)
+                .map_err(|e| {
+                    EmbeddingError::text_processing(format!("Failed to add tokens to batch: {}", e))
+                })?;
+        }
+
+        // Sequence ids are reused by every decode
+        self.clear_kv_cache();
+        self.decode(&mut batch).map_err(|e| {
+            EmbeddingError::text_processing(format!(
+                "Failed to decode tokens for embedding extraction: {}",
+                e
+            ))
+        })?;
+
+        (0..sequences.len())
+            .map(|seq_id| {
+                self.embeddings_seq_ith(seq_id as i32)
+                    .map(<[f32]>::to_vec)
+                    .map_err(embeddings_error)
+            })
+            .collect()
+    }
+}
+
+/// Split sequences of the given lengths, in order, into runs of at most `max_tokens`
+/// tokens and `max_sequences` sequences, each decoded at once. A sequence longer than
+/// `max_tokens` runs alone.
+fn plan_decodes(lengths: &[usize], max_tokens: usize, max_sequences: usize) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+    for (i, &length) in lengths.iter().enumerate() {
+        if i > start && (tokens + length > max_tokens || i - start >= max_sequences) {
+            runs.push(start..i);
+            start = i;
+            tokens = 0;
+        }
+        tokens += length;
+    }
+    if start < lengths.len() {
+        runs.push(start..lengths.len());
+    }
+    runs
+}
+
+/// Embed every sequence with as few decodes as the limits allow, in input order
+fn embed_token_sequences<D: SequenceDecoder>(
+    decoder: &mut D,
+    sequences: &[Vec<i32>],
+    max_tokens: usize,
+    max_sequences: usize,
+) -> Result<Vec<Vec<f32>>> {
+    let lengths: Vec<usize> = sequences.iter().map(Vec::len).collect();
+    let mut embeddings = Vec::with_capacity(sequences.len());
+    for run in plan_decodes(&lengths, max_tokens, max_sequences) {
+        debug!("Decoding {} sequences at once", run.len());
+        embeddings.extend(decoder.decode_sequences(&sequences[run])?);
+    }
+    Ok(embeddings)
+}
+
+/// Models without pooled embeddings fail here; report them as not being embedding models
+fn embeddings_error(error: EmbeddingsError) -> EmbeddingError {
+    match error {
+        EmbeddingsError::NotEnabled | EmbeddingsError::NonePoolType => {
+            EmbeddingError::NotEmbeddingModel(error.to_string())
+        }
+        other => EmbeddingError::text_processing(format!(
+            "Failed to extract embeddings from context: {}",
+            other
+        )),
+    }
+}
+
+/// Check the warm-up probe produced a usable vector and return its dimension
+fn check_warm_up(probe: Result<Vec<f32>>) -> Result<usize> {
+    let embedding = probe?;
+    if embedding.is_empty() {
+        return Err(EmbeddingError::NotEmbeddingModel(
+            "warm-up produced an empty embedding".to_string(),
+        ));
+    }
+    if embedding.iter().any(|value| !value.is_finite()) {
+        return Err(EmbeddingError::NotEmbeddingModel(
+            "warm-up produced non-finite values".to_string(),
+        ));
+    }
+    if embedding.iter().all(|&value| value == 0.0) {
+        return Err(EmbeddingError::NotEmbeddingModel(
+            "warm-up produced an all-zero embedding".to_string(),
+        ));
+    }
+    Ok(embedding.len())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::{HashAlgo, InvalidVectorPolicy, Pooling};
     use llama_loader::ModelSource;
 
     #[tokio::test]
@@ -362,10 +673,117 @@ mod tests {
             normalize_embeddings: true,
             max_sequence_length: Some(512),
             debug: true,
+            hash: HashAlgo::Sha256,
+            allow_http: false,
+            metadata_ttl_secs: None,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            pooling: Pooling::Native,
+            invalid_vectors: InvalidVectorPolicy::Fail,
+            cache: None,
         };
 
         assert_eq!(config.normalize_embeddings, true);
         assert_eq!(config.max_sequence_length, Some(512));
         assert_eq!(config.debug, true);
+        assert_eq!(config.hash, HashAlgo::Sha256);
+    }
+
+    #[test]
+    fn test_warm_up_rejects_chat_model() {
+        // A decoder-only chat model has no pooling, so llama.cpp refuses sequence embeddings
+        let probe = Err(embeddings_error(EmbeddingsError::NonePoolType));
+        assert!(matches!(
+            check_warm_up(probe),
+            Err(EmbeddingError::NotEmbeddingModel(_))
+        ));
+
+        let probe = Err(embeddings_error(EmbeddingsError::NotEnabled));
+        assert!(matches!(
+            check_warm_up(probe),
+            Err(EmbeddingError::NotEmbeddingModel(_))
+        ));
+
+        // Unusable output is rejected too
+        for output in [Vec::new(), vec![0.0; 8], vec![f32::NAN, 1.0]] {
+            assert!(matches!(
+                check_warm_up(Ok(output)),
+                Err(EmbeddingError::NotEmbeddingModel(_))
+            ));
+        }
+    }
+
+    /// Decoder returning each sequence's first token as its embedding, recording the
+    /// size of every decode
+    #[derive(Default)]
+    struct CountingDecoder {
+        decodes: Vec<usize>,
+    }
+
+    impl SequenceDecoder for CountingDecoder {
+        fn decode_sequences(&mut self, sequences: &[Vec<i32>]) -> Result<Vec<Vec<f32>>> {
+            self.decodes.push(sequences.len());
+            Ok(sequences
+                .iter()
+                .map(|tokens| vec![tokens[0] as f32])
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_batched_embedding_needs_fewer_decodes() {
+        let sequences: Vec<Vec<i32>> = (0..10).map(|i| vec![i; 3]).collect();
+
+        let mut per_text = CountingDecoder::default();
+        for sequence in &sequences {
+            embed_token_sequences(&mut per_text, std::slice::from_ref(sequence), 2048, 32).unwrap();
+        }
+        assert_eq!(per_text.decodes.len(), 10);
+
+        let mut batched = CountingDecoder::default();
+        let embeddings = embed_token_sequences(&mut batched, &sequences, 2048, 32).unwrap();
+        assert_eq!(batched.decodes, [10]);
+        assert_eq!(embeddings.len(), 10);
+    }
+
+    #[test]
+    fn test_batched_embedding_splits_and_keeps_order() {
+        let lengths = [3, 3, 3, 5, 1, 8];
+        let sequences: Vec<Vec<i32>> = lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| vec![i as i32; length])
+            .collect();
+
+        let mut decoder = CountingDecoder::default();
+        let embeddings = embed_token_sequences(&mut decoder, &sequences, 8, 32).unwrap();
+        assert_eq!(decoder.decodes, [2, 2, 1, 1]);
+        let order: Vec<f32> = embeddings.iter().map(|embedding| embedding[0]).collect();
+        assert_eq!(order, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_plan_decodes_limits() {
+        // The sequence limit splits runs that fit the token limit
+        assert_eq!(plan_decodes(&[1; 5], 100, 2), [0..2, 2..4, 4..5]);
+        // A sequence over the token limit runs alone
+        assert_eq!(plan_decodes(&[2, 10, 2], 4, 32), [0..1, 1..2, 2..3]);
+        assert!(plan_decodes(&[], 4, 32).is_empty());
+    }
+
+    #[test]
+    fn test_warm_up_returns_dimension() {
+        assert_eq!(check_warm_up(Ok(vec![0.5; 384])).unwrap(), 384);
+
+        // Other failures are passed through unchanged
+        assert!(matches!(
+            check_warm_up(Err(EmbeddingError::ModelNotLoaded)),
+            Err(EmbeddingError::ModelNotLoaded)
+        ));
+        assert!(matches!(
+            embeddings_error(EmbeddingsError::LogitsNotEnabled),
+            EmbeddingError::TextProcessing(_)
+        ));
     }
 }
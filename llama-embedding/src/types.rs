@@ -1,10 +1,14 @@
+use crate::cache::EmbeddingCacheConfig;
+use crate::error::EmbeddingError;
 use llama_loader::ModelSource;
 use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::path::PathBuf;
 
 /// Configuration for embedding operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmbeddingConfig {
-    /// Model source (HuggingFace or local)
+    /// Model source (HuggingFace, local or URL)
     pub model_source: ModelSource,
     /// Normalize embeddings to unit vectors
     pub normalize_embeddings: bool,
@@ -12,6 +16,33 @@ pub struct EmbeddingConfig {
     pub max_sequence_length: Option<usize>,
     /// Enable debug logging
     pub debug: bool,
+    /// Algorithm used to hash each input text
+    #[serde(default)]
+    pub hash: HashAlgo,
+    /// Allow `Url` model sources to use plain `http`
+    #[serde(default)]
+    pub allow_http: bool,
+    /// Seconds a cached HuggingFace repository listing is used; `None` for the loader default
+    #[serde(default)]
+    pub metadata_ttl_secs: Option<u64>,
+    /// List the HuggingFace repository even when the cached listing is fresh
+    #[serde(default)]
+    pub refresh_metadata: bool,
+    /// Directory for downloaded models; `None` for the loader default
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    /// Load the model even when it is estimated not to fit in available memory
+    #[serde(default)]
+    pub force_load: bool,
+    /// How token embeddings are pooled into one vector per text
+    #[serde(default)]
+    pub pooling: Pooling,
+    /// What happens to a vector with NaN/Inf components or the wrong dimension
+    #[serde(default)]
+    pub invalid_vectors: InvalidVectorPolicy,
+    /// Embeddings kept between runs so repeated texts are not embedded again; `None` to disable
+    #[serde(default)]
+    pub cache: Option<EmbeddingCacheConfig>,
 }
 
 impl Default for EmbeddingConfig {
@@ -24,16 +55,193 @@ impl Default for EmbeddingConfig {
             normalize_embeddings: false,
             max_sequence_length: None,
             debug: false,
+            hash: HashAlgo::default(),
+            allow_http: false,
+            metadata_ttl_secs: None,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            pooling: Pooling::default(),
+            invalid_vectors: InvalidVectorPolicy::default(),
+            cache: None,
         }
     }
 }
 
+/// How token embeddings are pooled into one vector per text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    /// The pooling the model was trained with, as recorded in its GGUF metadata
+    #[default]
+    Native,
+    /// Mean of the token embeddings
+    Mean,
+}
+
+/// What happens to an embedding vector with NaN/Inf components or a dimension other
+/// than the model's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidVectorPolicy {
+    /// The text fails with `EmbeddingError::InvalidVector`
+    #[default]
+    Fail,
+    /// The record is dropped with `EmbeddingError::SkippedRecord`
+    SkipRecord,
+    /// The vector is replaced by zeros and the result flagged `zero_filled`
+    ZeroFill,
+}
+
+impl InvalidVectorPolicy {
+    /// Check `embedding` has `dimension` finite components, applying this policy if not.
+    ///
+    /// Returns the vector to keep and whether it was zero-filled.
+    pub fn apply(
+        self,
+        embedding: Vec<f32>,
+        dimension: usize,
+    ) -> Result<(Vec<f32>, bool), EmbeddingError> {
+        let Some(problem) = vector_problem(&embedding, dimension) else {
+            return Ok((embedding, false));
+        };
+        match self {
+            InvalidVectorPolicy::Fail => Err(EmbeddingError::InvalidVector(problem)),
+            InvalidVectorPolicy::SkipRecord => Err(EmbeddingError::SkippedRecord(problem)),
+            InvalidVectorPolicy::ZeroFill => Ok((vec![0.0; dimension], true)),
+        }
+    }
+}
+
+impl std::fmt::Display for InvalidVectorPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InvalidVectorPolicy::Fail => "fail",
+            InvalidVectorPolicy::SkipRecord => "skip",
+            InvalidVectorPolicy::ZeroFill => "zero-fill",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for InvalidVectorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fail" => Ok(InvalidVectorPolicy::Fail),
+            "skip" => Ok(InvalidVectorPolicy::SkipRecord),
+            "zero-fill" => Ok(InvalidVectorPolicy::ZeroFill),
+            other => Err(format!(
+                "unknown invalid vector policy '{}' (expected fail, skip or zero-fill)",
+                other
+            )),
+        }
+    }
+}
+
+/// What is wrong with `embedding`, if anything
+fn vector_problem(embedding: &[f32], dimension: usize) -> Option<String> {
+    if embedding.len() != dimension {
+        return Some(format!(
+            "dimension {}, expected {}",
+            embedding.len(),
+            dimension
+        ));
+    }
+    let non_finite = embedding.iter().filter(|value| !value.is_finite()).count();
+    (non_finite > 0).then(|| format!("{} NaN or infinite components", non_finite))
+}
+
+/// Hash algorithm for the per-text hash stored with each embedding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    /// Hex MD5 digest
+    #[default]
+    Md5,
+    /// Hex 64-bit XXH3 digest
+    Xxh3,
+    /// Hex SHA-256 digest
+    Sha256,
+    /// Skip hashing; results carry an empty hash
+    None,
+}
+
+impl HashAlgo {
+    /// Hash `text`, or `None` when hashing is disabled
+    pub fn hash(&self, text: &str) -> Option<String> {
+        hash_text(*self, text, digest)
+    }
+
+    /// Name of the output column holding the hash, or `None` when it is omitted.
+    ///
+    /// MD5 keeps the original `text_hash` name so existing outputs stay readable.
+    pub fn column_name(&self) -> Option<&'static str> {
+        match self {
+            HashAlgo::Md5 => Some("text_hash"),
+            HashAlgo::Xxh3 => Some("text_xxh3"),
+            HashAlgo::Sha256 => Some("text_sha256"),
+            HashAlgo::None => None,
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HashAlgo::Md5 => "md5",
+            HashAlgo::Xxh3 => "xxh3",
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for HashAlgo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "md5" => Ok(HashAlgo::Md5),
+            "xxh3" => Ok(HashAlgo::Xxh3),
+            "sha256" => Ok(HashAlgo::Sha256),
+            "none" => Ok(HashAlgo::None),
+            other => Err(format!(
+                "unknown hash algorithm '{}' (expected md5, xxh3, sha256 or none)",
+                other
+            )),
+        }
+    }
+}
+
+/// Hash `text` with `digest` unless hashing is disabled, in which case `digest` is never called
+fn hash_text<F>(algo: HashAlgo, text: &str, digest: F) -> Option<String>
+where
+    F: FnOnce(HashAlgo, &[u8]) -> String,
+{
+    match algo {
+        HashAlgo::None => None,
+        algo => Some(digest(algo, text.as_bytes())),
+    }
+}
+
+fn digest(algo: HashAlgo, bytes: &[u8]) -> String {
+    match algo {
+        HashAlgo::Md5 => format!("{:x}", md5::compute(bytes)),
+        HashAlgo::Xxh3 => format!("{:016x}", xxhash_rust::xxh3::xxh3_64(bytes)),
+        HashAlgo::Sha256 => format!("{:x}", sha2::Sha256::digest(bytes)),
+        HashAlgo::None => String::new(),
+    }
+}
+
 /// Result of a single text embedding operation
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmbeddingResult {
     /// Original text that was embedded
     pub text: String,
-    /// MD5 hash of the text for deduplication
+    /// Hash of the text for deduplication; empty when hashing is disabled
     pub text_hash: String,
     /// Embedding vector
     pub embedding: Vec<f32>,
@@ -41,17 +249,38 @@ pub struct EmbeddingResult {
     pub sequence_length: usize,
     /// Processing time in milliseconds
     pub processing_time_ms: u64,
+    /// The model produced an invalid vector, replaced by zeros under
+    /// `InvalidVectorPolicy::ZeroFill`
+    #[serde(default)]
+    pub zero_filled: bool,
 }
 
 impl EmbeddingResult {
-    /// Create a new embedding result
+    /// Create a new embedding result with an MD5 text hash
     pub fn new(
         text: String,
         embedding: Vec<f32>,
         sequence_length: usize,
         processing_time_ms: u64,
     ) -> Self {
-        let text_hash = format!("{:x}", md5::compute(&text));
+        Self::with_hash(
+            text,
+            embedding,
+            sequence_length,
+            processing_time_ms,
+            HashAlgo::Md5,
+        )
+    }
+
+    /// Create a new embedding result, hashing the text with `hash`
+    pub fn with_hash(
+        text: String,
+        embedding: Vec<f32>,
+        sequence_length: usize,
+        processing_time_ms: u64,
+        hash: HashAlgo,
+    ) -> Self {
+        let text_hash = hash.hash(&text).unwrap_or_default();
 
         Self {
             text,
@@ -59,13 +288,17 @@ impl EmbeddingResult {
             embedding,
             sequence_length,
             processing_time_ms,
+            zero_filled: false,
         }
     }
 
-    /// Normalize the embedding vector to unit length (L2 norm)
+    /// Normalize the embedding vector to unit length (L2 norm).
+    ///
+    /// Zero vectors, and vectors whose magnitude is not finite, are left unchanged
+    /// rather than divided into NaN.
     pub fn normalize(&mut self) {
         let magnitude: f32 = self.embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
-        if magnitude > 0.0 {
+        if magnitude > 0.0 && magnitude.is_finite() {
             for value in &mut self.embedding {
                 *value /= magnitude;
             }
@@ -118,6 +351,53 @@ mod tests {
         assert!((result.embedding[1] - 0.8).abs() < 1e-6);
     }
 
+    #[test]
+    fn test_normalize_leaves_zero_vector() {
+        let mut result = EmbeddingResult::new("test".to_string(), vec![0.0; 4], 1, 1);
+        result.normalize();
+        assert_eq!(result.embedding, vec![0.0; 4]);
+
+        let mut result = EmbeddingResult::new("test".to_string(), vec![f32::INFINITY, 1.0], 1, 1);
+        result.normalize();
+        assert!(!result.embedding.iter().any(|value| value.is_nan()));
+    }
+
+    #[test]
+    fn test_invalid_vector_policies() {
+        let valid = vec![0.5, 0.5];
+        for policy in [
+            InvalidVectorPolicy::Fail,
+            InvalidVectorPolicy::SkipRecord,
+            InvalidVectorPolicy::ZeroFill,
+        ] {
+            assert_eq!(
+                policy.apply(valid.clone(), 2).unwrap(),
+                (valid.clone(), false)
+            );
+        }
+
+        let nan = vec![f32::NAN, 0.5];
+        assert!(matches!(
+            InvalidVectorPolicy::Fail.apply(nan.clone(), 2),
+            Err(EmbeddingError::InvalidVector(_))
+        ));
+        assert!(matches!(
+            InvalidVectorPolicy::SkipRecord.apply(nan.clone(), 2),
+            Err(EmbeddingError::SkippedRecord(_))
+        ));
+        assert_eq!(
+            InvalidVectorPolicy::ZeroFill.apply(nan, 2).unwrap(),
+            (vec![0.0, 0.0], true)
+        );
+
+        // A vector of the wrong dimension is invalid too
+        assert!(matches!(
+            InvalidVectorPolicy::Fail.apply(vec![0.5; 3], 2),
+            Err(EmbeddingError::InvalidVector(message)) if message.contains("dimension 3")
+        ));
+        assert_eq!("zero-fill".parse(), Ok(InvalidVectorPolicy::ZeroFill));
+    }
+
     #[test]
     fn test_embedding_config_default() {
         let config = EmbeddingConfig::default();
@@ -133,4 +413,74 @@ mod tests {
             _ => panic!("Expected HuggingFace model source"),
         }
     }
+
+    #[test]
+    fn test_hash_known_vectors() {
+        let cases = [
+            (HashAlgo::Md5, "", "d41d8cd98f00b204e9800998ecf8427e"),
+            (HashAlgo::Md5, "abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (HashAlgo::Xxh3, "", "2d06800538d394c2"),
+            (HashAlgo::Xxh3, "abc", "78af5f94892f3950"),
+            (
+                HashAlgo::Sha256,
+                "",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                HashAlgo::Sha256,
+                "abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+        ];
+        for (algo, text, expected) in cases {
+            assert_eq!(
+                algo.hash(text).as_deref(),
+                Some(expected),
+                "{} of {:?}",
+                algo,
+                text
+            );
+        }
+        assert_eq!(HashAlgo::None.hash("abc"), None);
+    }
+
+    #[test]
+    fn test_hash_none_computes_nothing() {
+        let calls = std::cell::Cell::new(0);
+        let counting = |algo: HashAlgo, bytes: &[u8]| {
+            calls.set(calls.get() + 1);
+            digest(algo, bytes)
+        };
+
+        for i in 0..10_000 {
+            let text = format!("text number {}", i);
+            assert_eq!(hash_text(HashAlgo::None, &text, counting), None);
+        }
+        assert_eq!(calls.get(), 0);
+
+        assert!(hash_text(HashAlgo::Xxh3, "text", counting).is_some());
+        assert_eq!(calls.get(), 1);
+
+        let result =
+            EmbeddingResult::with_hash("text".to_string(), vec![1.0], 1, 0, HashAlgo::None);
+        assert!(result.text_hash.is_empty());
+    }
+
+    #[test]
+    fn test_hash_algo_names() {
+        for algo in [
+            HashAlgo::Md5,
+            HashAlgo::Xxh3,
+            HashAlgo::Sha256,
+            HashAlgo::None,
+        ] {
+            assert_eq!(algo.to_string().parse::<HashAlgo>().unwrap(), algo);
+        }
+        assert_eq!("SHA256".parse::<HashAlgo>().unwrap(), HashAlgo::Sha256);
+        assert!("crc32".parse::<HashAlgo>().is_err());
+
+        assert_eq!(HashAlgo::Md5.column_name(), Some("text_hash"));
+        assert_eq!(HashAlgo::Xxh3.column_name(), Some("text_xxh3"));
+        assert_eq!(HashAlgo::None.column_name(), None);
+    }
 }
@@ -60,6 +60,7 @@ fn test_batch_config_creation() {
         progress_report_interval_batches: 5,
         memory_limit_mb: Some(100),
         enable_memory_monitoring: false,
+        dedupe: false,
     };
     assert_eq!(custom_config.batch_size, 64);
     assert!(!custom_config.continue_on_error);
@@ -1,4 +1,6 @@
-use llama_embedding::{BatchProcessor, EmbeddingConfig, EmbeddingModel};
+use llama_embedding::{
+    BatchProcessor, EmbeddingConfig, EmbeddingModel, HashAlgo, InvalidVectorPolicy, Pooling,
+};
 use llama_loader::ModelSource;
 use std::io::Write;
 use std::sync::Arc;
@@ -15,6 +17,15 @@ async fn test_embedding_model_creation() {
         normalize_embeddings: true,
         max_sequence_length: Some(512),
         debug: false,
+        hash: HashAlgo::Md5,
+        allow_http: false,
+        metadata_ttl_secs: None,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        pooling: Pooling::Native,
+        invalid_vectors: InvalidVectorPolicy::Fail,
+        cache: None,
     };
 
     // Test model creation (should work even if model loading fails)
@@ -193,6 +204,15 @@ async fn test_real_model_integration() {
         normalize_embeddings: true,
         max_sequence_length: Some(256),
         debug: true,
+        hash: HashAlgo::Md5,
+        allow_http: false,
+        metadata_ttl_secs: None,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        pooling: Pooling::Native,
+        invalid_vectors: InvalidVectorPolicy::Fail,
+        cache: None,
     };
 
     // Would test actual model loading and embedding generation
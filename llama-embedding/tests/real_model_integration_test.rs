@@ -12,7 +12,10 @@
 //! - Error handling scenarios
 //! - Cache integration
 
-use llama_embedding::{BatchProcessor, EmbeddingConfig, EmbeddingModel};
+use llama_embedding::{
+    BatchConfig, BatchProcessor, EmbeddingConfig, EmbeddingModel, HashAlgo, InvalidVectorPolicy,
+    Pooling,
+};
 use llama_loader::ModelSource;
 use std::io::{BufWriter, Write};
 use std::path::Path;
@@ -44,6 +47,15 @@ fn create_qwen_config() -> EmbeddingConfig {
         normalize_embeddings: false,
         max_sequence_length: None,
         debug: true,
+        hash: HashAlgo::Md5,
+        allow_http: false,
+        metadata_ttl_secs: None,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        pooling: Pooling::Native,
+        invalid_vectors: InvalidVectorPolicy::Fail,
+        cache: None,
     }
 }
 
@@ -428,6 +440,42 @@ async fn test_md5_hash_consistency() {
     println!("  - Different texts produce different hashes");
 }
 
+/// Duplicate skipping in the real batch processor, across batch boundaries
+#[tokio::test]
+#[ignore = "Requires real model download"]
+async fn test_dedupe_skips_repeated_lines() {
+    let mut config = create_qwen_config();
+    config.hash = HashAlgo::Xxh3;
+    let mut model = EmbeddingModel::new(config)
+        .await
+        .expect("Failed to create embedding model");
+    model.load_model().await.expect("Failed to load model");
+
+    // 11 lines: one blank and 10 texts, 4 of them distinct
+    let mut temp_file = NamedTempFile::new().unwrap();
+    for line in [
+        "alpha", "beta", "alpha", "gamma", "beta", "", "alpha", "delta", "gamma", "delta", "alpha",
+    ] {
+        writeln!(temp_file, "{}", line).unwrap();
+    }
+
+    let batch_config = BatchConfig {
+        batch_size: 3,
+        dedupe: true,
+        ..Default::default()
+    };
+    let mut processor = BatchProcessor::with_config(Arc::new(model), batch_config);
+    let results = processor
+        .process_file(temp_file.path())
+        .await
+        .expect("Failed to process file");
+
+    let embedded: Vec<&str> = results.iter().map(|r| r.text.as_str()).collect();
+    assert_eq!(embedded, vec!["alpha", "beta", "gamma", "delta"]);
+    assert_eq!(processor.stats().duplicates_skipped, 6);
+    assert_eq!(processor.stats().successful_embeddings, 4);
+}
+
 /// Test 8: Error Handling Tests
 #[tokio::test]
 #[ignore = "Requires real model download"]
@@ -20,6 +20,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         use_hf_params: true,
         retry_config: RetryConfig::default(),
         debug: false,
+        local_search_depth: 0,
+        allow_http: false,
+        metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+        chat_template: None,
+        extra_stop_sequences: Vec::new(),
     };
 
     let local_config = ModelConfig {
@@ -31,6 +40,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         use_hf_params: false,
         retry_config: RetryConfig::default(),
         debug: false,
+        local_search_depth: 0,
+        allow_http: false,
+        metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+        chat_template: None,
+        extra_stop_sequences: Vec::new(),
     };
 
     println!("HuggingFace config: {:?}", hf_config);
@@ -70,6 +88,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         initial_delay_ms: 500,
         backoff_multiplier: 1.5,
         max_delay_ms: 15000,
+        max_retries_transient: Some(8),
+        max_retries_other: Some(1),
     };
 
     println!("Custom retry config: {:?}", retry_config);
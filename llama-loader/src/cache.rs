@@ -3,16 +3,28 @@
 //! Provides efficient caching for downloaded models with LRU eviction and platform-appropriate
 //! cache directories. Enables sharing between `llama-agent`, `llama-embedding`, and `llama-cli` crates.
 
+use crate::detection::pick_hf_model_file;
 use crate::error::ModelError;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::ffi::OsString;
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::fs as async_fs;
 use tracing::{debug, info, warn};
 
 const DEFAULT_MAX_CACHE_SIZE_GB: u64 = 50;
 const CACHE_METADATA_FILENAME: &str = "cache_metadata.json";
+const REPO_LISTINGS_FILENAME: &str = "repo_listings.json";
+/// How long to wait for another process to finish downloading the same file
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// File written to check that the cache directory is writable
+const WRITE_PROBE_FILENAME: &str = ".write_probe";
+
+/// Environment variable naming the cache directory when none is configured
+pub const CACHE_DIR_ENV: &str = "LLAMA_CACHE_DIR";
 
 /// File metadata used for cache key generation
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -52,6 +64,15 @@ pub struct CacheEntry {
     pub last_accessed: u64,
     /// Creation time as unix timestamp
     pub created_at: u64,
+    /// Download request (`repo|filename`) this entry satisfies
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Filename the download resolved to
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// SHA-256 of the cached file, checked before reusing another process's download
+    #[serde(default)]
+    pub sha256: Option<String>,
 }
 
 impl CacheEntry {
@@ -67,6 +88,9 @@ impl CacheEntry {
             size_bytes,
             last_accessed: now,
             created_at: now,
+            source: None,
+            filename: None,
+            sha256: None,
         }
     }
 
@@ -79,6 +103,72 @@ impl CacheEntry {
     }
 }
 
+/// Files of a HuggingFace repository and the model file auto-detected among them
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RepoListing {
+    /// Paths of the files in the repository
+    pub files: Vec<String>,
+    /// Model file picked from `files`
+    pub filename: String,
+    /// When the repository was listed, as unix timestamp
+    pub listed_at: u64,
+}
+
+impl RepoListing {
+    /// Listing of `files` made now, with the model file picked from them
+    pub fn new(files: Vec<String>) -> Result<Self, ModelError> {
+        let filename = pick_hf_model_file(&files)?;
+        Ok(Self {
+            files,
+            filename,
+            listed_at: unix_now(),
+        })
+    }
+
+    /// Time since the repository was listed
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(unix_now().saturating_sub(self.listed_at))
+    }
+
+    /// Whether the listing is younger than `ttl`
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        self.age() < ttl
+    }
+}
+
+/// A file served by [`CacheManager::get_or_download`]
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    /// Local path of the file
+    pub path: PathBuf,
+    /// Filename the download resolved to
+    pub filename: String,
+    /// Size of the file in bytes
+    pub size_bytes: u64,
+    /// SHA-256 of the file, when recorded
+    pub sha256: Option<String>,
+    /// Whether the file was already cached, so nothing was downloaded
+    pub cache_hit: bool,
+}
+
+/// OS advisory lock (`flock`) on a cache entry's lock file, held while it is downloaded.
+///
+/// Released on drop, or by the OS when the holder exits, so a crashed download never leaves
+/// a lock behind. The lock file itself stays: deleting it would let a waiter lock the
+/// removed file while a newcomer locks a new one.
+#[derive(Debug)]
+pub struct CacheLock {
+    path: PathBuf,
+    _file: std::fs::File,
+}
+
+impl CacheLock {
+    /// Path of the lock file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
 /// Cache manager for model files with LRU eviction
 #[derive(Debug)]
 pub struct CacheManager {
@@ -88,6 +178,10 @@ pub struct CacheManager {
     max_cache_size_bytes: Option<u64>,
     /// Cache entries indexed by cache key
     entries: HashMap<String, CacheEntry>,
+    /// HuggingFace repository listings indexed by repo
+    listings: HashMap<String, RepoListing>,
+    /// How long to wait for another download of the same file
+    lock_timeout: Duration,
 }
 
 impl CacheManager {
@@ -97,15 +191,39 @@ impl CacheManager {
             cache_dir,
             max_cache_size_bytes: Some(DEFAULT_MAX_CACHE_SIZE_GB * 1024 * 1024 * 1024),
             entries: HashMap::new(),
+            listings: HashMap::new(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
         }
     }
 
-    /// Create a CacheManager with platform-appropriate default cache directory
+    /// Create a CacheManager in `LLAMA_CACHE_DIR`, or the platform-appropriate default
+    /// cache directory when it is not set
     pub fn with_default_cache_dir() -> Result<Self, ModelError> {
-        let cache_dir = Self::get_platform_cache_dir()?;
+        let cache_dir = Self::resolve_cache_dir(None)?;
         Ok(Self::new(cache_dir))
     }
 
+    /// The cache directory to use: `configured` when given, else `LLAMA_CACHE_DIR`,
+    /// else the platform cache directory
+    pub fn resolve_cache_dir(configured: Option<&Path>) -> Result<PathBuf, ModelError> {
+        Self::resolve_cache_dir_with_env(configured, std::env::var_os(CACHE_DIR_ENV))
+    }
+
+    /// [`resolve_cache_dir`](Self::resolve_cache_dir) with the value of
+    /// `LLAMA_CACHE_DIR` given rather than read from the environment
+    pub fn resolve_cache_dir_with_env(
+        configured: Option<&Path>,
+        env_dir: Option<OsString>,
+    ) -> Result<PathBuf, ModelError> {
+        if let Some(dir) = configured {
+            return Ok(dir.to_path_buf());
+        }
+        match env_dir.filter(|dir| !dir.is_empty()) {
+            Some(dir) => Ok(PathBuf::from(dir)),
+            None => Self::get_platform_cache_dir(),
+        }
+    }
+
     /// Set maximum cache size in GB
     pub fn with_max_size_gb(mut self, max_size_gb: u64) -> Self {
         self.max_cache_size_bytes = Some(max_size_gb * 1024 * 1024 * 1024);
@@ -118,13 +236,19 @@ impl CacheManager {
         self
     }
 
+    /// Set how long to wait for another process downloading the same file
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
     /// Initialize the cache manager by loading existing metadata and ensuring directory exists
     pub async fn initialize(&mut self) -> Result<(), ModelError> {
-        // Create cache directory if it doesn't exist
-        async_fs::create_dir_all(&self.cache_dir).await?;
+        self.ensure_cache_dir().await?;
 
         // Load existing cache metadata
         self.load_metadata().await?;
+        self.load_listings().await;
 
         // Validate and clean up stale entries
         self.cleanup_stale_entries().await?;
@@ -138,7 +262,35 @@ impl CacheManager {
         Ok(())
     }
 
-    /// Get platform-appropriate cache directory
+    /// Create the cache directory, accessible only to the current user, if it is
+    /// missing and check that files can be written to it
+    async fn ensure_cache_dir(&self) -> Result<(), ModelError> {
+        let unwritable = |e: std::io::Error| {
+            ModelError::Cache(format!(
+                "Cache directory {} is not writable: {}\n💡 Pass --cache-dir or set {} to a writable directory",
+                self.cache_dir.display(),
+                e,
+                CACHE_DIR_ENV
+            ))
+        };
+
+        if !self.cache_dir.is_dir() {
+            let mut builder = async_fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            builder.mode(0o700);
+            builder.create(&self.cache_dir).await.map_err(unwritable)?;
+        }
+
+        let probe = self.cache_dir.join(WRITE_PROBE_FILENAME);
+        async_fs::write(&probe, b"").await.map_err(unwritable)?;
+        let _ = async_fs::remove_file(&probe).await;
+        Ok(())
+    }
+
+    /// Get platform-appropriate cache directory: under `$XDG_CACHE_HOME` (or
+    /// `~/.cache`) on Linux, `~/Library/Caches` on macOS and the local app data
+    /// folder on Windows
     pub fn get_platform_cache_dir() -> Result<PathBuf, ModelError> {
         let cache_dir = if cfg!(target_os = "windows") {
             dirs::cache_dir()
@@ -237,7 +389,12 @@ impl CacheManager {
                 model_path.display(),
                 cached_path.display()
             );
-            async_fs::copy(model_path, &cached_path).await?;
+            if model_path.starts_with(&self.cache_dir) {
+                // Downloads staged inside the cache directory are moved rather than copied
+                async_fs::rename(model_path, &cached_path).await?;
+            } else {
+                async_fs::copy(model_path, &cached_path).await?;
+            }
         } else {
             debug!("Model already cached at: {}", cached_path.display());
         }
@@ -258,6 +415,230 @@ impl CacheManager {
         Ok(())
     }
 
+    /// Model file to load from `repo`, auto-detected from a listing of its files.
+    ///
+    /// A cached listing younger than `ttl` is used without calling `list`, unless `refresh`
+    /// is set. Otherwise the repository is listed again, and if that fails the cached listing
+    /// is used regardless of its age, so a brief HuggingFace outage does not stop a model that
+    /// is already cached from loading.
+    pub async fn resolve_repo_file<F, Fut>(
+        &mut self,
+        repo: &str,
+        ttl: Duration,
+        refresh: bool,
+        list: F,
+    ) -> Result<String, ModelError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<String>, ModelError>>,
+    {
+        // Another process may have listed the repository since this one started
+        self.load_listings().await;
+        let cached = self.listings.get(repo).cloned();
+        if let Some(listing) = cached.as_ref().filter(|l| !refresh && l.is_fresh(ttl)) {
+            debug!(
+                "Using listing of {} from {:?} ago: {}",
+                repo,
+                listing.age(),
+                listing.filename
+            );
+            return Ok(listing.filename.clone());
+        }
+
+        match list().await {
+            Ok(files) => {
+                let listing = RepoListing::new(files)?;
+                let filename = listing.filename.clone();
+                self.listings.insert(repo.to_string(), listing);
+                self.save_listings().await?;
+                Ok(filename)
+            }
+            Err(e) => match cached {
+                Some(listing) => {
+                    warn!(
+                        "Failed to list {}, using the listing from {:?} ago: {}",
+                        repo,
+                        listing.age(),
+                        e
+                    );
+                    Ok(listing.filename)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Cached listing of `repo`, if it has been listed
+    pub fn repo_listing(&self, repo: &str) -> Option<&RepoListing> {
+        self.listings.get(repo)
+    }
+
+    /// Return `filename` from `repo`, downloading it with `download` only if no process has yet.
+    ///
+    /// Concurrent callers for the same file, in this or other processes, are serialized by a
+    /// `flock` on a `.lock` file in the cache directory: the first downloads while the rest
+    /// wait (up to the lock timeout) and then reuse its file once its size and checksum check
+    /// out. `download` returns the downloaded path and the filename it resolved to; `filename`
+    /// may be `None` when the download auto-detects it.
+    pub async fn get_or_download<F, Fut>(
+        &mut self,
+        repo: &str,
+        filename: Option<&str>,
+        download: F,
+    ) -> Result<CachedFile, ModelError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(PathBuf, String), ModelError>>,
+    {
+        let source = format!("{}|{}", repo, filename.unwrap_or("*"));
+        let (_lock, waited) = self.lock_entry(&source).await?;
+
+        // Another process may have cached the file since this one started
+        self.load_metadata().await?;
+        if let Some(cached) = self.find_download(repo, filename, waited).await? {
+            info!("Using cached download: {}", cached.path.display());
+            return Ok(cached);
+        }
+
+        let (downloaded_path, actual_filename) = download().await?;
+        let file_metadata = FileMetadata::from_path(&downloaded_path).await?;
+        let cache_key = Self::generate_cache_key(repo, &actual_filename, &file_metadata);
+
+        let (path, cache_hit) = match self.get_cached_model(&cache_key).await {
+            Some(cached) => (cached, true),
+            None => {
+                debug!("Caching model: {}", downloaded_path.display());
+                self.cache_model(&downloaded_path, &cache_key).await?;
+                match self.entries.get(&cache_key) {
+                    // A staged download was moved into the cache
+                    Some(entry) if !downloaded_path.exists() => (entry.path.clone(), false),
+                    _ => (downloaded_path, false),
+                }
+            }
+        };
+
+        let sha256 = file_sha256(&path).await?;
+        if let Some(entry) = self.entries.get_mut(&cache_key) {
+            entry.source = Some(source);
+            entry.filename = Some(actual_filename.clone());
+            entry.sha256 = Some(sha256.clone());
+        }
+        self.save_metadata().await?;
+
+        Ok(CachedFile {
+            path,
+            filename: actual_filename,
+            size_bytes: file_metadata.size_bytes,
+            sha256: Some(sha256),
+            cache_hit,
+        })
+    }
+
+    /// Take the lock for a download request, waiting while another process or task holds it.
+    ///
+    /// Also returns whether it had to wait.
+    pub async fn lock_entry(&self, source: &str) -> Result<(CacheLock, bool), ModelError> {
+        async_fs::create_dir_all(&self.cache_dir).await?;
+        let name = format!("{:x}", Sha256::digest(source.as_bytes()));
+        let path = self.cache_dir.join(format!("{}.lock", name));
+        let start = Instant::now();
+        let mut waited = false;
+
+        loop {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+            match file.try_lock() {
+                Ok(()) => {
+                    debug!("Acquired cache lock {} for {}", path.display(), source);
+                    return Ok((CacheLock { path, _file: file }, waited));
+                }
+                Err(std::fs::TryLockError::WouldBlock) => {
+                    if start.elapsed() >= self.lock_timeout {
+                        return Err(ModelError::Cache(format!(
+                            "Timed out after {:?} waiting for another download of {} (lock file {})",
+                            self.lock_timeout,
+                            source,
+                            path.display()
+                        )));
+                    }
+                    if !waited {
+                        info!("Waiting for another download of {}", source);
+                        waited = true;
+                    }
+                    tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+                }
+                Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Find a cached file for a download request, dropping it if it fails verification
+    async fn find_download(
+        &mut self,
+        repo: &str,
+        filename: Option<&str>,
+        verify_checksum: bool,
+    ) -> Result<Option<CachedFile>, ModelError> {
+        let source = format!("{}|{}", repo, filename.unwrap_or("*"));
+        // Files downloaded while auto-detecting the filename serve requests for that filename
+        let auto_detected = format!("{}|*", repo);
+        let Some((key, entry)) = self
+            .entries
+            .iter()
+            .find(|(_, entry)| {
+                entry.source.as_deref() == Some(source.as_str())
+                    || (entry.source.as_deref() == Some(auto_detected.as_str())
+                        && filename.is_some()
+                        && entry.filename.as_deref() == filename)
+            })
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+        else {
+            return Ok(None);
+        };
+
+        let size_matches = async_fs::metadata(&entry.path)
+            .await
+            .is_ok_and(|metadata| metadata.len() == entry.size_bytes);
+        let checksum_matches = match (&entry.sha256, verify_checksum && size_matches) {
+            (Some(expected), true) => file_sha256(&entry.path).await? == *expected,
+            _ => true,
+        };
+
+        if !size_matches || !checksum_matches {
+            warn!(
+                "Cached file {} failed verification, downloading again",
+                entry.path.display()
+            );
+            self.entries.remove(&key);
+            self.save_metadata().await?;
+            return Ok(None);
+        }
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.touch();
+        }
+        self.save_metadata().await?;
+
+        let filename = entry.filename.clone().unwrap_or_else(|| {
+            entry
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        Ok(Some(CachedFile {
+            path: entry.path,
+            filename,
+            size_bytes: entry.size_bytes,
+            sha256: entry.sha256,
+            cache_hit: true,
+        }))
+    }
+
     /// Cleanup old models using LRU eviction
     pub async fn cleanup_old_models(&mut self) -> Result<(), ModelError> {
         if let Some(max_size) = self.max_cache_size_bytes {
@@ -266,6 +647,11 @@ impl CacheManager {
         Ok(())
     }
 
+    /// Directory where cached models are stored
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
     /// Get current cache size in bytes
     pub fn get_cache_size_bytes(&self) -> u64 {
         self.entries.values().map(|e| e.size_bytes).sum()
@@ -371,6 +757,27 @@ impl CacheManager {
         Ok(())
     }
 
+    /// Load repository listings from disk, keeping none if they cannot be read
+    async fn load_listings(&mut self) {
+        let listings_path = self.cache_dir.join(REPO_LISTINGS_FILENAME);
+        let Ok(content) = async_fs::read_to_string(&listings_path).await else {
+            return;
+        };
+        match serde_json::from_str(&content) {
+            Ok(listings) => self.listings = listings,
+            Err(e) => warn!("Failed to parse repository listings, ignoring them: {}", e),
+        }
+    }
+
+    /// Save repository listings to disk
+    async fn save_listings(&self) -> Result<(), ModelError> {
+        async_fs::create_dir_all(&self.cache_dir).await?;
+        let content = serde_json::to_string_pretty(&self.listings)
+            .map_err(|e| ModelError::Cache(format!("Failed to serialize listings: {}", e)))?;
+        async_fs::write(self.cache_dir.join(REPO_LISTINGS_FILENAME), content).await?;
+        Ok(())
+    }
+
     /// Save cache metadata to disk
     async fn save_metadata(&self) -> Result<(), ModelError> {
         let metadata_path = self.cache_dir.join(CACHE_METADATA_FILENAME);
@@ -385,6 +792,27 @@ impl CacheManager {
     }
 }
 
+/// Current time as unix timestamp
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// SHA-256 of a file's contents as lowercase hex
+async fn file_sha256(path: &Path) -> Result<String, ModelError> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(|e| ModelError::Cache(format!("Checksum task failed: {}", e)))?
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -411,6 +839,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cache_dir_precedence() {
+        let configured = Path::new("/scratch/configured");
+        let env_dir = || Some(OsString::from("/scratch/env"));
+
+        // Configuration (e.g. --cache-dir) > LLAMA_CACHE_DIR > platform default
+        assert_eq!(
+            CacheManager::resolve_cache_dir_with_env(Some(configured), env_dir()).unwrap(),
+            configured
+        );
+        assert_eq!(
+            CacheManager::resolve_cache_dir_with_env(None, env_dir()).unwrap(),
+            Path::new("/scratch/env")
+        );
+        let platform = CacheManager::get_platform_cache_dir().unwrap();
+        assert_eq!(
+            CacheManager::resolve_cache_dir_with_env(None, None).unwrap(),
+            platform
+        );
+        // An empty variable counts as unset
+        assert_eq!(
+            CacheManager::resolve_cache_dir_with_env(None, Some(OsString::new())).unwrap(),
+            platform
+        );
+    }
+
+    #[tokio::test]
+    async fn test_initialize_creates_private_cache_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_dir = temp_dir.path().join("nested").join("cache");
+        let mut cache_manager = CacheManager::new(cache_dir.clone());
+        cache_manager.initialize().await.unwrap();
+
+        assert!(cache_dir.is_dir());
+        assert!(!cache_dir.join(WRITE_PROBE_FILENAME).exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&cache_dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_initialize_unwritable_cache_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        // A directory cannot be created below a regular file
+        let file = temp_dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let mut cache_manager = CacheManager::new(file.join("cache"));
+
+        let err = cache_manager.initialize().await.unwrap_err();
+        assert!(matches!(err, ModelError::Cache(_)));
+        let message = err.to_string();
+        assert!(message.contains("is not writable"), "{}", message);
+        assert!(message.contains(CACHE_DIR_ENV));
+    }
+
     #[tokio::test]
     async fn test_platform_cache_dir() {
         let cache_dir = CacheManager::get_platform_cache_dir().unwrap();
@@ -572,4 +1058,269 @@ mod tests {
             assert_eq!(cache_manager.get_cache_count(), 1);
         }
     }
+
+    /// Stub download that writes `content` and counts its calls
+    async fn stub_download(
+        dir: PathBuf,
+        content: &'static [u8],
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    ) -> Result<(PathBuf, String), ModelError> {
+        calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join("model.gguf");
+        create_test_file(&path, content).await?;
+        Ok((path, "model.gguf".to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_downloads_share_one_download() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        // Separate managers stand in for separate processes sharing the cache directory
+        let tasks: Vec<_> = (0..2)
+            .map(|i| {
+                let cache_dir = cache_dir.clone();
+                let download_dir = temp_dir.path().join(format!("download-{}", i));
+                let calls = calls.clone();
+                tokio::spawn(async move {
+                    let mut cache_manager = CacheManager::new(cache_dir);
+                    cache_manager.initialize().await.unwrap();
+                    cache_manager
+                        .get_or_download("org/repo", Some("model.gguf"), || {
+                            stub_download(download_dir, b"model weights", calls)
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await.unwrap());
+        }
+
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(results.iter().filter(|r| r.cache_hit).count(), 1);
+        for result in &results {
+            assert_eq!(result.filename, "model.gguf");
+            assert_eq!(result.size_bytes, 13);
+            assert_eq!(
+                tokio::fs::read(&result.path).await.unwrap(),
+                b"model weights"
+            );
+        }
+
+        // The lock is released once both are done
+        let cache_manager = CacheManager::new(cache_dir);
+        let (_, waited) = cache_manager
+            .lock_entry("org/repo|model.gguf")
+            .await
+            .unwrap();
+        assert!(!waited);
+    }
+
+    #[tokio::test]
+    async fn test_lock_file_left_behind_does_not_block() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_manager = CacheManager::new(temp_dir.path().to_path_buf())
+            .with_lock_timeout(Duration::from_secs(5));
+
+        // A crashed holder leaves its lock file, but the OS has released the lock
+        let (lock, _) = cache_manager.lock_entry("org/repo|*").await.unwrap();
+        let lock_path = lock.path().to_path_buf();
+        drop(lock);
+        assert!(lock_path.exists());
+
+        let (lock, waited) = cache_manager.lock_entry("org/repo|*").await.unwrap();
+        assert!(!waited);
+        assert_eq!(lock.path(), lock_path);
+    }
+
+    #[tokio::test]
+    async fn test_live_lock_times_out() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_manager = CacheManager::new(temp_dir.path().to_path_buf())
+            .with_lock_timeout(Duration::from_millis(300));
+
+        // Locks are per open file, so a second lock in the same process waits too
+        let _held = cache_manager.lock_entry("org/repo|*").await.unwrap();
+        let error = cache_manager.lock_entry("org/repo|*").await.unwrap_err();
+        assert!(matches!(error, ModelError::Cache(ref msg) if msg.contains("Timed out")));
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_cached_file_is_downloaded_again() {
+        let temp_dir = TempDir::new().unwrap();
+        let download_dir = temp_dir.path().join("download");
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut cache_manager = CacheManager::new(temp_dir.path().join("cache"));
+        cache_manager.initialize().await.unwrap();
+
+        let first = cache_manager
+            .get_or_download("org/repo", None, || {
+                stub_download(download_dir.clone(), b"model weights", calls.clone())
+            })
+            .await
+            .unwrap();
+        assert!(!first.cache_hit);
+
+        // Truncate the cached copy, as an interrupted copy would
+        let entry_path = cache_manager.entries.values().next().unwrap().path.clone();
+        std::fs::write(&entry_path, b"model").unwrap();
+
+        cache_manager
+            .get_or_download("org/repo", None, || {
+                stub_download(download_dir.clone(), b"model weights", calls.clone())
+            })
+            .await
+            .unwrap();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    /// Stub repository listing that counts its calls, failing when `files` is `None`
+    async fn stub_listing(
+        files: Option<&'static [&'static str]>,
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    ) -> Result<Vec<String>, ModelError> {
+        calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        match files {
+            Some(files) => Ok(files.iter().map(|f| f.to_string()).collect()),
+            None => Err(ModelError::Network(
+                "HuggingFace API unavailable".to_string(),
+            )),
+        }
+    }
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[tokio::test]
+    async fn test_repo_listing_is_reused_until_it_expires() {
+        let temp_dir = TempDir::new().unwrap();
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut cache_manager = CacheManager::new(temp_dir.path().to_path_buf());
+        cache_manager.initialize().await.unwrap();
+        let files = Some(&["README.md", "model-Q4_K_M.gguf"][..]);
+
+        for _ in 0..2 {
+            let filename = cache_manager
+                .resolve_repo_file("org/repo", DAY, false, || {
+                    stub_listing(files, calls.clone())
+                })
+                .await
+                .unwrap();
+            assert_eq!(filename, "model-Q4_K_M.gguf");
+        }
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // An expired listing, or a forced refresh, lists the repository again
+        let renamed = Some(&["model-Q5_K_M.gguf"][..]);
+        let filename = cache_manager
+            .resolve_repo_file("org/repo", Duration::ZERO, false, || {
+                stub_listing(renamed, calls.clone())
+            })
+            .await
+            .unwrap();
+        assert_eq!(filename, "model-Q5_K_M.gguf");
+        cache_manager
+            .resolve_repo_file("org/repo", DAY, true, || {
+                stub_listing(renamed, calls.clone())
+            })
+            .await
+            .unwrap();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        let listing = cache_manager.repo_listing("org/repo").unwrap();
+        assert_eq!(listing.files, ["model-Q5_K_M.gguf"]);
+        assert!(listing.is_fresh(DAY));
+    }
+
+    #[tokio::test]
+    async fn test_stale_repo_listing_is_used_when_listing_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut cache_manager = CacheManager::new(temp_dir.path().to_path_buf());
+        cache_manager.initialize().await.unwrap();
+
+        // Without a cached listing the error is returned
+        let error = cache_manager
+            .resolve_repo_file("org/repo", DAY, false, || stub_listing(None, calls.clone()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ModelError::Network(_)));
+
+        cache_manager
+            .resolve_repo_file("org/repo", DAY, false, || {
+                stub_listing(Some(&["model.gguf"]), calls.clone())
+            })
+            .await
+            .unwrap();
+        let filename = cache_manager
+            .resolve_repo_file("org/repo", Duration::ZERO, false, || {
+                stub_listing(None, calls.clone())
+            })
+            .await
+            .unwrap();
+        assert_eq!(filename, "model.gguf");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cached_startup_lists_and_downloads_nothing() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let download_dir = temp_dir.path().join("download");
+        let listings = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let downloads = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        // Each startup uses a fresh manager, as a new process would
+        for _ in 0..2 {
+            let mut cache_manager = CacheManager::new(cache_dir.clone());
+            cache_manager.initialize().await.unwrap();
+            let filename = cache_manager
+                .resolve_repo_file("org/repo", DAY, false, || {
+                    stub_listing(Some(&["model.gguf"]), listings.clone())
+                })
+                .await
+                .unwrap();
+            cache_manager
+                .get_or_download("org/repo", Some(&filename), || {
+                    stub_download(download_dir.clone(), b"model weights", downloads.clone())
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(listings.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(downloads.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_auto_detected_download_serves_its_filename() {
+        let temp_dir = TempDir::new().unwrap();
+        let download_dir = temp_dir.path().join("download");
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut cache_manager = CacheManager::new(temp_dir.path().join("cache"));
+        cache_manager.initialize().await.unwrap();
+
+        // Cached before filenames were resolved from the listing
+        cache_manager
+            .get_or_download("org/repo", None, || {
+                stub_download(download_dir.clone(), b"model weights", calls.clone())
+            })
+            .await
+            .unwrap();
+        let cached = cache_manager
+            .get_or_download("org/repo", Some("model.gguf"), || {
+                stub_download(download_dir.clone(), b"model weights", calls.clone())
+            })
+            .await
+            .unwrap();
+
+        assert!(cached.cache_hit);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
 }
@@ -1,5 +1,9 @@
 use crate::error::ModelError;
-use crate::multipart::detect_multi_part_base;
+use crate::huggingface::list_repo_files;
+use crate::multipart::ShardName;
+use crate::resolver::{ModelResolver, RepoFile, Resolution, ResolutionPrefs};
+use crate::types::{is_glob_pattern, ModelConfig, ModelSource};
+use std::path::{Path, PathBuf};
 use tracing::info;
 
 /// Auto-detects the best model file from a HuggingFace repository
@@ -9,63 +13,261 @@ pub async fn auto_detect_hf_model_file(
     // List files in the repository
     match repo_api.info().await {
         Ok(repo_info) => {
-            let mut gguf_files = Vec::new();
-            let mut bf16_files = Vec::new();
-
-            // Look for GGUF files in the repository
-            for sibling in repo_info.siblings {
-                if sibling.rfilename.ends_with(".gguf") {
-                    let filename = sibling.rfilename.to_lowercase();
-                    if filename.contains("bf16") {
-                        bf16_files.push(sibling.rfilename);
-                    } else {
-                        gguf_files.push(sibling.rfilename);
-                    }
+            let files: Vec<String> = repo_info
+                .siblings
+                .into_iter()
+                .map(|sibling| sibling.rfilename)
+                .collect();
+            pick_hf_model_file(&files)
+        }
+        Err(e) => Err(ModelError::LoadingFailed(format!(
+            "Failed to get repository info: {}",
+            e
+        ))),
+    }
+}
+
+/// Picks the model file to load from the files of a HuggingFace repository.
+///
+/// BF16 files are preferred, then the shallowest and first `.gguf` file in sorted
+/// order; for multi-part models the name of the first part is returned. The reasons
+/// for the choice are logged; see [`ModelResolver`].
+pub fn pick_hf_model_file(files: &[String]) -> Result<String, ModelError> {
+    let files: Vec<RepoFile> = files.iter().map(RepoFile::new).collect();
+    let resolution = ModelResolver::resolve(&files, &ResolutionPrefs::default());
+    info!("Model file auto-detection: {}", resolution);
+    resolution.into_chosen("HuggingFace repository")
+}
+
+/// Explains which file auto-detection picks for `config` and why, without loading it.
+///
+/// Returns `None` when the source names its file, so nothing is detected. HuggingFace
+/// repositories are listed afresh rather than from the listing cache.
+pub async fn explain_model_choice(config: &ModelConfig) -> Result<Option<Resolution>, ModelError> {
+    let files = match &config.source {
+        ModelSource::HuggingFace {
+            repo,
+            filename: None,
+        } => list_repo_files(repo)
+            .await?
+            .into_iter()
+            .map(RepoFile::new)
+            .collect(),
+        ModelSource::Local {
+            folder,
+            filename: None,
+        } if folder.is_dir() => local_repo_files(
+            folder,
+            &collect_gguf_files(folder, config.local_search_depth)?,
+        ),
+        _ => return Ok(None),
+    };
+    Ok(Some(ModelResolver::resolve(
+        &files,
+        &ResolutionPrefs::default(),
+    )))
+}
+
+/// Finds the model file to load from a local folder.
+///
+/// Searches `folder` and, up to `max_depth` levels of subdirectories, for
+/// `.gguf` files. With no `filename` one is picked by [`ModelResolver`],
+/// preferring BF16 and shallower files. A `filename` containing `*` or `?` is matched
+/// (case-insensitively) against file names and must identify a single model;
+/// shards of one multi-part model count as a single match. Any other
+/// `filename` must exist directly in `folder` or, failing that, exactly once
+/// in the searched subdirectories. A `folder` that is itself a model file is
+/// returned as is.
+pub fn find_local_model_file(
+    folder: &Path,
+    filename: Option<&str>,
+    max_depth: usize,
+) -> Result<PathBuf, ModelError> {
+    // A model file given in place of its folder
+    if folder.is_file() && filename.is_none() {
+        return Ok(folder.to_path_buf());
+    }
+
+    if let Some(filename) = filename.filter(|f| !is_glob_pattern(f)) {
+        let direct = folder.join(filename);
+        if direct.is_file() || max_depth == 0 {
+            return if direct.exists() {
+                Ok(direct)
+            } else {
+                Err(ModelError::NotFound(format!(
+                    "Model file does not exist: {}",
+                    direct.display()
+                )))
+            };
+        }
+    }
+
+    let files = collect_gguf_files(folder, max_depth)?;
+
+    let Some(filename) = filename else {
+        let resolution = ModelResolver::resolve(
+            &local_repo_files(folder, &files),
+            &ResolutionPrefs::default(),
+        );
+        info!("Model file auto-detection: {}", resolution);
+        return resolution
+            .into_chosen(&folder.display().to_string())
+            .map(|chosen| folder.join(chosen));
+    };
+
+    let pattern = filename.to_lowercase();
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    for path in &files {
+        let name = file_name_lower(path);
+        let matched = if is_glob_pattern(filename) {
+            glob_match(&pattern, &name)
+        } else {
+            name == pattern
+        };
+        let candidate = first_shard_path(path);
+        if matched && !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+
+    match candidates.len() {
+        0 => Err(ModelError::NotFound(format!(
+            "No model file matching '{}' found in {} (searched {} levels deep)",
+            filename,
+            folder.display(),
+            max_depth
+        ))),
+        1 => {
+            info!(
+                "Found model file matching '{}': {:?}",
+                filename, candidates[0]
+            );
+            Ok(candidates.remove(0))
+        }
+        _ => Err(ModelError::AmbiguousModelFile {
+            pattern: filename.to_string(),
+            candidates: candidates
+                .iter()
+                .map(|path| {
+                    path.strip_prefix(folder)
+                        .unwrap_or(path)
+                        .display()
+                        .to_string()
+                })
+                .collect(),
+        }),
+    }
+}
+
+/// Lists `.gguf` files under `folder` up to `max_depth` levels deep, shallowest first
+fn collect_gguf_files(folder: &Path, max_depth: usize) -> Result<Vec<PathBuf>, ModelError> {
+    let mut files = Vec::new();
+    let mut level = vec![folder.to_path_buf()];
+
+    for depth in 0..=max_depth {
+        let mut next_level = Vec::new();
+        let mut level_files = Vec::new();
+
+        for dir in &level {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                // The top-level folder must be readable; unreadable subfolders are skipped
+                Err(e) if depth == 0 => {
+                    return Err(ModelError::LoadingFailed(format!(
+                        "Cannot read directory {}: {}",
+                        dir.display(),
+                        e
+                    )))
                 }
-            }
+                Err(_) => continue,
+            };
 
-            // Prioritize BF16 files - check for multi-part files first
-            if !bf16_files.is_empty() {
-                // Sort to ensure consistent ordering
-                bf16_files.sort();
-
-                // Check if this is a multi-part file
-                if let Some(base_filename) = detect_multi_part_base(&bf16_files[0]) {
-                    info!("Found multi-part BF16 model file: {}", base_filename);
-                    return Ok(base_filename);
-                } else {
-                    info!("Found BF16 model file: {}", bf16_files[0]);
-                    return Ok(bf16_files[0].clone());
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    next_level.push(path);
+                } else if path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("gguf"))
+                {
+                    level_files.push(path);
                 }
             }
+        }
 
-            // Fallback to first GGUF file
-            if !gguf_files.is_empty() {
-                gguf_files.sort();
-                if let Some(base_filename) = detect_multi_part_base(&gguf_files[0]) {
-                    info!("Found multi-part GGUF model file: {}", base_filename);
-                    return Ok(base_filename);
-                } else {
-                    info!("Found GGUF model file: {}", gguf_files[0]);
-                    return Ok(gguf_files[0].clone());
-                }
+        level_files.sort();
+        files.extend(level_files);
+        next_level.sort();
+        level = next_level;
+    }
+
+    Ok(files)
+}
+
+/// `files` as paths relative to `folder`, with their sizes
+fn local_repo_files(folder: &Path, files: &[PathBuf]) -> Vec<RepoFile> {
+    files
+        .iter()
+        .map(|path| {
+            let relative = path.strip_prefix(folder).unwrap_or(path);
+            let file = RepoFile::new(relative.to_string_lossy());
+            match std::fs::metadata(path) {
+                Ok(metadata) => file.with_size(metadata.len()),
+                Err(_) => file,
             }
+        })
+        .collect()
+}
+
+fn file_name_lower(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+/// Maps any shard of a multi-part model to its first shard, which llama.cpp loads
+fn first_shard_path(path: &Path) -> PathBuf {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(ShardName::parse)
+        .map(|shard| path.with_file_name(shard.filename_for(1)))
+        .unwrap_or_else(|| path.to_path_buf())
+}
 
-            Err(ModelError::NotFound(
-                "No .gguf model files found in HuggingFace repository".to_string(),
-            ))
+/// Matches `text` against a pattern where `*` matches any run of characters and `?` any one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last `*` absorb one more character
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, t));
+        } else {
+            return false;
         }
-        Err(e) => Err(ModelError::LoadingFailed(format!(
-            "Failed to get repository info: {}",
-            e
-        ))),
     }
+
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use tempfile::TempDir;
 
-    // Note: These would be integration tests that require actual HuggingFace API access
+    // Note: HuggingFace detection would need integration tests with actual API access
     // For unit testing, we'd need to mock the ApiRepo and repo_info structures
 
     #[test]
@@ -73,4 +275,171 @@ mod tests {
         // Basic test to ensure the module compiles correctly
         // If this test runs, the module definition is valid
     }
+
+    #[test]
+    fn test_pick_hf_model_file() {
+        let files = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let repo = files(&["README.md", "model-Q8_0.gguf", "model-Q4_K_M.gguf"]);
+        assert_eq!(pick_hf_model_file(&repo).unwrap(), "model-Q4_K_M.gguf");
+
+        let repo = files(&[
+            "model-Q4_K_M.gguf",
+            "BF16/model-BF16-00002-of-00002.gguf",
+            "BF16/model-BF16-00001-of-00002.gguf",
+        ]);
+        assert_eq!(
+            pick_hf_model_file(&repo).unwrap(),
+            "BF16/model-BF16-00001-of-00002.gguf"
+        );
+
+        assert!(matches!(
+            pick_hf_model_file(&files(&["README.md"])),
+            Err(ModelError::NotFound(_))
+        ));
+    }
+
+    /// Creates a model tree: models/{top.gguf, qwen/Q4/*, qwen/Q8/*, llama/split-*}
+    fn create_model_tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let files = [
+            "top.gguf",
+            "notes.txt",
+            "qwen/Q4/qwen-q4_k_m.gguf",
+            "qwen/Q8/qwen-q8_0.gguf",
+            "qwen/Q8/qwen-BF16.gguf",
+            "llama/split-Q4_K_M-00001-of-00002.gguf",
+            "llama/split-Q4_K_M-00002-of-00002.gguf",
+        ];
+        for file in files {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"GGUF").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*q4_k_m*.gguf", "qwen-q4_k_m.gguf"));
+        assert!(glob_match("model-?.gguf", "model-1.gguf"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("model-?.gguf", "model-10.gguf"));
+        assert!(!glob_match("*.gguf", "model.bin"));
+        assert!(!glob_match("a*b", "acbc"));
+    }
+
+    #[test]
+    fn test_find_local_model_without_recursion() {
+        let dir = create_model_tree();
+
+        let found = find_local_model_file(dir.path(), None, 0).unwrap();
+        assert_eq!(found, dir.path().join("top.gguf"));
+
+        // Nested files are invisible at depth 0
+        let result = find_local_model_file(dir.path(), Some("qwen-q8_0.gguf"), 0);
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+        let result = find_local_model_file(dir.path(), Some("*q8_0*"), 0);
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_find_local_model_recursive_prefers_bf16() {
+        let dir = create_model_tree();
+        std::fs::remove_file(dir.path().join("top.gguf")).unwrap();
+
+        let found = find_local_model_file(dir.path(), None, 2).unwrap();
+        assert_eq!(found, dir.path().join("qwen/Q8/qwen-BF16.gguf"));
+    }
+
+    #[tokio::test]
+    async fn test_explain_model_choice_for_local_folder() {
+        let dir = create_model_tree();
+        let config = ModelConfig {
+            source: ModelSource::Local {
+                folder: dir.path().to_path_buf(),
+                filename: None,
+            },
+            local_search_depth: 2,
+            ..Default::default()
+        };
+
+        let resolution = explain_model_choice(&config).await.unwrap().unwrap();
+        assert_eq!(resolution.chosen.as_deref(), Some("qwen/Q8/qwen-BF16.gguf"));
+        // Only .gguf files are collected; the two shards are sized together
+        assert_eq!(resolution.candidates.len(), 6);
+        for candidate in &resolution.candidates {
+            let expected = if candidate.path.starts_with("llama/") {
+                8
+            } else {
+                4
+            };
+            assert_eq!(candidate.size_bytes, Some(expected), "{}", candidate.path);
+        }
+
+        // Nothing is detected when the file is named
+        let config = ModelConfig {
+            source: ModelSource::Local {
+                folder: dir.path().to_path_buf(),
+                filename: Some("top.gguf".to_string()),
+            },
+            ..config
+        };
+        assert!(explain_model_choice(&config).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_local_model_given_model_file() {
+        let dir = create_model_tree();
+        let model_file = dir.path().join("qwen/Q4/qwen-q4_k_m.gguf");
+
+        assert_eq!(
+            find_local_model_file(&model_file, None, 2).unwrap(),
+            model_file
+        );
+    }
+
+    #[test]
+    fn test_find_local_model_exact_name_in_subfolder() {
+        let dir = create_model_tree();
+
+        let found = find_local_model_file(dir.path(), Some("qwen-q8_0.gguf"), 2).unwrap();
+        assert_eq!(found, dir.path().join("qwen/Q8/qwen-q8_0.gguf"));
+
+        // Depth 1 does not reach qwen/Q8
+        let result = find_local_model_file(dir.path(), Some("qwen-q8_0.gguf"), 1);
+        assert!(matches!(result, Err(ModelError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_find_local_model_glob_resolves_multipart_to_first_shard() {
+        let dir = create_model_tree();
+
+        let found = find_local_model_file(dir.path(), Some("split-*"), 1).unwrap();
+        assert_eq!(
+            found,
+            dir.path().join("llama/split-Q4_K_M-00001-of-00002.gguf")
+        );
+    }
+
+    #[test]
+    fn test_find_local_model_ambiguous_glob_lists_candidates() {
+        let dir = create_model_tree();
+
+        match find_local_model_file(dir.path(), Some("*q4_k_m*"), 2) {
+            Err(ModelError::AmbiguousModelFile {
+                pattern,
+                candidates,
+            }) => {
+                assert_eq!(pattern, "*q4_k_m*");
+                assert_eq!(candidates.len(), 2);
+                assert!(candidates.iter().any(|c| c.ends_with("qwen-q4_k_m.gguf")));
+                assert!(candidates
+                    .iter()
+                    .any(|c| c.ends_with("split-Q4_K_M-00001-of-00002.gguf")));
+            }
+            other => panic!("Expected AmbiguousModelFile, got {:?}", other),
+        }
+    }
 }
@@ -1,3 +1,6 @@
+use crate::memory::format_bytes;
+use crate::retry::RetryReport;
+use std::path::PathBuf;
 use thiserror::Error;
 
 /// Errors that can occur during model loading operations
@@ -7,6 +10,10 @@ pub enum ModelError {
     #[error("Model loading failed: {0}\n🔧 Check available memory (4-8GB needed), verify GGUF file integrity, ensure compatible llama.cpp version")]
     LoadingFailed(String),
 
+    /// A request needs the model, but none is loaded
+    #[error("Model not loaded\n⏳ Wait for the model to finish loading, or load one before sending requests")]
+    NotLoaded,
+
     /// Model not found at the specified location
     #[error("Model not found: {0}\n📁 Verify file path is correct, file exists and is readable. For HuggingFace: check repo name and filename")]
     NotFound(String),
@@ -30,6 +37,64 @@ pub enum ModelError {
     /// Cache operation error
     #[error("Cache error: {0}\n💽 Check cache directory permissions and disk space")]
     Cache(String),
+
+    /// A filename pattern matched more than one local model
+    #[error("Pattern '{pattern}' matches multiple model files: {}\n🎯 Use a more specific filename pattern or an exact filename", candidates.join(", "))]
+    AmbiguousModelFile {
+        pattern: String,
+        candidates: Vec<String>,
+    },
+
+    /// One or more shards of a multi-part model could not be obtained
+    #[error("Incomplete multi-part model, missing shards: {}\n🧩 Check the repository contains every shard and retry to resume the download", missing.join(", "))]
+    IncompleteMultipart { missing: Vec<String> },
+
+    /// A downloaded file did not match its expected SHA-256
+    #[error("Checksum mismatch for {url}: expected sha256 {expected}, got {actual}\n🔐 Verify the sha256 in the model config matches the file served at this URL")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// The file is not a GGUF model llama.cpp can read
+    #[error("Invalid model file {}: {detail}\n📄 Point the model source at a .gguf file; convert other formats with llama.cpp's convert_hf_to_gguf.py", path.display())]
+    InvalidFormat { path: PathBuf, detail: String },
+
+    /// Loading needs more memory than the system has available
+    #[error("Not enough memory to load the model: about {} needed, {} available\n🧠 Use a smaller quantization (e.g. Q4_K_M) or close other programs", format_bytes(*required_estimate), format_bytes(*available))]
+    OutOfMemory {
+        required_estimate: u64,
+        available: u64,
+    },
+
+    /// The pre-load estimate says the model will not fit in available memory
+    #[error("Model needs about {} of memory, only {} available\n🧠 Use a smaller quantization or context, close other programs, or set force_load (--force-load) to load anyway", format_bytes(*required), format_bytes(*available))]
+    InsufficientMemory { required: u64, available: u64 },
+
+    /// llama.cpp does not know the model's architecture
+    #[error("Unsupported model architecture '{arch}'\n🏗️ This llama.cpp build cannot run it; upgrade llama-agent or choose a model with a supported architecture")]
+    UnsupportedArchitecture { arch: String },
+
+    /// The file is shorter than its header declares
+    #[error("Model file {} is truncated: expected {expected} bytes, found {actual}\n✂️ The download was probably interrupted; delete the file and download it again", path.display())]
+    Truncated {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+
+    /// Loading was cancelled, e.g. by Ctrl-C during a download
+    #[error("Model loading cancelled\n⏹️ Partial downloads were removed")]
+    Cancelled,
+
+    /// Download failed after exhausting retries (or failing fast)
+    #[error("Model download failed: {message}")]
+    DownloadFailed {
+        message: String,
+        /// Every failed attempt, for diagnostics
+        report: RetryReport,
+    },
 }
 
 impl ModelError {
@@ -38,6 +103,14 @@ impl ModelError {
         Self::LoadingFailed(message.into())
     }
 
+    /// Per-attempt retry history, if this error came from a retried download
+    pub fn retry_report(&self) -> Option<&RetryReport> {
+        match self {
+            ModelError::DownloadFailed { report, .. } => Some(report),
+            _ => None,
+        }
+    }
+
     /// Check if this error is retriable
     pub fn is_retriable(&self) -> bool {
         matches!(
@@ -90,6 +163,27 @@ mod tests {
         assert!(!ModelError::InferenceFailed("test".to_string()).is_retriable());
     }
 
+    #[test]
+    fn test_load_failure_messages() {
+        let err = ModelError::Truncated {
+            path: PathBuf::from("/models/model.gguf"),
+            expected: 4096,
+            actual: 1024,
+        };
+        assert!(!err.is_retriable());
+        assert!(err.to_string().starts_with(
+            "Model file /models/model.gguf is truncated: expected 4096 bytes, found 1024"
+        ));
+
+        let err = ModelError::OutOfMemory {
+            required_estimate: 6 * 1024 * 1024 * 1024,
+            available: 2 * 1024 * 1024 * 1024,
+        };
+        assert!(err
+            .to_string()
+            .contains("about 6.0 GiB needed, 2.0 GiB available"));
+    }
+
     #[test]
     fn test_io_error_conversion() {
         let io_err = io::Error::new(io::ErrorKind::NotFound, "file not found");
@@ -104,11 +198,30 @@ mod tests {
         assert!(matches!(model_err, ModelError::LoadingFailed(_)));
     }
 
+    #[test]
+    fn test_download_failed_carries_retry_report() {
+        let err = ModelError::DownloadFailed {
+            message: "404 Not Found".to_string(),
+            report: RetryReport::new("download of 'model.gguf'"),
+        };
+        assert!(!err.is_retriable());
+        assert_eq!(
+            err.retry_report().map(|r| r.operation.as_str()),
+            Some("download of 'model.gguf'")
+        );
+        assert!(ModelError::Network("test".to_string())
+            .retry_report()
+            .is_none());
+    }
+
     #[test]
     fn test_error_display() {
         let err = ModelError::LoadingFailed("test error".to_string());
         let display_str = format!("{}", err);
         assert!(display_str.contains("test error"));
         assert!(display_str.contains("🔧")); // Contains helpful emoji
+
+        assert!(!ModelError::Cancelled.is_retriable());
+        assert!(ModelError::Cancelled.to_string().contains("cancelled"));
     }
 }
@@ -0,0 +1,155 @@
+//! Sampling defaults published with HuggingFace models
+//!
+//! Many repositories ship a `generation_config.json` next to their weights with the
+//! sampling settings the authors recommend. With `ModelConfig::use_hf_params` the
+//! loader fetches it alongside the GGUF file and keeps the fields llama-agent can use.
+//! The file is optional: a missing or malformed one only logs a warning.
+
+use crate::error::ModelError;
+use serde::{Deserialize, Deserializer};
+use std::path::Path;
+
+/// Name of the sampling defaults file in a HuggingFace repository
+pub const GENERATION_CONFIG_FILENAME: &str = "generation_config.json";
+
+/// Sampling defaults read from a model's `generation_config.json`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct HfGenerationDefaults {
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    #[serde(default)]
+    pub repetition_penalty: Option<f32>,
+    /// End-of-sequence tokens; the file gives either one id or a list
+    #[serde(default, rename = "eos_token_id", deserialize_with = "one_or_many")]
+    pub eos_token_ids: Vec<u32>,
+    /// Total length of prompt and completion
+    #[serde(default)]
+    pub max_length: Option<u32>,
+    #[serde(default)]
+    pub max_new_tokens: Option<u32>,
+}
+
+impl HfGenerationDefaults {
+    /// Parse the contents of a `generation_config.json`
+    pub fn from_json(json: &str) -> Result<Self, ModelError> {
+        serde_json::from_str(json).map_err(|e| {
+            ModelError::InvalidConfig(format!("Malformed {}: {}", GENERATION_CONFIG_FILENAME, e))
+        })
+    }
+
+    /// Read and parse a downloaded `generation_config.json`
+    pub fn from_file(path: &Path) -> Result<Self, ModelError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Completion length limit: `max_new_tokens`. `max_length` also counts the prompt,
+    /// so it does not bound the completion and is not used
+    pub fn max_tokens(&self) -> Option<u32> {
+        self.max_new_tokens
+    }
+
+    /// Whether the file set none of the fields used for generation
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(u32),
+        Many(Vec<u32>),
+    }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(id)) => vec![id],
+        Some(OneOrMany::Many(ids)) => ids,
+        None => Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn fixture(json: &str) -> HfGenerationDefaults {
+        HfGenerationDefaults::from_json(json).unwrap()
+    }
+
+    #[test]
+    fn test_parse_real_world_generation_configs() {
+        let llama3 = fixture(include_str!("../tests/data/llama3_generation_config.json"));
+        assert_eq!(llama3.temperature, Some(0.6));
+        assert_eq!(llama3.top_p, Some(0.9));
+        assert_eq!(llama3.top_k, None);
+        assert_eq!(llama3.eos_token_ids, vec![128001, 128008, 128009]);
+        assert_eq!(llama3.max_tokens(), None);
+
+        let qwen2 = fixture(include_str!("../tests/data/qwen2_generation_config.json"));
+        assert_eq!(qwen2.temperature, Some(0.7));
+        assert_eq!(qwen2.top_p, Some(0.8));
+        assert_eq!(qwen2.top_k, Some(20));
+        assert_eq!(qwen2.repetition_penalty, Some(1.05));
+        assert_eq!(qwen2.eos_token_ids, vec![151645, 151643]);
+
+        // A single eos id and only a total length
+        let mistral = fixture(include_str!("../tests/data/mistral_generation_config.json"));
+        assert_eq!(mistral.eos_token_ids, vec![2]);
+        assert_eq!(mistral.max_length, Some(32768));
+        assert_eq!(mistral.max_tokens(), None);
+        assert_eq!(mistral.temperature, None);
+
+        // Nothing about sampling beyond the end tokens
+        let gemma2 = fixture(include_str!("../tests/data/gemma2_generation_config.json"));
+        assert_eq!(gemma2.eos_token_ids, vec![1, 107]);
+        assert!(!gemma2.is_empty());
+        assert!(fixture("{}").is_empty());
+    }
+
+    #[test]
+    fn test_max_tokens_ignores_max_length() {
+        let defaults = fixture(r#"{"max_length": 4096, "max_new_tokens": 512}"#);
+        assert_eq!(defaults.max_tokens(), Some(512));
+    }
+
+    #[test]
+    fn test_null_fields_are_unset() {
+        let defaults = fixture(r#"{"temperature": null, "eos_token_id": null}"#);
+        assert!(defaults.is_empty());
+    }
+
+    #[test]
+    fn test_malformed_generation_config() {
+        for json in [
+            "not json",
+            r#"{"temperature": "hot"}"#,
+            r#"{"eos_token_id": -1}"#,
+        ] {
+            let err = HfGenerationDefaults::from_json(json).unwrap_err();
+            assert!(matches!(err, ModelError::InvalidConfig(_)), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_from_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(GENERATION_CONFIG_FILENAME);
+        std::fs::write(&path, r#"{"top_k": 40}"#).unwrap();
+        assert_eq!(
+            HfGenerationDefaults::from_file(&path).unwrap().top_k,
+            Some(40)
+        );
+
+        let missing = dir.path().join("missing.json");
+        assert!(matches!(
+            HfGenerationDefaults::from_file(&missing),
+            Err(ModelError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,580 @@
+//! GGUF header checks run before a file is handed to llama.cpp
+//!
+//! llama.cpp reports every failed load the same way, so the header is read first: a
+//! file without the GGUF magic fails with [`ModelError::InvalidFormat`], and one
+//! shorter than its header says with [`ModelError::Truncated`]. When llama.cpp still
+//! fails, [`load_model_file`] blames an architecture it does not know or a lack of
+//! memory where it can tell.
+
+use crate::error::ModelError;
+use crate::memory::{available_memory_bytes, estimate_required_memory, ModelShape};
+use llama_cpp_2::{
+    llama_backend::LlamaBackend,
+    model::{params::LlamaModelParams, LlamaModel},
+};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+/// Tensor data alignment when the file does not set `general.alignment`
+const DEFAULT_ALIGNMENT: u64 = 32;
+
+/// Dimensions a ggml tensor may have
+const MAX_DIMS: u32 = 4;
+
+/// Architectures this llama.cpp build loads, by `general.architecture` value
+const KNOWN_ARCHITECTURES: &[&str] = &[
+    "llama",
+    "llama4",
+    "deci",
+    "falcon",
+    "grok",
+    "gpt2",
+    "gptj",
+    "gptneox",
+    "mpt",
+    "baichuan",
+    "starcoder",
+    "refact",
+    "bert",
+    "nomic-bert",
+    "nomic-bert-moe",
+    "modern-bert",
+    "jina-bert-v2",
+    "bloom",
+    "stablelm",
+    "qwen",
+    "qwen2",
+    "qwen2moe",
+    "qwen2vl",
+    "qwen3",
+    "qwen3moe",
+    "phi2",
+    "phi3",
+    "phimoe",
+    "plamo",
+    "codeshell",
+    "orion",
+    "internlm2",
+    "minicpm",
+    "minicpm3",
+    "gemma",
+    "gemma2",
+    "gemma3",
+    "gemma3n",
+    "starcoder2",
+    "mamba",
+    "mamba2",
+    "xverse",
+    "command-r",
+    "cohere2",
+    "dbrx",
+    "olmo",
+    "olmo2",
+    "olmoe",
+    "openelm",
+    "arctic",
+    "deepseek",
+    "deepseek2",
+    "chatglm",
+    "glm4",
+    "bitnet",
+    "t5",
+    "t5encoder",
+    "jais",
+    "nemotron",
+    "exaone",
+    "rwkv6",
+    "rwkv6qwen2",
+    "rwkv7",
+    "granite",
+    "granitemoe",
+    "chameleon",
+    "wavtokenizer-dec",
+    "plm",
+    "bailingmoe",
+    "dots1",
+    "arcee",
+    "ernie4_5",
+    "hunyuan-moe",
+    "smollm3",
+    "mistral3",
+];
+
+/// What the header of a GGUF file declares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GgufHeader {
+    pub version: u32,
+    pub tensor_count: u64,
+    /// `general.architecture`, if set
+    pub architecture: Option<String>,
+    /// Layer and attention sizes, as far as the metadata gives them
+    pub shape: ModelShape,
+    /// Smallest file size holding every tensor the header lists
+    pub declared_size: u64,
+    /// Actual size of the file
+    pub file_size: u64,
+}
+
+/// Read and check the header of the GGUF file at `path`
+pub fn read_header(path: &Path) -> Result<GgufHeader, ModelError> {
+    let file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let mut reader = HeaderReader {
+        inner: BufReader::new(file),
+        path: path.to_path_buf(),
+        pos: 0,
+        len: file_size,
+    };
+
+    let magic = reader.bytes(GGUF_MAGIC.len() as u64).map_err(|e| match e {
+        // Too short to even hold the magic
+        ModelError::Truncated { .. } => reader.invalid("file is too small to be a GGUF model"),
+        other => other,
+    })?;
+    if magic != GGUF_MAGIC {
+        return Err(reader.invalid(&format!("expected GGUF magic bytes, found {:02x?}", magic)));
+    }
+
+    let version = reader.u32()?;
+    if !(2..=3).contains(&version) {
+        return Err(reader.invalid(&format!("unsupported GGUF version {}", version)));
+    }
+    let tensor_count = reader.u64()?;
+    let metadata_count = reader.u64()?;
+
+    let mut architecture = None;
+    let mut alignment = DEFAULT_ALIGNMENT;
+    let mut shape = ModelShape::default();
+    for _ in 0..metadata_count {
+        let key = reader.string()?;
+        let value_type = reader.u32()?;
+        match (key.as_str(), value_type) {
+            ("general.architecture", 8) => architecture = Some(reader.string()?),
+            ("general.alignment", 4) => alignment = u64::from(reader.u32()?).max(1),
+            (key, 4 | 10) => match shape_field(&mut shape, key) {
+                Some(field) => *field = Some(reader.unsigned(value_type)?),
+                None => reader.skip_value(value_type)?,
+            },
+            _ => reader.skip_value(value_type)?,
+        }
+    }
+
+    let mut data_size = 0u64;
+    for _ in 0..tensor_count {
+        reader.skip_string()?;
+        let dims = reader.u32()?;
+        if dims > MAX_DIMS {
+            return Err(reader.invalid(&format!("tensor with {} dimensions", dims)));
+        }
+        let mut elements = 1u64;
+        for _ in 0..dims {
+            elements = elements.saturating_mul(reader.u64()?);
+        }
+        let tensor_type = reader.u32()?;
+        let offset = reader.u64()?;
+        let size = tensor_bytes(tensor_type, elements).unwrap_or(0);
+        data_size = data_size.max(offset.saturating_add(size));
+    }
+
+    let declared_size = if tensor_count == 0 {
+        reader.pos
+    } else {
+        reader
+            .pos
+            .div_ceil(alignment)
+            .saturating_mul(alignment)
+            .saturating_add(data_size)
+    };
+    if file_size < declared_size {
+        return Err(ModelError::Truncated {
+            path: path.to_path_buf(),
+            expected: declared_size,
+            actual: file_size,
+        });
+    }
+
+    debug!(
+        "GGUF v{} header of {}: {} tensors, architecture {:?}",
+        version,
+        path.display(),
+        tensor_count,
+        architecture
+    );
+    Ok(GgufHeader {
+        version,
+        tensor_count,
+        architecture,
+        shape,
+        declared_size,
+        file_size,
+    })
+}
+
+/// The [`ModelShape`] field an architecture-prefixed metadata key such as
+/// `llama.block_count` sets
+fn shape_field<'a>(shape: &'a mut ModelShape, key: &str) -> Option<&'a mut Option<u64>> {
+    let (prefix, name) = key.split_once('.')?;
+    if prefix == "general" {
+        return None;
+    }
+    match name {
+        "block_count" => Some(&mut shape.layer_count),
+        "embedding_length" => Some(&mut shape.embedding_length),
+        "attention.head_count" => Some(&mut shape.head_count),
+        "attention.head_count_kv" => Some(&mut shape.head_count_kv),
+        _ => None,
+    }
+}
+
+/// Load a model file into llama.cpp, checking its header first
+pub fn load_model_file(
+    backend: &LlamaBackend,
+    path: &Path,
+    params: &LlamaModelParams,
+) -> Result<LlamaModel, ModelError> {
+    let header = read_header(path)?;
+    load_model_file_with_header(backend, path, &header, params)
+}
+
+/// [`load_model_file`] for a file whose `header` was already read and checked
+pub fn load_model_file_with_header(
+    backend: &LlamaBackend,
+    path: &Path,
+    header: &GgufHeader,
+    params: &LlamaModelParams,
+) -> Result<LlamaModel, ModelError> {
+    LlamaModel::load_from_file(backend, path, params)
+        .map_err(|e| classify_load_failure(path, header, &e.to_string(), available_memory_bytes()))
+}
+
+/// Explain why llama.cpp could not load a file whose header looked fine
+pub fn classify_load_failure(
+    path: &Path,
+    header: &GgufHeader,
+    detail: &str,
+    available_memory: Option<u64>,
+) -> ModelError {
+    if let Some(arch) = &header.architecture {
+        if !KNOWN_ARCHITECTURES.contains(&arch.as_str()) {
+            return ModelError::UnsupportedArchitecture { arch: arch.clone() };
+        }
+    }
+
+    let required_estimate = estimate_required_memory(header.file_size);
+    match available_memory {
+        Some(available) if required_estimate > available => ModelError::OutOfMemory {
+            required_estimate,
+            available,
+        },
+        _ => ModelError::LoadingFailed(format!(
+            "Failed to load model from {}: {}",
+            path.display(),
+            detail
+        )),
+    }
+}
+
+/// Bytes taken by a tensor of `elements` elements of ggml type `tensor_type`; `None`
+/// for types without a fixed block layout
+fn tensor_bytes(tensor_type: u32, elements: u64) -> Option<u64> {
+    let (block_elements, block_bytes) = match tensor_type {
+        0 => (1, 4),      // F32
+        1 => (1, 2),      // F16
+        2 => (32, 18),    // Q4_0
+        3 => (32, 20),    // Q4_1
+        6 => (32, 22),    // Q5_0
+        7 => (32, 24),    // Q5_1
+        8 => (32, 34),    // Q8_0
+        9 => (32, 36),    // Q8_1
+        10 => (256, 84),  // Q2_K
+        11 => (256, 110), // Q3_K
+        12 => (256, 144), // Q4_K
+        13 => (256, 176), // Q5_K
+        14 => (256, 210), // Q6_K
+        15 => (256, 292), // Q8_K
+        24 => (1, 1),     // I8
+        25 => (1, 2),     // I16
+        26 => (1, 4),     // I32
+        27 => (1, 8),     // I64
+        28 => (1, 8),     // F64
+        30 => (1, 2),     // BF16
+        _ => return None,
+    };
+    Some((elements / block_elements).saturating_mul(block_bytes))
+}
+
+/// Little-endian reader that reports reads past the end of the file as truncation
+struct HeaderReader {
+    inner: BufReader<File>,
+    path: PathBuf,
+    pos: u64,
+    len: u64,
+}
+
+impl HeaderReader {
+    fn invalid(&self, detail: &str) -> ModelError {
+        ModelError::InvalidFormat {
+            path: self.path.clone(),
+            detail: detail.to_string(),
+        }
+    }
+
+    /// Fail unless `n` more bytes follow
+    fn need(&self, n: u64) -> Result<(), ModelError> {
+        let end = self.pos.saturating_add(n);
+        if end > self.len {
+            return Err(ModelError::Truncated {
+                path: self.path.clone(),
+                expected: end,
+                actual: self.len,
+            });
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, n: u64) -> Result<Vec<u8>, ModelError> {
+        self.need(n)?;
+        let mut buf = vec![0; n as usize];
+        self.inner.read_exact(&mut buf)?;
+        self.pos += n;
+        Ok(buf)
+    }
+
+    fn skip(&mut self, n: u64) -> Result<(), ModelError> {
+        self.need(n)?;
+        // Keeps the buffer when skipping within it, which most skipped values are
+        self.inner.seek_relative(n as i64)?;
+        self.pos += n;
+        Ok(())
+    }
+
+    fn u32(&mut self) -> Result<u32, ModelError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+
+    fn u64(&mut self) -> Result<u64, ModelError> {
+        let bytes = self.bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+    }
+
+    /// An unsigned integer of GGUF type u32 or u64
+    fn unsigned(&mut self, value_type: u32) -> Result<u64, ModelError> {
+        match value_type {
+            4 => Ok(u64::from(self.u32()?)),
+            _ => self.u64(),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ModelError> {
+        let len = self.u64()?;
+        Ok(String::from_utf8_lossy(&self.bytes(len)?).into_owned())
+    }
+
+    fn skip_string(&mut self) -> Result<(), ModelError> {
+        let len = self.u64()?;
+        self.skip(len)
+    }
+
+    /// Skip a metadata value of GGUF type `value_type`
+    fn skip_value(&mut self, value_type: u32) -> Result<(), ModelError> {
+        if let Some(size) = fixed_value_size(value_type) {
+            return self.skip(size);
+        }
+        match value_type {
+            8 => self.skip_string(),
+            9 => {
+                let element_type = self.u32()?;
+                let count = self.u64()?;
+                match fixed_value_size(element_type) {
+                    Some(size) => self.skip(count.saturating_mul(size)),
+                    None => {
+                        for _ in 0..count {
+                            self.skip_value(element_type)?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            other => Err(self.invalid(&format!("unknown metadata value type {}", other))),
+        }
+    }
+}
+
+/// Size of a GGUF metadata value of fixed width
+fn fixed_value_size(value_type: u32) -> Option<u64> {
+    match value_type {
+        0 | 1 | 7 => Some(1), // u8, i8, bool
+        2 | 3 => Some(2),     // u16, i16
+        4..=6 => Some(4),     // u32, i32, f32
+        10..=12 => Some(8),   // u64, i64, f64
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn string(out: &mut Vec<u8>, value: &str) {
+        out.extend((value.len() as u64).to_le_bytes());
+        out.extend(value.as_bytes());
+    }
+
+    /// A GGUF v3 file with an architecture, 16 layers and one F32 tensor of
+    /// `elements` values, followed by `data_bytes` bytes of tensor data
+    fn gguf_file(architecture: &str, elements: u64, data_bytes: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(GGUF_MAGIC);
+        out.extend(3u32.to_le_bytes());
+        out.extend(1u64.to_le_bytes()); // tensors
+        out.extend(5u64.to_le_bytes()); // metadata entries
+
+        string(&mut out, "general.name");
+        out.extend(8u32.to_le_bytes());
+        string(&mut out, "test model");
+        string(&mut out, "general.architecture");
+        out.extend(8u32.to_le_bytes());
+        string(&mut out, architecture);
+        string(&mut out, &format!("{}.block_count", architecture));
+        out.extend(4u32.to_le_bytes());
+        out.extend(16u32.to_le_bytes());
+        string(&mut out, &format!("{}.embedding_length", architecture));
+        out.extend(10u32.to_le_bytes());
+        out.extend(2048u64.to_le_bytes());
+        // Per-layer head counts are arrays, which do not set the shape
+        string(&mut out, &format!("{}.attention.head_count", architecture));
+        out.extend(9u32.to_le_bytes());
+        out.extend(4u32.to_le_bytes());
+        out.extend(2u64.to_le_bytes());
+        out.extend(16u32.to_le_bytes());
+        out.extend(16u32.to_le_bytes());
+
+        string(&mut out, "weight");
+        out.extend(1u32.to_le_bytes());
+        out.extend(elements.to_le_bytes());
+        out.extend(0u32.to_le_bytes()); // F32
+        out.extend(0u64.to_le_bytes()); // offset
+
+        while out.len() % DEFAULT_ALIGNMENT as usize != 0 {
+            out.push(0);
+        }
+        out.extend(vec![0u8; data_bytes]);
+        out
+    }
+
+    #[test]
+    fn test_valid_header() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        let contents = gguf_file("llama", 16, 64);
+        std::fs::write(&path, &contents).unwrap();
+
+        let header = read_header(&path).unwrap();
+        assert_eq!(header.version, 3);
+        assert_eq!(header.tensor_count, 1);
+        assert_eq!(header.architecture.as_deref(), Some("llama"));
+        assert_eq!(
+            header.shape,
+            ModelShape {
+                layer_count: Some(16),
+                embedding_length: Some(2048),
+                head_count: None,
+                head_count_kv: None,
+            }
+        );
+        assert_eq!(header.declared_size, contents.len() as u64);
+        assert_eq!(header.file_size, contents.len() as u64);
+    }
+
+    #[test]
+    fn test_wrong_magic() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        std::fs::write(&path, b"PK\x03\x04 this is a zip archive").unwrap();
+
+        match read_header(&path) {
+            Err(ModelError::InvalidFormat { path: p, detail }) => {
+                assert_eq!(p, path);
+                assert!(detail.contains("magic"), "{}", detail);
+            }
+            other => panic!("expected InvalidFormat, got {:?}", other),
+        }
+
+        std::fs::write(&path, b"GG").unwrap();
+        assert!(matches!(
+            read_header(&path),
+            Err(ModelError::InvalidFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_tensor_data() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        let contents = gguf_file("llama", 16, 64);
+        std::fs::write(&path, &contents[..contents.len() - 40]).unwrap();
+
+        match read_header(&path) {
+            Err(ModelError::Truncated {
+                expected, actual, ..
+            }) => {
+                assert_eq!(expected, contents.len() as u64);
+                assert_eq!(actual, contents.len() as u64 - 40);
+            }
+            other => panic!("expected Truncated, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        let contents = gguf_file("llama", 16, 64);
+        std::fs::write(&path, &contents[..30]).unwrap();
+
+        match read_header(&path) {
+            Err(ModelError::Truncated {
+                expected, actual, ..
+            }) => {
+                assert_eq!(actual, 30);
+                assert!(expected > 30);
+            }
+            other => panic!("expected Truncated, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_classify_load_failure() {
+        let path = Path::new("/models/model.gguf");
+        let header = |architecture: &str| GgufHeader {
+            version: 3,
+            tensor_count: 1,
+            architecture: Some(architecture.to_string()),
+            shape: ModelShape::default(),
+            declared_size: 1000,
+            file_size: 1000,
+        };
+
+        assert!(matches!(
+            classify_load_failure(path, &header("quantum-llm"), "null result", None),
+            ModelError::UnsupportedArchitecture { arch } if arch == "quantum-llm"
+        ));
+        assert!(matches!(
+            classify_load_failure(path, &header("llama"), "null result", Some(500)),
+            ModelError::OutOfMemory {
+                required_estimate: 1200,
+                available: 500
+            }
+        ));
+        assert!(matches!(
+            classify_load_failure(path, &header("llama"), "null result", Some(1 << 30)),
+            ModelError::LoadingFailed(msg) if msg.contains("null result")
+        ));
+    }
+}
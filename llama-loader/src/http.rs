@@ -0,0 +1,428 @@
+use crate::error::ModelError;
+use crate::retry::retry_with_report;
+use crate::types::RetryConfig;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::fs as async_fs;
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// Failure of a single download attempt, worded for retry classification
+#[derive(Debug, Error)]
+enum FetchError {
+    #[error("{0}")]
+    Http(reqwest::Error),
+
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("checksum mismatch: expected sha256 {expected}, got {actual}")]
+    Checksum { expected: String, actual: String },
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        // Drop the URL so its host or port cannot skew classification
+        FetchError::Http(err.without_url())
+    }
+}
+
+/// Returns true when a model argument is an `http(s)://` link rather than a repo or path
+pub fn is_model_url(model: &str) -> bool {
+    let lower = model.to_ascii_lowercase();
+    lower.starts_with("https://") || lower.starts_with("http://")
+}
+
+/// Parse a model URL, accepting `https` and, when `allow_http` is set, plain `http`
+pub fn parse_model_url(url: &str, allow_http: bool) -> Result<Url, ModelError> {
+    let parsed = Url::parse(url)
+        .map_err(|e| ModelError::InvalidConfig(format!("Invalid model URL '{}': {}", url, e)))?;
+
+    match parsed.scheme() {
+        "https" => Ok(parsed),
+        "http" if allow_http => Ok(parsed),
+        "http" => Err(ModelError::InvalidConfig(format!(
+            "Model URL '{}' uses plain http; use https or set allow_http",
+            url
+        ))),
+        scheme => Err(ModelError::InvalidConfig(format!(
+            "Unsupported scheme '{}' in model URL '{}'; only https is supported",
+            scheme, url
+        ))),
+    }
+}
+
+/// Filename for a URL source: `filename` if given, otherwise the last segment of the URL path
+pub fn url_filename(url: &Url, filename: Option<&str>) -> Result<String, ModelError> {
+    if let Some(filename) = filename {
+        return Ok(filename.to_string());
+    }
+
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            ModelError::InvalidConfig(format!(
+                "Cannot derive a filename from model URL '{}'; set filename",
+                url
+            ))
+        })
+}
+
+/// Key identifying a URL in the cache: SHA-256 of the normalized URL
+pub fn url_cache_key(url: &Url) -> String {
+    format!("{:x}", Sha256::digest(url.as_str().as_bytes()))
+}
+
+/// Check that `sha256` looks like a hex SHA-256 digest
+pub fn validate_sha256(sha256: &str) -> Result<(), ModelError> {
+    if sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(ModelError::InvalidConfig(format!(
+            "Invalid sha256 '{}': expected 64 hex characters",
+            sha256
+        )))
+    }
+}
+
+/// Download `url` to `dest_dir/filename` with retries, verifying `sha256` when given.
+///
+/// The body is written to a `.part` file that is renamed into place once complete.
+/// Cancelling `cancel` abandons the download, removes the `.part` file
+/// and returns [`ModelError::Cancelled`].
+pub async fn download_url(
+    url: &Url,
+    filename: &str,
+    sha256: Option<&str>,
+    dest_dir: &Path,
+    retry_config: &RetryConfig,
+    cancel: &CancellationToken,
+) -> Result<PathBuf, ModelError> {
+    async_fs::create_dir_all(dest_dir).await?;
+    let dest = dest_dir.join(filename);
+    let partial = dest_dir.join(format!("{}.part", filename));
+    let operation = format!("download of '{}'", url);
+
+    info!("Downloading model from {}", url);
+    let result = tokio::select! {
+        biased;
+        _ = cancel.cancelled() => None,
+        result = retry_with_report(&operation, retry_config, || {
+            fetch_to_file(url, &partial, sha256)
+        }) => Some(result),
+    };
+    let Some(result) = result else {
+        // The attempt holding the file open was dropped with the select
+        let _ = async_fs::remove_file(&partial).await;
+        info!("Download of {} cancelled", url);
+        return Err(ModelError::Cancelled);
+    };
+    match result {
+        Ok(()) => {
+            async_fs::rename(&partial, &dest).await?;
+            Ok(dest)
+        }
+        Err((e, report)) => {
+            let _ = async_fs::remove_file(&partial).await;
+            Err(ModelError::DownloadFailed {
+                message: format!(
+                    "Failed to download model from '{}' after {} retries: {}\n💡 Check the URL is reachable and serves the model file",
+                    url,
+                    report.attempts.len().saturating_sub(1),
+                    e
+                ),
+                report,
+            })
+        }
+    }
+}
+
+/// One download attempt, streaming the body to `path`
+async fn fetch_to_file(url: &Url, path: &Path, sha256: Option<&str>) -> Result<(), FetchError> {
+    let mut response = reqwest::get(url.clone()).await?.error_for_status()?;
+
+    let mut file = async_fs::File::create(path).await?;
+    let mut hasher = Sha256::new();
+    let mut size_bytes = 0u64;
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+        size_bytes += chunk.len() as u64;
+    }
+    file.sync_all().await?;
+    debug!("Downloaded {} bytes from {}", size_bytes, url);
+
+    if let Some(expected) = sha256 {
+        let actual = format!("{:x}", hasher.finalize());
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(FetchError::Checksum {
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Minimal HTTP server for download tests
+#[cfg(test)]
+pub(crate) mod test_server {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Serve `body` at `/model.gguf` and 404 elsewhere; returns the base URL and a request counter
+    pub async fn serve(body: &'static [u8]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let request = read_request(&mut stream).await;
+                let (status, body): (&str, &[u8]) = if request.starts_with("GET /model.gguf ") {
+                    ("200 OK", body)
+                } else {
+                    ("404 Not Found", b"not found")
+                };
+                let header = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes()).await;
+                let _ = stream.write_all(body).await;
+            }
+        });
+
+        (base, requests)
+    }
+
+    /// Serve the first `head` bytes of a `total`-byte body for any path, then stall
+    /// as a slow download would; returns the base URL
+    pub async fn serve_stalled(head: &'static [u8], total: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    read_request(&mut stream).await;
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        total
+                    );
+                    let _ = stream.write_all(header.as_bytes()).await;
+                    let _ = stream.write_all(head).await;
+                    let _ = stream.flush().await;
+                    tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                });
+            }
+        });
+
+        base
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        String::from_utf8_lossy(&request).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use tempfile::tempdir;
+
+    const BODY: &[u8] = b"GGUF model bytes";
+
+    fn fast_retries() -> RetryConfig {
+        RetryConfig {
+            max_retries: 1,
+            initial_delay_ms: 10,
+            ..RetryConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_model_url_schemes() {
+        assert!(parse_model_url("https://example.com/m.gguf", false).is_ok());
+        assert!(parse_model_url("http://example.com/m.gguf", true).is_ok());
+
+        let error = parse_model_url("http://example.com/m.gguf", false).unwrap_err();
+        assert!(error.to_string().contains("allow_http"));
+        let error = parse_model_url("ftp://example.com/m.gguf", true).unwrap_err();
+        assert!(error.to_string().contains("Unsupported scheme 'ftp'"));
+        assert!(parse_model_url("not a url", false).is_err());
+    }
+
+    #[test]
+    fn test_is_model_url() {
+        assert!(is_model_url("https://example.com/m.gguf"));
+        assert!(is_model_url("HTTP://example.com/m.gguf"));
+        assert!(!is_model_url("org/repo"));
+        assert!(!is_model_url("./models/m.gguf"));
+    }
+
+    #[test]
+    fn test_url_filename() {
+        let url = Url::parse("https://example.com/models/m-Q4_K_M.gguf?token=x").unwrap();
+        assert_eq!(url_filename(&url, None).unwrap(), "m-Q4_K_M.gguf");
+        assert_eq!(
+            url_filename(&url, Some("other.gguf")).unwrap(),
+            "other.gguf"
+        );
+
+        let url = Url::parse("https://example.com/").unwrap();
+        assert!(url_filename(&url, None).is_err());
+    }
+
+    #[test]
+    fn test_url_cache_key() {
+        let key = |url: &str| url_cache_key(&Url::parse(url).unwrap());
+
+        let base = key("https://example.com/m.gguf");
+        assert_eq!(base.len(), 64);
+        // Equivalent spellings normalize to the same key
+        assert_eq!(base, key("HTTPS://Example.COM/m.gguf"));
+        assert_eq!(base, key("https://example.com:443/m.gguf"));
+        assert_ne!(base, key("https://example.com/other.gguf"));
+        assert_ne!(base, key("https://mirror.example.com/m.gguf"));
+    }
+
+    #[test]
+    fn test_validate_sha256() {
+        assert!(validate_sha256(&"ab".repeat(32)).is_ok());
+        assert!(validate_sha256("abc").is_err());
+        assert!(validate_sha256(&"zz".repeat(32)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_url() {
+        let (base, requests) = test_server::serve(BODY).await;
+        let dir = tempdir().unwrap();
+        let url = parse_model_url(&format!("{}/model.gguf", base), true).unwrap();
+        let sha256 = format!("{:x}", Sha256::digest(BODY));
+
+        let path = download_url(
+            &url,
+            "model.gguf",
+            Some(&sha256),
+            dir.path(),
+            &fast_retries(),
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(path, dir.path().join("model.gguf"));
+        assert_eq!(std::fs::read(&path).unwrap(), BODY);
+        assert!(!dir.path().join("model.gguf.part").exists());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_download_url_failures() {
+        let (base, requests) = test_server::serve(BODY).await;
+        let dir = tempdir().unwrap();
+
+        // 404 fails without retrying
+        let url = parse_model_url(&format!("{}/missing.gguf", base), true).unwrap();
+        let error = download_url(
+            &url,
+            "missing.gguf",
+            None,
+            dir.path(),
+            &fast_retries(),
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(error, ModelError::DownloadFailed { ref report, .. } if report.attempts.len() == 1)
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // A checksum mismatch is retried, then reported
+        let url = parse_model_url(&format!("{}/model.gguf", base), true).unwrap();
+        let error = download_url(
+            &url,
+            "model.gguf",
+            Some(&"0".repeat(64)),
+            dir.path(),
+            &fast_retries(),
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("checksum mismatch"));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(!dir.path().join("model.gguf").exists());
+        assert!(!dir.path().join("model.gguf.part").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_url_cancelled() {
+        let base = test_server::serve_stalled(b"GGUF partial", 1 << 20).await;
+        let url = Url::parse(&format!("{}/model.gguf", base)).unwrap();
+        let dir = tempdir().unwrap();
+        let cancel = CancellationToken::new();
+
+        let partial = dir.path().join("model.gguf.part");
+        let watcher = {
+            let partial = partial.clone();
+            let cancel = cancel.clone();
+            tokio::spawn(async move {
+                // Cancel once the download has started writing
+                while !partial.exists() {
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                }
+                cancel.cancel();
+            })
+        };
+
+        let error = download_url(
+            &url,
+            "model.gguf",
+            None,
+            dir.path(),
+            &fast_retries(),
+            &cancel,
+        )
+        .await
+        .unwrap_err();
+        watcher.await.unwrap();
+        assert!(matches!(error, ModelError::Cancelled), "{:?}", error);
+        assert!(!partial.exists());
+        assert!(!dir.path().join("model.gguf").exists());
+
+        // An already-cancelled token stops before anything is fetched
+        let error = download_url(
+            &url,
+            "model.gguf",
+            None,
+            dir.path(),
+            &fast_retries(),
+            &cancel,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, ModelError::Cancelled));
+    }
+}
@@ -1,26 +1,122 @@
+use crate::cache::CacheManager;
 use crate::detection::auto_detect_hf_model_file;
 use crate::error::ModelError;
-use crate::multipart::download_multi_part_model;
+use crate::generation_config::{HfGenerationDefaults, GENERATION_CONFIG_FILENAME};
+use crate::multipart::{download_multi_part_model, ShardName};
 use crate::retry::download_with_retry;
 use crate::types::RetryConfig;
-use hf_hub::api::tokio::ApiBuilder;
+use hf_hub::api::tokio::{Api, ApiBuilder, ApiError};
+use hf_hub::Cache;
 use llama_cpp_2::{
     llama_backend::LlamaBackend,
     model::{params::LlamaModelParams, LlamaModel},
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
-/// Loads a model from HuggingFace and returns path info for caching
+/// Revision models are fetched from
+pub const DEFAULT_REVISION: &str = "main";
+
+/// Subdirectory of the model cache directory that HuggingFace files are downloaded into
+const HF_CACHE_SUBDIR: &str = "huggingface";
+
+/// The hf-hub cache that HuggingFace files are downloaded into, inside the model cache
+/// directory `cache_dir`
+pub fn hf_cache(cache_dir: &Path) -> Cache {
+    Cache::new(cache_dir.join(HF_CACHE_SUBDIR))
+}
+
+/// API client downloading into `cache`. The login token stays where
+/// `huggingface-cli login` saved it, outside `cache`.
+fn hf_api(cache: &Cache) -> Result<Api, ApiError> {
+    ApiBuilder::from_cache(cache.clone())
+        .with_token(Cache::default().token())
+        .build()
+}
+
+/// Lists the files of a HuggingFace repository
+pub async fn list_repo_files(repo: &str) -> Result<Vec<String>, ModelError> {
+    let api = ApiBuilder::new().build().map_err(|e| {
+        ModelError::Network(format!(
+            "Failed to create HuggingFace API client for {}: {}",
+            repo, e
+        ))
+    })?;
+    let repo_info = api
+        .model(repo.to_string())
+        .info()
+        .await
+        .map_err(|e| ModelError::Network(format!("Failed to list files of {}: {}", repo, e)))?;
+    Ok(repo_info
+        .siblings
+        .into_iter()
+        .map(|sibling| sibling.rfilename)
+        .collect())
+}
+
+/// Fetches the sampling defaults of a HuggingFace repository.
+///
+/// The file is downloaded into `cache` like the model itself, so later loads read it
+/// from disk. Returns `None`, with a warning, when the repository has no usable
+/// `generation_config.json`.
+pub async fn fetch_generation_defaults(
+    repo: &str,
+    cache: &Cache,
+    retry_config: &RetryConfig,
+    cancel: &CancellationToken,
+) -> Option<HfGenerationDefaults> {
+    let fetched = async {
+        let api = hf_api(cache).map_err(|e| {
+            ModelError::Network(format!(
+                "Failed to create HuggingFace API client for {}: {}",
+                repo, e
+            ))
+        })?;
+        let path = download_with_retry(
+            &api.model(repo.to_string()),
+            cache,
+            GENERATION_CONFIG_FILENAME,
+            repo,
+            retry_config,
+            cancel,
+        )
+        .await?;
+        HfGenerationDefaults::from_file(&path)
+    };
+
+    match fetched.await {
+        Ok(defaults) => {
+            info!(
+                "Using sampling defaults from {} of {}",
+                GENERATION_CONFIG_FILENAME, repo
+            );
+            Some(defaults)
+        }
+        Err(e) => {
+            warn!(
+                "Ignoring {} of {}: {}; built-in sampling defaults apply",
+                GENERATION_CONFIG_FILENAME, repo, e
+            );
+            None
+        }
+    }
+}
+
+/// Loads a model from HuggingFace into `cache` and returns path info for caching.
+///
+/// Cancelling `cancel` stops the download and fails with [`ModelError::Cancelled`].
 pub async fn load_huggingface_model_with_path(
     repo: &str,
     filename: Option<&str>,
+    cache: &Cache,
     retry_config: &RetryConfig,
+    cancel: &CancellationToken,
 ) -> Result<(PathBuf, String), ModelError> {
     info!("Loading HuggingFace model: {}", repo);
 
     // Create HuggingFace API client
-    let api = match ApiBuilder::new().build() {
+    let api = match hf_api(cache) {
         Ok(api) => api,
         Err(e) => {
             return Err(ModelError::Network(format!(
@@ -52,11 +148,27 @@ pub async fn load_huggingface_model_with_path(
     info!("Downloading model file: {}", target_filename);
 
     // Download the model file(s) with retry logic
-    let model_path = if let Some(parts) = get_all_parts(&target_filename) {
-        info!("Downloading multi-part model with {} parts", parts.len());
-        download_multi_part_model(&repo_api, &parts, repo, retry_config).await?
+    let model_path = if let Some(shard) = ShardName::parse(&target_filename) {
+        info!("Downloading multi-part model with {} parts", shard.total);
+        download_multi_part_model(
+            &repo_api,
+            cache,
+            &target_filename,
+            repo,
+            retry_config,
+            cancel,
+        )
+        .await?
     } else {
-        download_with_retry(&repo_api, &target_filename, repo, retry_config).await?
+        download_with_retry(
+            &repo_api,
+            cache,
+            &target_filename,
+            repo,
+            retry_config,
+            cancel,
+        )
+        .await?
     };
 
     info!("Model downloaded to: {}", model_path.display());
@@ -72,17 +184,19 @@ pub async fn load_huggingface_model(
     retry_config: &RetryConfig,
 ) -> Result<LlamaModel, ModelError> {
     // Use the new function to get the path, then load the model
-    let (model_path, _) = load_huggingface_model_with_path(repo, filename, retry_config).await?;
+    let cache = hf_cache(&CacheManager::resolve_cache_dir(None)?);
+    let (model_path, _) = load_huggingface_model_with_path(
+        repo,
+        filename,
+        &cache,
+        retry_config,
+        &CancellationToken::new(),
+    )
+    .await?;
 
     // Load the downloaded model
     let model_params = LlamaModelParams::default();
-    let model = LlamaModel::load_from_file(backend, &model_path, &model_params).map_err(|e| {
-        ModelError::LoadingFailed(format!(
-            "Failed to load downloaded model from {}: {}",
-            model_path.display(),
-            e
-        ))
-    })?;
+    let model = crate::gguf::load_model_file(backend, &model_path, &model_params)?;
 
     Ok(model)
 }
@@ -135,4 +249,12 @@ mod tests {
         let parts = get_all_parts("model-part1-of-3.gguf");
         assert!(parts.is_none());
     }
+
+    #[test]
+    fn test_hf_cache_is_inside_cache_dir() {
+        let cache = hf_cache(Path::new("/scratch/models"));
+        assert_eq!(cache.path(), Path::new("/scratch/models/huggingface"));
+        let repo = cache.model("org/model".to_string());
+        assert!(repo.get("model.gguf").is_none());
+    }
 }
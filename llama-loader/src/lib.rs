@@ -7,15 +7,26 @@
 pub mod cache;
 pub mod detection;
 pub mod error;
+pub mod generation_config;
+pub mod gguf;
+pub mod http;
 pub mod huggingface;
 pub mod loader;
+pub mod memory;
 pub mod multipart;
+pub mod resolver;
 pub mod retry;
 pub mod types;
 
 // Re-export main types for convenience
-pub use cache::{CacheManager, FileMetadata};
+pub use cache::{CacheLock, CacheManager, CachedFile, FileMetadata, RepoListing};
 pub use error::ModelError;
+pub use generation_config::HfGenerationDefaults;
 pub use huggingface::{load_huggingface_model, load_huggingface_model_with_path};
 pub use loader::ModelLoader;
-pub use types::{LoadedModel, ModelConfig, ModelMetadata, ModelSource, RetryConfig};
+pub use resolver::{Candidate, ModelResolver, Rejection, RepoFile, Resolution, ResolutionPrefs};
+pub use retry::{ErrorClass, RetryAttempt, RetryReport};
+pub use types::{
+    CustomTemplate, LoadedModel, ModelConfig, ModelMetadata, ModelSource, RetryConfig, RoleFormat,
+    TemplateOverride,
+};
@@ -1,32 +1,54 @@
-use crate::cache::{CacheManager, FileMetadata};
+use crate::cache::{CacheManager, CachedFile};
+use crate::detection::find_local_model_file;
 use crate::error::ModelError;
-use crate::huggingface::load_huggingface_model_with_path;
-use crate::types::{LoadedModel, ModelConfig, ModelMetadata, ModelSource, RetryConfig};
-use llama_cpp_2::{
-    llama_backend::LlamaBackend,
-    model::{params::LlamaModelParams, LlamaModel},
+use crate::gguf::{load_model_file_with_header, read_header};
+use crate::http::{download_url, parse_model_url, url_cache_key, url_filename};
+use crate::huggingface::{
+    fetch_generation_defaults, hf_cache, list_repo_files, load_huggingface_model_with_path,
+    DEFAULT_REVISION,
 };
-use std::path::{Path, PathBuf};
+use crate::memory::{available_memory_bytes, check_memory_for_load, estimate_model_memory};
+use crate::multipart::resolve_local_shards;
+use crate::types::{LoadedModel, ModelConfig, ModelMetadata, ModelSource, RetryConfig};
+use llama_cpp_2::{llama_backend::LlamaBackend, model::params::LlamaModelParams};
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Instant;
-use tracing::{debug, info};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
 /// Manages loading of LLAMA models from various sources with caching support
 pub struct ModelLoader {
     backend: Arc<LlamaBackend>,
     cache_manager: CacheManager,
     retry_config: RetryConfig,
+    cancel: CancellationToken,
+    memory_budget: MemoryBudget,
+    /// Contexts created for each loaded model, which each hold a KV cache
+    context_count: usize,
 }
 
 impl ModelLoader {
     /// Create a new ModelLoader with the given backend and default cache manager
     pub fn new(backend: Arc<LlamaBackend>) -> Result<Self, ModelError> {
-        let cache_manager = CacheManager::with_default_cache_dir()?;
+        Self::with_cache_dir(backend, None)
+    }
+
+    /// Create a ModelLoader caching downloads in `cache_dir`, or in the default
+    /// directory when `None` (see [`CacheManager::resolve_cache_dir`])
+    pub fn with_cache_dir(
+        backend: Arc<LlamaBackend>,
+        cache_dir: Option<&Path>,
+    ) -> Result<Self, ModelError> {
+        let cache_manager = CacheManager::new(CacheManager::resolve_cache_dir(cache_dir)?);
         // Initialize the cache manager in a blocking context if needed
         Ok(Self {
             backend,
             cache_manager,
             retry_config: RetryConfig::default(),
+            cancel: CancellationToken::new(),
+            memory_budget: MemoryBudget::default(),
+            context_count: 1,
         })
     }
 
@@ -40,9 +62,26 @@ impl ModelLoader {
             backend,
             cache_manager,
             retry_config,
+            cancel: CancellationToken::new(),
+            memory_budget: MemoryBudget::default(),
+            context_count: 1,
         }
     }
 
+    /// Abandon loads in progress when `cancel` is cancelled. A download is stopped,
+    /// its partial files are removed and the load fails with [`ModelError::Cancelled`].
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Size the memory check before a load for `count` contexts per model, each
+    /// holding its own KV cache
+    pub fn with_context_count(mut self, count: usize) -> Self {
+        self.context_count = count.max(1);
+        self
+    }
+
     /// Initialize the ModelLoader (must be called in an async context)
     pub async fn initialize(&mut self) -> Result<(), ModelError> {
         self.cache_manager.initialize().await
@@ -51,17 +90,53 @@ impl ModelLoader {
     /// Load a model from the specified configuration with cache support
     pub async fn load_model(&mut self, config: &ModelConfig) -> Result<LoadedModel, ModelError> {
         config.validate()?;
+        self.memory_budget = MemoryBudget::from_config(config);
 
         let _start_time = Instant::now();
         info!("Loading model from config: {:?}", config.source);
 
         match &config.source {
             ModelSource::HuggingFace { repo, filename } => {
-                self.load_model_with_cache(repo, filename.as_deref(), &config.retry_config)
-                    .await
+                let mut loaded = self
+                    .load_model_with_cache(
+                        repo,
+                        filename.as_deref(),
+                        &config.retry_config,
+                        &ListingPolicy::from_config(config),
+                    )
+                    .await?;
+                if config.use_hf_params {
+                    loaded.hf_generation_defaults = fetch_generation_defaults(
+                        repo,
+                        &hf_cache(self.cache_manager.cache_dir()),
+                        &config.retry_config,
+                        &self.cancel,
+                    )
+                    .await;
+                }
+                Ok(loaded)
             }
             ModelSource::Local { folder, filename } => {
-                self.load_local_model(folder, filename.as_deref()).await
+                self.load_local_model_with_depth(
+                    folder,
+                    filename.as_deref(),
+                    config.local_search_depth,
+                )
+                .await
+            }
+            ModelSource::Url {
+                url,
+                filename,
+                sha256,
+            } => {
+                self.load_url_model(
+                    url,
+                    filename.as_deref(),
+                    sha256.as_deref(),
+                    config.allow_http,
+                    &config.retry_config,
+                )
+                .await
             }
         }
     }
@@ -72,76 +147,101 @@ impl ModelLoader {
         repo: &str,
         filename: Option<&str>,
         retry_config: &RetryConfig,
+        listing: &ListingPolicy,
     ) -> Result<LoadedModel, ModelError> {
-        // Try to load from HuggingFace first to get the actual file path and metadata
-        let start_time = Instant::now();
         debug!("Loading HuggingFace model with cache support: {}", repo);
 
-        // Load from HuggingFace (this handles download and multi-part logic)
-        let (model_path, actual_filename) = self
-            .load_hf_model_to_path(repo, filename, retry_config)
+        // Auto-detect the filename from the cached repository listing where possible
+        let filename = match filename {
+            Some(filename) => filename.to_string(),
+            None => self
+                .cache_manager
+                .resolve_repo_file(repo, listing.ttl, listing.refresh, || {
+                    list_repo_files(repo)
+                })
+                .await
+                .map_err(|e| {
+                    warn!("Failed to auto-detect model file: {}", e);
+                    ModelError::NotFound(format!(
+                        "Could not auto-detect model file in repository {}: {}. Please specify --filename",
+                        repo, e
+                    ))
+                })?,
+        };
+
+        // The cache lock ensures concurrent loads of the same model download it only once
+        let download_start = Instant::now();
+        let hf_cache = hf_cache(self.cache_manager.cache_dir());
+        let cached = self
+            .cache_manager
+            .get_or_download(repo, Some(&filename), || {
+                load_huggingface_model_with_path(
+                    repo,
+                    Some(&filename),
+                    &hf_cache,
+                    retry_config,
+                    &self.cancel,
+                )
+            })
             .await?;
+        let download_time = download_start.elapsed();
 
-        // Get file metadata for cache key generation
-        let file_metadata = FileMetadata::from_path(&model_path).await?;
-        let cache_key = CacheManager::generate_cache_key(repo, &actual_filename, &file_metadata);
-
-        // Check if we already have this model in cache
-        let cached_path = self.cache_manager.get_cached_model(&cache_key).await;
-
-        let (final_path, cache_hit) = if let Some(cached) = cached_path {
-            info!("Using cached model: {}", cached.display());
-            (cached, true)
-        } else {
-            // Cache the newly downloaded model
-            debug!("Caching model: {}", model_path.display());
-            self.cache_manager
-                .cache_model(&model_path, &cache_key)
-                .await?;
-            (model_path, false)
+        let source = ModelSource::HuggingFace {
+            repo: repo.to_string(),
+            filename: Some(cached.filename.clone()),
         };
+        let metadata = cached_model_metadata(
+            source,
+            Some(DEFAULT_REVISION.to_string()),
+            cached,
+            download_time,
+        );
+        self.load_from_metadata(metadata).await
+    }
 
-        // Load the model using llama-cpp-2
-        let model_params = LlamaModelParams::default();
-        let model =
-            LlamaModel::load_from_file(&self.backend, &final_path, &model_params).map_err(|e| {
-                ModelError::LoadingFailed(format!(
-                    "Failed to load model from {}: {}",
-                    final_path.display(),
-                    e
-                ))
-            })?;
-
-        let load_time = start_time.elapsed();
-        let metadata = ModelMetadata {
-            source: ModelSource::HuggingFace {
-                repo: repo.to_string(),
-                filename: Some(actual_filename.clone()),
-            },
-            filename: actual_filename,
-            size_bytes: file_metadata.size_bytes,
-            load_time,
-            cache_hit,
-        };
+    /// Load the file described by `metadata` into llama.cpp, recording the load time
+    async fn load_from_metadata(
+        &self,
+        mut metadata: ModelMetadata,
+    ) -> Result<LoadedModel, ModelError> {
+        if self.cancel.is_cancelled() {
+            return Err(ModelError::Cancelled);
+        }
+        let start_time = Instant::now();
+        let backend = self.backend.clone();
+        let path = metadata.path.clone();
+        // Shards after the first are covered by the total size in the metadata
+        let model_size = metadata.size_bytes;
+        let budget = self.memory_budget;
+        let n_ctx = budget.n_ctx.saturating_mul(self.context_count as u64);
+        let model = load_blocking(move || {
+            let header = read_header(&path)?;
+            if budget.force_load {
+                debug!("Skipping memory check: force_load is set");
+            } else {
+                let estimate = estimate_model_memory(
+                    model_size.max(header.file_size),
+                    n_ctx,
+                    &header.shape,
+                    budget.overhead_factor,
+                );
+                debug!("Estimated memory for {}: {:?}", path.display(), estimate);
+                check_memory_for_load(&estimate, available_memory_bytes())?;
+            }
+            load_model_file_with_header(&backend, &path, &header, &LlamaModelParams::default())
+        })
+        .await?;
+        metadata.load_time = start_time.elapsed();
+        info!("Model loaded: {}", metadata.summary());
 
         Ok(LoadedModel {
             model,
-            path: final_path,
+            path: metadata.path.clone(),
             metadata,
+            hf_generation_defaults: None,
         })
     }
 
-    /// Helper method to load HuggingFace model and return the downloaded path
-    async fn load_hf_model_to_path(
-        &self,
-        repo: &str,
-        filename: Option<&str>,
-        retry_config: &RetryConfig,
-    ) -> Result<(PathBuf, String), ModelError> {
-        // Use the new function that returns both path and filename
-        load_huggingface_model_with_path(repo, filename, retry_config).await
-    }
-
     /// Load a model from HuggingFace (deprecated - use load_model with ModelConfig instead)
     pub async fn load_huggingface_model(
         &mut self,
@@ -150,7 +250,7 @@ impl ModelLoader {
         retry_config: &RetryConfig,
     ) -> Result<LoadedModel, ModelError> {
         // Use the provided retry_config, falling back to the struct's default
-        self.load_model_with_cache(repo, filename, retry_config)
+        self.load_model_with_cache(repo, filename, retry_config, &ListingPolicy::default())
             .await
     }
 
@@ -162,7 +262,7 @@ impl ModelLoader {
     ) -> Result<LoadedModel, ModelError> {
         // Clone the retry config to avoid borrow conflicts
         let retry_config = self.retry_config.clone();
-        self.load_model_with_cache(repo, filename, &retry_config)
+        self.load_model_with_cache(repo, filename, &retry_config, &ListingPolicy::default())
             .await
     }
 
@@ -172,120 +272,225 @@ impl ModelLoader {
         folder: &Path,
         filename: Option<&str>,
     ) -> Result<LoadedModel, ModelError> {
-        let start_time = Instant::now();
-        info!("Loading local model from folder: {:?}", folder);
-
-        let model_path = if let Some(filename) = filename {
-            let path = folder.join(filename);
-            if !path.exists() {
-                return Err(ModelError::NotFound(format!(
-                    "Model file does not exist: {}",
-                    path.display()
-                )));
-            }
-            path
-        } else {
-            // Auto-detect with BF16 preference
-            self.auto_detect_model_file(folder).await?
-        };
+        self.load_local_model_with_depth(folder, filename, 0).await
+    }
 
-        info!("Loading model from path: {:?}", model_path);
-
-        // Get file metadata for proper size tracking
-        let file_metadata = tokio::fs::metadata(&model_path).await?;
-        let size_bytes = file_metadata.len();
-
-        let model_params = LlamaModelParams::default();
-        let model =
-            LlamaModel::load_from_file(&self.backend, &model_path, &model_params).map_err(|e| {
-                ModelError::LoadingFailed(format!(
-                    "Failed to load model from {}: {}",
-                    model_path.display(),
-                    e
-                ))
-            })?;
-
-        let load_time = start_time.elapsed();
-        let filename_str = model_path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-
-        let metadata = ModelMetadata {
-            source: ModelSource::Local {
-                folder: folder.to_path_buf(),
-                filename: Some(filename_str.clone()),
-            },
-            filename: filename_str,
-            size_bytes,
-            load_time,
-            cache_hit: false, // Local models are not cached
-        };
+    /// Load a model from local filesystem, searching up to `search_depth` levels of subfolders.
+    ///
+    /// `filename` may be a glob pattern (`*`, `?`); see [`find_local_model_file`].
+    pub async fn load_local_model_with_depth(
+        &self,
+        folder: &Path,
+        filename: Option<&str>,
+        search_depth: usize,
+    ) -> Result<LoadedModel, ModelError> {
+        let metadata = resolve_local_model(folder, filename, search_depth).await?;
+        self.load_from_metadata(metadata).await
+    }
 
-        Ok(LoadedModel {
-            model,
-            path: model_path,
-            metadata,
-        })
+    /// Load a model from a direct download link, downloading it into the cache once
+    pub async fn load_url_model(
+        &mut self,
+        url: &str,
+        filename: Option<&str>,
+        sha256: Option<&str>,
+        allow_http: bool,
+        retry_config: &RetryConfig,
+    ) -> Result<LoadedModel, ModelError> {
+        let metadata = resolve_url_model(
+            &mut self.cache_manager,
+            url,
+            filename,
+            sha256,
+            allow_http,
+            retry_config,
+            &self.cancel,
+        )
+        .await?;
+        self.load_from_metadata(metadata).await
     }
+}
 
-    /// Auto-detect model file in local directory with BF16 preference
-    async fn auto_detect_model_file(&self, folder: &Path) -> Result<PathBuf, ModelError> {
-        let mut gguf_files = Vec::new();
-        let mut bf16_files = Vec::new();
-
-        // Read directory
-        let mut entries = match tokio::fs::read_dir(folder).await {
-            Ok(entries) => entries,
-            Err(e) => {
-                return Err(ModelError::LoadingFailed(format!(
-                    "Cannot read directory {}: {}",
-                    folder.display(),
-                    e
-                )))
-            }
-        };
+/// When a HuggingFace repository is listed to auto-detect its model file
+struct ListingPolicy {
+    /// Age up to which a cached listing is used
+    ttl: Duration,
+    /// List the repository even when the cached listing is fresh
+    refresh: bool,
+}
 
-        while let Some(entry) = entries
-            .next_entry()
-            .await
-            .map_err(|e| ModelError::LoadingFailed(e.to_string()))?
-        {
-            let path = entry.path();
-            if let Some(extension) = path.extension() {
-                if extension == "gguf" {
-                    let filename = path.file_name().unwrap().to_string_lossy().to_lowercase();
-                    if filename.contains("bf16") {
-                        bf16_files.push(path);
-                    } else {
-                        gguf_files.push(path);
-                    }
-                }
-            }
+impl ListingPolicy {
+    fn from_config(config: &ModelConfig) -> Self {
+        Self {
+            ttl: config.metadata_ttl(),
+            refresh: config.refresh_metadata,
         }
+    }
+}
 
-        // Prioritize BF16 files
-        if !bf16_files.is_empty() {
-            info!("Found BF16 model file: {:?}", bf16_files[0]);
-            return Ok(bf16_files[0].clone());
-        }
+impl Default for ListingPolicy {
+    fn default() -> Self {
+        Self::from_config(&ModelConfig::default())
+    }
+}
 
-        // Fallback to first GGUF file
-        if !gguf_files.is_empty() {
-            info!("Found GGUF model file: {:?}", gguf_files[0]);
-            return Ok(gguf_files[0].clone());
+/// What the memory check before a load assumes, and whether it runs
+#[derive(Debug, Clone, Copy)]
+struct MemoryBudget {
+    /// Load even when the model is estimated not to fit
+    force_load: bool,
+    overhead_factor: f64,
+    /// Context length of each context created for the model
+    n_ctx: u64,
+}
+
+impl MemoryBudget {
+    fn from_config(config: &ModelConfig) -> Self {
+        Self {
+            force_load: config.force_load,
+            overhead_factor: config.memory_overhead_factor,
+            n_ctx: config.context_size() as u64,
         }
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::from_config(&ModelConfig::default())
+    }
+}
+
+/// Run a synchronous load step, such as reading a GGUF file into llama.cpp, on
+/// tokio's blocking pool. Loading a large model takes seconds to minutes; running
+/// it on a runtime thread would stall every other task on that thread meanwhile,
+/// including the whole runtime when it has a single thread.
+async fn load_blocking<T, F>(load: F) -> Result<T, ModelError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ModelError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(load)
+        .await
+        .map_err(|e| ModelError::LoadingFailed(format!("Model load task failed: {}", e)))?
+}
 
-        Err(ModelError::NotFound(format!(
-            "No .gguf model files found in {}",
-            folder.display()
-        )))
+/// Metadata for a downloaded model served by the cache manager
+fn cached_model_metadata(
+    source: ModelSource,
+    revision: Option<String>,
+    cached: CachedFile,
+    download_time: Duration,
+) -> ModelMetadata {
+    ModelMetadata {
+        source,
+        filename: cached.filename,
+        path: cached.path,
+        revision,
+        size_bytes: cached.size_bytes,
+        download_time: Some(download_time),
+        load_time: Duration::ZERO,
+        cache_hit: cached.cache_hit,
     }
 }
 
+/// Fetch a URL model through the cache, keyed by the hash of the URL
+async fn resolve_url_model(
+    cache_manager: &mut CacheManager,
+    url: &str,
+    filename: Option<&str>,
+    sha256: Option<&str>,
+    allow_http: bool,
+    retry_config: &RetryConfig,
+    cancel: &CancellationToken,
+) -> Result<ModelMetadata, ModelError> {
+    let parsed = parse_model_url(url, allow_http)?;
+    let target = url_filename(&parsed, filename)?;
+    let key = url_cache_key(&parsed);
+    let staging_dir = cache_manager.cache_dir().join("downloads").join(&key);
+    debug!("Loading model from URL {} (cache key {})", parsed, key);
+
+    let download_start = Instant::now();
+    let cached = cache_manager
+        .get_or_download(&key, Some(&target), || async {
+            let path =
+                download_url(&parsed, &target, sha256, &staging_dir, retry_config, cancel).await?;
+            Ok((path, target.clone()))
+        })
+        .await?;
+    let download_time = download_start.elapsed();
+
+    // A cached copy must still match the configured checksum
+    if let (Some(expected), Some(actual)) = (sha256, &cached.sha256) {
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(ModelError::ChecksumMismatch {
+                url: url.to_string(),
+                expected: expected.to_string(),
+                actual: actual.clone(),
+            });
+        }
+    }
+
+    let source = ModelSource::Url {
+        url: url.to_string(),
+        filename: Some(cached.filename.clone()),
+        sha256: sha256.map(str::to_string),
+    };
+    Ok(cached_model_metadata(source, None, cached, download_time))
+}
+
+/// Find a local model file, searching up to `search_depth` levels of subfolders
+async fn resolve_local_model(
+    folder: &Path,
+    filename: Option<&str>,
+    search_depth: usize,
+) -> Result<ModelMetadata, ModelError> {
+    info!("Loading local model from folder: {:?}", folder);
+
+    // Directory walks, pattern matching and shard checks touch the filesystem
+    // synchronously
+    let model_path = {
+        let folder = folder.to_path_buf();
+        let filename = filename.map(str::to_string);
+        load_blocking(move || {
+            let model_path = find_local_model_file(&folder, filename.as_deref(), search_depth)?;
+            // Fail early if a multi-part model is missing shards, rather than inside llama.cpp
+            resolve_local_shards(&model_path)
+        })
+        .await?
+    };
+
+    info!("Loading model from path: {:?}", model_path);
+
+    // Get file metadata for proper size tracking
+    let size_bytes = tokio::fs::metadata(&model_path).await?.len();
+    let filename_str = model_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    Ok(ModelMetadata {
+        source: ModelSource::Local {
+            // The model may have been found in a subfolder
+            folder: model_path.parent().unwrap_or(folder).to_path_buf(),
+            filename: Some(filename_str.clone()),
+        },
+        filename: filename_str,
+        path: model_path,
+        revision: None,
+        size_bytes,
+        download_time: None,
+        load_time: Duration::ZERO,
+        cache_hit: false, // Local models are not cached
+    })
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::http::test_server;
+    use sha2::Digest;
+    use tempfile::tempdir;
 
     // Note: Integration tests would go here for ModelLoader methods
     // These require a real LlamaBackend and are better suited for integration tests
@@ -296,4 +501,199 @@ mod tests {
         // This test just verifies the structure compiles correctly
         // If this test runs, the struct definition is valid
     }
+
+    #[tokio::test]
+    async fn test_runtime_stays_responsive_during_blocking_load() {
+        // A current-thread runtime has no other thread to fall back on
+        let ticks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                let mut interval = tokio::time::interval(Duration::from_millis(100));
+                loop {
+                    interval.tick().await;
+                    ticks.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+        });
+
+        let loaded = load_blocking(|| {
+            std::thread::sleep(Duration::from_secs(2));
+            Ok("model")
+        })
+        .await
+        .unwrap();
+        ticker.abort();
+
+        assert_eq!(loaded, "model");
+        let ticks = ticks.load(std::sync::atomic::Ordering::Relaxed);
+        assert!(
+            ticks >= 10,
+            "timer fired only {} times during the load",
+            ticks
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_model_metadata() {
+        let temp_dir = tempdir().unwrap();
+        let nested = temp_dir.path().join("nested");
+        std::fs::create_dir(&nested).unwrap();
+        std::fs::write(nested.join("model-Q4_K_M.gguf"), b"GGUF0123").unwrap();
+
+        let metadata = resolve_local_model(temp_dir.path(), None, 1).await.unwrap();
+
+        assert_eq!(metadata.filename, "model-Q4_K_M.gguf");
+        assert_eq!(metadata.path, nested.join("model-Q4_K_M.gguf"));
+        assert_eq!(
+            metadata.source,
+            ModelSource::Local {
+                folder: nested,
+                filename: Some("model-Q4_K_M.gguf".to_string()),
+            }
+        );
+        assert_eq!(metadata.size_bytes, 8);
+        assert_eq!(metadata.revision, None);
+        assert_eq!(metadata.download_time, None);
+        assert!(!metadata.cache_hit);
+    }
+
+    fn hf_source() -> ModelSource {
+        ModelSource::HuggingFace {
+            repo: "org/repo".to_string(),
+            filename: Some("model.gguf".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_model_metadata() {
+        let temp_dir = tempdir().unwrap();
+        let downloaded = temp_dir.path().join("model.gguf");
+        std::fs::write(&downloaded, b"GGUF0123").unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let mut cache_manager = CacheManager::new(cache_dir.clone());
+        cache_manager.initialize().await.unwrap();
+        let download = || async { Ok((downloaded.clone(), "model.gguf".to_string())) };
+
+        // The first load caches the download
+        let cached = cache_manager
+            .get_or_download("org/repo", None, download)
+            .await
+            .unwrap();
+        let first = cached_model_metadata(hf_source(), None, cached, Duration::from_secs(2));
+        assert!(!first.cache_hit);
+        assert_eq!(first.path, downloaded);
+        assert_eq!(first.download_time, Some(Duration::from_secs(2)));
+
+        // The second is served from the cache without downloading
+        let cached = cache_manager
+            .get_or_download("org/repo", None, || async {
+                Err(ModelError::NotFound("download attempted".to_string()))
+            })
+            .await
+            .unwrap();
+        let second = cached_model_metadata(
+            hf_source(),
+            Some(DEFAULT_REVISION.to_string()),
+            cached,
+            Duration::from_millis(5),
+        );
+        assert!(second.cache_hit);
+        assert!(second.path.starts_with(&cache_dir));
+        assert_eq!(
+            second.source,
+            ModelSource::HuggingFace {
+                repo: "org/repo".to_string(),
+                filename: Some("model.gguf".to_string()),
+            }
+        );
+        assert_eq!(second.filename, "model.gguf");
+        assert_eq!(second.revision.as_deref(), Some(DEFAULT_REVISION));
+        assert_eq!(second.size_bytes, 8);
+        assert_eq!(second.download_time, Some(Duration::from_millis(5)));
+        assert!(second.summary().contains("cache hit"));
+    }
+
+    #[tokio::test]
+    async fn test_url_model_metadata() {
+        let (base, requests) = test_server::serve(b"GGUF0123").await;
+        let temp_dir = tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let mut cache_manager = CacheManager::new(cache_dir.clone());
+        cache_manager.initialize().await.unwrap();
+        let url = format!("{}/model.gguf", base);
+        let sha256 = format!("{:x}", sha2::Sha256::digest(b"GGUF0123"));
+        let retry_config = RetryConfig::default();
+        let cancel = CancellationToken::new();
+
+        // Plain http is rejected unless allowed
+        let error = resolve_url_model(
+            &mut cache_manager,
+            &url,
+            None,
+            None,
+            false,
+            &retry_config,
+            &cancel,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, ModelError::InvalidConfig(_)));
+
+        let first = resolve_url_model(
+            &mut cache_manager,
+            &url,
+            None,
+            Some(&sha256),
+            true,
+            &retry_config,
+            &cancel,
+        )
+        .await
+        .unwrap();
+        assert!(!first.cache_hit);
+        assert!(first.path.starts_with(&cache_dir));
+        assert_eq!(std::fs::read(&first.path).unwrap(), b"GGUF0123");
+        assert_eq!(first.filename, "model.gguf");
+        assert_eq!(first.size_bytes, 8);
+        assert_eq!(first.revision, None);
+        assert_eq!(
+            first.source,
+            ModelSource::Url {
+                url: url.clone(),
+                filename: Some("model.gguf".to_string()),
+                sha256: Some(sha256.clone()),
+            }
+        );
+
+        // The second load is served from the cache
+        let second = resolve_url_model(
+            &mut cache_manager,
+            &url,
+            None,
+            None,
+            true,
+            &retry_config,
+            &cancel,
+        )
+        .await
+        .unwrap();
+        assert!(second.cache_hit);
+        assert_eq!(second.path, first.path);
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // A cached copy that no longer matches the configured checksum is rejected
+        let error = resolve_url_model(
+            &mut cache_manager,
+            &url,
+            None,
+            Some(&"0".repeat(64)),
+            true,
+            &retry_config,
+            &cancel,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, ModelError::ChecksumMismatch { .. }));
+    }
 }
@@ -0,0 +1,216 @@
+//! Memory estimates checked before loading a model, and system memory figures used
+//! to explain load failures
+
+use crate::error::ModelError;
+
+/// Bytes per KV cache element; llama.cpp keeps the cache in f16 by default
+const KV_CACHE_ELEMENT_BYTES: u64 = 2;
+
+/// Memory a model of `model_bytes` needs: the weights plus a fifth for context and
+/// compute buffers
+pub fn estimate_required_memory(model_bytes: u64) -> u64 {
+    model_bytes + model_bytes / 5
+}
+
+/// Shape of a model from its GGUF metadata, which sizes its KV cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModelShape {
+    /// `<arch>.block_count`
+    pub layer_count: Option<u64>,
+    /// `<arch>.embedding_length`
+    pub embedding_length: Option<u64>,
+    /// `<arch>.attention.head_count`
+    pub head_count: Option<u64>,
+    /// `<arch>.attention.head_count_kv`; fewer than `head_count` with grouped-query
+    /// attention
+    pub head_count_kv: Option<u64>,
+}
+
+impl ModelShape {
+    /// Values stored per token and layer for keys, and again for values
+    fn kv_width(&self) -> Option<u64> {
+        let embedding_length = self.embedding_length?;
+        match (self.head_count, self.head_count_kv) {
+            (Some(heads), Some(kv_heads)) if heads > 0 => {
+                Some(embedding_length.saturating_mul(kv_heads) / heads)
+            }
+            _ => Some(embedding_length),
+        }
+    }
+}
+
+/// Memory a model is expected to need once loaded with its contexts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    pub weights: u64,
+    pub kv_cache: u64,
+    pub overhead: u64,
+}
+
+impl MemoryEstimate {
+    pub fn total(&self) -> u64 {
+        self.weights
+            .saturating_add(self.kv_cache)
+            .saturating_add(self.overhead)
+    }
+}
+
+/// Estimate the memory a model of `file_size` bytes needs with `n_ctx` tokens of
+/// context in total, across every context created for it.
+///
+/// Assumptions:
+/// - the weights take as much memory as they take on disk, GGUF storing them in
+///   their loaded form;
+/// - the KV cache holds an f16 key and value vector per layer for every token of
+///   context, narrowed by grouped-query attention where `shape` says so;
+/// - compute buffers and runtime overhead take `overhead_factor` times the weights;
+/// - without a layer count and embedding length in `shape`, the KV cache takes a fifth
+///   of the weights, as in [`estimate_required_memory`].
+pub fn estimate_model_memory(
+    file_size: u64,
+    n_ctx: u64,
+    shape: &ModelShape,
+    overhead_factor: f64,
+) -> MemoryEstimate {
+    let kv_cache = match (shape.layer_count, shape.kv_width()) {
+        (Some(layers), Some(width)) => (2 * KV_CACHE_ELEMENT_BYTES)
+            .saturating_mul(layers)
+            .saturating_mul(n_ctx)
+            .saturating_mul(width),
+        _ => file_size / 5,
+    };
+    MemoryEstimate {
+        weights: file_size,
+        kv_cache,
+        overhead: (file_size as f64 * overhead_factor.max(0.0)) as u64,
+    }
+}
+
+/// Fail with [`ModelError::InsufficientMemory`] when `estimate` needs more than the
+/// `available` memory. Loading goes ahead when available memory is unknown.
+pub fn check_memory_for_load(
+    estimate: &MemoryEstimate,
+    available: Option<u64>,
+) -> Result<(), ModelError> {
+    match available {
+        Some(available) if estimate.total() > available => Err(ModelError::InsufficientMemory {
+            required: estimate.total(),
+            available,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Memory the OS can hand out without swapping, if it can be determined
+pub fn available_memory_bytes() -> Option<u64> {
+    if cfg!(target_os = "linux") {
+        parse_meminfo_available(&std::fs::read_to_string("/proc/meminfo").ok()?)
+    } else if cfg!(target_os = "macos") {
+        // Total rather than available memory; macOS reports no single free figure
+        let output = std::process::Command::new("sysctl")
+            .args(["-n", "hw.memsize"])
+            .output()
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+/// `MemAvailable` from the contents of `/proc/meminfo`, in bytes
+pub fn parse_meminfo_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Byte count for messages, e.g. "1.5 GiB" or "300.0 MiB"
+pub fn format_bytes(bytes: u64) -> String {
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+    const MIB: f64 = 1024.0 * 1024.0;
+    if bytes as f64 >= GIB {
+        format!("{:.1} GiB", bytes as f64 / GIB)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / MIB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_meminfo_available() {
+        let meminfo = "MemTotal:       32658196 kB\n\
+                       MemFree:         1234567 kB\n\
+                       MemAvailable:   20000000 kB\n";
+        assert_eq!(parse_meminfo_available(meminfo), Some(20000000 * 1024));
+        assert_eq!(parse_meminfo_available("MemTotal: 1 kB\n"), None);
+    }
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    /// Llama 3 8B: 32 layers, 4096 wide, 32 query and 8 KV heads
+    const LLAMA3_8B: ModelShape = ModelShape {
+        layer_count: Some(32),
+        embedding_length: Some(4096),
+        head_count: Some(32),
+        head_count_kv: Some(8),
+    };
+
+    #[test]
+    fn test_estimate_model_memory_from_shape() {
+        let estimate = estimate_model_memory(4 * GIB, 8192, &LLAMA3_8B, 0.1);
+        assert_eq!(estimate.weights, 4 * GIB);
+        // 2 (K and V) x 2 bytes x 32 layers x 8192 tokens x 1024 KV width
+        assert_eq!(estimate.kv_cache, GIB);
+        assert_eq!(estimate.overhead, 4 * GIB / 10);
+        assert_eq!(estimate.total(), 4 * GIB + GIB + 4 * GIB / 10);
+
+        // Twice the context, twice the cache
+        let longer = estimate_model_memory(4 * GIB, 16384, &LLAMA3_8B, 0.1);
+        assert_eq!(longer.kv_cache, 2 * GIB);
+
+        // Without grouped-query attention every head is cached
+        let full_attention = ModelShape {
+            head_count_kv: None,
+            ..LLAMA3_8B
+        };
+        let estimate = estimate_model_memory(4 * GIB, 8192, &full_attention, 0.0);
+        assert_eq!(estimate.kv_cache, 4 * GIB);
+        assert_eq!(estimate.overhead, 0);
+    }
+
+    #[test]
+    fn test_estimate_model_memory_without_metadata() {
+        let estimate = estimate_model_memory(1000, 8192, &ModelShape::default(), 0.1);
+        assert_eq!(estimate.kv_cache, 200);
+        assert_eq!(estimate.total(), 1300);
+    }
+
+    #[test]
+    fn test_check_memory_for_load() {
+        let estimate = estimate_model_memory(4 * GIB, 8192, &LLAMA3_8B, 0.1);
+        assert!(check_memory_for_load(&estimate, Some(8 * GIB)).is_ok());
+        assert!(check_memory_for_load(&estimate, None).is_ok());
+        match check_memory_for_load(&estimate, Some(4 * GIB)) {
+            Err(ModelError::InsufficientMemory {
+                required,
+                available,
+            }) => {
+                assert_eq!(required, estimate.total());
+                assert_eq!(available, 4 * GIB);
+            }
+            other => panic!("expected InsufficientMemory, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_estimate_and_format() {
+        assert_eq!(estimate_required_memory(1000), 1200);
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024 / 2), "1.5 GiB");
+        assert_eq!(format_bytes(300 * 1024 * 1024), "300.0 MiB");
+    }
+}
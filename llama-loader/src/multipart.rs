@@ -1,69 +1,453 @@
 use crate::error::ModelError;
 use crate::retry::download_with_retry;
 use crate::types::RetryConfig;
-use std::path::PathBuf;
-use tracing::info;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
-/// Downloads all parts of a multi-part model
+/// Parsed components of a multi-part GGUF filename like "model-00002-of-00005.gguf"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardName {
+    /// Filename prefix before the shard numbering (may include a subdirectory)
+    pub base: String,
+    /// 1-based shard index
+    pub index: u32,
+    /// Total number of shards
+    pub total: u32,
+}
+
+impl ShardName {
+    /// Parses a shard filename, returning `None` for single-file models or malformed numbering
+    pub fn parse(filename: &str) -> Option<Self> {
+        use regex::Regex;
+        let re = Regex::new(r"^(.+)-(\d{5})-of-(\d{5})\.gguf$").ok()?;
+        let captures = re.captures(filename)?;
+
+        let index: u32 = captures.get(2)?.as_str().parse().ok()?;
+        let total: u32 = captures.get(3)?.as_str().parse().ok()?;
+        if index == 0 || index > total {
+            return None;
+        }
+
+        Some(Self {
+            base: captures.get(1)?.as_str().to_string(),
+            index,
+            total,
+        })
+    }
+
+    /// Filename of the shard with the given index in the same set
+    pub fn filename_for(&self, index: u32) -> String {
+        format!("{}-{:05}-of-{:05}.gguf", self.base, index, self.total)
+    }
+
+    /// Filenames of every shard in the set, in order
+    pub fn all_filenames(&self) -> Vec<String> {
+        (1..=self.total).map(|i| self.filename_for(i)).collect()
+    }
+}
+
+/// Lists every shard of the model that `shard` belongs to.
+///
+/// When the repository listing is available, shards absent from it are
+/// reported as `ModelError::IncompleteMultipart` before anything is downloaded.
+pub fn enumerate_shards(
+    shard: &str,
+    repo_files: Option<&[String]>,
+) -> Result<Vec<String>, ModelError> {
+    let name = ShardName::parse(shard).ok_or_else(|| {
+        ModelError::InvalidConfig(format!("'{}' is not a multi-part GGUF filename", shard))
+    })?;
+    let shards = name.all_filenames();
+
+    if let Some(files) = repo_files {
+        let missing: Vec<String> = shards
+            .iter()
+            .filter(|shard| !files.contains(shard))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(ModelError::IncompleteMultipart { missing });
+        }
+    }
+
+    Ok(shards)
+}
+
+/// Which shards can be reused from the cache and which must be fetched
+#[derive(Debug, Default, PartialEq)]
+pub struct ShardPlan {
+    /// Shards already cached and valid, with their paths
+    pub cached: Vec<(String, PathBuf)>,
+    /// Shards that must be downloaded, in order
+    pub to_download: Vec<String>,
+    /// Cached files that failed verification and must be discarded before downloading
+    pub invalid: Vec<PathBuf>,
+}
+
+/// Decides which shards need downloading, using `lookup` to find cached copies
+pub fn plan_shard_downloads<F>(shards: &[String], lookup: F) -> ShardPlan
+where
+    F: Fn(&str) -> Option<PathBuf>,
+{
+    let mut plan = ShardPlan::default();
+
+    for shard in shards {
+        match lookup(shard) {
+            Some(path) if verify_cached_shard(&path) => {
+                plan.cached.push((shard.clone(), path));
+            }
+            Some(path) => {
+                plan.invalid.push(path);
+                plan.to_download.push(shard.clone());
+            }
+            None => plan.to_download.push(shard.clone()),
+        }
+    }
+
+    plan
+}
+
+/// Checks that a cached shard is a readable GGUF file.
+///
+/// Files stored under their SHA-256 digest (as in the HuggingFace blob cache,
+/// where snapshot entries are symlinks to blobs) are also hashed and compared.
+pub fn verify_cached_shard(path: &Path) -> bool {
+    let Ok(resolved) = std::fs::canonicalize(path) else {
+        return false;
+    };
+    let Ok(mut file) = std::fs::File::open(&resolved) else {
+        return false;
+    };
+
+    let mut magic = [0u8; 4];
+    if file.read_exact(&mut magic).is_err() || &magic != b"GGUF" {
+        return false;
+    }
+
+    let expected_digest = resolved
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit()));
+
+    match expected_digest {
+        Some(expected) => match sha256_file(&resolved) {
+            Ok(actual) => actual.eq_ignore_ascii_case(expected),
+            Err(_) => false,
+        },
+        None => true,
+    }
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Downloads all shards of a multi-part model, reusing valid shards in `cache`, the
+/// cache `repo_api` was built with.
+///
+/// `shard` may name any shard of the set. Returns the path of the first shard,
+/// which llama.cpp uses to load the whole model, or
+/// `ModelError::IncompleteMultipart` if any shard could not be obtained.
 pub async fn download_multi_part_model(
     repo_api: &hf_hub::api::tokio::ApiRepo,
-    parts: &[String],
+    cache: &hf_hub::Cache,
+    shard: &str,
     repo: &str,
     retry_config: &RetryConfig,
+    cancel: &CancellationToken,
 ) -> Result<PathBuf, ModelError> {
+    let repo_files = match repo_api.info().await {
+        Ok(info) => Some(
+            info.siblings
+                .into_iter()
+                .map(|sibling| sibling.rfilename)
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => {
+            warn!(
+                "Could not list files in {}: {}. Relying on the shard naming pattern",
+                repo, e
+            );
+            None
+        }
+    };
+    let shards = enumerate_shards(shard, repo_files.as_deref())?;
+    let total = shards.len();
+
+    // Verifying cached shards hashes whole files, so keep it off the async runtime
+    let cache_repo = cache.model(repo.to_string());
+    let plan = {
+        let shards = shards.clone();
+        tokio::task::spawn_blocking(move || {
+            plan_shard_downloads(&shards, |shard| cache_repo.get(shard))
+        })
+        .await
+        .map_err(|e| ModelError::LoadingFailed(format!("Shard verification failed: {}", e)))?
+    };
+
     info!(
-        "Starting download of {} parts for multi-part model",
-        parts.len()
+        "Multi-part model has {} shards: {} cached, {} to download",
+        total,
+        plan.cached.len(),
+        plan.to_download.len()
     );
 
-    let mut downloaded_paths = Vec::new();
+    for path in &plan.invalid {
+        warn!("Discarding invalid cached shard: {}", path.display());
+        discard_cached_file(path).await;
+    }
 
-    // Download each part
-    for (index, part) in parts.iter().enumerate() {
-        info!(
-            "Downloading part {} of {}: {}",
-            index + 1,
-            parts.len(),
-            part
-        );
+    let mut paths: HashMap<String, PathBuf> = plan.cached.into_iter().collect();
+    let mut missing = Vec::new();
+
+    for (index, shard) in shards.iter().enumerate() {
+        if paths.contains_key(shard) {
+            info!("Shard {} of {} already cached: {}", index + 1, total, shard);
+            continue;
+        }
 
-        let path = download_with_retry(repo_api, part, repo, retry_config).await?;
+        info!("Downloading shard {} of {}: {}", index + 1, total, shard);
+        match download_with_retry(repo_api, cache, shard, repo, retry_config, cancel).await {
+            Ok(path) => {
+                paths.insert(shard.clone(), path);
+            }
+            // Shards downloaded so far stay cached for the next attempt
+            Err(ModelError::Cancelled) => return Err(ModelError::Cancelled),
+            Err(e) => {
+                warn!("Failed to obtain shard {}: {}", shard, e);
+                missing.push(shard.clone());
+            }
+        }
+    }
 
-        downloaded_paths.push(path);
+    if !missing.is_empty() {
+        return Err(ModelError::IncompleteMultipart { missing });
     }
 
-    info!("Successfully downloaded all {} parts", parts.len());
+    info!("All {} shards available", total);
 
     // Return the path to the first part (which llama.cpp uses to load multi-part files)
-    Ok(downloaded_paths[0].clone())
+    paths
+        .remove(&shards[0])
+        .ok_or_else(|| ModelError::IncompleteMultipart {
+            missing: vec![shards[0].clone()],
+        })
+}
+
+/// Checks that every shard of a local multi-part model sits next to `path`.
+///
+/// Returns the path of the first shard for multi-part models and `path`
+/// unchanged otherwise.
+pub fn resolve_local_shards(path: &Path) -> Result<PathBuf, ModelError> {
+    let Some(name) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(ShardName::parse)
+    else {
+        return Ok(path.to_path_buf());
+    };
+
+    let folder = path.parent().unwrap_or_else(|| Path::new(""));
+    let missing: Vec<String> = name
+        .all_filenames()
+        .into_iter()
+        .filter(|shard| !folder.join(shard).is_file())
+        .collect();
+    if !missing.is_empty() {
+        return Err(ModelError::IncompleteMultipart { missing });
+    }
+
+    Ok(folder.join(name.filename_for(1)))
+}
+
+/// Removes a cached file, including the blob behind a snapshot symlink
+async fn discard_cached_file(path: &Path) {
+    if let Ok(target) = tokio::fs::canonicalize(path).await {
+        if target != path {
+            let _ = tokio::fs::remove_file(&target).await;
+        }
+    }
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove cached file {}: {}", path.display(), e);
+        }
+    }
 }
 
 /// Detects if a filename is part of a multi-part GGUF file and returns the base filename (first part)
 pub fn detect_multi_part_base(filename: &str) -> Option<String> {
     // Check for pattern like "model-00001-of-00002.gguf"
-    use regex::Regex;
-    let re = Regex::new(r"^(.+)-(\d{5})-of-(\d{5})\.gguf$").ok()?;
-
-    if let Some(captures) = re.captures(filename) {
-        let base_name = captures.get(1)?.as_str();
-        let current_part = captures.get(2)?.as_str();
-        let total_parts = captures.get(3)?.as_str();
+    let name = ShardName::parse(filename)?;
 
-        info!(
-            "Detected multi-part GGUF file: {} (part {} of {})",
-            base_name, current_part, total_parts
-        );
+    info!(
+        "Detected multi-part GGUF file: {} (part {} of {})",
+        name.base, name.index, name.total
+    );
 
-        // Return the first part filename pattern
-        Some(format!("{}-00001-of-{}.gguf", base_name, total_parts))
-    } else {
-        None
-    }
+    // Return the first part filename pattern
+    Some(name.filename_for(1))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
+
+    fn write_file(dir: &Path, name: &str, content: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn shard_names() -> Vec<String> {
+        ShardName::parse("model-00001-of-00003.gguf")
+            .unwrap()
+            .all_filenames()
+    }
+
+    #[test]
+    fn test_shard_name_parse() {
+        let name = ShardName::parse("sub/dir/model-q4-00002-of-00005.gguf").unwrap();
+        assert_eq!(name.base, "sub/dir/model-q4");
+        assert_eq!(name.index, 2);
+        assert_eq!(name.total, 5);
+        assert_eq!(name.filename_for(5), "sub/dir/model-q4-00005-of-00005.gguf");
+
+        assert!(ShardName::parse("model.gguf").is_none());
+        assert!(ShardName::parse("model-00000-of-00003.gguf").is_none());
+        assert!(ShardName::parse("model-00004-of-00003.gguf").is_none());
+    }
+
+    #[test]
+    fn test_enumerate_shards_from_any_shard() {
+        let shards = enumerate_shards("model-00003-of-00003.gguf", None).unwrap();
+        assert_eq!(shards, shard_names());
+
+        assert!(matches!(
+            enumerate_shards("model.gguf", None),
+            Err(ModelError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_enumerate_shards_checks_repo_listing() {
+        let mut listing = shard_names();
+        listing.push("README.md".to_string());
+        assert_eq!(
+            enumerate_shards("model-00001-of-00003.gguf", Some(&listing)).unwrap(),
+            shard_names()
+        );
+
+        listing.retain(|file| file != "model-00002-of-00003.gguf");
+        match enumerate_shards("model-00001-of-00003.gguf", Some(&listing)) {
+            Err(ModelError::IncompleteMultipart { missing }) => {
+                assert_eq!(missing, vec!["model-00002-of-00003.gguf".to_string()]);
+            }
+            other => panic!("Expected IncompleteMultipart, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_plan_resumes_only_missing_and_invalid_shards() {
+        let cache = TempDir::new().unwrap();
+        let shards = shard_names();
+
+        // Shard 1 is valid, shard 2 is truncated, shard 3 was never downloaded
+        let valid = write_file(cache.path(), &shards[0], b"GGUF shard one");
+        let truncated = write_file(cache.path(), &shards[1], b"GG");
+
+        let plan = plan_shard_downloads(&shards, |shard| {
+            let path = cache.path().join(shard);
+            path.exists().then_some(path)
+        });
+
+        assert_eq!(plan.cached, vec![(shards[0].clone(), valid)]);
+        assert_eq!(plan.to_download, vec![shards[1].clone(), shards[2].clone()]);
+        assert_eq!(plan.invalid, vec![truncated]);
+    }
+
+    #[test]
+    fn test_plan_with_complete_cache_downloads_nothing() {
+        let cache = TempDir::new().unwrap();
+        let shards = shard_names();
+        for shard in &shards {
+            write_file(cache.path(), shard, b"GGUF data");
+        }
+
+        let plan = plan_shard_downloads(&shards, |shard| Some(cache.path().join(shard)));
+        assert_eq!(plan.cached.len(), 3);
+        assert!(plan.to_download.is_empty());
+        assert!(plan.invalid.is_empty());
+    }
+
+    #[test]
+    fn test_verify_cached_shard_checks_blob_digest() {
+        let cache = TempDir::new().unwrap();
+        let content = b"GGUF blob content";
+        let digest = format!("{:x}", Sha256::digest(content));
+
+        let good_blob = write_file(cache.path(), &digest, content);
+        assert!(verify_cached_shard(&good_blob));
+
+        let bad_blob = write_file(cache.path(), &"0".repeat(64), content);
+        assert!(!verify_cached_shard(&bad_blob));
+
+        let not_gguf = write_file(cache.path(), "plain.gguf", b"not a gguf file");
+        assert!(!verify_cached_shard(&not_gguf));
+
+        assert!(!verify_cached_shard(&cache.path().join("missing.gguf")));
+    }
+
+    #[test]
+    fn test_resolve_local_shards() {
+        let dir = TempDir::new().unwrap();
+        let shards = shard_names();
+        write_file(dir.path(), &shards[0], b"GGUF");
+        write_file(dir.path(), &shards[2], b"GGUF");
+
+        match resolve_local_shards(&dir.path().join(&shards[2])) {
+            Err(ModelError::IncompleteMultipart { missing }) => {
+                assert_eq!(missing, vec![shards[1].clone()]);
+            }
+            other => panic!("Expected IncompleteMultipart, got {:?}", other),
+        }
+
+        write_file(dir.path(), &shards[1], b"GGUF");
+        assert_eq!(
+            resolve_local_shards(&dir.path().join(&shards[2])).unwrap(),
+            dir.path().join(&shards[0])
+        );
+
+        // Single-file models pass through untouched
+        let single = dir.path().join("model.gguf");
+        assert_eq!(resolve_local_shards(&single).unwrap(), single);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verify_cached_shard_follows_snapshot_symlinks() {
+        let cache = TempDir::new().unwrap();
+        let content = b"GGUF linked shard";
+        let blob = write_file(
+            cache.path(),
+            &format!("{:x}", Sha256::digest(content)),
+            content,
+        );
+        let link = cache.path().join("model-00001-of-00002.gguf");
+        std::os::unix::fs::symlink(&blob, &link).unwrap();
+        assert!(verify_cached_shard(&link));
+
+        // A dangling snapshot link counts as missing data
+        std::fs::remove_file(&blob).unwrap();
+        assert!(!verify_cached_shard(&link));
+    }
 
     #[test]
     fn test_detect_multi_part_base_valid() {
@@ -0,0 +1,681 @@
+//! Choosing the model file to load when a source names none
+//!
+//! [`ModelResolver`] ranks the `.gguf` files of a repository or folder by preferred
+//! quantization and picks one. Every file offered ends up in the [`Resolution`] with
+//! the reason it was or was not chosen, so a surprising pick can be explained.
+
+use crate::error::ModelError;
+use crate::multipart::ShardName;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A file offered to the resolver, by its path within the repository or folder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoFile {
+    pub path: String,
+    /// Size in bytes, when the listing reports it
+    pub size_bytes: Option<u64>,
+}
+
+impl RepoFile {
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            size_bytes: None,
+        }
+    }
+
+    pub fn with_size(mut self, size_bytes: u64) -> Self {
+        self.size_bytes = Some(size_bytes);
+        self
+    }
+}
+
+/// What [`ModelResolver`] looks for in a file list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolutionPrefs {
+    /// Quantization tags, most preferred first, matched case-insensitively anywhere in
+    /// the path. Files matching none rank after every file that matches one.
+    pub preferred_quantizations: Vec<String>,
+    /// Memory available for the model; files known to be larger are rejected
+    pub memory_limit_bytes: Option<u64>,
+}
+
+impl Default for ResolutionPrefs {
+    fn default() -> Self {
+        Self {
+            preferred_quantizations: vec!["BF16".to_string()],
+            memory_limit_bytes: None,
+        }
+    }
+}
+
+impl ResolutionPrefs {
+    pub fn with_preferred_quantizations<I, S>(mut self, quantizations: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.preferred_quantizations = quantizations.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_memory_limit(mut self, bytes: u64) -> Self {
+        self.memory_limit_bytes = Some(bytes);
+        self
+    }
+
+    /// Position of the first preferred quantization found in `path`
+    fn rank(&self, path: &str) -> Option<usize> {
+        let path = path.to_lowercase();
+        self.preferred_quantizations
+            .iter()
+            .position(|quantization| path.contains(&quantization.to_lowercase()))
+    }
+}
+
+/// Why a file was not chosen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// Not a `.gguf` file
+    WrongExtension,
+    /// A later shard of a multi-part model, which is loaded through its first shard
+    MultipartShard { first_shard: String },
+    /// Larger than `ResolutionPrefs::memory_limit_bytes`
+    TooLarge { size_bytes: u64, limit_bytes: u64 },
+    /// Its quantization is preferred less than the chosen file's
+    QuantNotPreferred,
+    /// Preferred as much as the chosen file, which is shallower or sorts first by name
+    Tie,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::WrongExtension => write!(f, "not a .gguf file"),
+            Rejection::MultipartShard { first_shard } => {
+                write!(f, "later shard of {}", first_shard)
+            }
+            Rejection::TooLarge {
+                size_bytes,
+                limit_bytes,
+            } => write!(
+                f,
+                "{} exceeds the memory limit of {}",
+                format_gb(*size_bytes),
+                format_gb(*limit_bytes)
+            ),
+            Rejection::QuantNotPreferred => {
+                write!(f, "quantization preferred less than the chosen file's")
+            }
+            Rejection::Tie => write!(
+                f,
+                "preferred as much as the chosen file, which is shallower or sorts first"
+            ),
+        }
+    }
+}
+
+/// A file the resolver considered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The file as listed
+    pub path: String,
+    /// The file llama.cpp would load for it: the first shard of a multi-part model
+    pub load_path: String,
+    /// Quantization read from the path, e.g. `Q4_K_M`
+    pub quantization: Option<String>,
+    /// Size of the file, or of every listed shard of a multi-part model, when known
+    pub size_bytes: Option<u64>,
+    /// Index of the preferred quantization the path matches
+    pub rank: Option<usize>,
+    /// Why the file was not chosen; `None` for the chosen file
+    pub rejection: Option<Rejection>,
+}
+
+/// The file chosen from a list, and the verdict on every file offered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// Path to load, `None` when no file qualified
+    pub chosen: Option<String>,
+    /// Every file offered, in the order given
+    pub candidates: Vec<Candidate>,
+}
+
+impl Resolution {
+    /// The chosen path, or `ModelError::NotFound` describing `source`
+    pub fn into_chosen(self, source: &str) -> Result<String, ModelError> {
+        self.chosen.ok_or_else(|| {
+            let reason = if self
+                .candidates
+                .iter()
+                .any(|c| matches!(c.rejection, Some(Rejection::TooLarge { .. })))
+            {
+                "No .gguf model file fits the memory limit"
+            } else {
+                "No .gguf model files found"
+            };
+            ModelError::NotFound(format!("{} in {}", reason, source))
+        })
+    }
+}
+
+/// One line per candidate, chosen file first
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.chosen {
+            Some(chosen) => write!(f, "Chose {}", chosen)?,
+            None => write!(f, "No model file qualified")?,
+        }
+        let mut candidates: Vec<&Candidate> = self.candidates.iter().collect();
+        candidates.sort_by_key(|c| c.rejection.is_some());
+        for candidate in candidates {
+            write!(f, "\n  {}", candidate.path)?;
+            let mut details = Vec::new();
+            if let Some(quantization) = &candidate.quantization {
+                details.push(quantization.clone());
+            }
+            if let Some(size_bytes) = candidate.size_bytes {
+                details.push(format_gb(size_bytes));
+            }
+            if let Some(rank) = candidate.rank {
+                details.push(format!("preference {}", rank + 1));
+            }
+            if !details.is_empty() {
+                write!(f, " ({})", details.join(", "))?;
+            }
+            match &candidate.rejection {
+                Some(rejection) => write!(f, ": {}", rejection)?,
+                None => write!(f, ": chosen")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_gb(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+}
+
+/// Picks the model file to load from a repository listing or folder contents.
+///
+/// Only `.gguf` files qualify, multi-part models count once through their first
+/// shard, and files known to exceed the memory limit are rejected. Of the rest, the
+/// most preferred quantization wins, then the shallowest path, then the first by name.
+pub struct ModelResolver;
+
+impl ModelResolver {
+    pub fn resolve(files: &[RepoFile], prefs: &ResolutionPrefs) -> Resolution {
+        let shards: Vec<Option<ShardName>> = files
+            .iter()
+            .map(|file| ShardName::parse(&file.path))
+            .collect();
+
+        // Each multi-part model is represented by its lowest listed shard, sized as
+        // the sum of its listed shards
+        let mut sets: HashMap<String, (usize, Option<u64>)> = HashMap::new();
+        for (i, (file, shard)) in files.iter().zip(&shards).enumerate() {
+            let Some(shard) = shard else { continue };
+            let first_shard = shard.filename_for(1);
+            match sets.get_mut(&first_shard) {
+                Some((representative, size)) => {
+                    if shards[*representative]
+                        .as_ref()
+                        .is_some_and(|current| shard.index < current.index)
+                    {
+                        *representative = i;
+                    }
+                    *size = size.zip(file.size_bytes).map(|(a, b)| a + b);
+                }
+                None => {
+                    sets.insert(first_shard, (i, file.size_bytes));
+                }
+            }
+        }
+
+        let mut candidates: Vec<Candidate> = files
+            .iter()
+            .zip(&shards)
+            .enumerate()
+            .map(|(i, (file, shard))| {
+                let (load_path, size_bytes, rejection) = match shard {
+                    _ if !is_gguf(&file.path) => (
+                        file.path.clone(),
+                        file.size_bytes,
+                        Some(Rejection::WrongExtension),
+                    ),
+                    Some(shard) => {
+                        let first_shard = shard.filename_for(1);
+                        let (representative, size) = sets[&first_shard];
+                        let rejection = (representative != i).then(|| Rejection::MultipartShard {
+                            first_shard: first_shard.clone(),
+                        });
+                        (first_shard, size, rejection)
+                    }
+                    None => (file.path.clone(), file.size_bytes, None),
+                };
+                let too_large = match (size_bytes, prefs.memory_limit_bytes) {
+                    (Some(size_bytes), Some(limit_bytes)) if size_bytes > limit_bytes => {
+                        Some(Rejection::TooLarge {
+                            size_bytes,
+                            limit_bytes,
+                        })
+                    }
+                    _ => None,
+                };
+                Candidate {
+                    quantization: detect_quantization(&file.path),
+                    rank: prefs.rank(&file.path),
+                    path: file.path.clone(),
+                    load_path,
+                    size_bytes,
+                    rejection: rejection.or(too_large),
+                }
+            })
+            .collect();
+
+        let best = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.rejection.is_none())
+            .min_by_key(|(_, c)| {
+                (
+                    c.rank.unwrap_or(usize::MAX),
+                    path_depth(&c.load_path),
+                    c.load_path.clone(),
+                )
+            })
+            .map(|(i, c)| (i, c.rank));
+
+        let Some((best, best_rank)) = best else {
+            return Resolution {
+                chosen: None,
+                candidates,
+            };
+        };
+        for (i, candidate) in candidates.iter_mut().enumerate() {
+            if i != best && candidate.rejection.is_none() {
+                // The chosen file has the best rank, so any other rank is worse
+                candidate.rejection = Some(if candidate.rank != best_rank {
+                    Rejection::QuantNotPreferred
+                } else {
+                    Rejection::Tie
+                });
+            }
+        }
+
+        Resolution {
+            chosen: Some(candidates[best].load_path.clone()),
+            candidates,
+        }
+    }
+}
+
+fn is_gguf(path: &str) -> bool {
+    path.to_lowercase().ends_with(".gguf")
+}
+
+/// Number of directories above the file
+fn path_depth(path: &str) -> usize {
+    path.matches(['/', '\\']).count()
+}
+
+/// Detect the quantization type from a GGUF filename such as "Qwen3-0.6B-Q4_K_M.gguf"
+pub fn detect_quantization(filename: &str) -> Option<String> {
+    filename
+        .split(['-', '.', '/'])
+        .find(|token| is_quantization_tag(token))
+        .map(|token| token.to_ascii_uppercase())
+}
+
+fn is_quantization_tag(token: &str) -> bool {
+    let upper = token.to_ascii_uppercase();
+    if matches!(upper.as_str(), "BF16" | "F16" | "F32" | "FP16" | "FP32") {
+        return true;
+    }
+
+    let Some(rest) = upper.strip_prefix("IQ").or_else(|| upper.strip_prefix('Q')) else {
+        return false;
+    };
+
+    let mut parts = rest.split('_');
+    let bits_valid = parts
+        .next()
+        .is_some_and(|bits| !bits.is_empty() && bits.chars().all(|c| c.is_ascii_digit()));
+    bits_valid
+        && parts.all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1024 * 1024 * 1024;
+
+    fn files(paths: &[&str]) -> Vec<RepoFile> {
+        paths.iter().map(|path| RepoFile::new(*path)).collect()
+    }
+
+    fn resolve(paths: &[&str]) -> Resolution {
+        ModelResolver::resolve(&files(paths), &ResolutionPrefs::default())
+    }
+
+    fn rejection<'a>(resolution: &'a Resolution, path: &str) -> Option<&'a Rejection> {
+        resolution
+            .candidates
+            .iter()
+            .find(|c| c.path == path)
+            .unwrap_or_else(|| panic!("{} was not considered", path))
+            .rejection
+            .as_ref()
+    }
+
+    #[test]
+    fn test_every_file_is_a_candidate() {
+        let resolution = resolve(&["README.md", "model-Q4_K_M.gguf", "config.json"]);
+        assert_eq!(resolution.chosen.as_deref(), Some("model-Q4_K_M.gguf"));
+        assert_eq!(resolution.candidates.len(), 3);
+        assert_eq!(
+            rejection(&resolution, "README.md"),
+            Some(&Rejection::WrongExtension)
+        );
+        assert_eq!(rejection(&resolution, "model-Q4_K_M.gguf"), None);
+
+        // Candidates stay in the order given
+        let paths: Vec<&str> = resolution
+            .candidates
+            .iter()
+            .map(|c| c.path.as_str())
+            .collect();
+        assert_eq!(paths, ["README.md", "model-Q4_K_M.gguf", "config.json"]);
+    }
+
+    #[test]
+    fn test_extension_is_case_insensitive() {
+        let resolution = resolve(&["MODEL-Q4_0.GGUF", "model.gguf.part"]);
+        assert_eq!(resolution.chosen.as_deref(), Some("MODEL-Q4_0.GGUF"));
+        assert_eq!(
+            rejection(&resolution, "model.gguf.part"),
+            Some(&Rejection::WrongExtension)
+        );
+    }
+
+    #[test]
+    fn test_nothing_to_choose() {
+        for paths in [&[][..], &["README.md", "tokenizer.json"][..]] {
+            let resolution = resolve(paths);
+            assert_eq!(resolution.chosen, None);
+            let err = resolution.into_chosen("org/repo").unwrap_err();
+            assert!(
+                matches!(&err, ModelError::NotFound(msg) if msg == "No .gguf model files found in org/repo"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_ties_break_on_depth_then_name() {
+        let resolution = resolve(&["b/model-Q8_0.gguf", "model-Q8_0.gguf", "a-Q4_0.gguf"]);
+        assert_eq!(resolution.chosen.as_deref(), Some("a-Q4_0.gguf"));
+        assert_eq!(
+            rejection(&resolution, "model-Q8_0.gguf"),
+            Some(&Rejection::Tie)
+        );
+        assert_eq!(
+            rejection(&resolution, "b/model-Q8_0.gguf"),
+            Some(&Rejection::Tie)
+        );
+
+        // A shallower file wins even if a deeper one sorts first
+        let resolution = resolve(&["a/model.gguf", "z-model.gguf"]);
+        assert_eq!(resolution.chosen.as_deref(), Some("z-model.gguf"));
+
+        // Windows separators count towards depth too
+        let resolution = resolve(&["a\\model.gguf", "z-model.gguf"]);
+        assert_eq!(resolution.chosen.as_deref(), Some("z-model.gguf"));
+    }
+
+    #[test]
+    fn test_tie_result_does_not_depend_on_listing_order() {
+        let mut paths = vec!["m-Q4_0.gguf", "m-Q5_0.gguf", "m-Q6_K.gguf"];
+        let first = resolve(&paths).chosen;
+        paths.reverse();
+        assert_eq!(resolve(&paths).chosen, first);
+        assert_eq!(first.as_deref(), Some("m-Q4_0.gguf"));
+    }
+
+    #[test]
+    fn test_bf16_preferred_by_default() {
+        let resolution = resolve(&["a-Q4_K_M.gguf", "deep/er/model-bf16.gguf"]);
+        assert_eq!(
+            resolution.chosen.as_deref(),
+            Some("deep/er/model-bf16.gguf")
+        );
+        assert_eq!(
+            rejection(&resolution, "a-Q4_K_M.gguf"),
+            Some(&Rejection::QuantNotPreferred)
+        );
+
+        // A directory name carries the quantization as well as a file name
+        let resolution = resolve(&["a-Q4_K_M.gguf", "BF16/model.gguf"]);
+        assert_eq!(resolution.chosen.as_deref(), Some("BF16/model.gguf"));
+    }
+
+    #[test]
+    fn test_mixed_quantizations_follow_preference_order() {
+        let paths = files(&[
+            "model-Q8_0.gguf",
+            "model-Q4_K_M.gguf",
+            "model-q5_k_m.gguf",
+            "model-F16.gguf",
+        ]);
+        let prefs = ResolutionPrefs::default().with_preferred_quantizations(["Q5_K_M", "Q4_K_M"]);
+        let resolution = ModelResolver::resolve(&paths, &prefs);
+        assert_eq!(resolution.chosen.as_deref(), Some("model-q5_k_m.gguf"));
+
+        let chosen = &resolution.candidates[2];
+        assert_eq!(chosen.quantization.as_deref(), Some("Q5_K_M"));
+        assert_eq!(chosen.rank, Some(0));
+        assert_eq!(resolution.candidates[1].rank, Some(1));
+        assert_eq!(resolution.candidates[0].rank, None);
+        for path in ["model-Q8_0.gguf", "model-Q4_K_M.gguf", "model-F16.gguf"] {
+            assert_eq!(
+                rejection(&resolution, path),
+                Some(&Rejection::QuantNotPreferred),
+                "{}",
+                path
+            );
+        }
+
+        // Without the first choice, the second is taken
+        let prefs = prefs.with_preferred_quantizations(["IQ2_XS", "Q8_0"]);
+        let resolution = ModelResolver::resolve(&paths, &prefs);
+        assert_eq!(resolution.chosen.as_deref(), Some("model-Q8_0.gguf"));
+
+        // No preferences at all leaves the tie-break
+        let prefs = prefs.with_preferred_quantizations(Vec::<String>::new());
+        let resolution = ModelResolver::resolve(&paths, &prefs);
+        assert_eq!(resolution.chosen.as_deref(), Some("model-F16.gguf"));
+        assert_eq!(
+            rejection(&resolution, "model-Q8_0.gguf"),
+            Some(&Rejection::Tie)
+        );
+    }
+
+    #[test]
+    fn test_quantization_detected_per_candidate() {
+        let resolution = resolve(&["Qwen3-0.6B-IQ4_XS.gguf", "BF16/model.gguf", "model.gguf"]);
+        let quantizations: Vec<Option<&str>> = resolution
+            .candidates
+            .iter()
+            .map(|c| c.quantization.as_deref())
+            .collect();
+        assert_eq!(quantizations, [Some("IQ4_XS"), Some("BF16"), None]);
+    }
+
+    #[test]
+    fn test_multipart_set_loads_through_first_shard() {
+        let resolution = ModelResolver::resolve(
+            &[
+                RepoFile::new("model-00002-of-00003.gguf").with_size(4 * GB),
+                RepoFile::new("model-00001-of-00003.gguf").with_size(4 * GB),
+                RepoFile::new("model-00003-of-00003.gguf").with_size(2 * GB),
+            ],
+            &ResolutionPrefs::default(),
+        );
+        assert_eq!(
+            resolution.chosen.as_deref(),
+            Some("model-00001-of-00003.gguf")
+        );
+        for candidate in &resolution.candidates {
+            assert_eq!(candidate.load_path, "model-00001-of-00003.gguf");
+            assert_eq!(candidate.size_bytes, Some(10 * GB));
+        }
+        assert_eq!(
+            rejection(&resolution, "model-00003-of-00003.gguf"),
+            Some(&Rejection::MultipartShard {
+                first_shard: "model-00001-of-00003.gguf".to_string()
+            })
+        );
+        assert_eq!(rejection(&resolution, "model-00001-of-00003.gguf"), None);
+    }
+
+    #[test]
+    fn test_multipart_set_without_first_shard_listed() {
+        let resolution = resolve(&[
+            "big/model-00003-of-00003.gguf",
+            "big/model-00002-of-00003.gguf",
+        ]);
+        // The lowest listed shard stands for the set; llama.cpp still loads the first
+        assert_eq!(
+            resolution.chosen.as_deref(),
+            Some("big/model-00001-of-00003.gguf")
+        );
+        assert_eq!(
+            rejection(&resolution, "big/model-00002-of-00003.gguf"),
+            None
+        );
+        assert!(matches!(
+            rejection(&resolution, "big/model-00003-of-00003.gguf"),
+            Some(Rejection::MultipartShard { .. })
+        ));
+    }
+
+    #[test]
+    fn test_multipart_sets_compete_with_single_files() {
+        let resolution = resolve(&[
+            "Q4_K_M/model-Q4_K_M-00001-of-00002.gguf",
+            "Q4_K_M/model-Q4_K_M-00002-of-00002.gguf",
+            "BF16/model-BF16-00002-of-00002.gguf",
+            "BF16/model-BF16-00001-of-00002.gguf",
+            "model-Q8_0.gguf",
+        ]);
+        assert_eq!(
+            resolution.chosen.as_deref(),
+            Some("BF16/model-BF16-00001-of-00002.gguf")
+        );
+        assert_eq!(
+            rejection(&resolution, "Q4_K_M/model-Q4_K_M-00001-of-00002.gguf"),
+            Some(&Rejection::QuantNotPreferred)
+        );
+        assert!(matches!(
+            rejection(&resolution, "Q4_K_M/model-Q4_K_M-00002-of-00002.gguf"),
+            Some(Rejection::MultipartShard { .. })
+        ));
+
+        // Between equally preferred entries a set ties like a single file would
+        let resolution = resolve(&["b-00001-of-00002.gguf", "b-00002-of-00002.gguf", "a.gguf"]);
+        assert_eq!(resolution.chosen.as_deref(), Some("a.gguf"));
+        assert_eq!(
+            rejection(&resolution, "b-00001-of-00002.gguf"),
+            Some(&Rejection::Tie)
+        );
+    }
+
+    #[test]
+    fn test_multipart_size_unknown_if_any_shard_unsized() {
+        let resolution = ModelResolver::resolve(
+            &[
+                RepoFile::new("m-00001-of-00002.gguf").with_size(30 * GB),
+                RepoFile::new("m-00002-of-00002.gguf"),
+            ],
+            &ResolutionPrefs::default().with_memory_limit(8 * GB),
+        );
+        // Without a known size the set cannot be ruled out
+        assert_eq!(resolution.candidates[0].size_bytes, None);
+        assert_eq!(resolution.chosen.as_deref(), Some("m-00001-of-00002.gguf"));
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let paths = [
+            RepoFile::new("model-BF16.gguf").with_size(16 * GB),
+            RepoFile::new("model-Q8_0.gguf").with_size(8 * GB),
+            RepoFile::new("model-Q4_K_M.gguf").with_size(5 * GB),
+            RepoFile::new("model-Q2_K.gguf"),
+        ];
+        let prefs =
+            ResolutionPrefs::default().with_preferred_quantizations(["BF16", "Q8_0", "Q4_K_M"]);
+
+        let resolution = ModelResolver::resolve(&paths, &prefs);
+        assert_eq!(resolution.chosen.as_deref(), Some("model-BF16.gguf"));
+
+        // A file exactly at the limit still fits
+        let resolution = ModelResolver::resolve(&paths, &prefs.clone().with_memory_limit(8 * GB));
+        assert_eq!(resolution.chosen.as_deref(), Some("model-Q8_0.gguf"));
+        assert_eq!(
+            rejection(&resolution, "model-BF16.gguf"),
+            Some(&Rejection::TooLarge {
+                size_bytes: 16 * GB,
+                limit_bytes: 8 * GB
+            })
+        );
+        assert_eq!(
+            rejection(&resolution, "model-Q4_K_M.gguf"),
+            Some(&Rejection::QuantNotPreferred)
+        );
+
+        // A file of unknown size is not rejected
+        let resolution = ModelResolver::resolve(&paths, &prefs.with_memory_limit(GB));
+        assert_eq!(resolution.chosen.as_deref(), Some("model-Q2_K.gguf"));
+    }
+
+    #[test]
+    fn test_nothing_fits_memory_limit() {
+        let resolution = ModelResolver::resolve(
+            &[RepoFile::new("model.gguf").with_size(4 * GB)],
+            &ResolutionPrefs::default().with_memory_limit(2 * GB),
+        );
+        let err = resolution.into_chosen("/models").unwrap_err();
+        assert!(
+            matches!(&err, ModelError::NotFound(msg) if msg.contains("fits the memory limit")),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_display_explains_choice() {
+        let resolution = ModelResolver::resolve(
+            &[
+                RepoFile::new("README.md"),
+                RepoFile::new("model-Q4_K_M.gguf").with_size(GB / 2),
+                RepoFile::new("model-BF16.gguf").with_size(2 * GB),
+            ],
+            &ResolutionPrefs::default(),
+        );
+        assert_eq!(
+            resolution.to_string(),
+            "Chose model-BF16.gguf\n\
+             \x20 model-BF16.gguf (BF16, 2.0 GB, preference 1): chosen\n\
+             \x20 README.md: not a .gguf file\n\
+             \x20 model-Q4_K_M.gguf (Q4_K_M, 0.5 GB): quantization preferred less than the chosen file's"
+        );
+
+        assert_eq!(
+            resolve(&["README.md"]).to_string(),
+            "No model file qualified\n  README.md: not a .gguf file"
+        );
+    }
+}
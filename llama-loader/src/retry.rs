@@ -1,102 +1,295 @@
 use crate::error::ModelError;
 use crate::types::RetryConfig;
-use std::path::PathBuf;
-use tracing::{info, warn};
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Broad category of a failed attempt, used to decide whether to retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The requested repository or file does not exist (404)
+    NotFound,
+    /// Authentication or authorization failed (401/403)
+    Auth,
+    /// Temporary failures: timeouts, 5xx, rate limiting, connection resets
+    Transient,
+    /// Anything not recognized above
+    Other,
+}
+
+impl ErrorClass {
+    /// Whether errors of this class should fail without retrying
+    pub fn is_fail_fast(self) -> bool {
+        matches!(self, ErrorClass::NotFound | ErrorClass::Auth)
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorClass::NotFound => "not found",
+            ErrorClass::Auth => "auth",
+            ErrorClass::Transient => "transient",
+            ErrorClass::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A single failed attempt recorded in a [`RetryReport`]
+#[derive(Debug, Clone)]
+pub struct RetryAttempt {
+    /// 1-based attempt number
+    pub attempt: u32,
+    /// When the attempt failed
+    pub timestamp: SystemTime,
+    /// Error message of the failure
+    pub error: String,
+    /// Classification of the error
+    pub class: ErrorClass,
+    /// Delay before the next attempt, or `None` if no retry followed
+    pub delay: Option<Duration>,
+}
+
+/// History of every failed attempt of a retried operation
+#[derive(Debug, Clone)]
+pub struct RetryReport {
+    /// Description of the operation, e.g. the file being downloaded
+    pub operation: String,
+    /// Failed attempts in order
+    pub attempts: Vec<RetryAttempt>,
+}
+
+impl RetryReport {
+    pub fn new(operation: impl Into<String>) -> Self {
+        Self {
+            operation: operation.into(),
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Classification of the last failure, if any
+    pub fn final_class(&self) -> Option<ErrorClass> {
+        self.attempts.last().map(|attempt| attempt.class)
+    }
+}
+
+impl fmt::Display for RetryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Retry report for {} ({} attempts):",
+            self.operation,
+            self.attempts.len()
+        )?;
+        for attempt in &self.attempts {
+            let elapsed = attempt
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            write!(
+                f,
+                "\n  #{} at {}.{:03}s [{}] {}",
+                attempt.attempt,
+                elapsed.as_secs(),
+                elapsed.subsec_millis(),
+                attempt.class,
+                attempt.error
+            )?;
+            match attempt.delay {
+                Some(delay) => write!(f, " -> retrying in {}ms", delay.as_millis())?,
+                None => write!(f, " -> giving up")?,
+            }
+        }
+        Ok(())
+    }
+}
 
-/// Downloads a model file with retry logic and exponential backoff
+/// Downloads a model file with retry logic and exponential backoff.
+///
+/// hf-hub downloads into the `tmp` directory of `cache`, the cache `repo_api` was
+/// built with, and moves the file into place once complete. Cancelling `cancel`
+/// abandons the download, removes the partial files it left there and returns
+/// [`ModelError::Cancelled`].
 pub async fn download_with_retry(
     repo_api: &hf_hub::api::tokio::ApiRepo,
+    cache: &hf_hub::Cache,
     filename: &str,
     repo: &str,
     retry_config: &RetryConfig,
+    cancel: &CancellationToken,
 ) -> Result<PathBuf, ModelError> {
-    let mut attempt = 0;
+    let operation = format!("download of '{}' from '{}'", filename, repo);
+    let started = SystemTime::now();
+
+    let result = tokio::select! {
+        biased;
+        _ = cancel.cancelled() => None,
+        result = retry_with_report(&operation, retry_config, || repo_api.get(filename)) => Some(result),
+    };
+    let Some(result) = result else {
+        let tmp_dir = cache.path().join("tmp");
+        remove_partial_downloads(&tmp_dir, started).await;
+        info!("Download of '{}' from '{}' cancelled", filename, repo);
+        return Err(ModelError::Cancelled);
+    };
+    match result {
+        Ok(path) => Ok(path),
+        Err((e, report)) => {
+            let retries_attempted = report.attempts.len().saturating_sub(1) as u32;
+            Err(ModelError::DownloadFailed {
+                message: format_download_error(filename, repo, &e, retries_attempted),
+                report,
+            })
+        }
+    }
+}
+
+/// Removes the files in `tmp_dir` modified since `since`: the partial files of a
+/// download abandoned after starting at `since`
+pub(crate) async fn remove_partial_downloads(tmp_dir: &Path, since: SystemTime) {
+    let Ok(mut entries) = tokio::fs::read_dir(tmp_dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        let recent = metadata.modified().is_ok_and(|modified| modified >= since);
+        if metadata.is_file() && recent {
+            debug!("Removing partial download {}", entry.path().display());
+            if let Err(e) = tokio::fs::remove_file(entry.path()).await {
+                warn!(
+                    "Failed to remove partial download {}: {}",
+                    entry.path().display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Runs `operation` until it succeeds or its error class runs out of retries.
+///
+/// NotFound and Auth errors fail immediately. Transient and unrecognized
+/// errors are retried with exponential backoff, up to the per-class limit from
+/// [`RetryConfig::max_retries_for`]. On failure the last error is returned
+/// together with a report of every attempt.
+pub async fn retry_with_report<T, E, F, Fut>(
+    operation: &str,
+    retry_config: &RetryConfig,
+    mut op: F,
+) -> Result<T, (E, RetryReport)>
+where
+    E: std::error::Error,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut report = RetryReport::new(operation);
     let mut delay = retry_config.initial_delay_ms;
 
     loop {
-        match repo_api.get(filename).await {
-            Ok(path) => {
-                if attempt > 0 {
+        let e = match op().await {
+            Ok(value) => {
+                if !report.attempts.is_empty() {
                     info!(
-                        "Successfully downloaded {} after {} retries",
-                        filename, attempt
+                        "{} succeeded after {} retries",
+                        operation,
+                        report.attempts.len()
                     );
                 }
-                return Ok(path);
+                return Ok(value);
             }
-            Err(e) => {
-                attempt += 1;
-
-                // Check if this is a retriable error
-                let is_retriable = is_retriable_error(&e);
-
-                if attempt > retry_config.max_retries || !is_retriable {
-                    return Err(ModelError::LoadingFailed(format_download_error(
-                        filename,
-                        repo,
-                        &e,
-                        attempt - 1,
-                    )));
-                }
+            Err(e) => e,
+        };
+
+        let class = classify_error(&e);
+        let retries_used = report.attempts.len() as u32;
+        let attempt = retries_used + 1;
+        let retry = !class.is_fail_fast() && retries_used < retry_config.max_retries_for(class);
+
+        report.attempts.push(RetryAttempt {
+            attempt,
+            timestamp: SystemTime::now(),
+            error: e.to_string(),
+            class,
+            delay: retry.then(|| Duration::from_millis(delay)),
+        });
+
+        if !retry {
+            warn!(
+                "{} failed on attempt {} ({} error), not retrying: {}",
+                operation, attempt, class, e
+            );
+            return Err((e, report));
+        }
 
-                warn!(
-                    "Download attempt {} failed for '{}': {}. Retrying in {}ms...",
-                    attempt, filename, e, delay
-                );
+        warn!(
+            "Attempt {} of {} failed ({} error): {}. Retrying in {}ms...",
+            attempt, operation, class, e, delay
+        );
 
-                // Wait with exponential backoff
-                tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
+        // Wait with exponential backoff
+        tokio::time::sleep(Duration::from_millis(delay)).await;
 
-                // Calculate next delay with exponential backoff
-                delay = ((delay as f64) * retry_config.backoff_multiplier) as u64;
-                delay = delay.min(retry_config.max_delay_ms);
-            }
-        }
+        // Calculate next delay with exponential backoff
+        delay = ((delay as f64) * retry_config.backoff_multiplier) as u64;
+        delay = delay.min(retry_config.max_delay_ms);
     }
 }
 
-/// Determines if an error is retriable based on the error message
-pub fn is_retriable_error(error: &dyn std::error::Error) -> bool {
+/// Classifies an error based on its message
+pub fn classify_error(error: &dyn std::error::Error) -> ErrorClass {
     let error_msg = error.to_string().to_lowercase();
 
     // Check for specific HTTP status codes or error patterns
-    if error_msg.contains("500") || error_msg.contains("internal server error") {
-        return true;
-    }
-    if error_msg.contains("502") || error_msg.contains("bad gateway") {
-        return true;
-    }
-    if error_msg.contains("503") || error_msg.contains("service unavailable") {
-        return true;
-    }
-    if error_msg.contains("504") || error_msg.contains("gateway timeout") {
-        return true;
-    }
-    if error_msg.contains("429") || error_msg.contains("too many requests") {
-        return true;
+    let transient_patterns = [
+        "500",
+        "internal server error",
+        "502",
+        "bad gateway",
+        "503",
+        "service unavailable",
+        "504",
+        "gateway timeout",
+        "429",
+        "too many requests",
+    ];
+    if transient_patterns.iter().any(|p| error_msg.contains(p)) {
+        return ErrorClass::Transient;
     }
 
-    // Network-level errors are retriable
+    // Network-level errors are transient
     if error_msg.contains("connection")
         || error_msg.contains("timeout")
+        || error_msg.contains("timed out")
+        || error_msg.contains("reset")
         || error_msg.contains("network")
     {
-        return true;
+        return ErrorClass::Transient;
     }
 
-    // Client errors (4xx) are generally not retriable
+    // Client errors (4xx) are not worth retrying
     if error_msg.contains("404") || error_msg.contains("not found") {
-        return false;
-    }
-    if error_msg.contains("403") || error_msg.contains("forbidden") {
-        return false;
+        return ErrorClass::NotFound;
     }
-    if error_msg.contains("401") || error_msg.contains("unauthorized") {
-        return false;
+    if error_msg.contains("403")
+        || error_msg.contains("forbidden")
+        || error_msg.contains("401")
+        || error_msg.contains("unauthorized")
+    {
+        return ErrorClass::Auth;
     }
 
-    // Default to retriable for unknown errors
-    true
+    ErrorClass::Other
+}
+
+/// Determines if an error is retriable based on the error message
+pub fn is_retriable_error(error: &dyn std::error::Error) -> bool {
+    !classify_error(error).is_fail_fast()
 }
 
 /// Formats a comprehensive error message for download failures
@@ -130,7 +323,7 @@ pub fn format_download_error(
         "🌐 Network error. Check your internet connection and try again."
     };
 
-    let additional_help = "💡 Check model file exists, is valid GGUF format, and sufficient memory is available\n🔧 You can increase retry attempts by configuring retry_config.max_retries";
+    let additional_help = "💡 Check model file exists, is valid GGUF format, and sufficient memory is available\n🔧 You can increase retry attempts by configuring retry_config.max_retries (or max_retries_transient / max_retries_other)";
 
     format!("{}\n{}\n{}", base_message, guidance, additional_help)
 }
@@ -253,4 +446,163 @@ mod tests {
         }
         assert_eq!(delay, retry_config.max_delay_ms); // Should cap at 30s
     }
+
+    #[derive(Debug)]
+    struct MockError(String);
+    impl std::fmt::Display for MockError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+    impl std::error::Error for MockError {}
+
+    fn fast_retry_config() -> RetryConfig {
+        RetryConfig {
+            max_retries: 3,
+            initial_delay_ms: 1,
+            backoff_multiplier: 2.0,
+            max_delay_ms: 4,
+            ..RetryConfig::default()
+        }
+    }
+
+    /// Runs a mocked operation that always fails with `message`, returning the call count
+    async fn run_failing(config: &RetryConfig, message: &str) -> (u32, RetryReport) {
+        let mut calls = 0;
+        let result: Result<(), _> = retry_with_report("mock download", config, || {
+            calls += 1;
+            let message = message.to_string();
+            async move { Err(MockError(message)) }
+        })
+        .await;
+        let (_, report) = result.unwrap_err();
+        (calls, report)
+    }
+
+    #[test]
+    fn test_classify_error() {
+        let class = |msg: &str| classify_error(&MockError(msg.to_string()));
+
+        assert_eq!(class("404 Not Found"), ErrorClass::NotFound);
+        assert_eq!(class("401 Unauthorized"), ErrorClass::Auth);
+        assert_eq!(class("403 Forbidden"), ErrorClass::Auth);
+        assert_eq!(class("503 Service Unavailable"), ErrorClass::Transient);
+        assert_eq!(class("request timed out"), ErrorClass::Transient);
+        assert_eq!(class("connection reset by peer"), ErrorClass::Transient);
+        assert_eq!(class("checksum mismatch"), ErrorClass::Other);
+    }
+
+    #[tokio::test]
+    async fn test_remove_partial_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let earlier = dir.path().join("earlier");
+        std::fs::write(&earlier, b"another download").unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let since = SystemTime::now();
+        let partial = dir.path().join("aB3xY9q");
+        std::fs::write(&partial, b"partial").unwrap();
+
+        remove_partial_downloads(dir.path(), since).await;
+        assert!(earlier.exists());
+        assert!(!partial.exists());
+
+        // A missing tmp directory is nothing to clean up
+        remove_partial_downloads(&dir.path().join("missing"), since).await;
+    }
+
+    #[tokio::test]
+    async fn test_not_found_fails_fast() {
+        let (calls, report) = run_failing(&fast_retry_config(), "404 Not Found").await;
+
+        assert_eq!(calls, 1);
+        assert_eq!(report.attempts.len(), 1);
+        assert_eq!(report.final_class(), Some(ErrorClass::NotFound));
+        assert!(report.attempts[0].delay.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_auth_fails_fast() {
+        let (calls, report) = run_failing(&fast_retry_config(), "401 Unauthorized").await;
+
+        assert_eq!(calls, 1);
+        assert_eq!(report.final_class(), Some(ErrorClass::Auth));
+    }
+
+    #[tokio::test]
+    async fn test_transient_uses_transient_limit() {
+        let config = RetryConfig {
+            max_retries_transient: Some(5),
+            max_retries_other: Some(0),
+            ..fast_retry_config()
+        };
+        let (calls, report) = run_failing(&config, "503 Service Unavailable").await;
+
+        // One initial attempt plus five retries
+        assert_eq!(calls, 6);
+        assert_eq!(report.attempts.len(), 6);
+        assert!(report
+            .attempts
+            .iter()
+            .all(|a| a.class == ErrorClass::Transient));
+
+        // Delays back off exponentially, cap at max_delay_ms, and the last attempt gives up
+        let delays: Vec<_> = report.attempts.iter().map(|a| a.delay).collect();
+        assert_eq!(delays[0], Some(Duration::from_millis(1)));
+        assert_eq!(delays[1], Some(Duration::from_millis(2)));
+        assert_eq!(delays[3], Some(Duration::from_millis(4)));
+        assert_eq!(delays[5], None);
+    }
+
+    #[tokio::test]
+    async fn test_other_uses_other_limit() {
+        let config = RetryConfig {
+            max_retries_transient: Some(5),
+            max_retries_other: Some(1),
+            ..fast_retry_config()
+        };
+        let (calls, report) = run_failing(&config, "unexpected EOF").await;
+
+        assert_eq!(calls, 2);
+        assert_eq!(report.final_class(), Some(ErrorClass::Other));
+    }
+
+    #[tokio::test]
+    async fn test_default_limits_fall_back_to_max_retries() {
+        let (calls, _) = run_failing(&fast_retry_config(), "502 Bad Gateway").await;
+        assert_eq!(calls, 4);
+
+        let (calls, _) = run_failing(&fast_retry_config(), "unexpected EOF").await;
+        assert_eq!(calls, 4);
+    }
+
+    #[tokio::test]
+    async fn test_success_after_transient_failures() {
+        let mut calls = 0;
+        let result = retry_with_report("mock download", &fast_retry_config(), || {
+            calls += 1;
+            let attempt = calls;
+            async move {
+                if attempt < 3 {
+                    Err(MockError("connection reset".to_string()))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_report_display() {
+        let (_, report) = run_failing(&fast_retry_config(), "404 Not Found").await;
+        let rendered = report.to_string();
+
+        assert!(rendered.contains("mock download"));
+        assert!(rendered.contains("#1"));
+        assert!(rendered.contains("[not found]"));
+        assert!(rendered.contains("giving up"));
+    }
 }
@@ -1,6 +1,9 @@
+use crate::generation_config::HfGenerationDefaults;
+use crate::http::{parse_model_url, url_filename, validate_sha256};
+use crate::retry::ErrorClass;
 use llama_cpp_2::model::LlamaModel;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// A loaded model with associated metadata
@@ -12,25 +15,53 @@ pub struct LoadedModel {
     pub path: PathBuf,
     /// Metadata about the model loading process
     pub metadata: ModelMetadata,
+    /// Sampling defaults from the repository's `generation_config.json`, fetched when
+    /// `ModelConfig::use_hf_params` is set
+    pub hf_generation_defaults: Option<HfGenerationDefaults>,
 }
 
 /// Metadata about a loaded model
 #[derive(Debug, Clone)]
 pub struct ModelMetadata {
-    /// The source from which the model was loaded
+    /// The source from which the model was loaded, with the resolved filename
     pub source: ModelSource,
     /// The filename of the model
     pub filename: String,
+    /// Local path of the loaded file (the first part for multi-part models)
+    pub path: PathBuf,
+    /// HuggingFace revision the file was fetched from; `None` for local and URL models
+    pub revision: Option<String>,
     /// Size of the model file in bytes
     pub size_bytes: u64,
-    /// Time taken to load the model
+    /// Time spent fetching the file from HuggingFace or a URL; `None` for local models
+    pub download_time: Option<Duration>,
+    /// Time taken by llama.cpp to load the file
     pub load_time: Duration,
     /// Whether this was loaded from cache
     pub cache_hit: bool,
 }
 
+impl ModelMetadata {
+    /// One-line summary for logs, e.g. "model.gguf (1.2 GB, cache hit) at /path/model.gguf"
+    pub fn summary(&self) -> String {
+        let cache = match (&self.source, self.cache_hit) {
+            (ModelSource::Local { .. }, _) => "local",
+            (_, true) => "cache hit",
+            (_, false) => "downloaded",
+        };
+        format!(
+            "{} ({:.1} GB, {}) at {}",
+            self.filename,
+            self.size_bytes as f64 / (1024.0 * 1024.0 * 1024.0),
+            cache,
+            self.path.display()
+        )
+    }
+}
+
 /// Configuration for model retry logic
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RetryConfig {
     /// Maximum number of retry attempts
     pub max_retries: u32,
@@ -40,6 +71,21 @@ pub struct RetryConfig {
     pub backoff_multiplier: f64,
     /// Maximum delay between retries in milliseconds
     pub max_delay_ms: u64,
+    /// Retry limit for transient errors (timeouts, 5xx, connection resets); defaults to `max_retries`
+    pub max_retries_transient: Option<u32>,
+    /// Retry limit for unclassified errors; defaults to `max_retries`
+    pub max_retries_other: Option<u32>,
+}
+
+impl RetryConfig {
+    /// Maximum number of retries for errors of the given class
+    pub fn max_retries_for(&self, class: ErrorClass) -> u32 {
+        match class {
+            ErrorClass::NotFound | ErrorClass::Auth => 0,
+            ErrorClass::Transient => self.max_retries_transient.unwrap_or(self.max_retries),
+            ErrorClass::Other => self.max_retries_other.unwrap_or(self.max_retries),
+        }
+    }
 }
 
 impl Default for RetryConfig {
@@ -49,6 +95,8 @@ impl Default for RetryConfig {
             initial_delay_ms: 1000, // 1 second
             backoff_multiplier: 2.0,
             max_delay_ms: 30000, // 30 seconds
+            max_retries_transient: None,
+            max_retries_other: None,
         }
     }
 }
@@ -57,6 +105,7 @@ impl Default for RetryConfig {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ModelSource {
     /// Load from HuggingFace repository
+    #[serde(alias = "huggingface", alias = "hugging_face")]
     HuggingFace {
         /// Repository name (e.g., "microsoft/DialoGPT-medium")
         repo: String,
@@ -64,27 +113,101 @@ pub enum ModelSource {
         filename: Option<String>,
     },
     /// Load from local filesystem
+    #[serde(alias = "local")]
     Local {
         /// Path to the folder containing the model
         folder: PathBuf,
         /// Optional specific filename to load
         filename: Option<String>,
     },
+    /// Download from a direct link to a GGUF file
+    #[serde(alias = "url")]
+    Url {
+        /// `https` URL of the file (`http` requires `ModelConfig::allow_http`)
+        url: String,
+        /// Filename to store the download under; defaults to the last URL path segment
+        #[serde(default)]
+        filename: Option<String>,
+        /// Expected SHA-256 of the file, verified after download
+        #[serde(default)]
+        sha256: Option<String>,
+    },
 }
 
 /// Configuration for model loading
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ModelConfig {
     /// The source from which to load the model
     pub source: ModelSource,
     /// Batch size for model operations
     pub batch_size: u32,
-    /// Whether to use HuggingFace parameters
+    /// Whether to fetch the sampling defaults of HuggingFace models from their
+    /// `generation_config.json`
     pub use_hf_params: bool,
     /// Configuration for retry logic
     pub retry_config: RetryConfig,
     /// Enable debug output
     pub debug: bool,
+    /// Directory levels below a local folder searched for model files (0 = folder only)
+    pub local_search_depth: usize,
+    /// Allow `Url` sources to use plain `http`
+    pub allow_http: bool,
+    /// Seconds a cached HuggingFace repository listing is used without listing the repo again
+    pub metadata_ttl_secs: u64,
+    /// List the HuggingFace repository even when the cached listing is fresh
+    pub refresh_metadata: bool,
+    /// Directory for downloaded models; `None` uses `LLAMA_CACHE_DIR` or the platform
+    /// cache directory (see `CacheManager::resolve_cache_dir`)
+    pub cache_dir: Option<PathBuf>,
+    /// Load the model even when it is estimated not to fit in available memory
+    pub force_load: bool,
+    /// Fraction of the weights added to the memory estimate for compute buffers and
+    /// runtime allocations (see `memory::estimate_model_memory`)
+    pub memory_overhead_factor: f64,
+    /// Chat template used in place of the one detected from the model source
+    pub chat_template: Option<TemplateOverride>,
+    /// Stop sequences added to those of the chat template for every request
+    pub extra_stop_sequences: Vec<String>,
+}
+
+/// A chat template chosen in the config rather than detected from the model source.
+///
+/// Written as a builtin template name, or as a table spelling out the template.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TemplateOverride {
+    /// One of [`TemplateOverride::BUILTIN_NAMES`]
+    Named(String),
+    /// A template given in full
+    Custom(CustomTemplate),
+}
+
+impl TemplateOverride {
+    /// Names of the builtin chat templates
+    pub const BUILTIN_NAMES: &'static [&'static str] = &["qwen", "phi3", "llama3", "generic"];
+}
+
+/// How each role's messages are wrapped in a custom chat template
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CustomTemplate {
+    pub system: RoleFormat,
+    pub user: RoleFormat,
+    pub assistant: RoleFormat,
+    pub tool: RoleFormat,
+    /// Text after the last message, where the model starts its reply
+    pub assistant_header: String,
+    /// Sequences that end an assistant turn
+    pub stop_sequences: Vec<String>,
+}
+
+/// Text written before and after a message's content
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoleFormat {
+    pub prefix: String,
+    pub suffix: String,
 }
 
 impl Default for ModelConfig {
@@ -98,14 +221,41 @@ impl Default for ModelConfig {
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: Self::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: Self::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         }
     }
 }
 
 impl ModelConfig {
+    /// Default lifetime of a cached repository listing: one day
+    pub const DEFAULT_METADATA_TTL_SECS: u64 = 24 * 60 * 60;
+
+    /// Default memory overhead on top of the weights: 10%
+    pub const DEFAULT_MEMORY_OVERHEAD_FACTOR: f64 = 0.1;
+
+    /// Context length each model context is created with: 8192 tokens, or the batch
+    /// size when that is larger
+    pub fn context_size(&self) -> u32 {
+        self.batch_size.max(8192)
+    }
+
+    /// How long a cached HuggingFace repository listing stays fresh
+    pub fn metadata_ttl(&self) -> Duration {
+        Duration::from_secs(self.metadata_ttl_secs)
+    }
+
     /// Validate the model configuration
     pub fn validate(&self) -> Result<(), crate::error::ModelError> {
-        self.source.validate()?;
+        self.source
+            .validate_with_options(self.local_search_depth, self.allow_http)?;
 
         if self.batch_size == 0 {
             return Err(crate::error::ModelError::InvalidConfig(
@@ -119,13 +269,83 @@ impl ModelConfig {
             ));
         }
 
+        if !self.memory_overhead_factor.is_finite() || self.memory_overhead_factor < 0.0 {
+            return Err(crate::error::ModelError::InvalidConfig(
+                "Memory overhead factor must be a non-negative number".to_string(),
+            ));
+        }
+
+        if let Some(TemplateOverride::Named(name)) = &self.chat_template {
+            if !TemplateOverride::BUILTIN_NAMES.contains(&name.as_str()) {
+                return Err(crate::error::ModelError::InvalidConfig(format!(
+                    "Unknown chat template '{}'; expected one of: {}",
+                    name,
+                    TemplateOverride::BUILTIN_NAMES.join(", ")
+                )));
+            }
+        }
+
+        let custom_stops = match &self.chat_template {
+            Some(TemplateOverride::Custom(custom)) => custom.stop_sequences.as_slice(),
+            _ => &[],
+        };
+        if custom_stops
+            .iter()
+            .chain(&self.extra_stop_sequences)
+            .any(|stop| stop.is_empty())
+        {
+            return Err(crate::error::ModelError::InvalidConfig(
+                "Stop sequences cannot be empty".to_string(),
+            ));
+        }
+
         Ok(())
     }
 }
 
 impl ModelSource {
+    /// Build a local source from a path that is either a model folder or a `.gguf` file.
+    ///
+    /// A file path is split into its folder and filename, taking precedence over `filename`.
+    pub fn local(path: impl Into<PathBuf>, filename: Option<String>) -> Self {
+        let path = path.into();
+        let is_model_file = path.is_file() || has_gguf_extension(&path);
+
+        match (is_model_file, path.parent(), path.file_name()) {
+            (true, Some(parent), Some(name)) => ModelSource::Local {
+                folder: if parent.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    parent.to_path_buf()
+                },
+                filename: Some(name.to_string_lossy().to_string()),
+            },
+            _ => ModelSource::Local {
+                folder: path,
+                filename,
+            },
+        }
+    }
+
     /// Validate that the model source configuration is valid
     pub fn validate(&self) -> Result<(), crate::error::ModelError> {
+        self.validate_with_search_depth(0)
+    }
+
+    /// Validate the source, allowing local filenames that only exist in subfolders up to `search_depth`
+    pub fn validate_with_search_depth(
+        &self,
+        search_depth: usize,
+    ) -> Result<(), crate::error::ModelError> {
+        self.validate_with_options(search_depth, false)
+    }
+
+    /// Validate the source with the search depth and plain-`http` policy of a `ModelConfig`
+    pub fn validate_with_options(
+        &self,
+        search_depth: usize,
+        allow_http: bool,
+    ) -> Result<(), crate::error::ModelError> {
         match self {
             ModelSource::HuggingFace { repo, filename } => {
                 if repo.is_empty() {
@@ -168,10 +388,30 @@ impl ModelSource {
             }
             ModelSource::Local { folder, filename } => {
                 if !folder.exists() {
-                    return Err(crate::error::ModelError::NotFound(format!(
-                        "Local folder does not exist: {}",
-                        folder.display()
-                    )));
+                    let path = match filename {
+                        Some(f) if !f.is_empty() && !is_glob_pattern(f) => folder.join(f),
+                        _ => folder.clone(),
+                    };
+                    return Err(crate::error::ModelError::NotFound(
+                        describe_missing_local_path(&path),
+                    ));
+                }
+
+                // A model file given in place of its folder is loaded directly
+                if folder.is_file() {
+                    if !has_gguf_extension(folder) {
+                        return Err(crate::error::ModelError::InvalidConfig(format!(
+                            "Model file must have .gguf extension: {}",
+                            folder.display()
+                        )));
+                    }
+                    if filename.is_some() {
+                        return Err(crate::error::ModelError::InvalidConfig(format!(
+                            "A filename can only be given with a folder, not the model file {}",
+                            folder.display()
+                        )));
+                    }
+                    return Ok(());
                 }
 
                 if !folder.is_dir() {
@@ -187,6 +427,11 @@ impl ModelSource {
                             "Filename cannot be empty".to_string(),
                         ));
                     }
+                    // Patterns and nested files are resolved when the model is loaded
+                    if is_glob_pattern(f) || search_depth > 0 {
+                        return Ok(());
+                    }
+
                     if !f.ends_with(".gguf") {
                         return Err(crate::error::ModelError::InvalidConfig(
                             "Model file must have .gguf extension".to_string(),
@@ -195,10 +440,9 @@ impl ModelSource {
 
                     let full_path = folder.join(f);
                     if !full_path.exists() {
-                        return Err(crate::error::ModelError::NotFound(format!(
-                            "Model file does not exist: {}",
-                            full_path.display()
-                        )));
+                        return Err(crate::error::ModelError::NotFound(
+                            describe_missing_local_path(&full_path),
+                        ));
                     }
 
                     if !full_path.is_file() {
@@ -209,15 +453,69 @@ impl ModelSource {
                     }
                 }
 
+                Ok(())
+            }
+            ModelSource::Url {
+                url,
+                filename,
+                sha256,
+            } => {
+                let parsed = parse_model_url(url, allow_http)?;
+                let filename = url_filename(&parsed, filename.as_deref())?;
+                if filename.contains(['/', '\\']) {
+                    return Err(crate::error::ModelError::InvalidConfig(format!(
+                        "Filename cannot contain path separators: {}",
+                        filename
+                    )));
+                }
+                if let Some(sha256) = sha256 {
+                    validate_sha256(sha256)?;
+                }
+
                 Ok(())
             }
         }
     }
 }
 
+/// Returns true when a filename contains `*` or `?` wildcards
+pub fn is_glob_pattern(filename: &str) -> bool {
+    filename.contains(['*', '?'])
+}
+
+/// Returns true when a path ends in `.gguf`, in any case
+pub fn has_gguf_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gguf"))
+}
+
+/// Describes a local model path that does not exist, telling a file missing from an
+/// existing folder apart from a path whose folder is missing too
+pub fn describe_missing_local_path(path: &Path) -> String {
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => return format!("Local model path not found: {}", path.display()),
+    };
+    if parent.is_dir() {
+        format!(
+            "{} does not exist in folder {}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            parent.display()
+        )
+    } else {
+        format!(
+            "Local model path not found: {} (folder {} does not exist either)",
+            path.display(),
+            parent.display()
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ModelError;
     use std::time::Duration;
 
     #[test]
@@ -285,6 +583,187 @@ mod tests {
         assert!(source.validate().is_err());
     }
 
+    #[test]
+    fn test_model_source_validation_local_model_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let model_file = temp_dir.path().join("qwen2.5-7b-q4_k_m.gguf");
+        std::fs::write(&model_file, b"GGUF").unwrap();
+        let notes = temp_dir.path().join("notes.txt");
+        std::fs::write(&notes, b"notes").unwrap();
+        let local = |folder: &Path, filename: Option<&str>| ModelSource::Local {
+            folder: folder.to_path_buf(),
+            filename: filename.map(str::to_string),
+        };
+
+        // A model file in place of its folder
+        assert!(local(&model_file, None).validate().is_ok());
+        assert!(matches!(
+            local(&model_file, Some("other.gguf")).validate(),
+            Err(ModelError::InvalidConfig(_))
+        ));
+
+        // A directory, as before
+        assert!(local(temp_dir.path(), None).validate().is_ok());
+
+        // Wrong extension
+        match local(&notes, None).validate() {
+            Err(ModelError::InvalidConfig(msg)) => {
+                assert!(msg.contains(".gguf extension"), "{}", msg)
+            }
+            other => panic!("Expected InvalidConfig, got {:?}", other),
+        }
+
+        // A missing file in an existing folder
+        match local(&temp_dir.path().join("missing.gguf"), None).validate() {
+            Err(ModelError::NotFound(msg)) => assert_eq!(
+                msg,
+                format!(
+                    "missing.gguf does not exist in folder {}",
+                    temp_dir.path().display()
+                )
+            ),
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+
+        // A missing file in a missing folder
+        let missing = temp_dir.path().join("nowhere/missing.gguf");
+        match local(&missing, None).validate() {
+            Err(ModelError::NotFound(msg)) => {
+                assert!(msg.starts_with("Local model path not found"), "{}", msg)
+            }
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+
+        // The same checks apply to a path split by ModelSource::local
+        assert!(ModelSource::local(&model_file, None).validate().is_ok());
+        match ModelSource::local(temp_dir.path().join("missing.gguf"), None).validate() {
+            Err(ModelError::NotFound(msg)) => {
+                assert!(msg.starts_with("missing.gguf does not exist"), "{}", msg)
+            }
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+        match ModelSource::local(&missing, None).validate() {
+            Err(ModelError::NotFound(msg)) => {
+                assert!(msg.contains("does not exist either"), "{}", msg)
+            }
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_model_source_validation_local_search() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+
+        // Glob patterns are resolved at load time
+        let source = ModelSource::Local {
+            folder: temp_dir.path().to_path_buf(),
+            filename: Some("*q4_k_m*".to_string()),
+        };
+        assert!(source.validate().is_ok());
+
+        // Exact names must exist directly in the folder unless subfolders are searched
+        let source = ModelSource::Local {
+            folder: temp_dir.path().to_path_buf(),
+            filename: Some("nested.gguf".to_string()),
+        };
+        assert!(source.validate().is_err());
+        assert!(source.validate_with_search_depth(2).is_ok());
+    }
+
+    #[test]
+    fn test_model_source_validation_url() {
+        let url = |url: &str, filename: Option<&str>, sha256: Option<&str>| ModelSource::Url {
+            url: url.to_string(),
+            filename: filename.map(str::to_string),
+            sha256: sha256.map(str::to_string),
+        };
+
+        let sha256 = "ab".repeat(32);
+        assert!(url("https://example.com/m.gguf", None, Some(&sha256))
+            .validate()
+            .is_ok());
+
+        // Plain http needs allow_http
+        let source = url("http://example.com/m.gguf", None, None);
+        assert!(source.validate().is_err());
+        assert!(source.validate_with_options(0, true).is_ok());
+        let config = ModelConfig {
+            source,
+            allow_http: true,
+            ..ModelConfig::default()
+        };
+        assert!(config.validate().is_ok());
+
+        for invalid in [
+            url("file:///models/m.gguf", None, None),
+            url("not a url", None, None),
+            url("https://example.com/", None, None),
+            url("https://example.com/m", Some("a/b.gguf"), None),
+            url("https://example.com/m.gguf", None, Some("abc")),
+        ] {
+            assert!(
+                invalid.validate().is_err(),
+                "{:?} should be invalid",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_model_source_serde_compatibility() {
+        // Existing configs still deserialize
+        let source: ModelSource =
+            serde_json::from_str(r#"{"HuggingFace":{"repo":"org/repo","filename":null}}"#).unwrap();
+        assert!(matches!(source, ModelSource::HuggingFace { .. }));
+        let config: ModelConfig = serde_json::from_str(r#"{"batch_size":64}"#).unwrap();
+        assert!(!config.allow_http);
+
+        let source: ModelSource =
+            serde_json::from_str(r#"{"url":{"url":"https://example.com/m.gguf"}}"#).unwrap();
+        assert_eq!(
+            source,
+            ModelSource::Url {
+                url: "https://example.com/m.gguf".to_string(),
+                filename: None,
+                sha256: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_model_source_local_from_path() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let model_file = temp_dir.path().join("model.gguf");
+        std::fs::write(&model_file, b"GGUF").unwrap();
+
+        // A .gguf file is split into folder and filename
+        assert_eq!(
+            ModelSource::local(&model_file, Some("ignored.gguf".to_string())),
+            ModelSource::Local {
+                folder: temp_dir.path().to_path_buf(),
+                filename: Some("model.gguf".to_string()),
+            }
+        );
+
+        // A folder keeps the explicit filename
+        assert_eq!(
+            ModelSource::local(temp_dir.path(), Some("model.gguf".to_string())),
+            ModelSource::Local {
+                folder: temp_dir.path().to_path_buf(),
+                filename: Some("model.gguf".to_string()),
+            }
+        );
+
+        // A bare relative filename resolves against the current directory
+        assert_eq!(
+            ModelSource::local("model.gguf", None),
+            ModelSource::Local {
+                folder: PathBuf::from("."),
+                filename: Some("model.gguf".to_string()),
+            }
+        );
+    }
+
     #[test]
     fn test_model_metadata_creation() {
         let metadata = ModelMetadata {
@@ -293,7 +772,10 @@ mod tests {
                 filename: Some("test.gguf".to_string()),
             },
             filename: "test.gguf".to_string(),
+            path: PathBuf::from("/cache/test.gguf"),
+            revision: Some("main".to_string()),
             size_bytes: 1024,
+            download_time: Some(Duration::from_secs(3)),
             load_time: Duration::from_secs(1),
             cache_hit: false,
         };
@@ -301,6 +783,10 @@ mod tests {
         assert_eq!(metadata.filename, "test.gguf");
         assert_eq!(metadata.size_bytes, 1024);
         assert!(!metadata.cache_hit);
+        assert_eq!(
+            metadata.summary(),
+            "test.gguf (0.0 GB, downloaded) at /cache/test.gguf"
+        );
     }
 
     #[test]
@@ -311,4 +797,67 @@ mod tests {
         assert_eq!(config.backoff_multiplier, 2.0);
         assert_eq!(config.max_delay_ms, 30000);
     }
+
+    #[test]
+    fn test_retry_config_per_class_limits() {
+        let mut config = RetryConfig::default();
+        assert_eq!(config.max_retries_for(ErrorClass::Transient), 3);
+        assert_eq!(config.max_retries_for(ErrorClass::Other), 3);
+        assert_eq!(config.max_retries_for(ErrorClass::NotFound), 0);
+        assert_eq!(config.max_retries_for(ErrorClass::Auth), 0);
+
+        config.max_retries_transient = Some(8);
+        config.max_retries_other = Some(1);
+        assert_eq!(config.max_retries_for(ErrorClass::Transient), 8);
+        assert_eq!(config.max_retries_for(ErrorClass::Other), 1);
+    }
+
+    #[test]
+    fn test_model_config_chat_template_serde() {
+        // Configs written before template overrides existed still load
+        let config: ModelConfig = serde_json::from_str(r#"{"batch_size": 256}"#).unwrap();
+        assert_eq!(config.chat_template, None);
+        assert!(config.extra_stop_sequences.is_empty());
+
+        let config: ModelConfig = serde_json::from_str(
+            r#"{"chat_template": "llama3", "extra_stop_sequences": ["</answer>"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.chat_template,
+            Some(TemplateOverride::Named("llama3".to_string()))
+        );
+        assert_eq!(config.extra_stop_sequences, vec!["</answer>".to_string()]);
+        assert!(config.validate().is_ok());
+
+        let config: ModelConfig = serde_json::from_str(
+            r#"{"chat_template": {"user": {"prefix": "Q: ", "suffix": "\n"}, "assistant_header": "A:", "stop_sequences": ["Q:"]}}"#,
+        )
+        .unwrap();
+        let Some(TemplateOverride::Custom(custom)) = config.chat_template else {
+            panic!("expected a custom template");
+        };
+        assert_eq!(custom.user.prefix, "Q: ");
+        assert_eq!(custom.system, RoleFormat::default());
+        assert_eq!(custom.assistant_header, "A:");
+        assert_eq!(custom.stop_sequences, vec!["Q:".to_string()]);
+    }
+
+    #[test]
+    fn test_model_config_chat_template_validation() {
+        let config = ModelConfig {
+            chat_template: Some(TemplateOverride::Named("mistral".to_string())),
+            ..ModelConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ModelError::InvalidConfig(message)) if message.contains("mistral")
+        ));
+
+        let config = ModelConfig {
+            extra_stop_sequences: vec![String::new()],
+            ..ModelConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
 }
@@ -32,6 +32,15 @@ async fn test_model_loader_creation() {
         use_hf_params: true,
         retry_config: retry_config.clone(),
         debug: false,
+        local_search_depth: 0,
+        allow_http: false,
+        metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+        chat_template: None,
+        extra_stop_sequences: Vec::new(),
     };
 
     assert!(model_config.validate().is_err()); // Should fail because file doesn't exist
@@ -51,6 +60,15 @@ async fn test_model_config_validation() {
         use_hf_params: true,
         retry_config: RetryConfig::default(),
         debug: false,
+        local_search_depth: 0,
+        allow_http: false,
+        metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+        chat_template: None,
+        extra_stop_sequences: Vec::new(),
     };
     assert!(valid_config.validate().is_ok());
 
@@ -64,6 +82,15 @@ async fn test_model_config_validation() {
         use_hf_params: true,
         retry_config: RetryConfig::default(),
         debug: false,
+        local_search_depth: 0,
+        allow_http: false,
+        metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+        chat_template: None,
+        extra_stop_sequences: Vec::new(),
     };
     assert!(invalid_config.validate().is_err());
 
@@ -77,6 +104,15 @@ async fn test_model_config_validation() {
         use_hf_params: true,
         retry_config: RetryConfig::default(),
         debug: false,
+        local_search_depth: 0,
+        allow_http: false,
+        metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+        refresh_metadata: false,
+        cache_dir: None,
+        force_load: false,
+        memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+        chat_template: None,
+        extra_stop_sequences: Vec::new(),
     };
     assert!(invalid_hf_config.validate().is_err());
 }
@@ -178,6 +214,8 @@ async fn test_retry_config() {
         initial_delay_ms: 500,
         backoff_multiplier: 1.5,
         max_delay_ms: 10000,
+        max_retries_transient: Some(8),
+        max_retries_other: None,
     };
     assert_eq!(custom_config.max_retries, 5);
     assert_eq!(custom_config.initial_delay_ms, 500);
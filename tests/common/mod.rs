@@ -1,7 +1,10 @@
 use llama_agent::types::{
-    AgentConfig, Message, MessageRole, ModelConfig, ModelSource, QueueConfig, RetryConfig, Session,
-    SessionConfig, SessionId, ToolCall, ToolCallId, ToolDefinition, ToolResult,
+    AgentConfig, LimitsConfig, LoadMode, Message, MessageRole, ModelConfig, ModelSource,
+    ParallelExecutionConfig, QueueConfig, RetryConfig, Session, SessionConfig,
+    SessionEvictionPolicy, SessionId, SessionUsage, ToolCall, ToolCallId, ToolDefinition,
+    ToolPolicy, ToolResult, ValidationSeverity,
 };
+use llama_agent::RedactionPolicy;
 use std::path::PathBuf;
 use std::time::{Duration, SystemTime};
 use tempfile::TempDir;
@@ -24,17 +27,48 @@ impl TestHelper {
                 use_hf_params: false,
                 retry_config: RetryConfig::default(),
                 debug: false,
+                local_search_depth: 0,
+                allow_http: false,
+                metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+                refresh_metadata: false,
+                cache_dir: None,
+                force_load: false,
+                memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+                chat_template: None,
+                extra_stop_sequences: Vec::new(),
             },
             queue_config: QueueConfig {
                 max_queue_size: 10,
                 request_timeout: Duration::from_secs(5),
                 worker_threads: 1,
+                max_sequences_per_request: 4,
+                max_token_conversion_failures: 8,
+                context_pool_size: None,
+                use_template_stop_tokens: true,
+                enable_request_batching: false,
+                max_batched_requests: 4,
+                stop_token_collisions: ValidationSeverity::Error,
+                max_stop_tokens: 20,
+                max_stop_token_length: 100,
             },
             mcp_servers: vec![],
             session_config: SessionConfig {
                 max_sessions: 10,
                 session_timeout: Duration::from_secs(300), // 5 minutes for tests
+                default_tool_policy: ToolPolicy::AllowAll,
+                append_responses: true,
+                append_on_stream_drop: true,
+                eviction_policy: SessionEvictionPolicy::Reject,
+                system_prompt: None,
             },
+            parallel_execution_config: ParallelExecutionConfig::default(),
+            load_mode: LoadMode::Eager,
+            limits: LimitsConfig::default(),
+            audit_log: None,
+            default_system_prompt: None,
+            models: Vec::new(),
+            redaction: RedactionPolicy::LogFull,
+            post_processors: Vec::new(),
         }
     }
 
@@ -52,6 +86,7 @@ impl TestHelper {
                     tool_call_id: None,
                     tool_name: None,
                     timestamp: now,
+                    attachments: Vec::new(),
                 },
                 Message {
                     role: MessageRole::User,
@@ -59,6 +94,7 @@ impl TestHelper {
                     tool_call_id: None,
                     tool_name: None,
                     timestamp: now,
+                    attachments: Vec::new(),
                 },
                 Message {
                     role: MessageRole::Assistant,
@@ -66,6 +102,7 @@ impl TestHelper {
                     tool_call_id: None,
                     tool_name: None,
                     timestamp: now,
+                    attachments: Vec::new(),
                 },
             ],
             mcp_servers: vec![],
@@ -73,6 +110,8 @@ impl TestHelper {
             available_prompts: vec![],
             created_at: now,
             updated_at: now,
+            tool_policy: ToolPolicy::AllowAll,
+            usage: SessionUsage::default(),
         }
     }
 
@@ -92,6 +131,7 @@ impl TestHelper {
                 "required": ["input"]
             }),
             server_name: "test_server".to_string(),
+            original_name: None,
         }
     }
 
@@ -144,17 +184,48 @@ impl TestHelper {
                 use_hf_params: false,
                 retry_config: RetryConfig::default(),
                 debug: false,
+                local_search_depth: 0,
+                allow_http: false,
+                metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+                refresh_metadata: false,
+                cache_dir: None,
+                force_load: false,
+                memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+                chat_template: None,
+                extra_stop_sequences: Vec::new(),
             },
             queue_config: QueueConfig {
                 max_queue_size: 5,
                 request_timeout: Duration::from_secs(2),
                 worker_threads: 1,
+                max_sequences_per_request: 4,
+                max_token_conversion_failures: 8,
+                context_pool_size: None,
+                use_template_stop_tokens: true,
+                enable_request_batching: false,
+                max_batched_requests: 4,
+                stop_token_collisions: ValidationSeverity::Error,
+                max_stop_tokens: 20,
+                max_stop_token_length: 100,
             },
             mcp_servers: vec![],
             session_config: SessionConfig {
                 max_sessions: 5,
                 session_timeout: Duration::from_secs(60),
+                default_tool_policy: ToolPolicy::AllowAll,
+                append_responses: true,
+                append_on_stream_drop: true,
+                eviction_policy: SessionEvictionPolicy::Reject,
+                system_prompt: None,
             },
+            parallel_execution_config: ParallelExecutionConfig::default(),
+            load_mode: LoadMode::Eager,
+            limits: LimitsConfig::default(),
+            audit_log: None,
+            default_system_prompt: None,
+            models: Vec::new(),
+            redaction: RedactionPolicy::LogFull,
+            post_processors: Vec::new(),
         }
     }
 }
@@ -1,7 +1,9 @@
-use llama_agent::dependency_analysis::{DependencyAnalyzer, ParallelExecutionDecision};
+use llama_agent::dependency_analysis::{
+    plan_execution, DependencyAnalyzer, ParallelExecutionDecision, PlanError,
+};
 use llama_agent::types::{
     AccessType, ConflictType, ParallelExecutionConfig, ResourceAccess, ResourceType, ToolCall,
-    ToolCallId, ToolConflict,
+    ToolCallId, ToolConflict, ToolDefinition,
 };
 use serde_json::{json, Value};
 use std::collections::HashMap;
@@ -346,3 +348,214 @@ fn test_performance_with_many_tools() {
         ParallelExecutionDecision::Parallel | ParallelExecutionDecision::Sequential(_) => {}
     }
 }
+
+fn plan_names(calls: &[ToolCall], config: &ParallelExecutionConfig) -> Vec<Vec<String>> {
+    plan_execution(calls, &[], config)
+        .unwrap()
+        .stage_names()
+        .into_iter()
+        .map(|stage| stage.into_iter().map(str::to_string).collect())
+        .collect()
+}
+
+#[test]
+fn test_plan_independent_calls_share_a_stage() {
+    let calls = vec![
+        create_tool_call("search", json!({"query": "rust"})),
+        create_tool_call("translate", json!({"text": "hola"})),
+        create_tool_call("summarize", json!({"text": "long"})),
+    ];
+    let plan = plan_execution(&calls, &[], &ParallelExecutionConfig::default()).unwrap();
+    assert_eq!(
+        plan.stage_names(),
+        vec![vec!["search", "translate", "summarize"]]
+    );
+    assert_eq!(plan.call_count(), 3);
+    // Stages keep the original calls
+    assert_eq!(plan.stages[0][1].id, calls[1].id);
+}
+
+#[test]
+fn test_plan_empty() {
+    let plan = plan_execution(&[], &[], &ParallelExecutionConfig::default()).unwrap();
+    assert!(plan.stages.is_empty());
+}
+
+#[test]
+fn test_plan_chain() {
+    let calls = vec![
+        create_tool_call("search", json!({"query": "rust"})),
+        create_tool_call("summarize", json!({"text": "${search}"})),
+        create_tool_call("translate", json!({"text": "${summarize}"})),
+    ];
+    assert_eq!(
+        plan_names(&calls, &ParallelExecutionConfig::default()),
+        vec![vec!["search"], vec!["summarize"], vec!["translate"]]
+    );
+}
+
+#[test]
+fn test_plan_reference_to_later_call() {
+    let calls = vec![
+        create_tool_call("summarize", json!({"text": "${search}"})),
+        create_tool_call("search", json!({"query": "rust"})),
+    ];
+    assert_eq!(
+        plan_names(&calls, &ParallelExecutionConfig::default()),
+        vec![vec!["search"], vec!["summarize"]]
+    );
+}
+
+#[test]
+fn test_plan_diamond() {
+    let calls = vec![
+        create_tool_call("search", json!({"query": "rust"})),
+        create_tool_call("summarize", json!({"text": "${search}"})),
+        create_tool_call("classify", json!({"items": ["@search"]})),
+        create_tool_call(
+            "combine",
+            json!({"first": "${summarize}", "second": "${classify}"}),
+        ),
+    ];
+    assert_eq!(
+        plan_names(&calls, &ParallelExecutionConfig::default()),
+        vec![
+            vec!["search"],
+            vec!["summarize", "classify"],
+            vec!["combine"]
+        ]
+    );
+}
+
+#[test]
+fn test_plan_configured_conflicts_keep_call_order() {
+    let config = ParallelExecutionConfig {
+        tool_conflicts: vec![ToolConflict {
+            tool1: "tool_b".to_string(),
+            tool2: "tool_a".to_string(),
+            conflict_type: ConflictType::MutualExclusion,
+            description: "Shared lock".to_string(),
+        }],
+        never_parallel: vec![("tool_c".to_string(), "tool_d".to_string())],
+        ..Default::default()
+    };
+    let calls = vec![
+        create_tool_call("tool_a", json!({})),
+        create_tool_call("tool_b", json!({})),
+        create_tool_call("tool_c", json!({})),
+        create_tool_call("tool_d", json!({})),
+        create_tool_call("tool_e", json!({})),
+    ];
+    assert_eq!(
+        plan_names(&calls, &config),
+        vec![vec!["tool_a", "tool_c", "tool_e"], vec!["tool_b", "tool_d"]]
+    );
+}
+
+#[test]
+fn test_plan_resource_conflicts() {
+    let access = |name: &str, access_type: AccessType, exclusive: bool| {
+        vec![ResourceAccess {
+            resource: ResourceType::Database(name.to_string()),
+            access_type,
+            exclusive,
+        }]
+    };
+    let mut patterns = HashMap::new();
+    patterns.insert(
+        "writer".to_string(),
+        access("users", AccessType::Write, true),
+    );
+    patterns.insert(
+        "reader".to_string(),
+        access("users", AccessType::Read, false),
+    );
+    patterns.insert(
+        "auditor".to_string(),
+        access("users", AccessType::Read, false),
+    );
+    patterns.insert(
+        "other".to_string(),
+        access("orders", AccessType::Write, true),
+    );
+    let config = ParallelExecutionConfig {
+        resource_access_patterns: patterns,
+        ..Default::default()
+    };
+
+    let calls = vec![
+        create_tool_call("reader", json!({})),
+        create_tool_call("auditor", json!({})),
+        create_tool_call("writer", json!({})),
+        create_tool_call("other", json!({})),
+    ];
+    // Readers share a stage; the writer waits for both
+    assert_eq!(
+        plan_names(&calls, &config),
+        vec![vec!["reader", "auditor", "other"], vec!["writer"]]
+    );
+}
+
+#[test]
+fn test_plan_uses_tool_descriptions() {
+    let tool = |name: &str, description: &str| ToolDefinition {
+        name: name.to_string(),
+        description: description.to_string(),
+        parameters: json!({}),
+        server_name: "notes".to_string(),
+        original_name: None,
+    };
+    let tools = vec![
+        tool("load", "Read a file from disk"),
+        tool("save", "Write text to a file"),
+    ];
+    let calls = vec![
+        create_tool_call("load", json!({})),
+        create_tool_call("save", json!({})),
+    ];
+    let config = ParallelExecutionConfig::default();
+
+    let plan = plan_execution(&calls, &tools, &config).unwrap();
+    assert_eq!(plan.stage_names(), vec![vec!["load"], vec!["save"]]);
+
+    // Without descriptions nothing is known about either tool
+    let plan = plan_execution(&calls, &[], &config).unwrap();
+    assert_eq!(plan.stage_names(), vec![vec!["load", "save"]]);
+}
+
+#[test]
+fn test_plan_cycle_names_tools() {
+    let calls = vec![
+        create_tool_call("tool_a", json!({"input": "${tool_b}"})),
+        create_tool_call("tool_b", json!({"input": "${tool_a}"})),
+    ];
+    let error = plan_execution(&calls, &[], &ParallelExecutionConfig::default()).unwrap_err();
+    assert_eq!(
+        error,
+        PlanError::DependencyCycle {
+            tools: vec![
+                "tool_b".to_string(),
+                "tool_a".to_string(),
+                "tool_b".to_string()
+            ]
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "Tool calls depend on each other in a cycle: tool_b -> tool_a -> tool_b"
+    );
+}
+
+#[test]
+fn test_plan_cycle_excludes_bystanders() {
+    let calls = vec![
+        create_tool_call("free", json!({})),
+        create_tool_call("waiting", json!({"input": "${cycle_x}"})),
+        create_tool_call("cycle_x", json!({"input": "${cycle_z}"})),
+        create_tool_call("cycle_y", json!({"input": "${cycle_x}"})),
+        create_tool_call("cycle_z", json!({"input": "${cycle_y}"})),
+    ];
+    let error = plan_execution(&calls, &[], &ParallelExecutionConfig::default()).unwrap_err();
+    let PlanError::DependencyCycle { tools } = error;
+    assert_eq!(tools, vec!["cycle_y", "cycle_z", "cycle_x", "cycle_y"]);
+}
@@ -0,0 +1,1608 @@
+mod common;
+
+use async_trait::async_trait;
+use common::TestHelper;
+use futures::{Stream, StreamExt};
+use llama_agent::test_support::{agent_with_fake_model, agent_with_fake_models, FakeModel};
+use llama_agent::types::{
+    AgentAPI, AgentError, AuditLogConfig, FinishReason, GenerationRequest, GenerationResponse,
+    GetPromptResult, MCPError, Message, MessageRole, ModelMetadata, ModelSource, NamedModelConfig,
+    PromptDefinition, QueueError, SessionFilter, SessionId, ShutdownPhase, StreamChunk,
+    StreamChunking, StreamEvent, ToolCall, ToolCallId, ToolDefinition, ToolPolicy,
+    ValidationSeverity,
+};
+use llama_agent::{AgentServer, GenerationSink, MCPHealthStatus, MCPServer, PostProcessor};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+fn agent(model: &FakeModel) -> AgentServer {
+    agent_with_fake_model(TestHelper::minimal_config(), model.clone())
+        .expect("Agent with a fake model should start without a model file")
+}
+
+fn user_message(prompt: &str) -> Message {
+    Message {
+        role: MessageRole::User,
+        content: prompt.to_string(),
+        tool_call_id: None,
+        tool_name: None,
+        timestamp: SystemTime::now(),
+        attachments: Vec::new(),
+    }
+}
+
+async fn session_with_prompt(agent: &AgentServer, prompt: &str) -> SessionId {
+    let session = agent.create_session().await.unwrap();
+    agent
+        .add_message(&session.id, user_message(prompt))
+        .await
+        .unwrap();
+    session.id
+}
+
+fn stopped(reason: &str) -> FinishReason {
+    FinishReason::Stopped(reason.to_string())
+}
+
+/// Read a stream to its final chunk, returning the streamed text and that chunk
+async fn collect_stream(
+    mut stream: impl Stream<Item = Result<StreamChunk, AgentError>> + Unpin,
+) -> (String, StreamChunk) {
+    let mut text = String::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.unwrap();
+        if chunk.is_complete {
+            return (text, chunk);
+        }
+        text.push_str(&chunk.text);
+    }
+    panic!("Stream ended without a final chunk");
+}
+
+#[tokio::test]
+async fn test_generate_replays_reply_until_end_of_sequence() {
+    let model = FakeModel::new().with_reply(["Hello", ",", " world", "!"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Say hello").await;
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+
+    assert_eq!(response.generated_text, "Hello, world!");
+    assert_eq!(response.tokens_generated, 4);
+    assert!(response.prompt_tokens > 0);
+
+    // The response is stored as the next turn
+    let session = agent.get_session(&session_id).await.unwrap().unwrap();
+    let last = session.messages.last().unwrap();
+    assert_eq!(last.role, MessageRole::Assistant);
+    assert_eq!(last.content, "Hello, world!");
+}
+
+#[tokio::test]
+async fn test_generate_several_completions() {
+    let model = FakeModel::new()
+        .with_reply(["Paris", "."])
+        .with_reply(["Lyon"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Name a city").await;
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id).with_n(2))
+        .await
+        .unwrap();
+
+    let texts: Vec<&str> = response
+        .candidates
+        .iter()
+        .map(|candidate| candidate.text.as_str())
+        .collect();
+    assert_eq!(texts, vec!["Paris.", "Lyon"]);
+    assert_eq!(response.generated_text, "Paris.");
+    assert_eq!(response.tokens_generated, 2);
+    assert!(response.time_to_first_token.is_some());
+    assert_eq!(model.remaining_replies(), 0);
+}
+
+#[tokio::test]
+async fn test_stop_token_id_ends_generation() {
+    let reply = ["Hello", " there", "<|stop|>", " ignored"];
+    let model = FakeModel::new()
+        .with_reply(reply)
+        .with_reply(reply)
+        .with_reply(reply);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Greet me").await;
+    let stop_id = model.token_id("<|stop|>").unwrap();
+
+    let stream = agent
+        .generate_stream(GenerationRequest::new(session_id).with_stop_token_ids(vec![stop_id]))
+        .await
+        .unwrap();
+    let (text, last) = collect_stream(stream).await;
+
+    assert_eq!(text, "Hello there");
+    let response = last.response.unwrap();
+    assert_eq!(response.finish_reason, stopped("Stop token detected"));
+    assert_eq!(response.tokens_generated, 2);
+
+    // The stop token is never converted to text, streamed or not
+    let response = agent
+        .generate(GenerationRequest::new(session_id).with_stop_token_ids(vec![stop_id]))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Hello there");
+    assert_eq!(response.finish_reason, stopped("Stop token detected"));
+
+    // Without the id it is text like any other
+    let response = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Hello there<|stop|> ignored");
+}
+
+#[tokio::test]
+async fn test_stop_string_across_tokens() {
+    let model = FakeModel::new()
+        .with_reply(["Answer", ".", "\n\n", "User", ":", " more"])
+        .with_reply(["café", "é", "!", " more"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Answer briefly").await;
+
+    let stream = agent
+        .generate_stream(
+            GenerationRequest::new(session_id).with_stop_tokens(vec!["\n\nUser:".to_string()]),
+        )
+        .await
+        .unwrap();
+    let (text, last) = collect_stream(stream).await;
+
+    // The token completing the stop string is the last one generated
+    assert_eq!(text, "Answer.\n\nUser:");
+    assert_eq!(
+        last.response.unwrap().finish_reason,
+        stopped("Stop token detected")
+    );
+
+    // Multi-byte text before the stop string keeps the scan on char boundaries
+    let response = agent
+        .generate(GenerationRequest::new(session_id).with_stop_tokens(vec!["é!".to_string()]))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "caféé!");
+    assert_eq!(response.finish_reason, stopped("Stop token detected"));
+}
+
+#[tokio::test]
+async fn test_stop_token_colliding_with_template_is_rejected() {
+    let model = FakeModel::new().with_reply(["Hi", "!"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Say hi").await;
+
+    // "<|" is part of every ChatML marker, so generation would stop at once
+    let error = agent
+        .generate(GenerationRequest::new(session_id).with_stop_tokens(vec!["<|".to_string()]))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("\"<|im_start|>\""), "{}", error);
+
+    let mut config = TestHelper::minimal_config();
+    config.queue_config.stop_token_collisions = ValidationSeverity::Warning;
+    let agent = agent_with_fake_model(config, model).unwrap();
+    let session_id = session_with_prompt(&agent, "Say hi").await;
+    let response = agent
+        .generate(GenerationRequest::new(session_id).with_stop_tokens(vec!["<|".to_string()]))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Hi!");
+
+    // Blank stop tokens are rejected whatever the severity
+    let result = agent
+        .generate(GenerationRequest::new(session_id).with_stop_tokens(vec!["\n".to_string()]))
+        .await;
+    assert!(result.unwrap_err().to_string().contains("whitespace only"));
+}
+
+/// Generate `reply` with a fake model configured as HuggingFace repo `repo`, whose
+/// name picks the chat template
+async fn generate_as_model(
+    repo: &str,
+    use_template_stop_tokens: bool,
+    request: impl FnOnce(SessionId) -> GenerationRequest,
+    reply: &[&str],
+) -> GenerationResponse {
+    let mut config = TestHelper::minimal_config();
+    config.model.source = ModelSource::HuggingFace {
+        repo: repo.to_string(),
+        filename: Some("model.gguf".to_string()),
+    };
+    config.queue_config.use_template_stop_tokens = use_template_stop_tokens;
+    let model = FakeModel::new().with_reply(reply.iter().copied());
+    let agent = agent_with_fake_model(config, model).unwrap();
+    let session_id = session_with_prompt(&agent, "Say hi").await;
+    agent.generate(request(session_id)).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_template_stop_sequences_end_generation() {
+    let templates = [
+        ("Qwen/Qwen2.5-7B-Instruct-GGUF", "<|im_end|>"),
+        ("microsoft/Phi-3-mini-4k-instruct-gguf", "<|end|>"),
+    ];
+    for (repo, marker) in templates {
+        let reply = ["Hi", marker, "\nUser:", " go on"];
+        let response = generate_as_model(repo, true, GenerationRequest::new, &reply).await;
+        assert_eq!(response.tokens_generated, 2, "{}", repo);
+        assert_eq!(response.finish_reason, stopped("Stop token detected"));
+
+        // Without the template's stops the model writes the next turn itself
+        let response = generate_as_model(repo, false, GenerationRequest::new, &reply).await;
+        assert_eq!(response.tokens_generated, 4, "{}", repo);
+    }
+}
+
+#[tokio::test]
+async fn test_template_stop_sequences_respect_stop_token_cap() {
+    // The request's stop tokens fill the cap, so the template's are not added
+    let stops: Vec<String> = (0..20).map(|i| format!("STOP{}", i)).collect();
+    let response = generate_as_model(
+        "Qwen/Qwen2.5-7B-Instruct-GGUF",
+        true,
+        |session_id| GenerationRequest::new(session_id).with_stop_tokens(stops),
+        &["Hi", "<|im_end|>", " more", " STOP3", " ignored"],
+    )
+    .await;
+    assert_eq!(response.tokens_generated, 4);
+}
+
+#[tokio::test]
+async fn test_template_stop_sequences_respect_configured_cap() {
+    // One request stop fills a cap of one, so the template's are not added
+    let mut config = TestHelper::minimal_config();
+    config.model.source = ModelSource::HuggingFace {
+        repo: "Qwen/Qwen2.5-7B-Instruct-GGUF".to_string(),
+        filename: Some("model.gguf".to_string()),
+    };
+    config.queue_config.max_stop_tokens = 1;
+    let model = FakeModel::new().with_reply(["Hi", "<|im_end|>", " more", " END", " ignored"]);
+    let agent = agent_with_fake_model(config, model).unwrap();
+    let session_id = session_with_prompt(&agent, "Say hi").await;
+
+    let request = GenerationRequest::new(session_id).with_stop_tokens(vec!["END".to_string()]);
+    let response = agent.generate(request).await.unwrap();
+    assert_eq!(response.tokens_generated, 4);
+
+    // The request itself is held to the configured cap
+    let request = GenerationRequest::new(session_id)
+        .with_stop_tokens(vec!["END".to_string(), "STOP".to_string()]);
+    assert!(agent.generate(request).await.is_err());
+}
+
+#[tokio::test]
+async fn test_execute_tool_enforces_stored_policy() {
+    let mut config = TestHelper::minimal_config();
+    config.session_config.default_tool_policy = ToolPolicy::DenyList(vec!["shell".to_string()]);
+    let agent = agent_with_fake_model(config, FakeModel::new()).unwrap();
+    let shell = || ToolCall {
+        id: ToolCallId::new(),
+        name: "shell".to_string(),
+        arguments: serde_json::json!({}),
+    };
+
+    // A copy of a stored session cannot loosen the policy set on it
+    let mut session = agent.create_session().await.unwrap();
+    agent
+        .set_tool_policy(&session.id, ToolPolicy::AllowList(Vec::new()))
+        .await
+        .unwrap();
+    session.tool_policy = ToolPolicy::AllowAll;
+    let error = agent
+        .execute_tool(shell(), &session)
+        .await
+        .unwrap_err()
+        .into_root();
+    assert!(
+        matches!(&error, AgentError::ToolDenied { policy, .. } if *policy == ToolPolicy::AllowList(Vec::new())),
+        "{:?}",
+        error
+    );
+
+    // Nor can a session the agent never created
+    session.id = SessionId::new();
+    let error = agent
+        .execute_tool(shell(), &session)
+        .await
+        .unwrap_err()
+        .into_root();
+    assert!(
+        matches!(&error, AgentError::ToolDenied { policy, .. } if *policy == ToolPolicy::DenyList(vec!["shell".to_string()])),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_sentence_chunking_groups_tokens() {
+    let model = FakeModel::new().with_reply(["Hi", " there", ".", " How", " are", " you", "?"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Greet me").await;
+
+    let mut stream = agent
+        .generate_stream(GenerationRequest::new(session_id).with_chunking(StreamChunking::Sentence))
+        .await
+        .unwrap();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.unwrap();
+        if chunk.is_complete {
+            assert_eq!(chunk.response.unwrap().tokens_generated, 7);
+            break;
+        }
+        chunks.push((chunk.text, chunk.token_count));
+    }
+
+    // A sentence is only known to end once the next one starts; the rest is flushed at the end
+    assert_eq!(
+        chunks,
+        [
+            ("Hi there. ".to_string(), 4),
+            ("How are you?".to_string(), 7)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_max_tokens_limits_generation() {
+    let model = FakeModel::new().with_reply(["one", " two", " three", " four", " five"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Count to five").await;
+
+    let stream = agent
+        .generate_stream(GenerationRequest::new(session_id).with_max_tokens(3))
+        .await
+        .unwrap();
+    let (text, last) = collect_stream(stream).await;
+
+    assert_eq!(text, "one two three");
+    let response = last.response.unwrap();
+    assert_eq!(response.finish_reason, stopped("Maximum tokens reached"));
+    assert_eq!(response.tokens_generated, 3);
+}
+
+#[tokio::test]
+async fn test_debug_tokens_records_generated_token_ids() {
+    let reply = ["Hel", "lo", " wor", "ld"];
+    let model = FakeModel::new()
+        .with_reply(reply)
+        .with_reply(reply)
+        .with_reply(reply);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Say hello").await;
+    let expected: Vec<(u32, String)> = reply
+        .iter()
+        .map(|piece| (model.token_id(piece).unwrap(), piece.to_string()))
+        .collect();
+
+    let request = GenerationRequest::new(session_id).with_append_to_session(false);
+    let response = agent
+        .generate(request.clone().with_debug_tokens(true))
+        .await
+        .unwrap();
+    let recorded: Vec<(u32, String)> = response
+        .generated_tokens
+        .iter()
+        .map(|token| (token.id, token.piece.clone()))
+        .collect();
+    assert_eq!(recorded, expected);
+    assert!(response
+        .generated_tokens
+        .iter()
+        .all(|t| t.logprob.is_none()));
+
+    let mut stream = agent
+        .generate_stream(request.clone().with_debug_tokens(true))
+        .await
+        .unwrap();
+    let mut streamed_ids = Vec::new();
+    let mut last = None;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.unwrap();
+        streamed_ids.extend(chunk.token_ids.iter().copied());
+        if chunk.is_complete {
+            last = chunk.response;
+        }
+    }
+    let expected_ids: Vec<u32> = expected.iter().map(|(id, _)| *id).collect();
+    assert_eq!(streamed_ids, expected_ids);
+    assert_eq!(last.unwrap().generated_tokens.len(), reply.len());
+
+    // Nothing is recorded without the flag
+    let response = agent.generate(request).await.unwrap();
+    assert_eq!(response.generated_text, "Hello world");
+    assert!(response.generated_tokens.is_empty());
+}
+
+#[tokio::test]
+async fn test_response_reports_timing_breakdown() {
+    let model = FakeModel::new()
+        .with_reply(["a", " b", " c"])
+        .with_reply(["x", " y", " z"])
+        .with_token_delay(Duration::from_millis(5));
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Time this").await;
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id).with_append_to_session(false))
+        .await
+        .unwrap();
+    assert_timings_add_up(&response);
+
+    let stream = agent
+        .generate_stream(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    let (_, last) = collect_stream(stream).await;
+    assert_timings_add_up(&last.response.unwrap());
+
+    let stats = agent.queue_stats();
+    assert!(stats.average_decode_time >= Duration::from_millis(15));
+}
+
+fn assert_timings_add_up(response: &GenerationResponse) {
+    assert_eq!(response.tokens_generated, 3);
+    assert!(response.prompt_tokens > 0);
+    // Every sampled token waits for the token delay
+    assert!(response.decode_time >= Duration::from_millis(15));
+    let first_token = response.time_to_first_token.unwrap();
+    assert!(first_token >= Duration::from_millis(5));
+    assert!(first_token <= response.prompt_time + response.decode_time);
+
+    let measured = response.prompt_time + response.decode_time;
+    assert!(measured <= response.generation_time);
+    assert!(response.generation_time - measured < Duration::from_millis(50));
+}
+
+/// Run one request each on two sessions at once, returning the most requests the
+/// model saw generating together
+async fn peak_in_flight_for(worker_threads: usize, context_pool_size: Option<usize>) -> usize {
+    let model = FakeModel::new()
+        .with_reply(["a", " b", " c", " d"])
+        .with_reply(["w", " x", " y", " z"])
+        .with_token_delay(Duration::from_millis(20));
+    let mut config = TestHelper::minimal_config();
+    config.queue_config.worker_threads = worker_threads;
+    config.queue_config.context_pool_size = context_pool_size;
+    let agent = agent_with_fake_model(config, model.clone()).unwrap();
+    let first = session_with_prompt(&agent, "First prompt").await;
+    let second = session_with_prompt(&agent, "Second prompt").await;
+
+    let (a, b) = tokio::join!(
+        agent.generate(GenerationRequest::new(first)),
+        agent.generate(GenerationRequest::new(second))
+    );
+    assert_eq!(a.unwrap().tokens_generated, 4);
+    assert_eq!(b.unwrap().tokens_generated, 4);
+
+    let utilization = agent.queue_stats().worker_utilization;
+    assert_eq!(utilization.len(), worker_threads);
+    assert!(utilization.iter().all(|u| (0.0..=1.0).contains(u)));
+    model.peak_in_flight()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_parallel_workers_share_the_model() {
+    assert_eq!(peak_in_flight_for(2, Some(2)).await, 2);
+    // One context per worker by default
+    assert_eq!(peak_in_flight_for(2, None).await, 2);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_context_pool_bounds_requests_in_flight() {
+    assert_eq!(peak_in_flight_for(2, Some(1)).await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_request_batching_keeps_replies_apart() {
+    let model = FakeModel::new()
+        .with_reply(["a1", " a2", " a3"])
+        .with_reply(["b1", " b2", " b3"])
+        .with_reply(["c1", " c2", " c3"])
+        .with_reply(["d1", " d2", " d3"])
+        .with_token_delay(Duration::from_millis(20));
+    let mut config = TestHelper::minimal_config();
+    config.queue_config.enable_request_batching = true;
+    let agent = agent_with_fake_model(config, model.clone()).unwrap();
+    let mut sessions = Vec::new();
+    for i in 0..4 {
+        sessions.push(session_with_prompt(&agent, &format!("Prompt number {}", i)).await);
+    }
+
+    let responses = futures::future::join_all(
+        sessions
+            .iter()
+            .map(|&session_id| agent.generate(GenerationRequest::new(session_id))),
+    )
+    .await;
+    let mut texts: Vec<String> = responses
+        .into_iter()
+        .map(|response| {
+            let response = response.unwrap();
+            assert_eq!(response.tokens_generated, 3);
+            assert_eq!(
+                response.finish_reason,
+                stopped("End of sequence token detected")
+            );
+            response.generated_text
+        })
+        .collect();
+    texts.sort();
+    assert_eq!(texts, ["a1 a2 a3", "b1 b2 b3", "c1 c2 c3", "d1 d2 d3"]);
+
+    // With one worker and one context, requests queued behind the first one can
+    // only generate at the same time in a batch
+    assert!(model.peak_in_flight() > 1);
+    assert!(agent.queue_stats().average_batch_size > 1.0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn test_generation_does_not_starve_single_threaded_runtime() {
+    let model = FakeModel::new()
+        .with_reply(["a"; 20])
+        .with_token_delay(Duration::from_millis(50));
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Take your time").await;
+
+    let ticks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(50));
+            loop {
+                interval.tick().await;
+                ticks.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    });
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    ticker.abort();
+
+    assert_eq!(response.tokens_generated, 20);
+    let ticks = ticks.load(std::sync::atomic::Ordering::Relaxed);
+    assert!(
+        ticks >= 10,
+        "timer fired only {} times in one second",
+        ticks
+    );
+}
+
+#[tokio::test]
+async fn test_prefill_only_request_generates_nothing() {
+    let model = FakeModel::new().with_reply(["Never", " sampled"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Measure this prompt").await;
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id).with_max_tokens(0))
+        .await
+        .unwrap();
+
+    assert_eq!(response.generated_text, "");
+    assert_eq!(response.tokens_generated, 0);
+    assert_eq!(response.finish_reason, stopped("Prefill only"));
+    assert!(response.prompt_tokens > 0);
+    assert_eq!(model.tokens_sampled(), 0);
+
+    // Nothing is stored in the session
+    let session = agent.get_session(&session_id).await.unwrap().unwrap();
+    assert_eq!(session.messages.len(), 1);
+
+    let stream = agent
+        .generate_stream(GenerationRequest::new(session_id).with_max_tokens(0))
+        .await
+        .unwrap();
+    let (text, last) = collect_stream(stream).await;
+
+    assert_eq!(text, "");
+    let response = last.response.unwrap();
+    assert_eq!(response.finish_reason, stopped("Prefill only"));
+    assert_eq!(response.tokens_generated, 0);
+    assert!(response.prompt_tokens > 0);
+    assert_eq!(model.tokens_sampled(), 0);
+}
+
+#[tokio::test]
+async fn test_streamed_tool_call_is_detected() {
+    let model = FakeModel::new().with_reply([
+        "{\"function_name\": ",
+        "\"list_files\", ",
+        "\"arguments\": ",
+        "{\"path\": \".\"}}",
+    ]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "List the files").await;
+
+    let mut stream = agent
+        .generate_stream(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    let mut text = String::new();
+    let mut events = Vec::new();
+    let last = loop {
+        let chunk = stream.next().await.unwrap().unwrap();
+        if chunk.is_complete {
+            break chunk;
+        }
+        text.push_str(&chunk.text);
+        if chunk.event != StreamEvent::TextChunk {
+            events.push(chunk.event);
+        }
+    };
+
+    assert_eq!(
+        text,
+        r#"{"function_name": "list_files", "arguments": {"path": "."}}"#
+    );
+    // No server offers the tool, so the call fails and generation goes on
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[0],
+        StreamEvent::ToolCallStarted {
+            name: "list_files".to_string(),
+            arguments: serde_json::json!({"path": "."}),
+        }
+    );
+    assert!(matches!(
+        &events[1],
+        StreamEvent::ToolCallCompleted { name, is_error: true, .. } if name == "list_files"
+    ));
+    assert_eq!(events[2], StreamEvent::GenerationResumed);
+    assert_eq!(
+        last.response.unwrap().finish_reason,
+        stopped("End of sequence token detected")
+    );
+}
+
+#[tokio::test]
+async fn test_generate_continues_after_tool_call() {
+    let model = FakeModel::new()
+        .with_reply([r#"{"function_name": "list_files", "arguments": {"path": "."}}"#])
+        .with_reply(["No", " tools", " are", " available", "."]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "List the files").await;
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+
+    assert!(response.generated_text.ends_with("No tools are available."));
+    assert_eq!(model.remaining_replies(), 0);
+
+    // The call, its failed result and the final answer are stored in order
+    let session = agent.get_session(&session_id).await.unwrap().unwrap();
+    let roles: Vec<MessageRole> = session.messages.iter().map(|m| m.role.clone()).collect();
+    assert_eq!(
+        roles,
+        [
+            MessageRole::User,
+            MessageRole::Assistant,
+            MessageRole::Tool,
+            MessageRole::Assistant
+        ]
+    );
+    assert!(session.messages[2].tool_call_id.is_some());
+    assert_eq!(session.messages[3].content, "No tools are available.");
+}
+
+#[tokio::test]
+async fn test_dropped_stream_cancels_generation() {
+    let model = FakeModel::new()
+        .with_reply((0..1000).map(|i| format!(" {}", i)))
+        .with_reply(["Done"])
+        .with_token_delay(Duration::from_millis(2));
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Talk forever").await;
+
+    let mut stream = agent
+        .generate_stream(GenerationRequest::new(session_id).with_append_to_session(false))
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        assert!(!stream.next().await.unwrap().unwrap().is_complete);
+    }
+    drop(stream);
+
+    // The worker notices the cancellation before its next token and moves on
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let sampled = model.tokens_sampled();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(model.tokens_sampled(), sampled);
+    assert!(sampled < 1000);
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Done");
+}
+
+#[tokio::test]
+async fn test_audit_log_records_session_events_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let model = FakeModel::new()
+        .with_reply([r#"{"function_name": "list_files", "arguments": {"path": "."}}"#])
+        .with_reply(["No", " tools", " are", " available", "."]);
+    let mut config = TestHelper::minimal_config();
+    config.audit_log = Some(AuditLogConfig::new(&path));
+    let agent = agent_with_fake_model(config, model).unwrap();
+    agent
+        .audit_log()
+        .unwrap()
+        .set_redactor(|text| text.replace("hunter2", "[REDACTED]"));
+
+    let session_id = session_with_prompt(&agent, "List the files, password hunter2").await;
+    agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    agent.delete_session(&session_id).await.unwrap();
+    agent.audit_log().unwrap().flush().await;
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let events: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let names: Vec<&str> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "session_created",
+            "message_added",
+            "generation_started",
+            "tool_call",
+            "tool_result",
+            // The call and its result, stored as one turn
+            "message_added",
+            "message_added",
+            "message_added",
+            "generation_completed",
+            "session_deleted",
+        ]
+    );
+
+    let session = session_id.to_string();
+    assert!(events.iter().all(|e| e["session_id"] == session.as_str()));
+    assert!(events.iter().all(|e| e["timestamp"].is_string()));
+    assert_eq!(events[1]["role"], "user");
+    assert_eq!(events[1]["content"], "List the files, password [REDACTED]");
+    assert_eq!(events[3]["tool_name"], "list_files");
+    assert_eq!(events[3]["call_id"], events[4]["call_id"]);
+    assert!(events[4]["error"].is_string());
+    assert_eq!(events[7]["content"], "No tools are available.");
+    assert_eq!(events[8]["finish_reason"], "End of sequence token detected");
+    assert_eq!(events[8]["usage"]["requests"], 1);
+    assert!(!contents.contains("hunter2"));
+}
+
+#[tokio::test]
+async fn test_session_usage_accumulates_across_turns() {
+    let model = FakeModel::new()
+        .with_reply(["Hello", "!"])
+        .with_reply(["Fine", ",", " thanks"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Hi").await;
+
+    let first = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    agent
+        .add_message(&session_id, user_message("How are you?"))
+        .await
+        .unwrap();
+    let stream = agent
+        .generate_stream(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    let second = collect_stream(stream).await.1.response.unwrap();
+
+    // The second prompt repeats the first turn
+    assert!(second.prompt_tokens > first.prompt_tokens);
+    let usage = agent.get_usage(&session_id).await.unwrap();
+    assert_eq!(usage.requests, 2);
+    assert_eq!(usage.completion_tokens, 5);
+    assert_eq!(
+        usage.prompt_tokens,
+        u64::from(first.prompt_tokens + second.prompt_tokens)
+    );
+
+    let summaries = agent.list_sessions(SessionFilter::new()).await.unwrap();
+    assert_eq!(summaries[0].usage, usage);
+
+    assert!(matches!(
+        agent.get_usage(&SessionId::new()).await,
+        Err(AgentError::Session(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_full_queue_rejects_or_blocks() {
+    let reply: Vec<String> = (0..20).map(|i| format!(" {}", i)).collect();
+    let model = FakeModel::new()
+        .with_reply(reply.clone())
+        .with_reply(reply.clone())
+        .with_reply(reply)
+        .with_token_delay(Duration::from_millis(5));
+    let mut config = TestHelper::minimal_config();
+    config.queue_config.max_queue_size = 1;
+    let agent = agent_with_fake_model(config, model.clone()).unwrap();
+
+    // The single worker takes the first request and the second fills the queue
+    let first_id = session_with_prompt(&agent, "First").await;
+    let mut first = agent
+        .generate_stream(GenerationRequest::new(first_id))
+        .await
+        .unwrap();
+    assert!(!first.next().await.unwrap().unwrap().is_complete);
+    let second_id = session_with_prompt(&agent, "Second").await;
+    let second = agent
+        .generate_stream(GenerationRequest::new(second_id))
+        .await
+        .unwrap();
+
+    let third_id = session_with_prompt(&agent, "Third").await;
+    match agent
+        .generate_stream(GenerationRequest::new(third_id))
+        .await
+        .map_err(AgentError::into_root)
+    {
+        Err(AgentError::QueueFull {
+            current_depth,
+            max_depth,
+            estimated_wait,
+        }) => {
+            assert_eq!((current_depth, max_depth), (2, 1));
+            // Nothing has completed, so there is no estimate yet
+            assert_eq!(estimated_wait, None);
+        }
+        other => panic!("Expected QueueFull, got {:?}", other.map(|_| ())),
+    }
+
+    // Blocking waits until the worker takes the second request
+    let third = agent
+        .generate_stream(GenerationRequest::new(third_id).with_block_on_full(true))
+        .await
+        .unwrap();
+
+    for stream in [first, second, third] {
+        let (_, last) = collect_stream(stream).await;
+        assert_eq!(
+            last.response.unwrap().finish_reason,
+            stopped("End of sequence token detected")
+        );
+    }
+    assert_eq!(model.remaining_replies(), 0);
+}
+
+#[tokio::test]
+async fn test_requests_are_routed_to_the_named_model() {
+    let default_model = FakeModel::new().with_reply(["Default"]);
+    let small = FakeModel::new()
+        .with_reply(["Small"])
+        .with_reply(["Small again"]);
+    let agent = agent_with_fake_models(
+        TestHelper::minimal_config(),
+        default_model.clone(),
+        vec![("small", small.clone())],
+    )
+    .unwrap();
+    let session_id = session_with_prompt(&agent, "Hi").await;
+
+    let request = GenerationRequest::new(session_id).with_append_to_session(false);
+    let response = agent
+        .generate(request.clone().with_model("small"))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Small");
+    let response = agent.generate(request.clone()).await.unwrap();
+    assert_eq!(response.generated_text, "Default");
+    let stream = agent
+        .generate_stream(request.clone().with_model("small"))
+        .await
+        .unwrap();
+    let (text, _) = collect_stream(stream).await;
+    assert_eq!(text, "Small again");
+    assert_eq!(default_model.remaining_replies(), 0);
+    assert_eq!(small.remaining_replies(), 0);
+
+    match agent
+        .generate(request.clone().with_model("large"))
+        .await
+        .map_err(AgentError::into_root)
+    {
+        Err(AgentError::UnknownModel { name, available }) => {
+            assert_eq!(name, "large");
+            assert_eq!(available, ["default", "small"]);
+        }
+        other => panic!("Expected UnknownModel, got {:?}", other.map(|_| ())),
+    }
+    assert!(matches!(
+        agent
+            .generate_stream(request.with_model("large"))
+            .await
+            .map_err(AgentError::into_root),
+        Err(AgentError::UnknownModel { .. })
+    ));
+
+    let health = agent.health().await.unwrap();
+    let mut names: Vec<&String> = health.models.keys().collect();
+    names.sort();
+    assert_eq!(names, ["default", "small"]);
+}
+
+#[tokio::test]
+async fn test_tool_calls_are_parsed_with_the_routed_model_template() {
+    // Llama 3 tries pythonic calls first, the unknown default model JSON calls
+    let reply = r#"get_weather(city="Paris") {"function_name": "list_files", "arguments": {}}"#;
+    let default_model = FakeModel::new().with_reply([reply]);
+    let llama = FakeModel::new().with_reply([reply]);
+    let mut config = TestHelper::minimal_config();
+    let mut llama_config = config.model.clone();
+    llama_config.source = ModelSource::Local {
+        folder: "/tmp".into(),
+        filename: Some("Llama-3.2-1B-Instruct-Q4_K_M.gguf".to_string()),
+    };
+    config.models.push(NamedModelConfig {
+        name: "llama".to_string(),
+        model: llama_config,
+    });
+    let agent = agent_with_fake_models(config, default_model, vec![("llama", llama)]).unwrap();
+    let session_id = session_with_prompt(&agent, "What is the weather?").await;
+
+    let first_tool_call = |model: Option<&str>| {
+        let mut request = GenerationRequest::new(session_id).with_append_to_session(false);
+        if let Some(model) = model {
+            request = request.with_model(model);
+        }
+        let agent = &agent;
+        async move {
+            let mut stream = agent.generate_stream(request).await.unwrap();
+            loop {
+                let chunk = stream.next().await.unwrap().unwrap();
+                if let StreamEvent::ToolCallStarted { name, .. } = chunk.event {
+                    return name;
+                }
+                assert!(!chunk.is_complete, "no tool call was started");
+            }
+        }
+    };
+
+    assert_eq!(first_tool_call(Some("llama")).await, "get_weather");
+    assert_eq!(first_tool_call(None).await, "list_files");
+}
+
+/// MCP server that records how many tokens the model had sampled when it was shut down
+struct ShutdownRecorder {
+    model: FakeModel,
+    tokens_at_shutdown: Arc<Mutex<Option<usize>>>,
+}
+
+#[async_trait]
+impl MCPServer for ShutdownRecorder {
+    async fn initialize(&mut self) -> Result<(), MCPError> {
+        Ok(())
+    }
+
+    async fn list_tools(&mut self) -> Result<Vec<ToolDefinition>, MCPError> {
+        Ok(Vec::new())
+    }
+
+    async fn call_tool(
+        &mut self,
+        tool_name: &str,
+        _args: serde_json::Value,
+    ) -> Result<serde_json::Value, MCPError> {
+        Err(MCPError::ToolCallFailed(tool_name.to_string()))
+    }
+
+    async fn list_prompts(&mut self) -> Result<Vec<PromptDefinition>, MCPError> {
+        Ok(Vec::new())
+    }
+
+    async fn get_prompt(
+        &mut self,
+        prompt_name: &str,
+        _arguments: Option<serde_json::Value>,
+    ) -> Result<GetPromptResult, MCPError> {
+        Err(MCPError::Protocol(format!("No prompt {}", prompt_name)))
+    }
+
+    async fn health(&self) -> Result<MCPHealthStatus, MCPError> {
+        Ok(MCPHealthStatus::Healthy)
+    }
+
+    async fn shutdown(&mut self) -> Result<(), MCPError> {
+        *self.tokens_at_shutdown.lock().unwrap() = Some(self.model.tokens_sampled());
+        Ok(())
+    }
+
+    async fn notify_tools_list_changed(&mut self) -> Result<(), MCPError> {
+        Ok(())
+    }
+
+    async fn notify_prompts_list_changed(&mut self) -> Result<(), MCPError> {
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "recorder"
+    }
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_queue_before_stopping_mcp_servers() {
+    let model = FakeModel::new()
+        .with_reply((0..20).map(|i| format!(" {}", i)))
+        .with_token_delay(Duration::from_millis(10));
+    let agent = Arc::new(agent(&model));
+    let tokens_at_shutdown = Arc::new(Mutex::new(None));
+    agent
+        .mcp_client()
+        .add_server_instance(Box::new(ShutdownRecorder {
+            model: model.clone(),
+            tokens_at_shutdown: tokens_at_shutdown.clone(),
+        }))
+        .await
+        .unwrap();
+
+    // A request is running when shutdown starts
+    let session_id = session_with_prompt(&agent, "Count").await;
+    let running = tokio::spawn({
+        let agent = agent.clone();
+        async move { agent.generate(GenerationRequest::new(session_id)).await }
+    });
+    while model.tokens_sampled() == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let shutdown = tokio::spawn({
+        let agent = agent.clone();
+        async move { agent.shutdown_with_deadline(Duration::from_secs(10)).await }
+    });
+
+    // New work is turned away while the running request finishes
+    loop {
+        match agent.create_session().await {
+            Err(AgentError::ShuttingDown) => break,
+            Ok(_) => tokio::time::sleep(Duration::from_millis(1)).await,
+            Err(e) => panic!("Unexpected error: {}", e),
+        }
+    }
+    assert!(matches!(
+        agent
+            .generate(GenerationRequest::new(session_id))
+            .await
+            .map_err(AgentError::into_root),
+        Err(AgentError::ShuttingDown)
+    ));
+    assert!(matches!(
+        agent
+            .generate_stream(GenerationRequest::new(session_id))
+            .await
+            .map_err(AgentError::into_root),
+        Err(AgentError::ShuttingDown)
+    ));
+
+    let report = shutdown.await.unwrap().unwrap();
+    let response = running.await.unwrap().unwrap();
+    assert_eq!(response.tokens_generated, 20);
+    assert_eq!(report.cancelled_requests, 0);
+    assert!(!report.mcp_timed_out);
+
+    // The MCP server stopped only after the request had generated every token
+    assert_eq!(*tokens_at_shutdown.lock().unwrap(), Some(20));
+    let phases: Vec<ShutdownPhase> = report.phases.iter().map(|(phase, _)| *phase).collect();
+    assert_eq!(
+        phases,
+        [
+            ShutdownPhase::StopAccepting,
+            ShutdownPhase::DrainQueue,
+            ShutdownPhase::StopMcpServers,
+            ShutdownPhase::UnloadModel,
+            ShutdownPhase::FlushAuditLog
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_shutdown_cancels_requests_at_drain_deadline() {
+    let model = FakeModel::new()
+        .with_reply((0..1000).map(|i| format!(" {}", i)))
+        .with_token_delay(Duration::from_millis(5));
+    let agent = Arc::new(agent(&model));
+    let session_id = session_with_prompt(&agent, "Talk forever").await;
+    let running = tokio::spawn({
+        let agent = agent.clone();
+        async move { agent.generate(GenerationRequest::new(session_id)).await }
+    });
+    while model.tokens_sampled() == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let report = agent
+        .shutdown_with_deadline(Duration::from_millis(300))
+        .await
+        .unwrap();
+    assert_eq!(report.cancelled_requests, 1);
+
+    // The worker stops before its next token instead of finishing the reply
+    let _ = running.await.unwrap();
+    assert!(model.tokens_sampled() < 1000);
+}
+
+#[tokio::test]
+async fn test_worker_panic_fails_request_and_worker_is_replaced() {
+    let model = FakeModel::new().with_panic_on("explode");
+    let agent = agent(&model);
+
+    let session_id = session_with_prompt(&agent, "Please explode").await;
+    let error = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Worker panicked"),
+        "unexpected error: {}",
+        error
+    );
+
+    // The only worker was replaced, so the next request still runs
+    model.push_reply(["Still here"]);
+    let session_id = session_with_prompt(&agent, "Hello").await;
+    let response = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Still here");
+
+    let health = agent.health().await.unwrap();
+    let status = &health.models["default"];
+    assert_eq!(status.worker_panics, 1);
+    assert!(status
+        .last_worker_panic
+        .as_deref()
+        .is_some_and(|message| message.contains("explode")));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_batched_worker_panic_is_counted_once() {
+    let model = FakeModel::new()
+        .with_reply(["a1", " a2", " a3"])
+        .with_token_delay(Duration::from_millis(20))
+        .with_panic_on("explode");
+    let mut config = TestHelper::minimal_config();
+    config.queue_config.enable_request_batching = true;
+    let agent = Arc::new(agent_with_fake_model(config, model.clone()).unwrap());
+
+    let session_id = session_with_prompt(&agent, "Hello").await;
+    let first = tokio::spawn({
+        let agent = agent.clone();
+        async move { agent.generate(GenerationRequest::new(session_id)).await }
+    });
+    while model.tokens_sampled() == 0 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    // Queued behind the first request, these three are taken as one batch
+    let mut sessions = Vec::new();
+    for i in 0..3 {
+        sessions.push(session_with_prompt(&agent, &format!("Please explode {}", i)).await);
+    }
+    let exploding: Vec<_> = sessions
+        .into_iter()
+        .map(|session_id| {
+            let agent = agent.clone();
+            tokio::spawn(async move { agent.generate(GenerationRequest::new(session_id)).await })
+        })
+        .collect();
+    while agent.queue_stats().current_queue_size < 3 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(first.await.unwrap().unwrap().generated_text, "a1 a2 a3");
+    for request in exploding {
+        let error = request.await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("Worker panicked"));
+    }
+
+    let stats = agent.queue_stats();
+    assert_eq!(stats.worker_panics, 1);
+    assert_eq!(stats.failed_requests, 3);
+}
+
+#[tokio::test]
+async fn test_health_reports_model_metadata() {
+    let temp_dir = TestHelper::temp_dir();
+    let config = TestHelper::config_with_local_model(&temp_dir, "test-Q4_K_M.gguf");
+    let metadata = ModelMetadata {
+        source: config.model.source.clone(),
+        filename: "test-Q4_K_M.gguf".to_string(),
+        path: temp_dir.path().join("test-Q4_K_M.gguf"),
+        revision: None,
+        size_bytes: 15,
+        download_time: None,
+        load_time: Duration::from_millis(250),
+        cache_hit: false,
+    };
+    let before = SystemTime::now();
+    let agent =
+        agent_with_fake_model(config, FakeModel::new().with_metadata(metadata.clone())).unwrap();
+
+    let health = agent.health().await.expect("Health check failed");
+    let model = health.model.expect("Health should report model info");
+    assert_eq!(model.source_type, "local");
+    assert_eq!(model.location, temp_dir.path().display().to_string());
+    assert_eq!(model.filename, "test-Q4_K_M.gguf");
+    assert_eq!(model.path, metadata.path);
+    assert_eq!(model.revision, None);
+    assert_eq!(model.size_bytes, 15);
+    assert_eq!(model.quantization.as_deref(), Some("Q4_K_M"));
+    assert_eq!(model.download_duration, None);
+    assert_eq!(model.load_duration, Duration::from_millis(250));
+    assert!(!model.cache_hit);
+    assert!(before <= model.loaded_at && model.loaded_at <= SystemTime::now());
+
+    // Each model's status carries the same metadata
+    let status = &health.models["default"];
+    assert_eq!(status.model.as_ref().unwrap().filename, "test-Q4_K_M.gguf");
+}
+
+/// Records the callbacks a generation sink receives, in order
+#[derive(Default)]
+struct RecordingSink {
+    calls: Mutex<Vec<String>>,
+}
+
+impl RecordingSink {
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: String) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl GenerationSink for RecordingSink {
+    fn on_chunk(&self, chunk: StreamChunk) {
+        self.record(format!("chunk:{}", chunk.text));
+    }
+
+    fn on_tool_event(&self, event: StreamEvent) {
+        self.record(format!("event:{:?}", event));
+    }
+
+    fn on_complete(&self, response: GenerationResponse) {
+        self.record(format!("complete:{}", response.generated_text));
+    }
+
+    fn on_error(&self, error: AgentError) {
+        self.record(format!("error:{}", error));
+    }
+
+    fn on_cancelled(&self) {
+        self.record("cancelled".to_string());
+    }
+}
+
+#[tokio::test]
+async fn test_sink_receives_chunks_then_completion() {
+    let model = FakeModel::new().with_reply(["Hello", ",", " world"]);
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Say hello").await;
+
+    let sink = Arc::new(RecordingSink::default());
+    agent
+        .generate_with_sink(GenerationRequest::new(session_id), sink.clone())
+        .await
+        .unwrap()
+        .finished()
+        .await;
+
+    assert_eq!(
+        sink.calls(),
+        [
+            "chunk:Hello",
+            "chunk:,",
+            "chunk: world",
+            "complete:Hello, world"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_queue_failure_carries_request_context() {
+    let model = FakeModel::new().with_panic_on("explode");
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Please explode").await;
+
+    let error = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap_err();
+    let context = error.context().expect("error has no request context");
+    let request_id = context.request_id.clone().expect("no request id");
+    assert_eq!(context.session_id, Some(session_id));
+    assert_eq!(context.worker_id, Some(0));
+    assert!(matches!(
+        error.root(),
+        AgentError::Queue(QueueError::WorkerError(_))
+    ));
+    assert!(
+        error.to_string().starts_with(&format!(
+            "[request {}, session {}, worker 0]",
+            request_id, session_id
+        )),
+        "unexpected error: {}",
+        error
+    );
+
+    // Streamed failures carry their own request's id
+    let session_id = session_with_prompt(&agent, "Please explode again").await;
+    let mut stream = agent
+        .generate_stream(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    let error = loop {
+        match stream.next().await {
+            Some(Err(e)) => break e,
+            Some(Ok(_)) => continue,
+            None => panic!("stream ended without an error"),
+        }
+    };
+    let context = error
+        .context()
+        .expect("stream error has no request context");
+    assert_eq!(context.session_id, Some(session_id));
+    assert_ne!(context.request_id.as_deref(), Some(request_id.as_str()));
+}
+
+#[tokio::test]
+async fn test_sink_receives_generation_error() {
+    let model = FakeModel::new().with_panic_on("explode");
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Please explode").await;
+
+    let sink = Arc::new(RecordingSink::default());
+    agent
+        .generate_with_sink(GenerationRequest::new(session_id), sink.clone())
+        .await
+        .unwrap()
+        .finished()
+        .await;
+
+    let calls = sink.calls();
+    assert_eq!(calls.len(), 1, "unexpected callbacks: {:?}", calls);
+    assert!(
+        calls[0].starts_with("error:"),
+        "unexpected callback: {}",
+        calls[0]
+    );
+    assert!(calls[0].contains("Worker panicked"));
+}
+
+#[tokio::test]
+async fn test_cancelled_sink_generation_stops() {
+    let model = FakeModel::new()
+        .with_reply((0..1000).map(|i| format!(" {}", i)))
+        .with_token_delay(Duration::from_millis(2));
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Talk forever").await;
+
+    let sink = Arc::new(RecordingSink::default());
+    let generation = agent
+        .generate_with_sink(
+            GenerationRequest::new(session_id).with_append_to_session(false),
+            sink.clone(),
+        )
+        .await
+        .unwrap();
+    while sink.calls().len() < 3 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    generation.cancel();
+    generation.finished().await;
+
+    let calls = sink.calls();
+    assert_eq!(calls.last().map(String::as_str), Some("cancelled"));
+    assert!(calls[..calls.len() - 1]
+        .iter()
+        .all(|call| call.starts_with("chunk:")));
+
+    // Cancelling dropped the stream, which cancels the request
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let sampled = model.tokens_sampled();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(model.tokens_sampled(), sampled);
+    assert!(sampled < 1000);
+    assert_eq!(sink.calls(), calls);
+}
+
+#[tokio::test]
+async fn test_max_duration_returns_partial_text() {
+    let model = FakeModel::new()
+        .with_reply((0..100).map(|i| format!(" {}", i)))
+        .with_reply((0..100).map(|i| format!(" {}", i)))
+        .with_token_delay(Duration::from_millis(10));
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Count slowly").await;
+
+    let response = agent
+        .generate(
+            GenerationRequest::new(session_id)
+                .with_max_tokens(1000)
+                .with_max_duration(Duration::from_millis(200))
+                .with_append_to_session(false),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.finish_reason, stopped("Time limit reached"));
+    assert!(response.tokens_generated > 0 && response.tokens_generated < 100);
+    assert!(response.generated_text.starts_with(" 0"));
+
+    // Streams end the same way, with the reason on the final chunk
+    let stream = agent
+        .generate_stream(
+            GenerationRequest::new(session_id)
+                .with_max_duration(Duration::from_millis(200))
+                .with_append_to_session(false),
+        )
+        .await
+        .unwrap();
+    let (text, last) = collect_stream(stream).await;
+    let response = last.response.unwrap();
+    assert_eq!(response.finish_reason, stopped("Time limit reached"));
+    assert_eq!(response.generated_text, text);
+    assert!(response.tokens_generated > 0 && response.tokens_generated < 100);
+}
+
+#[tokio::test]
+async fn test_max_tokens_and_max_duration_whichever_first() {
+    let model = FakeModel::new()
+        .with_reply(["a", "b", "c", "d", "e"])
+        .with_token_delay(Duration::from_millis(10));
+    let agent = agent(&model);
+    let session_id = session_with_prompt(&agent, "Spell").await;
+
+    let response = agent
+        .generate(
+            GenerationRequest::new(session_id)
+                .with_max_tokens(2)
+                .with_max_duration(Duration::from_secs(4)),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.finish_reason, stopped("Maximum tokens reached"));
+    assert_eq!(response.generated_text, "ab");
+
+    // The budget cannot outlast the request timeout, nor be zero
+    for max_duration in [Duration::ZERO, Duration::from_secs(60)] {
+        let error = agent
+            .generate(GenerationRequest::new(session_id).with_max_duration(max_duration))
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("max_duration"),
+            "unexpected error: {}",
+            error
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_post_processors_clean_response_and_stored_answer() {
+    let mut config = TestHelper::minimal_config();
+    config.post_processors = vec![
+        PostProcessor::StripControlTokens,
+        PostProcessor::TrimWhitespace,
+    ];
+    let model = FakeModel::new()
+        .with_reply(["\n", "Paris", "<|im_end|>", " "])
+        .with_reply(["\n", "Rome", "<|im_end|>"]);
+    let agent = agent_with_fake_model(config, model).unwrap();
+    let session_id = session_with_prompt(&agent, "Name a city").await;
+
+    let response = agent
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Paris");
+    let session = agent.get_session(&session_id).await.unwrap().unwrap();
+    assert_eq!(session.messages.last().unwrap().content, "Paris");
+
+    // Streamed chunks only lose control tokens; the final response is fully processed
+    agent
+        .add_message(&session_id, user_message("Another one"))
+        .await
+        .unwrap();
+    let stream = agent
+        .generate_stream(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    let (streamed, last) = collect_stream(stream).await;
+    assert_eq!(streamed, "\nRome");
+    assert_eq!(last.response.unwrap().generated_text, "Rome");
+    let session = agent.get_session(&session_id).await.unwrap().unwrap();
+    assert_eq!(session.messages.last().unwrap().content, "Rome");
+}
+
+#[tokio::test]
+async fn test_checkpointed_session_continues_in_another_agent() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("session.json");
+    let first = agent(&FakeModel::new().with_reply(["Paris"]));
+    let session_id = session_with_prompt(&first, "Name a city").await;
+    first
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    first.checkpoint_session(&session_id, &path).await.unwrap();
+    first.shutdown().await.unwrap();
+
+    let second = agent(&FakeModel::new().with_reply(["Rome"]));
+    let restored = second.restore_session(&path).await.unwrap();
+    assert!(restored.model_matches);
+    assert_eq!(restored.session.id, session_id);
+    assert_eq!(restored.session.messages.len(), 2);
+    assert_eq!(restored.session.messages[1].content, "Paris");
+
+    second
+        .add_message(&session_id, user_message("Another one"))
+        .await
+        .unwrap();
+    let response = second
+        .generate(GenerationRequest::new(session_id))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "Rome");
+    let session = second.get_session(&session_id).await.unwrap().unwrap();
+    assert_eq!(session.messages.len(), 4);
+
+    assert!(matches!(
+        second
+            .checkpoint_session(&SessionId::new(), &dir.path().join("missing.json"))
+            .await,
+        Err(AgentError::Session(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_checkpoint_from_another_model_restores_messages_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("session.json");
+    let first = agent(&FakeModel::new());
+    let session_id = session_with_prompt(&first, "Hi").await;
+    first.checkpoint_session(&session_id, &path).await.unwrap();
+
+    let mut config = TestHelper::minimal_config();
+    config.model.source = ModelSource::Local {
+        folder: dir.path().to_path_buf(),
+        filename: Some("other.gguf".to_string()),
+    };
+    let other = agent_with_fake_model(config, FakeModel::new()).unwrap();
+    let restored = other.restore_session(&path).await.unwrap();
+    assert!(!restored.model_matches);
+    let session = other.get_session(&session_id).await.unwrap().unwrap();
+    assert_eq!(session.messages[0].content, "Hi");
+}
@@ -2,7 +2,7 @@ mod common;
 
 use common::TestHelper;
 use llama_agent::types::*;
-use llama_agent::AgentServer;
+use llama_agent::{AgentServer, RedactionPolicy};
 use std::time::Duration;
 use tokio::time::timeout;
 
@@ -59,6 +59,7 @@ async fn test_session_workflow() {
         tool_call_id: None,
         tool_name: None,
         timestamp: std::time::SystemTime::now(),
+        attachments: Vec::new(),
     };
 
     session_manager
@@ -223,6 +224,7 @@ async fn test_message_handling() {
         tool_call_id: Some(tool_call_id),
         tool_name: Some("test_tool".to_string()),
         timestamp: std::time::SystemTime::now(),
+        attachments: Vec::new(),
     };
 
     assert_eq!(tool_message.tool_call_id, Some(tool_call_id));
@@ -252,6 +254,7 @@ async fn test_concurrent_session_access() {
                     tool_call_id: None,
                     tool_name: None,
                     timestamp: std::time::SystemTime::now(),
+                    attachments: Vec::new(),
                 };
                 manager.add_message(&session.id, message).await?;
             }
@@ -305,10 +308,27 @@ async fn test_error_handling_paths() {
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
         session_config: SessionConfig::default(),
+        parallel_execution_config: ParallelExecutionConfig::default(),
+        load_mode: LoadMode::Eager,
+        limits: LimitsConfig::default(),
+        audit_log: None,
+        default_system_prompt: None,
+        models: Vec::new(),
+        redaction: RedactionPolicy::LogFull,
+        post_processors: Vec::new(),
     };
 
     assert!(invalid_config.validate().is_err());
@@ -324,10 +344,27 @@ async fn test_error_handling_paths() {
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: vec![],
         session_config: SessionConfig::default(),
+        parallel_execution_config: ParallelExecutionConfig::default(),
+        load_mode: LoadMode::Eager,
+        limits: LimitsConfig::default(),
+        audit_log: None,
+        default_system_prompt: None,
+        models: Vec::new(),
+        redaction: RedactionPolicy::LogFull,
+        post_processors: Vec::new(),
     };
 
     assert!(invalid_hf_config.validate().is_err());
@@ -342,15 +379,25 @@ async fn test_error_handling_paths() {
                 command: "echo".to_string(),
                 args: vec![],
                 timeout_secs: None,
+                strict_protocol_version: false,
             },
             MCPServerConfig {
                 name: "duplicate".to_string(),
                 command: "echo".to_string(),
                 args: vec![],
                 timeout_secs: None,
+                strict_protocol_version: false,
             },
         ],
         session_config: SessionConfig::default(),
+        parallel_execution_config: ParallelExecutionConfig::default(),
+        load_mode: LoadMode::Eager,
+        limits: LimitsConfig::default(),
+        audit_log: None,
+        default_system_prompt: None,
+        models: Vec::new(),
+        redaction: RedactionPolicy::LogFull,
+        post_processors: Vec::new(),
     };
 
     assert!(duplicate_mcp_config.validate().is_err());
@@ -378,13 +425,22 @@ async fn test_timeout_scenarios() {
                 max_tokens: Some(100),
                 temperature: Some(0.7),
                 top_p: Some(0.9),
+                seed: None,
                 stop_tokens: vec![],
+                stop_token_ids: vec![],
+                n: None,
+                append_to_session: None,
+                chunking: StreamChunking::Token,
+                block_on_full: false,
+                model: None,
+                max_duration: None,
                 stopping_config: None,
+                debug_tokens: false,
             };
 
             let result = timeout(
                 Duration::from_millis(100),
-                queue.submit_request(request, &session),
+                queue.submit_request(request, std::sync::Arc::new(session)),
             )
             .await;
             assert!(result.is_ok()); // Timeout should complete, but request should fail
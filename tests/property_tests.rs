@@ -41,6 +41,7 @@ prop_compose! {
             tool_call_id,
             tool_name,
             timestamp: std::time::SystemTime::now(),
+            attachments: Vec::new(),
         }
     }
 }
@@ -64,6 +65,7 @@ prop_compose! {
                 }
             }),
             server_name,
+            original_name: None,
         }
     }
 }
@@ -80,6 +82,7 @@ prop_compose! {
             command,
             args,
             timeout_secs,
+            strict_protocol_version: false,
         }
     }
 }
@@ -98,6 +101,15 @@ prop_compose! {
             use_hf_params,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         }
     }
 }
@@ -112,6 +124,15 @@ prop_compose! {
             max_queue_size,
             request_timeout: Duration::from_secs(request_timeout_secs),
             worker_threads,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         }
     }
 }
@@ -124,6 +145,11 @@ prop_compose! {
         SessionConfig {
             max_sessions,
             session_timeout: Duration::from_secs(session_timeout_secs),
+            default_tool_policy: ToolPolicy::AllowAll,
+            append_responses: true,
+            append_on_stream_drop: true,
+            eviction_policy: SessionEvictionPolicy::Reject,
+            system_prompt: None,
         }
     }
 }
@@ -284,6 +310,7 @@ proptest! {
             tool_call_id: None,
             tool_name: None,
             timestamp: std::time::SystemTime::now(),
+            attachments: Vec::new(),
         };
 
         // Should be able to serialize/deserialize messages with empty content
@@ -302,6 +329,7 @@ proptest! {
             tool_call_id: None,
             tool_name: None,
             timestamp: std::time::SystemTime::now(),
+            attachments: Vec::new(),
         };
 
         // Should handle long content properly
@@ -320,6 +348,7 @@ proptest! {
             tool_call_id: None,
             tool_name: None,
             timestamp: std::time::SystemTime::now(),
+            attachments: Vec::new(),
         };
 
         // Should handle various Unicode characters
@@ -360,6 +389,15 @@ proptest! {
             max_queue_size: 100,
             request_timeout: Duration::from_secs(timeout_secs),
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         };
 
         let validation_result = config.validate();
@@ -422,6 +460,7 @@ async fn test_message_timestamp_ordering() {
             tool_call_id: None,
             tool_name: None,
             timestamp: std::time::SystemTime::now(),
+            attachments: Vec::new(),
         };
         messages.push(message);
     }
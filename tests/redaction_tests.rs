@@ -0,0 +1,95 @@
+mod common;
+
+use common::TestHelper;
+use llama_agent::test_support::{agent_with_fake_model, FakeModel};
+use llama_agent::types::{AgentAPI, AuditLogConfig, GenerationRequest, Message, MessageRole};
+use llama_agent::RedactionPolicy;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const SECRET_PROMPT: &str = "My card number is 4111-1111-1111-1111";
+const SECRET_REPLY: &str = "Your PIN is 8642";
+
+/// Log output captured by a test subscriber
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CapturedLogs {
+    fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+/// Run one generation under `policy`, returning the debug logs and the audit log
+async fn logs_for(policy: RedactionPolicy) -> (String, String) {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let mut config = TestHelper::minimal_config();
+    config.audit_log = Some(AuditLogConfig::new(&path));
+    config.redaction = policy;
+    let agent = agent_with_fake_model(config, FakeModel::new().with_reply([SECRET_REPLY])).unwrap();
+
+    let session = agent.create_session().await.unwrap();
+    agent
+        .add_message(
+            &session.id,
+            Message {
+                role: MessageRole::User,
+                content: SECRET_PROMPT.to_string(),
+                tool_call_id: None,
+                tool_name: None,
+                timestamp: SystemTime::now(),
+                attachments: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+    let response = agent
+        .generate(GenerationRequest::new(session.id).with_append_to_session(true))
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, SECRET_REPLY);
+    agent.audit_log().unwrap().flush().await;
+
+    (logs.contents(), std::fs::read_to_string(&path).unwrap())
+}
+
+// The policy is process-wide, so every policy is checked from this one test
+#[tokio::test]
+async fn test_redaction_policy_applies_to_logs_and_audit_log() {
+    for policy in [RedactionPolicy::LogLengthsOnly, RedactionPolicy::LogNothing] {
+        let name = format!("{:?}", policy);
+        let (logs, audit) = logs_for(policy).await;
+        for secret in [SECRET_PROMPT, SECRET_REPLY] {
+            assert!(!logs.contains(secret), "{} logged '{}'", name, secret);
+            assert!(!audit.contains(secret), "{} audited '{}'", name, secret);
+        }
+    }
+
+    let (logs, audit) = logs_for(RedactionPolicy::LogFull).await;
+    for secret in [SECRET_PROMPT, SECRET_REPLY] {
+        assert!(logs.contains(secret), "LogFull did not log '{}'", secret);
+        assert!(audit.contains(secret), "LogFull did not audit '{}'", secret);
+    }
+}
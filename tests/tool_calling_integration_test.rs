@@ -15,7 +15,7 @@ use llama_agent::{
     types::{
         AgentAPI, AgentConfig, FinishReason, GenerationRequest, MCPServerConfig, Message,
         MessageRole, ModelConfig, ModelSource, QueueConfig, RetryConfig, SessionConfig,
-        StoppingConfig, ToolCall, ToolCallId,
+        StoppingConfig, ToolCall, ToolCallId, ValidationSeverity,
     },
     AgentServer,
 };
@@ -42,11 +42,29 @@ async fn test_complete_tool_calling_workflow() -> Result<(), Box<dyn std::error:
             use_hf_params: true,
             retry_config: RetryConfig::default(),
             debug: true,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig {
             max_queue_size: 10,
             request_timeout: Duration::from_secs(120), // Longer timeout for testing
             worker_threads: 1,
+            max_sequences_per_request: 4,
+            max_token_conversion_failures: 8,
+            context_pool_size: None,
+            use_template_stop_tokens: true,
+            enable_request_batching: false,
+            max_batched_requests: 4,
+            stop_token_collisions: ValidationSeverity::Error,
+            max_stop_tokens: 20,
+            max_stop_token_length: 100,
         },
         mcp_servers: vec![MCPServerConfig {
             name: "filesystem".to_string(),
@@ -57,6 +75,7 @@ async fn test_complete_tool_calling_workflow() -> Result<(), Box<dyn std::error:
                 ".".to_string(),
             ],
             timeout_secs: None,
+            strict_protocol_version: false,
         }],
         session_config: SessionConfig::default(),
     };
@@ -260,6 +279,7 @@ async fn test_generation_with_tool_calls(
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     };
 
     agent.add_message(&session.id, message).await?;
@@ -270,6 +290,8 @@ async fn test_generation_with_tool_calls(
         max_tokens: Some(200),
         repetition_detection: None,
         eos_detection: true,
+        stop_token_ids: Vec::new(),
+        max_duration: None,
     };
 
     let request = GenerationRequest::new(session.id)
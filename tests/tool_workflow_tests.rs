@@ -2,8 +2,8 @@ use llama_agent::{
     agent::AgentServer,
     types::{
         AgentAPI, AgentConfig, FinishReason, MCPServerConfig, Message, MessageRole, ModelConfig,
-        ModelSource, QueueConfig, RetryConfig, Session, SessionConfig, SessionId, ToolCall,
-        ToolCallId, ToolDefinition, ToolResult,
+        ModelSource, QueueConfig, RetryConfig, Session, SessionConfig, SessionId, SessionUsage,
+        ToolCall, ToolCallId, ToolDefinition, ToolPolicy, ToolResult,
     },
 };
 use serde_json::json;
@@ -33,6 +33,15 @@ async fn test_tool_call_detection() {
             use_hf_params: false,
             retry_config: RetryConfig::default(),
             debug: false,
+            local_search_depth: 0,
+            allow_http: false,
+            metadata_ttl_secs: ModelConfig::DEFAULT_METADATA_TTL_SECS,
+            refresh_metadata: false,
+            cache_dir: None,
+            force_load: false,
+            memory_overhead_factor: ModelConfig::DEFAULT_MEMORY_OVERHEAD_FACTOR,
+            chat_template: None,
+            extra_stop_sequences: Vec::new(),
         },
         queue_config: QueueConfig::default(),
         mcp_servers: Vec::new(),
@@ -135,6 +144,7 @@ fn test_multi_step_scenario_structure() {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     });
 
     // Simulate tool result
@@ -145,6 +155,7 @@ fn test_multi_step_scenario_structure() {
         tool_call_id: Some(call_id),
         tool_name: Some("list_files".to_string()),
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     });
 
     // Simulate follow-up response
@@ -154,6 +165,7 @@ fn test_multi_step_scenario_structure() {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     });
 
     assert_eq!(session.messages.len(), 4); // User + 3 added messages
@@ -220,6 +232,7 @@ fn test_session_state_management() {
         tool_call_id: None,
         tool_name: None,
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     });
 
     // Small delay to ensure timestamp difference
@@ -239,6 +252,7 @@ fn test_session_state_management() {
         tool_call_id: Some(ToolCallId::new()),
         tool_name: Some("test_tool".to_string()),
         timestamp: SystemTime::now(),
+        attachments: Vec::new(),
     });
 
     assert_eq!(session.messages.len(), initial_message_count + 2);
@@ -295,12 +309,14 @@ fn create_test_session_with_tools() -> Session {
             tool_call_id: None,
             tool_name: None,
             timestamp: SystemTime::now(),
+            attachments: Vec::new(),
         }],
         mcp_servers: vec![MCPServerConfig {
             name: "test_server".to_string(),
             command: "test_command".to_string(),
             args: vec![],
             timeout_secs: None,
+            strict_protocol_version: false,
         }],
         available_tools: vec![ToolDefinition {
             name: "test_tool".to_string(),
@@ -315,9 +331,12 @@ fn create_test_session_with_tools() -> Session {
                 }
             }),
             server_name: "test_server".to_string(),
+            original_name: None,
         }],
         available_prompts: vec![],
         created_at: SystemTime::now(),
         updated_at: SystemTime::now(),
+        tool_policy: ToolPolicy::AllowAll,
+        usage: SessionUsage::default(),
     }
 }